@@ -1,4 +1,4 @@
-use crate::ids::TransactionId;
+use crate::ids::{ContainerId, PageId, TransactionId};
 use regex::Error as RegexError;
 use std::error::Error;
 use std::fmt;
@@ -27,6 +27,18 @@ pub enum CrustyError {
     InvalidMutationError(String),
     /// Transaction Rollback
     TransactionRollback(TransactionId),
+    /// A page read back from disk failed its checks and was not handed out.
+    CorruptPage {
+        container_id: ContainerId,
+        page_id: PageId,
+        reason: String,
+    },
+    /// Growing a container would take its heap file past the most bytes it may use.
+    QuotaExceeded {
+        container_id: ContainerId,
+        quota: u64,
+        size: u64,
+    },
 }
 
 impl fmt::Display for CrustyError {
@@ -44,6 +56,22 @@ impl fmt::Display for CrustyError {
                 CrustyError::InvalidMutationError(s) => format!("InvalidMutationError {}", s),
                 CrustyError::TransactionRollback(tid) =>
                     format!("Transaction Rolledback {:?}", tid),
+                CrustyError::CorruptPage {
+                    container_id,
+                    page_id,
+                    reason,
+                } => format!(
+                    "Corrupt page {} of container {}: {}",
+                    page_id, container_id, reason
+                ),
+                CrustyError::QuotaExceeded {
+                    container_id,
+                    quota,
+                    size,
+                } => format!(
+                    "Container {} would grow to {} bytes, past its quota of {} bytes",
+                    container_id, size, quota
+                ),
             }
         )
     }
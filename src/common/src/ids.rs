@@ -7,6 +7,7 @@ static TXN_COUNTER: AtomicU64 = AtomicU64::new(0);
 pub type TidType = u64;
 
 /// Permissions for locks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Permissions {
     ReadOnly,
     ReadWrite,
@@ -117,8 +118,57 @@ impl ValueId {
             slot_id: Some(slot_id),
         }
     }
+
+    /// Packs the id into a u64 for indexes and other places that store ids compactly.
+    /// Bits 0-15 hold the slot, 16-31 the page, 32-47 the container and 48-55 the segment.
+    /// Bits 56-58 record which of the segment, page and slot are present.
+    pub fn to_u64(&self) -> u64 {
+        let mut packed = (self.container_id as u64) << VALUE_ID_CONTAINER_SHIFT;
+        if let Some(segment_id) = self.segment_id {
+            packed |= ((segment_id as u64) << VALUE_ID_SEGMENT_SHIFT) | VALUE_ID_HAS_SEGMENT;
+        }
+        if let Some(page_id) = self.page_id {
+            packed |= ((page_id as u64) << VALUE_ID_PAGE_SHIFT) | VALUE_ID_HAS_PAGE;
+        }
+        if let Some(slot_id) = self.slot_id {
+            packed |= slot_id as u64 | VALUE_ID_HAS_SLOT;
+        }
+        packed
+    }
+
+    /// Unpacks an id produced by to_u64.
+    pub fn from_u64(packed: u64) -> Self {
+        let present = |flag: u64| packed & flag != 0;
+        ValueId {
+            container_id: (packed >> VALUE_ID_CONTAINER_SHIFT) as ContainerId,
+            segment_id: present(VALUE_ID_HAS_SEGMENT)
+                .then_some((packed >> VALUE_ID_SEGMENT_SHIFT) as SegmentId),
+            page_id: present(VALUE_ID_HAS_PAGE)
+                .then_some((packed >> VALUE_ID_PAGE_SHIFT) as PageId),
+            slot_id: present(VALUE_ID_HAS_SLOT).then_some(packed as SlotId),
+        }
+    }
+}
+
+impl From<ValueId> for u64 {
+    fn from(id: ValueId) -> Self {
+        id.to_u64()
+    }
+}
+
+impl From<u64> for ValueId {
+    fn from(packed: u64) -> Self {
+        ValueId::from_u64(packed)
+    }
 }
 
+const VALUE_ID_PAGE_SHIFT: u32 = 16;
+const VALUE_ID_CONTAINER_SHIFT: u32 = 32;
+const VALUE_ID_SEGMENT_SHIFT: u32 = 48;
+const VALUE_ID_HAS_SEGMENT: u64 = 1 << 56;
+const VALUE_ID_HAS_PAGE: u64 = 1 << 57;
+const VALUE_ID_HAS_SLOT: u64 = 1 << 58;
+
 impl fmt::Debug for ValueId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut buf: String = format!("<c_id:{}", self.container_id);
@@ -139,6 +189,10 @@ impl fmt::Debug for ValueId {
     }
 }
 
+/// Log sequence number as a log page and slot, ordered by position in the log.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 pub struct Lsn {
     pub page_id: PageId,
     pub slot_id: SlotId,
@@ -148,3 +202,33 @@ pub struct Lsn {
 pub type LogicalTimeStamp = u32;
 pub type AtomicTimeStamp = AtomicU32;
 pub type ContainerId = u16;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_value_id_u64_roundtrip() {
+        let ids = [
+            ValueId::new(7),
+            ValueId::new_page(ContainerId::MAX, 3),
+            ValueId::new_slot(1, PageId::MAX, SlotId::MAX),
+            ValueId::new_slot(0, 0, 0),
+            ValueId {
+                container_id: 9,
+                segment_id: Some(SegmentId::MAX),
+                page_id: None,
+                slot_id: Some(4),
+            },
+        ];
+        for id in ids {
+            assert_eq!(id, ValueId::from_u64(id.to_u64()));
+            assert_eq!(id, ValueId::from(u64::from(id)));
+        }
+        // Absent parts are told apart from zero ones
+        assert_ne!(
+            ValueId::new(0).to_u64(),
+            ValueId::new_slot(0, 0, 0).to_u64()
+        );
+    }
+}
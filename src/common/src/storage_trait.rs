@@ -57,6 +57,19 @@ pub trait StorageTrait {
         tid: TransactionId,
     ) -> Result<ValueId, CrustyError>;
 
+    /// Commit the changes made on behalf of tid so they survive a crash. Changes a storage
+    /// manager logs are rolled back by its recovery unless their transaction committed.
+    /// Storage managers that do not log changes have nothing to do.
+    fn commit_transaction(&self, _tid: TransactionId) -> Result<(), CrustyError> {
+        Ok(())
+    }
+
+    /// Roll back the changes made on behalf of tid since it last committed.
+    /// Storage managers that do not log changes keep them.
+    fn abort_transaction(&self, _tid: TransactionId) -> Result<(), CrustyError> {
+        Ok(())
+    }
+
     /// Create a new container to be stored.
     /// fn create_container(&self, name: String) -> ContainerId;
     /// Creates a new container object.
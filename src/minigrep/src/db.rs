@@ -0,0 +1,125 @@
+use crate::{LineMatch, Matcher};
+use common::ids::{ContainerId, Permissions, TransactionId, ValueId};
+use common::storage_trait::StorageTrait;
+use common::Tuple;
+use serde::Serialize;
+
+/// How stored record bytes are turned into a searchable line.
+#[derive(Debug, Clone, Default)]
+pub enum RecordFormat {
+    /// Records are UTF-8 text.
+    #[default]
+    Utf8,
+    /// Records are serialized tuples. Fields are joined with commas, or rendered as
+    /// `name=value` when column names are given.
+    Tuple { columns: Option<Vec<String>> },
+}
+
+impl RecordFormat {
+    /// The line to search for a record, None if the record does not decode in this format.
+    pub fn decode(&self, bytes: &[u8]) -> Option<String> {
+        match self {
+            RecordFormat::Utf8 => std::str::from_utf8(bytes).ok().map(str::to_string),
+            RecordFormat::Tuple { columns } => {
+                // Tuple::from_bytes panics on foreign bytes, and scans meet plenty of those
+                let tuple: Tuple = serde_cbor::from_slice(bytes).ok()?;
+                match columns {
+                    None => Some(tuple.to_csv()),
+                    Some(columns) if columns.len() == tuple.len() => {
+                        let fields: Vec<String> = columns
+                            .iter()
+                            .zip(tuple.field_vals())
+                            .map(|(name, field)| format!("{name}={field}"))
+                            .collect();
+                        Some(fields.join(" "))
+                    }
+                    Some(_) => None,
+                }
+            }
+        }
+    }
+}
+
+/// A selected record and the id it is stored under.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordMatch {
+    pub value_id: ValueId,
+    /// line_number is the record's position in the container scan (1-based).
+    #[serde(flatten)]
+    pub result: LineMatch,
+}
+
+/// Scans a container through the storage API and yields the records matcher selects.
+/// Records that do not decode in `format` are skipped, like binary files in a recursive search.
+pub fn search_container<'a, S: StorageTrait>(
+    sm: &S,
+    container_id: ContainerId,
+    matcher: &'a Matcher,
+    format: &'a RecordFormat,
+) -> impl Iterator<Item = RecordMatch> + 'a
+where
+    S::ValIterator: 'a,
+{
+    sm.get_iterator(container_id, TransactionId::new(), Permissions::ReadOnly)
+        .enumerate()
+        .filter_map(move |(i, (bytes, value_id))| {
+            let line = format.decode(&bytes)?;
+            let result = matcher.line_match(i + 1, &line)?;
+            Some(RecordMatch { value_id, result })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::prelude::*;
+    use memstore::storage_manager::StorageManager;
+
+    fn container(values: Vec<Vec<u8>>) -> StorageManager {
+        let sm = StorageManager::new_test_sm();
+        sm.create_table(1).unwrap();
+        sm.insert_values(1, values, TransactionId::new());
+        sm
+    }
+
+    #[test]
+    fn greps_text_records() {
+        let sm = container(vec![
+            b"to be".to_vec(),
+            vec![0xff, 0xfe],
+            b"or not".to_vec(),
+            b"TO BE".to_vec(),
+        ]);
+        let matcher = Matcher::new("to", true, false);
+        let found: Vec<RecordMatch> =
+            search_container(&sm, 1, &matcher, &RecordFormat::Utf8).collect();
+        let lines: Vec<&str> = found.iter().map(|m| m.result.line.as_str()).collect();
+        assert_eq!(vec!["to be", "TO BE"], lines);
+        assert_eq!(1, found[0].result.line_number);
+        assert_eq!(4, found[1].result.line_number);
+        assert_ne!(found[0].value_id, found[1].value_id);
+    }
+
+    #[test]
+    fn greps_tuples_with_schema() {
+        let tuple = Tuple::new(vec![Field::Int(7), Field::String("to be".to_string())]);
+        let sm = container(vec![tuple.to_bytes(), b"to be".to_vec()]);
+        let matcher = Matcher::new("name=to", false, false);
+        let format = RecordFormat::Tuple {
+            columns: Some(vec!["id".to_string(), "name".to_string()]),
+        };
+        let found: Vec<RecordMatch> = search_container(&sm, 1, &matcher, &format).collect();
+        assert_eq!(1, found.len());
+        assert_eq!("id=7 name=to be", found[0].result.line);
+
+        let format = RecordFormat::Tuple { columns: None };
+        assert_eq!(
+            Some("7,to be".to_string()),
+            format.decode(&tuple.to_bytes())
+        );
+        let format = RecordFormat::Tuple {
+            columns: Some(vec!["id".to_string()]),
+        };
+        assert_eq!(None, format.decode(&tuple.to_bytes()));
+    }
+}
@@ -0,0 +1,56 @@
+use flate2::bufread::MultiGzDecoder;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// First two bytes of every gzip member.
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Whether an input should be decompressed, judged by its `.gz` extension or its leading bytes.
+pub fn is_gzip(path: &Path, head: &[u8]) -> bool {
+    path.extension().is_some_and(|ext| ext == "gz") || head.starts_with(&GZIP_MAGIC)
+}
+
+/// Streams the decompressed lines of a gzip input (concatenated members included).
+pub fn gzip_reader<R: BufRead>(reader: R) -> BufReader<MultiGzDecoder<R>> {
+    BufReader::new(MultiGzDecoder::new(reader))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{search_reader, LineMatch, Matcher};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::{self, Write};
+
+    fn gzip(contents: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(contents.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn detects_by_extension_or_magic() {
+        let compressed = gzip("to be");
+        assert!(is_gzip(Path::new("log.txt.gz"), b""));
+        assert!(is_gzip(Path::new("log.txt"), &compressed));
+        assert!(!is_gzip(Path::new("log.txt"), b"to be"));
+    }
+
+    #[test]
+    fn searches_decompressed_lines() {
+        let mut compressed = gzip("Rust:\nsafe, fast, productive.\n");
+        //a second member, as produced by appending to a .gz log
+        compressed.extend(gzip("Trust me.\n"));
+        let matches: Vec<LineMatch> = search_reader(
+            gzip_reader(&compressed[..]),
+            Matcher::new("rust", true, false),
+        )
+        .collect::<io::Result<_>>()
+        .unwrap();
+        assert_eq!(
+            vec![1, 3],
+            matches.iter().map(|m| m.line_number).collect::<Vec<_>>()
+        );
+    }
+}
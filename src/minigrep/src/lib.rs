@@ -1,3 +1,28 @@
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::io::{self, BufRead};
+
+pub mod db;
+pub mod input;
+pub mod replace;
+pub mod walk;
+
+/// Byte range of one occurrence of the query inside a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A selected line with its 1-based line number and the spans the query matched.
+/// Inverted searches select lines without occurrences, so their spans are empty.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LineMatch {
+    pub line_number: usize,
+    pub line: String,
+    pub spans: Vec<Span>,
+}
+
 pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
     contents
         .lines()
@@ -21,6 +46,359 @@ pub fn search_case_insensitive<'a>(
     results
 }
 
+pub fn search_inverted<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    contents
+        .lines()
+        .filter(|line| !line.contains(query))
+        .collect()
+}
+
+pub fn search_case_insensitive_inverted<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    contents
+        .lines()
+        .filter(|line| !line.to_lowercase().contains(&query))
+        .collect()
+}
+
+/// Selected lines paired with their 1-based line numbers.
+/// With `invert` set, the lines that do NOT contain the query are selected.
+pub fn search_lines<'a>(
+    query: &str,
+    contents: &'a str,
+    ignore_case: bool,
+    invert: bool,
+) -> Vec<(usize, &'a str)> {
+    let matcher = Matcher::new(query, ignore_case, invert);
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| matcher.is_selected(line))
+        .map(|(i, line)| (i + 1, line))
+        .collect()
+}
+
+/// Structured matches for every selected line, used by machine-readable output.
+pub fn search_matches(
+    query: &str,
+    contents: &str,
+    ignore_case: bool,
+    invert: bool,
+) -> Vec<LineMatch> {
+    let matcher = Matcher::new(query, ignore_case, invert);
+    contents
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| matcher.line_match(i + 1, line))
+        .collect()
+}
+
+/// Decides whether a line is selected and where the query occurs in it.
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    pattern: Pattern,
+    ignore_case: bool,
+    invert: bool,
+    mode: MatchMode,
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+    Literal { query: String, lowered: String },
+    Regex { source: String, regex: Regex },
+}
+
+/// How much of the line an occurrence has to cover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+    /// Anywhere inside the line.
+    #[default]
+    Substring,
+    /// Only occurrences delimited by non-word characters or the line ends (-w).
+    WholeWord,
+    /// The entire line must be the occurrence (-x).
+    WholeLine,
+}
+
+impl Matcher {
+    /// Matches query as a literal substring.
+    pub fn new(query: &str, ignore_case: bool, invert: bool) -> Matcher {
+        Matcher {
+            pattern: Pattern::Literal {
+                query: query.to_string(),
+                lowered: query.to_lowercase(),
+            },
+            ignore_case,
+            invert,
+            mode: MatchMode::Substring,
+        }
+    }
+
+    /// Matches query as a regular expression.
+    pub fn regex(query: &str, ignore_case: bool, invert: bool) -> Result<Matcher, regex::Error> {
+        let regex = build_regex(query, ignore_case, MatchMode::Substring)?;
+        Ok(Matcher {
+            pattern: Pattern::Regex {
+                source: query.to_string(),
+                regex,
+            },
+            ignore_case,
+            invert,
+            mode: MatchMode::Substring,
+        })
+    }
+
+    /// The same matcher restricted to whole words or whole lines.
+    pub fn with_mode(mut self, mode: MatchMode) -> Matcher {
+        if let Pattern::Regex { source, regex } = &mut self.pattern {
+            *regex = build_regex(source, self.ignore_case, mode)
+                .expect("anchoring a valid regex keeps it valid");
+        }
+        self.mode = mode;
+        self
+    }
+
+    /// Whether line is part of the output (an occurrence, or no occurrence when inverted).
+    pub fn is_selected(&self, line: &str) -> bool {
+        let found = match (&self.pattern, self.mode) {
+            (Pattern::Literal { query, lowered }, MatchMode::Substring) => {
+                if self.ignore_case {
+                    line.to_lowercase().contains(lowered)
+                } else {
+                    line.contains(query)
+                }
+            }
+            (Pattern::Regex { regex, .. }, _) => regex.is_match(line),
+            _ => !self.occurrences(line).is_empty(),
+        };
+        found != self.invert
+    }
+
+    /// Occurrences of the query in line; always empty for inverted matchers.
+    pub fn spans(&self, line: &str) -> Vec<Span> {
+        if self.invert {
+            return Vec::new();
+        }
+        self.occurrences(line)
+    }
+
+    fn occurrences(&self, line: &str) -> Vec<Span> {
+        match &self.pattern {
+            Pattern::Literal { query, lowered } => match self.mode {
+                MatchMode::Substring => literal_spans(query, line, self.ignore_case),
+                MatchMode::WholeWord => literal_spans(query, line, self.ignore_case)
+                    .into_iter()
+                    .filter(|span| is_word_bounded(line, *span))
+                    .collect(),
+                MatchMode::WholeLine => {
+                    let equal = if self.ignore_case {
+                        line.to_lowercase() == *lowered
+                    } else {
+                        line == query
+                    };
+                    if equal {
+                        vec![Span {
+                            start: 0,
+                            end: line.len(),
+                        }]
+                    } else {
+                        Vec::new()
+                    }
+                }
+            },
+            Pattern::Regex { regex, .. } => regex
+                .find_iter(line)
+                .map(|m| Span {
+                    start: m.start(),
+                    end: m.end(),
+                })
+                .collect(),
+        }
+    }
+
+    /// line with every occurrence substituted by replacement.
+    /// Regex matchers expand capture references such as `$1` or `${name}`.
+    pub fn replace(&self, line: &str, replacement: &str) -> String {
+        if let Pattern::Regex { regex, .. } = &self.pattern {
+            if !self.invert {
+                return regex.replace_all(line, replacement).into_owned();
+            }
+        }
+        let mut out = String::with_capacity(line.len());
+        let mut last = 0;
+        for Span { start, end } in self.spans(line) {
+            out.push_str(&line[last..start]);
+            out.push_str(replacement);
+            last = end;
+        }
+        out.push_str(&line[last..]);
+        out
+    }
+
+    /// The structured match for a selected line, None if line is not selected.
+    pub fn line_match(&self, line_number: usize, line: &str) -> Option<LineMatch> {
+        if !self.is_selected(line) {
+            return None;
+        }
+        Some(LineMatch {
+            line_number,
+            line: line.to_string(),
+            spans: self.spans(line),
+        })
+    }
+}
+
+fn build_regex(query: &str, ignore_case: bool, mode: MatchMode) -> Result<Regex, regex::Error> {
+    let anchored = match mode {
+        MatchMode::Substring => query.to_string(),
+        MatchMode::WholeWord => format!(r"\b(?:{query})\b"),
+        MatchMode::WholeLine => format!("^(?:{query})$"),
+    };
+    RegexBuilder::new(&anchored)
+        .case_insensitive(ignore_case)
+        .build()
+}
+
+fn literal_spans(query: &str, line: &str, ignore_case: bool) -> Vec<Span> {
+    match_spans(query, line, ignore_case)
+        .into_iter()
+        .map(|(start, end)| Span { start, end })
+        .collect()
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+///no word character directly before or after span
+fn is_word_bounded(line: &str, span: Span) -> bool {
+    let before = line[..span.start].chars().next_back();
+    let after = line[span.end..].chars().next();
+    !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+}
+
+/// Searches a line stream, yielding owned matches as they are found.
+/// Only the current line is buffered, so memory use does not depend on the input size.
+pub struct StreamSearch<R> {
+    reader: R,
+    matcher: Matcher,
+    line_number: usize,
+    buf: String,
+}
+
+/// Lazily searches every line read from reader.
+pub fn search_reader<R: BufRead>(reader: R, matcher: Matcher) -> StreamSearch<R> {
+    StreamSearch {
+        reader,
+        matcher,
+        line_number: 0,
+        buf: String::new(),
+    }
+}
+
+impl<R: BufRead> Iterator for StreamSearch<R> {
+    type Item = io::Result<LineMatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            self.line_number += 1;
+            //strip the terminator the same way str::lines does
+            let line = self.buf.strip_suffix('\n').unwrap_or(&self.buf);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if let Some(m) = self.matcher.line_match(self.line_number, line) {
+                return Some(Ok(m));
+            }
+        }
+    }
+}
+
+/// Searches a byte buffer such as a memory-mapped file, splitting lines lazily.
+/// Lines are borrowed from the buffer until they are selected.
+pub struct SliceSearch<'a> {
+    rest: &'a [u8],
+    matcher: Matcher,
+    line_number: usize,
+}
+
+/// Lazily searches every line of bytes.
+pub fn search_bytes(bytes: &[u8], matcher: Matcher) -> SliceSearch<'_> {
+    SliceSearch {
+        rest: bytes,
+        matcher,
+        line_number: 0,
+    }
+}
+
+impl Iterator for SliceSearch<'_> {
+    type Item = io::Result<LineMatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.rest.is_empty() {
+            let (line, rest) = match self.rest.iter().position(|&b| b == b'\n') {
+                Some(i) => (&self.rest[..i], &self.rest[i + 1..]),
+                None => (self.rest, &self.rest[self.rest.len()..]),
+            };
+            self.rest = rest;
+            self.line_number += 1;
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let line = match std::str::from_utf8(line) {
+                Ok(line) => line,
+                Err(e) => {
+                    //stop at the first bad line like the streaming reader does
+                    self.rest = &[];
+                    return Some(Err(io::Error::new(io::ErrorKind::InvalidData, e)));
+                }
+            };
+            if let Some(m) = self.matcher.line_match(self.line_number, line) {
+                return Some(Ok(m));
+            }
+        }
+        None
+    }
+}
+
+/// Byte ranges of every non-overlapping occurrence of query in line, for highlighting.
+pub fn match_spans(query: &str, line: &str, ignore_case: bool) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    if query.is_empty() {
+        return spans;
+    }
+    let mut start = 0;
+    while start < line.len() {
+        match match_end(query, &line[start..], ignore_case) {
+            Some(len) => {
+                spans.push((start, start + len));
+                start += len;
+            }
+            None => {
+                //advance one char so start always stays on a char boundary
+                start += line[start..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+    }
+    spans
+}
+
+///byte length of the prefix of hay matching query or None
+fn match_end(query: &str, hay: &str, ignore_case: bool) -> Option<usize> {
+    if !ignore_case {
+        return hay.starts_with(query).then_some(query.len());
+    }
+    let mut hay_chars = hay.char_indices();
+    for q in query.chars() {
+        let (_, h) = hay_chars.next()?;
+        if !q.to_lowercase().eq(h.to_lowercase()) {
+            return None;
+        }
+    }
+    Some(hay_chars.next().map_or(hay.len(), |(i, _)| i))
+}
 
 #[cfg(test)]
 mod tests {
@@ -52,4 +430,169 @@ Trust me.";
             search_case_insensitive(query, contents)
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn inverted() {
+        let query = "duct";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape.";
+
+        assert_eq!(
+            vec!["Rust:", "Pick three.", "Duct tape."],
+            search_inverted(query, contents)
+        );
+        assert_eq!(
+            vec!["Rust:", "Pick three."],
+            search_case_insensitive_inverted(query, contents)
+        );
+    }
+
+    #[test]
+    fn spans() {
+        assert_eq!(
+            vec![(0, 2), (9, 11)],
+            match_spans("to", "to be or to", false)
+        );
+        assert_eq!(
+            vec![(0, 2), (9, 11)],
+            match_spans("TO", "To be or tO", true)
+        );
+        assert!(match_spans("TO", "To be or tO", false).is_empty());
+        assert_eq!(vec![(2, 4)], match_spans("ab", "éab", false));
+    }
+
+    #[test]
+    fn structured_matches() {
+        let contents = "Rust:\nTrust me, rust.";
+        assert_eq!(
+            vec![LineMatch {
+                line_number: 2,
+                line: "Trust me, rust.".to_string(),
+                spans: vec![Span { start: 1, end: 5 }, Span { start: 10, end: 14 }],
+            }],
+            search_matches("rust", contents, false, false)
+        );
+        let inverted = search_matches("trust", contents, true, true);
+        assert_eq!(1, inverted[0].line_number);
+        assert!(inverted[0].spans.is_empty());
+    }
+
+    #[test]
+    fn streaming_matches_buffered() {
+        let contents = "Rust:\r\nsafe, fast, productive.\nPick three.\nTrust me.\n";
+        let streamed: Vec<LineMatch> =
+            search_reader(contents.as_bytes(), Matcher::new("rUsT", true, false))
+                .collect::<io::Result<_>>()
+                .unwrap();
+        assert_eq!(search_matches("rUsT", contents, true, false), streamed);
+        assert_eq!(
+            vec![1, 4],
+            streamed.iter().map(|m| m.line_number).collect::<Vec<_>>()
+        );
+        assert_eq!("Rust:", streamed[0].line);
+    }
+
+    #[test]
+    fn streaming_reports_invalid_utf8() {
+        let bytes: &[u8] = b"ok\n\xff\xfe\n";
+        let mut iter = search_reader(bytes, Matcher::new("o", false, false));
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn byte_search_matches_streaming() {
+        let contents = "Rust:\r\nsafe, fast, productive.\nPick three.\nTrust me.";
+        let matcher = Matcher::new("rust", true, false);
+        let sliced: Vec<LineMatch> = search_bytes(contents.as_bytes(), matcher.clone())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        let streamed: Vec<LineMatch> = search_reader(contents.as_bytes(), matcher)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(streamed, sliced);
+        assert_eq!(2, sliced.len());
+
+        let mut iter = search_bytes(b"ok\n\xff\nok", Matcher::new("o", false, false));
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn regex_matcher() {
+        let matcher = Matcher::regex(r"(\w+)@(\w+)", true, false).unwrap();
+        assert!(matcher.is_selected("mail ME@host now"));
+        assert_eq!(
+            vec![Span { start: 5, end: 12 }],
+            matcher.spans("mail ME@host now")
+        );
+        assert_eq!(
+            "mail host.ME now",
+            matcher.replace("mail ME@host now", "$2.$1")
+        );
+        assert!(Matcher::regex("(", false, false).is_err());
+    }
+
+    #[test]
+    fn whole_word_and_line() {
+        let word = Matcher::new("to", true, false).with_mode(MatchMode::WholeWord);
+        assert!(word.is_selected("To be"));
+        assert!(!word.is_selected("tomato, today"));
+        assert_eq!(
+            vec![Span { start: 13, end: 15 }],
+            word.spans("tomato, to_o to")
+        );
+        assert!(Matcher::new("to", false, true)
+            .with_mode(MatchMode::WholeWord)
+            .is_selected("tomato"));
+
+        let line = Matcher::new("pick three.", true, false).with_mode(MatchMode::WholeLine);
+        assert!(line.is_selected("Pick three."));
+        assert!(!line.is_selected("Pick three. Again"));
+
+        let regex = Matcher::regex("t[a-z]+", false, false)
+            .unwrap()
+            .with_mode(MatchMode::WholeWord);
+        assert_eq!(
+            vec![Span { start: 6, end: 8 }, Span { start: 9, end: 12 }],
+            regex.spans("stop, to top")
+        );
+        let regex = Matcher::regex("a|ab", false, false)
+            .unwrap()
+            .with_mode(MatchMode::WholeLine);
+        assert!(regex.is_selected("ab"));
+    }
+
+    #[test]
+    fn literal_replace() {
+        let matcher = Matcher::new("to", true, false);
+        assert_eq!(
+            "X be or not X be",
+            matcher.replace("To be or not to be", "X")
+        );
+        //the replacement is literal, capture syntax is not expanded
+        assert_eq!("$1 be", matcher.replace("to be", "$1"));
+    }
+
+    #[test]
+    fn line_numbers_with_invert() {
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Duct tape.";
+
+        assert_eq!(
+            vec![(2, "safe, fast, productive."), (4, "Duct tape.")],
+            search_lines("DUCT", contents, true, false)
+        );
+        assert_eq!(
+            vec![(1, "Rust:"), (3, "Pick three.")],
+            search_lines("DUCT", contents, true, true)
+        );
+    }
+}
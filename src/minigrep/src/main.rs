@@ -1,71 +1,604 @@
-use std::env;
-use std::fs;
-use std::process;
+use clap::{Parser, ValueEnum};
+use common::prelude::ContainerId;
+use heapstore::storage_manager::StorageManager;
+use memmap2::Mmap;
+use minigrep::db::{search_container, RecordFormat, RecordMatch};
+use minigrep::input::{gzip_reader, is_gzip};
+use minigrep::replace::replace_in_place;
+use minigrep::walk::{walk, FileFilter};
+use minigrep::{search_bytes, search_reader, LineMatch, MatchMode, Matcher, Span};
+use serde::Serialize;
 use std::error::Error;
-use minigrep::{search, search_case_insensitive};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
+//ANSI escapes used to highlight matches
+const COLOR_MATCH: &str = "\x1b[1;31m";
+const COLOR_RESET: &str = "\x1b[0m";
+//files at least this large are memory-mapped even without --mmap
+const MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
 
-fn main() {
-    // let args: Vec<String> = env::args().collect();
+// grep-compatible exit statuses: 0 selected a line, 1 selected nothing, 2 error
+fn main() -> ExitCode {
+    let config = Config::parse();
 
-    let config = Config::build(env::args()).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
-    });
+    match run(config) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Whether any line was selected (or replaced, with --in-place).
+fn run(config: Config) -> Result<bool, Box<dyn Error>> {
+    let mut filter = FileFilter::new().respect_gitignore(config.gitignore);
+    for glob in &config.include {
+        filter = filter.include(glob)?;
+    }
+    for glob in &config.exclude {
+        filter = filter.exclude(glob)?;
+    }
 
-    // println!("Searching for {}", config.query);
-    // println!("In file {}", config.file_path);
+    let mut files: Vec<PathBuf> = Vec::new();
+    for path in &config.file_paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            if !config.recursive {
+                return Err(format!("{} is a directory (use -r)", path.display()).into());
+            }
+            files.extend(walk(path, &filter)?);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
 
-    if let Err(e) = run(config) {
-        eprintln!("Application error: {e}");
-        process::exit(1);
+    let multiple_files = files.len() > 1 || config.recursive;
+    let matcher = if config.regex {
+        Matcher::regex(&config.query, config.ignore_case, config.invert_match)?
+    } else {
+        Matcher::new(&config.query, config.ignore_case, config.invert_match)
+    };
+    let matcher = if config.line_regexp {
+        matcher.with_mode(MatchMode::WholeLine)
+    } else if config.word_regexp {
+        matcher.with_mode(MatchMode::WholeWord)
+    } else {
+        matcher
+    };
+
+    if let Some(storage_dir) = &config.db {
+        return search_db(&config, storage_dir, &matcher);
+    }
+
+    if config.in_place {
+        let replacement = config
+            .replace
+            .as_deref()
+            .ok_or("--in-place requires --replace")?;
+        let mut replaced = 0;
+        for file_path in &files {
+            let mut reader = BufReader::new(File::open(file_path)?);
+            if is_gzip(file_path, reader.fill_buf()?) {
+                let msg = format!(
+                    "{} is compressed, cannot edit in place",
+                    file_path.display()
+                );
+                return Err(msg.into());
+            }
+            drop(reader);
+            replaced += match replace_in_place(file_path, &matcher, replacement) {
+                Ok(changed) => changed,
+                // a binary found while walking directories is left as it is
+                Err(e) if config.recursive && e.kind() == io::ErrorKind::InvalidData => {
+                    warn_skipped(file_path, &e);
+                    0
+                }
+                Err(e) => return Err(e.into()),
+            };
+        }
+        return Ok(replaced > 0);
+    }
+
+    let printer = Printer {
+        json: config.json,
+        line_number: config.line_number,
+        color: !config.json && config.color.enabled(),
+        with_file_name: multiple_files,
+    };
+
+    let mut matched = false;
+    for file_path in &files {
+        let mut reader = BufReader::new(File::open(file_path)?);
+        // compressed inputs always stream through the decoder
+        let compressed = is_gzip(file_path, reader.fill_buf()?);
+        let mapped = if compressed {
+            None
+        } else {
+            map_file(reader.get_ref(), config.mmap)
+        };
+        let results: Box<dyn Iterator<Item = io::Result<LineMatch>>> = match &mapped {
+            Some(map) => Box::new(search_bytes(map, matcher.clone())),
+            None if compressed => Box::new(search_reader(gzip_reader(reader), matcher.clone())),
+            None => Box::new(search_reader(reader, matcher.clone())),
+        };
+        // the searches are lazy, so stopping early also stops reading the file
+        let limit = config.max_count.unwrap_or(usize::MAX);
+        let mut selected = 0;
+        for result in results.take(limit) {
+            let line_match = match result {
+                Ok(line_match) => line_match,
+                // binaries found while walking directories are skipped rather than failing the run
+                Err(e) if config.recursive && e.kind() == io::ErrorKind::InvalidData => {
+                    warn_skipped(file_path, &e);
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            selected += 1;
+            if config.quiet {
+                return Ok(true);
+            }
+            if !config.count {
+                let line_match = substitute(&config, &matcher, line_match);
+                printer.print_match(file_path, &line_match)?;
+            }
+        }
+        if config.count && !config.quiet {
+            printer.print_count(file_path, selected)?;
+        }
+        matched |= selected > 0;
     }
+
+    Ok(matched)
 }
 
-fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.file_path)?;
+/// Tells the user on stderr why the rest of a file found while walking was not searched.
+fn warn_skipped(file_path: &Path, e: &io::Error) {
+    eprintln!("Skipping {}: {e}", file_path.display());
+}
 
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
+/// Greps the records of a heapstore container, printing the ValueId of each selected record.
+fn search_db(
+    config: &Config,
+    storage_dir: &Path,
+    matcher: &Matcher,
+) -> Result<bool, Box<dyn Error>> {
+    if !storage_dir.is_dir() {
+        return Err(format!("{} is not a storage directory", storage_dir.display()).into());
+    }
+    let container_id = config.container.ok_or("--db requires --container")?;
+    let format = if config.tuples {
+        RecordFormat::Tuple {
+            columns: config.schema.clone(),
+        }
     } else {
-        search(&config.query, &contents)
+        RecordFormat::Utf8
     };
+    let printer = Printer {
+        json: config.json,
+        line_number: config.line_number,
+        color: !config.json && config.color.enabled(),
+        with_file_name: false,
+    };
+
+    // the directory may belong to a running database, which a grep must leave as it found it
+    let sm = StorageManager::open_read_only(storage_dir)?;
+    let limit = config.max_count.unwrap_or(usize::MAX);
+    let mut selected = 0;
+    for record in search_container(&sm, container_id, matcher, &format).take(limit) {
+        selected += 1;
+        if config.quiet {
+            return Ok(true);
+        }
+        if !config.count {
+            let record = RecordMatch {
+                result: substitute(config, matcher, record.result),
+                ..record
+            };
+            printer.print_record(&record)?;
+        }
+    }
+    if config.count && !config.quiet {
+        printer.print_count(storage_dir, selected)?;
+    }
+    Ok(selected > 0)
+}
+
+/// Applies --replace to a selected line before it is printed.
+fn substitute(config: &Config, matcher: &Matcher, line_match: LineMatch) -> LineMatch {
+    match &config.replace {
+        Some(replacement) => LineMatch {
+            line: matcher.replace(&line_match.line, replacement),
+            // spans refer to the original text, not the substituted one
+            spans: Vec::new(),
+            ..line_match
+        },
+        None => line_match,
+    }
+}
+
+/// Memory-maps large files (or any file with --mmap), None to fall back to streaming.
+fn map_file(file: &File, force: bool) -> Option<Mmap> {
+    let len = file.metadata().ok()?.len();
+    if len == 0 || !(force || len >= MMAP_THRESHOLD) {
+        return None;
+    }
+    // Safety: the map is only read while searching; a file truncated concurrently by
+    // another process can still fault, the same caveat grep-like tools accept.
+    unsafe { Mmap::map(file) }.ok()
+}
+
+/// Formats selected lines as plain text or JSON as they stream out of the search.
+struct Printer {
+    json: bool,
+    line_number: bool,
+    color: bool,
+    // prefix every output line with the file name once more than one file is searched
+    with_file_name: bool,
+}
+
+impl Printer {
+    fn print_match(&self, file_path: &Path, result: &LineMatch) -> Result<(), Box<dyn Error>> {
+        if self.json {
+            let json = JsonMatch {
+                file: file_path.display().to_string(),
+                result,
+            };
+            println!("{}", serde_json::to_string(&json)?);
+            return Ok(());
+        }
+        let line = if self.color {
+            highlight(&result.line, &result.spans)
+        } else {
+            result.line.clone()
+        };
+        let prefix = self.prefix(file_path);
+        if self.line_number {
+            println!("{prefix}{}:{line}", result.line_number);
+        } else {
+            println!("{prefix}{line}");
+        }
+        Ok(())
+    }
 
-    for line in results {
-        println!("{line}");
+    fn print_record(&self, record: &RecordMatch) -> Result<(), Box<dyn Error>> {
+        if self.json {
+            println!("{}", serde_json::to_string(record)?);
+            return Ok(());
+        }
+        let result = &record.result;
+        let line = if self.color {
+            highlight(&result.line, &result.spans)
+        } else {
+            result.line.clone()
+        };
+        if self.line_number {
+            println!("{:?}:{}:{line}", record.value_id, result.line_number);
+        } else {
+            println!("{:?}:{line}", record.value_id);
+        }
+        Ok(())
+    }
+
+    fn print_count(&self, file_path: &Path, count: usize) -> Result<(), Box<dyn Error>> {
+        if self.json {
+            let file = file_path.display().to_string();
+            println!("{}", serde_json::to_string(&JsonCount { file, count })?);
+        } else {
+            println!("{}{count}", self.prefix(file_path));
+        }
+        Ok(())
+    }
+
+    fn prefix(&self, file_path: &Path) -> String {
+        if self.with_file_name {
+            format!("{}:", file_path.display())
+        } else {
+            String::new()
+        }
     }
+}
+
+/// One JSON object is printed per selected line (or per file with --count).
+#[derive(Serialize)]
+struct JsonMatch<'a> {
+    file: String,
+    #[serde(flatten)]
+    result: &'a LineMatch,
+}
 
-    Ok(())
+#[derive(Serialize)]
+struct JsonCount {
+    file: String,
+    count: usize,
 }
 
+fn highlight(line: &str, spans: &[Span]) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut last = 0;
+    for &Span { start, end } in spans {
+        out.push_str(&line[last..start]);
+        out.push_str(COLOR_MATCH);
+        out.push_str(&line[start..end]);
+        out.push_str(COLOR_RESET);
+        last = end;
+    }
+    out.push_str(&line[last..]);
+    out
+}
+
+/// Search for lines containing a query string
+#[derive(Parser, Debug)]
+#[clap(name = "minigrep")]
 pub struct Config {
+    /// String to search for
     pub query: String,
-    pub file_path: String,
+    /// Files to search (directories need -r)
+    #[clap(required_unless_present = "db")]
+    pub file_paths: Vec<String>,
+    /// Search the records of a heapstore container instead of files
+    #[clap(
+        long = "db",
+        value_name = "STORAGE_DIR",
+        requires = "container",
+        conflicts_with = "file_paths"
+    )]
+    pub db: Option<PathBuf>,
+    /// Container to search with --db
+    #[clap(long = "container", value_name = "ID", requires = "db")]
+    pub container: Option<ContainerId>,
+    /// Decode --db records as tuples instead of UTF-8 text
+    #[clap(long = "tuples", requires = "db")]
+    pub tuples: bool,
+    /// Comma separated column names used to label tuple fields
+    #[clap(
+        long = "schema",
+        value_name = "COLUMNS",
+        value_delimiter = ',',
+        requires = "tuples"
+    )]
+    pub schema: Option<Vec<String>>,
+    /// Ignore case distinctions
+    #[clap(short = 'i', long = "ignore-case")]
     pub ignore_case: bool,
+    /// Select lines that do not contain the query
+    #[clap(short = 'v', long = "invert-match")]
+    pub invert_match: bool,
+    /// Only match whole words
+    #[clap(short = 'w', long = "word-regexp")]
+    pub word_regexp: bool,
+    /// Only select lines that match the query entirely
+    #[clap(short = 'x', long = "line-regexp", conflicts_with = "word_regexp")]
+    pub line_regexp: bool,
+    /// Prefix each line with its line number
+    #[clap(short = 'n', long = "line-number")]
+    pub line_number: bool,
+    /// Print only the number of selected lines per file
+    #[clap(short = 'c', long = "count")]
+    pub count: bool,
+    /// Stop reading a file after NUM selected lines
+    #[clap(short = 'm', long = "max-count", value_name = "NUM")]
+    pub max_count: Option<usize>,
+    /// Print nothing and exit with status 0 as soon as any line is selected
+    #[clap(short = 'q', long = "quiet", alias = "silent")]
+    pub quiet: bool,
+    /// Search directories recursively
+    #[clap(short = 'r', long = "recursive")]
+    pub recursive: bool,
+    /// Only search files whose name matches GLOB
+    #[clap(long = "include", value_name = "GLOB")]
+    pub include: Vec<String>,
+    /// Skip files and directories whose name matches GLOB
+    #[clap(long = "exclude", value_name = "GLOB")]
+    pub exclude: Vec<String>,
+    /// Skip paths ignored by .gitignore files
+    #[clap(long = "gitignore")]
+    pub gitignore: bool,
+    /// Treat the query as a regular expression
+    #[clap(short = 'E', long = "regex")]
+    pub regex: bool,
+    /// Print selected lines with every occurrence replaced ($1 etc. refer to regex groups)
+    #[clap(long = "replace", value_name = "REPLACEMENT")]
+    pub replace: Option<String>,
+    /// Rewrite the files with the replacements applied instead of printing
+    #[clap(long = "in-place", requires = "replace", conflicts_with = "db")]
+    pub in_place: bool,
+    /// Memory-map files instead of streaming them (automatic for very large files)
+    #[clap(long = "mmap")]
+    pub mmap: bool,
+    /// Print one JSON object per selected line instead of plain text
+    #[clap(long = "json")]
+    pub json: bool,
+    /// Highlight matches
+    #[clap(long = "color", value_enum, default_value = "auto")]
+    pub color: ColorChoice,
 }
 
-impl Config {
-    fn build(
-        mut args: impl Iterator<Item = String>,
-    ) -> Result<Config, &'static str> {
-        args.next();
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ColorChoice {
+    Always,
+    Never,
+    Auto,
+}
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
-        };
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => io::stdout().is_terminal(),
+        }
+    }
+}
 
-        let file_path = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file path"),
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::prelude::TransactionId;
+    use common::storage_trait::StorageTrait;
+    use std::fs;
+
+    #[test]
+    fn parses_combined_and_long_flags() {
+        let config = Config::try_parse_from([
+            "minigrep",
+            "-inv",
+            "--recursive",
+            "--include=*.rs",
+            "--color",
+            "never",
+            "to",
+            "a",
+            "b",
+        ])
+        .unwrap();
+        assert!(
+            config.ignore_case && config.line_number && config.invert_match && config.recursive
+        );
+        assert!(!config.count);
+        assert_eq!("to", config.query);
+        assert_eq!(vec!["a", "b"], config.file_paths);
+        assert_eq!(vec!["*.rs"], config.include);
+    }
+
+    #[test]
+    fn requires_query_and_path() {
+        assert!(Config::try_parse_from(["minigrep", "to"]).is_err());
+        assert!(Config::try_parse_from(["minigrep", "-z", "to", "a"]).is_err());
+    }
+
+    #[test]
+    fn parses_max_count() {
+        let config = Config::try_parse_from(["minigrep", "-qm", "2", "to", "a"]).unwrap();
+        assert!(config.quiet);
+        assert_eq!(Some(2), config.max_count);
+        assert!(Config::try_parse_from(["minigrep", "-m", "two", "to", "a"]).is_err());
+    }
+
+    #[test]
+    fn parses_db_mode() {
+        let config = Config::try_parse_from([
+            "minigrep",
+            "--db",
+            "data",
+            "--container",
+            "3",
+            "--tuples",
+            "--schema",
+            "id,name",
+            "to",
+        ])
+        .unwrap();
+        assert_eq!(Some(PathBuf::from("data")), config.db);
+        assert_eq!(Some(3), config.container);
+        assert_eq!(
+            Some(vec!["id".to_string(), "name".to_string()]),
+            config.schema
+        );
+        assert!(config.file_paths.is_empty());
+        assert!(Config::try_parse_from(["minigrep", "--db", "data", "to"]).is_err());
+        assert!(Config::try_parse_from(["minigrep", "--schema", "id", "to", "a"]).is_err());
+    }
 
-        let ignore_case = env::var("IGNORE_CASE").is_ok();
+    #[test]
+    fn db_search_leaves_storage_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let tid = TransactionId::new();
+        let sm = StorageManager::new(dir.path());
+        sm.create_table(1).unwrap();
+        sm.insert_values(1, vec![b"to be".to_vec(), b"or not".to_vec()], tid);
+        sm.commit_transaction(tid).unwrap();
+        sm.flush_all().unwrap();
+        let db = dir.path().to_str().unwrap();
+        let config =
+            Config::try_parse_from(["minigrep", "-c", "--db", db, "--container", "1", "to"])
+                .unwrap();
+        let matcher = Matcher::new("to", false, false);
+        let wal_path = dir.path().join("wal");
+        let shutdown_mark = dir.path().join("clean_shutdown");
 
-        Ok(Config {
-            query,
-            file_path,
-            ignore_case,
-        })
+        //grepping a running database leaves its log for it to recover from
+        let wal = fs::read(&wal_path).unwrap();
+        assert!(search_db(&config, dir.path(), &matcher).unwrap());
+        assert_eq!(wal, fs::read(&wal_path).unwrap());
+        assert!(!shutdown_mark.exists());
+
+        //and the mark of a clean shutdown stays for its next open
+        sm.shutdown();
+        drop(sm);
+        let wal = fs::read(&wal_path).unwrap();
+        let mark = fs::read(&shutdown_mark).unwrap();
+        assert!(search_db(&config, dir.path(), &matcher).unwrap());
+        assert_eq!(wal, fs::read(&wal_path).unwrap());
+        assert_eq!(mark, fs::read(&shutdown_mark).unwrap());
+
+        //an empty heap file is reported rather than given a header
+        let heap_file = dir.path().join("1.hf");
+        fs::write(&heap_file, []).unwrap();
+        assert!(search_db(&config, dir.path(), &matcher).is_err());
+        assert_eq!(0, fs::metadata(&heap_file).unwrap().len());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn recursive_search_skips_binary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = dir.path().join("a.txt");
+        let binary = dir.path().join("b.bin");
+        fs::write(&text, "to be\n").unwrap();
+        fs::write(&binary, b"to\n\xff\xfe to\n").unwrap();
+        let root = dir.path().to_str().unwrap();
+
+        let config = Config::try_parse_from(["minigrep", "-rc", "to", root]).unwrap();
+        assert!(run(config).unwrap());
+
+        let config = Config::try_parse_from([
+            "minigrep",
+            "-r",
+            "--replace",
+            "or",
+            "--in-place",
+            "to",
+            root,
+        ])
+        .unwrap();
+        assert!(run(config).unwrap());
+        assert_eq!("or be\n", fs::read_to_string(&text).unwrap());
+        assert_eq!(b"to\n\xff\xfe to\n".to_vec(), fs::read(&binary).unwrap());
+        assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+
+        //a binary named on its own is still an error
+        let path = binary.to_str().unwrap();
+        let config = Config::try_parse_from(["minigrep", "to", path]).unwrap();
+        assert!(run(config).is_err());
+    }
+
+    #[test]
+    fn highlights_spans() {
+        assert_eq!(
+            format!("{COLOR_MATCH}to{COLOR_RESET} be"),
+            highlight("to be", &[Span { start: 0, end: 2 }])
+        );
+    }
+
+    #[test]
+    fn json_objects_include_file() {
+        let result = LineMatch {
+            line_number: 3,
+            line: "to be".to_string(),
+            spans: vec![Span { start: 0, end: 2 }],
+        };
+        let json = JsonMatch {
+            file: "poem.txt".to_string(),
+            result: &result,
+        };
+        assert_eq!(
+            r#"{"file":"poem.txt","line_number":3,"line":"to be","spans":[{"start":0,"end":2}]}"#,
+            serde_json::to_string(&json).unwrap()
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use crate::Matcher;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Rewrites path with every occurrence substituted and returns how many lines changed.
+/// The new contents are written to a temporary file next to path and renamed over it,
+/// so readers see either the old or the new file, never a half-written one.
+pub fn replace_in_place(path: &Path, matcher: &Matcher, replacement: &str) -> io::Result<usize> {
+    let tmp_path = temp_path(path);
+    let result = write_replaced(path, &tmp_path, matcher, replacement).and_then(|changed| {
+        fs::set_permissions(&tmp_path, fs::metadata(path)?.permissions())?;
+        fs::rename(&tmp_path, path)?;
+        Ok(changed)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.minigrep-tmp"))
+}
+
+fn write_replaced(
+    path: &Path,
+    tmp_path: &Path,
+    matcher: &Matcher,
+    replacement: &str,
+) -> io::Result<usize> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let tmp = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(tmp_path)?;
+    let mut writer = BufWriter::new(tmp);
+    let mut changed = 0;
+    let mut buf = String::new();
+    loop {
+        buf.clear();
+        if reader.read_line(&mut buf)? == 0 {
+            break;
+        }
+        //keep each line's own terminator so untouched lines are byte-identical
+        let body_len = buf.trim_end_matches(['\r', '\n']).len();
+        let (line, terminator) = buf.split_at(body_len);
+        if matcher.is_selected(line) {
+            let replaced = matcher.replace(line, replacement);
+            if replaced != line {
+                changed += 1;
+            }
+            writer.write_all(replaced.as_bytes())?;
+        } else {
+            writer.write_all(line.as_bytes())?;
+        }
+        writer.write_all(terminator.as_bytes())?;
+    }
+    let tmp = writer.into_inner().map_err(|e| e.into_error())?;
+    tmp.sync_all()?;
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_file_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "key=1\r\nother\nkey=22").unwrap();
+
+        let matcher = Matcher::regex(r"key=(\d+)", false, false).unwrap();
+        assert_eq!(2, replace_in_place(&path, &matcher, "value=$1").unwrap());
+        assert_eq!(
+            "value=1\r\nother\nvalue=22",
+            fs::read_to_string(&path).unwrap()
+        );
+        //only the rewritten file is left behind
+        assert_eq!(1, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn missing_file_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let matcher = Matcher::new("a", false, false);
+        assert!(replace_in_place(&dir.path().join("missing"), &matcher, "b").is_err());
+        assert_eq!(0, fs::read_dir(dir.path()).unwrap().count());
+    }
+}
@@ -0,0 +1,274 @@
+use glob::{MatchOptions, Pattern, PatternError};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Decides which files a directory walk hands to the searcher.
+/// `--include` globs whitelist file names, `--exclude` globs drop files and
+/// directories by name, and `.gitignore` rules are honored when enabled.
+#[derive(Default)]
+pub struct FileFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    respect_gitignore: bool,
+}
+
+impl FileFilter {
+    pub fn new() -> FileFilter {
+        FileFilter::default()
+    }
+
+    /// Only search files whose name matches `glob` (may be given several times).
+    pub fn include(mut self, glob: &str) -> Result<FileFilter, PatternError> {
+        self.include.push(Pattern::new(glob)?);
+        Ok(self)
+    }
+
+    /// Skip files and directories whose name matches `glob`.
+    pub fn exclude(mut self, glob: &str) -> Result<FileFilter, PatternError> {
+        self.exclude.push(Pattern::new(glob)?);
+        Ok(self)
+    }
+
+    /// Honor `.gitignore` files found while walking (and skip `.git` itself).
+    pub fn respect_gitignore(mut self, respect: bool) -> FileFilter {
+        self.respect_gitignore = respect;
+        self
+    }
+
+    fn excluded(&self, name: &str) -> bool {
+        self.exclude.iter().any(|p| p.matches(name))
+    }
+
+    fn included(&self, name: &str) -> bool {
+        self.include.is_empty() || self.include.iter().any(|p| p.matches(name))
+    }
+
+    /// Whether a file passes the include/exclude globs (gitignore is applied by `walk`).
+    pub fn accepts_file(&self, path: &Path) -> bool {
+        let name = file_name(path);
+        !self.excluded(&name) && self.included(&name)
+    }
+}
+
+/// Every file under `root` that passes `filter`, in sorted order.
+/// A plain file root is returned as-is without applying the filter.
+pub fn walk(root: &Path, filter: &FileFilter) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if root.is_dir() {
+        let mut ignores = Vec::new();
+        walk_dir(root, filter, &mut ignores, &mut files)?;
+    } else {
+        files.push(root.to_path_buf());
+    }
+    Ok(files)
+}
+
+fn walk_dir(
+    dir: &Path,
+    filter: &FileFilter,
+    ignores: &mut Vec<Gitignore>,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    let pushed = if filter.respect_gitignore {
+        match Gitignore::load(dir)? {
+            Some(gitignore) => {
+                ignores.push(gitignore);
+                true
+            }
+            None => false,
+        }
+    } else {
+        false
+    };
+
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+
+    for path in entries {
+        let is_dir = path.is_dir();
+        let name = file_name(&path);
+        if filter.excluded(&name) {
+            continue;
+        }
+        if filter.respect_gitignore && (name == ".git" || is_ignored(ignores, &path, is_dir)) {
+            continue;
+        }
+        if is_dir {
+            walk_dir(&path, filter, ignores, files)?;
+        } else if filter.included(&name) {
+            files.push(path);
+        }
+    }
+
+    if pushed {
+        ignores.pop();
+    }
+    Ok(())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Last matching rule wins, with deeper .gitignore files consulted after their parents.
+fn is_ignored(ignores: &[Gitignore], path: &Path, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for gitignore in ignores {
+        if let Some(negated) = gitignore.matched(path, is_dir) {
+            ignored = !negated;
+        }
+    }
+    ignored
+}
+
+/// The rules of one .gitignore file, relative to the directory holding it.
+struct Gitignore {
+    base: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+struct IgnoreRule {
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+    //patterns containing a slash match the path relative to base, others only the name
+    anchored: bool,
+}
+
+impl Gitignore {
+    fn load(dir: &Path) -> io::Result<Option<Gitignore>> {
+        let path = dir.join(".gitignore");
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path)?;
+        Ok(Some(Gitignore::parse(dir, &contents)))
+    }
+
+    fn parse(base: &Path, contents: &str) -> Gitignore {
+        let rules = contents
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim_end();
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let (dir_only, line) = match line.strip_suffix('/') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let anchored = line.contains('/');
+                let line = line.trim_start_matches('/');
+                //unparsable lines are skipped like git does
+                let pattern = Pattern::new(line).ok()?;
+                Some(IgnoreRule {
+                    pattern,
+                    negated,
+                    dir_only,
+                    anchored,
+                })
+            })
+            .collect();
+        Gitignore {
+            base: base.to_path_buf(),
+            rules,
+        }
+    }
+
+    /// Some(negated) for the last rule matching path, None if no rule matches.
+    fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.base).ok()?;
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        let name = file_name(path);
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                if rule.dir_only && !is_dir {
+                    return false;
+                }
+                if rule.anchored {
+                    rule.pattern.matches_path_with(relative, options)
+                } else {
+                    rule.pattern.matches(&name)
+                }
+            })
+            .map(|rule| rule.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "to be or not to be\n").unwrap();
+    }
+
+    fn names(root: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .into_iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn include_and_exclude_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        touch(&root.join("a.rs"));
+        touch(&root.join("b.txt"));
+        touch(&root.join("src/c.rs"));
+        touch(&root.join("src/skip_me.rs"));
+
+        let filter = FileFilter::new()
+            .include("*.rs")
+            .unwrap()
+            .exclude("skip_*")
+            .unwrap();
+        let files = walk(root, &filter).unwrap();
+        assert_eq!(vec!["a.rs", "src/c.rs"], names(root, files));
+    }
+
+    #[test]
+    fn gitignore_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(
+            root.join(".gitignore"),
+            "target/\n*.bin\n!keep.bin\n/top.txt\n",
+        )
+        .unwrap();
+        touch(&root.join("target/debug/out.txt"));
+        touch(&root.join("data.bin"));
+        touch(&root.join("keep.bin"));
+        touch(&root.join("top.txt"));
+        touch(&root.join("sub/top.txt"));
+        touch(&root.join("sub/notes.txt"));
+        fs::write(root.join("sub/.gitignore"), "notes.txt\n").unwrap();
+
+        let filter = FileFilter::new().respect_gitignore(true);
+        let files = walk(root, &filter).unwrap();
+        assert_eq!(
+            vec![".gitignore", "keep.bin", "sub/.gitignore", "sub/top.txt"],
+            names(root, files)
+        );
+
+        //without gitignore awareness everything is searched
+        let files = walk(root, &FileFilter::new()).unwrap();
+        assert_eq!(8, files.len());
+    }
+}
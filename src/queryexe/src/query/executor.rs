@@ -4,6 +4,7 @@ use crate::Managers;
 
 use common::data_reader::DataReader;
 use common::prelude::*;
+use common::storage_trait::StorageTrait;
 use common::ConvertedResult;
 use common::QueryResult;
 use sqlparser::ast::Values;
@@ -77,14 +78,32 @@ impl Executor {
             )));
         }
 
-        let insert_count = mutator::insert_validated_tuples(
+        let inserted = mutator::insert_validated_tuples(
             *table_id,
             &validated_converted_result.converted,
             txn_id,
             self.managers,
-        )?;
+        );
+        self.complete(txn_id, inserted)
+    }
 
-        Ok(insert_count)
+    /// Commit txn_id in the storage manager if result is Ok and roll back its changes
+    /// otherwise, so the changes of a statement survive a crash once it has succeeded.
+    pub fn complete<T>(
+        &self,
+        txn_id: TransactionId,
+        result: Result<T, CrustyError>,
+    ) -> Result<T, CrustyError> {
+        match result {
+            Ok(value) => {
+                self.managers.sm.commit_transaction(txn_id)?;
+                Ok(value)
+            }
+            Err(e) => {
+                self.managers.sm.abort_transaction(txn_id)?;
+                Err(e)
+            }
+        }
     }
 
     /// Import database from csv file at path.
@@ -99,6 +118,16 @@ impl Executor {
         rdr: &mut dyn DataReader,
         table_id: &ContainerId,
         txn_id: TransactionId,
+    ) -> Result<usize, CrustyError> {
+        let inserted = self.insert_records_from_reader(rdr, table_id, txn_id);
+        self.complete(txn_id, inserted)
+    }
+
+    fn insert_records_from_reader(
+        &self,
+        rdr: &mut dyn DataReader,
+        table_id: &ContainerId,
+        txn_id: TransactionId,
     ) -> Result<usize, CrustyError> {
         // TODO: Magic number
         let max_records_in_mem = 100000;
@@ -98,19 +98,21 @@ impl Conductor {
         physical_plan: PhysicalPlan,
         db_state: &'static DatabaseState,
     ) -> Result<QueryResult, CrustyError> {
+        let tid = self.active_txn.tid()?;
         let op_iterator = physical_plan_to_op_iterator(
             db_state.managers,
             &db_state.catalog,
             &physical_plan,
-            self.active_txn.tid()?,
+            tid,
             db_state.get_current_time(),
         )?;
 
         // We populate the executor with the state: physical plan, and storage manager ref
         self.executor.configure_query(op_iterator);
 
-        // Finally, execute the query
-        self.executor.execute()
+        // Finally, execute the query and commit what it changed
+        let result = self.executor.execute();
+        self.executor.complete(tid, result)
     }
 
     pub fn run_opiterator(
@@ -1,9 +1,11 @@
 use criterion::{black_box, Criterion};
 //<strip only="pg">
+use common::ids::{Permissions, TransactionId, ValueId};
 use common::storage_trait::StorageTrait;
 use common::testutil::get_random_vec_of_byte_vec;
 use heapstore::storage_manager::StorageManager;
 use heapstore::testutil::bench_sm_insert;
+use heapstore::ReplacementPolicyKind;
 //</strip>
 
 pub fn sm_ins_bench(c: &mut Criterion) {
@@ -17,3 +19,35 @@ pub fn sm_ins_bench(c: &mut Criterion) {
     });
     //</strip>
 }
+
+/// Hot point reads interleaved with full scans under each buffer pool replacement policy.
+/// The table is larger than the pool so the scans compete with the hot pages for frames.
+pub fn sm_replacement_bench(c: &mut Criterion) {
+    let to_insert = get_random_vec_of_byte_vec(5000, 80, 100);
+    //<strip only="pg">
+    let mut group = c.benchmark_group("sm replacement");
+    for kind in [ReplacementPolicyKind::Lru, ReplacementPolicyKind::Clock] {
+        let sm = StorageManager::new_test_sm();
+        sm.set_replacement_policy(kind.build());
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+        let ids: Vec<ValueId> = to_insert
+            .iter()
+            .map(|v| sm.insert_value(cid, v.clone(), tid))
+            .collect();
+        let hot = &ids[..ids.len() / 20];
+        group.bench_function(kind.to_string(), |b| {
+            b.iter(|| {
+                for _ in 0..4 {
+                    for id in hot {
+                        black_box(sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+                    }
+                }
+                black_box(sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+            })
+        });
+    }
+    group.finish();
+    //</strip>
+}
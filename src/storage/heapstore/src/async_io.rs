@@ -0,0 +1,297 @@
+use crate::buffer_pool::BufferPool;
+use crate::forward::{forwarded_to, is_forwarded, loop_error};
+use crate::heap_page::HeapPage;
+use crate::heapfile::HeapFile;
+use crate::page::Page;
+use crate::storage_manager::StorageManager;
+use crate::WRITE_THROUGH;
+use common::prelude::*;
+use std::sync::Arc;
+use tokio::task::{self, JoinSet};
+
+/// Page reads and writes that run on tokio's blocking thread pool, so a task can have many page
+/// reads outstanding at once instead of waiting on each pread in turn.
+/// Every function here must be called from within a tokio runtime.
+impl HeapFile {
+    /// Read a page without blocking the calling task.
+    pub(crate) async fn read_page_async(self: Arc<Self>, pid: PageId) -> Result<Page, CrustyError> {
+        task::spawn_blocking(move || self.read_page_from_file(pid))
+            .await
+            .map_err(join_error)?
+    }
+
+    /// Write a page without blocking the calling task.
+    pub(crate) async fn write_page_async(self: Arc<Self>, page: Page) -> Result<(), CrustyError> {
+        task::spawn_blocking(move || self.write_page_to_file(&page))
+            .await
+            .map_err(join_error)?
+    }
+}
+
+/// Page of hf through the buffer pool when there is one. A miss is read without holding the
+/// pool lock, so misses on different pages are in flight together.
+async fn fetch_page_async(
+    bp: Option<Arc<BufferPool>>,
+    hf: Arc<HeapFile>,
+    page_id: PageId,
+) -> Result<Page, CrustyError> {
+    let bp = match bp {
+        Some(bp) => bp,
+        None => return hf.read_page_async(page_id).await,
+    };
+    if let Some(page) = bp.cached_page(hf.container_id, page_id) {
+        return Ok(page);
+    }
+    let page = Arc::clone(&hf).read_page_async(page_id).await?;
+    bp.install_read(&hf, page)
+}
+
+fn join_error(e: task::JoinError) -> CrustyError {
+    CrustyError::CrustyError(format!("Page I/O task failed: {}", e))
+}
+
+/// Async variants of the storage manager's read and write paths.
+impl StorageManager {
+    /// Page of a container read without blocking the calling task.
+    pub async fn get_page_async(
+        &self,
+        container_id: ContainerId,
+        page_id: PageId,
+    ) -> Result<Page, CrustyError> {
+        let hf = self.get_hf(container_id)?;
+        let mut page = fetch_page_async(self.buffer_pool.clone(), hf, page_id).await?;
+        page.set_max_record_size(self.get_max_record_size());
+        Ok(page)
+    }
+
+    /// Write a page of a container without blocking the calling task.
+    /// Like write_page it is cached dirty when there is a buffer pool, unless it extends the file.
+    pub async fn write_page_async(
+        &self,
+        container_id: ContainerId,
+        page: Page,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let hf = self.get_hf(container_id)?;
+        tracing::trace!(
+            tid = tid.id(),
+            container_id,
+            page_id = page.get_page_id(),
+            "store page"
+        );
+        match &self.buffer_pool {
+            Some(bp) if !WRITE_THROUGH && page.get_page_id() < hf.num_pages() => {
+                hf.note_free_space(page.get_page_id(), page.get_free_space());
+                bp.put_page(&hf, page, true)
+            }
+            Some(bp) => {
+                self.flush_log(std::slice::from_ref(&page))?;
+                Arc::clone(&hf).write_page_async(page.clone()).await?;
+                bp.put_page(&hf, page, false)
+            }
+            None => {
+                self.flush_log(std::slice::from_ref(&page))?;
+                hf.write_page_async(page).await
+            }
+        }
+    }
+
+    /// Value stored at id, read without blocking the calling task.
+    /// Forwarding markers are followed like get_value does.
+    pub async fn get_value_async(&self, id: ValueId) -> Result<Vec<u8>, CrustyError> {
+        let missing = || CrustyError::CrustyError(format!("No value stored at {:?}", id));
+        let mut passed = Vec::new();
+        let mut at = id;
+        loop {
+            let (page_id, slot_id) = match (at.page_id, at.slot_id) {
+                (Some(page_id), Some(slot_id)) => (page_id, slot_id),
+                _ => return Err(missing()),
+            };
+            let page = self.get_page_async(id.container_id, page_id).await?;
+            let Some(next) = forwarded_to(&page, slot_id)? else {
+                return page.get_value(slot_id).ok_or_else(missing);
+            };
+            passed.push(at);
+            if passed.contains(&next) {
+                return Err(loop_error(&passed, next));
+            }
+            at = next;
+        }
+    }
+
+    /// Values stored at ids in the same order, with the reads of all their pages in flight
+    /// together.
+    pub async fn get_values_async(&self, ids: &[ValueId]) -> Vec<Result<Vec<u8>, CrustyError>> {
+        let mut reads = JoinSet::new();
+        let mut results: Vec<Result<Vec<u8>, CrustyError>> = Vec::with_capacity(ids.len());
+        for (i, id) in ids.iter().enumerate() {
+            let missing = CrustyError::CrustyError(format!("No value stored at {:?}", id));
+            results.push(Err(missing));
+            let (page_id, slot_id) = match (id.page_id, id.slot_id) {
+                (Some(page_id), Some(slot_id)) => (page_id, slot_id),
+                _ => continue,
+            };
+            let hf = match self.get_hf(id.container_id) {
+                Ok(hf) => hf,
+                Err(e) => {
+                    results[i] = Err(e);
+                    continue;
+                }
+            };
+            let bp = self.buffer_pool.clone();
+            reads.spawn(async move { (i, slot_id, fetch_page_async(bp, hf, page_id).await) });
+        }
+        let mut forwarded = Vec::new();
+        while let Some(read) = reads.join_next().await {
+            match read {
+                Ok((i, slot_id, Ok(page))) if is_forwarded(&page, slot_id) => forwarded.push(i),
+                Ok((i, slot_id, Ok(page))) => {
+                    if let Some(value) = page.get_value(slot_id) {
+                        results[i] = Ok(value);
+                    }
+                }
+                Ok((i, _, Err(e))) => results[i] = Err(e),
+                Err(e) => error!("Value read task failed: {}", e),
+            }
+        }
+        // Values that moved are few, so their markers are followed one at a time
+        for i in forwarded {
+            results[i] = self.get_value_async(ids[i]).await;
+        }
+        results
+    }
+
+    /// Every value of a container with its id in page and slot order, reading up to
+    /// max_outstanding pages at a time. Forwarding markers are skipped like get_iterator does.
+    pub async fn scan_async(
+        &self,
+        container_id: ContainerId,
+        max_outstanding: usize,
+    ) -> Result<Vec<(Vec<u8>, ValueId)>, CrustyError> {
+        let hf = self.get_hf(container_id)?;
+        let num_pages = hf.num_pages();
+        let mut pages: Vec<Option<Page>> = vec![None; num_pages as usize];
+        let mut reads = JoinSet::new();
+        let mut next: PageId = 0;
+        loop {
+            while next < num_pages && reads.len() < max_outstanding.max(1) {
+                let (bp, hf, page_id) = (self.buffer_pool.clone(), Arc::clone(&hf), next);
+                reads.spawn(async move { (page_id, fetch_page_async(bp, hf, page_id).await) });
+                next += 1;
+            }
+            match reads.join_next().await {
+                Some(read) => {
+                    let (page_id, page) = read.map_err(join_error)?;
+                    pages[page_id as usize] = Some(page?);
+                }
+                None => break,
+            }
+        }
+        let mut values = Vec::new();
+        for page in pages.into_iter().flatten() {
+            let page_id = page.get_page_id();
+            let forwarded: Vec<SlotId> = (0..page.get_num_slots() as SlotId)
+                .filter(|slot_id| is_forwarded(&page, *slot_id))
+                .collect();
+            values.extend(
+                page.into_iter()
+                    .filter(|(_, slot_id)| !forwarded.contains(slot_id))
+                    .map(|(value, slot_id)| {
+                        (value, ValueId::new_slot(container_id, page_id, slot_id))
+                    }),
+            );
+        }
+        Ok(values)
+    }
+
+    /// flush_all without blocking the calling task.
+    pub async fn flush_all_async(&self) -> Result<(), CrustyError> {
+        let bp = self.buffer_pool.clone();
+        let hfs: Vec<Arc<HeapFile>> = self
+            .cid_heapfile_map
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        task::spawn_blocking(move || {
+            if let Some(bp) = bp {
+                bp.flush_all()?;
+            }
+            for hf in hfs {
+                hf.sync()?;
+                hf.persist_fsm()?;
+            }
+            Ok(())
+        })
+        .await
+        .map_err(join_error)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn hs_async_reads_and_writes() {
+        init();
+        let rt = Runtime::new().unwrap();
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        sm.create_table(1).unwrap();
+        let vals = get_random_vec_of_byte_vec(400, 40, 400);
+        let ids = sm.insert_values(1, vals.clone(), tid);
+        sm.clear_cache();
+
+        rt.block_on(async {
+            assert_eq!(vals[7], sm.get_value_async(ids[7]).await.unwrap());
+            let mut wanted = ids.clone();
+            wanted.push(ValueId::new_slot(1, 0, 999));
+            let got = sm.get_values_async(&wanted).await;
+            for (val, got) in vals.iter().zip(&got) {
+                assert_eq!(val, got.as_ref().unwrap());
+            }
+            assert!(got.last().unwrap().is_err());
+
+            let scanned = sm.scan_async(1, 4).await.unwrap();
+            let sync: Vec<_> = sm.get_iterator(1, tid, Permissions::ReadOnly).collect();
+            assert_eq!(sync, scanned);
+            assert!(sm.scan_async(2, 4).await.is_err());
+
+            //a value deleted on the page makes room to store it again through the async path
+            let page_id = ids[0].page_id.unwrap();
+            sm.delete_value(ids[0], tid).unwrap();
+            let mut page = sm.get_page_async(1, page_id).await.unwrap();
+            let slot_id = page.add_value(&vals[0]).unwrap();
+            sm.write_page_async(1, page, tid).await.unwrap();
+            sm.flush_all_async().await.unwrap();
+            assert_eq!(
+                vals[0],
+                sm.get_value_async(ValueId::new_slot(1, page_id, slot_id))
+                    .await
+                    .unwrap()
+            );
+
+            //a value moved behind a forwarding marker is still found by its old id
+            let big = get_random_byte_vec(3000);
+            let moved = sm
+                .update_value_with(big.clone(), ids[1], tid, true)
+                .unwrap();
+            assert_ne!(ids[1], moved);
+            assert_eq!(big, sm.get_value_async(ids[1]).await.unwrap());
+            let got = sm.get_values_async(&[ids[1], ids[2]]).await;
+            assert_eq!(big, *got[0].as_ref().unwrap());
+            assert_eq!(vals[2], *got[1].as_ref().unwrap());
+            let scanned = sm.scan_async(1, 4).await.unwrap();
+            let sync: Vec<_> = sm.get_iterator(1, tid, Permissions::ReadOnly).collect();
+            assert_eq!(sync, scanned);
+        });
+        sm.clear_cache();
+        let stats = sm.buffer_pool_stats().unwrap();
+        assert!(stats.misses > 0 && stats.hits > 0);
+    }
+}
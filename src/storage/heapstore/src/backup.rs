@@ -0,0 +1,502 @@
+use crate::container::{ContainerCatalog, CATALOG_FILENAME};
+use crate::encryption::seal_path;
+use crate::file_header::HeapFileHeader;
+use crate::fsm::fsm_path;
+use crate::heap_page::HeapPage;
+use crate::page::Page;
+use crate::segment::{extra_segments, remove_extra_segments, segment_path, SegmentedFile};
+use crate::storage_manager::{StorageManager, PERSIST_CONFIG_FILENAME};
+use crate::wal::{Wal, WAL_FILENAME};
+use common::prelude::*;
+use common::PAGE_SIZE;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// File in a backup saying which log position it was taken at and what it builds on.
+const MANIFEST_FILENAME: &str = "backup";
+
+/// What a backup was taken at, to take and check the incremental backups over it by.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct BackupManifest {
+    /// End of the log when the backup started. Every page changed since has an Lsn from here on.
+    lsn: Lsn,
+    /// Lsn of the backup an incremental backup holds the changes since, None for a full one.
+    since: Option<Lsn>,
+}
+
+impl BackupManifest {
+    fn load(dir: &Path) -> Result<Self, CrustyError> {
+        let path = dir.join(MANIFEST_FILENAME);
+        let reader =
+            BufReader::new(File::open(&path).map_err(|e| {
+                CrustyError::CrustyError(format!("{:?} is not a backup: {}", dir, e))
+            })?);
+        serde_json::from_reader(reader).map_err(|e| {
+            CrustyError::CrustyError(format!("Error reading backup manifest {:?}: {}", path, e))
+        })
+    }
+
+    fn save(&self, dir: &Path) -> Result<(), CrustyError> {
+        let bytes = serde_json::to_vec(self).map_err(|e| {
+            CrustyError::CrustyError(format!("Error encoding backup manifest: {}", e))
+        })?;
+        let mut file = File::create(dir.join(MANIFEST_FILENAME))?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// File in an incremental backup holding the changed pages of a heap file.
+fn pages_path(heap_file: &Path) -> PathBuf {
+    heap_file.with_extension("pages")
+}
+
+/// Path heap_file has in dir.
+fn moved_to(heap_file: &Path, dir: &Path) -> Result<PathBuf, CrustyError> {
+    let name = heap_file.file_name().ok_or_else(|| {
+        CrustyError::CrustyError(format!("Heap file path {:?} has no file name", heap_file))
+    })?;
+    Ok(dir.join(name))
+}
+
+/// Copy the free space map and seal file beside the heap file from to beside to, removing
+/// those of to that from has none of.
+fn copy_sidecars(from: &Path, to: &Path) -> Result<(), CrustyError> {
+    for (from, to) in [
+        (fsm_path(from), fsm_path(to)),
+        (seal_path(from), seal_path(to)),
+    ] {
+        if from.exists() {
+            fs::copy(&from, &to)?;
+            File::open(&to)?.sync_all()?;
+        } else if let Err(e) = fs::remove_file(&to) {
+            if e.kind() != ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copy the heap file at from into to_dir, along with its segment files and the free space map
+/// and seal file beside it if there are any, and return where it was copied to.
+fn copy_heap_file(from: &Path, to_dir: &Path) -> Result<PathBuf, CrustyError> {
+    let to = moved_to(from, to_dir)?;
+    remove_extra_segments(&to)?;
+    fs::copy(from, &to)?;
+    File::open(&to)?.sync_all()?;
+    for (n, segment) in extra_segments(from).iter().enumerate() {
+        let copy = segment_path(&to, n + 1);
+        fs::copy(segment, &copy)?;
+        File::open(&copy)?.sync_all()?;
+    }
+    copy_sidecars(from, &to)?;
+    Ok(to)
+}
+
+/// Write the header page of the heap file at from into to_dir, followed by every data page
+/// stored with an Lsn from since on, or with none, each after its PageId and the length of its
+/// compressed image. The free space map and seal file are copied whole. Returns how many pages
+/// were written.
+fn copy_changed_pages(from: &Path, to_dir: &Path, since: Lsn) -> Result<PageId, CrustyError> {
+    let to = moved_to(from, to_dir)?;
+    let file = SegmentedFile::open(from)?;
+    let mut data = [0u8; PAGE_SIZE];
+    file.read_exact_at(&mut data, 0)?;
+    let header = HeapFileHeader::from_bytes(&data)?;
+    let mut pages = BufWriter::new(File::create(pages_path(&to))?);
+    pages.write_all(&data)?;
+    let mut changed = 0;
+    for pid in 0..header.page_count {
+        file.read_exact_at(&mut data, (pid as u64 + 1) * PAGE_SIZE as u64)?;
+        // The Lsn is in the part of the page that stays in the clear when it is encrypted
+        let lsn = Page::from_bytes_unchecked(data).get_lsn();
+        if lsn >= since || lsn == Lsn::default() {
+            let image = Page::from_bytes_unchecked(data).to_bytes_compressed();
+            pages.write_all(&pid.to_le_bytes())?;
+            pages.write_all(&(image.len() as u32).to_le_bytes())?;
+            pages.write_all(&image)?;
+            changed += 1;
+        }
+    }
+    pages.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    copy_sidecars(from, &to)?;
+    Ok(changed)
+}
+
+/// Write the pages an incremental backup holds of a heap file over the heap file at to, which
+/// is created if it does not exist, and cut it to the pages its header counts.
+fn apply_changed_pages(from: &Path, to: &Path) -> Result<(), CrustyError> {
+    let mut pages = BufReader::new(File::open(pages_path(from))?);
+    let mut data = [0u8; PAGE_SIZE];
+    pages.read_exact(&mut data)?;
+    let header = HeapFileHeader::from_bytes(&data)?;
+    let mut file = SegmentedFile::create(to, header.segment_pages)?;
+    file.write_all_at(&data, 0)?;
+    let mut pid = [0u8; std::mem::size_of::<PageId>()];
+    let mut len = [0u8; 4];
+    let mut image = Vec::with_capacity(PAGE_SIZE);
+    loop {
+        match pages.read_exact(&mut pid) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        pages.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > PAGE_SIZE {
+            return Err(CrustyError::CrustyError(format!(
+                "Page image of {} bytes in {:?} is longer than a page",
+                len,
+                pages_path(from)
+            )));
+        }
+        image.resize(len, 0);
+        pages.read_exact(&mut image)?;
+        let page = Page::from_bytes_compressed(&image)?;
+        let offset = (PageId::from_le_bytes(pid) as u64 + 1) * PAGE_SIZE as u64;
+        file.write_all_at(page.to_bytes(), offset)?;
+    }
+    file.set_len((header.page_count as u64 + 1) * PAGE_SIZE as u64)?;
+    file.sync_all()?;
+    copy_sidecars(from, to)
+}
+
+/// Error unless dir can be made into a new storage directory.
+fn check_empty(dir: &Path) -> Result<(), CrustyError> {
+    if dir.join(CATALOG_FILENAME).exists() {
+        return Err(CrustyError::CrustyError(format!(
+            "{:?} already holds a storage manager",
+            dir
+        )));
+    }
+    fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+impl StorageManager {
+    /// Copy every container into backup_dir, which must not hold a storage manager, without
+    /// stopping reads and writes. The heap files are copied one at a time while they may be
+    /// changing, then the write-ahead log, which still holds every change to them since a
+    /// checkpoint taken first. So opening the copy as a storage directory recovers it to the
+    /// state the log was copied in, less the changes of transactions that had not committed.
+    /// Temp containers are not copied.
+    pub fn backup(&self, backup_dir: &Path) -> Result<(), CrustyError> {
+        self.take_backup(backup_dir, None)
+    }
+
+    /// Back up into backup_dir as backup does, copying only the pages changed since the
+    /// backup in since was taken, whether that was a full or an incremental one. Restoring it
+    /// takes restore_incremental with every backup from the last full one up to it.
+    pub fn backup_incremental(&self, backup_dir: &Path, since: &Path) -> Result<(), CrustyError> {
+        let since = BackupManifest::load(since)?;
+        self.take_backup(backup_dir, Some(since.lsn))
+    }
+
+    /// Back up into backup_dir the whole containers, or with since only the pages changed
+    /// from that Lsn on.
+    fn take_backup(&self, backup_dir: &Path, since: Option<Lsn>) -> Result<(), CrustyError> {
+        let wal = self.wal.as_ref().ok_or_else(|| {
+            CrustyError::CrustyError("Storage manager has no write-ahead log to back up".into())
+        })?;
+        check_empty(backup_dir)?;
+        let manifest = BackupManifest {
+            lsn: wal.end_lsn(),
+            since,
+        };
+        // Only the changes since the checkpoint have to be replayed on the copy
+        self.checkpoint()?;
+        wal.hold();
+        let copied = self.copy_containers(backup_dir, wal, since);
+        wal.release();
+        let pages = copied?;
+        manifest.save(backup_dir)?;
+        match since {
+            Some(_) => info!(
+                "Backed up {} changed pages of {:?} to {:?}",
+                pages, self.storage_dir, backup_dir
+            ),
+            None => info!("Backed up {:?} to {:?}", self.storage_dir, backup_dir),
+        }
+        Ok(())
+    }
+
+    /// Copy the containers, then the catalog naming the copies, then the log, into backup_dir.
+    /// With since, only the pages changed from since on are copied, and how many is returned.
+    fn copy_containers(
+        &self,
+        backup_dir: &Path,
+        wal: &Wal,
+        since: Option<Lsn>,
+    ) -> Result<PageId, CrustyError> {
+        // No container is created or removed while the catalog is held
+        let _saving = self.catalog_lock.lock().unwrap();
+        let mut catalog = ContainerCatalog::load(&self.storage_dir)?.ok_or_else(|| {
+            CrustyError::CrustyError(format!("{:?} has no catalog", self.storage_dir))
+        })?;
+        let heapfiles = self.cid_heapfile_map.read().unwrap().clone();
+        let mut pages = 0;
+        for entry in catalog.containers.iter_mut() {
+            // A heap file is only written under its file lock, so it is copied page by page whole
+            let hf = heapfiles.get(&entry.meta.id);
+            let _file = hf.map(|hf| hf.file.read().unwrap());
+            match since {
+                Some(since) => pages += copy_changed_pages(&entry.path, backup_dir, since)?,
+                None => {
+                    copy_heap_file(&entry.path, backup_dir)?;
+                }
+            }
+            entry.path = moved_to(&entry.path, backup_dir)?;
+        }
+        catalog.save(backup_dir)?;
+        let config = self.storage_dir.join(PERSIST_CONFIG_FILENAME);
+        if config.exists() {
+            fs::copy(&config, backup_dir.join(PERSIST_CONFIG_FILENAME))?;
+        }
+        wal.copy_to(&backup_dir.join(WAL_FILENAME))?;
+        #[cfg(unix)]
+        File::open(backup_dir)?.sync_all()?;
+        Ok(pages)
+    }
+
+    /// Copy a full backup taken with backup into storage_dir, which must not hold a storage
+    /// manager, to be opened with new, or new_with_keys if it has encrypted containers. The
+    /// backup is left as it is, so it can be restored again.
+    pub fn restore(backup_dir: &Path, storage_dir: &Path) -> Result<(), CrustyError> {
+        StorageManager::restore_incremental(backup_dir, &[], storage_dir)
+    }
+
+    /// Restore the full backup in base_dir as restore does, then lay the incremental backups
+    /// in incrementals over it in the order they were taken. Each must have been taken since
+    /// the one before it, so none can be left out.
+    pub fn restore_incremental(
+        base_dir: &Path,
+        incrementals: &[&Path],
+        storage_dir: &Path,
+    ) -> Result<(), CrustyError> {
+        let mut last = BackupManifest::load(base_dir)?;
+        if last.since.is_some() {
+            return Err(CrustyError::CrustyError(format!(
+                "{:?} is an incremental backup, not a full one",
+                base_dir
+            )));
+        }
+        for dir in incrementals {
+            let manifest = BackupManifest::load(dir)?;
+            if manifest.since != Some(last.lsn) {
+                return Err(CrustyError::CrustyError(format!(
+                    "{:?} was not taken since the backup before it",
+                    dir
+                )));
+            }
+            last = manifest;
+        }
+        check_empty(storage_dir)?;
+        let load = |dir: &Path| {
+            ContainerCatalog::load(dir)?
+                .ok_or_else(|| CrustyError::CrustyError(format!("{:?} is not a backup", dir)))
+        };
+        let mut containers = load(base_dir)?.containers;
+        for entry in containers.iter_mut() {
+            entry.path = copy_heap_file(&entry.path, storage_dir)?;
+        }
+        let mut latest = base_dir;
+        for dir in incrementals {
+            let mut next = load(dir)?.containers;
+            for entry in next.iter_mut() {
+                let to = moved_to(&entry.path, storage_dir)?;
+                apply_changed_pages(&entry.path, &to)?;
+                entry.path = to;
+            }
+            // Containers removed since the backup before are removed here too
+            for gone in containers
+                .iter()
+                .filter(|c| !next.iter().any(|n| n.path == c.path))
+            {
+                remove_extra_segments(&gone.path)?;
+                for path in [
+                    gone.path.clone(),
+                    fsm_path(&gone.path),
+                    seal_path(&gone.path),
+                ] {
+                    if path.exists() {
+                        fs::remove_file(path)?;
+                    }
+                }
+            }
+            containers = next;
+            latest = dir;
+        }
+        for name in [PERSIST_CONFIG_FILENAME, WAL_FILENAME] {
+            let from = latest.join(name);
+            if from.exists() {
+                fs::copy(&from, storage_dir.join(name))?;
+            }
+        }
+        // Saved last, so a restore cut short is not taken for a storage directory
+        ContainerCatalog::new(containers).save(storage_dir)?;
+        info!(
+            "Restored backup {:?} and {} incremental backups to {:?}",
+            base_dir,
+            incrementals.len(),
+            storage_dir
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_backup_and_restore() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let backup_dir = TempDir::new(gen_random_test_sm_dir(), true);
+        let restore_dir = TempDir::new(gen_random_test_sm_dir(), true);
+        let committed = TransactionId::new();
+        let running = TransactionId::new();
+        let vals = get_random_vec_of_byte_vec(200, 50, 200);
+        let sm = StorageManager::new(&tdir);
+        sm.create_table(1).unwrap();
+        let kept = sm.insert_values(1, vals[..100].to_vec(), committed);
+        sm.commit_transaction(committed).unwrap();
+        let undone = sm.insert_values(1, vals[100..150].to_vec(), running);
+
+        sm.backup(&backup_dir).unwrap();
+        assert!(sm.backup(&backup_dir).is_err());
+        let later = TransactionId::new();
+        let after = sm.insert_values(1, vals[150..].to_vec(), later);
+        sm.commit_transaction(later).unwrap();
+
+        //the restored copy has what was committed when the backup was taken, and only that
+        StorageManager::restore(&backup_dir, &restore_dir).unwrap();
+        assert!(StorageManager::restore(&backup_dir, &restore_dir).is_err());
+        let restored = StorageManager::new(&restore_dir);
+        let tid = TransactionId::new();
+        for (id, val) in kept.iter().zip(&vals) {
+            assert_eq!(
+                *val,
+                restored.get_value(*id, tid, Permissions::ReadOnly).unwrap()
+            );
+        }
+        for id in undone.iter().chain(&after) {
+            assert!(restored.get_value(*id, tid, Permissions::ReadOnly).is_err());
+        }
+        let info = restored.list_containers();
+        assert!(info[0].path.starts_with(&*restore_dir));
+
+        //the source is untouched
+        for (id, val) in after.iter().zip(&vals[150..]) {
+            assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+    }
+
+    #[test]
+    fn hs_backup_incremental() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let dirs: Vec<TempDir> = (0..4)
+            .map(|_| TempDir::new(gen_random_test_sm_dir(), true))
+            .collect();
+        let (full, first, second, restore_dir) = (&*dirs[0], &*dirs[1], &*dirs[2], &*dirs[3]);
+        let tid = TransactionId::new();
+        let vals = get_random_vec_of_byte_vec(300, 50, 200);
+        let sm = StorageManager::new(&tdir);
+        sm.create_table(1).unwrap();
+        sm.create_table(2).unwrap();
+        sm.create_table(3).unwrap();
+        let ids = sm.insert_values(1, vals[..100].to_vec(), tid);
+        let untouched = sm.insert_values(2, vals[..100].to_vec(), tid);
+        sm.insert_values(3, vals[..10].to_vec(), tid);
+        sm.commit_transaction(tid).unwrap();
+        sm.backup(full).unwrap();
+        assert!(sm.backup_incremental(first, restore_dir).is_err());
+
+        let added = sm.insert_values(1, vals[100..200].to_vec(), tid);
+        sm.delete_value(ids[0], tid).unwrap();
+        sm.commit_transaction(tid).unwrap();
+        sm.backup_incremental(first, full).unwrap();
+
+        //a container nothing changed in is only its header
+        let pages = fs::metadata(pages_path(&first.join("2.hf"))).unwrap().len();
+        assert_eq!(PAGE_SIZE as u64, pages);
+        assert!(fs::metadata(pages_path(&first.join("1.hf"))).unwrap().len() > pages);
+
+        sm.remove_container(3).unwrap();
+        sm.create_table(4).unwrap();
+        let created = sm.insert_values(4, vals[200..].to_vec(), tid);
+        let updated = sm.update_value(vals[0].clone(), ids[1], tid).unwrap();
+        sm.commit_transaction(tid).unwrap();
+        sm.backup_incremental(second, first).unwrap();
+
+        //incrementals are only laid over the backup they were taken since
+        assert!(StorageManager::restore_incremental(full, &[second], restore_dir).is_err());
+        assert!(StorageManager::restore_incremental(first, &[second], restore_dir).is_err());
+        StorageManager::restore_incremental(full, &[first, second], restore_dir).unwrap();
+        assert!(!restore_dir.join("3.hf").exists());
+        let restored = StorageManager::new(restore_dir);
+        assert!(restored
+            .get_value(ids[0], tid, Permissions::ReadOnly)
+            .is_err());
+        assert_eq!(
+            vals[0],
+            restored
+                .get_value(updated, tid, Permissions::ReadOnly)
+                .unwrap()
+        );
+        let expected = ids[2..]
+            .iter()
+            .zip(&vals[2..100])
+            .chain(added.iter().zip(&vals[100..200]))
+            .chain(untouched.iter().zip(&vals[..100]))
+            .chain(created.iter().zip(&vals[200..]));
+        for (id, val) in expected {
+            assert_eq!(
+                *val,
+                restored.get_value(*id, tid, Permissions::ReadOnly).unwrap()
+            );
+        }
+        let ids: Vec<ContainerId> = restored.list_containers().iter().map(|c| c.id).collect();
+        assert_eq!(vec![1, 2, 4], ids);
+    }
+
+    #[test]
+    fn hs_backup_incremental_compressed() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let full = TempDir::new(gen_random_test_sm_dir(), true);
+        let incremental = TempDir::new(gen_random_test_sm_dir(), true);
+        let restore_dir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        let sm = StorageManager::new(&tdir);
+        sm.create_table(1).unwrap();
+        let mut ids = sm.insert_values(1, vec![vec![1; 100], vec![2; 100]], tid);
+        sm.commit_transaction(tid).unwrap();
+        sm.backup(&full).unwrap();
+        ids.push(sm.insert_value(1, vec![3; 100], tid));
+        sm.commit_transaction(tid).unwrap();
+        sm.backup_incremental(&incremental, &full).unwrap();
+
+        //the changed page is mostly free space so its image is far smaller than the page
+        let pages = fs::metadata(pages_path(&incremental.join("1.hf")))
+            .unwrap()
+            .len();
+        assert!(pages < PAGE_SIZE as u64 + PAGE_SIZE as u64 / 4);
+
+        StorageManager::restore_incremental(&full, &[&incremental], &restore_dir).unwrap();
+        let restored = StorageManager::new(&restore_dir);
+        for (id, byte) in ids.iter().zip(1u8..) {
+            assert_eq!(
+                vec![byte; 100],
+                restored.get_value(*id, tid, Permissions::ReadOnly).unwrap()
+            );
+        }
+    }
+}
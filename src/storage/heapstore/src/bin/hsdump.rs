@@ -0,0 +1,205 @@
+use clap::Parser;
+use common::prelude::*;
+use common::PAGE_SIZE;
+use heapstore::fsck::page_problems;
+use heapstore::{read_heap_file, HeapFileHeader, HeapPage, Page, PageFormat, NO_SEGMENTS};
+use std::error::Error;
+use std::path::PathBuf;
+use std::process;
+
+//bytes per line of the record hex dump
+const DUMP_WIDTH: usize = 16;
+
+/// Print the layout of a heapstore heap file page by page
+#[derive(Parser, Debug)]
+#[clap(name = "hsdump")]
+struct Args {
+    /// Heap file to inspect, read with the segment files after it if it is split
+    file: PathBuf,
+    /// Also decode the slot directory and records of the page at this index
+    #[clap(short = 'p', long = "page", value_name = "PAGE")]
+    page: Option<PageId>,
+    /// Print --page as summary, report, hex or json instead of the decoded listing
+    #[clap(long = "format", value_name = "FORMAT", requires = "page")]
+    format: Option<PageFormat>,
+    /// Write an annotated HTML rendering of --page to this file
+    #[clap(long = "html", value_name = "FILE", requires = "page")]
+    html: Option<PathBuf>,
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(&args) {
+        eprintln!("hsdump: {e}");
+        process::exit(1);
+    }
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let bytes = read_heap_file(&args.file)?;
+    // Files written before heap files had a header page start straight with data pages
+    let (header, data) = if HeapFileHeader::is_header(&bytes) {
+        let first: &[u8; PAGE_SIZE] = bytes[..PAGE_SIZE].try_into().unwrap();
+        (Some(HeapFileHeader::from_bytes(first)), &bytes[PAGE_SIZE..])
+    } else {
+        (None, &bytes[..])
+    };
+    let pages: Vec<Page> = data
+        .chunks_exact(PAGE_SIZE)
+        .map(|chunk| Page::from_bytes_unchecked(chunk.try_into().unwrap()))
+        .collect();
+
+    println!("file: {}", args.file.display());
+    println!(
+        "size: {} bytes, {} pages of {} bytes",
+        bytes.len(),
+        pages.len(),
+        PAGE_SIZE
+    );
+    let trailing = bytes.len() % PAGE_SIZE;
+    if trailing != 0 {
+        println!("warning: {trailing} trailing bytes do not form a full page");
+    }
+    match header {
+        Some(Ok(h)) => {
+            println!(
+                "header: container {}, {} pages, format version {}, fsm root {}, created {}",
+                h.container_id, h.page_count, h.format_version, h.fsm_root, h.created_at
+            );
+            if h.segment_pages != NO_SEGMENTS {
+                println!("segments: {} pages each", h.segment_pages);
+            }
+        }
+        Some(Err(e)) => println!("header: {e}"),
+        None => println!("header: none, file starts with a data page"),
+    }
+    let unstamped = pages.iter().filter(|p| p.stored_checksum() == 0).count();
+    let mismatched = pages.iter().filter(|p| !p.verify_checksum()).count();
+    println!(
+        "checksums: {} verified, {} mismatched, {} never stamped",
+        pages.len() - unstamped - mismatched,
+        mismatched,
+        unstamped
+    );
+    println!();
+
+    println!(
+        "{:>6} {:>6} {:>6} {:>6} {:>6} {:>10}  status",
+        "page", "id", "slots", "live", "free", "free_start"
+    );
+    for (index, page) in pages.iter().enumerate() {
+        let entries = page.slot_entries();
+        let live = entries.iter().filter(|e| e.in_use).count();
+        let problems = page_problems(index, page);
+        let status = if problems.is_empty() {
+            "ok".to_string()
+        } else {
+            problems.join("; ")
+        };
+        println!(
+            "{:>6} {:>6} {:>6} {:>6} {:>6} {:>10}  {}",
+            index,
+            page.get_page_id(),
+            entries.len(),
+            live,
+            page.get_free_space(),
+            page.free_start(),
+            status
+        );
+    }
+
+    if let Some(index) = args.page {
+        let page = pages.get(index as usize).ok_or_else(|| {
+            format!(
+                "page {index} is past the end of the file ({} pages)",
+                pages.len()
+            )
+        })?;
+        println!();
+        match args.format {
+            Some(format) => println!("{}", page.render(format)),
+            None => print_page(index, page),
+        }
+        if let Some(html) = &args.html {
+            page.write_html(html)?;
+            println!();
+            println!("wrote {}", html.display());
+        }
+    }
+    Ok(())
+}
+
+/// Decoded header, slot directory and record bytes of one page.
+fn print_page(index: PageId, page: &Page) {
+    println!("page {index}");
+    println!(
+        "header: page_id {}, num_slots {}, free_start {}, checksum {:08x}, lsn {}.{}, fill_factor {}%, slot_encoding {:?}, prefix_compressed {}, secure_delete {}, sorted {}, header_size {}, free_space {}",
+        page.get_page_id(),
+        page.slot_count(),
+        page.free_start(),
+        page.stored_checksum(),
+        page.get_lsn().page_id,
+        page.get_lsn().slot_id,
+        page.get_fill_factor(),
+        page.get_slot_encoding(),
+        page.is_prefix_compressed(),
+        page.is_secure_delete(),
+        page.is_sorted(),
+        page.get_header_size(),
+        page.get_free_space()
+    );
+    if let Some(zone) = page.zone_map() {
+        println!(
+            "zone map: min {:02x?}, max {:02x?}{}",
+            zone.min.unwrap_or_default(),
+            zone.max.unwrap_or_default(),
+            if zone.max_truncated { " (prefix)" } else { "" }
+        );
+    }
+    println!();
+
+    let entries = page.slot_entries();
+    println!("{:>6} {:>6} {:>6}  state", "slot", "offset", "length");
+    for entry in &entries {
+        let state = if entry.in_use { "live" } else { "free" };
+        println!(
+            "{:>6} {:>6} {:>6}  {}",
+            entry.slot_id, entry.offset, entry.length, state
+        );
+    }
+
+    for entry in entries.iter().filter(|e| e.in_use) {
+        println!();
+        println!("slot {} ({} bytes)", entry.slot_id, entry.length);
+        match page.raw_slot_bytes(entry.slot_id) {
+            Some(bytes) => print!("{}", hex_dump(bytes)),
+            None => println!("  <record lies outside the page>"),
+        }
+    }
+}
+
+/// Offset, hex bytes and printable ASCII, DUMP_WIDTH bytes per line.
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(DUMP_WIDTH).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        out.push_str(&format!(
+            "  {:04x}  {:<width$}  |{}|\n",
+            line * DUMP_WIDTH,
+            hex.join(" "),
+            ascii,
+            width = DUMP_WIDTH * 3 - 1
+        ));
+    }
+    out
+}
@@ -0,0 +1,64 @@
+use clap::Parser;
+use heapstore::fsck::{check, repair};
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Check a heapstore storage directory and repair what can be recovered
+#[derive(Parser, Debug)]
+#[clap(name = "hsfsck")]
+struct Args {
+    /// Storage directory holding the catalog and heap files
+    storage_dir: PathBuf,
+    /// Fix repairable issues (drop catalog entries of missing files, truncate partial pages,
+    /// salvage records from corrupt pages into a new container)
+    #[clap(long = "repair")]
+    repair: bool,
+    /// With --repair, only print what would be changed
+    #[clap(short = 'n', long = "dry-run", requires = "repair")]
+    dry_run: bool,
+}
+
+// exit status 0 when the directory is clean (or fully repaired), 1 when issues remain, 2 on errors
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("hsfsck: {e}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Whether the directory is clean once run finishes.
+fn run(args: &Args) -> Result<bool, Box<dyn Error>> {
+    let issues = check(&args.storage_dir)?;
+    if issues.is_empty() {
+        println!("{}: clean", args.storage_dir.display());
+        return Ok(true);
+    }
+    for issue in &issues {
+        let note = if issue.is_repairable() {
+            ""
+        } else {
+            " (not repairable)"
+        };
+        println!("{issue}{note}");
+    }
+    if !args.repair {
+        println!("{} issues found, rerun with --repair to fix", issues.len());
+        return Ok(false);
+    }
+
+    let actions = repair(&args.storage_dir, &issues, args.dry_run)?;
+    let verb = if args.dry_run { "would" } else { "did" };
+    for action in &actions {
+        println!("{verb} {action}");
+    }
+    if args.dry_run {
+        return Ok(false);
+    }
+    Ok(issues.iter().all(|issue| issue.is_repairable()))
+}
@@ -0,0 +1,47 @@
+use clap::Parser;
+use common::storage_trait::StorageTrait;
+use heapstore::storage_manager::StorageManager;
+use heapstore::trace::{read_trace, replay};
+use std::error::Error;
+use std::fs::File;
+use std::path::PathBuf;
+use std::process;
+
+/// Replay a storage manager trace against a fresh heapstore instance
+#[derive(Parser, Debug)]
+#[clap(name = "hsreplay")]
+struct Args {
+    /// Trace recorded with ycsb --trace or heapstore::trace::Traced
+    trace: PathBuf,
+    /// Keep the recorded pacing, sped up by this factor (as fast as possible if omitted)
+    #[clap(short = 's', long = "speed")]
+    speed: Option<f64>,
+    /// Storage directory to replay into instead of a temporary one
+    #[clap(long = "storage-dir")]
+    storage_dir: Option<PathBuf>,
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(&args) {
+        eprintln!("hsreplay: {e}");
+        process::exit(1);
+    }
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    let records = read_trace(File::open(&args.trace)?)?;
+    let sm = match &args.storage_dir {
+        Some(dir) => StorageManager::new(dir),
+        None => StorageManager::new_test_sm(),
+    };
+    let report = replay(&sm, &records, args.speed)?;
+    println!(
+        "replayed {} operations in {:.3}s ({} errors, {} ids remapped)",
+        report.operations,
+        report.elapsed.as_secs_f64(),
+        report.errors,
+        report.remapped_ids
+    );
+    Ok(())
+}
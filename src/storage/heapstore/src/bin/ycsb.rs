@@ -0,0 +1,132 @@
+use clap::Parser;
+use common::storage_trait::StorageTrait;
+use common::PAGE_SLOTS;
+use heapstore::storage_manager::StorageManager;
+use heapstore::trace::{TraceWriter, Traced};
+use heapstore::workload::{run_workload, KeyDistribution, OpMix, WorkloadConfig};
+use heapstore::{BufferPoolSize, HeapFileIo, ReplacementPolicyKind, DEFAULT_PREFETCH_WINDOW};
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+/// Run a YCSB-style workload against the heapstore storage manager
+#[derive(Parser, Debug)]
+#[clap(name = "ycsb")]
+struct Args {
+    /// Core workload a-f or a mix such as read=50,update=40,scan=10
+    #[clap(short = 'w', long = "workload", default_value = "a")]
+    workload: OpMix,
+    /// Records loaded before the measured run
+    #[clap(short = 'r', long = "records", default_value_t = 1000)]
+    records: usize,
+    /// Operations in the measured run
+    #[clap(short = 'o', long = "operations", default_value_t = 10_000)]
+    operations: usize,
+    /// Stop after this many seconds instead of a fixed operation count
+    #[clap(short = 'd', long = "duration", value_name = "SECS")]
+    duration: Option<f64>,
+    /// Worker threads
+    #[clap(short = 't', long = "threads", default_value_t = 1)]
+    threads: usize,
+    /// Key distribution: uniform, zipfian or latest
+    #[clap(long = "distribution", default_value = "zipfian")]
+    distribution: KeyDistribution,
+    /// Smallest value in bytes
+    #[clap(long = "min-size", default_value_t = 100)]
+    min_size: usize,
+    /// Largest value in bytes
+    #[clap(long = "max-size", default_value_t = 100)]
+    max_size: usize,
+    /// Records read by each scan
+    #[clap(long = "scan-length", default_value_t = 10)]
+    scan_length: usize,
+    /// Random seed for values, keys and the operation sequence
+    #[clap(long = "seed", default_value_t = 0)]
+    seed: u64,
+    /// Storage directory to use instead of a temporary one
+    #[clap(long = "storage-dir")]
+    storage_dir: Option<PathBuf>,
+    /// Record every storage manager call to this file for hsreplay
+    #[clap(long = "trace", value_name = "FILE")]
+    trace: Option<PathBuf>,
+    /// Buffer pool replacement policy: lru or clock
+    #[clap(long = "replacement", default_value = "lru")]
+    replacement: ReplacementPolicyKind,
+    /// Write back dirty pages in the background every this many milliseconds
+    #[clap(long = "flush-interval", value_name = "MS")]
+    flush_interval: Option<u64>,
+    /// Buffer pool size in pages, or in bytes with a unit such as 512KiB or 64MiB
+    #[clap(long = "pool-size")]
+    pool_size: Option<BufferPoolSize>,
+    /// How heap files read and write pages: pread, direct on Linux, or mmap when built with the
+    /// mmap feature
+    #[clap(long = "io", default_value = "pread")]
+    io: HeapFileIo,
+    /// Pages a scan reads ahead of itself into the buffer pool, 0 for none
+    #[clap(long = "prefetch", default_value_t = DEFAULT_PREFETCH_WINDOW)]
+    prefetch: usize,
+}
+
+fn main() {
+    let args = Args::parse();
+    if args.min_size > args.max_size {
+        eprintln!("ycsb: --min-size is larger than --max-size");
+        process::exit(2);
+    }
+    let config = WorkloadConfig {
+        records: args.records,
+        operations: if args.duration.is_some() {
+            None
+        } else {
+            Some(args.operations)
+        },
+        duration: args.duration.map(Duration::from_secs_f64),
+        threads: args.threads,
+        mix: args.workload,
+        distribution: args.distribution,
+        value_size: (args.min_size, args.max_size),
+        scan_length: args.scan_length,
+        seed: args.seed,
+        ..WorkloadConfig::default()
+    };
+
+    let sm = match &args.storage_dir {
+        Some(dir) => StorageManager::new(dir),
+        None => StorageManager::new_test_sm(),
+    };
+    sm.set_replacement_policy(args.replacement.build());
+    sm.set_file_io(args.io);
+    sm.set_prefetch_window(args.prefetch);
+    if let Some(size) = args.pool_size {
+        if let Err(e) = sm.set_buffer_pool_size(size) {
+            eprintln!("ycsb: {e}");
+            process::exit(2);
+        }
+    }
+    if let Some(ms) = args.flush_interval {
+        sm.start_flusher(Duration::from_millis(ms), PAGE_SLOTS);
+    }
+    let (result, stats) = match &args.trace {
+        Some(path) => match TraceWriter::create(path) {
+            Ok(trace) => {
+                let traced = Traced::new(sm, trace);
+                let result = run_workload(&traced, &config);
+                (result, traced.inner().buffer_pool_stats())
+            }
+            Err(e) => (Err(e), None),
+        },
+        None => (run_workload(&sm, &config), sm.buffer_pool_stats()),
+    };
+    match result {
+        Ok(report) => {
+            print!("{report}");
+            if let Some(stats) = stats {
+                println!("{stats}");
+            }
+        }
+        Err(e) => {
+            eprintln!("ycsb: {e}");
+            process::exit(1);
+        }
+    }
+}
@@ -7,12 +7,14 @@ mod test {
     use crate::heap_page::HeapPage;
     use crate::storage_manager::StorageManager;
     use crate::testutil::*;
-    use common::ids::{PageId, Permissions, TransactionId, ValueId};
+    use crate::BufferPoolSize;
+    use common::ids::{ContainerId, PageId, Permissions, TransactionId, ValueId};
     use common::storage_trait::StorageTrait;
     use common::testutil::*;
     use common::PAGE_SLOTS;
     use std::sync::Arc;
     use std::thread;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_bp_a_get() {
@@ -268,4 +270,310 @@ mod test {
             assert_eq!(1, rc);
         }
     }
+
+    #[test]
+    fn test_bp_pin_and_write_back() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        if sm.buffer_pool.is_none() {
+            return;
+        }
+        let hfid = 1;
+        sm.create_table(hfid).unwrap();
+        let tid = TransactionId::new();
+        fill_hf_sm(&sm, hfid, (PAGE_SLOTS + 1) as PageId, 10, 100, 100);
+        let (_rc, base_wc) = sm.get_hf_read_write_count(hfid);
+
+        //a pinned page survives a scan of more pages than the pool holds
+        let mut p0 = sm
+            .get_page(hfid, 0, tid, Permissions::ReadWrite, true)
+            .unwrap();
+        for i in 1..=PAGE_SLOTS {
+            sm.get_page(hfid, i as PageId, tid, Permissions::ReadOnly, false)
+                .unwrap();
+        }
+        sm.get_page(hfid, 0, tid, Permissions::ReadOnly, false)
+            .unwrap();
+        #[cfg(feature = "profile")]
+        {
+            let (rc, _wc) = sm.get_hf_read_write_count(hfid);
+            assert_eq!((PAGE_SLOTS + 1) as u16, rc);
+        }
+        assert!(sm.unpin_page(hfid, 0));
+        assert!(!sm.unpin_page(hfid, 0));
+
+        //a changed page stays dirty in the pool until it is written back
+        p0.add_value(&get_random_byte_vec(20)).unwrap();
+        sm.write_page(hfid, &p0, tid).unwrap();
+        assert_eq!(1, sm.buffer_pool.as_ref().unwrap().dirty_count());
+        #[cfg(feature = "profile")]
+        {
+            let (_rc, wc) = sm.get_hf_read_write_count(hfid);
+            assert_eq!(base_wc, wc);
+            sm.clear_cache();
+            let (_rc, wc) = sm.get_hf_read_write_count(hfid);
+            assert_eq!(base_wc + 1, wc);
+        }
+        sm.clear_cache();
+        assert_eq!(0, sm.buffer_pool.as_ref().unwrap().len());
+        let p0_read = sm
+            .get_page(hfid, 0, tid, Permissions::ReadOnly, false)
+            .unwrap();
+        //written back with its checksum stamped
+        p0.update_checksum();
+        assert_eq!(p0.to_bytes()[..], p0_read.to_bytes()[..]);
+    }
+
+    #[test]
+    fn test_bp_page_guard() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        if sm.buffer_pool.is_none() {
+            return;
+        }
+        let hfid = 1;
+        sm.create_table(hfid).unwrap();
+        let tid = TransactionId::new();
+        fill_hf_sm(&sm, hfid, (PAGE_SLOTS + 1) as PageId, 10, 100, 100);
+        sm.clear_cache();
+        assert!(sm.pin_page(hfid, (PAGE_SLOTS + 1) as PageId).is_err());
+        assert!(sm.pin_page(hfid + 1, 0).is_err());
+
+        //a write through the guard marks the page dirty and later reads see it
+        let guard = sm.pin_page(hfid, 0).unwrap();
+        assert_eq!(0, guard.page_id());
+        assert!(!guard.is_dirty());
+        let value = get_random_byte_vec(20);
+        let slot_id = guard.write().add_value(&value).unwrap();
+        assert!(guard.is_dirty());
+        let id = ValueId::new_slot(hfid, 0, slot_id);
+        assert_eq!(value, sm.get_value(id, tid, Permissions::ReadOnly).unwrap());
+
+        //the guarded page outlives a scan of more pages than the pool holds
+        for i in 1..=PAGE_SLOTS {
+            sm.get_page(hfid, i as PageId, tid, Permissions::ReadOnly, false)
+                .unwrap();
+        }
+        assert_eq!(Some(value.clone()), guard.read().get_value(slot_id));
+
+        //every other frame pinned leaves no room for another page
+        let others: Vec<_> = (1..PAGE_SLOTS)
+            .map(|i| sm.pin_page(hfid, i as PageId).unwrap())
+            .collect();
+        assert!(sm.pin_page(hfid, PAGE_SLOTS as PageId).is_err());
+        drop(others);
+        assert!(sm.pin_page(hfid, PAGE_SLOTS as PageId).is_ok());
+
+        //dropping the guard unpins the page so it can be written back and evicted
+        drop(guard);
+        assert!(!sm.unpin_page(hfid, 0));
+        sm.clear_cache();
+        assert_eq!(0, sm.buffer_pool.as_ref().unwrap().len());
+        assert_eq!(value, sm.get_value(id, tid, Permissions::ReadOnly).unwrap());
+    }
+
+    #[test]
+    fn test_bp_flush_oldest_and_flusher() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        if sm.buffer_pool.is_none() {
+            return;
+        }
+        let hfid = 1;
+        sm.create_table(hfid).unwrap();
+        fill_hf_sm(&sm, hfid, 4, 10, 100, 100);
+        sm.flush_all().unwrap();
+        let bp = sm.buffer_pool.as_ref().unwrap();
+        assert_eq!(0, bp.dirty_count());
+
+        //pages are written back in the order they became dirty
+        for pid in [3, 1, 2] {
+            sm.pin_page(hfid, pid)
+                .unwrap()
+                .write()
+                .add_value(&get_random_byte_vec(10))
+                .unwrap();
+        }
+        assert_eq!(3, bp.dirty_count());
+        assert_eq!(1, bp.flush_oldest(1).unwrap());
+        assert!(!sm.pin_page(hfid, 3).unwrap().is_dirty());
+        assert!(sm.pin_page(hfid, 1).unwrap().is_dirty());
+        assert_eq!(1, bp.flush_oldest(1).unwrap());
+        assert!(!sm.pin_page(hfid, 1).unwrap().is_dirty());
+        assert!(sm.pin_page(hfid, 2).unwrap().is_dirty());
+
+        //the background flusher drains dirty pages without eviction or shutdown
+        sm.pin_page(hfid, 0)
+            .unwrap()
+            .write()
+            .add_value(&get_random_byte_vec(10))
+            .unwrap();
+        sm.start_flusher(Duration::from_millis(5), 1);
+        let start = Instant::now();
+        while bp.dirty_count() > 0 {
+            assert!(start.elapsed().as_secs() < 10, "flusher did not run");
+            thread::sleep(Duration::from_millis(5));
+        }
+        sm.stop_flusher();
+        //written back pages stay cached
+        assert_eq!(4, bp.len());
+    }
+
+    #[test]
+    fn test_bp_stats_and_resize() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        if sm.buffer_pool.is_none() {
+            return;
+        }
+        let hfid = 1;
+        sm.create_table(hfid).unwrap();
+        let tid = TransactionId::new();
+        fill_hf_sm(&sm, hfid, 20, 10, 100, 100);
+        sm.clear_cache();
+        sm.reset_buffer_pool_stats();
+        let stats = sm.buffer_pool_stats().unwrap();
+        assert_eq!((0, 0, 0), (stats.hits, stats.misses, stats.occupancy));
+        assert_eq!(PAGE_SLOTS, stats.capacity);
+
+        for _ in 0..2 {
+            for pid in 0..20 {
+                sm.get_page(hfid, pid, tid, Permissions::ReadOnly, false)
+                    .unwrap();
+            }
+        }
+        let stats = sm.buffer_pool_stats().unwrap();
+        assert_eq!((20, 20, 0), (stats.hits, stats.misses, stats.evictions));
+        assert_eq!(20, stats.occupancy);
+        assert_eq!(0.5, stats.hit_ratio());
+
+        //shrinking the pool evicts the least recently used pages and keeps the dirty one
+        sm.pin_page(hfid, 0)
+            .unwrap()
+            .write()
+            .add_value(&get_random_byte_vec(10))
+            .unwrap();
+        sm.set_buffer_pool_size(BufferPoolSize::Pages(10)).unwrap();
+        let stats = sm.buffer_pool_stats().unwrap();
+        assert_eq!(
+            (10, 10, 10),
+            (stats.capacity, stats.occupancy, stats.evictions)
+        );
+        assert_eq!((1, 0), (stats.dirty, stats.dirty_writes));
+        sm.flush_all().unwrap();
+        assert_eq!(1, sm.buffer_pool_stats().unwrap().dirty_writes);
+        for pid in 0..20 {
+            sm.get_page(hfid, pid, tid, Permissions::ReadOnly, false)
+                .unwrap();
+        }
+        let stats = sm.buffer_pool_stats().unwrap();
+        assert_eq!(10, stats.occupancy);
+        assert!(stats.evictions > 10);
+
+        sm.set_buffer_pool_size("16KiB".parse().unwrap()).unwrap();
+        assert_eq!(4, sm.buffer_pool_stats().unwrap().capacity);
+        assert!(sm.set_buffer_pool_size(BufferPoolSize::Bytes(100)).is_err());
+        assert!("4TB".parse::<BufferPoolSize>().is_err());
+        assert_eq!(
+            BufferPoolSize::Pages(64),
+            "64".parse::<BufferPoolSize>().unwrap()
+        );
+        sm.reset_buffer_pool_stats();
+        assert_eq!(0, sm.buffer_pool_stats().unwrap().evictions);
+    }
+
+    #[test]
+    fn test_bp_read_ahead() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        if sm.buffer_pool.is_none() {
+            return;
+        }
+        let hfid = 1;
+        sm.create_table(hfid).unwrap();
+        let tid = TransactionId::new();
+        fill_hf_sm(&sm, hfid, 20, 10, 100, 100);
+        sm.clear_cache();
+        sm.reset_buffer_pool_stats();
+
+        //starting a scan reads the window of pages after the first one in the background
+        sm.set_prefetch_window(4);
+        let mut iter = sm.get_iterator(hfid, tid, Permissions::ReadOnly);
+        iter.next().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while sm.buffer_pool_stats().unwrap().prefetches < 4 {
+            assert!(Instant::now() < deadline, "pages were not read ahead");
+            thread::sleep(Duration::from_millis(5));
+        }
+        for pid in 1..5 {
+            sm.get_page(hfid, pid, tid, Permissions::ReadOnly, false)
+                .unwrap();
+        }
+        let stats = sm.buffer_pool_stats().unwrap();
+        assert_eq!((4, 4, 1), (stats.prefetches, stats.hits, stats.misses));
+        assert_eq!(199, iter.count());
+
+        sm.clear_cache();
+        sm.reset_buffer_pool_stats();
+        sm.set_prefetch_window(0);
+        assert_eq!(
+            200,
+            sm.get_iterator(hfid, tid, Permissions::ReadOnly).count()
+        );
+        let stats = sm.buffer_pool_stats().unwrap();
+        assert_eq!((0, 20), (stats.prefetches, stats.misses));
+    }
+
+    #[test]
+    fn test_bp_coalesced_flush() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        if sm.buffer_pool.is_none() {
+            return;
+        }
+        let hfid = 1;
+        sm.create_table(hfid).unwrap();
+        let tid = TransactionId::new();
+        fill_hf_sm(&sm, hfid, 20, 10, 100, 100);
+        sm.clear_cache();
+        sm.reset_buffer_pool_stats();
+
+        let dirtied: Vec<PageId> = vec![0, 1, 2, 3, 4, 8, 9, 15];
+        for &pid in &dirtied {
+            sm.pin_page(hfid, pid)
+                .unwrap()
+                .write()
+                .add_value(&get_random_byte_vec(10))
+                .unwrap();
+        }
+        //the three runs of dirty pages are written with one write each
+        let all: Vec<(ContainerId, PageId)> = (0..20).map(|pid| (hfid, pid)).collect();
+        assert_eq!(8, sm.flush_pages(&all).unwrap());
+        let stats = sm.buffer_pool_stats().unwrap();
+        assert_eq!(
+            (8, 3, 0),
+            (stats.dirty_writes, stats.flush_writes, stats.dirty)
+        );
+        assert_eq!(0, sm.flush_pages(&all).unwrap());
+
+        sm.pin_page(hfid, 19)
+            .unwrap()
+            .write()
+            .add_value(&get_random_byte_vec(10))
+            .unwrap();
+        sm.flush_all().unwrap();
+        assert_eq!(4, sm.buffer_pool_stats().unwrap().flush_writes);
+        sm.clear_cache();
+        for pid in 0..20 {
+            let page = sm
+                .get_page(hfid, pid, tid, Permissions::ReadOnly, false)
+                .unwrap();
+            let expected = if dirtied.contains(&pid) || pid == 19 {
+                11
+            } else {
+                10
+            };
+            assert_eq!(expected, page.slot_count(), "page {pid}");
+        }
+    }
 }
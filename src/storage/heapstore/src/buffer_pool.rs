@@ -1,12 +1,1117 @@
-use crate::page::Page;
 use crate::heap_page::HeapPage;
-use crate::WRITE_THROUGH;
-use common::ids::ValueId;
-use common::CrustyError;
-use common::{PAGE_SIZE, PAGE_SLOTS};
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
-use std::sync::{Arc, RwLock, RwLockWriteGuard};
+use crate::heapfile::HeapFile;
+use crate::latch::LatchedPage;
+use crate::page::Page;
+use crate::wal::Wal;
+use common::prelude::*;
+use common::PAGE_SIZE;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A page is cached under its container and page id.
+pub type FrameKey = (ContainerId, PageId);
+
+/// Chooses which cached page the buffer pool evicts when it needs a frame.
+/// The pool tells the policy about every page it caches, touches and drops, and asks it for a
+/// victim only among the pages that are not pinned.
+pub trait ReplacementPolicy: Send {
+    /// Short name for logs and benchmark reports.
+    fn name(&self) -> &'static str;
+    /// A page entered the pool or was read or written through it.
+    fn record_access(&mut self, key: FrameKey);
+    /// A page left the pool.
+    fn remove(&mut self, key: FrameKey);
+    /// Page to evict among those is_evictable accepts, or None if there is none.
+    /// The page stays known to the policy until the pool calls remove.
+    fn victim(&mut self, is_evictable: &dyn Fn(FrameKey) -> bool) -> Option<FrameKey>;
+}
+
+/// Evicts the page that was used longest ago.
+#[derive(Default)]
+pub struct LruPolicy {
+    /// Pages from least to most recently used.
+    order: BTreeMap<u64, FrameKey>,
+    /// Position of every page in order.
+    stamps: HashMap<FrameKey, u64>,
+    clock: u64,
+}
+
+impl LruPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReplacementPolicy for LruPolicy {
+    fn name(&self) -> &'static str {
+        "lru"
+    }
+
+    fn record_access(&mut self, key: FrameKey) {
+        self.clock += 1;
+        if let Some(old) = self.stamps.insert(key, self.clock) {
+            self.order.remove(&old);
+        }
+        self.order.insert(self.clock, key);
+    }
+
+    fn remove(&mut self, key: FrameKey) {
+        if let Some(stamp) = self.stamps.remove(&key) {
+            self.order.remove(&stamp);
+        }
+    }
+
+    fn victim(&mut self, is_evictable: &dyn Fn(FrameKey) -> bool) -> Option<FrameKey> {
+        self.order.values().copied().find(|&key| is_evictable(key))
+    }
+}
+
+/// Second chance replacement. Pages sit on a ring with a referenced bit set on every access.
+/// The hand sweeps the ring clearing set bits and evicts the first page whose bit is already
+/// clear, so an access costs a bit flip instead of moving a page in a list, and a single scan
+/// cannot push out pages that are used again before the hand comes back around.
+#[derive(Default)]
+pub struct ClockPolicy {
+    /// Ring slots holding a page and its referenced bit. Removed pages leave a hole for reuse.
+    ring: Vec<Option<(FrameKey, bool)>>,
+    /// Ring slot of every page.
+    index: HashMap<FrameKey, usize>,
+    /// Holes in the ring.
+    free: Vec<usize>,
+    hand: usize,
+}
+
+impl ClockPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReplacementPolicy for ClockPolicy {
+    fn name(&self) -> &'static str {
+        "clock"
+    }
+
+    fn record_access(&mut self, key: FrameKey) {
+        if let Some(&slot) = self.index.get(&key) {
+            self.ring[slot] = Some((key, true));
+            return;
+        }
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None => {
+                self.ring.push(None);
+                self.ring.len() - 1
+            }
+        };
+        self.ring[slot] = Some((key, true));
+        self.index.insert(key, slot);
+    }
+
+    fn remove(&mut self, key: FrameKey) {
+        if let Some(slot) = self.index.remove(&key) {
+            self.ring[slot] = None;
+            self.free.push(slot);
+        }
+    }
+
+    fn victim(&mut self, is_evictable: &dyn Fn(FrameKey) -> bool) -> Option<FrameKey> {
+        // Two sweeps clear every bit the first one finds set
+        for _ in 0..2 * self.ring.len() {
+            let slot = self.hand;
+            self.hand = (self.hand + 1) % self.ring.len();
+            match &mut self.ring[slot] {
+                Some((key, referenced)) if is_evictable(*key) => {
+                    if *referenced {
+                        *referenced = false;
+                    } else {
+                        return Some(*key);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// Replacement policies that can be chosen by name, for configuration and benchmarks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ReplacementPolicyKind {
+    #[default]
+    Lru,
+    Clock,
+}
+
+impl ReplacementPolicyKind {
+    /// A new empty policy of this kind.
+    pub fn build(self) -> Box<dyn ReplacementPolicy> {
+        match self {
+            ReplacementPolicyKind::Lru => Box::new(LruPolicy::new()),
+            ReplacementPolicyKind::Clock => Box::new(ClockPolicy::new()),
+        }
+    }
+}
+
+impl FromStr for ReplacementPolicyKind {
+    type Err = CrustyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lru" => Ok(ReplacementPolicyKind::Lru),
+            "clock" => Ok(ReplacementPolicyKind::Clock),
+            _ => Err(CrustyError::CrustyError(format!(
+                "Unknown replacement policy {}, expected lru or clock",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ReplacementPolicyKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.build().name())
+    }
+}
+
+/// Size and replacement policy of a buffer pool, saved on shutdown so the next open sets the
+/// pool up the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PoolSettings {
+    pub(crate) frames: usize,
+    /// None for a policy that is not one of the ReplacementPolicyKinds.
+    pub(crate) policy: Option<ReplacementPolicyKind>,
+}
+
+/// Contents of a frame, shared with the guards pinning it.
+struct SharedPage {
+    page: LatchedPage,
+    /// Pool tick at which the page last went from clean to dirty, 0 while it is clean.
+    /// Only set under the page's write latch and only cleared under its read latch, so a write
+    /// back never clears the flag of a change it did not write.
+    dirtied_at: AtomicU64,
+}
+
+impl SharedPage {
+    /// Frame contents, dirty since dirtied_at or clean if it is 0.
+    fn new(page: Page, dirtied_at: u64) -> Arc<Self> {
+        Arc::new(SharedPage {
+            page: LatchedPage::new(page),
+            dirtied_at: AtomicU64::new(dirtied_at),
+        })
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirtied_at.load(Ordering::Acquire) != 0
+    }
+
+    /// Mark the page dirty as of tick unless it already was.
+    fn mark_dirty(&self, tick: u64) {
+        let _ = self
+            .dirtied_at
+            .compare_exchange(0, tick, Ordering::AcqRel, Ordering::Acquire);
+    }
+
+    /// Copy of the page and when it became dirty if it is dirty, marking it clean.
+    /// The caller marks it dirty again as of the returned tick if writing the copy fails.
+    fn take_dirty(&self) -> Option<(u64, Page)> {
+        let page = self.page.read();
+        match self.dirtied_at.swap(0, Ordering::AcqRel) {
+            0 => None,
+            dirtied_at => Some((dirtied_at, page.clone())),
+        }
+    }
+
+    /// Write the page to hf if it is dirty and mark it clean, making wal durable up to the
+    /// page's last change first. True if it was written.
+    fn write_back(&self, hf: &HeapFile, wal: Option<&Arc<Wal>>) -> Result<bool, CrustyError> {
+        let page = self.page.read();
+        let dirtied_at = self.dirtied_at.swap(0, Ordering::AcqRel);
+        if dirtied_at == 0 {
+            return Ok(false);
+        }
+        let written = wal
+            .map_or(Ok(()), |wal| wal.flush(page.get_lsn()))
+            .and_then(|_| hf.write_page_to_file(&page));
+        if let Err(e) = written {
+            self.dirtied_at.store(dirtied_at, Ordering::Release);
+            return Err(e);
+        }
+        Ok(true)
+    }
+}
+
+/// How much memory the buffer pool may use, as a number of pages or of bytes.
+/// A size in bytes is rounded down to whole pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferPoolSize {
+    Pages(usize),
+    Bytes(usize),
+}
+
+impl BufferPoolSize {
+    /// Number of frames, Err if the size does not hold a single page.
+    pub fn frames(self) -> Result<usize, CrustyError> {
+        let frames = match self {
+            BufferPoolSize::Pages(pages) => pages,
+            BufferPoolSize::Bytes(bytes) => bytes / PAGE_SIZE,
+        };
+        if frames == 0 {
+            return Err(CrustyError::CrustyError(format!(
+                "Buffer pool size {:?} is less than one {} byte page",
+                self, PAGE_SIZE
+            )));
+        }
+        Ok(frames)
+    }
+}
+
+impl FromStr for BufferPoolSize {
+    type Err = CrustyError;
+
+    /// A number of pages such as 64, or of bytes with a unit such as 4096B, 512KiB or 1GiB.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (digits, unit) = s.split_at(split);
+        let n: usize = digits.parse().map_err(|_| {
+            CrustyError::CrustyError(format!(
+                "Buffer pool size {} does not start with a number",
+                s
+            ))
+        })?;
+        let unit_bytes = match unit.trim() {
+            "" => return Ok(BufferPoolSize::Pages(n)),
+            "B" => 1,
+            "KiB" => 1 << 10,
+            "MiB" => 1 << 20,
+            "GiB" => 1 << 30,
+            other => {
+                return Err(CrustyError::CrustyError(format!(
+                    "Unknown buffer pool size unit {}, expected B, KiB, MiB or GiB",
+                    other
+                )))
+            }
+        };
+        n.checked_mul(unit_bytes)
+            .map(BufferPoolSize::Bytes)
+            .ok_or_else(|| CrustyError::CrustyError(format!("Buffer pool size {} is too large", s)))
+    }
+}
+
+/// Counters of a buffer pool since it was created or its statistics were reset, and its
+/// occupancy when they were taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct BufferPoolStats {
+    /// Page lookups served from a frame.
+    pub hits: u64,
+    /// Page lookups that read the page from its heap file.
+    pub misses: u64,
+    /// Frames dropped to make room for another page.
+    pub evictions: u64,
+    /// Dirty pages written back, on eviction or by a flush.
+    pub dirty_writes: u64,
+    /// Pages read into the pool ahead of a scan reaching them.
+    pub prefetches: u64,
+    /// Writes issued writing back dirty pages. A flush writes each run of consecutive pages
+    /// with one.
+    pub flush_writes: u64,
+    /// Frames holding a page.
+    pub occupancy: usize,
+    /// Frames holding a page that has not been written back.
+    pub dirty: usize,
+    /// Number of frames.
+    pub capacity: usize,
+}
+
+impl BufferPoolStats {
+    /// Share of lookups served from a frame, 0 before the first lookup.
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+impl fmt::Display for BufferPoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "buffer pool: {}/{} frames ({} dirty), {} hits, {} misses ({:.1}% hit), {} evictions, {} dirty writes in {} writes, {} prefetches",
+            self.occupancy,
+            self.capacity,
+            self.dirty,
+            self.hits,
+            self.misses,
+            self.hit_ratio() * 100.0,
+            self.evictions,
+            self.dirty_writes,
+            self.flush_writes,
+            self.prefetches
+        )
+    }
+}
+
+/// Running totals behind BufferPoolStats.
+#[derive(Default)]
+struct PoolCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    dirty_writes: AtomicU64,
+    prefetches: AtomicU64,
+    flush_writes: AtomicU64,
+}
+
+/// One cached page and what the pool needs to know to evict it.
+struct Frame {
+    /// File the page is written back to.
+    hf: Arc<HeapFile>,
+    shared: Arc<SharedPage>,
+    /// Outstanding pins. A pinned frame is never evicted.
+    pins: usize,
+}
+
+/// A page pinned in the buffer pool, unpinned when the guard is dropped.
+/// Reads and writes go to the cached page under its latch and a write marks it dirty so it is
+/// written back on eviction or flush. Guards are not coordinated with the storage manager's own
+/// value updates, so code writing a page through a guard should own that page.
+/// Do not call into the storage manager for the same page while holding its write latch.
+pub struct PageGuard {
+    pool: Arc<BufferPool>,
+    key: FrameKey,
+    shared: Arc<SharedPage>,
+}
+
+impl PageGuard {
+    pub fn container_id(&self) -> ContainerId {
+        self.key.0
+    }
 
+    pub fn page_id(&self) -> PageId {
+        self.key.1
+    }
+
+    /// Shared latch on the page.
+    pub fn read(&self) -> RwLockReadGuard<'_, Page> {
+        self.shared.page.read()
+    }
+
+    /// Exclusive latch on the page, which is marked dirty.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Page> {
+        let page = self.shared.page.write();
+        self.shared.mark_dirty(self.pool.tick());
+        page
+    }
+
+    /// Changed since it was read or last written back.
+    pub fn is_dirty(&self) -> bool {
+        self.shared.is_dirty()
+    }
+}
+
+impl Drop for PageGuard {
+    fn drop(&mut self) {
+        self.pool.unpin_shared(self.key, &self.shared);
+    }
+}
+
+struct PoolState {
+    frames: HashMap<FrameKey, Frame>,
+    policy: Box<dyn ReplacementPolicy>,
+    /// Bumped whenever frames are discarded, so a read that raced a discard is not cached.
+    discards: u64,
+}
+
+/// Fixed number of page frames shared by every container of a storage manager.
+/// Misses read through to the heap file and the replacement policy picks an unpinned frame to
+/// make room, written back first if it is dirty.
+/// The pool lock is held across the I/O of a miss so two readers of a page read it once.
 pub(crate) struct BufferPool {
+    /// Number of frames, only changed under the state lock.
+    capacity: AtomicUsize,
+    state: Mutex<PoolState>,
+    counters: PoolCounters,
+    /// Source of the ticks ordering when pages became dirty.
+    dirty_clock: AtomicU64,
+    /// Held while a flush writes copies of pages, so an older copy never lands after a newer one.
+    flushing: Mutex<()>,
+    /// Log of the changes to the cached pages, flushed past a page's changes before the page
+    /// is written back.
+    wal: OnceLock<Arc<Wal>>,
+}
+
+/// A dirty frame pinned so it can be written back without the pool lock.
+type PinnedFrame = (FrameKey, Arc<HeapFile>, Arc<SharedPage>);
+
+impl BufferPool {
+    /// Pool of capacity frames evicting the least recently used page.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self::with_policy(capacity, Box::new(LruPolicy::new()))
+    }
+
+    /// Pool of capacity frames evicting the pages policy picks.
+    pub(crate) fn with_policy(capacity: usize, policy: Box<dyn ReplacementPolicy>) -> Self {
+        assert!(capacity > 0, "A buffer pool needs at least one frame");
+        BufferPool {
+            capacity: AtomicUsize::new(capacity),
+            state: Mutex::new(PoolState {
+                frames: HashMap::new(),
+                policy,
+                discards: 0,
+            }),
+            dirty_clock: AtomicU64::new(0),
+            flushing: Mutex::new(()),
+            counters: PoolCounters::default(),
+            wal: OnceLock::new(),
+        }
+    }
+
+    /// Write back no page before wal holds its changes durably. Only the first log set is used.
+    pub(crate) fn set_wal(&self, wal: Arc<Wal>) {
+        let _ = self.wal.set(wal);
+    }
+
+    /// Switch to another replacement policy. Cached pages are handed to it in page order.
+    pub(crate) fn set_policy(&self, mut policy: Box<dyn ReplacementPolicy>) {
+        let mut state = self.state.lock().unwrap();
+        let mut keys: Vec<FrameKey> = state.frames.keys().copied().collect();
+        keys.sort_unstable();
+        for key in keys {
+            policy.record_access(key);
+        }
+        state.policy = policy;
+    }
+
+    /// Current size and replacement policy.
+    pub(crate) fn settings(&self) -> PoolSettings {
+        PoolSettings {
+            frames: self.capacity(),
+            policy: self.policy_name().parse().ok(),
+        }
+    }
+
+    /// Resize to and switch to the policy of settings. A policy of None is left as it is.
+    pub(crate) fn apply_settings(&self, settings: PoolSettings) -> Result<(), CrustyError> {
+        self.set_capacity(settings.frames)?;
+        if let Some(kind) = settings.policy {
+            self.set_policy(kind.build());
+        }
+        Ok(())
+    }
+
+    /// Name of the replacement policy in use.
+    pub(crate) fn policy_name(&self) -> &'static str {
+        self.state.lock().unwrap().policy.name()
+    }
+
+    /// Number of frames.
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Change the number of frames. Shrinking evicts unpinned frames, written back first if they
+    /// are dirty, until the pool fits. Frames left over because they are pinned are evicted as
+    /// later misses need room.
+    pub(crate) fn set_capacity(&self, capacity: usize) -> Result<(), CrustyError> {
+        assert!(capacity > 0, "A buffer pool needs at least one frame");
+        let mut state = self.state.lock().unwrap();
+        self.capacity.store(capacity, Ordering::Relaxed);
+        while state.frames.len() > capacity {
+            if state.frames.values().all(|f| f.pins > 0) {
+                debug!(
+                    "Buffer pool holds {} pinned frames, more than its {} frames",
+                    state.frames.len(),
+                    capacity
+                );
+                break;
+            }
+            self.evict_one(&mut state)?;
+        }
+        Ok(())
+    }
+
+    /// Counters since the pool was created or they were reset, and the current occupancy.
+    pub(crate) fn stats(&self) -> BufferPoolStats {
+        let state = self.state.lock().unwrap();
+        BufferPoolStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+            dirty_writes: self.counters.dirty_writes.load(Ordering::Relaxed),
+            prefetches: self.counters.prefetches.load(Ordering::Relaxed),
+            flush_writes: self.counters.flush_writes.load(Ordering::Relaxed),
+            occupancy: state.frames.len(),
+            dirty: state
+                .frames
+                .values()
+                .filter(|f| f.shared.is_dirty())
+                .count(),
+            capacity: self.capacity(),
+        }
+    }
+
+    /// Start the counters again from 0.
+    pub(crate) fn reset_stats(&self) {
+        let counters = &self.counters;
+        for counter in [
+            &counters.hits,
+            &counters.misses,
+            &counters.evictions,
+            &counters.dirty_writes,
+            &counters.prefetches,
+            &counters.flush_writes,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Number of frames holding a page.
+    pub(crate) fn len(&self) -> usize {
+        self.state.lock().unwrap().frames.len()
+    }
+
+    /// Pages the frames hold, in container and page order.
+    pub(crate) fn resident_pages(&self) -> Vec<FrameKey> {
+        let mut keys: Vec<FrameKey> = self.state.lock().unwrap().frames.keys().copied().collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Number of frames changed since they were last written back.
+    pub(crate) fn dirty_count(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .frames
+            .values()
+            .filter(|f| f.shared.is_dirty())
+            .count()
+    }
+
+    /// Pin a page, read from hf on a miss, until the returned guard is dropped.
+    pub(crate) fn pin_page(
+        self: &Arc<Self>,
+        hf: &Arc<HeapFile>,
+        page_id: PageId,
+    ) -> Result<PageGuard, CrustyError> {
+        let shared = self.pin_frame(hf, page_id)?;
+        Ok(PageGuard {
+            pool: Arc::clone(self),
+            key: (hf.container_id, page_id),
+            shared,
+        })
+    }
+
+    /// Copy of a page, read from hf on a miss. With pin the frame stays until unpin is called.
+    pub(crate) fn get_page(
+        &self,
+        hf: &Arc<HeapFile>,
+        page_id: PageId,
+        pin: bool,
+    ) -> Result<Page, CrustyError> {
+        let shared = self.pin_frame(hf, page_id)?;
+        // Copied outside the pool lock so a guard holding the page's write latch stalls only
+        // this reader
+        let page = shared.page.read().clone();
+        if !pin {
+            self.unpin_shared((hf.container_id, page_id), &shared);
+        }
+        Ok(page)
+    }
+
+    /// Cache page as the current copy of its page, replacing any cached one.
+    /// A dirty page is written back when evicted or flushed, a clean one never is.
+    pub(crate) fn put_page(
+        &self,
+        hf: &Arc<HeapFile>,
+        page: Page,
+        dirty: bool,
+    ) -> Result<(), CrustyError> {
+        let mut state = self.state.lock().unwrap();
+        let key = (hf.container_id, page.get_page_id());
+        let shared = match state.frames.get_mut(&key) {
+            Some(frame) => {
+                frame.pins += 1;
+                Arc::clone(&frame.shared)
+            }
+            None => {
+                let dirtied_at = if dirty { self.tick() } else { 0 };
+                self.insert_frame(&mut state, hf, SharedPage::new(page, dirtied_at))?;
+                state.touch(key);
+                return Ok(());
+            }
+        };
+        state.touch(key);
+        drop(state);
+        {
+            let mut cached = shared.page.write();
+            *cached = page;
+            if dirty {
+                shared.mark_dirty(self.tick());
+            }
+        }
+        self.unpin_shared(key, &shared);
+        Ok(())
+    }
+
+    /// Copy of a page if it is cached, counted as a hit. None is not counted, so a caller reading
+    /// the page itself reports the miss with install_read.
+    #[cfg(feature = "async")]
+    pub(crate) fn cached_page(&self, container_id: ContainerId, page_id: PageId) -> Option<Page> {
+        let shared = {
+            let mut state = self.state.lock().unwrap();
+            let key = (container_id, page_id);
+            let frame = state.frames.get_mut(&key)?;
+            frame.pins += 1;
+            let shared = Arc::clone(&frame.shared);
+            state.touch(key);
+            shared
+        };
+        self.counters.hits.fetch_add(1, Ordering::Relaxed);
+        let page = shared.page.read().clone();
+        self.unpin_shared((container_id, page_id), &shared);
+        Some(page)
+    }
+
+    /// Cache a page read from hf without the pool lock, counted as a miss, and return the current
+    /// copy. If the page was cached meanwhile that copy wins since it may be newer.
+    #[cfg(feature = "async")]
+    pub(crate) fn install_read(&self, hf: &Arc<HeapFile>, page: Page) -> Result<Page, CrustyError> {
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        let mut state = self.state.lock().unwrap();
+        let key = (hf.container_id, page.get_page_id());
+        let shared = match state.frames.get_mut(&key) {
+            Some(frame) => {
+                frame.pins += 1;
+                Arc::clone(&frame.shared)
+            }
+            None => {
+                self.insert_frame(&mut state, hf, SharedPage::new(page.clone(), 0))?;
+                state.touch(key);
+                return Ok(page);
+            }
+        };
+        state.touch(key);
+        drop(state);
+        let cached = shared.page.read().clone();
+        self.unpin_shared(key, &shared);
+        Ok(cached)
+    }
+
+    /// Drop one pin of a page. False if the page was not pinned.
+    pub(crate) fn unpin(&self, container_id: ContainerId, page_id: PageId) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.frames.get_mut(&(container_id, page_id)) {
+            Some(frame) if frame.pins > 0 => {
+                frame.pins -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Write every dirty page back in container and page order and mark it clean.
+    pub(crate) fn flush_all(&self) -> Result<(), CrustyError> {
+        let dirty = self.pin_dirty(false, usize::MAX);
+        self.write_back_pinned(dirty).map(|_| ())
+    }
+
+    /// Write back the dirty pages among keys together and mark them clean. Pages not cached or
+    /// clean are skipped. Returns how many were written.
+    pub(crate) fn flush_pages(&self, keys: &[FrameKey]) -> Result<usize, CrustyError> {
+        let keys: BTreeSet<FrameKey> = keys.iter().copied().collect();
+        let dirty: Vec<PinnedFrame> = {
+            let mut state = self.state.lock().unwrap();
+            keys.into_iter()
+                .filter_map(|key| {
+                    let frame = state.frames.get_mut(&key)?;
+                    if !frame.shared.is_dirty() {
+                        return None;
+                    }
+                    frame.pins += 1;
+                    Some((key, Arc::clone(&frame.hf), Arc::clone(&frame.shared)))
+                })
+                .collect()
+        };
+        self.write_back_pinned(dirty)
+    }
+
+    /// Write back up to max of the pages that have been dirty the longest, together in page
+    /// order. Returns how many were written.
+    pub(crate) fn flush_oldest(&self, max: usize) -> Result<usize, CrustyError> {
+        let dirty = self.pin_dirty(true, max);
+        self.write_back_pinned(dirty)
+    }
+
+    /// Write back every dirty page then drop every frame that is not pinned.
+    pub(crate) fn clear(&self) -> Result<(), CrustyError> {
+        self.flush_all()?;
+        let mut state = self.state.lock().unwrap();
+        let unpinned: Vec<FrameKey> = state
+            .frames
+            .iter()
+            .filter(|(_, f)| f.pins == 0)
+            .map(|(k, _)| *k)
+            .collect();
+        for key in unpinned {
+            state.remove(key);
+        }
+        state.discards += 1;
+        Ok(())
+    }
+
+    /// Drop the frames of a container without writing them back, pinned or not.
+    pub(crate) fn discard_container(&self, container_id: ContainerId) {
+        self.discard_pages(container_id, 0);
+    }
+
+    /// Drop the frames of a container's pages from first_page on without writing them back,
+    /// pinned or not.
+    pub(crate) fn discard_pages(&self, container_id: ContainerId, first_page: PageId) {
+        let mut state = self.state.lock().unwrap();
+        let keys: Vec<FrameKey> = state
+            .frames
+            .keys()
+            .filter(|(cid, pid)| *cid == container_id && *pid >= first_page)
+            .copied()
+            .collect();
+        for key in keys {
+            state.remove(key);
+        }
+        state.discards += 1;
+    }
+
+    /// Drop every frame without writing it back.
+    pub(crate) fn discard_all(&self) {
+        let mut state = self.state.lock().unwrap();
+        let keys: Vec<FrameKey> = state.frames.keys().copied().collect();
+        for key in keys {
+            state.remove(key);
+        }
+        state.discards += 1;
+    }
+
+    /// Read a page into the pool ahead of use unless it is cached. The read happens without the
+    /// pool lock and the page is cached clean. True if it was read.
+    /// Not cached if a container was discarded meanwhile, since the page may belong to it.
+    pub(crate) fn prefetch(
+        &self,
+        hf: &Arc<HeapFile>,
+        page_id: PageId,
+    ) -> Result<bool, CrustyError> {
+        let key = (hf.container_id, page_id);
+        let discards = {
+            let state = self.state.lock().unwrap();
+            if state.frames.contains_key(&key) {
+                return Ok(false);
+            }
+            state.discards
+        };
+        let page = hf.read_page_from_file(page_id)?;
+        let mut state = self.state.lock().unwrap();
+        if state.discards != discards || state.frames.contains_key(&key) {
+            return Ok(false);
+        }
+        self.insert_frame(&mut state, hf, SharedPage::new(page, 0))?;
+        state.touch(key);
+        self.counters.prefetches.fetch_add(1, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    /// Next tick of the dirty clock, never 0.
+    fn tick(&self) -> u64 {
+        self.dirty_clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Pin up to max dirty frames, oldest first or in container and page order.
+    fn pin_dirty(&self, oldest_first: bool, max: usize) -> Vec<PinnedFrame> {
+        let mut state = self.state.lock().unwrap();
+        let mut dirty: Vec<(u64, FrameKey)> = state
+            .frames
+            .iter()
+            .map(|(k, f)| (f.shared.dirtied_at.load(Ordering::Acquire), *k))
+            .filter(|(dirtied_at, _)| *dirtied_at != 0)
+            .map(|(dirtied_at, k)| (if oldest_first { dirtied_at } else { 0 }, k))
+            .collect();
+        dirty.sort_unstable();
+        dirty.truncate(max);
+        dirty
+            .into_iter()
+            .map(|(_, key)| {
+                let frame = state.frames.get_mut(&key).unwrap();
+                frame.pins += 1;
+                (key, Arc::clone(&frame.hf), Arc::clone(&frame.shared))
+            })
+            .collect()
+    }
+
+    /// Write back pinned frames in order without holding the pool lock, then unpin them.
+    /// Stops writing at the first error. Returns how many pages were written.
+    fn write_back_pinned(&self, mut frames: Vec<PinnedFrame>) -> Result<usize, CrustyError> {
+        let span = tracing::trace_span!(
+            "flush",
+            dirty = frames.len(),
+            written = tracing::field::Empty
+        );
+        let _entered = span.enter();
+        frames.sort_unstable_by_key(|(key, _, _)| *key);
+        let mut result = Ok(0);
+        {
+            let _flushing = self.flushing.lock().unwrap();
+            for group in frames.chunk_by(|a, b| a.0 .0 == b.0 .0) {
+                match self.write_back_container(group) {
+                    Ok(written) => *result.as_mut().unwrap() += written,
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+        }
+        for (key, _, shared) in &frames {
+            self.unpin_shared(*key, shared);
+        }
+        if let Ok(written) = result {
+            span.record("written", written);
+        }
+        result
+    }
+
+    /// Write back the dirty ones among pinned frames of one container sorted by page id, each
+    /// run of consecutive pages with one write. Pages are copied under their latch and written
+    /// after it is released, so a guard holding several latches cannot deadlock with a flush.
+    fn write_back_container(&self, frames: &[PinnedFrame]) -> Result<usize, CrustyError> {
+        let hf = &frames[0].1;
+        let taken: Vec<(&Arc<SharedPage>, u64, Page)> = frames
+            .iter()
+            .filter_map(|(_, _, shared)| {
+                let (dirtied_at, page) = shared.take_dirty()?;
+                Some((shared, dirtied_at, page))
+            })
+            .collect();
+        if taken.is_empty() {
+            return Ok(0);
+        }
+        let pages: Vec<Page> = taken.iter().map(|(_, _, page)| page.clone()).collect();
+        let last_change = pages.iter().map(|page| page.get_lsn()).max().unwrap();
+        let written = self
+            .wal
+            .get()
+            .map_or(Ok(()), |wal| wal.flush(last_change))
+            .and_then(|_| hf.write_pages_to_file(&pages));
+        match written {
+            Ok(writes) => {
+                tracing::trace!(
+                    container_id = hf.container_id,
+                    pages = pages.len(),
+                    writes,
+                    "wrote back dirty pages"
+                );
+                let counters = &self.counters;
+                counters
+                    .dirty_writes
+                    .fetch_add(pages.len() as u64, Ordering::Relaxed);
+                counters
+                    .flush_writes
+                    .fetch_add(writes as u64, Ordering::Relaxed);
+                Ok(pages.len())
+            }
+            Err(e) => {
+                for (shared, dirtied_at, _) in taken {
+                    shared.mark_dirty(dirtied_at);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Pin the frame of a page, reading it from hf on a miss.
+    fn pin_frame(
+        &self,
+        hf: &Arc<HeapFile>,
+        page_id: PageId,
+    ) -> Result<Arc<SharedPage>, CrustyError> {
+        let mut state = self.state.lock().unwrap();
+        let key = (hf.container_id, page_id);
+        if state.frames.contains_key(&key) {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.counters.misses.fetch_add(1, Ordering::Relaxed);
+            let page = hf.read_page_from_file(page_id)?;
+            self.insert_frame(&mut state, hf, SharedPage::new(page, 0))?;
+        }
+        state.touch(key);
+        let frame = state.frames.get_mut(&key).unwrap();
+        frame.pins += 1;
+        Ok(Arc::clone(&frame.shared))
+    }
+
+    /// Drop a pin taken on shared, unless its frame has since been discarded.
+    fn unpin_shared(&self, key: FrameKey, shared: &Arc<SharedPage>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(frame) = state.frames.get_mut(&key) {
+            if Arc::ptr_eq(&frame.shared, shared) && frame.pins > 0 {
+                frame.pins -= 1;
+            }
+        }
+    }
+
+    /// Drop the policy's pick among the unpinned frames, written back first if it is dirty.
+    fn evict_one(&self, state: &mut PoolState) -> Result<(), CrustyError> {
+        let PoolState { frames, policy, .. } = &mut *state;
+        let victim = policy
+            .victim(&|key| frames[&key].pins == 0)
+            .ok_or_else(|| {
+                CrustyError::CrustyError(format!(
+                    "All {} buffer pool frames are pinned",
+                    frames.len()
+                ))
+            })?;
+        // Unpinned so no guard holds its latch
+        let frame = &state.frames[&victim];
+        if frame.shared.write_back(&frame.hf, self.wal.get())? {
+            self.counters.dirty_writes.fetch_add(1, Ordering::Relaxed);
+            self.counters.flush_writes.fetch_add(1, Ordering::Relaxed);
+        }
+        tracing::trace!(container_id = victim.0, page_id = victim.1, "evicted page");
+        state.remove(victim);
+        self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Add a frame, evicting the policy's pick among the unpinned ones if the pool is full.
+    fn insert_frame(
+        &self,
+        state: &mut PoolState,
+        hf: &Arc<HeapFile>,
+        shared: Arc<SharedPage>,
+    ) -> Result<(), CrustyError> {
+        while state.frames.len() >= self.capacity() {
+            self.evict_one(state)?;
+        }
+        let key = (hf.container_id, shared.page.read().get_page_id());
+        state.frames.insert(
+            key,
+            Frame {
+                hf: Arc::clone(hf),
+                shared,
+                pins: 0,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Thread writing back the pages of a buffer pool that have been dirty the longest, so dirty
+/// pages do not wait for eviction or shutdown to reach disk.
+/// Stops when stopped or dropped.
+pub(crate) struct Flusher {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl Flusher {
+    /// Every interval write back up to batch of pool's oldest dirty pages.
+    pub(crate) fn start(pool: Arc<BufferPool>, interval: Duration, batch: usize) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::Builder::new()
+            .name("heapstore-flusher".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    match pool.flush_oldest(batch) {
+                        Ok(0) => {}
+                        Ok(written) => trace!("Flusher wrote back {} pages", written),
+                        Err(e) => error!("Error writing back dirty pages: {}", e),
+                    }
+                }
+            })
+            .expect("error spawning the buffer pool flusher");
+        Flusher { stop, handle }
+    }
+
+    /// Stop the thread and wait for a write back in progress to finish.
+    pub(crate) fn stop(self) {
+        let _ = self.stop.send(());
+        if self.handle.join().is_err() {
+            error!("Buffer pool flusher panicked");
+        }
+    }
+}
+
+impl PoolState {
+    /// Tell the policy a cached page was used.
+    fn touch(&mut self, key: FrameKey) {
+        self.policy.record_access(key);
+    }
+
+    fn remove(&mut self, key: FrameKey) {
+        if self.frames.remove(&key).is_some() {
+            self.policy.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testutil::init;
+
+    fn all(_: FrameKey) -> bool {
+        true
+    }
+
+    #[test]
+    fn hs_bp_lru_policy() {
+        init();
+        let mut lru = LruPolicy::new();
+        for pid in 0..4 {
+            lru.record_access((1, pid));
+        }
+        lru.record_access((1, 0));
+        assert_eq!(Some((1, 1)), lru.victim(&all));
+        assert_eq!(Some((1, 2)), lru.victim(&|key| key != (1, 1)));
+        lru.remove((1, 1));
+        lru.remove((1, 2));
+        lru.remove((1, 3));
+        assert_eq!(Some((1, 0)), lru.victim(&all));
+        lru.remove((1, 0));
+        assert_eq!(None, lru.victim(&all));
+    }
+
+    #[test]
+    fn hs_bp_clock_policy() {
+        init();
+        let mut clock = ClockPolicy::new();
+        assert_eq!(None, clock.victim(&all));
+        for pid in 0..4 {
+            clock.record_access((1, pid));
+        }
+        //every bit is set so the hand clears them all and comes back to the first page
+        assert_eq!(Some((1, 0)), clock.victim(&all));
+        clock.remove((1, 0));
+        //a page used since the sweep gets a second chance
+        clock.record_access((1, 1));
+        assert_eq!(Some((1, 2)), clock.victim(&all));
+        assert_eq!(Some((1, 3)), clock.victim(&|key| key != (1, 2)));
+        assert_eq!(None, clock.victim(&|_| false));
+        //a new page reuses the hole left by a removed one
+        clock.record_access((2, 0));
+        assert_eq!(4, clock.ring.len());
+
+        assert_eq!(
+            ReplacementPolicyKind::Clock,
+            "clock".parse::<ReplacementPolicyKind>().unwrap()
+        );
+        assert_eq!("lru", ReplacementPolicyKind::Lru.to_string());
+        assert!("fifo".parse::<ReplacementPolicyKind>().is_err());
+    }
 }
@@ -0,0 +1,238 @@
+use crate::buffer_pool::BufferPool;
+use crate::heapfile::HeapFile;
+use crate::metrics::{Metrics, StorageOp};
+use crate::storage_manager::{ContainerMap, StorageManager};
+use crate::wal::Wal;
+use common::prelude::*;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// What a checkpoint needs of a storage manager, so a background thread can take one.
+struct CheckpointTarget {
+    wal: Arc<Wal>,
+    buffer_pool: Option<Arc<BufferPool>>,
+    containers: ContainerMap,
+    metrics: Arc<Metrics>,
+}
+
+impl CheckpointTarget {
+    /// Write every page changed before now to its heap file, log a checkpoint and drop the
+    /// part of the log recovery no longer needs. Returns the checkpoint's Lsn.
+    fn run(&self) -> Result<Lsn, CrustyError> {
+        let _timer = self.metrics.time(StorageOp::Checkpoint);
+        let mut hfs: Vec<Arc<HeapFile>> =
+            self.containers.read().unwrap().values().cloned().collect();
+        hfs.sort_by_key(|hf| hf.container_id);
+        // No page is between being logged and being stored while every container is latched,
+        // so every change logged before redo_from is in the pool or the heap files
+        let redo_from = {
+            let _latches: Vec<MutexGuard<()>> = hfs
+                .iter()
+                .map(|hf| hf.write_latch.lock().unwrap())
+                .collect();
+            self.wal.end_lsn()
+        };
+        if let Some(bp) = &self.buffer_pool {
+            bp.flush_all()?;
+        }
+        for hf in &hfs {
+            hf.sync()?;
+            hf.persist_fsm()?;
+        }
+        let (lsn, needed) = self.wal.checkpoint(redo_from)?;
+        self.wal.truncate(needed)?;
+        Ok(lsn)
+    }
+}
+
+/// Background thread taking a checkpoint of a storage manager at a fixed interval.
+pub(crate) struct Checkpointer {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl Checkpointer {
+    fn start(target: CheckpointTarget, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::Builder::new()
+            .name("heapstore-checkpointer".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    match target.run() {
+                        Ok(lsn) => trace!("Checkpoint at {:?}", lsn),
+                        Err(e) => error!("Error taking a checkpoint: {}", e),
+                    }
+                }
+            })
+            .expect("error spawning the checkpointer");
+        Checkpointer { stop, handle }
+    }
+
+    /// Stop the thread and wait for a checkpoint in progress to finish.
+    pub(crate) fn stop(self) {
+        let _ = self.stop.send(());
+        if self.handle.join().is_err() {
+            error!("Checkpointer panicked");
+        }
+    }
+}
+
+impl StorageManager {
+    fn checkpoint_target(&self) -> Option<CheckpointTarget> {
+        Some(CheckpointTarget {
+            wal: Arc::clone(self.wal.as_ref()?),
+            buffer_pool: self.buffer_pool.clone(),
+            containers: Arc::clone(&self.cid_heapfile_map),
+            metrics: Arc::clone(&self.metrics),
+        })
+    }
+
+    /// Write every dirty page and free space map to disk, log a checkpoint and truncate the
+    /// write-ahead log to the records recovery still needs: those after the checkpoint and
+    /// those of transactions that were running when it was taken. Returns the checkpoint's Lsn.
+    pub fn checkpoint(&self) -> Result<Lsn, CrustyError> {
+        match self.checkpoint_target() {
+            Some(target) => target.run(),
+            None => Err(CrustyError::CrustyError(
+                "Storage manager has no write-ahead log to checkpoint".to_string(),
+            )),
+        }
+    }
+
+    /// Take a checkpoint every interval on a background thread, replacing any checkpointer
+    /// already running. Does nothing without a write-ahead log.
+    pub fn start_checkpointer(&self, interval: Duration) {
+        self.stop_checkpointer();
+        if let Some(target) = self.checkpoint_target() {
+            *self.checkpointer.lock().unwrap() = Some(Checkpointer::start(target, interval));
+        }
+    }
+
+    /// Stop the background checkpointer if one is running.
+    pub fn stop_checkpointer(&self) {
+        if let Some(checkpointer) = self.checkpointer.lock().unwrap().take() {
+            checkpointer.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::{LogRecord, WAL_FILENAME};
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_checkpoint_truncates_log() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let (winner, loser) = (TransactionId::new(), TransactionId::new());
+        let kept = get_random_vec_of_byte_vec(60, 50, 100);
+        let (kept_ids, lost_ids);
+        {
+            let sm = StorageManager::new(&tdir);
+            let wal = Arc::clone(sm.wal.as_ref().unwrap());
+            sm.create_table(1).unwrap();
+            kept_ids = sm.insert_values(1, kept.clone(), winner);
+            sm.commit_transaction(winner).unwrap();
+            let mut lost = sm.insert_values(1, get_random_vec_of_byte_vec(5, 50, 100), loser);
+
+            //the winner's records are gone but the running loser's are kept for undo
+            let entries = wal.entries().unwrap();
+            let first_loser = entries.iter().find(|e| e.tid == loser).unwrap().lsn;
+            let lsn = sm.checkpoint().unwrap();
+            let entries = wal.entries().unwrap();
+            assert_eq!(first_loser, entries[0].lsn);
+            assert!(entries.iter().all(|e| e.tid == loser || e.lsn == lsn));
+            match &entries.last().unwrap().record {
+                LogRecord::Checkpoint { active, .. } => assert_eq!(loser, active[0].0),
+                record => panic!("Expected a checkpoint, found {:?}", record),
+            }
+
+            //the loser keeps going after the checkpoint and a committed change only reaches
+            //the log
+            lost.extend(sm.insert_values(1, get_random_vec_of_byte_vec(5, 50, 100), loser));
+            sm.update_value(vec![7; 30], kept_ids[0], winner).unwrap();
+            sm.commit_transaction(winner).unwrap();
+            lost_ids = lost;
+        }
+
+        let sm = StorageManager::new(&tdir);
+        let tid = TransactionId::new();
+        assert_eq!(
+            vec![7; 30],
+            sm.get_value(kept_ids[0], tid, Permissions::ReadOnly)
+                .unwrap()
+        );
+        for (id, value) in kept_ids.iter().zip(&kept).skip(1) {
+            assert_eq!(
+                *value,
+                sm.get_value(*id, tid, Permissions::ReadOnly).unwrap()
+            );
+        }
+        for id in &lost_ids {
+            assert!(sm.get_value(*id, tid, Permissions::ReadOnly).is_err());
+        }
+
+        //with nothing running a checkpoint leaves only itself
+        sm.checkpoint().unwrap();
+        assert_eq!(1, sm.wal.as_ref().unwrap().entries().unwrap().len());
+    }
+
+    #[test]
+    fn hs_checkpoint_trait_writes_shrink_log() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let sm = <StorageManager as StorageTrait>::new(&tdir);
+        let wal = Arc::clone(sm.wal.as_ref().unwrap());
+        let wal_len = || std::fs::metadata(tdir.join(WAL_FILENAME)).unwrap().len();
+        sm.create_table(1).unwrap();
+        let tid = TransactionId::new();
+        for _ in 0..4 {
+            let ids = sm.insert_values(1, get_random_vec_of_byte_vec(200, 50, 100), tid);
+            sm.update_value(vec![3; 40], ids[0], tid).unwrap();
+            sm.delete_value(ids[1], tid).unwrap();
+            sm.commit_transaction(tid).unwrap();
+            //a transaction that logged no change to roll back does not pin the log
+            let idle = TransactionId::new();
+            let allocated = LogRecord::AllocatePage {
+                container_id: 1,
+                page_id: 0,
+            };
+            wal.append(idle, allocated).unwrap();
+            wal.flush(wal.end_lsn()).unwrap();
+            let before = wal_len();
+            sm.checkpoint().unwrap();
+            assert!(wal_len() < before);
+            assert_eq!(1, wal.entries().unwrap().len());
+        }
+        assert_eq!(
+            4 * 199,
+            sm.get_iterator(1, tid, Permissions::ReadOnly).count()
+        );
+    }
+
+    #[test]
+    fn hs_checkpoint_thread() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        sm.create_table(1).unwrap();
+        sm.insert_values(1, get_random_vec_of_byte_vec(20, 50, 100), tid);
+        sm.commit_transaction(tid).unwrap();
+        sm.start_checkpointer(Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(100));
+        sm.stop_checkpointer();
+        let entries = sm.wal.as_ref().unwrap().entries().unwrap();
+        assert!(matches!(
+            entries.last().unwrap().record,
+            LogRecord::Checkpoint { .. }
+        ));
+        assert_eq!(1, entries.len());
+        assert_eq!(0, sm.buffer_pool_stats().unwrap().dirty);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::encryption::KeyId;
+use crate::fixed_page::PageLayout;
+use crate::segment::DEFAULT_SEGMENT_SIZE;
+use common::ids::{StateMeta, StateType};
+use common::prelude::*;
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// File in the storage directory listing the containers.
+pub(crate) const CATALOG_FILENAME: &str = "catalog";
+/// Current layout of the catalog file.
+const CATALOG_VERSION: u32 = 1;
+
+/// How a new container is set up by StorageManager::create_container_with.
+#[derive(Debug, Clone)]
+pub struct ContainerOptions {
+    /// Unique name the container can be looked up by.
+    pub name: Option<String>,
+    /// What the container holds.
+    pub state_type: StateType,
+    /// Containers the state of this one is computed from.
+    pub dependencies: Option<Vec<ContainerId>>,
+    /// How the container's pages lay out their records.
+    pub layout: PageLayout,
+    /// Key, added with StorageManager::add_encryption_key, to encrypt the container's heap
+    /// file with. None leaves it unencrypted.
+    pub encryption_key: Option<KeyId>,
+    /// Most bytes the container's heap file may grow to, see
+    /// StorageManager::set_container_quota. None lets it grow without limit.
+    pub quota: Option<u64>,
+    /// Bytes of each segment file the container's heap file is split into, so it never
+    /// needs a file larger than that, rounded down to whole pages. Segments wholly past the
+    /// last page are removed when pages are cut off. None keeps the heap file in one file.
+    pub segment_size: Option<usize>,
+}
+
+impl Default for ContainerOptions {
+    fn default() -> Self {
+        ContainerOptions {
+            name: None,
+            state_type: StateType::BaseTable,
+            dependencies: None,
+            layout: PageLayout::default(),
+            encryption_key: None,
+            quota: None,
+            segment_size: Some(DEFAULT_SEGMENT_SIZE),
+        }
+    }
+}
+
+impl ContainerOptions {
+    /// Registry entry for a container created with these options.
+    pub(crate) fn to_meta(&self, container_id: ContainerId) -> StateMeta {
+        StateMeta {
+            state_type: self.state_type.clone(),
+            id: container_id,
+            name: self.name.clone(),
+            last_update: None,
+            dependencies: self.dependencies.clone(),
+        }
+    }
+}
+
+/// A container as listed by StorageManager::list_containers.
+#[derive(Debug, Clone)]
+pub struct ContainerInfo {
+    pub id: ContainerId,
+    pub name: Option<String>,
+    pub state_type: StateType,
+    pub dependencies: Option<Vec<ContainerId>>,
+    pub layout: PageLayout,
+    /// Data pages in the container's heap file.
+    pub pages: PageId,
+    /// The container's heap file.
+    pub path: PathBuf,
+    /// Key the container's heap file is encrypted with, None if it is not.
+    pub encryption_key: Option<KeyId>,
+    /// Most bytes the container's heap file may grow to, None if it has no quota.
+    pub quota: Option<u64>,
+}
+
+/// What is remembered of a container between runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CatalogEntry {
+    pub(crate) meta: StateMeta,
+    /// The container's heap file.
+    pub(crate) path: PathBuf,
+    #[serde(default)]
+    pub(crate) layout: PageLayout,
+    #[serde(default)]
+    pub(crate) quota: Option<u64>,
+}
+
+/// Every container of a storage manager, saved whenever one is created, changed or removed so
+/// they survive a process that exits without shutting down.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ContainerCatalog {
+    version: u32,
+    pub(crate) containers: Vec<CatalogEntry>,
+}
+
+impl ContainerCatalog {
+    pub(crate) fn new(mut containers: Vec<CatalogEntry>) -> Self {
+        containers.sort_by_key(|c| c.meta.id);
+        ContainerCatalog {
+            version: CATALOG_VERSION,
+            containers,
+        }
+    }
+
+    /// Catalog saved in dir, None if there is none.
+    pub(crate) fn load(dir: &Path) -> Result<Option<Self>, CrustyError> {
+        let path = dir.join(CATALOG_FILENAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let reader = BufReader::new(File::open(&path)?);
+        let catalog: ContainerCatalog = serde_json::from_reader(reader).map_err(|e| {
+            CrustyError::CrustyError(format!("Error reading catalog {:?}: {}", path, e))
+        })?;
+        if catalog.version > CATALOG_VERSION {
+            return Err(CrustyError::CrustyError(format!(
+                "Catalog version {} is newer than the supported version {}",
+                catalog.version, CATALOG_VERSION
+            )));
+        }
+        Ok(Some(catalog))
+    }
+
+    /// Replace the catalog saved in dir. The new catalog is written and synced beside the old
+    /// one then renamed over it, so a crash leaves one or the other whole.
+    pub(crate) fn save(&self, dir: &Path) -> Result<(), CrustyError> {
+        let path = dir.join(CATALOG_FILENAME);
+        let tmp = path.with_extension("tmp");
+        let bytes = serde_json::to_vec(self)
+            .map_err(|e| CrustyError::CrustyError(format!("Error encoding catalog: {}", e)))?;
+        let mut file = File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        // The rename itself is durable once the directory is synced
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use crate::page::Page;
+use common::prelude::*;
+use common::PAGE_SIZE;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+
+///page sized buffer at the alignment direct io needs for memory, offsets and lengths
+#[repr(C, align(4096))]
+struct AlignedPage([u8; PAGE_SIZE]);
+
+///second handle on a heap file opened with O_DIRECT so data page reads and writes bypass the os
+///page cache and every caching decision is the buffer pool's
+///the header keeps going through the buffered handle since it is never on this one's pages
+pub(crate) struct DirectFile {
+    file: File,
+}
+
+impl DirectFile {
+    ///Err if the file system does not support direct io
+    pub(crate) fn open(path: &Path) -> Result<Self, CrustyError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)
+            .map_err(|e| {
+                CrustyError::CrustyError(format!(
+                    "Cannot open {} for direct I/O: {}",
+                    path.to_string_lossy(),
+                    e
+                ))
+            })?;
+        Ok(DirectFile { file })
+    }
+
+    ///page stored at offset read straight from the device
+    pub(crate) fn read_page(&self, offset: u64) -> Result<Page, CrustyError> {
+        let mut buf = Box::new(AlignedPage([0; PAGE_SIZE]));
+        self.file.read_exact_at(&mut buf.0, offset)?;
+        Page::from_bytes(buf.0)
+    }
+
+    ///write page at offset straight to the device
+    pub(crate) fn write_page(&self, page: &Page, offset: u64) -> Result<(), CrustyError> {
+        let mut buf = Box::new(AlignedPage([0; PAGE_SIZE]));
+        buf.0.copy_from_slice(page.to_bytes());
+        self.file.write_all_at(&buf.0, offset)?;
+        Ok(())
+    }
+}
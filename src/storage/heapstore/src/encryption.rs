@@ -0,0 +1,238 @@
+use crate::file_header::HeapFileHeader;
+use crate::heap_page::{HeapPage, FIXED_PAGE_META_SIZE};
+use crate::heapfile::HeapFile;
+use crate::page::Page;
+use crate::storage_manager::StorageManager;
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce, Tag};
+use common::prelude::*;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Id a container's heap file header names its encryption key by.
+pub type KeyId = u32;
+/// Key id of a heap file whose pages are not encrypted.
+pub const NO_KEY_ID: KeyId = 0;
+/// AES-256 key.
+pub type EncryptionKey = [u8; 32];
+/// Keys a storage manager was given, by id.
+pub(crate) type KeyRing = Arc<RwLock<HashMap<KeyId, EncryptionKey>>>;
+
+/// Bytes of the nonce a page body is encrypted with.
+const NONCE_SIZE: usize = 12;
+/// Bytes of a page's seal: the nonce, then the tag authenticating the page.
+const SEAL_SIZE: usize = NONCE_SIZE + 16;
+
+/// File beside a heap file holding the seals of its pages.
+pub(crate) fn seal_path(heap_file: &Path) -> PathBuf {
+    heap_file.with_extension("seal")
+}
+
+/// Key key_id out of keys, or an error saying container_id needs it.
+pub(crate) fn key_for(
+    keys: &HashMap<KeyId, EncryptionKey>,
+    key_id: KeyId,
+    container_id: ContainerId,
+) -> Result<&EncryptionKey, CrustyError> {
+    keys.get(&key_id).ok_or_else(|| {
+        CrustyError::CrustyError(format!(
+            "Container {} is encrypted with key {}, which was not supplied",
+            container_id, key_id
+        ))
+    })
+}
+
+/// Encrypts the bodies of the pages of one heap file with AES-256-GCM on their way to disk and
+/// decrypts them on the way back. The fixed header of a page stays in the clear, so the file
+/// can still be told apart page by page, and is authenticated with the body. A full page has
+/// no room for a nonce and a tag, so they are kept in a seal file beside the heap file.
+pub(crate) struct PageSealer {
+    key_id: KeyId,
+    cipher: Aes256Gcm,
+    seals: File,
+}
+
+impl PageSealer {
+    /// Sealer for the heap file at path with the key its header names, None if it names none.
+    /// Errors if keys does not hold that key.
+    pub(crate) fn for_file(
+        path: &Path,
+        header: &HeapFileHeader,
+        keys: &HashMap<KeyId, EncryptionKey>,
+    ) -> Result<Option<Self>, CrustyError> {
+        if header.key_id == NO_KEY_ID {
+            return Ok(None);
+        }
+        let key = key_for(keys, header.key_id, header.container_id)?;
+        let seals = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(seal_path(path))?;
+        Ok(Some(PageSealer {
+            key_id: header.key_id,
+            cipher: Aes256Gcm::new(key.into()),
+            seals,
+        }))
+    }
+
+    /// Copy of page with its body encrypted under a fresh nonce, once its seal is stored.
+    pub(crate) fn seal(&self, page: &Page) -> Result<Page, CrustyError> {
+        let page_id = page.get_page_id();
+        let mut data = *page.to_bytes();
+        let (header, body) = data.split_at_mut(FIXED_PAGE_META_SIZE);
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), header, body)
+            .map_err(|_| CrustyError::CrustyError(format!("Error encrypting page {}", page_id)))?;
+        let mut seal = [0u8; SEAL_SIZE];
+        seal[..NONCE_SIZE].copy_from_slice(&nonce);
+        seal[NONCE_SIZE..].copy_from_slice(&tag);
+        self.seals.write_all_at(&seal, seal_offset(page_id))?;
+        Ok(Page::from_bytes_unchecked(data))
+    }
+
+    /// Page page_id as read from the heap file, with its body decrypted. Err saying why if it
+    /// has no seal or does not match it, as for a page changed on disk or sealed with another
+    /// key.
+    pub(crate) fn unseal(&self, page_id: PageId, page: &Page) -> Result<Page, String> {
+        let mut seal = [0u8; SEAL_SIZE];
+        self.seals
+            .read_exact_at(&mut seal, seal_offset(page_id))
+            .map_err(|e| format!("its seal cannot be read: {}", e))?;
+        let mut data = *page.to_bytes();
+        let (header, body) = data.split_at_mut(FIXED_PAGE_META_SIZE);
+        self.cipher
+            .decrypt_in_place_detached(
+                Nonce::from_slice(&seal[..NONCE_SIZE]),
+                header,
+                body,
+                Tag::from_slice(&seal[NONCE_SIZE..]),
+            )
+            .map_err(|_| format!("it does not decrypt with key {}", self.key_id))?;
+        Ok(Page::from_bytes_unchecked(data))
+    }
+
+    /// Drop the seals of the pages from keep on.
+    pub(crate) fn truncate(&self, keep: PageId) -> Result<(), CrustyError> {
+        self.seals.set_len(seal_offset(keep))?;
+        Ok(())
+    }
+
+    /// Force the seals written so far to disk.
+    pub(crate) fn sync(&self) -> Result<(), CrustyError> {
+        self.seals.sync_data()?;
+        Ok(())
+    }
+}
+
+fn seal_offset(page_id: PageId) -> u64 {
+    page_id as u64 * SEAL_SIZE as u64
+}
+
+impl HeapFile {
+    /// Page as it is stored in the file: sealed if the file is encrypted.
+    pub(crate) fn sealed(&self, page: &Page) -> Result<Page, CrustyError> {
+        match &self.sealer {
+            Some(sealer) => sealer.seal(page),
+            None => Ok(page.clone()),
+        }
+    }
+
+    /// Page page_id as read from the file, decrypted if the file is encrypted.
+    pub(crate) fn unsealed(&self, page_id: PageId, page: Page) -> Result<Page, String> {
+        match &self.sealer {
+            Some(sealer) => sealer.unseal(page_id, &page),
+            None => Ok(page),
+        }
+    }
+}
+
+impl StorageManager {
+    /// Open storage_dir as new does, with the keys of the encrypted containers in it.
+    /// Keys are never written to the storage directory, so each open of a directory holding
+    /// encrypted containers must supply them again; an encrypted container whose key is
+    /// missing cannot be opened. Only heap files are encrypted, not the write-ahead log.
+    pub fn new_with_keys(storage_dir: &Path, keys: HashMap<KeyId, EncryptionKey>) -> Self {
+        StorageManager::open(storage_dir, keys, false)
+    }
+
+    /// Make key available under key_id to the containers created from now on, for
+    /// ContainerOptions::encryption_key to name. Errors for NO_KEY_ID.
+    pub fn add_encryption_key(&self, key_id: KeyId, key: EncryptionKey) -> Result<(), CrustyError> {
+        if key_id == NO_KEY_ID {
+            return Err(CrustyError::CrustyError(format!(
+                "Key id {} means a container is not encrypted",
+                NO_KEY_ID
+            )));
+        }
+        self.encryption_keys.write().unwrap().insert(key_id, key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::ContainerOptions;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use std::fs;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_encryption_container() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        let key: EncryptionKey = rand::random();
+        let vals = get_random_vec_of_byte_vec(100, 50, 200);
+        let ids;
+        {
+            let sm = StorageManager::new(&tdir);
+            let options = ContainerOptions {
+                encryption_key: Some(3),
+                ..Default::default()
+            };
+            assert!(sm.create_container_with(1, options.clone()).is_err());
+            assert!(sm.add_encryption_key(NO_KEY_ID, key).is_err());
+            sm.add_encryption_key(3, key).unwrap();
+            sm.create_container_with(1, options).unwrap();
+            sm.create_table(2).unwrap();
+            ids = sm.insert_values(1, vals.clone(), tid);
+            sm.insert_values(2, vals.clone(), tid);
+            sm.commit_transaction(tid).unwrap();
+            sm.shutdown();
+
+            //the key is in the header and no value is in the clear
+            let info = sm.list_containers();
+            assert_eq!(Some(3), info[0].encryption_key);
+            assert_eq!(None, info[1].encryption_key);
+            let plain = fs::read(tdir.join("2.hf")).unwrap();
+            let sealed = fs::read(tdir.join("1.hf")).unwrap();
+            let found = |file: &[u8], val: &[u8]| file.windows(val.len()).any(|w| w == val);
+            assert!(vals.iter().all(|v| found(&plain, v)));
+            assert!(!vals.iter().any(|v| found(&sealed, v)));
+            assert!(tdir.join("1.seal").exists());
+            assert!(!tdir.join("2.seal").exists());
+        }
+
+        //the container cannot be opened without its key or read with another
+        let opened = std::panic::catch_unwind(|| StorageManager::new(&tdir));
+        assert!(opened.is_err());
+        let sm = StorageManager::new_with_keys(&tdir, HashMap::from([(3, [7; 32])]));
+        assert!(sm.get_value(ids[0], tid, Permissions::ReadOnly).is_err());
+        assert_eq!(vec![(1, 0)], sm.quarantined_pages());
+        drop(sm);
+
+        let sm = StorageManager::new_with_keys(&tdir, HashMap::from([(3, key)]));
+        for (id, val) in ids.iter().zip(&vals) {
+            assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use crate::storage_manager::StorageManager;
+use common::prelude::*;
+use common::storage_trait::StorageTrait;
+use common::{Field, TableSchema, Tuple};
+use serde_json::{Map, Value};
+use std::io::{BufWriter, Write};
+
+/// How export writes the tuples of a container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A row of comma separated fields per tuple with no header row, as import_csv reads.
+    Csv,
+    /// A JSON object per line mapping the name of each attribute to its field.
+    JsonLines,
+}
+
+/// Field as a JSON value: numbers, strings and booleans as themselves, NULL as null, and
+/// decimals and dates as the strings they are displayed as, which keeps them exact.
+fn field_to_json(field: &Field) -> Value {
+    match field {
+        Field::Int(i) => Value::from(*i),
+        Field::String(s) => Value::from(s.as_str()),
+        Field::Bool(b) => Value::from(*b),
+        Field::Null => Value::Null,
+        Field::Decimal(..) | Field::Date(_) => Value::from(field.to_string()),
+    }
+}
+
+impl StorageManager {
+    /// Write every value of a container to writer as a tuple of schema in format, in the
+    /// order a scan reads them. Returns how many were written. Errs if the container does
+    /// not exist, writer fails or a value is not a tuple with a field per attribute of schema.
+    pub fn export(
+        &self,
+        container_id: ContainerId,
+        schema: &TableSchema,
+        format: ExportFormat,
+        writer: impl Write,
+    ) -> Result<usize, CrustyError> {
+        let exported = match format {
+            ExportFormat::Csv => {
+                let mut csv = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(writer);
+                let exported = self.for_each_tuple(container_id, schema, |_, tuple| {
+                    csv.write_record(tuple.field_vals.iter().map(|f| f.to_string()))
+                        .map_err(|e| CrustyError::IOError(e.to_string()))
+                })?;
+                csv.flush()?;
+                exported
+            }
+            ExportFormat::JsonLines => {
+                let mut out = BufWriter::new(writer);
+                let exported = self.for_each_tuple(container_id, schema, |id, tuple| {
+                    let object: Map<String, Value> = schema
+                        .attributes
+                        .iter()
+                        .zip(&tuple.field_vals)
+                        .map(|(attr, field)| (attr.name.clone(), field_to_json(field)))
+                        .collect();
+                    serde_json::to_writer(&mut out, &object).map_err(|e| {
+                        CrustyError::SerializationError(format!("Error encoding {:?}: {}", id, e))
+                    })?;
+                    out.write_all(b"\n")?;
+                    Ok(())
+                })?;
+                out.flush()?;
+                exported
+            }
+        };
+        debug!(
+            "Exported {} tuples of container {} as {:?}",
+            exported, container_id, format
+        );
+        Ok(exported)
+    }
+
+    /// Call f with every value of a container as a tuple of schema, returning how many.
+    fn for_each_tuple(
+        &self,
+        container_id: ContainerId,
+        schema: &TableSchema,
+        mut f: impl FnMut(ValueId, Tuple) -> Result<(), CrustyError>,
+    ) -> Result<usize, CrustyError> {
+        self.get_hf(container_id)?;
+        let tid = TransactionId::new();
+        let mut count = 0;
+        for (bytes, id) in self.get_iterator(container_id, tid, Permissions::ReadOnly) {
+            let tuple: Tuple = serde_cbor::from_slice(&bytes).map_err(|e| {
+                CrustyError::SerializationError(format!("Value {:?} is not a tuple: {}", id, e))
+            })?;
+            if tuple.field_vals.len() != schema.attributes.len() {
+                return Err(CrustyError::ValidationError(format!(
+                    "Tuple {:?} has {} fields but the schema has {} attributes",
+                    id,
+                    tuple.field_vals.len(),
+                    schema.attributes.len()
+                )));
+            }
+            f(id, tuple)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testutil::*;
+    use common::DataType;
+
+    #[test]
+    fn hs_export_csv_and_json_lines() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        sm.create_table(1).unwrap();
+        let schema = TableSchema::from_vecs(
+            vec!["id", "name", "price", "on_sale"],
+            vec![
+                DataType::Int,
+                DataType::String,
+                DataType::Decimal(5, 2),
+                DataType::Bool,
+            ],
+        );
+        let input = "1,\"pen, blue\",1.50,true\n2,NULL,20.00,false\n";
+        sm.import_csv(1, input.as_bytes(), &schema).unwrap();
+
+        //csv reads back the way it was imported
+        let mut out = Vec::new();
+        assert_eq!(
+            2,
+            sm.export(1, &schema, ExportFormat::Csv, &mut out).unwrap()
+        );
+        assert_eq!(input, String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        sm.export(1, &schema, ExportFormat::JsonLines, &mut out)
+            .unwrap();
+        let lines: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                serde_json::json!({"id": 1, "name": "pen, blue", "price": "1.50", "on_sale": true}),
+                serde_json::json!({"id": 2, "name": null, "price": "20.00", "on_sale": false}),
+            ],
+            lines
+        );
+
+        //the schema must fit the tuples
+        let narrow = TableSchema::from_vecs(vec!["id"], vec![DataType::Int]);
+        assert!(sm
+            .export(1, &narrow, ExportFormat::Csv, Vec::new())
+            .is_err());
+        assert!(sm
+            .export(2, &schema, ExportFormat::Csv, Vec::new())
+            .is_err());
+    }
+}
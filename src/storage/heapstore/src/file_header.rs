@@ -0,0 +1,196 @@
+use crate::encryption::{KeyId, NO_KEY_ID};
+use crate::heap_page::{
+    PAGE_META_CHECKSUM_OFFSET, PAGE_META_MAGIC_OFFSET, PAGE_META_VERSION_OFFSET,
+};
+use common::prelude::*;
+use common::PAGE_SIZE;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+///container the file belongs to
+const HEADER_CONTAINER_ID_OFFSET: usize = 0;
+///data pages that follow the header
+const HEADER_PAGE_COUNT_OFFSET: usize = 2;
+///page holding the root of the free space map
+const HEADER_FSM_ROOT_OFFSET: usize = 4;
+///page size the file was written with
+const HEADER_PAGE_SIZE_OFFSET: usize = 12;
+///creation time in seconds since the unix epoch
+const HEADER_CREATED_AT_OFFSET: usize = 20;
+///key the data pages are encrypted with, zero in files written before encryption
+const HEADER_KEY_ID_OFFSET: usize = 28;
+///pages of each segment file the heap file is split into, zero in files written before segments
+const HEADER_SEGMENT_PAGES_OFFSET: usize = 32;
+///marks a heap file header at the same offset data pages keep their magic
+pub(crate) const HEADER_MAGIC: u16 = u16::from_le_bytes(*b"HF");
+///current layout of the header page
+pub const HEAP_FILE_FORMAT_VERSION: u8 = 2;
+///fsm_root when the free space map is kept in the .fsm file beside the heap file
+pub const NO_FSM_ROOT: PageId = PageId::MAX;
+///segment_pages of a heap file kept whole in one file
+pub const NO_SEGMENTS: u32 = 0;
+
+///page at the start of every heap file saying what the file is and how many data pages follow
+///the checksum magic and version sit where a data page keeps them so tools can tell them apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct HeapFileHeader {
+    pub container_id: ContainerId,
+    ///data pages in the file after the header
+    pub page_count: PageId,
+    pub format_version: u8,
+    ///data page holding the free space map or NO_FSM_ROOT
+    pub fsm_root: PageId,
+    ///seconds since the unix epoch when the file was created
+    pub created_at: u64,
+    ///key the data page bodies are encrypted with or NO_KEY_ID if they are not
+    pub key_id: KeyId,
+    ///pages of each segment file counting the header or NO_SEGMENTS if the file is not split
+    pub segment_pages: u32,
+}
+
+impl HeapFileHeader {
+    ///header for a new empty file of container_id created now
+    pub fn new(container_id: ContainerId) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        HeapFileHeader {
+            container_id,
+            page_count: 0,
+            format_version: HEAP_FILE_FORMAT_VERSION,
+            fsm_root: NO_FSM_ROOT,
+            created_at,
+            key_id: NO_KEY_ID,
+            segment_pages: NO_SEGMENTS,
+        }
+    }
+
+    ///whether bytes start with a heap file header rather than a data page
+    pub fn is_header(bytes: &[u8]) -> bool {
+        bytes
+            .get(PAGE_META_MAGIC_OFFSET..PAGE_META_MAGIC_OFFSET + 2)
+            .is_some_and(|m| m == HEADER_MAGIC.to_le_bytes())
+    }
+
+    ///header page with its checksum stamped
+    pub fn to_bytes(&self) -> [u8; PAGE_SIZE] {
+        let mut data = [0u8; PAGE_SIZE];
+        let fields: [(usize, &[u8]); 8] = [
+            (HEADER_CONTAINER_ID_OFFSET, &self.container_id.to_le_bytes()),
+            (HEADER_PAGE_COUNT_OFFSET, &self.page_count.to_le_bytes()),
+            (HEADER_FSM_ROOT_OFFSET, &self.fsm_root.to_le_bytes()),
+            (HEADER_PAGE_SIZE_OFFSET, &(PAGE_SIZE as u32).to_le_bytes()),
+            (PAGE_META_MAGIC_OFFSET, &HEADER_MAGIC.to_le_bytes()),
+            (HEADER_CREATED_AT_OFFSET, &self.created_at.to_le_bytes()),
+            (HEADER_KEY_ID_OFFSET, &self.key_id.to_le_bytes()),
+            (
+                HEADER_SEGMENT_PAGES_OFFSET,
+                &self.segment_pages.to_le_bytes(),
+            ),
+        ];
+        for (offset, bytes) in fields {
+            put(&mut data, offset, bytes);
+        }
+        data[PAGE_META_VERSION_OFFSET] = self.format_version;
+        let checksum = checksum(&data).to_le_bytes();
+        put(&mut data, PAGE_META_CHECKSUM_OFFSET, &checksum);
+        data
+    }
+
+    ///header read back from a file's first page
+    ///Err if it is not a header, was written by a newer version or another page size, or its
+    ///checksum does not match
+    pub fn from_bytes(data: &[u8; PAGE_SIZE]) -> Result<Self, CrustyError> {
+        if !Self::is_header(data) {
+            return Err(CrustyError::CrustyError(
+                "File does not start with a heap file header".to_string(),
+            ));
+        }
+        let stored = u32::from_le_bytes(get(data, PAGE_META_CHECKSUM_OFFSET));
+        if stored != checksum(data) {
+            return Err(CrustyError::CrustyError(format!(
+                "Heap file header checksum {:08x} does not match contents {:08x}",
+                stored,
+                checksum(data)
+            )));
+        }
+        let format_version = data[PAGE_META_VERSION_OFFSET];
+        if format_version > HEAP_FILE_FORMAT_VERSION {
+            return Err(CrustyError::CrustyError(format!(
+                "Heap file format version {} is newer than the supported version {}",
+                format_version, HEAP_FILE_FORMAT_VERSION
+            )));
+        }
+        let page_size = u32::from_le_bytes(get(data, HEADER_PAGE_SIZE_OFFSET));
+        if page_size as usize != PAGE_SIZE {
+            return Err(CrustyError::CrustyError(format!(
+                "Heap file was written with {} byte pages, not {}",
+                page_size, PAGE_SIZE
+            )));
+        }
+        Ok(HeapFileHeader {
+            container_id: ContainerId::from_le_bytes(get(data, HEADER_CONTAINER_ID_OFFSET)),
+            page_count: PageId::from_le_bytes(get(data, HEADER_PAGE_COUNT_OFFSET)),
+            format_version,
+            fsm_root: PageId::from_le_bytes(get(data, HEADER_FSM_ROOT_OFFSET)),
+            created_at: u64::from_le_bytes(get(data, HEADER_CREATED_AT_OFFSET)),
+            key_id: KeyId::from_le_bytes(get(data, HEADER_KEY_ID_OFFSET)),
+            segment_pages: u32::from_le_bytes(get(data, HEADER_SEGMENT_PAGES_OFFSET)),
+        })
+    }
+}
+
+fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+    data[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+fn get<const L: usize>(data: &[u8], offset: usize) -> [u8; L] {
+    data[offset..offset + L].try_into().unwrap()
+}
+
+///crc32 of the header page skipping the checksum itself as data pages do
+fn checksum(data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&data[..PAGE_META_CHECKSUM_OFFSET]);
+    hasher.update(&data[PAGE_META_CHECKSUM_OFFSET + 4..]);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap_page::HeapPage;
+    use crate::page::Page;
+    use common::testutil::init;
+
+    #[test]
+    fn hs_file_header_roundtrip() {
+        init();
+        let mut header = HeapFileHeader::new(12);
+        header.page_count = 300;
+        let bytes = header.to_bytes();
+        assert!(HeapFileHeader::is_header(&bytes));
+        assert_eq!(header, HeapFileHeader::from_bytes(&bytes).unwrap());
+        assert_eq!(NO_FSM_ROOT, header.fsm_root);
+        assert!(header.created_at > 0);
+        assert_eq!(NO_KEY_ID, header.key_id);
+        assert_eq!(NO_SEGMENTS, header.segment_pages);
+        header.key_id = 7;
+        header.segment_pages = 64;
+        assert_eq!(
+            header,
+            HeapFileHeader::from_bytes(&header.to_bytes()).unwrap()
+        );
+
+        //headers and data pages reject each other
+        assert!(!HeapFileHeader::is_header(Page::new(0).to_bytes()));
+        assert!(HeapFileHeader::from_bytes(Page::new(0).to_bytes()).is_err());
+        assert!(Page::from_bytes(bytes).is_err());
+
+        let mut torn = bytes;
+        torn[HEADER_PAGE_COUNT_OFFSET] ^= 1;
+        assert!(HeapFileHeader::from_bytes(&torn).is_err());
+        let mut newer = header;
+        newer.format_version = HEAP_FILE_FORMAT_VERSION + 1;
+        assert!(HeapFileHeader::from_bytes(&newer.to_bytes()).is_err());
+    }
+}
@@ -0,0 +1,312 @@
+use crate::heap_page::{
+    FIXED_PAGE_META_SIZE, PAGE_FORMAT_VERSION, PAGE_META_MAGIC_OFFSET, PAGE_META_VERSION_OFFSET,
+};
+use common::prelude::*;
+use common::PAGE_SIZE;
+
+///record width in bytes stored once for the whole page
+const FIXED_META_RECORD_WIDTH_OFFSET: usize = 2;
+///number of present records
+const FIXED_META_COUNT_OFFSET: usize = 4;
+///marks a fixed record page at the same offset the slotted page keeps its magic
+pub(crate) const FIXED_PAGE_MAGIC: u16 = u16::from_le_bytes(*b"HR");
+///presence bitmap one bit per record index starts right after the header
+const BITMAP_START: usize = FIXED_PAGE_META_SIZE;
+
+///how the pages of a container lay out their records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PageLayout {
+    ///variable length records behind a slot directory
+    #[default]
+    Slotted,
+    ///records of one width addressed by index with a presence bitmap and no slot directory
+    FixedRecord { record_width: u16 },
+}
+
+///page of records that all have the same width
+///the header keeps the page id magic and version where the slotted page does so tools can tell
+///them apart followed by a presence bitmap and then the record array
+///a record never moves so the page needs neither slot entries nor compaction
+#[derive(Clone)]
+pub struct FixedRecordPage {
+    data: [u8; PAGE_SIZE],
+}
+
+impl FixedRecordPage {
+    ///new empty page holding records of record_width bytes
+    ///None if the width is 0 or not even one record fits
+    pub fn new(page_id: PageId, record_width: u16) -> Option<Self> {
+        if record_width == 0 || Self::capacity_for(record_width) == 0 {
+            return None;
+        }
+        let mut data = [0u8; PAGE_SIZE];
+        data[0..2].copy_from_slice(&page_id.to_le_bytes());
+        data[FIXED_META_RECORD_WIDTH_OFFSET..FIXED_META_RECORD_WIDTH_OFFSET + 2]
+            .copy_from_slice(&record_width.to_le_bytes());
+        data[PAGE_META_MAGIC_OFFSET..PAGE_META_MAGIC_OFFSET + 2]
+            .copy_from_slice(&FIXED_PAGE_MAGIC.to_le_bytes());
+        data[PAGE_META_VERSION_OFFSET] = PAGE_FORMAT_VERSION;
+        Some(FixedRecordPage { data })
+    }
+
+    ///page from raw bytes
+    ///Err if they do not carry the fixed record magic or describe an impossible layout
+    pub fn from_bytes(data: [u8; PAGE_SIZE]) -> Result<Self, CrustyError> {
+        let page = FixedRecordPage { data };
+        let magic = u16::from_le_bytes(
+            data[PAGE_META_MAGIC_OFFSET..PAGE_META_MAGIC_OFFSET + 2]
+                .try_into()
+                .unwrap(),
+        );
+        if magic != FIXED_PAGE_MAGIC {
+            return Err(CrustyError::CrustyError(format!(
+                "Page magic {:04x} does not mark a fixed record page",
+                magic
+            )));
+        }
+        if data[PAGE_META_VERSION_OFFSET] > PAGE_FORMAT_VERSION {
+            return Err(CrustyError::CrustyError(format!(
+                "Page layout version {} is newer than the supported version {}",
+                data[PAGE_META_VERSION_OFFSET], PAGE_FORMAT_VERSION
+            )));
+        }
+        if page.record_width() == 0 || page.capacity() == 0 || page.len() > page.capacity() {
+            return Err(CrustyError::CrustyError(format!(
+                "Fixed record page with width {} cannot hold {} records",
+                page.record_width(),
+                page.len()
+            )));
+        }
+        Ok(page)
+    }
+
+    ///reference to the page's raw bytes
+    pub fn to_bytes(&self) -> &[u8; PAGE_SIZE] {
+        &self.data
+    }
+
+    ///page ID
+    pub fn get_page_id(&self) -> PageId {
+        PageId::from_le_bytes(self.data[0..2].try_into().unwrap())
+    }
+
+    ///bytes in every record on the page
+    pub fn record_width(&self) -> u16 {
+        u16::from_le_bytes(
+            self.data[FIXED_META_RECORD_WIDTH_OFFSET..FIXED_META_RECORD_WIDTH_OFFSET + 2]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    ///number of record indexes the page has room for
+    pub fn capacity(&self) -> usize {
+        Self::capacity_for(self.record_width())
+    }
+
+    ///number of present records
+    pub fn len(&self) -> usize {
+        u16::from_le_bytes(
+            self.data[FIXED_META_COUNT_OFFSET..FIXED_META_COUNT_OFFSET + 2]
+                .try_into()
+                .unwrap(),
+        ) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    ///bytes still available for records
+    pub fn get_free_space(&self) -> usize {
+        (self.capacity() - self.len()) * self.record_width() as usize
+    }
+
+    ///stores bytes at the lowest absent index and returns it
+    ///None if bytes is not exactly record_width long or the page is full
+    pub fn add_value(&mut self, bytes: &[u8]) -> Option<SlotId> {
+        if bytes.len() != self.record_width() as usize {
+            return None;
+        }
+        let index = (0..self.capacity()).find(|&i| !self.is_present(i))?;
+        self.record_mut(index).copy_from_slice(bytes);
+        self.set_present(index, true);
+        self.set_len(self.len() + 1);
+        Some(index as SlotId)
+    }
+
+    ///copy of the record at slot_id or None if it is absent
+    pub fn get_value(&self, slot_id: SlotId) -> Option<Vec<u8>> {
+        self.get_value_ref(slot_id).map(|r| r.to_vec())
+    }
+
+    ///record bytes at slot_id borrowed from the page or None if it is absent
+    pub fn get_value_ref(&self, slot_id: SlotId) -> Option<&[u8]> {
+        let index = slot_id as usize;
+        (index < self.capacity() && self.is_present(index)).then(|| self.record(index))
+    }
+
+    ///overwrites the record at slot_id in place
+    ///None if it is absent or bytes is not exactly record_width long
+    pub fn update_value(&mut self, slot_id: SlotId, bytes: &[u8]) -> Option<()> {
+        self.get_value_ref(slot_id)?;
+        if bytes.len() != self.record_width() as usize {
+            return None;
+        }
+        self.record_mut(slot_id as usize).copy_from_slice(bytes);
+        Some(())
+    }
+
+    ///marks the record at slot_id absent and zeroes it
+    ///None if it was already absent
+    pub fn delete_value(&mut self, slot_id: SlotId) -> Option<()> {
+        self.get_value_ref(slot_id)?;
+        let index = slot_id as usize;
+        self.record_mut(index).fill(0);
+        self.set_present(index, false);
+        self.set_len(self.len() - 1);
+        Some(())
+    }
+
+    ///present records in index order
+    pub fn iter(&self) -> impl Iterator<Item = (SlotId, &[u8])> + '_ {
+        (0..self.capacity())
+            .filter(move |&i| self.is_present(i))
+            .map(move |i| (i as SlotId, self.record(i)))
+    }
+
+    ///records of record_width that fit beside their bitmap bits after the header
+    fn capacity_for(record_width: u16) -> usize {
+        if record_width == 0 {
+            return 0;
+        }
+        let body_bits = (PAGE_SIZE - FIXED_PAGE_META_SIZE) * 8;
+        let mut capacity = body_bits / (record_width as usize * 8 + 1);
+        //rounding the bitmap up to whole bytes can push the last record past the page end
+        while capacity > 0
+            && Self::records_start(capacity) + capacity * record_width as usize > PAGE_SIZE
+        {
+            capacity -= 1;
+        }
+        capacity
+    }
+
+    ///first record byte for a page of the given capacity
+    fn records_start(capacity: usize) -> usize {
+        BITMAP_START + capacity.div_ceil(8)
+    }
+
+    fn is_present(&self, index: usize) -> bool {
+        self.data[BITMAP_START + index / 8] & (1 << (index % 8)) != 0
+    }
+
+    fn set_present(&mut self, index: usize, present: bool) {
+        let byte = &mut self.data[BITMAP_START + index / 8];
+        if present {
+            *byte |= 1 << (index % 8);
+        } else {
+            *byte &= !(1 << (index % 8));
+        }
+    }
+
+    fn set_len(&mut self, len: usize) {
+        self.data[FIXED_META_COUNT_OFFSET..FIXED_META_COUNT_OFFSET + 2]
+            .copy_from_slice(&(len as u16).to_le_bytes());
+    }
+
+    fn record_range(&self, index: usize) -> std::ops::Range<usize> {
+        let width = self.record_width() as usize;
+        let start = Self::records_start(self.capacity()) + index * width;
+        start..start + width
+    }
+
+    fn record(&self, index: usize) -> &[u8] {
+        &self.data[self.record_range(index)]
+    }
+
+    fn record_mut(&mut self, index: usize) -> &mut [u8] {
+        let range = self.record_range(index);
+        &mut self.data[range]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap_page::HeapPage;
+    use crate::page::Page;
+    use common::testutil::*;
+
+    #[test]
+    fn hs_fixed_page_basic() {
+        init();
+        let mut p = FixedRecordPage::new(3, 16).unwrap();
+        assert_eq!(3, p.get_page_id());
+        assert!(p.is_empty());
+        let capacity = p.capacity();
+        //no per record overhead beyond one bitmap bit
+        assert!(capacity * 16 + capacity.div_ceil(8) + FIXED_PAGE_META_SIZE <= PAGE_SIZE);
+        assert!(
+            (capacity + 1) * 16 + (capacity + 1).div_ceil(8) + FIXED_PAGE_META_SIZE > PAGE_SIZE
+        );
+
+        let vals = get_random_vec_of_byte_vec(capacity, 16, 16);
+        for (i, v) in vals.iter().enumerate() {
+            assert_eq!(Some(i as SlotId), p.add_value(v));
+        }
+        assert!(p.is_full());
+        assert_eq!(0, p.get_free_space());
+        assert_eq!(None, p.add_value(&vals[0]));
+        assert_eq!(
+            Some(vals[capacity - 1].clone()),
+            p.get_value(capacity as SlotId - 1)
+        );
+
+        //wrong widths are refused
+        assert_eq!(None, p.update_value(0, &[1; 15]));
+        assert_eq!(Some(()), p.update_value(0, &[1; 16]));
+        assert_eq!(Some(&[1u8; 16][..]), p.get_value_ref(0));
+
+        //a deleted index is the next one reused
+        assert_eq!(Some(()), p.delete_value(5));
+        assert_eq!(None, p.delete_value(5));
+        assert_eq!(None, p.get_value(5));
+        assert_eq!(16, p.get_free_space());
+        assert_eq!(capacity - 1, p.iter().count());
+        assert_eq!(None, p.add_value(&[2; 17]));
+        assert_eq!(Some(5), p.add_value(&[2; 16]));
+        assert_eq!(None, p.get_value(capacity as SlotId));
+    }
+
+    #[test]
+    fn hs_fixed_page_bytes() {
+        init();
+        assert!(FixedRecordPage::new(0, 0).is_none());
+        assert!(FixedRecordPage::new(0, PAGE_SIZE as u16).is_none());
+        let widest = (PAGE_SIZE - FIXED_PAGE_META_SIZE - 1) as u16;
+        assert_eq!(1, FixedRecordPage::new(0, widest).unwrap().capacity());
+
+        let mut p = FixedRecordPage::new(1, 8).unwrap();
+        for i in 0..10u8 {
+            p.add_value(&[i; 8]).unwrap();
+        }
+        p.delete_value(4).unwrap();
+        let loaded = FixedRecordPage::from_bytes(*p.to_bytes()).unwrap();
+        assert_eq!(9, loaded.len());
+        assert_eq!(
+            p.iter().collect::<Vec<_>>(),
+            loaded.iter().collect::<Vec<_>>()
+        );
+
+        //each layout rejects the other's pages
+        assert!(FixedRecordPage::from_bytes(*Page::new(1).to_bytes()).is_err());
+        assert!(Page::from_bytes(*p.to_bytes()).is_err());
+        let mut bad = *p.to_bytes();
+        bad[FIXED_META_RECORD_WIDTH_OFFSET..FIXED_META_RECORD_WIDTH_OFFSET + 2].fill(0);
+        assert!(FixedRecordPage::from_bytes(bad).is_err());
+    }
+}
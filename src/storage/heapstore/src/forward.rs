@@ -0,0 +1,397 @@
+use crate::heap_page::{HeapPage, SLOT_FLAG_FORWARDED};
+use crate::heapfile::HeapFile;
+use crate::metrics::StorageOp;
+use crate::page::Page;
+use crate::storage_manager::StorageManager;
+use crate::wal::SlotImage;
+use common::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Whether slot_id of page holds a forwarding marker rather than a record.
+pub(crate) fn is_forwarded(page: &Page, slot_id: SlotId) -> bool {
+    page.get_slot_flags(slot_id)
+        .is_some_and(|flags| flags & SLOT_FLAG_FORWARDED != 0)
+}
+
+/// Where the forwarding marker in slot_id of page points, None if the slot is not a marker.
+pub(crate) fn forwarded_to(page: &Page, slot_id: SlotId) -> Result<Option<ValueId>, CrustyError> {
+    if !is_forwarded(page, slot_id) {
+        return Ok(None);
+    }
+    let bytes = page.get_value(slot_id).unwrap_or_default();
+    let packed: [u8; 8] = bytes.as_slice().try_into().map_err(|_| {
+        CrustyError::CrustyError(format!(
+            "Forwarding marker in slot {} of page {} holds {} bytes",
+            slot_id,
+            page.get_page_id(),
+            bytes.len()
+        ))
+    })?;
+    Ok(Some(ValueId::from(u64::from_le_bytes(packed))))
+}
+
+/// Replace the record in slot_id of page with a marker forwarding to id.
+/// False if the page cannot flag the slot, in which case the slot is left as it was.
+fn write_marker(page: &mut Page, slot_id: SlotId, id: ValueId) -> bool {
+    let mut marked = page.clone();
+    // Read after the update, which clears the compressed flag of the old bytes
+    let flagged = marked
+        .update_value(slot_id, &id.to_u64().to_le_bytes())
+        .and_then(|_| marked.get_slot_flags(slot_id))
+        .and_then(|flags| marked.set_slot_flags(slot_id, flags | SLOT_FLAG_FORWARDED));
+    if flagged.is_some() {
+        *page = marked;
+    }
+    flagged.is_some()
+}
+
+fn slot_of(id: ValueId) -> Option<(PageId, SlotId)> {
+    Some((id.page_id?, id.slot_id?))
+}
+
+pub(crate) fn loop_error(passed: &[ValueId], next: ValueId) -> CrustyError {
+    CrustyError::CrustyError(format!(
+        "Forwarding markers from {:?} loop back to {:?}",
+        passed[0], next
+    ))
+}
+
+impl StorageManager {
+    /// Follow the forwarding markers from id to the record they lead to, which need not exist.
+    /// Returns the ids of the markers passed on the way and the id of the record.
+    pub(crate) fn follow_forwards(
+        &self,
+        hf: &Arc<HeapFile>,
+        mut id: ValueId,
+        tid: TransactionId,
+        perm: Permissions,
+    ) -> Result<(Vec<ValueId>, ValueId), CrustyError> {
+        let mut markers = Vec::new();
+        while let Some((page_id, slot_id)) = slot_of(id) {
+            if page_id >= hf.num_pages() {
+                break;
+            }
+            let page = self.fetch_page(hf, page_id, tid, perm)?;
+            let Some(next) = forwarded_to(&page, slot_id)? else {
+                break;
+            };
+            markers.push(id);
+            if markers.contains(&next) {
+                return Err(loop_error(&markers, next));
+            }
+            id = next;
+        }
+        Ok((markers, id))
+    }
+
+    /// The record id leads to, following any forwarding markers, or None if there is none.
+    /// Each page on the way is read once.
+    pub(crate) fn read_forwarded(
+        &self,
+        hf: &Arc<HeapFile>,
+        id: ValueId,
+        tid: TransactionId,
+        perm: Permissions,
+    ) -> Result<Option<Vec<u8>>, CrustyError> {
+        let mut passed = Vec::new();
+        let mut id = id;
+        while let Some((page_id, slot_id)) = slot_of(id) {
+            if page_id >= hf.num_pages() {
+                break;
+            }
+            let page = self.fetch_page(hf, page_id, tid, perm)?;
+            let Some(next) = forwarded_to(&page, slot_id)? else {
+                return page
+                    .get_value_chained(slot_id, |page_id| self.fetch_page(hf, page_id, tid, perm));
+            };
+            passed.push(id);
+            if passed.contains(&next) {
+                return Err(loop_error(&passed, next));
+            }
+            id = next;
+        }
+        Ok(None)
+    }
+
+    /// Update a value in place if its page has room, otherwise move it to a page that does
+    /// and return its new id. With forward the old slot keeps a marker so the old id, and any
+    /// id that forwarded to it, still resolves to the record. Without it, or if the page's slot
+    /// encoding has no room for flags, the old slot is freed. The old slot is only changed once
+    /// the moved record is stored, so an update that fails leaves the record as it was.
+    /// A record in overflow pages always moves, and its old slot and pages are freed.
+    /// An id that is itself a marker updates the record it leads to. Deleting removes only the
+    /// markers on the way from the id deleted, so delete a forwarded record by its oldest id.
+    pub fn update_value_with(
+        &self,
+        value: Vec<u8>,
+        id: ValueId,
+        tid: TransactionId,
+        forward: bool,
+    ) -> Result<ValueId, CrustyError> {
+        let _timer = self.metrics.time(StorageOp::Update);
+        let _span = tracing::trace_span!(
+            "update_value",
+            tid = tid.id(),
+            container_id = id.container_id,
+            page_id = id.page_id,
+            slot_id = id.slot_id,
+            len = value.len()
+        )
+        .entered();
+        let hf = self.get_hf(id.container_id)?;
+        let missing = || CrustyError::CrustyError(format!("Cannot update missing value {:?}", id));
+        let _latch = hf.write_latch.lock().unwrap();
+        let (markers, target) = self.follow_forwards(&hf, id, tid, Permissions::ReadWrite)?;
+        let (page_id, slot_id) = slot_of(target).ok_or_else(missing)?;
+        if page_id >= hf.num_pages() {
+            return Err(missing());
+        }
+        let mut page = self.fetch_page(&hf, page_id, tid, Permissions::ReadWrite)?;
+        let before = SlotImage::of(&page, slot_id).ok_or_else(missing)?;
+        let chain = page.get_overflow_pointer(slot_id);
+        if page.update_value(slot_id, &value).is_some() {
+            self.log_slot(&hf, &mut page, slot_id, Some(before), tid)?;
+            self.store_page(&hf, &mut page, tid)?;
+            return Ok(target);
+        }
+        // Too big for its page now, so it moves and gets a new id. The new copy is stored
+        // before the old slot changes, so a move that fails leaves the record where it was
+        let new_id = self.insert_into(&hf, &value, tid)?;
+        let mut page = self.fetch_page(&hf, page_id, tid, Permissions::ReadWrite)?;
+        let marked = forward && write_marker(&mut page, slot_id, new_id);
+        if !marked {
+            page.delete_value(slot_id);
+        }
+        self.log_slot(&hf, &mut page, slot_id, Some(before), tid)?;
+        self.store_page(&hf, &mut page, tid)?;
+        if let Some(pointer) = chain {
+            self.free_overflow_chain(&hf, pointer, tid)?;
+        }
+        if let (false, Some(&last)) = (marked, markers.last()) {
+            // The chain now ends at a freed slot, so the marker before it skips ahead
+            self.repoint_marker(&hf, last, new_id, tid)?;
+        }
+        Ok(new_id)
+    }
+
+    /// Delete the record id leads to along with the forwarding markers on the way.
+    /// The markers go first so none is ever left pointing at a freed slot.
+    pub(crate) fn delete_forwarded(
+        &self,
+        hf: &Arc<HeapFile>,
+        id: ValueId,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let (markers, target) = self.follow_forwards(hf, id, tid, Permissions::ReadWrite)?;
+        for id in markers.into_iter().chain([target]) {
+            let Some((page_id, slot_id)) = slot_of(id) else {
+                continue;
+            };
+            if page_id >= hf.num_pages() {
+                continue;
+            }
+            let mut page = self.fetch_page(hf, page_id, tid, Permissions::ReadWrite)?;
+            let before = SlotImage::of(&page, slot_id);
+            let chain = page.get_overflow_pointer(slot_id);
+            if page.delete_value(slot_id).is_some() {
+                self.log_slot(hf, &mut page, slot_id, before, tid)?;
+                self.store_page(hf, &mut page, tid)?;
+            }
+            if let Some(pointer) = chain {
+                self.free_overflow_chain(hf, pointer, tid)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Point every forwarding marker of hf at a record that moved at the record's new id.
+    /// The caller holds hf's write latch.
+    pub(crate) fn repoint_forwards(
+        &self,
+        hf: &Arc<HeapFile>,
+        moved: &[(ValueId, ValueId)],
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let moved: HashMap<ValueId, ValueId> = moved.iter().copied().collect();
+        for page_id in 0..hf.num_pages() {
+            let mut page = self.fetch_page(hf, page_id, tid, Permissions::ReadWrite)?;
+            let mut changed = false;
+            for slot_id in 0..page.get_num_slots() as SlotId {
+                if let Some(new_id) = forwarded_to(&page, slot_id)?.and_then(|t| moved.get(&t)) {
+                    let before = SlotImage::of(&page, slot_id);
+                    if write_marker(&mut page, slot_id, *new_id) {
+                        self.log_slot(hf, &mut page, slot_id, before, tid)?;
+                        changed = true;
+                    }
+                }
+            }
+            if changed {
+                self.store_page(hf, &mut page, tid)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn repoint_marker(
+        &self,
+        hf: &Arc<HeapFile>,
+        marker: ValueId,
+        to: ValueId,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let Some((page_id, slot_id)) = slot_of(marker) else {
+            return Ok(());
+        };
+        let mut page = self.fetch_page(hf, page_id, tid, Permissions::ReadWrite)?;
+        let before = SlotImage::of(&page, slot_id);
+        if write_marker(&mut page, slot_id, to) {
+            self.log_slot(hf, &mut page, slot_id, before, tid)?;
+            self.store_page(hf, &mut page, tid)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use common::PAGE_SIZE;
+
+    #[test]
+    fn hs_forward_update() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        sm.create_table(1).unwrap();
+        let vals = get_random_vec_of_byte_vec(100, 100, 100);
+        let ids = sm.insert_values(1, vals, tid);
+        let first = ids[0];
+        let count = || sm.get_iterator(1, tid, Permissions::ReadOnly).count();
+
+        //the old id keeps resolving and scans see the record once at its new id
+        let big = get_random_byte_vec(1500);
+        let moved = sm.update_value_with(big.clone(), first, tid, true).unwrap();
+        assert_ne!(first.page_id, moved.page_id);
+        assert_eq!(
+            big,
+            sm.get_value(first, tid, Permissions::ReadOnly).unwrap()
+        );
+        assert_eq!(
+            big,
+            sm.get_value(moved, tid, Permissions::ReadOnly).unwrap()
+        );
+        assert_eq!(ids.len(), count());
+        let scanned = sm.get_iterator(1, tid, Permissions::ReadOnly);
+        assert!(scanned
+            .filter(|(val, _)| *val == big)
+            .all(|(_, id)| id == moved));
+
+        //updating through the old id moves the record again behind a second marker
+        let extra = sm.insert_values(1, get_random_vec_of_byte_vec(30, 100, 100), tid);
+        assert!(extra.iter().any(|id| id.page_id == moved.page_id));
+        let total = ids.len() + extra.len();
+        let bigger = get_random_byte_vec(3000);
+        let again = sm
+            .update_value_with(bigger.clone(), first, tid, true)
+            .unwrap();
+        assert_ne!(moved, again);
+        for id in [first, moved, again] {
+            assert_eq!(
+                bigger,
+                sm.get_value(id, tid, Permissions::ReadOnly).unwrap()
+            );
+        }
+        let hf = sm.get_hf(1).unwrap();
+        let chain = sm
+            .follow_forwards(&hf, first, tid, Permissions::ReadOnly)
+            .unwrap();
+        assert_eq!((vec![first, moved], again), chain);
+        let small = vec![9; 20];
+        assert_eq!(again, sm.update_value(small.clone(), moved, tid).unwrap());
+        assert_eq!(
+            small,
+            sm.get_value(first, tid, Permissions::ReadOnly).unwrap()
+        );
+        assert_eq!(total, count());
+
+        //deleting through a marker removes the record and every marker
+        sm.delete_value(first, tid).unwrap();
+        for id in [first, moved, again] {
+            assert!(sm.get_value(id, tid, Permissions::ReadOnly).is_err());
+        }
+        assert_eq!(total - 1, count());
+
+        //without forward the old id stops resolving
+        let plain = sm.update_value(big.clone(), ids[1], tid).unwrap();
+        assert_ne!(ids[1], plain);
+        assert!(sm.get_value(ids[1], tid, Permissions::ReadOnly).is_err());
+        assert_eq!(
+            big,
+            sm.get_value(plain, tid, Permissions::ReadOnly).unwrap()
+        );
+    }
+
+    #[test]
+    fn hs_forward_failed_move() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        sm.create_table(1).unwrap();
+        let vals = get_random_vec_of_byte_vec(30, 100, 100);
+        let ids = sm.insert_values(1, vals.clone(), tid);
+        assert_eq!(1, sm.get_hf(1).unwrap().num_pages());
+        let unchanged = || {
+            assert_eq!(1, sm.get_hf(1).unwrap().num_pages());
+            for (id, val) in ids.iter().zip(&vals) {
+                assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+            }
+        };
+
+        //a move the quota stops leaves the record in its old slot
+        sm.set_container_quota(1, Some(sm.disk_usage(1).unwrap().heap_file))
+            .unwrap();
+        for forward in [false, true] {
+            let moved = sm.update_value_with(get_random_byte_vec(1500), ids[0], tid, forward);
+            assert!(matches!(moved, Err(CrustyError::QuotaExceeded { .. })));
+            unchanged();
+        }
+
+        //so does a record too large for any page, before its overflow pages are added
+        let moved = sm.update_value(get_random_byte_vec(3 * PAGE_SIZE), ids[0], tid);
+        assert!(matches!(moved, Err(CrustyError::QuotaExceeded { .. })));
+        unchanged();
+        let inserted = sm.try_insert_value(1, get_random_byte_vec(3 * PAGE_SIZE), tid);
+        assert!(matches!(inserted, Err(CrustyError::QuotaExceeded { .. })));
+        unchanged();
+    }
+
+    #[test]
+    fn hs_forward_vacuum() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        sm.create_table(1).unwrap();
+        let ids = sm.insert_values(1, get_random_vec_of_byte_vec(100, 100, 100), tid);
+        let big = get_random_byte_vec(900);
+        let moved = sm
+            .update_value_with(big.clone(), ids[0], tid, true)
+            .unwrap();
+        for id in &ids[1..] {
+            sm.delete_value(*id, tid).unwrap();
+        }
+
+        //vacuum moves the record back next to its marker and repoints the marker
+        let report = sm.vacuum_container(1).unwrap();
+        let (_, new_id) = *report.moved.iter().find(|(old, _)| *old == moved).unwrap();
+        assert_eq!(ids[0].page_id, new_id.page_id);
+        assert_eq!(
+            big,
+            sm.get_value(ids[0], tid, Permissions::ReadOnly).unwrap()
+        );
+        let scanned: Vec<_> = sm.get_iterator(1, tid, Permissions::ReadOnly).collect();
+        assert_eq!(vec![(big, new_id)], scanned);
+    }
+}
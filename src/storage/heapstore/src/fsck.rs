@@ -0,0 +1,819 @@
+use crate::container::CATALOG_FILENAME;
+use crate::encryption::{seal_path, NO_KEY_ID};
+use crate::file_header::HeapFileHeader;
+use crate::fsm::{fsm_path, FreeSpaceMap};
+use crate::heap_page::{
+    HeapPage, SlotEntry, FIXED_PAGE_META_SIZE, PAGE_FORMAT_VERSION, PAGE_MAGIC,
+};
+use crate::page::Page;
+use crate::recovery::CLEAN_SHUTDOWN_FILENAME;
+use crate::segment::{extra_segments, read_heap_file, SegmentedFile};
+use crate::storage_manager::PERSIST_CONFIG_FILENAME;
+use crate::wal::WAL_FILENAME;
+use crate::warmup::HOT_PAGES_FILENAME;
+use common::prelude::*;
+use common::PAGE_SIZE;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+///config key holding the container id to heap file path map
+const CATALOG_PATHS_KEY: &str = "cid_path_map";
+///container catalog key holding the list of containers
+const CATALOG_CONTAINERS_KEY: &str = "containers";
+
+///a problem found while scanning a storage directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue {
+    ///the catalog file exists but cannot be parsed
+    UnreadableCatalog(String),
+    ///catalog entry whose heap file does not exist
+    MissingFile {
+        container_id: ContainerId,
+        path: PathBuf,
+    },
+    ///file in the storage directory that no catalog entry refers to
+    Unreferenced(PathBuf),
+    ///heap file length is not a whole number of pages
+    TrailingBytes { path: PathBuf, len: usize },
+    ///heap file header that cannot be read or counts more pages than the file holds
+    BadHeader { path: PathBuf, problem: String },
+    ///page whose header or slot directory is inconsistent
+    CorruptPage {
+        path: PathBuf,
+        page_index: PageId,
+        problems: Vec<String>,
+    },
+    ///free space map beside a heap file that does not match the free space of its pages
+    FsmDrift { path: PathBuf, problem: String },
+}
+
+impl Issue {
+    ///whether repair can fix this issue
+    pub fn is_repairable(&self) -> bool {
+        matches!(
+            self,
+            Issue::MissingFile { .. }
+                | Issue::TrailingBytes { .. }
+                | Issue::CorruptPage { .. }
+                | Issue::FsmDrift { .. }
+        )
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::UnreadableCatalog(e) => write!(f, "catalog cannot be read: {}", e),
+            Issue::MissingFile { container_id, path } => write!(
+                f,
+                "container {} refers to missing file {}",
+                container_id,
+                path.display()
+            ),
+            Issue::Unreferenced(path) => {
+                write!(f, "{} is not referenced by the catalog", path.display())
+            }
+            Issue::TrailingBytes { path, len } => write!(
+                f,
+                "{} is {} bytes, not a multiple of the {} byte page size",
+                path.display(),
+                len,
+                PAGE_SIZE
+            ),
+            Issue::BadHeader { path, problem } => {
+                write!(f, "{} header: {}", path.display(), problem)
+            }
+            Issue::CorruptPage {
+                path,
+                page_index,
+                problems,
+            } => write!(
+                f,
+                "{} page {}: {}",
+                path.display(),
+                page_index,
+                problems.join("; ")
+            ),
+            Issue::FsmDrift { path, problem } => {
+                write!(f, "{} free space map: {}", path.display(), problem)
+            }
+        }
+    }
+}
+
+///inconsistencies in the checksum header and slot directory of the page stored at index
+pub fn page_problems(index: usize, page: &Page) -> Vec<String> {
+    let mut problems = Vec::new();
+    if !page.verify_checksum() {
+        problems.push(format!(
+            "checksum {:08x} does not match contents {:08x}",
+            page.stored_checksum(),
+            page.compute_checksum()
+        ));
+    }
+    if page.magic() != PAGE_MAGIC {
+        problems.push(format!(
+            "magic {:04x} does not mark a heap page",
+            page.magic()
+        ));
+    } else if page.format_version() != PAGE_FORMAT_VERSION {
+        problems.push(format!(
+            "layout version {} is not the current version {}",
+            page.format_version(),
+            PAGE_FORMAT_VERSION
+        ));
+    }
+    if page.get_page_id() as usize != index {
+        problems.push(format!(
+            "page id {} stored at index {}",
+            page.get_page_id(),
+            index
+        ));
+    }
+    if let Err(corruption) = page.check_integrity() {
+        problems.push(corruption.to_string());
+    }
+    problems
+}
+
+///record lies inside the body between the fixed header and the slot directory
+fn in_bounds(entry: &SlotEntry, body_end: usize) -> bool {
+    entry.offset as usize >= FIXED_PAGE_META_SIZE
+        && entry.offset as usize + entry.length as usize <= body_end
+}
+
+///which file the containers were listed in
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CatalogFormat {
+    ///the container catalog saved on every container change which the storage manager prefers
+    Containers,
+    ///the shutdown config of a directory without a container catalog
+    Config,
+}
+
+///the storage manager's persisted container map kept as raw json so unknown fields survive a rewrite
+struct Catalog {
+    path: PathBuf,
+    json: Value,
+    format: CatalogFormat,
+}
+
+impl Catalog {
+    ///None when the directory has no catalog yet
+    fn load(storage_dir: &Path) -> Result<Option<Catalog>, Issue> {
+        let (path, format, key) = if storage_dir.join(CATALOG_FILENAME).is_file() {
+            let path = storage_dir.join(CATALOG_FILENAME);
+            (path, CatalogFormat::Containers, CATALOG_CONTAINERS_KEY)
+        } else {
+            let path = storage_dir.join(PERSIST_CONFIG_FILENAME);
+            (path, CatalogFormat::Config, CATALOG_PATHS_KEY)
+        };
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let contents =
+            fs::read_to_string(&path).map_err(|e| Issue::UnreadableCatalog(e.to_string()))?;
+        let json: Value =
+            serde_json::from_str(&contents).map_err(|e| Issue::UnreadableCatalog(e.to_string()))?;
+        let well_formed = match format {
+            CatalogFormat::Containers => json[key].is_array(),
+            CatalogFormat::Config => json[key].is_object(),
+        };
+        if !well_formed {
+            return Err(Issue::UnreadableCatalog(format!(
+                "no {} in {:?}",
+                key, path
+            )));
+        }
+        Ok(Some(Catalog { path, json, format }))
+    }
+
+    ///container ids and heap file paths in id order skipping entries that do not parse
+    fn entries(&self) -> Vec<(ContainerId, PathBuf)> {
+        let mut entries: Vec<(ContainerId, PathBuf)> = match self.format {
+            CatalogFormat::Containers => self.json[CATALOG_CONTAINERS_KEY]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|c| {
+                    let id = c["meta"]["id"].as_u64()?.try_into().ok()?;
+                    Some((id, PathBuf::from(c["path"].as_str()?)))
+                })
+                .collect(),
+            CatalogFormat::Config => self.json[CATALOG_PATHS_KEY]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter_map(|(id, path)| Some((id.parse().ok()?, PathBuf::from(path.as_str()?))))
+                .collect(),
+        };
+        entries.sort();
+        entries
+    }
+
+    fn remove(&mut self, container_id: ContainerId) {
+        match self.format {
+            CatalogFormat::Containers => {
+                if let Some(list) = self.json[CATALOG_CONTAINERS_KEY].as_array_mut() {
+                    list.retain(|c| c["meta"]["id"].as_u64() != Some(container_id as u64));
+                }
+            }
+            CatalogFormat::Config => {
+                if let Some(map) = self.json[CATALOG_PATHS_KEY].as_object_mut() {
+                    map.remove(&container_id.to_string());
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, container_id: ContainerId, path: &Path) {
+        let path = path.to_string_lossy().into_owned();
+        match self.format {
+            CatalogFormat::Containers => {
+                if let Some(list) = self.json[CATALOG_CONTAINERS_KEY].as_array_mut() {
+                    list.push(serde_json::json!({
+                        "meta": {"state_type": "BaseTable", "id": container_id},
+                        "path": path,
+                    }));
+                }
+            }
+            CatalogFormat::Config => {
+                if let Some(map) = self.json[CATALOG_PATHS_KEY].as_object_mut() {
+                    map.insert(container_id.to_string(), Value::String(path));
+                }
+            }
+        }
+    }
+
+    fn save(&self) -> Result<(), CrustyError> {
+        let contents = serde_json::to_string(&self.json)
+            .map_err(|e| CrustyError::CrustyError(e.to_string()))?;
+        fs::write(&self.path, contents)?;
+        Ok(())
+    }
+}
+
+///heap files to scan with catalog paths first then any other file in the directory
+fn heap_files(
+    storage_dir: &Path,
+    catalog: Option<&Catalog>,
+    issues: &mut Vec<Issue>,
+) -> Result<Vec<PathBuf>, CrustyError> {
+    let mut files = Vec::new();
+    if let Some(catalog) = catalog {
+        for (container_id, path) in catalog.entries() {
+            if path.is_file() {
+                files.push(path);
+            } else {
+                issues.push(Issue::MissingFile { container_id, path });
+            }
+        }
+    }
+
+    let mut others: Vec<PathBuf> = fs::read_dir(storage_dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    others.sort();
+    for path in others {
+        //the catalogs, the write-ahead log, the shutdown mark and the hot pages are not heap files
+        let is_metadata = path.file_name().is_some_and(|n| {
+            [
+                PERSIST_CONFIG_FILENAME,
+                CATALOG_FILENAME,
+                WAL_FILENAME,
+                CLEAN_SHUTDOWN_FILENAME,
+                HOT_PAGES_FILENAME,
+            ]
+            .contains(&n.to_str().unwrap_or_default())
+        });
+        //a heap file's segments, free space map and the seals of its encrypted pages live beside it
+        let is_known = files.iter().any(|f| {
+            same_file(f, &path)
+                || same_file(&fsm_path(f), &path)
+                || same_file(&seal_path(f), &path)
+                || extra_segments(f).iter().any(|s| same_file(s, &path))
+        });
+        if path.is_file() && !is_metadata && !is_known {
+            issues.push(Issue::Unreferenced(path));
+        }
+    }
+    Ok(files)
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+///page and length problems of one heap file read with its segments as one
+fn check_file(path: &Path, issues: &mut Vec<Issue>) -> Result<(), CrustyError> {
+    let bytes = read_heap_file(path)?;
+    if bytes.len() % PAGE_SIZE != 0 {
+        issues.push(Issue::TrailingBytes {
+            path: path.to_path_buf(),
+            len: bytes.len(),
+        });
+    }
+    let start = data_start(&bytes);
+    let mut encrypted = false;
+    let mut page_count = None;
+    if start > 0 {
+        let stored = bytes.len() / PAGE_SIZE - 1;
+        let problem = match HeapFileHeader::from_bytes(bytes[..PAGE_SIZE].try_into().unwrap()) {
+            Ok(header) if header.page_count as usize > stored => Some(format!(
+                "counts {} pages but only {} are stored",
+                header.page_count, stored
+            )),
+            Ok(header) => {
+                encrypted = header.key_id != NO_KEY_ID;
+                page_count = Some(header.page_count as usize);
+                None
+            }
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(problem) = problem {
+            issues.push(Issue::BadHeader {
+                path: path.to_path_buf(),
+                problem,
+            });
+        }
+    }
+    //the bodies of encrypted pages cannot be checked without the key
+    if encrypted {
+        return Ok(());
+    }
+    for (index, chunk) in bytes[start..].chunks_exact(PAGE_SIZE).enumerate() {
+        let page = Page::from_bytes_unchecked(chunk.try_into().unwrap());
+        let problems = page_problems(index, &page);
+        if !problems.is_empty() {
+            issues.push(Issue::CorruptPage {
+                path: path.to_path_buf(),
+                page_index: index as PageId,
+                problems,
+            });
+        }
+    }
+    if let Some(problem) = fsm_problem(path, &bytes, page_count)? {
+        issues.push(Issue::FsmDrift {
+            path: path.to_path_buf(),
+            problem,
+        });
+    }
+    Ok(())
+}
+
+///free space map of the data pages in bytes, of which the header counts page_count if it has one
+///pages that fail a check count as full as they do when a heap file rebuilds its map
+fn free_space_map(bytes: &[u8], page_count: Option<usize>) -> FreeSpaceMap {
+    let start = data_start(bytes);
+    let mut fsm = FreeSpaceMap::new();
+    let chunks = bytes[start..].chunks_exact(PAGE_SIZE);
+    let count = page_count.unwrap_or(chunks.len());
+    for (index, chunk) in chunks.take(count).enumerate() {
+        let page = Page::from_bytes_unchecked(chunk.try_into().unwrap());
+        let free = if page_problems(index, &page).is_empty() {
+            page.get_free_space()
+        } else {
+            0
+        };
+        fsm.update(index as PageId, free);
+    }
+    fsm
+}
+
+///how the free space map saved beside the heap file at path differs from its pages
+///None if there is no saved map, as one is built when the file is opened
+fn fsm_problem(
+    path: &Path,
+    bytes: &[u8],
+    page_count: Option<usize>,
+) -> Result<Option<String>, CrustyError> {
+    let saved = match fs::read(fsm_path(path)) {
+        Ok(saved) => saved,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let expected = free_space_map(bytes, page_count);
+    if saved.len() != expected.len() {
+        return Ok(Some(format!(
+            "covers {} pages but the file has {}",
+            saved.len(),
+            expected.len()
+        )));
+    }
+    let wrong = saved
+        .iter()
+        .zip(expected.to_bytes())
+        .filter(|(saved, expected)| saved != expected)
+        .count();
+    Ok((wrong > 0).then(|| format!("{} pages are in the wrong bucket", wrong)))
+}
+
+///writes the free space map of the heap file at path as its pages now stand
+fn rebuild_fsm(path: &Path) -> Result<(), CrustyError> {
+    let bytes = read_heap_file(path)?;
+    let page_count = bytes
+        .get(..data_start(&bytes))
+        .and_then(|header| HeapFileHeader::from_bytes(header.try_into().ok()?).ok())
+        .map(|header| header.page_count as usize);
+    let fsm = free_space_map(&bytes, page_count);
+    fs::write(fsm_path(path), fsm.to_bytes())?;
+    Ok(())
+}
+
+///offset of the first data page
+///files written before heap files had a header page start straight with data pages
+fn data_start(bytes: &[u8]) -> usize {
+    if HeapFileHeader::is_header(bytes) {
+        PAGE_SIZE
+    } else {
+        0
+    }
+}
+
+///validates the catalog and every heap file in storage_dir
+pub fn check(storage_dir: &Path) -> Result<Vec<Issue>, CrustyError> {
+    let mut issues = Vec::new();
+    let catalog = match Catalog::load(storage_dir) {
+        Ok(catalog) => catalog,
+        Err(issue) => {
+            issues.push(issue);
+            None
+        }
+    };
+    for path in heap_files(storage_dir, catalog.as_ref(), &mut issues)? {
+        check_file(&path, &mut issues)?;
+    }
+    Ok(issues)
+}
+
+///fixes the repairable issues and returns a description of each action
+///corrupt pages are replaced by empty pages after their in bounds records are copied into a new salvage container
+///a free space map that drifted from its pages is written again from them
+///with dry_run nothing is written and the descriptions say what would be done
+pub fn repair(
+    storage_dir: &Path,
+    issues: &[Issue],
+    dry_run: bool,
+) -> Result<Vec<String>, CrustyError> {
+    let mut actions = Vec::new();
+    let mut catalog = Catalog::load(storage_dir).ok().flatten();
+    let mut catalog_changed = false;
+    let mut salvaged: Vec<Vec<u8>> = Vec::new();
+
+    for issue in issues {
+        match issue {
+            Issue::MissingFile { container_id, .. } => {
+                actions.push(format!("drop container {} from the catalog", container_id));
+                if let Some(catalog) = catalog.as_mut() {
+                    catalog.remove(*container_id);
+                    catalog_changed = true;
+                }
+            }
+            Issue::TrailingBytes { path, len } => {
+                let keep = len - len % PAGE_SIZE;
+                actions.push(format!("truncate {} to {} bytes", path.display(), keep));
+                if !dry_run {
+                    SegmentedFile::open(path)?.set_len(keep as u64)?;
+                }
+            }
+            Issue::CorruptPage {
+                path, page_index, ..
+            } => {
+                let (page, offset) = read_page(path, *page_index)?;
+                let records = salvage_records(&page);
+                actions.push(format!(
+                    "salvage {} records and tombstone {} page {}",
+                    records.len(),
+                    path.display(),
+                    page_index
+                ));
+                salvaged.extend(records);
+                if !dry_run {
+                    write_page(path, offset, &mut Page::new(*page_index))?;
+                    //the empty page has room the saved free space map does not know of
+                    if fsm_path(path).exists() {
+                        rebuild_fsm(path)?;
+                    }
+                }
+            }
+            Issue::FsmDrift { path, .. } => {
+                actions.push(format!(
+                    "rebuild the free space map of {} from its pages",
+                    path.display()
+                ));
+                if !dry_run {
+                    rebuild_fsm(path)?;
+                }
+            }
+            Issue::UnreadableCatalog(_) | Issue::Unreferenced(_) | Issue::BadHeader { .. } => {}
+        }
+    }
+
+    if !salvaged.is_empty() {
+        let container_id = catalog
+            .as_ref()
+            .and_then(|c| c.entries().last().map(|(id, _)| id + 1))
+            .unwrap_or(0);
+        let path = storage_dir.join(format!("salvage_{}.hf", container_id));
+        actions.push(format!(
+            "write {} salvaged records to container {} at {}",
+            salvaged.len(),
+            container_id,
+            path.display()
+        ));
+        if !dry_run {
+            write_salvage_file(&path, container_id, &salvaged)?;
+        }
+        if let Some(catalog) = catalog.as_mut() {
+            catalog.insert(container_id, &path);
+            catalog_changed = true;
+        }
+    }
+
+    if catalog_changed && !dry_run {
+        if let Some(catalog) = &catalog {
+            catalog.save()?;
+        }
+    }
+    Ok(actions)
+}
+
+///live records that lie inside the page body
+fn salvage_records(page: &Page) -> Vec<Vec<u8>> {
+    let body_end = page.slot_directory_offset();
+    page.slot_entries()
+        .iter()
+        .filter(|e| e.in_use && in_bounds(e, body_end))
+        .filter_map(|e| page.get_value(e.slot_id))
+        .collect()
+}
+
+///data page at page_index and the file offset it was read from
+fn read_page(path: &Path, page_index: PageId) -> Result<(Page, u64), CrustyError> {
+    let bytes = read_heap_file(path)?;
+    let start = data_start(&bytes) + page_index as usize * PAGE_SIZE;
+    let chunk = bytes.get(start..start + PAGE_SIZE).ok_or_else(|| {
+        CrustyError::CrustyError(format!("{} has no page {}", path.display(), page_index))
+    })?;
+    Ok((
+        Page::from_bytes_unchecked(chunk.try_into().unwrap()),
+        start as u64,
+    ))
+}
+
+fn write_page(path: &Path, offset: u64, page: &mut Page) -> Result<(), CrustyError> {
+    page.update_checksum();
+    let mut file = SegmentedFile::open(path)?;
+    file.write_all_at(page.to_bytes(), offset)?;
+    file.sync_all()
+}
+
+///packs records into fresh pages in order behind a header for container_id
+fn write_salvage_file(
+    path: &Path,
+    container_id: ContainerId,
+    records: &[Vec<u8>],
+) -> Result<(), CrustyError> {
+    let mut pages = vec![Page::new(0)];
+    for record in records {
+        if pages.last_mut().unwrap().add_value(record).is_none() {
+            let mut page = Page::new(pages.len() as PageId);
+            page.add_value(record).ok_or_else(|| {
+                CrustyError::CrustyError(format!(
+                    "salvaged record of {} bytes does not fit a page",
+                    record.len()
+                ))
+            })?;
+            pages.push(page);
+        }
+    }
+    let mut header = HeapFileHeader::new(container_id);
+    header.page_count = pages.len() as PageId;
+    let mut file = fs::File::create(path)?;
+    file.write_all(&header.to_bytes())?;
+    for page in &mut pages {
+        page.update_checksum();
+        page.write_to(&mut file)?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsm::FSM_BUCKETS;
+    use crate::storage_manager::StorageManager;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    fn write_catalog(dir: &Path, entries: &[(ContainerId, &Path)]) {
+        let map: serde_json::Map<String, Value> = entries
+            .iter()
+            .map(|(id, path)| (id.to_string(), Value::String(path.display().to_string())))
+            .collect();
+        let json = serde_json::json!({
+            "storage_dir": dir.display().to_string(),
+            "is_temp": false,
+            "cid_path_map": map,
+        });
+        fs::write(dir.join(PERSIST_CONFIG_FILENAME), json.to_string()).unwrap();
+    }
+
+    #[test]
+    fn hs_fsck_clean_directory() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let hf = tdir.join("1.hf");
+        let mut page = Page::new(0);
+        page.add_value(&get_random_byte_vec(100)).unwrap();
+        page.update_checksum();
+        fs::write(&hf, page.to_bytes()).unwrap();
+        write_catalog(&tdir, &[(1, &hf)]);
+        assert_eq!(Vec::<Issue>::new(), check(&tdir).unwrap());
+    }
+
+    #[test]
+    fn hs_fsck_checksum_mismatch() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let hf = tdir.join("1.hf");
+        let mut page = Page::new(0);
+        page.add_value(&get_random_byte_vec(100)).unwrap();
+        page.update_checksum();
+        let mut bytes = page.to_bytes().to_vec();
+        bytes[PAGE_SIZE - 1] ^= 0xff;
+        fs::write(&hf, &bytes).unwrap();
+        write_catalog(&tdir, &[(1, &hf)]);
+
+        let issues = check(&tdir).unwrap();
+        assert_eq!(1, issues.len());
+        assert!(issues[0].to_string().contains("checksum"));
+        repair(&tdir, &issues, false).unwrap();
+        assert_eq!(Vec::<Issue>::new(), check(&tdir).unwrap());
+    }
+
+    #[test]
+    fn hs_fsck_repair() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let hf = tdir.join("1.hf");
+        let good = get_random_byte_vec(100);
+        let mut p0 = Page::new(0);
+        p0.add_value(&good).unwrap();
+        //second page claims a record running past the page end
+        let mut p1 = Page::new(1);
+        p1.add_value(&good).unwrap();
+        p1.add_value(&[7; 50]).unwrap();
+        let mut bytes = p1.to_bytes().to_vec();
+        let length_at = PAGE_SIZE - 2 * 6 + 2;
+        bytes[length_at..length_at + 2].copy_from_slice(&(PAGE_SIZE as u16).to_le_bytes());
+        let mut file = p0.to_bytes().to_vec();
+        file.extend_from_slice(&bytes);
+        file.extend_from_slice(&[1, 2, 3]);
+        fs::write(&hf, &file).unwrap();
+        write_catalog(&tdir, &[(1, &hf), (2, &tdir.join("gone.hf"))]);
+
+        let issues = check(&tdir).unwrap();
+        assert_eq!(3, issues.len());
+        assert!(issues.iter().all(Issue::is_repairable));
+
+        let planned = repair(&tdir, &issues, true).unwrap();
+        assert_eq!(4, planned.len());
+        assert_eq!(file, fs::read(&hf).unwrap());
+
+        repair(&tdir, &issues, false).unwrap();
+        let issues = check(&tdir).unwrap();
+        assert_eq!(Vec::<Issue>::new(), issues);
+        let repaired = fs::read(&hf).unwrap();
+        assert_eq!(2 * PAGE_SIZE, repaired.len());
+        let mut tombstone = Page::new(1);
+        tombstone.update_checksum();
+        assert_eq!(tombstone.to_bytes(), &repaired[PAGE_SIZE..]);
+
+        let salvage = fs::read(tdir.join("salvage_2.hf")).unwrap();
+        let header = HeapFileHeader::from_bytes(salvage[..PAGE_SIZE].try_into().unwrap()).unwrap();
+        assert_eq!((2, 1), (header.container_id, header.page_count));
+        let page = Page::from_bytes(salvage[PAGE_SIZE..].try_into().unwrap()).unwrap();
+        assert_eq!(Some(good), page.get_value(0));
+        assert_eq!(None, page.get_value(1));
+    }
+
+    #[test]
+    fn hs_fsck_header() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let hf = tdir.join("1.hf");
+        let mut header = HeapFileHeader::new(1);
+        header.page_count = 2;
+        let mut p0 = Page::new(0);
+        p0.add_value(&get_random_byte_vec(100)).unwrap();
+        p0.update_checksum();
+        //second data page carries the wrong page id
+        let mut p1 = Page::new(5);
+        p1.update_checksum();
+        let mut file = header.to_bytes().to_vec();
+        file.extend_from_slice(p0.to_bytes());
+        file.extend_from_slice(p1.to_bytes());
+        fs::write(&hf, &file).unwrap();
+        write_catalog(&tdir, &[(1, &hf)]);
+
+        let issues = check(&tdir).unwrap();
+        assert_eq!(1, issues.len());
+        assert!(issues[0]
+            .to_string()
+            .contains("page 1: page id 5 stored at index 1"));
+        repair(&tdir, &issues, false).unwrap();
+        assert_eq!(Vec::<Issue>::new(), check(&tdir).unwrap());
+        let repaired = fs::read(&hf).unwrap();
+        assert_eq!(&file[..2 * PAGE_SIZE], &repaired[..2 * PAGE_SIZE]);
+
+        header.page_count = 3;
+        file[..PAGE_SIZE].copy_from_slice(&header.to_bytes());
+        fs::write(&hf, &file).unwrap();
+        let issues = check(&tdir).unwrap();
+        assert!(matches!(&issues[0], Issue::BadHeader { .. }));
+        assert!(!issues[0].is_repairable());
+        file[PAGE_SIZE - 1] ^= 0xff;
+        fs::write(&hf, &file).unwrap();
+        assert!(check(&tdir).unwrap()[0].to_string().contains("checksum"));
+    }
+
+    #[test]
+    fn hs_fsck_container_catalog() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        {
+            let sm = StorageManager::new(&tdir);
+            for cid in 1..=2 {
+                sm.create_table(cid).unwrap();
+                sm.insert_values(cid, get_random_vec_of_byte_vec(50, 40, 400), tid);
+            }
+            sm.flush_all().unwrap();
+        }
+        assert_eq!(Vec::<Issue>::new(), check(&tdir).unwrap());
+
+        //the catalog written on every container change is what gets repaired
+        fs::remove_file(tdir.join("2.hf")).unwrap();
+        fs::remove_file(tdir.join("2.fsm")).unwrap();
+        let issues = check(&tdir).unwrap();
+        assert!(matches!(
+            issues[..],
+            [Issue::MissingFile {
+                container_id: 2,
+                ..
+            }]
+        ));
+        repair(&tdir, &issues, false).unwrap();
+        assert_eq!(Vec::<Issue>::new(), check(&tdir).unwrap());
+        let sm = StorageManager::new(&tdir);
+        let ids: Vec<ContainerId> = sm.list_containers().iter().map(|c| c.id).collect();
+        assert_eq!(vec![1], ids);
+    }
+
+    #[test]
+    fn hs_fsck_fsm_drift() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        {
+            let sm = StorageManager::new(&tdir);
+            sm.create_table(1).unwrap();
+            sm.insert_values(1, get_random_vec_of_byte_vec(50, 40, 400), tid);
+            sm.flush_all().unwrap();
+        }
+        let fsm = tdir.join("1.fsm");
+        let saved = fs::read(&fsm).unwrap();
+        assert!(saved.len() > 1);
+        assert_eq!(Vec::<Issue>::new(), check(&tdir).unwrap());
+
+        //every page claimed empty and one bucket past the last
+        let mut corrupted = vec![(FSM_BUCKETS - 1) as u8; saved.len()];
+        corrupted[0] = FSM_BUCKETS as u8;
+        fs::write(&fsm, &corrupted).unwrap();
+        let issues = check(&tdir).unwrap();
+        assert!(matches!(issues[..], [Issue::FsmDrift { .. }]));
+        assert!(issues[0].is_repairable());
+        assert!(issues[0].to_string().contains("wrong bucket"));
+        repair(&tdir, &issues, true).unwrap();
+        assert_eq!(corrupted, fs::read(&fsm).unwrap());
+        repair(&tdir, &issues, false).unwrap();
+        assert_eq!(saved, fs::read(&fsm).unwrap());
+        assert_eq!(Vec::<Issue>::new(), check(&tdir).unwrap());
+
+        //a map cut short is rebuilt as well
+        fs::write(&fsm, &saved[1..]).unwrap();
+        let issues = check(&tdir).unwrap();
+        assert!(issues[0].to_string().contains("covers"));
+        repair(&tdir, &issues, false).unwrap();
+        assert_eq!(saved, fs::read(&fsm).unwrap());
+    }
+}
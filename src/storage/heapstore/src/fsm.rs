@@ -0,0 +1,153 @@
+use crate::heap_page::BYTES_PER_SLOT_META;
+use common::prelude::*;
+use common::PAGE_SIZE;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+///number of coarse free space categories a page can fall into
+pub(crate) const FSM_BUCKETS: usize = 64;
+///free bytes covered by one bucket
+const FSM_BUCKET_WIDTH: usize = PAGE_SIZE / FSM_BUCKETS;
+
+///per container map from each page to a coarse count of its free bytes
+///a page in bucket b has at least b * FSM_BUCKET_WIDTH bytes free so finding a page for a record
+///looks at no more than FSM_BUCKETS sets however many pages the container has
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FreeSpaceMap {
+    ///bucket of every page indexed by PageId
+    buckets: Vec<u8>,
+    ///pages in each bucket
+    pages: Vec<BTreeSet<PageId>>,
+}
+
+impl FreeSpaceMap {
+    pub(crate) fn new() -> Self {
+        FreeSpaceMap {
+            buckets: Vec::new(),
+            pages: vec![BTreeSet::new(); FSM_BUCKETS],
+        }
+    }
+
+    ///map from one bucket byte per page as written by to_bytes
+    ///None if a byte is not a valid bucket
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut fsm = FreeSpaceMap::new();
+        for (page_id, &bucket) in bytes.iter().enumerate() {
+            if bucket as usize >= FSM_BUCKETS {
+                return None;
+            }
+            fsm.set_bucket(page_id as PageId, bucket);
+        }
+        Some(fsm)
+    }
+
+    ///one bucket byte per page in PageId order
+    pub(crate) fn to_bytes(&self) -> &[u8] {
+        &self.buckets
+    }
+
+    ///number of pages the map covers
+    pub(crate) fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    ///records that page_id now has free bytes free
+    ///pages past the end of the map are added and any skipped over count as full
+    pub(crate) fn update(&mut self, page_id: PageId, free: usize) {
+        self.set_bucket(page_id, Self::bucket_for(free));
+    }
+
+    ///forgets every page from len on
+    pub(crate) fn truncate(&mut self, len: usize) {
+        for (page_id, &bucket) in self.buckets.iter().enumerate().skip(len) {
+            self.pages[bucket as usize].remove(&(page_id as PageId));
+        }
+        self.buckets.truncate(len);
+    }
+
+    ///lowest page whose bucket guarantees room for a record of len bytes and its slot entry
+    ///None if no page is known to have enough room
+    pub(crate) fn find(&self, len: usize) -> Option<PageId> {
+        let wanted = (len + BYTES_PER_SLOT_META).div_ceil(FSM_BUCKET_WIDTH);
+        self.pages
+            .get(wanted..)?
+            .iter()
+            .filter_map(|pages| pages.first().copied())
+            .min()
+    }
+
+    ///bucket for a page with free bytes free rounded down so it never overstates the room
+    fn bucket_for(free: usize) -> u8 {
+        (free / FSM_BUCKET_WIDTH).min(FSM_BUCKETS - 1) as u8
+    }
+
+    fn set_bucket(&mut self, page_id: PageId, bucket: u8) {
+        let index = page_id as usize;
+        while self.buckets.len() <= index {
+            self.pages[0].insert(self.buckets.len() as PageId);
+            self.buckets.push(0);
+        }
+        self.pages[self.buckets[index] as usize].remove(&page_id);
+        self.pages[bucket as usize].insert(page_id);
+        self.buckets[index] = bucket;
+    }
+}
+
+///file next to a heap file that holds its free space map between runs
+pub(crate) fn fsm_path(heap_file: &Path) -> PathBuf {
+    heap_file.with_extension("fsm")
+}
+
+///map persisted for a heap file of num_pages pages
+///None if there is none or it does not cover exactly those pages and must be rebuilt
+pub(crate) fn load_fsm(heap_file: &Path, num_pages: PageId) -> Option<FreeSpaceMap> {
+    let bytes = fs::read(fsm_path(heap_file)).ok()?;
+    if bytes.len() != num_pages as usize {
+        debug!(
+            "Free space map of {:?} covers {} pages not {}",
+            heap_file,
+            bytes.len(),
+            num_pages
+        );
+        return None;
+    }
+    FreeSpaceMap::from_bytes(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testutil::init;
+
+    #[test]
+    fn hs_fsm_find_update() {
+        init();
+        let mut fsm = FreeSpaceMap::new();
+        assert_eq!(None, fsm.find(0));
+        fsm.update(2, PAGE_SIZE);
+        //pages skipped over are known only as full
+        assert_eq!(3, fsm.len());
+        assert_eq!(Some(2), fsm.find(100));
+        assert_eq!(None, fsm.find(PAGE_SIZE));
+
+        fsm.update(0, 300);
+        fsm.update(1, 1000);
+        assert_eq!(Some(0), fsm.find(100));
+        assert_eq!(Some(1), fsm.find(500));
+        assert_eq!(Some(2), fsm.find(1000));
+        //buckets round down so a page is never offered for a record it may not hold
+        assert_eq!(Some(1), fsm.find(300 - BYTES_PER_SLOT_META));
+        fsm.update(2, 0);
+        assert_eq!(None, fsm.find(1000));
+        assert_eq!(Some(1), fsm.find(500));
+        fsm.truncate(1);
+        assert_eq!(1, fsm.len());
+        assert_eq!(None, fsm.find(500));
+        assert_eq!(Some(0), fsm.find(100));
+
+        let loaded = FreeSpaceMap::from_bytes(fsm.to_bytes()).unwrap();
+        assert_eq!(fsm, loaded);
+        assert!(FreeSpaceMap::from_bytes(&[0, FSM_BUCKETS as u8]).is_none());
+    }
+}
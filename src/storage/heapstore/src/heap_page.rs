@@ -1,24 +1,94 @@
 use crate::page;
-use crate::page::{Offset, Page};
+use crate::page::{Offset, Page, SizedPage};
 use common::prelude::*;
 use common::PAGE_SIZE;
+use std::borrow::Cow;
 use std::fmt;
 use std::fmt::Write;
 
 ///byte length of a slot value distinct from Offset which is a position
-pub type SlotLength = u16;
+pub type SlotLength = u32;
 
 //page header field byte offsets
 ///num_slots byte offset in the header
 const PAGE_META_NUM_SLOTS_OFFSET: usize = 2;
 ///free_start byte offset in the header
 const PAGE_META_FREE_START_OFFSET: usize = 4;
-///reserved padding byte offset in the header
-const PAGE_META_RESERVED_OFFSET: usize = 6;
+///fill factor percent byte offset in the header 0 means the default
+pub(crate) const PAGE_META_FILL_FACTOR_OFFSET: usize = 6;
+///page flag bits byte offset in the header
+pub(crate) const PAGE_META_FLAGS_OFFSET: usize = 7;
+///page flag set when the slot directory uses the compact encoding
+const PAGE_FLAG_COMPACT_SLOTS: u8 = 0x01;
+///page flag set when valid records are stored as a varint shared prefix length and suffix
+///relative to the previous valid record in SlotId order
+const PAGE_FLAG_PREFIX_COMPRESSED: u8 = 0x02;
+///page flag set when a zone map region sits at the very end of the page after the slot directory
+const PAGE_FLAG_ZONE_MAP: u8 = 0x04;
+
+///bytes of a key the zone map keeps longer min keys are cut and longer max keys marked truncated
+pub const ZONE_MAP_KEY_SIZE: usize = 16;
+///zone map region state min length max length max truncated then the min and max keys
+const ZONE_MAP_REGION_SIZE: usize = 4 + 2 * ZONE_MAP_KEY_SIZE;
+///zone map state of a page no keyed record has been added to
+const ZONE_MAP_EMPTY: u8 = 0;
+///zone map state whose bounds cover every record
+const ZONE_MAP_VALID: u8 = 1;
+///zone map state after a record was added or changed without its key
+const ZONE_MAP_STALE: u8 = 2;
+
+///page flag set when a bloom filter region sits just before the zone map or at the page end
+const PAGE_FLAG_BLOOM_FILTER: u8 = 0x08;
+///page flag set on pages too large for u16 offsets whose slots and free_start are stored as u32
+const PAGE_FLAG_LARGE_SLOTS: u8 = 0x10;
+///page flag set when deleted and relocated record bytes are zeroed instead of left in the body
+const PAGE_FLAG_SECURE_DELETE: u8 = 0x20;
+///page flag set when slots 0..num_slots are all valid and ordered by a caller's record key
+pub(crate) const PAGE_FLAG_SORTED: u8 = 0x40;
+///page flag set only in an on disk image whose body after the header is lz4 compressed
+pub(crate) const PAGE_FLAG_BODY_COMPRESSED: u8 = 0x80;
+///bits in a page bloom filter
+pub const BLOOM_FILTER_BITS: usize = 512;
+///bit positions set per key
+const BLOOM_FILTER_HASHES: u64 = 3;
+///bloom filter region state byte then the bits
+const BLOOM_FILTER_REGION_SIZE: usize = 1 + BLOOM_FILTER_BITS / 8;
+///bloom filter state whose bits hold the key of every keyed insert
+const BLOOM_FILTER_VALID: u8 = 1;
+///bloom filter state after a record was added or changed without its key
+const BLOOM_FILTER_STALE: u8 = 2;
+///percent of the page inserts may fill unless configured otherwise
+pub const DEFAULT_FILL_FACTOR: u8 = 100;
+///crc32 checksum byte offset in the header
+pub(crate) const PAGE_META_CHECKSUM_OFFSET: usize = 8;
+///lsn of the last logged change byte offset in the header log page then log slot
+const PAGE_META_LSN_OFFSET: usize = 12;
+///magic number byte offset in the header marks the bytes as a heap page
+pub(crate) const PAGE_META_MAGIC_OFFSET: usize = 16;
+///page layout version byte offset in the header the byte after it is reserved
+pub(crate) const PAGE_META_VERSION_OFFSET: usize = 18;
+///magic number of every heap page stored as the bytes HS
+pub(crate) const PAGE_MAGIC: u16 = u16::from_le_bytes(*b"HS");
+///layout version written into new pages and expected by from_bytes
+pub const PAGE_FORMAT_VERSION: u8 = 1;
 ///size of the fixed page metadata block
-const FIXED_PAGE_META_SIZE: usize = 8;
+pub(crate) const FIXED_PAGE_META_SIZE: usize = 20;
 ///size of one slot metadata entry
-const BYTES_PER_SLOT_META: usize = 6;
+pub(crate) const BYTES_PER_SLOT_META: usize = 6;
+///size of one slot metadata entry in the large encoding offset u32 length u32 then the in_use byte
+pub(crate) const LARGE_BYTES_PER_SLOT_META: usize = 9;
+///largest page whose offsets fit the u16 fields of the wide and compact encodings
+pub const MAX_WIDE_SLOT_PAGE_SIZE: usize = u16::MAX as usize;
+///u32 free_start kept in the last bytes of a large slot page since the header field is a u16
+const LARGE_FREE_START_SIZE: usize = 4;
+///size of one slot metadata entry in the compact encoding offset then in_use and length packed in a u16
+pub(crate) const COMPACT_BYTES_PER_SLOT_META: usize = 4;
+///largest page the compact encoding can describe since lengths keep only 14 bits
+pub const MAX_COMPACT_SLOT_PAGE_SIZE: usize = 1 << COMPACT_SLOT_IN_USE_SHIFT;
+///low bits of a compact slot's packed u16 holding the record length
+const COMPACT_SLOT_LENGTH_MASK: u16 = (1 << COMPACT_SLOT_IN_USE_SHIFT) - 1;
+///the in_use flag sits in the top two bits of a compact slot's packed u16
+const COMPACT_SLOT_IN_USE_SHIFT: u32 = 14;
 
 //slot entry field offsets relative to slot entry start
 ///record page offset within a slot entry
@@ -27,561 +97,3789 @@ const SLOT_OFFSET_OFFSET: usize = 0;
 const SLOT_LENGTH_OFFSET: usize = 2;
 ///in_use flag offset within a slot entry
 const SLOT_IN_USE_OFFSET: usize = 4;
+///record byte length within a large slot entry
+const LARGE_SLOT_LENGTH_OFFSET: usize = 4;
+///in_use flag offset within a large slot entry
+const LARGE_SLOT_IN_USE_OFFSET: usize = 8;
 
 ///slot holds a live record
-const SLOT_IN_USE_VALID: u8 = 1;
+pub(crate) const SLOT_IN_USE_VALID: u8 = 1;
 ///slot is free or deleted
-const SLOT_IN_USE_FREE: u8 = 0;
+pub(crate) const SLOT_IN_USE_FREE: u8 = 0;
+///slot holds an overflow pointer to a chain of continuation pages
+pub(crate) const SLOT_IN_USE_OVERFLOW: u8 = 2;
+///slot holds one chunk of an overflow record on a continuation page
+pub(crate) const SLOT_IN_USE_CONTINUATION: u8 = 3;
+///bits of the in_use byte holding the slot state the rest are slot flags
+const SLOT_STATE_MASK: u8 = 0x03;
+///slot flag for a record logically deleted but kept for readers that may still see it
+pub const SLOT_FLAG_TOMBSTONE: u8 = 0x04;
+///slot flag for a record that holds the address of where the record moved
+pub const SLOT_FLAG_FORWARDED: u8 = 0x08;
+///slot flag for a record stored compressed by a higher layer
+pub const SLOT_FLAG_COMPRESSED: u8 = 0x10;
+///slot flag for a record locked by a higher layer
+pub const SLOT_FLAG_LOCKED: u8 = 0x20;
+///every slot flag bit set_slot_flags accepts
+pub const SLOT_FLAGS_MASK: u8 =
+    SLOT_FLAG_TOMBSTONE | SLOT_FLAG_FORWARDED | SLOT_FLAG_COMPRESSED | SLOT_FLAG_LOCKED;
 
-pub trait HeapPage {
-    fn add_value(&mut self, bytes: &[u8]) -> Option<SlotId>;
-    fn get_value(&self, slot_id: SlotId) -> Option<Vec<u8>>;
-    fn delete_value(&mut self, slot_id: SlotId) -> Option<()>;
-    fn get_header_size(&self) -> usize;
-    fn get_free_space(&self) -> usize;
+///when a page moves records to close the holes left by deletes and updates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompactionPolicy {
+    ///only vacuum moves records so inserts fail once the contiguous gap is too small
+    Never,
+    ///compact when an insert or update needs a larger contiguous gap
+    #[default]
+    OnDemand,
+    ///also compact after a delete or update leaves at least dead_space_threshold unreferenced bytes
+    Aggressive { dead_space_threshold: usize },
 }
 
-impl HeapPage for Page {
-    ///total header size including all slot entries
-    fn get_header_size(&self) -> usize {
-        FIXED_PAGE_META_SIZE + self.get_num_slots() * BYTES_PER_SLOT_META
+///how slot directory entries are laid out recorded in the page flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SlotEncoding {
+    ///6 bytes per slot offset u16 length u16 and an in_use byte
+    #[default]
+    Wide,
+    ///4 bytes per slot with in_use in the top two bits of the length only for pages up to MAX_COMPACT_SLOT_PAGE_SIZE
+    Compact,
+    ///9 bytes per slot with u32 offset and length used by every page larger than MAX_WIDE_SLOT_PAGE_SIZE
+    Large,
+}
+
+///occupancy and fragmentation of one page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PageStats {
+    ///slots holding a record overflow pointer or continuation chunk
+    pub live_slots: usize,
+    ///free slot entries waiting to be reused
+    pub dead_slots: usize,
+    ///bytes of body referenced by live slots
+    pub live_bytes: usize,
+    ///body bytes below free_start no live slot points at which only compaction reclaims
+    pub dead_bytes: usize,
+    ///gap between free_start and the slot directory usable without compacting
+    pub largest_free_chunk: usize,
+    ///fixed header plus slot directory bytes
+    pub header_bytes: usize,
+}
+
+///first broken layout invariant check_integrity finds on a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageCorruption {
+    ///the fixed header summaries and num_slots entries need more bytes than the page holds
+    DirectoryOverflow { header_size: usize },
+    ///stored free_start lies before the body or inside the slot directory
+    FreeStartOutOfRange { free_start: usize },
+    ///slot entry whose in_use byte sets bits no slot state or flag uses
+    UnknownSlotState { slot_id: SlotId, in_use: u8 },
+    ///live record reaching outside the body
+    SlotOutOfBounds {
+        slot_id: SlotId,
+        offset: Offset,
+        length: SlotLength,
+    },
+    ///live record ending past free_start where the next insert would overwrite it
+    RecordPastFreeStart {
+        slot_id: SlotId,
+        end: usize,
+        free_start: usize,
+    },
+    ///two live records sharing body bytes
+    SlotsOverlap { first: SlotId, second: SlotId },
+}
+
+impl fmt::Display for PageCorruption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PageCorruption::DirectoryOverflow { header_size } => {
+                write!(f, "slot directory needs {} bytes", header_size)
+            }
+            PageCorruption::FreeStartOutOfRange { free_start } => {
+                write!(f, "free_start {} outside the body", free_start)
+            }
+            PageCorruption::UnknownSlotState { slot_id, in_use } => {
+                write!(f, "slot {} has unknown state {}", slot_id, in_use)
+            }
+            PageCorruption::SlotOutOfBounds { slot_id, .. } => {
+                write!(f, "slot {} out of bounds", slot_id)
+            }
+            PageCorruption::RecordPastFreeStart {
+                slot_id,
+                end,
+                free_start,
+            } => write!(
+                f,
+                "slot {} ends at {} past free_start {}",
+                slot_id, end, free_start
+            ),
+            PageCorruption::SlotsOverlap { first, second } => {
+                write!(f, "slots {} and {} overlap", first, second)
+            }
+        }
     }
+}
 
-    ///free bytes remaining
-    fn get_free_space(&self) -> usize {
-        let header_size = self.get_header_size();
-        let used_bytes: usize = self
-            .iter_used_slots()
-            .map(|(_, len)| len as usize)
-            .sum::<usize>();
-        PAGE_SIZE.saturating_sub(header_size).saturating_sub(used_bytes)
+impl std::error::Error for PageCorruption {}
+
+///why try_add_value could not store a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageInsertError {
+    ///the record is longer than the page's max record size so it belongs in overflow storage
+    RecordTooLarge { len: usize, max: usize },
+    ///the record needs more bytes than the page has free below its fill factor
+    PageFull { needed: usize, available: usize },
+    ///the record would fit but the slot entry it needs would not
+    SlotDirectoryFull,
+    ///sorted pages only take records through add_value_sorted
+    SortedPage,
+}
+
+impl fmt::Display for PageInsertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PageInsertError::RecordTooLarge { len, max } => {
+                write!(
+                    f,
+                    "record of {} bytes exceeds the max record size {}",
+                    len, max
+                )
+            }
+            PageInsertError::PageFull { needed, available } => {
+                write!(
+                    f,
+                    "record needs {} bytes but {} are free",
+                    needed, available
+                )
+            }
+            PageInsertError::SlotDirectoryFull => write!(f, "no room for another slot entry"),
+            PageInsertError::SortedPage => write!(f, "sorted page only takes sorted inserts"),
+        }
     }
+}
 
-    ///inserts bytes and returns the assigned SlotId or None if no space
-    ///always reuses the lowest free SlotId
-    fn add_value(&mut self, bytes: &[u8]) -> Option<SlotId> {
-        let value_len = bytes.len();
-        if value_len > PAGE_SIZE {
-            return None;
+impl std::error::Error for PageInsertError {}
+
+impl From<PageInsertError> for CrustyError {
+    fn from(error: PageInsertError) -> Self {
+        CrustyError::CrustyError(error.to_string())
+    }
+}
+
+///min and max sort keys of the records on a page for skipping pages in range scans
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ZoneMap {
+    ///smallest key cut to ZONE_MAP_KEY_SIZE bytes None if no keyed record was added
+    pub min: Option<Vec<u8>>,
+    ///largest key or its first ZONE_MAP_KEY_SIZE bytes when max_truncated
+    pub max: Option<Vec<u8>>,
+    ///max is only a prefix so any key starting with it may be on the page
+    pub max_truncated: bool,
+}
+
+///space for a record and the SlotId it will take held back by reserve until commit or abort
+///dropping it without either keeps them held until the page is reloaded
+#[derive(Debug, PartialEq, Eq)]
+#[must_use]
+pub struct Reservation {
+    slot_id: SlotId,
+    capacity: usize,
+}
+
+impl Reservation {
+    ///SlotId the committed record will have
+    pub fn slot_id(&self) -> SlotId {
+        self.slot_id
+    }
+
+    ///most bytes commit may store
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl ZoneMap {
+    ///whether a key in low..=high may be on the page
+    pub fn overlaps(&self, low: &[u8], high: &[u8]) -> bool {
+        let (Some(min), Some(max)) = (&self.min, &self.max) else {
+            return false;
+        };
+        if high < min.as_slice() {
+            return false;
         }
-    
-        let slot_id = self.find_lowest_free_slot_id();
-        let num_slots = self.get_num_slots();
-        let need_new_slot = (slot_id as usize) >= num_slots;
-    
-        let extra_header = if need_new_slot { BYTES_PER_SLOT_META } else { 0 };
-        if self.get_free_space() < value_len + extra_header {
-            return None;
+        if self.max_truncated {
+            &low[..low.len().min(ZONE_MAP_KEY_SIZE)] <= max.as_slice()
+        } else {
+            low <= max.as_slice()
         }
-    
-        //compact before growing the header so free_start is accurate for the shift
-        let free_start = self.get_free_start();
-        let contiguous_space = PAGE_SIZE.saturating_sub(free_start + extra_header);
-        if contiguous_space < value_len {
-            self.compact();
+    }
+
+    ///widens the bounds to cover key
+    fn widen(&mut self, key: &[u8]) {
+        let cut = &key[..key.len().min(ZONE_MAP_KEY_SIZE)];
+        let truncated = key.len() > ZONE_MAP_KEY_SIZE;
+        if self.min.as_deref().is_none_or(|min| cut < min) {
+            self.min = Some(cut.to_vec());
         }
-    
-        if need_new_slot {
-            if num_slots > 0 {
-                self.shift_body_for_new_slot();
+        match self.max.as_deref() {
+            Some(max) if cut < max => {}
+            Some(max) if cut == max => self.max_truncated |= truncated,
+            _ => {
+                self.max = Some(cut.to_vec());
+                self.max_truncated = truncated;
             }
-            self.set_num_slots(num_slots + 1);
         }
-    
-        let insert_offset = self.get_free_start();
-        if insert_offset + value_len > PAGE_SIZE {
-            return None;
+    }
+}
+
+///bloom filter bit positions of key by double hashing its mixed fnv-1a hash
+fn bloom_positions(key: &[u8]) -> impl Iterator<Item = usize> {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in key {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    //fnv only carries low bits upward so mix the high bits back down before taking a modulus
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+    (0..BLOOM_FILTER_HASHES)
+        .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % BLOOM_FILTER_BITS as u64) as usize)
+}
+
+///record stored by add_value_compressed expanded back to its bytes
+///None if the block is malformed or claims more than the N bytes a record may have
+fn decompress_record<const N: usize>(stored: &[u8]) -> Option<Vec<u8>> {
+    let len = u32::from_le_bytes(stored.get(..4)?.try_into().unwrap()) as usize;
+    if len > N {
+        return None;
+    }
+    let mut value = vec![0u8; len];
+    let expanded = lz4_flex::block::decompress_into(&stored[4..], &mut value).ok()?;
+    (expanded == len).then_some(value)
+}
+
+///whether the slot's bytes are occupied whatever kind of record they hold
+fn is_live(in_use: u8) -> bool {
+    matches!(
+        in_use,
+        SLOT_IN_USE_VALID | SLOT_IN_USE_OVERFLOW | SLOT_IN_USE_CONTINUATION
+    )
+}
+
+///longest varint read back enough for any length an Offset can hold
+const VARINT_MAX_BYTES: usize = 5;
+
+///appends n as a little endian base 128 varint
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+///value and byte length of the varint at the start of bytes or None if truncated
+fn read_varint(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut n = 0;
+    for (i, b) in bytes.iter().enumerate().take(VARINT_MAX_BYTES) {
+        n |= ((b & 0x7f) as usize) << (7 * i);
+        if b & 0x80 == 0 {
+            return Some((n, i + 1));
         }
-    
-        self.data[insert_offset..insert_offset + value_len].clone_from_slice(bytes);
-        self.write_slot(slot_id, insert_offset as Offset, value_len as SlotLength, SLOT_IN_USE_VALID);
-        self.set_free_start(insert_offset + value_len);
-    
-        Some(slot_id)
     }
+    None
+}
 
-    ///record bytes for slot_id or None if invalid or deleted
-    fn get_value(&self, slot_id: SlotId) -> Option<Vec<u8>> {
-        if self.get_slot_in_use(slot_id)? != SLOT_IN_USE_VALID {
-            return None;
+///value stored as the length it shares with prev followed by the rest of its bytes
+fn encode_prefixed(value: &[u8], prev: Option<&[u8]>) -> Vec<u8> {
+    let shared = prev.map_or(0, |prev| {
+        prev.iter().zip(value).take_while(|(a, b)| a == b).count()
+    });
+    let mut stored = Vec::with_capacity(value.len() - shared + 1);
+    write_varint(&mut stored, shared);
+    stored.extend_from_slice(&value[shared..]);
+    stored
+}
+
+pub trait HeapPage {
+    fn add_value(&mut self, bytes: &[u8]) -> Option<SlotId>;
+    fn try_add_value(&mut self, bytes: &[u8]) -> Result<SlotId, PageInsertError>;
+    fn add_value_with_slot(&mut self, slot_id: SlotId, bytes: &[u8]) -> Option<()>;
+    fn add_values(&mut self, values: &[&[u8]]) -> Vec<Option<SlotId>>;
+    fn add_value_compressed(&mut self, bytes: &[u8], min_saving: usize) -> Option<SlotId>;
+    fn reserve(&mut self, len: usize) -> Option<Reservation>;
+    fn commit(&mut self, reservation: Reservation, bytes: &[u8]) -> Option<SlotId>;
+    fn abort(&mut self, reservation: Reservation);
+    fn get_value(&self, slot_id: SlotId) -> Option<Vec<u8>>;
+    fn get_value_ref(&self, slot_id: SlotId) -> Option<&[u8]>;
+    fn get_value_after(&self, slot_id: SlotId, prev: Option<&[u8]>) -> Option<Vec<u8>>;
+    fn iter(&self) -> HeapPageIter<'_, Self>;
+    fn delete_value(&mut self, slot_id: SlotId) -> Option<()>;
+    fn update_value(&mut self, slot_id: SlotId, bytes: &[u8]) -> Option<()>;
+    fn get_header_size(&self) -> usize;
+    fn get_free_space(&self) -> usize;
+    fn get_lsn(&self) -> Lsn;
+    fn set_lsn(&mut self, lsn: Lsn);
+    fn get_fill_factor(&self) -> u8;
+    fn set_fill_factor(&mut self, percent: u8) -> Option<()>;
+    fn get_compaction_policy(&self) -> CompactionPolicy;
+    fn set_compaction_policy(&mut self, policy: CompactionPolicy);
+    fn get_max_record_size(&self) -> usize;
+    fn set_max_record_size(&mut self, max: Option<usize>);
+    fn vacuum(&mut self);
+    fn stats(&self) -> PageStats;
+    fn live_bytes(&self) -> usize;
+    fn dead_bytes(&self) -> usize;
+    fn check_integrity(&self) -> Result<(), PageCorruption>;
+    fn get_slot_encoding(&self) -> SlotEncoding;
+    fn set_slot_encoding(&mut self, encoding: SlotEncoding) -> Option<()>;
+    fn get_slot_flags(&self, slot_id: SlotId) -> Option<u8>;
+    fn set_slot_flags(&mut self, slot_id: SlotId, flags: u8) -> Option<()>;
+    fn is_prefix_compressed(&self) -> bool;
+    fn set_prefix_compression(&mut self, enabled: bool) -> Option<()>;
+    fn is_secure_delete(&self) -> bool;
+    fn set_secure_delete(&mut self, enabled: bool);
+    fn set_zone_map(&mut self, enabled: bool) -> Option<()>;
+    fn zone_map(&self) -> Option<ZoneMap>;
+    fn set_bloom_filter(&mut self, enabled: bool) -> Option<()>;
+    fn may_contain(&self, key: &[u8]) -> bool;
+    fn add_value_with_key(&mut self, bytes: &[u8], key: &[u8]) -> Option<SlotId>;
+    fn rebuild_summaries<F>(&mut self, key_of: F)
+    where
+        Self: Sized,
+        F: Fn(&[u8]) -> Vec<u8>;
+    fn vacuum_with_keys<F>(&mut self, key_of: F)
+    where
+        Self: Sized,
+        F: Fn(&[u8]) -> Vec<u8>;
+    fn is_sorted(&self) -> bool;
+    fn set_sorted<F>(&mut self, key_of: F) -> Option<()>
+    where
+        Self: Sized,
+        F: Fn(&[u8]) -> &[u8];
+    fn clear_sorted(&mut self);
+    fn add_value_sorted<F>(&mut self, bytes: &[u8], key_of: F) -> Option<SlotId>
+    where
+        Self: Sized,
+        F: Fn(&[u8]) -> &[u8];
+    fn find_by_key<F>(&self, key: &[u8], extract: F) -> Option<SlotId>
+    where
+        Self: Sized,
+        F: Fn(&[u8]) -> &[u8];
+
+    ///valid records for which pred holds in ascending SlotId order
+    ///pred sees the bytes in place so rejected records are never copied out of the page
+    fn scan_filtered<F>(&self, pred: F) -> HeapPageFilter<'_, Self, F>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        HeapPageFilter {
+            inner: self.iter(),
+            pred,
         }
-        let (offset, length) = self.get_slot_offset_length(slot_id)?;
-        let offset = offset as usize;
-        let length = length as usize;
-        if offset + length > PAGE_SIZE {
-            return None;
+    }
+
+    ///runs a mutation logged at lsn and advances the page lsn to it if the mutation succeeded
+    ///the page lsn never moves backwards so replaying an older record leaves it alone
+    fn logged<T, F>(&mut self, lsn: Lsn, mutation: F) -> Option<T>
+    where
+        Self: Sized,
+        F: FnOnce(&mut Self) -> Option<T>,
+    {
+        let result = mutation(self)?;
+        if lsn > self.get_lsn() {
+            self.set_lsn(lsn);
+        }
+        Some(result)
+    }
+}
+
+impl<const N: usize> HeapPage for SizedPage<N> {
+    ///lsn of the last logged change to this page default if never logged
+    fn get_lsn(&self) -> Lsn {
+        let at = PAGE_META_LSN_OFFSET;
+        Lsn {
+            page_id: PageId::from_le_bytes(self.data[at..at + 2].try_into().unwrap()),
+            slot_id: SlotId::from_le_bytes(self.data[at + 2..at + 4].try_into().unwrap()),
         }
-        Some(self.data[offset..offset + length].to_vec())
     }
 
-    ///marks slot as free or None if out of range or already deleted
-    fn delete_value(&mut self, slot_id: SlotId) -> Option<()> {
-        if (slot_id as usize) >= self.get_num_slots() {
-            return None;
+    fn set_lsn(&mut self, lsn: Lsn) {
+        let at = PAGE_META_LSN_OFFSET;
+        self.data_mut()[at..at + 2].copy_from_slice(&lsn.page_id.to_le_bytes());
+        self.data_mut()[at + 2..at + 4].copy_from_slice(&lsn.slot_id.to_le_bytes());
+    }
+
+    ///percent of the page add_value may fill before refusing inserts
+    fn get_fill_factor(&self) -> u8 {
+        match self.data[PAGE_META_FILL_FACTOR_OFFSET] {
+            0 => DEFAULT_FILL_FACTOR,
+            percent => percent,
         }
-        if self.get_slot_in_use(slot_id)? != SLOT_IN_USE_VALID {
+    }
+
+    ///stores the fill factor in the header so it survives a round trip to disk
+    ///None unless percent is 1 to 100
+    ///updates may still grow records into the space the fill factor holds back
+    fn set_fill_factor(&mut self, percent: u8) -> Option<()> {
+        if percent == 0 || percent > 100 {
             return None;
         }
-        self.set_slot_in_use(slot_id, SLOT_IN_USE_FREE);
+        self.data_mut()[PAGE_META_FILL_FACTOR_OFFSET] = percent;
         Some(())
     }
-}
 
-//private helper methods
-impl Page {
-    ///number of slot entries in the header
-    fn get_num_slots(&self) -> usize {
-        u16::from_le_bytes(
-            self.data[PAGE_META_NUM_SLOTS_OFFSET..PAGE_META_NUM_SLOTS_OFFSET + 2]
-                .try_into()
-                .unwrap(),
-        ) as usize
+    fn get_compaction_policy(&self) -> CompactionPolicy {
+        self.compaction
     }
 
-    ///writes num_slots to the header
-    fn set_num_slots(&mut self, n: usize) {
-        self.data[PAGE_META_NUM_SLOTS_OFFSET..PAGE_META_NUM_SLOTS_OFFSET + 2]
-            .copy_from_slice(&(n as u16).to_le_bytes());
+    fn set_compaction_policy(&mut self, policy: CompactionPolicy) {
+        self.compaction = policy;
     }
 
-    ///first free body byte clamps to body_start if the stored value is stale
-    fn get_free_start(&self) -> usize {
-        let num_slots = self.get_num_slots();
-        let body_start = FIXED_PAGE_META_SIZE + num_slots * BYTES_PER_SLOT_META;
-        let stored = Offset::from_le_bytes(
-            self.data[PAGE_META_FREE_START_OFFSET..PAGE_META_FREE_START_OFFSET + 2]
-                .try_into()
-                .unwrap(),
-        ) as usize;
-        let raw = if stored < body_start { body_start } else { stored };
-        raw.min(PAGE_SIZE)
+    ///largest record add_value update_value and reserve accept
+    ///defaults to the largest record an empty page can hold
+    fn get_max_record_size(&self) -> usize {
+        let largest = N - FIXED_PAGE_META_SIZE - self.slot_meta_size();
+        self.max_record_size.map_or(largest, |max| max.min(largest))
     }
 
-    ///writes free_start to the header clamped to PAGE_SIZE
-    fn set_free_start(&mut self, pos: usize) {
-        let pos = pos.min(PAGE_SIZE);
-        self.data[PAGE_META_FREE_START_OFFSET..PAGE_META_FREE_START_OFFSET + 2]
-            .copy_from_slice(&(pos as Offset).to_le_bytes());
+    ///caps the records the page accepts so larger ones can be routed to overflow storage
+    ///kept in memory only like the compaction policy and None restores the default
+    ///records already on the page are left as they are
+    fn set_max_record_size(&mut self, max: Option<usize>) {
+        self.max_record_size = max;
     }
 
-    ///byte offset of slot_id metadata entry in data
-    fn slot_meta_offset(&self, slot_id: SlotId) -> usize {
-        FIXED_PAGE_META_SIZE + (slot_id as usize) * BYTES_PER_SLOT_META
+    ///moves every live record to the start of the body so all free space is contiguous
+    ///runs whatever the compaction policy
+    fn vacuum(&mut self) {
+        self.truncate_free_tail();
+        self.compact();
     }
 
-    ///offset and length for slot_id or None if out of range
-    fn get_slot_offset_length(&self, slot_id: SlotId) -> Option<(Offset, SlotLength)> {
-        if slot_id as usize >= self.get_num_slots() {
-            return None;
+    ///verifies the header slot directory and record placement agree
+    ///reads only the layout so it is safe on pages loaded without from_bytes checks
+    fn check_integrity(&self) -> Result<(), PageCorruption> {
+        let header_size = self.get_header_size();
+        if header_size > N {
+            return Err(PageCorruption::DirectoryOverflow { header_size });
+        }
+        let body_end = self.slot_directory_start();
+        let free_start = self.stored_free_start();
+        if free_start < FIXED_PAGE_META_SIZE || free_start > body_end {
+            return Err(PageCorruption::FreeStartOutOfRange { free_start });
+        }
+        let mut live = Vec::new();
+        for slot_id in 0..self.get_num_slots() as SlotId {
+            let raw = self.slot_state_byte(slot_id).unwrap_or(SLOT_IN_USE_FREE);
+            if raw & !(SLOT_STATE_MASK | SLOT_FLAGS_MASK) != 0 {
+                return Err(PageCorruption::UnknownSlotState {
+                    slot_id,
+                    in_use: raw,
+                });
+            }
+            if raw & SLOT_STATE_MASK == SLOT_IN_USE_FREE {
+                continue;
+            }
+            let (offset, length) = self.get_slot_offset_length(slot_id).unwrap_or_default();
+            let end = offset as usize + length as usize;
+            if (offset as usize) < FIXED_PAGE_META_SIZE || end > body_end {
+                return Err(PageCorruption::SlotOutOfBounds {
+                    slot_id,
+                    offset,
+                    length,
+                });
+            }
+            if end > free_start {
+                return Err(PageCorruption::RecordPastFreeStart {
+                    slot_id,
+                    end,
+                    free_start,
+                });
+            }
+            live.push((offset as usize, end, slot_id));
+        }
+        live.sort_unstable();
+        match live.windows(2).find(|pair| pair[0].1 > pair[1].0) {
+            Some(pair) => Err(PageCorruption::SlotsOverlap {
+                first: pair[0].2,
+                second: pair[1].2,
+            }),
+            None => Ok(()),
         }
-        let base = self.slot_meta_offset(slot_id);
-        let offset = Offset::from_le_bytes(
-            self.data[base + SLOT_OFFSET_OFFSET..base + SLOT_OFFSET_OFFSET + 2]
-                .try_into()
-                .unwrap(),
-        );
-        let length = SlotLength::from_le_bytes(
-            self.data[base + SLOT_LENGTH_OFFSET..base + SLOT_LENGTH_OFFSET + 2]
-                .try_into()
-                .unwrap(),
-        );
-        Some((offset, length))
     }
 
-    ///in_use flag for slot_id or None if out of range
-    fn get_slot_in_use(&self, slot_id: SlotId) -> Option<u8> {
-        if slot_id as usize >= self.get_num_slots() {
-            return None;
+    ///live and dead slot and byte counts for choosing pages to compact or insert into
+    fn stats(&self) -> PageStats {
+        let num_slots = self.get_num_slots();
+        let live_slots = self.iter_used_slots().count();
+        PageStats {
+            live_slots,
+            dead_slots: num_slots - live_slots,
+            live_bytes: self.live_bytes(),
+            dead_bytes: self.dead_bytes(),
+            largest_free_chunk: self
+                .slot_directory_start()
+                .saturating_sub(self.get_free_start()),
+            header_bytes: self.get_header_size(),
         }
-        let base = self.slot_meta_offset(slot_id);
-        Some(self.data[base + SLOT_IN_USE_OFFSET])
     }
 
-    ///sets in_use for slot_id
-    fn set_slot_in_use(&mut self, slot_id: SlotId, in_use: u8) {
-        let base = self.slot_meta_offset(slot_id);
-        self.data[base + SLOT_IN_USE_OFFSET] = in_use;
+    ///body bytes held by live records counted as slots are written so no scan is needed
+    fn live_bytes(&self) -> usize {
+        self.live_byte_count
     }
 
-    ///writes offset and length and in_use into slot_id metadata
-    fn write_slot(&mut self, slot_id: SlotId, offset: Offset, length: SlotLength, in_use: u8) {
-        let base = self.slot_meta_offset(slot_id);
-        self.data[base..base + 2].copy_from_slice(&offset.to_le_bytes());
-        self.data[base + 2..base + 4].copy_from_slice(&length.to_le_bytes());
-        self.data[base + SLOT_IN_USE_OFFSET] = in_use;
+    ///body bytes below free_start left behind by deleted shrunk or relocated records
+    ///compaction returns them so the pages with the most are the best to compact first
+    fn dead_bytes(&self) -> usize {
+        (self.get_free_start() - FIXED_PAGE_META_SIZE).saturating_sub(self.live_byte_count)
     }
 
-    ///lowest free SlotId or num_slots if all in use
-    fn find_lowest_free_slot_id(&self) -> SlotId {
-        let num_slots = self.get_num_slots();
-        for slot_id in 0..num_slots {
-            if self
-                .get_slot_in_use(slot_id as SlotId)
-                .map_or(true, |u| u == SLOT_IN_USE_FREE)
-            {
-                return slot_id as SlotId;
-            }
+    fn get_slot_encoding(&self) -> SlotEncoding {
+        let flags = self.data[PAGE_META_FLAGS_OFFSET];
+        if flags & PAGE_FLAG_LARGE_SLOTS != 0 {
+            SlotEncoding::Large
+        } else if flags & PAGE_FLAG_COMPACT_SLOTS != 0 {
+            SlotEncoding::Compact
+        } else {
+            SlotEncoding::Wide
         }
-        num_slots as SlotId
     }
 
-    ///slot_id and length for every live slot
-    fn iter_used_slots(&self) -> impl Iterator<Item = (SlotId, SlotLength)> + '_ {
+    ///rewrites every slot entry in the new encoding keeping SlotIds offsets and records
+    ///None if the page is too large for the compact encoding or the wider directory does not fit
+    ///or a slot carries flags the compact encoding has no room for
+    ///the large encoding is fixed by the page size so it can be neither chosen nor left
+    fn set_slot_encoding(&mut self, encoding: SlotEncoding) -> Option<()> {
+        if encoding == self.get_slot_encoding() {
+            return Some(());
+        }
+        if encoding == SlotEncoding::Large || self.get_slot_encoding() == SlotEncoding::Large {
+            return None;
+        }
+        if encoding == SlotEncoding::Compact
+            && (N > MAX_COMPACT_SLOT_PAGE_SIZE
+                || (0..self.get_num_slots() as SlotId).any(|sid| self.slot_flags(sid) != 0))
+        {
+            return None;
+        }
         let num_slots = self.get_num_slots();
-        (0..num_slots).filter_map(move |i| {
-            let sid = i as SlotId;
-            if self
-                .get_slot_in_use(sid)
-                .map_or(false, |u| u == SLOT_IN_USE_VALID)
-            {
-                self.get_slot_offset_length(sid).map(|(_, len)| (sid, len))
-            } else {
-                None
+        let entry_size = match encoding {
+            SlotEncoding::Compact => COMPACT_BYTES_PER_SLOT_META,
+            _ => BYTES_PER_SLOT_META,
+        };
+        let directory_end = N - self.tail_size();
+        let new_start = directory_end.checked_sub(num_slots * entry_size)?;
+        if new_start < self.get_free_start() {
+            self.compact();
+            if new_start < self.get_free_start() {
+                return None;
             }
-        })
-    }
-
-    ///moves all live records to body start and resets free_start
-    fn compact(&mut self) {
-        let num_slots = self.get_num_slots();
-        let body_start = FIXED_PAGE_META_SIZE + num_slots * BYTES_PER_SLOT_META;
+        }
 
-        //sort by offset so copies never overlap
-        let mut used: Vec<(SlotId, usize, usize)> = (0..num_slots)
-            .filter_map(|i| {
-                let sid = i as SlotId;
-                if self
-                    .get_slot_in_use(sid)
-                    .map_or(false, |u| u == SLOT_IN_USE_VALID)
-                {
-                    self.get_slot_offset_length(sid)
-                        .map(|(off, len)| (sid, off as usize, len as usize))
-                } else {
-                    None
-                }
+        let entries: Vec<(Offset, SlotLength, u8)> = (0..num_slots as SlotId)
+            .map(|sid| {
+                let (offset, length) = self.get_slot_offset_length(sid).unwrap();
+                (offset, length, self.get_slot_in_use(sid).unwrap())
             })
             .collect();
-        used.sort_by_key(|&(_, off, _)| off);
-
-        let mut write_pos = body_start;
-        for (slot_id, old_offset, length) in used {
-            if old_offset != write_pos {
-                self.data.copy_within(old_offset..old_offset + length, write_pos);
-            }
-            self.write_slot(slot_id, write_pos as Offset, length as SlotLength, SLOT_IN_USE_VALID);
-            write_pos += length;
+        let old_start = self.slot_directory_start();
+        self.data_mut()[PAGE_META_FLAGS_OFFSET] ^= PAGE_FLAG_COMPACT_SLOTS;
+        self.data_mut()[old_start.min(new_start)..directory_end].fill(0);
+        //every entry now reads as free so write_slot counts each record once
+        self.live_byte_count = 0;
+        for (sid, (offset, length, in_use)) in entries.into_iter().enumerate() {
+            self.write_slot(sid as SlotId, offset, length, in_use);
         }
-        self.set_free_start(write_pos);
+        Some(())
     }
 
-    ///shifts body right by BYTES_PER_SLOT_META for a new slot entry
-    ///bumps all existing slot offsets to match
-    fn shift_body_for_new_slot(&mut self) {
-        let num_slots = self.get_num_slots();
-        let old_body_start = FIXED_PAGE_META_SIZE + num_slots * BYTES_PER_SLOT_META;
-        let new_body_start = old_body_start + BYTES_PER_SLOT_META;
-
-        let free_start = self.get_free_start();
-        let body_len = free_start.saturating_sub(old_body_start);
+    ///SLOT_FLAG bits of a live slot or None if the slot is not live
+    fn get_slot_flags(&self, slot_id: SlotId) -> Option<u8> {
+        is_live(self.get_slot_in_use(slot_id)?).then(|| self.slot_flags(slot_id))
+    }
 
-        if body_len > 0 {
-            let shift_len = body_len.min(PAGE_SIZE - new_body_start);
-            self.data
-                .copy_within(old_body_start..old_body_start + shift_len, new_body_start);
+    ///replaces the SLOT_FLAG bits of a live slot which move with the record and clear on delete
+    ///None if the slot is not live flags has bits outside SLOT_FLAGS_MASK
+    ///flags would change SLOT_FLAG_COMPRESSED which only the page sets to describe the bytes
+    ///or the compact slot encoding leaves no room for them
+    fn set_slot_flags(&mut self, slot_id: SlotId, flags: u8) -> Option<()> {
+        if !is_live(self.get_slot_in_use(slot_id)?)
+            || flags & !SLOT_FLAGS_MASK != 0
+            || (flags ^ self.slot_flags(slot_id)) & SLOT_FLAG_COMPRESSED != 0
+        {
+            return None;
+        }
+        if self.get_slot_encoding() == SlotEncoding::Compact && flags != 0 {
+            return None;
         }
+        self.write_slot_flags(slot_id, flags);
+        Some(())
+    }
 
-        //zero stale bytes now occupied by the new slot entry
-        self.data[old_body_start..new_body_start].fill(0);
+    fn is_prefix_compressed(&self) -> bool {
+        self.data[PAGE_META_FLAGS_OFFSET] & PAGE_FLAG_PREFIX_COMPRESSED != 0
+    }
 
-        for slot_id in 0..num_slots {
-            let sid = slot_id as SlotId;
-            if self
-                .get_slot_in_use(sid)
-                .map_or(false, |u| u == SLOT_IN_USE_VALID)
-            {
-                if let Some((off, len)) = self.get_slot_offset_length(sid) {
-                    self.write_slot(
-                        sid,
-                        (off as usize + BYTES_PER_SLOT_META) as Offset,
-                        len,
-                        SLOT_IN_USE_VALID,
-                    );
-                }
+    ///re-encodes every valid record for the new mode into a compacted body keeping SlotIds
+    ///None if the page is sorted or does not hold the records uncompressed
+    ///or holds records compressed on their own
+    fn set_prefix_compression(&mut self, enabled: bool) -> Option<()> {
+        if enabled == self.is_prefix_compressed() {
+            return Some(());
+        }
+        //shifting slot entries in sorted mode would change which record each is encoded against
+        if self.is_sorted() || self.has_compressed_records() {
+            return None;
+        }
+        //decode everything under the old mode before the body is overwritten
+        let mut prev: Option<Vec<u8>> = None;
+        let mut records = Vec::new();
+        for slot_id in 0..self.get_num_slots() as SlotId {
+            let in_use = self.get_slot_in_use(slot_id)?;
+            if in_use == SLOT_IN_USE_VALID {
+                let value = self.decode_value(slot_id)?;
+                let stored = if enabled {
+                    encode_prefixed(&value, prev.as_deref())
+                } else {
+                    value.clone()
+                };
+                prev = Some(value);
+                records.push((slot_id, in_use, stored));
+            } else if is_live(in_use) {
+                records.push((slot_id, in_use, self.slot_bytes(slot_id)?.to_vec()));
             }
         }
+        let body_len: usize = records.iter().map(|(_, _, stored)| stored.len()).sum();
+        if FIXED_PAGE_META_SIZE + body_len > self.slot_directory_start() {
+            return None;
+        }
 
-        let new_free = (free_start + BYTES_PER_SLOT_META).min(PAGE_SIZE);
-        self.set_free_start(new_free);
+        let mut offset = FIXED_PAGE_META_SIZE;
+        for (slot_id, in_use, stored) in records {
+            self.data_mut()[offset..offset + stored.len()].copy_from_slice(&stored);
+            self.write_slot(
+                slot_id,
+                offset as Offset,
+                stored.len() as SlotLength,
+                in_use,
+            );
+            offset += stored.len();
+        }
+        self.set_free_start(offset);
+        self.data_mut()[PAGE_META_FLAGS_OFFSET] ^= PAGE_FLAG_PREFIX_COMPRESSED;
+        self.scrub_free_body();
+        Some(())
     }
-}
 
-///consuming iterator over valid records in ascending SlotId order
-pub struct HeapPageIntoIter {
-    page: Page,
-    current_slot: SlotId,
-    num_slots: usize,
-}
+    fn is_secure_delete(&self) -> bool {
+        self.data[PAGE_META_FLAGS_OFFSET] & PAGE_FLAG_SECURE_DELETE != 0
+    }
 
-impl Iterator for HeapPageIntoIter {
-    type Item = (Vec<u8>, SlotId);
+    ///zeroes record bytes as they are deleted updated or moved by compaction from now on
+    ///enabling compacts the page so bytes left by earlier deletes are zeroed too
+    fn set_secure_delete(&mut self, enabled: bool) {
+        if enabled {
+            self.data_mut()[PAGE_META_FLAGS_OFFSET] |= PAGE_FLAG_SECURE_DELETE;
+            self.compact();
+        } else {
+            self.data_mut()[PAGE_META_FLAGS_OFFSET] &= !PAGE_FLAG_SECURE_DELETE;
+        }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        while (self.current_slot as usize) < self.num_slots {
-            let slot_id = self.current_slot;
-            self.current_slot += 1;
-            if let Some(value) = self.page.get_value(slot_id) {
-                return Some((value, slot_id));
-            }
+    ///reserves or releases the zone map region moving the slot directory
+    ///a page that already holds records starts with a stale zone map until rebuild_summaries
+    ///None if there is no room for the region
+    fn set_zone_map(&mut self, enabled: bool) -> Option<()> {
+        if self.toggle_summary(PAGE_FLAG_ZONE_MAP, enabled)? && enabled {
+            let state = if self.iter_used_slots().next().is_some() {
+                ZONE_MAP_STALE
+            } else {
+                ZONE_MAP_EMPTY
+            };
+            self.write_zone_map(state, &ZoneMap::default());
         }
-        None
+        Some(())
     }
-}
 
-///creates a consuming iterator from a page
-impl IntoIterator for Page {
-    type Item = (Vec<u8>, SlotId);
-    type IntoIter = HeapPageIntoIter;
+    ///bounds of the keys on this page or None if there is no zone map or it is stale
+    fn zone_map(&self) -> Option<ZoneMap> {
+        let at = self.zone_map_offset()?;
+        if self.data[at] == ZONE_MAP_STALE {
+            return None;
+        }
+        let key = |len: u8, start: usize| {
+            let len = (len as usize).min(ZONE_MAP_KEY_SIZE);
+            self.data[start..start + len].to_vec()
+        };
+        let keys_at = at + 4;
+        Some(ZoneMap {
+            min: (self.data[at] == ZONE_MAP_VALID).then(|| key(self.data[at + 1], keys_at)),
+            max: (self.data[at] == ZONE_MAP_VALID)
+                .then(|| key(self.data[at + 2], keys_at + ZONE_MAP_KEY_SIZE)),
+            max_truncated: self.data[at + 3] != 0,
+        })
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        let num_slots = self.get_num_slots();
-        HeapPageIntoIter {
-            page: self,
-            current_slot: 0,
-            num_slots,
+    ///reserves or releases the bloom filter region moving the slot directory
+    ///a page that already holds records starts with a stale filter until rebuild_summaries
+    ///None if there is no room for the region
+    fn set_bloom_filter(&mut self, enabled: bool) -> Option<()> {
+        if self.toggle_summary(PAGE_FLAG_BLOOM_FILTER, enabled)? && enabled {
+            let at = self.bloom_filter_offset()?;
+            self.data_mut()[at..at + BLOOM_FILTER_REGION_SIZE].fill(0);
+            self.data_mut()[at] = if self.iter_used_slots().next().is_some() {
+                BLOOM_FILTER_STALE
+            } else {
+                BLOOM_FILTER_VALID
+            };
         }
+        Some(())
     }
-}
 
-#[cfg(test)]
-mod tests {
+    ///false only if no record was added with key since the filter was last rebuilt
+    ///true without a filter or while it is stale and for deleted keys until rebuild_summaries
+    fn may_contain(&self, key: &[u8]) -> bool {
+        let Some(at) = self.bloom_filter_offset() else {
+            return true;
+        };
+        if self.data[at] != BLOOM_FILTER_VALID {
+            return true;
+        }
+        let bits = &self.data[at + 1..at + BLOOM_FILTER_REGION_SIZE];
+        bloom_positions(key).all(|bit| bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    ///inserts bytes like add_value and records its sort key in the zone map and bloom filter
+    fn add_value_with_key(&mut self, bytes: &[u8], key: &[u8]) -> Option<SlotId> {
+        let zone = self.zone_map();
+        let bloom_valid = self
+            .bloom_filter_offset()
+            .is_some_and(|at| self.data[at] == BLOOM_FILTER_VALID);
+        let slot_id = self.add_value(bytes)?;
+        if let Some(mut zone) = zone {
+            zone.widen(key);
+            self.write_zone_map(ZONE_MAP_VALID, &zone);
+        }
+        if let Some(at) = self.bloom_filter_offset().filter(|_| bloom_valid) {
+            self.data_mut()[at] = BLOOM_FILTER_VALID;
+            self.add_bloom_key(key);
+        }
+        Some(slot_id)
+    }
+
+    ///recomputes the zone map and bloom filter from the key of every valid record
+    ///overflow records are not covered so a page holding them keeps stale summaries
+    fn rebuild_summaries<F>(&mut self, key_of: F)
+    where
+        F: Fn(&[u8]) -> Vec<u8>,
+    {
+        if self.tail_size() == 0 {
+            return;
+        }
+        let has_overflow = (0..self.get_num_slots() as SlotId)
+            .any(|sid| self.get_slot_in_use(sid) == Some(SLOT_IN_USE_OVERFLOW));
+        if has_overflow {
+            return self.invalidate_summaries();
+        }
+        let keys: Vec<Vec<u8>> = self.iter().map(|(_, value)| key_of(&value)).collect();
+        let mut zone = ZoneMap::default();
+        for key in &keys {
+            zone.widen(key);
+        }
+        let state = if zone.min.is_some() {
+            ZONE_MAP_VALID
+        } else {
+            ZONE_MAP_EMPTY
+        };
+        self.write_zone_map(state, &zone);
+        if let Some(at) = self.bloom_filter_offset() {
+            self.data_mut()[at..at + BLOOM_FILTER_REGION_SIZE].fill(0);
+            self.data_mut()[at] = BLOOM_FILTER_VALID;
+            for key in &keys {
+                self.add_bloom_key(key);
+            }
+        }
+    }
+
+    ///vacuums then rebuilds the summaries so deleted keys stop matching the bloom filter
+    fn vacuum_with_keys<F>(&mut self, key_of: F)
+    where
+        F: Fn(&[u8]) -> Vec<u8>,
+    {
+        self.vacuum();
+        self.rebuild_summaries(key_of);
+    }
+
+    fn is_sorted(&self) -> bool {
+        self.data[PAGE_META_FLAGS_OFFSET] & PAGE_FLAG_SORTED != 0
+    }
+
+    ///orders the slot directory by key_of and keeps it ordered from now on
+    ///valid records are renumbered from 0 in key order with equal keys in SlotId order
+    ///and free entries are dropped so slot ids are positions while the page stays sorted
+    ///None if the page is prefix compressed or holds overflow slots or compressed records
+    fn set_sorted<F>(&mut self, key_of: F) -> Option<()>
+    where
+        F: Fn(&[u8]) -> &[u8],
+    {
+        if self.is_prefix_compressed() || self.has_compressed_records() {
+            return None;
+        }
+        let mut entries = Vec::new();
+        for slot_id in 0..self.get_num_slots() as SlotId {
+            match self.get_slot_in_use(slot_id)? {
+                SLOT_IN_USE_FREE => {}
+                SLOT_IN_USE_VALID => entries.push((
+                    self.get_slot_offset_length(slot_id)?,
+                    self.slot_flags(slot_id),
+                )),
+                _ => return None,
+            }
+        }
+        let record = |&(offset, length): &(Offset, SlotLength)| {
+            &self.data[offset as usize..(offset + length) as usize]
+        };
+        entries.sort_by(|(a, _), (b, _)| key_of(record(a)).cmp(key_of(record(b))));
+
+        let old_start = self.slot_directory_start();
+        self.set_num_slots(entries.len());
+        let new_start = self.slot_directory_start();
+        self.data_mut()[old_start..new_start].fill(0);
+        for (slot_id, ((offset, length), flags)) in entries.into_iter().enumerate() {
+            self.write_slot(slot_id as SlotId, offset, length, SLOT_IN_USE_VALID);
+            self.write_slot_flags(slot_id as SlotId, flags);
+        }
+        self.data_mut()[PAGE_META_FLAGS_OFFSET] |= PAGE_FLAG_SORTED;
+        Some(())
+    }
+
+    ///leaves sorted mode keeping the current slot order
+    fn clear_sorted(&mut self) {
+        self.data_mut()[PAGE_META_FLAGS_OFFSET] &= !PAGE_FLAG_SORTED;
+    }
+
+    ///inserts bytes after every record whose key is not greater and shifts the later slot entries up
+    ///returns the record's position which is its SlotId until the next sorted insert or delete
+    ///None if the page is not sorted lacks space or the record is over the max record size
+    fn add_value_sorted<F>(&mut self, bytes: &[u8], key_of: F) -> Option<SlotId>
+    where
+        F: Fn(&[u8]) -> &[u8],
+    {
+        if !self.is_sorted() || bytes.len() > self.get_max_record_size() {
+            return None;
+        }
+        let key = key_of(bytes);
+        let position = self.sorted_partition_point(|record| key_of(record) <= key);
+        //a sorted page has no free entries so the record lands at the end of the directory
+        let slot_id = self.insert_slot(bytes, SLOT_IN_USE_VALID)?;
+        self.move_slot_entry(slot_id as usize, position);
+        Some(position as SlotId)
+    }
+
+    ///lowest SlotId whose record has key under extract found by binary search over the slot directory
+    ///extract must be the key_of the page was sorted with
+    ///None if no record has the key or the page is not sorted
+    fn find_by_key<F>(&self, key: &[u8], extract: F) -> Option<SlotId>
+    where
+        F: Fn(&[u8]) -> &[u8],
+    {
+        if !self.is_sorted() {
+            return None;
+        }
+        let position = self.sorted_partition_point(|record| extract(record) < key) as SlotId;
+        let record = self.slot_bytes(position)?;
+        (extract(record) == key).then_some(position)
+    }
+
+    ///total metadata size the fixed header plus every slot entry and summary region at the page tail
+    fn get_header_size(&self) -> usize {
+        FIXED_PAGE_META_SIZE + self.tail_size() + self.get_num_slots() * self.slot_meta_size()
+    }
+
+    ///free bytes remaining not counting those held by reservations
+    fn get_free_space(&self) -> usize {
+        let header_size = self.get_header_size();
+        N.saturating_sub(header_size)
+            .saturating_sub(self.live_bytes())
+            .saturating_sub(self.reserved_bytes())
+    }
+
+    ///inserts bytes and returns the assigned SlotId or None if no space below the fill factor
+    ///or the record is over the max record size
+    ///always reuses the lowest free SlotId
+    ///sorted pages only take records through add_value_sorted
+    fn add_value(&mut self, bytes: &[u8]) -> Option<SlotId> {
+        self.try_add_value(bytes).ok()
+    }
+
+    ///add_value saying why a record could not be stored
+    fn try_add_value(&mut self, bytes: &[u8]) -> Result<SlotId, PageInsertError> {
+        if self.is_sorted() {
+            return Err(PageInsertError::SortedPage);
+        }
+        let max = self.get_max_record_size();
+        if bytes.len() > max {
+            return Err(PageInsertError::RecordTooLarge {
+                len: bytes.len(),
+                max,
+            });
+        }
+        let slot_id = self.find_lowest_free_slot_id();
+        match self.add_value_with_slot(slot_id, bytes) {
+            Some(()) => Ok(slot_id),
+            None => Err(self.insert_error(slot_id, bytes.len())),
+        }
+    }
+
+    ///inserts bytes at slot_id growing the directory with free slots up to it so recovery can keep ValueIds stable
+    ///None if the slot is in use or reserved the page is sorted or there is no space below the fill factor
+    ///or the record is over the max record size
+    ///under prefix compression the next valid record is re-encoded against the new one
+    fn add_value_with_slot(&mut self, slot_id: SlotId, bytes: &[u8]) -> Option<()> {
+        if self.is_sorted()
+            || bytes.len() > self.get_max_record_size()
+            || self.is_reserved(slot_id)
+            || self
+                .get_slot_in_use(slot_id)
+                .is_some_and(|in_use| in_use != SLOT_IN_USE_FREE)
+        {
+            return None;
+        }
+        if !self.is_prefix_compressed() {
+            return self.insert_slot_at(slot_id, bytes, SLOT_IN_USE_VALID);
+        }
+        let prev = self
+            .prev_valid_slot(slot_id)
+            .and_then(|s| self.decode_value(s));
+        let next = self
+            .next_valid_slot(slot_id)
+            .and_then(|s| Some((s, self.decode_value(s)?)));
+        self.insert_slot_at(
+            slot_id,
+            &encode_prefixed(bytes, prev.as_deref()),
+            SLOT_IN_USE_VALID,
+        )?;
+        if let Some((next_id, next_value)) = next {
+            let restored = encode_prefixed(&next_value, Some(bytes));
+            if self.replace_slot_bytes(next_id, &restored).is_none() {
+                self.set_slot_in_use(slot_id, SLOT_IN_USE_FREE);
+                self.truncate_free_tail();
+                return None;
+            }
+        }
+        Some(())
+    }
+
+    ///inserts bytes lz4 compressed and flagged SLOT_FLAG_COMPRESSED when that saves more than
+    ///min_saving bytes otherwise plainly as add_value does
+    ///get_value and iter decompress the record while get_value_ref cannot borrow it
+    ///records are kept plain on prefix compressed pages and under the compact slot encoding
+    ///which have no way to mark them and records longer than the page are refused
+    ///the max record size caps the bytes as stored
+    fn add_value_compressed(&mut self, bytes: &[u8], min_saving: usize) -> Option<SlotId> {
+        if bytes.len() > N
+            || self.is_sorted()
+            || self.is_prefix_compressed()
+            || self.get_slot_encoding() == SlotEncoding::Compact
+        {
+            return self.add_value(bytes);
+        }
+        let compressed = lz4_flex::compress_prepend_size(bytes);
+        if bytes.len().saturating_sub(compressed.len()) <= min_saving {
+            return self.add_value(bytes);
+        }
+        if compressed.len() > self.get_max_record_size() {
+            return None;
+        }
+        let slot_id = self.find_lowest_free_slot_id();
+        self.insert_slot_at(slot_id, &compressed, SLOT_IN_USE_VALID)?;
+        self.write_slot_flags(slot_id, SLOT_FLAG_COMPRESSED);
+        Some(slot_id)
+    }
+
+    ///first half of a two phase insert holding len bytes below the fill factor and the lowest free SlotId
+    ///so the record can be logged before it is stored while nothing reaches the page bytes
+    ///None if there is no space len is over the max record size or the page is sorted or prefix compressed
+    ///where a record's SlotId or stored size depends on its neighbours
+    fn reserve(&mut self, len: usize) -> Option<Reservation> {
+        if self.is_sorted() || self.is_prefix_compressed() || len > self.get_max_record_size() {
+            return None;
+        }
+        let slot_id = self.find_lowest_free_slot_id();
+        //a SlotId past the directory end needs the entries up to it as well
+        let new_entries = (slot_id as usize + 1).saturating_sub(self.get_num_slots());
+        let held = len + new_entries * self.slot_meta_size();
+        if self.insert_budget() < held {
+            return None;
+        }
+        self.reservations.push((slot_id, held));
+        Some(Reservation {
+            slot_id,
+            capacity: len,
+        })
+    }
+
+    ///stores bytes under the reserved SlotId releasing the reservation
+    ///None if this page does not hold the reservation or bytes are longer than it
+    ///in which case the reservation is released all the same
+    fn commit(&mut self, reservation: Reservation, bytes: &[u8]) -> Option<SlotId> {
+        self.release(&reservation)?;
+        if bytes.len() > reservation.capacity {
+            return None;
+        }
+        self.add_value_with_slot(reservation.slot_id, bytes)?;
+        Some(reservation.slot_id)
+    }
+
+    ///releases the space and SlotId of a reservation leaving the page bytes as they were
+    fn abort(&mut self, reservation: Reservation) {
+        self.release(&reservation);
+    }
+
+    ///inserts values in order with the same per-record results as repeated add_value
+    ///grows the slot directory once and compacts at most once for the whole batch
+    ///unless the page is prefix compressed or holds reservations where each value is added on its own
+    fn add_values(&mut self, values: &[&[u8]]) -> Vec<Option<SlotId>> {
+        if self.is_prefix_compressed() || self.is_sorted() || !self.reservations.is_empty() {
+            return values.iter().map(|value| self.add_value(value)).collect();
+        }
+        let num_slots = self.get_num_slots();
+        let mut free_slots = (0..num_slots as SlotId)
+            .filter(|&sid| self.get_slot_in_use(sid) == Some(SLOT_IN_USE_FREE))
+            .peekable();
+        let mut available = self.insert_budget();
+        if self.compaction == CompactionPolicy::Never {
+            available = available.min(self.slot_directory_start() - self.get_free_start());
+        }
+        let entry_size = self.slot_meta_size();
+        let max = self.get_max_record_size();
+        let mut new_slots = 0;
+        let mut total_len = 0;
+        let mut results = Vec::with_capacity(values.len());
+        for value in values {
+            let reuse = free_slots.peek().is_some();
+            let cost = value.len() + if reuse { 0 } else { entry_size };
+            if cost > available || value.len() > max {
+                results.push(None);
+                continue;
+            }
+            available -= cost;
+            total_len += value.len();
+            results.push(Some(match free_slots.next() {
+                Some(sid) => sid,
+                None => {
+                    new_slots += 1;
+                    (num_slots + new_slots - 1) as SlotId
+                }
+            }));
+        }
+
+        let extra_header = new_slots * entry_size;
+        if self.slot_directory_start() - self.get_free_start() < extra_header + total_len {
+            self.compact();
+        }
+        if new_slots > 0 {
+            let old_start = self.slot_directory_start();
+            self.set_num_slots(num_slots + new_slots);
+            let new_start = self.slot_directory_start();
+            self.data_mut()[new_start..old_start].fill(0);
+        }
+
+        let mut offset = self.get_free_start();
+        for (value, slot_id) in values.iter().zip(&results) {
+            if let Some(slot_id) = *slot_id {
+                self.data_mut()[offset..offset + value.len()].clone_from_slice(value);
+                self.write_slot(
+                    slot_id,
+                    offset as Offset,
+                    value.len() as SlotLength,
+                    SLOT_IN_USE_VALID,
+                );
+                offset += value.len();
+            }
+        }
+        self.set_free_start(offset);
+        if results.iter().any(Option::is_some) {
+            self.invalidate_summaries();
+        }
+        results
+    }
+
+    ///record bytes for slot_id or None if invalid or deleted
+    fn get_value(&self, slot_id: SlotId) -> Option<Vec<u8>> {
+        self.decode_value(slot_id)
+    }
+
+    ///borrowing iterator over valid records so a scan does not need to copy the page
+    ///record of slot_id given prev the record of the closest valid slot below it
+    ///so a scan in slot order rebuilds each prefix compressed record once
+    ///rather than walking back its chain of shared prefixes
+    fn get_value_after(&self, slot_id: SlotId, prev: Option<&[u8]>) -> Option<Vec<u8>> {
+        if !self.is_prefix_compressed()
+            || self.get_slot_in_use(slot_id)? != SLOT_IN_USE_VALID
+            || self.slot_flags(slot_id) & SLOT_FLAG_COMPRESSED != 0
+        {
+            return self.decode_value(slot_id);
+        }
+        let stored = self.slot_bytes(slot_id)?;
+        let (shared, header) = read_varint(stored)?;
+        let prefix = prev.unwrap_or_default().get(..shared)?;
+        let mut value = Vec::with_capacity(shared + stored.len() - header);
+        value.extend_from_slice(prefix);
+        value.extend_from_slice(&stored[header..]);
+        Some(value)
+    }
+
+    fn iter(&self) -> HeapPageIter<'_, Self> {
+        HeapPageIter {
+            page: self,
+            current_slot: 0,
+            num_slots: self.get_num_slots(),
+            prev: None,
+            prefixed: self.is_prefix_compressed(),
+        }
+    }
+
+    ///borrowed record bytes for slot_id without copying them out of the page
+    ///None for a record sharing a prefix with its predecessor or compressed on its own
+    ///which only get_value can rebuild
+    fn get_value_ref(&self, slot_id: SlotId) -> Option<&[u8]> {
+        if self.get_slot_in_use(slot_id)? != SLOT_IN_USE_VALID
+            || self.slot_flags(slot_id) & SLOT_FLAG_COMPRESSED != 0
+        {
+            return None;
+        }
+        let stored = self.slot_bytes(slot_id)?;
+        if !self.is_prefix_compressed() {
+            return Some(stored);
+        }
+        match read_varint(stored)? {
+            (0, header) => Some(&stored[header..]),
+            _ => None,
+        }
+    }
+
+    ///marks slot as free or None if out of range or already deleted
+    ///drops trailing free slot entries so their header bytes become free space
+    ///on a sorted page the later records shift down to close the gap
+    ///for an overflow pointer only the pointer is freed not its continuation pages
+    ///a continuation chunk is freed like a record so its page can hold records again
+    fn delete_value(&mut self, slot_id: SlotId) -> Option<()> {
+        if (slot_id as usize) >= self.get_num_slots() {
+            return None;
+        }
+        let in_use = self.get_slot_in_use(slot_id)?;
+        if in_use == SLOT_IN_USE_FREE {
+            return None;
+        }
+        let next = if in_use == SLOT_IN_USE_VALID && self.is_prefix_compressed() {
+            self.next_valid_slot(slot_id)
+                .and_then(|s| Some((s, self.decode_value(s)?)))
+        } else {
+            None
+        };
+        //zeroed before the successor moves so a compaction cannot put live bytes where they were
+        let (offset, length) = self.get_slot_offset_length(slot_id)?;
+        let record = offset as usize..(offset + length) as usize;
+        let scrubbed = self
+            .is_secure_delete()
+            .then(|| self.data[record.clone()].to_vec());
+        self.set_slot_in_use(slot_id, SLOT_IN_USE_FREE);
+        if scrubbed.is_some() {
+            self.data_mut()[record.clone()].fill(0);
+        }
+        if let Some((next_id, next_value)) = next {
+            let prev = self
+                .prev_valid_slot(slot_id)
+                .and_then(|s| self.decode_value(s));
+            let stored = encode_prefixed(&next_value, prev.as_deref());
+            //replace_slot_bytes fails before touching the body so the record is still in place
+            if self.replace_slot_bytes(next_id, &stored).is_none() {
+                if let Some(bytes) = scrubbed {
+                    self.data_mut()[record].copy_from_slice(&bytes);
+                }
+                self.set_slot_in_use(slot_id, in_use);
+                return None;
+            }
+        }
+        self.write_slot_flags(slot_id, 0);
+        if self.is_sorted() {
+            //later records move down one position so the free entry is the last one
+            self.move_slot_entry(slot_id as usize, self.get_num_slots() - 1);
+        }
+        self.truncate_free_tail();
+        self.compact_if_aggressive();
+        Some(())
+    }
+
+    ///replaces the record in slot_id keeping its SlotId or None if the slot is not live the page lacks space
+    ///or the record is over the max record size
+    ///rewrites in place when the record does not grow otherwise relocates it within the page compacting if needed
+    ///on a sorted page the new record must keep the old key or the order is lost
+    ///a compressed record is replaced by the new bytes stored plain
+    fn update_value(&mut self, slot_id: SlotId, bytes: &[u8]) -> Option<()> {
+        if self.get_slot_in_use(slot_id)? != SLOT_IN_USE_VALID
+            || bytes.len() > self.get_max_record_size()
+        {
+            return None;
+        }
+        if !self.is_prefix_compressed() {
+            self.replace_slot_bytes(slot_id, bytes)?;
+            let flags = self.slot_flags(slot_id);
+            self.write_slot_flags(slot_id, flags & !SLOT_FLAG_COMPRESSED);
+            self.invalidate_summaries();
+            return Some(());
+        }
+        let old_stored = self.slot_bytes(slot_id)?.to_vec();
+        let prev = self
+            .prev_valid_slot(slot_id)
+            .and_then(|s| self.decode_value(s));
+        let next = self
+            .next_valid_slot(slot_id)
+            .and_then(|s| Some((s, self.decode_value(s)?)));
+        self.replace_slot_bytes(slot_id, &encode_prefixed(bytes, prev.as_deref()))?;
+        if let Some((next_id, next_value)) = next {
+            if self
+                .replace_slot_bytes(next_id, &encode_prefixed(&next_value, Some(bytes)))
+                .is_none()
+            {
+                //the old record fitted before so it fits again once the body is compacted
+                self.compact();
+                self.replace_slot_bytes(slot_id, &old_stored);
+                return None;
+            }
+        }
+        self.invalidate_summaries();
+        Some(())
+    }
+}
+
+//private helper methods
+impl<const N: usize> SizedPage<N> {
+    ///record of a valid slot rebuilt from the chain of shared prefixes when prefix compressed
+    ///None if the slot is not valid or the chain is corrupt
+    pub(crate) fn decode_value(&self, slot_id: SlotId) -> Option<Vec<u8>> {
+        if self.get_slot_in_use(slot_id)? != SLOT_IN_USE_VALID {
+            return None;
+        }
+        if self.slot_flags(slot_id) & SLOT_FLAG_COMPRESSED != 0 {
+            return decompress_record::<N>(self.slot_bytes(slot_id)?);
+        }
+        if !self.is_prefix_compressed() {
+            return self.slot_bytes(slot_id).map(<[u8]>::to_vec);
+        }
+        //walk back to a record that shares nothing then replay the suffixes forward
+        let mut chain = Vec::new();
+        let mut current = slot_id;
+        loop {
+            let stored = self.slot_bytes(current)?;
+            let (shared, header) = read_varint(stored)?;
+            chain.push((shared, &stored[header..]));
+            if shared == 0 {
+                break;
+            }
+            current = self.prev_valid_slot(current)?;
+        }
+        let mut value = Vec::new();
+        for (shared, suffix) in chain.into_iter().rev() {
+            if shared > value.len() {
+                return None;
+            }
+            value.truncate(shared);
+            value.extend_from_slice(suffix);
+        }
+        Some(value)
+    }
+
+    ///true if any slot holds a record compressed by add_value_compressed
+    fn has_compressed_records(&self) -> bool {
+        (0..self.get_num_slots() as SlotId)
+            .any(|sid| self.slot_flags(sid) & SLOT_FLAG_COMPRESSED != 0)
+    }
+
+    ///first slot of a sorted page whose record fails pred where pred holds for a prefix of the slots
+    fn sorted_partition_point<P>(&self, pred: P) -> usize
+    where
+        P: Fn(&[u8]) -> bool,
+    {
+        let (mut low, mut high) = (0, self.get_num_slots());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.slot_bytes(mid as SlotId).is_some_and(&pred) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    ///moves the slot entry at from to position to shifting the entries between by one
+    fn move_slot_entry(&mut self, from: usize, to: usize) {
+        let (low, high) = (from.min(to), from.max(to));
+        let mut entries: Vec<(Offset, SlotLength, u8, u8)> = (low..=high)
+            .map(|i| {
+                let sid = i as SlotId;
+                let (offset, length) = self.get_slot_offset_length(sid).unwrap_or_default();
+                let in_use = self.get_slot_in_use(sid).unwrap_or(SLOT_IN_USE_FREE);
+                (offset, length, in_use, self.slot_flags(sid))
+            })
+            .collect();
+        let entry = entries.remove(from - low);
+        entries.insert(to - low, entry);
+        for (i, (offset, length, in_use, flags)) in entries.into_iter().enumerate() {
+            self.write_slot((low + i) as SlotId, offset, length, in_use);
+            self.write_slot_flags((low + i) as SlotId, flags);
+        }
+    }
+
+    ///closest valid slot below slot_id the record a prefix compressed slot_id is encoded against
+    fn prev_valid_slot(&self, slot_id: SlotId) -> Option<SlotId> {
+        (0..slot_id)
+            .rev()
+            .find(|&sid| self.get_slot_in_use(sid) == Some(SLOT_IN_USE_VALID))
+    }
+
+    ///closest valid slot above slot_id
+    fn next_valid_slot(&self, slot_id: SlotId) -> Option<SlotId> {
+        (slot_id as usize + 1..self.get_num_slots())
+            .map(|sid| sid as SlotId)
+            .find(|&sid| self.get_slot_in_use(sid) == Some(SLOT_IN_USE_VALID))
+    }
+
+    ///replaces the stored bytes of a live slot keeping its SlotId and in_use flag
+    ///None if the page lacks space
+    ///rewrites in place when the record does not grow otherwise relocates it within the page compacting if needed
+    fn replace_slot_bytes(&mut self, slot_id: SlotId, bytes: &[u8]) -> Option<()> {
+        let in_use = self.get_slot_in_use(slot_id)?;
+        let (offset, old_len) = self.get_slot_offset_length(slot_id)?;
+        let offset = offset as usize;
+        let old_len = old_len as usize;
+        let new_len = bytes.len();
+
+        //shrinking records and the last record in the body can grow where they are
+        let at_body_end = offset + old_len == self.get_free_start();
+        if new_len <= old_len || (at_body_end && offset + new_len <= self.slot_directory_start()) {
+            self.data_mut()[offset..offset + new_len].clone_from_slice(bytes);
+            if self.is_secure_delete() && new_len < old_len {
+                self.data_mut()[offset + new_len..offset + old_len].fill(0);
+            }
+            self.write_slot(slot_id, offset as Offset, new_len as SlotLength, in_use);
+            if at_body_end {
+                self.set_free_start(offset + new_len);
+            }
+            self.compact_if_aggressive();
+            return Some(());
+        }
+
+        if self.get_free_space() + old_len < new_len {
+            return None;
+        }
+        let contiguous_space = self.slot_directory_start() - self.get_free_start();
+        if self.compaction == CompactionPolicy::Never && contiguous_space < new_len {
+            return None;
+        }
+        //the old copy is dead from here so compaction can reclaim it
+        self.set_slot_in_use(slot_id, SLOT_IN_USE_FREE);
+        if self.is_secure_delete() {
+            self.data_mut()[offset..offset + old_len].fill(0);
+        }
+        if contiguous_space < new_len {
+            self.compact();
+        }
+        let insert_offset = self.get_free_start();
+        self.data_mut()[insert_offset..insert_offset + new_len].clone_from_slice(bytes);
+        self.write_slot(
+            slot_id,
+            insert_offset as Offset,
+            new_len as SlotLength,
+            in_use,
+        );
+        self.set_free_start(insert_offset + new_len);
+        self.compact_if_aggressive();
+        Some(())
+    }
+
+    ///number of slot entries in the header
+    pub(crate) fn get_num_slots(&self) -> usize {
+        u16::from_le_bytes(
+            self.data[PAGE_META_NUM_SLOTS_OFFSET..PAGE_META_NUM_SLOTS_OFFSET + 2]
+                .try_into()
+                .unwrap(),
+        ) as usize
+    }
+
+    ///writes num_slots to the header
+    ///entries added at the end count as free since every caller zeroes or rewrites them
+    fn set_num_slots(&mut self, n: usize) {
+        let old = self.get_num_slots();
+        self.data_mut()[PAGE_META_NUM_SLOTS_OFFSET..PAGE_META_NUM_SLOTS_OFFSET + 2]
+            .copy_from_slice(&(n as u16).to_le_bytes());
+        for slot_id in n..old {
+            self.mark_slot_free(slot_id, false);
+        }
+        for slot_id in old..n {
+            self.mark_slot_free(slot_id, true);
+        }
+        self.free_slot_bits.truncate(n.div_ceil(64));
+    }
+
+    ///stores bytes as they are under the lowest free SlotId marked in_use
+    ///None if no space below the fill factor
+    pub(crate) fn insert_slot(&mut self, bytes: &[u8], in_use: u8) -> Option<SlotId> {
+        let slot_id = self.find_lowest_free_slot_id();
+        self.insert_slot_at(slot_id, bytes, in_use)?;
+        Some(slot_id)
+    }
+
+    ///stores bytes as they are under slot_id which must be free or past the directory end
+    pub(crate) fn insert_slot_at(
+        &mut self,
+        slot_id: SlotId,
+        bytes: &[u8],
+        in_use: u8,
+    ) -> Option<()> {
+        let value_len = bytes.len();
+        if value_len > N {
+            return None;
+        }
+
+        let num_slots = self.get_num_slots();
+        let new_slots = (slot_id as usize + 1).saturating_sub(num_slots);
+
+        let extra_header = new_slots * self.slot_meta_size();
+        if self.insert_budget() < value_len + extra_header {
+            return None;
+        }
+
+        //the new slot entry takes the free bytes just below the slot directory
+        let free_start = self.get_free_start();
+        let contiguous_space = self
+            .slot_directory_start()
+            .saturating_sub(free_start + extra_header);
+        if contiguous_space < value_len {
+            if self.compaction == CompactionPolicy::Never {
+                return None;
+            }
+            self.compact();
+        }
+
+        if new_slots > 0 {
+            //entries skipped over stay free whatever stale bytes the region held
+            let old_start = self.slot_directory_start();
+            self.set_num_slots(num_slots + new_slots);
+            let new_start = self.slot_directory_start();
+            self.data_mut()[new_start..old_start].fill(0);
+        }
+
+        let insert_offset = self.get_free_start();
+        if insert_offset + value_len > self.slot_directory_start() {
+            return None;
+        }
+
+        self.data_mut()[insert_offset..insert_offset + value_len].clone_from_slice(bytes);
+        self.write_slot(
+            slot_id,
+            insert_offset as Offset,
+            value_len as SlotLength,
+            in_use,
+        );
+        self.write_slot_flags(slot_id, 0);
+        self.set_free_start(insert_offset + value_len);
+        self.invalidate_summaries();
+
+        Some(())
+    }
+
+    ///free bytes inserts may still use leaving the space above the fill factor for updates
+    fn insert_budget(&self) -> usize {
+        let reserved = N - N * self.get_fill_factor() as usize / 100;
+        self.get_free_space().saturating_sub(reserved)
+    }
+
+    ///bytes per slot directory entry in the page's current encoding
+    pub(crate) fn slot_meta_size(&self) -> usize {
+        match self.get_slot_encoding() {
+            SlotEncoding::Wide => BYTES_PER_SLOT_META,
+            SlotEncoding::Compact => COMPACT_BYTES_PER_SLOT_META,
+            SlotEncoding::Large => LARGE_BYTES_PER_SLOT_META,
+        }
+    }
+
+    ///switches a new page to the large slot format moving free_start into the page's last bytes
+    pub(crate) fn use_large_slots(&mut self) {
+        let free_start = self.get_free_start();
+        self.data_mut()[PAGE_META_FLAGS_OFFSET] |= PAGE_FLAG_LARGE_SLOTS;
+        self.data_mut()[PAGE_META_FREE_START_OFFSET..PAGE_META_FREE_START_OFFSET + 2].fill(0);
+        self.set_free_start(free_start);
+    }
+
+    ///bytes at the page end holding the u32 free_start of a large slot page
+    fn large_free_start_size(&self) -> usize {
+        if self.get_slot_encoding() == SlotEncoding::Large {
+            LARGE_FREE_START_SIZE
+        } else {
+            0
+        }
+    }
+
+    ///first byte of the slot directory which grows down from the summary regions toward the body
+    fn slot_directory_start(&self) -> usize {
+        (N - self.tail_size()).saturating_sub(self.get_num_slots() * self.slot_meta_size())
+    }
+
+    ///bytes of optional summary regions after the slot directory at the end of the page
+    pub(crate) fn tail_size(&self) -> usize {
+        self.large_free_start_size() + self.zone_map_size() + self.bloom_filter_size()
+    }
+
+    fn zone_map_size(&self) -> usize {
+        if self.data[PAGE_META_FLAGS_OFFSET] & PAGE_FLAG_ZONE_MAP != 0 {
+            ZONE_MAP_REGION_SIZE
+        } else {
+            0
+        }
+    }
+
+    fn bloom_filter_size(&self) -> usize {
+        if self.data[PAGE_META_FLAGS_OFFSET] & PAGE_FLAG_BLOOM_FILTER != 0 {
+            BLOOM_FILTER_REGION_SIZE
+        } else {
+            0
+        }
+    }
+
+    ///adds or drops the summary region behind flag keeping the contents of the others
+    ///Some(true) if the layout changed and None if the directory cannot make room
+    fn toggle_summary(&mut self, flag: u8, enabled: bool) -> Option<bool> {
+        if enabled == (self.data[PAGE_META_FLAGS_OFFSET] & flag != 0) {
+            return Some(false);
+        }
+        let size = match flag {
+            PAGE_FLAG_ZONE_MAP => ZONE_MAP_REGION_SIZE,
+            _ => BLOOM_FILTER_REGION_SIZE,
+        };
+        let tail = self.tail_size();
+        let new_tail = if enabled { tail + size } else { tail - size };
+        let zone = self
+            .zone_map_offset()
+            .map(|at| self.data[at..at + ZONE_MAP_REGION_SIZE].to_vec());
+        let bloom = self
+            .bloom_filter_offset()
+            .map(|at| self.data[at..at + BLOOM_FILTER_REGION_SIZE].to_vec());
+        self.move_slot_directory(new_tail)?;
+        self.data_mut()[PAGE_META_FLAGS_OFFSET] ^= flag;
+        let ext = self.large_free_start_size();
+        self.data_mut()[N - new_tail..N - ext].fill(0);
+        if let (Some(zone), Some(at)) = (zone, self.zone_map_offset()) {
+            self.data_mut()[at..at + ZONE_MAP_REGION_SIZE].copy_from_slice(&zone);
+        }
+        if let (Some(bloom), Some(at)) = (bloom, self.bloom_filter_offset()) {
+            self.data_mut()[at..at + BLOOM_FILTER_REGION_SIZE].copy_from_slice(&bloom);
+        }
+        Some(true)
+    }
+
+    ///moves the slot directory so it ends new_tail bytes before the page end compacting if it must grow into the body
+    ///None if the body leaves no room
+    fn move_slot_directory(&mut self, new_tail: usize) -> Option<()> {
+        let start = self.slot_directory_start();
+        let end = N - self.tail_size();
+        let new_end = N.checked_sub(new_tail)?;
+        let new_start = (start + new_end).checked_sub(end)?;
+        if new_start < self.get_free_start() {
+            self.compact();
+            if new_start < self.get_free_start() {
+                return None;
+            }
+        }
+        self.data_mut().copy_within(start..end, new_start);
+        if new_start > start {
+            self.data_mut()[start..new_start].fill(0);
+        }
+        Some(())
+    }
+
+    ///start of the zone map region if the page keeps one
+    fn zone_map_offset(&self) -> Option<usize> {
+        (self.zone_map_size() != 0).then(|| N - self.large_free_start_size() - ZONE_MAP_REGION_SIZE)
+    }
+
+    ///start of the bloom filter region if the page keeps one
+    fn bloom_filter_offset(&self) -> Option<usize> {
+        (self.bloom_filter_size() != 0).then(|| {
+            N - self.large_free_start_size() - self.zone_map_size() - BLOOM_FILTER_REGION_SIZE
+        })
+    }
+
+    ///sets the bloom filter bits of key
+    fn add_bloom_key(&mut self, key: &[u8]) {
+        if let Some(at) = self.bloom_filter_offset() {
+            for bit in bloom_positions(key) {
+                self.data_mut()[at + 1 + bit / 8] |= 1 << (bit % 8);
+            }
+        }
+    }
+
+    ///stores state and bounds in the zone map region
+    fn write_zone_map(&mut self, state: u8, zone: &ZoneMap) {
+        let Some(at) = self.zone_map_offset() else {
+            return;
+        };
+        let region = &mut self.data_mut()[at..at + ZONE_MAP_REGION_SIZE];
+        region.fill(0);
+        region[0] = state;
+        region[3] = zone.max_truncated as u8;
+        for (i, key) in [&zone.min, &zone.max].into_iter().enumerate() {
+            if let Some(key) = key {
+                let start = 4 + i * ZONE_MAP_KEY_SIZE;
+                region[1 + i] = key.len() as u8;
+                region[start..start + key.len()].copy_from_slice(key);
+            }
+        }
+    }
+
+    ///marks the zone map and bloom filter stale after a record was stored without its key
+    fn invalidate_summaries(&mut self) {
+        if let Some(at) = self.zone_map_offset() {
+            self.data_mut()[at] = ZONE_MAP_STALE;
+        }
+        if let Some(at) = self.bloom_filter_offset() {
+            self.data_mut()[at] = BLOOM_FILTER_STALE;
+        }
+    }
+
+    ///first free body byte clamps to body_start if the stored value is stale
+    fn get_free_start(&self) -> usize {
+        let body_start = FIXED_PAGE_META_SIZE;
+        let stored = self.stored_free_start();
+        let raw = if stored < body_start {
+            body_start
+        } else {
+            stored
+        };
+        raw.min(N)
+    }
+
+    ///free_start exactly as stored before any clamping
+    fn stored_free_start(&self) -> usize {
+        if self.get_slot_encoding() == SlotEncoding::Large {
+            u32::from_le_bytes(self.data[N - LARGE_FREE_START_SIZE..].try_into().unwrap()) as usize
+        } else {
+            u16::from_le_bytes(
+                self.data[PAGE_META_FREE_START_OFFSET..PAGE_META_FREE_START_OFFSET + 2]
+                    .try_into()
+                    .unwrap(),
+            ) as usize
+        }
+    }
+
+    ///writes free_start to the header clamped to the page size
+    fn set_free_start(&mut self, pos: usize) {
+        let pos = pos.min(N);
+        if self.get_slot_encoding() == SlotEncoding::Large {
+            self.data_mut()[N - LARGE_FREE_START_SIZE..]
+                .copy_from_slice(&(pos as u32).to_le_bytes());
+        } else {
+            self.data_mut()[PAGE_META_FREE_START_OFFSET..PAGE_META_FREE_START_OFFSET + 2]
+                .copy_from_slice(&(pos as u16).to_le_bytes());
+        }
+    }
+
+    ///byte offset of slot_id metadata entry in data slot 0 is the last entry in the page
+    pub(crate) fn slot_meta_offset(&self, slot_id: SlotId) -> usize {
+        N - self.tail_size() - (slot_id as usize + 1) * self.slot_meta_size()
+    }
+
+    ///packed length and in_use u16 of a compact slot entry at base
+    fn compact_slot_bits(&self, base: usize) -> u16 {
+        u16::from_le_bytes(
+            self.data[base + SLOT_LENGTH_OFFSET..base + SLOT_LENGTH_OFFSET + 2]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    ///offset and length for slot_id or None if out of range
+    fn get_slot_offset_length(&self, slot_id: SlotId) -> Option<(Offset, SlotLength)> {
+        if slot_id as usize >= self.get_num_slots() {
+            return None;
+        }
+        let base = self.slot_meta_offset(slot_id);
+        let u16_at = |at: usize| u16::from_le_bytes(self.data[at..at + 2].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_le_bytes(self.data[at..at + 4].try_into().unwrap());
+        Some(match self.get_slot_encoding() {
+            SlotEncoding::Wide => (
+                u16_at(base + SLOT_OFFSET_OFFSET) as Offset,
+                u16_at(base + SLOT_LENGTH_OFFSET) as SlotLength,
+            ),
+            SlotEncoding::Compact => (
+                u16_at(base + SLOT_OFFSET_OFFSET) as Offset,
+                (self.compact_slot_bits(base) & COMPACT_SLOT_LENGTH_MASK) as SlotLength,
+            ),
+            SlotEncoding::Large => (
+                u32_at(base + SLOT_OFFSET_OFFSET),
+                u32_at(base + LARGE_SLOT_LENGTH_OFFSET),
+            ),
+        })
+    }
+
+    ///in_use flag for slot_id or None if out of range
+    pub(crate) fn get_slot_in_use(&self, slot_id: SlotId) -> Option<u8> {
+        Some(self.slot_state_byte(slot_id)? & SLOT_STATE_MASK)
+    }
+
+    ///in_use byte of slot_id with its flag bits which the compact encoding does not store
+    fn slot_state_byte(&self, slot_id: SlotId) -> Option<u8> {
+        if slot_id as usize >= self.get_num_slots() {
+            return None;
+        }
+        let base = self.slot_meta_offset(slot_id);
+        Some(match self.get_slot_encoding() {
+            SlotEncoding::Wide => self.data[base + SLOT_IN_USE_OFFSET],
+            SlotEncoding::Compact => {
+                (self.compact_slot_bits(base) >> COMPACT_SLOT_IN_USE_SHIFT) as u8
+            }
+            SlotEncoding::Large => self.data[base + LARGE_SLOT_IN_USE_OFFSET],
+        })
+    }
+
+    ///flag bits of any slot entry 0 if out of range
+    pub(crate) fn slot_flags(&self, slot_id: SlotId) -> u8 {
+        self.slot_state_byte(slot_id).unwrap_or(0) & SLOT_FLAGS_MASK
+    }
+
+    ///replaces the flag bits of slot_id keeping its state a no-op under the compact encoding
+    pub(crate) fn write_slot_flags(&mut self, slot_id: SlotId, flags: u8) {
+        let at = self.slot_meta_offset(slot_id)
+            + match self.get_slot_encoding() {
+                SlotEncoding::Wide => SLOT_IN_USE_OFFSET,
+                SlotEncoding::Large => LARGE_SLOT_IN_USE_OFFSET,
+                SlotEncoding::Compact => return,
+            };
+        let state = self.data[at] & SLOT_STATE_MASK;
+        self.data_mut()[at] = state | flags;
+    }
+
+    ///sets in_use for slot_id
+    pub(crate) fn set_slot_in_use(&mut self, slot_id: SlotId, in_use: u8) {
+        let (offset, length) = self.get_slot_offset_length(slot_id).unwrap();
+        self.write_slot(slot_id, offset, length, in_use);
+    }
+
+    ///writes offset and length and in_use into slot_id metadata keeping the entry's flag bits
+    fn write_slot(&mut self, slot_id: SlotId, offset: Offset, length: SlotLength, in_use: u8) {
+        let old_len = self.live_slot_len(slot_id);
+        let base = self.slot_meta_offset(slot_id);
+        match self.get_slot_encoding() {
+            SlotEncoding::Wide => {
+                self.data_mut()[base..base + 2].copy_from_slice(&(offset as u16).to_le_bytes());
+                self.data_mut()[base + 2..base + 4].copy_from_slice(&(length as u16).to_le_bytes());
+                let flags = self.data[base + SLOT_IN_USE_OFFSET] & SLOT_FLAGS_MASK;
+                self.data_mut()[base + SLOT_IN_USE_OFFSET] = in_use | flags;
+            }
+            SlotEncoding::Compact => {
+                let bits = (length as u16 & COMPACT_SLOT_LENGTH_MASK)
+                    | ((in_use as u16) << COMPACT_SLOT_IN_USE_SHIFT);
+                self.data_mut()[base..base + 2].copy_from_slice(&(offset as u16).to_le_bytes());
+                self.data_mut()[base + 2..base + 4].copy_from_slice(&bits.to_le_bytes());
+            }
+            SlotEncoding::Large => {
+                self.data_mut()[base..base + 4].copy_from_slice(&offset.to_le_bytes());
+                self.data_mut()[base + 4..base + 8].copy_from_slice(&length.to_le_bytes());
+                let flags = self.data[base + LARGE_SLOT_IN_USE_OFFSET] & SLOT_FLAGS_MASK;
+                self.data_mut()[base + LARGE_SLOT_IN_USE_OFFSET] = in_use | flags;
+            }
+        }
+        self.live_byte_count = self.live_byte_count - old_len + self.live_slot_len(slot_id);
+        self.mark_slot_free(slot_id as usize, in_use == SLOT_IN_USE_FREE);
+    }
+
+    ///sets or clears the free bit of one slot entry
+    fn mark_slot_free(&mut self, slot_id: usize, free: bool) {
+        let (word, bit) = (slot_id / 64, 1u64 << (slot_id % 64));
+        if word >= self.free_slot_bits.len() {
+            if !free {
+                return;
+            }
+            self.free_slot_bits.resize(word + 1, 0);
+        }
+        if free {
+            self.free_slot_bits[word] |= bit;
+        } else {
+            self.free_slot_bits[word] &= !bit;
+        }
+    }
+
+    ///rebuilds the free slot bits from the slot directory after the bytes changed wholesale
+    pub(crate) fn rebuild_free_slots(&mut self) {
+        self.free_slot_bits.clear();
+        for slot_id in 0..self.get_num_slots() {
+            if self.get_slot_in_use(slot_id as SlotId) == Some(SLOT_IN_USE_FREE) {
+                self.mark_slot_free(slot_id, true);
+            }
+        }
+    }
+
+    ///record length of slot_id if it is live otherwise 0
+    fn live_slot_len(&self, slot_id: SlotId) -> usize {
+        match self.get_slot_in_use(slot_id) {
+            Some(in_use) if is_live(in_use) => self
+                .get_slot_offset_length(slot_id)
+                .map_or(0, |(_, len)| len as usize),
+            _ => 0,
+        }
+    }
+
+    ///rebuilds the live byte count from the slot directory after the bytes changed wholesale
+    pub(crate) fn recount_live_bytes(&mut self) {
+        self.live_byte_count = self.iter_used_slots().map(|(_, len)| len as usize).sum();
+    }
+
+    ///lowest free SlotId or num_slots if all in use
+    ///skipping SlotIds held by reservations
+    ///scans the free slot bits a word at a time rather than every slot entry
+    fn find_lowest_free_slot_id(&self) -> SlotId {
+        let num_slots = self.get_num_slots();
+        let free = self
+            .free_slot_bits
+            .iter()
+            .enumerate()
+            .flat_map(|(word, &bits)| {
+                let mut bits = bits;
+                std::iter::from_fn(move || {
+                    (bits != 0).then(|| {
+                        let bit = bits.trailing_zeros() as usize;
+                        bits &= bits - 1;
+                        word * 64 + bit
+                    })
+                })
+            })
+            .take_while(|&slot_id| slot_id < num_slots)
+            .map(|slot_id| slot_id as SlotId)
+            .chain(num_slots as SlotId..)
+            .find(|&slot_id| !self.is_reserved(slot_id))
+            .unwrap();
+        debug_assert_eq!(
+            (0..num_slots as SlotId)
+                .filter(|&sid| self.get_slot_in_use(sid) == Some(SLOT_IN_USE_FREE))
+                .chain(num_slots as SlotId..)
+                .find(|&sid| !self.is_reserved(sid)),
+            Some(free),
+            "free slot bits out of step with the slot directory"
+        );
+        free
+    }
+
+    ///why a record of len bytes within the max record size did not fit under slot_id
+    fn insert_error(&self, slot_id: SlotId, len: usize) -> PageInsertError {
+        let mut available = self.insert_budget();
+        if self.compaction == CompactionPolicy::Never {
+            available = available.min(self.slot_directory_start() - self.get_free_start());
+        }
+        let entry = if slot_id as usize >= self.get_num_slots() {
+            self.slot_meta_size()
+        } else {
+            0
+        };
+        if entry > 0 && len <= available {
+            PageInsertError::SlotDirectoryFull
+        } else {
+            PageInsertError::PageFull {
+                needed: len + entry,
+                available,
+            }
+        }
+    }
+
+    ///true if a reservation holds slot_id
+    fn is_reserved(&self, slot_id: SlotId) -> bool {
+        self.reservations.iter().any(|&(sid, _)| sid == slot_id)
+    }
+
+    ///bytes held back by reservations
+    fn reserved_bytes(&self) -> usize {
+        self.reservations.iter().map(|&(_, held)| held).sum()
+    }
+
+    ///drops the page's record of reservation or None if it holds none for that SlotId
+    fn release(&mut self, reservation: &Reservation) -> Option<()> {
+        let index = self
+            .reservations
+            .iter()
+            .position(|&(sid, _)| sid == reservation.slot_id)?;
+        self.reservations.swap_remove(index);
+        Some(())
+    }
+
+    ///bytes a live slot points at whatever kind of record it holds
+    pub(crate) fn slot_bytes(&self, slot_id: SlotId) -> Option<&[u8]> {
+        let (offset, length) = self.get_slot_offset_length(slot_id)?;
+        let offset = offset as usize;
+        let length = length as usize;
+        if offset + length > N {
+            return None;
+        }
+        Some(&self.data[offset..offset + length])
+    }
+
+    ///mutable bytes of a live slot for callers that overwrite a record without resizing it
+    pub(crate) fn slot_bytes_mut(&mut self, slot_id: SlotId) -> Option<&mut [u8]> {
+        let (offset, length) = self.get_slot_offset_length(slot_id)?;
+        let (offset, length) = (offset as usize, length as usize);
+        if offset + length > N {
+            return None;
+        }
+        Some(&mut self.data_mut()[offset..offset + length])
+    }
+
+    ///slot_id and length for every live slot
+    fn iter_used_slots(&self) -> impl Iterator<Item = (SlotId, SlotLength)> + '_ {
+        let num_slots = self.get_num_slots();
+        (0..num_slots).filter_map(move |i| {
+            let sid = i as SlotId;
+            if self.get_slot_in_use(sid).is_some_and(is_live) {
+                self.get_slot_offset_length(sid).map(|(_, len)| (sid, len))
+            } else {
+                None
+            }
+        })
+    }
+
+    ///removes free slot entries at the end of the directory zeroing the bytes they held
+    pub(crate) fn truncate_free_tail(&mut self) {
+        let old_start = self.slot_directory_start();
+        let mut num_slots = self.get_num_slots();
+        while num_slots > 0
+            && self.get_slot_in_use((num_slots - 1) as SlotId) == Some(SLOT_IN_USE_FREE)
+        {
+            num_slots -= 1;
+        }
+        self.set_num_slots(num_slots);
+        let new_start = self.slot_directory_start();
+        self.data_mut()[old_start..new_start].fill(0);
+    }
+
+    ///compacts under the aggressive policy once enough dead bytes pile up
+    fn compact_if_aggressive(&mut self) {
+        if let CompactionPolicy::Aggressive {
+            dead_space_threshold,
+        } = self.compaction
+        {
+            if self.dead_bytes() >= dead_space_threshold.max(1) {
+                self.compact();
+            }
+        }
+    }
+
+    ///moves all live records to body start and resets free_start
+    pub(crate) fn compact(&mut self) {
+        self.compactions += 1;
+        let num_slots = self.get_num_slots();
+        let body_start = FIXED_PAGE_META_SIZE;
+
+        //sort by offset so copies never overlap
+        let mut used: Vec<(SlotId, usize, usize, u8)> = (0..num_slots)
+            .filter_map(|i| {
+                let sid = i as SlotId;
+                let in_use = self.get_slot_in_use(sid)?;
+                if is_live(in_use) {
+                    self.get_slot_offset_length(sid)
+                        .map(|(off, len)| (sid, off as usize, len as usize, in_use))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        used.sort_by_key(|&(_, off, _, _)| off);
+        let live_records = used.len();
+
+        let mut write_pos = body_start;
+        for (slot_id, old_offset, length, in_use) in used {
+            if old_offset != write_pos {
+                self.data_mut()
+                    .copy_within(old_offset..old_offset + length, write_pos);
+            }
+            self.write_slot(slot_id, write_pos as Offset, length as SlotLength, in_use);
+            write_pos += length;
+        }
+        self.set_free_start(write_pos);
+        self.scrub_free_body();
+        tracing::trace!(
+            page_id = self.get_page_id(),
+            live_records,
+            free_bytes = self.get_free_space(),
+            "compacted page"
+        );
+    }
+
+    ///zeroes the gap between free_start and the slot directory under secure delete
+    ///where compaction leaves stale copies of moved and deleted records
+    fn scrub_free_body(&mut self) {
+        if self.is_secure_delete() {
+            let (start, end) = (self.get_free_start(), self.slot_directory_start());
+            self.data_mut()[start..end].fill(0);
+        }
+    }
+}
+
+///one slot directory entry as stored including free slots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SlotEntry {
+    pub slot_id: SlotId,
+    pub offset: Offset,
+    pub length: SlotLength,
+    pub in_use: bool,
+}
+
+//read only views of the layout for debugging tools
+impl<const N: usize> SizedPage<N> {
+    ///number of slot directory entries live or free
+    pub fn slot_count(&self) -> usize {
+        self.get_num_slots()
+    }
+
+    ///first free body byte as the header records it
+    pub fn free_start(&self) -> usize {
+        self.get_free_start()
+    }
+
+    ///first byte of the slot directory at the page tail where the body must end
+    pub fn slot_directory_offset(&self) -> usize {
+        self.slot_directory_start()
+    }
+
+    ///every slot directory entry in SlotId order stopping at the page end if num_slots is corrupt
+    pub fn slot_entries(&self) -> Vec<SlotEntry> {
+        (0..self.get_num_slots())
+            .take_while(|&i| {
+                (i + 1) * self.slot_meta_size() <= N - FIXED_PAGE_META_SIZE - self.tail_size()
+            })
+            .filter_map(|i| {
+                let slot_id = i as SlotId;
+                let (offset, length) = self.get_slot_offset_length(slot_id)?;
+                let in_use = is_live(self.get_slot_in_use(slot_id)?);
+                Some(SlotEntry {
+                    slot_id,
+                    offset,
+                    length,
+                    in_use,
+                })
+            })
+            .collect()
+    }
+
+    ///bytes stored under a live slot including overflow pointers and continuation chunks
+    pub fn raw_slot_bytes(&self, slot_id: SlotId) -> Option<&[u8]> {
+        if !is_live(self.get_slot_in_use(slot_id)?) {
+            return None;
+        }
+        self.slot_bytes(slot_id)
+    }
+}
+
+///borrowing iterator over valid records in ascending SlotId order
+///only records stored with a shared prefix are copied out of the page
+pub struct HeapPageIter<'a, P: ?Sized = Page> {
+    page: &'a P,
+    current_slot: SlotId,
+    num_slots: usize,
+    ///last record returned from a prefix compressed page which the next is decoded against
+    prev: Option<Vec<u8>>,
+    prefixed: bool,
+}
+
+impl<'a, P: HeapPage + ?Sized> Iterator for HeapPageIter<'a, P> {
+    type Item = (SlotId, Cow<'a, [u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while (self.current_slot as usize) < self.num_slots {
+            let slot_id = self.current_slot;
+            self.current_slot += 1;
+            if let Some(value) = self.page.get_value_ref(slot_id) {
+                if self.prefixed {
+                    self.prev = Some(value.to_vec());
+                }
+                return Some((slot_id, Cow::Borrowed(value)));
+            }
+            if let Some(value) = self.page.get_value_after(slot_id, self.prev.as_deref()) {
+                if self.prefixed {
+                    self.prev = Some(value.clone());
+                }
+                return Some((slot_id, Cow::Owned(value)));
+            }
+        }
+        None
+    }
+}
+
+///valid records matching a predicate returned by HeapPage::scan_filtered
+pub struct HeapPageFilter<'a, P: ?Sized, F> {
+    inner: HeapPageIter<'a, P>,
+    pred: F,
+}
+
+impl<'a, P: HeapPage + ?Sized, F: Fn(&[u8]) -> bool> Iterator for HeapPageFilter<'a, P, F> {
+    type Item = (SlotId, Cow<'a, [u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pred = &self.pred;
+        self.inner.find(|(_, value)| pred(value))
+    }
+}
+
+impl<const N: usize> SizedPage<N> {
+    ///valid records in ascending SlotId order addressed by ValueIds in container_id on this page
+    pub fn iter_value_ids(
+        &self,
+        container_id: ContainerId,
+    ) -> impl Iterator<Item = (ValueId, Cow<'_, [u8]>)> + '_ {
+        let page_id = self.get_page_id();
+        self.iter()
+            .map(move |(slot_id, value)| (ValueId::new_slot(container_id, page_id, slot_id), value))
+    }
+}
+
+///consuming iterator over valid records in ascending SlotId order
+pub struct HeapPageIntoIter<const N: usize = PAGE_SIZE> {
+    page: SizedPage<N>,
+    current_slot: SlotId,
+    num_slots: usize,
+    ///last record returned from a prefix compressed page which the next is decoded against
+    prev: Option<Vec<u8>>,
+}
+
+impl<const N: usize> Iterator for HeapPageIntoIter<N> {
+    type Item = (Vec<u8>, SlotId);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while (self.current_slot as usize) < self.num_slots {
+            let slot_id = self.current_slot;
+            self.current_slot += 1;
+            if let Some(value) = self.page.get_value_after(slot_id, self.prev.as_deref()) {
+                if self.page.is_prefix_compressed() {
+                    self.prev = Some(value.clone());
+                }
+                return Some((value, slot_id));
+            }
+        }
+        None
+    }
+}
+
+///creates a consuming iterator from a page
+impl<const N: usize> IntoIterator for SizedPage<N> {
+    type Item = (Vec<u8>, SlotId);
+    type IntoIter = HeapPageIntoIter<N>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let num_slots = self.get_num_slots();
+        HeapPageIntoIter {
+            page: self,
+            current_slot: 0,
+            num_slots,
+            prev: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
     use std::collections::VecDeque;
 
-    use super::*;
-    use common::testutil::init;
-    use common::testutil::*;
-    use common::Tuple;
-    use rand::Rng;
+    use super::*;
+    use common::testutil::init;
+    use common::testutil::*;
+    use common::Tuple;
+    use rand::Rng;
+
+    /// Limits how on how many bytes we can use for page metadata / header
+    /// (8 bytes of layout fields plus the 4 byte checksum, 4 byte lsn and 4 byte magic and version)
+    pub const FIXED_HEADER_SIZE: usize = 20;
+    pub const HEADER_PER_VAL_SIZE: usize = 6;
+
+    #[test]
+    fn hs_page_sizes_header_free_space() {
+        init();
+        let p = Page::new(0);
+        assert_eq!(0, p.get_page_id());
+
+        assert_eq!(PAGE_SIZE - p.get_header_size(), p.get_free_space());
+    }
+
+    #[test]
+    fn hs_page_debug_insert() {
+        init();
+        let mut p = Page::new(0);
+        let n = 20;
+        let size = 20;
+        let vals = get_ascending_vec_of_byte_vec_02x(n, size, size);
+        for x in &vals {
+            p.add_value(x);
+        }
+        assert_eq!(
+            p.get_free_space(),
+            PAGE_SIZE - p.get_header_size() - n * size
+        );
+    }
+
+    #[test]
+    fn hs_page_simple_insert() {
+        init();
+        let mut p = Page::new(5);
+        let tuple = int_vec_to_tuple(vec![0, 1, 2]);
+        let tuple_bytes = serde_cbor::to_vec(&tuple).unwrap();
+        let byte_len = tuple_bytes.len();
+        assert_eq!(Some(0), p.add_value(&tuple_bytes));
+        assert_eq!(
+            PAGE_SIZE - byte_len - p.get_header_size(),
+            p.get_free_space()
+        );
+        let tuple_bytes2 = serde_cbor::to_vec(&tuple).unwrap();
+        assert_eq!(Some(1), p.add_value(&tuple_bytes2));
+        assert_eq!(
+            PAGE_SIZE - p.get_header_size() - byte_len - byte_len,
+            p.get_free_space()
+        );
+    }
+
+    #[test]
+    fn hs_page_space() {
+        init();
+        let mut p = Page::new(0);
+        let size = 10;
+        let bytes = get_random_byte_vec(size);
+        assert_eq!(10, bytes.len());
+        assert_eq!(Some(0), p.add_value(&bytes));
+        assert_eq!(PAGE_SIZE - p.get_header_size() - size, p.get_free_space());
+        assert_eq!(Some(1), p.add_value(&bytes));
+        assert_eq!(
+            PAGE_SIZE - p.get_header_size() - size * 2,
+            p.get_free_space()
+        );
+        assert_eq!(Some(2), p.add_value(&bytes));
+        assert_eq!(
+            PAGE_SIZE - p.get_header_size() - size * 3,
+            p.get_free_space()
+        );
+    }
+
+    #[test]
+    fn hs_page_get_value() {
+        init();
+        let mut p = Page::new(0);
+        let tuple = int_vec_to_tuple(vec![0, 1, 2]);
+        let tuple_bytes = serde_cbor::to_vec(&tuple).unwrap();
+        assert_eq!(Some(0), p.add_value(&tuple_bytes));
+        let check_bytes = p.get_value(0).unwrap();
+        let check_tuple: Tuple = serde_cbor::from_slice(&check_bytes).unwrap();
+        assert_eq!(tuple_bytes, check_bytes);
+        assert_eq!(tuple, check_tuple);
+
+        let tuple2 = int_vec_to_tuple(vec![3, 3, 3]);
+        let tuple_bytes2 = serde_cbor::to_vec(&tuple2).unwrap();
+        assert_eq!(Some(1), p.add_value(&tuple_bytes2));
+        let check_bytes2 = p.get_value(1).unwrap();
+        let check_tuple2: Tuple = serde_cbor::from_slice(&check_bytes2).unwrap();
+        assert_eq!(tuple_bytes2, check_bytes2);
+        assert_eq!(tuple2, check_tuple2);
+
+        //Recheck
+        let check_bytes2 = p.get_value(1).unwrap();
+        let check_tuple2: Tuple = serde_cbor::from_slice(&check_bytes2).unwrap();
+        assert_eq!(tuple_bytes2, check_bytes2);
+        assert_eq!(tuple2, check_tuple2);
+        let check_bytes = p.get_value(0).unwrap();
+        let check_tuple: Tuple = serde_cbor::from_slice(&check_bytes).unwrap();
+        assert_eq!(tuple_bytes, check_bytes);
+        assert_eq!(tuple, check_tuple);
+
+        //Check that invalid slot gets None
+        assert_eq!(None, p.get_value(2));
+    }
+
+    #[test]
+    fn hs_page_header_size_small() {
+        init();
+        // Testing that the header is no more than 20 bytes for the header, and 6 bytes per value inserted
+        let mut p = Page::new(0);
+        assert!(p.get_header_size() <= FIXED_HEADER_SIZE);
+        let bytes = get_random_byte_vec(10);
+        assert_eq!(Some(0), p.add_value(&bytes));
+        assert!(p.get_header_size() <= FIXED_HEADER_SIZE + HEADER_PER_VAL_SIZE);
+        assert_eq!(Some(1), p.add_value(&bytes));
+        assert_eq!(Some(2), p.add_value(&bytes));
+        assert_eq!(Some(3), p.add_value(&bytes));
+        assert!(p.get_header_size() <= FIXED_HEADER_SIZE + HEADER_PER_VAL_SIZE * 4);
+    }
+
+    #[test]
+    fn hs_page_header_size_full() {
+        init();
+        // Testing that the header is no more than 20 bytes for the header, and 6 bytes per value inserted
+        let mut p = Page::new(0);
+        assert!(p.get_header_size() <= FIXED_HEADER_SIZE);
+        let byte_size = 10;
+        let bytes = get_random_byte_vec(byte_size);
+        // how many vals can we hold with 20 bytes
+        let num_vals: usize = (((PAGE_SIZE - FIXED_HEADER_SIZE) as f64
+            / (byte_size + HEADER_PER_VAL_SIZE) as f64)
+            .floor()) as usize;
+        if PAGE_SIZE == 4096 && FIXED_HEADER_SIZE == 8 && HEADER_PER_VAL_SIZE == 6 {
+            assert_eq!(255, num_vals);
+        }
+        for _ in 0..num_vals {
+            p.add_value(&bytes);
+        }
+        assert!(p.get_header_size() <= FIXED_HEADER_SIZE + (num_vals * HEADER_PER_VAL_SIZE));
+        assert!(
+            p.get_free_space()
+                >= PAGE_SIZE
+                    - (byte_size * num_vals)
+                    - FIXED_HEADER_SIZE
+                    - (num_vals * HEADER_PER_VAL_SIZE)
+        );
+    }
+
+    #[test]
+    fn hs_page_no_space() {
+        init();
+        let mut p = Page::new(0);
+        let size = PAGE_SIZE / 4;
+        let bytes = get_random_byte_vec(size);
+        assert_eq!(Some(0), p.add_value(&bytes));
+        assert_eq!(PAGE_SIZE - p.get_header_size() - size, p.get_free_space());
+        assert_eq!(Some(1), p.add_value(&bytes));
+        assert_eq!(
+            PAGE_SIZE - p.get_header_size() - size * 2,
+            p.get_free_space()
+        );
+        assert_eq!(Some(2), p.add_value(&bytes));
+        assert_eq!(
+            PAGE_SIZE - p.get_header_size() - size * 3,
+            p.get_free_space()
+        );
+        //Should reject here
+        assert_eq!(None, p.add_value(&bytes));
+        assert_eq!(
+            PAGE_SIZE - p.get_header_size() - size * 3,
+            p.get_free_space()
+        );
+        // Take small amount of data
+        let small_bytes = get_random_byte_vec(size / 4);
+        assert_eq!(Some(3), p.add_value(&small_bytes));
+        assert_eq!(
+            PAGE_SIZE - p.get_header_size() - size * 3 - small_bytes.len(),
+            p.get_free_space()
+        );
+    }
+
+    #[test]
+    fn hs_page_simple_delete() {
+        init();
+        let mut p = Page::new(0);
+        let tuple = int_vec_to_tuple(vec![0, 1, 2]);
+        let tuple_bytes = serde_cbor::to_vec(&tuple).unwrap();
+        assert_eq!(Some(0), p.add_value(&tuple_bytes));
+        let check_bytes = p.get_value(0).unwrap();
+        let check_tuple: Tuple = serde_cbor::from_slice(&check_bytes).unwrap();
+        assert_eq!(tuple_bytes, check_bytes);
+        assert_eq!(tuple, check_tuple);
 
-    /// Limits how on how many bytes we can use for page metadata / header
-    pub const FIXED_HEADER_SIZE: usize = 8;
-    pub const HEADER_PER_VAL_SIZE: usize = 6;
+        let tuple2 = int_vec_to_tuple(vec![3, 3, 3]);
+        let tuple_bytes2 = serde_cbor::to_vec(&tuple2).unwrap();
+        assert_eq!(Some(1), p.add_value(&tuple_bytes2));
+        let check_bytes2 = p.get_value(1).unwrap();
+        let check_tuple2: Tuple = serde_cbor::from_slice(&check_bytes2).unwrap();
+        assert_eq!(tuple_bytes2, check_bytes2);
+        assert_eq!(tuple2, check_tuple2);
+
+        //Delete slot 0
+        assert_eq!(Some(()), p.delete_value(0));
+
+        //Recheck slot 1
+        let check_bytes2 = p.get_value(1).unwrap();
+        let check_tuple2: Tuple = serde_cbor::from_slice(&check_bytes2).unwrap();
+        assert_eq!(tuple_bytes2, check_bytes2);
+        assert_eq!(tuple2, check_tuple2);
+
+        //Verify slot 0 is gone
+        assert_eq!(None, p.get_value(0));
+
+        //Check that invalid slot gets None
+        assert_eq!(None, p.get_value(2));
+
+        //Delete slot 1
+        assert_eq!(Some(()), p.delete_value(1));
+
+        //Verify slot 0 is gone
+        assert_eq!(None, p.get_value(1));
+    }
+
+    #[test]
+    fn hs_page_add_values() {
+        init();
+        let mut batch = Page::new(0);
+        let mut single = Page::new(0);
+        let first: Vec<Vec<u8>> = (0..6).map(|_| get_random_byte_vec(300)).collect();
+        for v in &first {
+            batch.add_value(v).unwrap();
+            single.add_value(v).unwrap();
+        }
+        for sid in [1, 4] {
+            batch.delete_value(sid).unwrap();
+            single.delete_value(sid).unwrap();
+        }
+
+        //reuses free slots first then grows the header, skipping what cannot fit
+        let second: Vec<Vec<u8>> = vec![
+            get_random_byte_vec(200),
+            get_random_byte_vec(PAGE_SIZE),
+            get_random_byte_vec(500),
+            get_random_byte_vec(400),
+            get_random_byte_vec(1200),
+            get_random_byte_vec(10),
+        ];
+        let refs: Vec<&[u8]> = second.iter().map(|v| &v[..]).collect();
+        let expected: Vec<Option<SlotId>> = refs.iter().map(|v| single.add_value(v)).collect();
+        let results = batch.add_values(&refs);
+        assert_eq!(expected, results);
+        assert_eq!(
+            vec![Some(1), None, Some(4), Some(6), Some(7), Some(8)],
+            results
+        );
+
+        for (v, sid) in second.iter().zip(&results) {
+            if let Some(sid) = sid {
+                assert_eq!(Some(&v[..]), batch.get_value_ref(*sid));
+            }
+        }
+        for sid in [0, 2, 3, 5] {
+            assert_eq!(first[sid], batch.get_value(sid as SlotId).unwrap());
+        }
+        assert_eq!(single.get_free_space(), batch.get_free_space());
+        assert_eq!(single.get_header_size(), batch.get_header_size());
+        assert_eq!(Vec::<Option<SlotId>>::new(), batch.add_values(&[]));
+    }
+
+    #[test]
+    fn hs_page_borrowing_iter() {
+        init();
+        let mut p = Page::new(0);
+        let values: Vec<Vec<u8>> = (0..5).map(|i| get_random_byte_vec(10 + i)).collect();
+        for v in &values {
+            p.add_value(v).unwrap();
+        }
+        assert_eq!(Some(()), p.delete_value(1));
+        assert_eq!(Some(()), p.delete_value(3));
+
+        let seen: Vec<(SlotId, Cow<[u8]>)> = p.iter().collect();
+        assert_eq!(
+            vec![
+                (0, Cow::Borrowed(&values[0][..])),
+                (2, Cow::Borrowed(&values[2][..])),
+                (4, Cow::Borrowed(&values[4][..]))
+            ],
+            seen
+        );
+        assert!(seen.iter().all(|(_, v)| matches!(v, Cow::Borrowed(_))));
+
+        //matches the consuming iterator without cloning for the scan
+        let owned: Vec<(Vec<u8>, SlotId)> = p.clone().into_iter().collect();
+        let borrowed: Vec<(Vec<u8>, SlotId)> = p.iter().map(|(s, v)| (v.to_vec(), s)).collect();
+        assert_eq!(owned, borrowed);
+        assert_eq!(0, Page::new(1).iter().count());
+    }
+
+    ///fills a page of N bytes then frees and reuses space through compaction
+    fn exercise_page_size<const N: usize>() -> usize {
+        let mut p = SizedPage::<N>::new(1);
+        assert_eq!(N - FIXED_PAGE_META_SIZE, p.get_free_space());
+        let mut values = Vec::new();
+        while let Some(slot_id) = p.add_value(&get_random_byte_vec(100)) {
+            values.push(p.get_value(slot_id).unwrap());
+        }
+        assert!(p.get_free_space() < 100 + BYTES_PER_SLOT_META);
+        for slot_id in (0..values.len()).step_by(2) {
+            p.delete_value(slot_id as SlotId).unwrap();
+        }
+        //a record bigger than any hole forces compaction
+        let big = get_random_byte_vec(300);
+        assert_eq!(Some(0), p.add_value(&big));
+        assert_eq!(big, p.get_value(0).unwrap());
+        for (slot_id, v) in p.iter().skip(1) {
+            assert_eq!(&values[slot_id as usize][..], &*v);
+        }
+
+        p.update_checksum();
+        let copy = SizedPage::<N>::from_bytes(*p.to_bytes()).unwrap();
+        assert!(copy.verify_checksum());
+        assert_eq!(p.get_free_space(), copy.get_free_space());
+        assert_eq!(
+            p.clone().into_iter().collect::<Vec<_>>(),
+            copy.into_iter().collect::<Vec<_>>()
+        );
+        values.len()
+    }
+
+    #[test]
+    fn hs_page_configurable_size() {
+        init();
+        let small = exercise_page_size::<1024>();
+        let default = exercise_page_size::<PAGE_SIZE>();
+        let large = exercise_page_size::<8192>();
+        let larger = exercise_page_size::<16384>();
+        assert!(small < default && default < large && large < larger);
+        assert_eq!(
+            (8192 - FIXED_PAGE_META_SIZE) / (100 + BYTES_PER_SLOT_META),
+            large
+        );
+    }
+
+    #[test]
+    fn hs_page_fill_factor() {
+        init();
+        let mut p = Page::new(0);
+        assert_eq!(DEFAULT_FILL_FACTOR, p.get_fill_factor());
+        assert_eq!(None, p.set_fill_factor(0));
+        assert_eq!(None, p.set_fill_factor(101));
+        assert_eq!(Some(()), p.set_fill_factor(90));
+
+        //inserts stop once the page is 90% occupied
+        let limit = PAGE_SIZE * 90 / 100;
+        let mut count = 0;
+        while p.add_value(&get_random_byte_vec(100)).is_some() {
+            count += 1;
+        }
+        assert_eq!(
+            (limit - FIXED_PAGE_META_SIZE) / (100 + BYTES_PER_SLOT_META),
+            count
+        );
+        assert!(PAGE_SIZE - p.get_free_space() <= limit);
+        assert!(p.get_free_space() >= PAGE_SIZE - limit);
+        assert_eq!(vec![None], p.add_values(&[&[0; 100]]));
+
+        //updates can still grow records into the headroom
+        let grown = get_random_byte_vec(300);
+        assert_eq!(Some(()), p.update_value(3, &grown));
+        assert_eq!(grown, p.get_value(3).unwrap());
+
+        //the setting is part of the page image
+        let copy = Page::from_bytes(*p.to_bytes()).unwrap();
+        assert_eq!(90, copy.get_fill_factor());
+        assert_eq!(Some(()), p.set_fill_factor(100));
+        assert!(p.add_value(&get_random_byte_vec(100)).is_some());
+    }
+
+    #[test]
+    fn hs_page_compaction_policy() {
+        init();
+        let fill = |p: &mut Page| {
+            let values: Vec<Vec<u8>> = (0..8).map(|_| get_random_byte_vec(500)).collect();
+            for v in &values {
+                p.add_value(v).unwrap();
+            }
+            for slot_id in [1, 3, 5] {
+                p.delete_value(slot_id).unwrap();
+            }
+            values
+        };
+
+        //never leaves the holes until an explicit vacuum
+        let mut p = Page::new(0);
+        p.set_compaction_policy(CompactionPolicy::Never);
+        let values = fill(&mut p);
+        let before = p.free_start();
+        assert_eq!(None, p.add_value(&get_random_byte_vec(1000)));
+        assert_eq!(vec![None], p.add_values(&[&[0; 1000]]));
+        assert_eq!(None, p.update_value(0, &get_random_byte_vec(1000)));
+        assert_eq!(values[0], p.get_value(0).unwrap());
+        assert_eq!(before, p.free_start());
+        p.vacuum();
+        assert_eq!(FIXED_PAGE_META_SIZE + 5 * 500, p.free_start());
+        assert_eq!(Some(1), p.add_value(&get_random_byte_vec(1000)));
+        for slot_id in [0, 2, 4, 6, 7] {
+            assert_eq!(values[slot_id], p.get_value(slot_id as SlotId).unwrap());
+        }
+
+        //on demand compacts only when an insert needs the room
+        let mut p = Page::new(0);
+        assert_eq!(CompactionPolicy::OnDemand, p.get_compaction_policy());
+        fill(&mut p);
+        assert_eq!(FIXED_PAGE_META_SIZE + 8 * 500, p.free_start());
+        assert_eq!(Some(1), p.add_value(&get_random_byte_vec(1000)));
+        assert_eq!(FIXED_PAGE_META_SIZE + 5 * 500 + 1000, p.free_start());
+
+        //aggressive compacts as soon as enough dead space builds up
+        let mut p = Page::new(0);
+        p.set_compaction_policy(CompactionPolicy::Aggressive {
+            dead_space_threshold: 1000,
+        });
+        let values = fill(&mut p);
+        //the second delete reached the threshold the third did not
+        assert_eq!(FIXED_PAGE_META_SIZE + 6 * 500, p.free_start());
+        assert_eq!(values[7], p.get_value(7).unwrap());
+        assert_eq!(Some(()), p.update_value(0, &[1; 100]));
+        assert_eq!(FIXED_PAGE_META_SIZE + 6 * 500, p.free_start());
+        assert_eq!(Some(()), p.update_value(2, &[2; 100]));
+        assert_eq!(FIXED_PAGE_META_SIZE + 3 * 500 + 200, p.free_start());
+        assert_eq!(vec![1; 100], p.get_value(0).unwrap());
+        assert_eq!(values[6], p.get_value(6).unwrap());
+
+        //the policy is not part of the page image
+        let copy = Page::from_bytes(*p.to_bytes()).unwrap();
+        assert_eq!(CompactionPolicy::OnDemand, copy.get_compaction_policy());
+        assert_eq!(p.get_compaction_policy(), p.clone().get_compaction_policy());
+    }
+
+    #[test]
+    fn hs_page_stats() {
+        init();
+        let mut p = Page::new(0);
+        assert_eq!(
+            PageStats {
+                largest_free_chunk: PAGE_SIZE - FIXED_PAGE_META_SIZE,
+                header_bytes: FIXED_PAGE_META_SIZE,
+                ..PageStats::default()
+            },
+            p.stats()
+        );
+
+        for size in [100, 200, 300, 400] {
+            p.add_value(&get_random_byte_vec(size)).unwrap();
+        }
+        p.delete_value(1).unwrap();
+        p.update_value(2, &[0; 50]).unwrap();
+        let header_bytes = FIXED_PAGE_META_SIZE + 4 * BYTES_PER_SLOT_META;
+        let stats = p.stats();
+        assert_eq!(
+            PageStats {
+                live_slots: 3,
+                dead_slots: 1,
+                live_bytes: 100 + 50 + 400,
+                dead_bytes: 200 + 250,
+                largest_free_chunk: PAGE_SIZE - header_bytes - 1000,
+                header_bytes,
+            },
+            stats
+        );
+        assert_eq!(
+            p.get_free_space(),
+            stats.dead_bytes + stats.largest_free_chunk
+        );
+
+        p.vacuum();
+        let stats = p.stats();
+        assert_eq!(0, stats.dead_bytes);
+        assert_eq!(p.get_free_space(), stats.largest_free_chunk);
+    }
+
+    #[test]
+    fn hs_page_live_dead_bytes() {
+        init();
+        //byte counts as a full slot scan sees them
+        fn scanned(p: &Page) -> (usize, usize) {
+            let live: usize = p.iter().map(|(_, v)| v.len()).sum();
+            (live, p.get_free_start() - FIXED_PAGE_META_SIZE - live)
+        }
+        let mut p = Page::new(0);
+        p.set_compaction_policy(CompactionPolicy::Never);
+        for size in [100, 200, 300, 400] {
+            p.add_value(&get_random_byte_vec(size)).unwrap();
+        }
+        assert_eq!((1000, 0), (p.live_bytes(), p.dead_bytes()));
+
+        p.delete_value(1).unwrap();
+        assert_eq!((800, 200), (p.live_bytes(), p.dead_bytes()));
+        p.update_value(2, &[0; 50]).unwrap();
+        assert_eq!((550, 450), (p.live_bytes(), p.dead_bytes()));
+        p.update_value(0, &[1; 150]).unwrap();
+        assert_eq!((600, 550), (p.live_bytes(), p.dead_bytes()));
+        assert_eq!(scanned(&p), (p.live_bytes(), p.dead_bytes()));
+
+        //the counts follow the bytes into clones loads and re-encoded directories
+        let loaded = Page::from_bytes(*p.to_bytes()).unwrap();
+        assert_eq!((600, 550), (loaded.live_bytes(), loaded.dead_bytes()));
+        p.set_slot_encoding(SlotEncoding::Compact).unwrap();
+        assert_eq!(600, p.live_bytes());
+        p.set_slot_encoding(SlotEncoding::Wide).unwrap();
+        assert_eq!(600, p.live_bytes());
+        assert_eq!(scanned(&p), (p.live_bytes(), p.dead_bytes()));
+
+        let mut patched = Page::new(0);
+        let diff = p.compare_page(patched.to_bytes().to_vec());
+        patched.apply_diff(&diff).unwrap();
+        assert_eq!(600, patched.live_bytes());
+
+        p.vacuum();
+        assert_eq!((600, 0), (p.live_bytes(), p.dead_bytes()));
+        for slot in 0..4 {
+            p.delete_value(slot);
+        }
+        assert_eq!((0, 600), (p.live_bytes(), p.dead_bytes()));
+        p.vacuum();
+        assert_eq!((0, 0), (p.live_bytes(), p.dead_bytes()));
+    }
+
+    #[test]
+    fn hs_page_compact_slot_encoding() {
+        init();
+        let value = [7u8; 10];
+        let mut wide = Page::new(0);
+        while wide.add_value(&value).is_some() {}
+        let mut compact = Page::new(0);
+        assert_eq!(Some(()), compact.set_slot_encoding(SlotEncoding::Compact));
+        assert_eq!(SlotEncoding::Compact, compact.get_slot_encoding());
+        while compact.add_value(&value).is_some() {}
+        let wide_count = wide.iter().count();
+        let compact_count = compact.iter().count();
+        assert_eq!((PAGE_SIZE - FIXED_PAGE_META_SIZE) / 16, wide_count);
+        assert_eq!((PAGE_SIZE - FIXED_PAGE_META_SIZE) / 14, compact_count);
+
+        //every slot state survives the packed in_use bits and a reload
+        compact.delete_value(3).unwrap();
+        compact.update_value(5, &[1, 2, 3]).unwrap();
+        let compact = Page::from_bytes(*compact.to_bytes()).unwrap();
+        assert_eq!(SlotEncoding::Compact, compact.get_slot_encoding());
+        assert_eq!(None, compact.get_value(3));
+        assert_eq!(Some(vec![1, 2, 3]), compact.get_value(5));
+        assert_eq!(compact_count - 1, compact.iter().count());
+
+        //converting back needs 2 more bytes per slot than a full page has
+        let mut full = compact.clone();
+        assert_eq!(None, full.set_slot_encoding(SlotEncoding::Wide));
+        assert_eq!(SlotEncoding::Compact, full.get_slot_encoding());
+        assert_eq!(compact_count - 1, full.iter().count());
+
+        let mut p = Page::new(0);
+        let vals = get_random_vec_of_byte_vec(40, 10, 60);
+        for v in &vals {
+            p.add_value(v).unwrap();
+        }
+        p.delete_value(7).unwrap();
+        let wide_free = p.get_free_space();
+        assert_eq!(Some(()), p.set_slot_encoding(SlotEncoding::Compact));
+        assert_eq!(wide_free + 40 * 2, p.get_free_space());
+        assert_eq!(Some(7), p.add_value(&vals[7]));
+        assert_eq!(Some(()), p.set_slot_encoding(SlotEncoding::Wide));
+        assert_eq!(SlotEncoding::Wide, p.get_slot_encoding());
+        for (i, v) in vals.iter().enumerate() {
+            assert_eq!(Some(v.clone()), p.get_value(i as SlotId));
+        }
+
+        let mut big = SizedPage::<32768>::new(0);
+        assert_eq!(None, big.set_slot_encoding(SlotEncoding::Compact));
+        let mut largest = SizedPage::<MAX_COMPACT_SLOT_PAGE_SIZE>::new(0);
+        assert_eq!(Some(()), largest.set_slot_encoding(SlotEncoding::Compact));
+        let record = get_random_byte_vec(largest.get_free_space() - COMPACT_BYTES_PER_SLOT_META);
+        assert_eq!(Some(0), largest.add_value(&record));
+        assert_eq!(record, largest.get_value(0).unwrap());
+    }
+
+    #[test]
+    fn hs_page_prefix_compression() {
+        init();
+        let record = |i: usize| format!("customer-record-region-west-{:06}", i).into_bytes();
+        let mut plain = Page::new(0);
+        let mut prefixed = Page::new(0);
+        assert_eq!(Some(()), prefixed.set_prefix_compression(true));
+        assert!(prefixed.is_prefix_compressed());
+        let mut i = 0;
+        while prefixed.add_value(&record(i)).is_some() {
+            if plain.add_value(&record(i)).is_some() {
+                assert_eq!(
+                    plain.get_value(i as SlotId),
+                    prefixed.get_value(i as SlotId)
+                );
+            }
+            i += 1;
+        }
+        assert!(i > 2 * plain.iter().count());
+        //only the first record shares nothing so it alone can be borrowed
+        assert!(prefixed.get_value_ref(0).is_some());
+        assert_eq!(None, prefixed.get_value_ref(1));
+
+        //a full page of shared prefixes cannot be stored uncompressed
+        assert_eq!(None, prefixed.set_prefix_compression(false));
+        assert!(prefixed.is_prefix_compressed());
+        assert_eq!(i, prefixed.iter().count());
+
+        //deletes updates and slot reuse re-encode the following record
+        let mut prefixed = Page::new(0);
+        prefixed.set_prefix_compression(true).unwrap();
+        let mut expected: Vec<Option<Vec<u8>>> = (0..100).map(|i| Some(record(i))).collect();
+        for v in expected.iter().flatten() {
+            prefixed.add_value(v).unwrap();
+        }
+        for slot_id in [5, 6, 9, 0] {
+            prefixed.delete_value(slot_id).unwrap();
+            expected[slot_id as usize] = None;
+        }
+        prefixed.update_value(7, b"zzz").unwrap();
+        expected[7] = Some(b"zzz".to_vec());
+        prefixed.update_value(10, &record(1000)).unwrap();
+        expected[10] = Some(record(1000));
+        assert_eq!(Some(0), prefixed.add_value(b"customer-fresh"));
+        expected[0] = Some(b"customer-fresh".to_vec());
+        assert_eq!(Some(5), prefixed.add_value(&record(5)));
+        expected[5] = Some(record(5));
+        prefixed.vacuum();
+        let prefixed = Page::from_bytes(*prefixed.to_bytes()).unwrap();
+        for (slot_id, value) in expected.iter().enumerate() {
+            assert_eq!(*value, prefixed.get_value(slot_id as SlotId));
+        }
+        let scanned: Vec<Vec<u8>> = prefixed.iter().map(|(_, v)| v.into_owned()).collect();
+        assert_eq!(
+            expected.iter().flatten().cloned().collect::<Vec<_>>(),
+            scanned
+        );
+        let consumed: Vec<Vec<u8>> = prefixed.clone().into_iter().map(|(v, _)| v).collect();
+        assert_eq!(scanned, consumed);
+
+        //shared lengths too long for three varint bytes as on pages over 2 MB read back
+        for n in [0, 127, 128, (1 << 21) + 5, u32::MAX as usize] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, n);
+            assert_eq!(Some((n, bytes.len())), read_varint(&bytes));
+        }
+
+        let mut p = Page::new(0);
+        for v in expected.iter().flatten() {
+            p.add_value(v).unwrap();
+        }
+        let before: Vec<_> = p.iter().map(|(s, v)| (s, v.into_owned())).collect();
+        let free = p.get_free_space();
+        p.set_prefix_compression(true).unwrap();
+        assert!(p.get_free_space() > free);
+        p.set_prefix_compression(false).unwrap();
+        assert_eq!(free, p.get_free_space());
+        assert_eq!(
+            before,
+            p.iter()
+                .map(|(s, v)| (s, v.into_owned()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn hs_page_shrink_slot_directory() {
+        init();
+        let mut p = Page::new(0);
+        for _ in 0..200 {
+            p.add_value(&get_random_byte_vec(4)).unwrap();
+        }
+        assert_eq!(
+            FIXED_HEADER_SIZE + 200 * HEADER_PER_VAL_SIZE,
+            p.get_header_size()
+        );
+
+        //a hole in the middle keeps its entry
+        p.delete_value(2).unwrap();
+        assert_eq!(200, p.slot_count());
+        for slot_id in (3..200).rev().step_by(2) {
+            p.delete_value(slot_id).unwrap();
+        }
+        assert_eq!(199, p.slot_count());
+        for slot_id in (4..199).step_by(2) {
+            p.delete_value(slot_id).unwrap();
+        }
+        //the trailing run and the hole before it go once the last live slot is deleted
+        assert_eq!(
+            FIXED_HEADER_SIZE + 2 * HEADER_PER_VAL_SIZE,
+            p.get_header_size()
+        );
+        assert_eq!(PAGE_SIZE - p.get_header_size() - 8, p.get_free_space());
+        let old_directory = PAGE_SIZE - 200 * HEADER_PER_VAL_SIZE..p.slot_directory_offset();
+        assert!(p.to_bytes()[old_directory].iter().all(|&b| b == 0));
+        assert_eq!(Some(2), p.add_value(&[1]));
+        assert_eq!(Some(()), p.delete_value(2));
+        p.delete_value(0).unwrap();
+        p.delete_value(1).unwrap();
+        assert_eq!(0, p.slot_count());
+        assert_eq!(PAGE_SIZE - FIXED_HEADER_SIZE, p.get_free_space());
+        assert_eq!(Some(0), p.add_value(&[1]));
+    }
+
+    #[test]
+    fn hs_page_add_value_with_slot() {
+        init();
+        let mut p = Page::new(0);
+        let b0 = get_random_byte_vec(30);
+        let b5 = get_random_byte_vec(40);
+        assert_eq!(Some(()), p.add_value_with_slot(5, &b5));
+        assert_eq!(6, p.slot_count());
+        assert_eq!(
+            FIXED_HEADER_SIZE + 6 * HEADER_PER_VAL_SIZE,
+            p.get_header_size()
+        );
+        assert_eq!(vec![5], p.iter().map(|(s, _)| s).collect::<Vec<_>>());
+        assert_eq!(None, p.add_value_with_slot(5, &b0));
+        assert_eq!(b5, p.get_value(5).unwrap());
+
+        //the skipped slots are free for both explicit and ordinary inserts
+        assert_eq!(Some(()), p.add_value_with_slot(2, &b0));
+        assert_eq!(Some(0), p.add_value(&b0));
+        assert_eq!(Some(1), p.add_value(&b0));
+        assert_eq!(Some(3), p.add_value(&b0));
+        p.delete_value(1).unwrap();
+        assert_eq!(Some(()), p.add_value_with_slot(1, &b5));
+        assert_eq!(b5, p.get_value(1).unwrap());
+
+        //no room for the record or for the directory growth
+        assert_eq!(
+            None,
+            p.add_value_with_slot(7, &get_random_byte_vec(PAGE_SIZE))
+        );
+        assert_eq!(None, p.add_value_with_slot(SlotId::MAX - 1, &b0));
+        assert_eq!(6, p.slot_count());
+
+        let mut prefixed = Page::new(0);
+        prefixed.set_prefix_compression(true).unwrap();
+        prefixed.add_value(b"prefix-aaa").unwrap();
+        prefixed.add_value_with_slot(3, b"prefix-ccc").unwrap();
+        prefixed.add_value_with_slot(2, b"prefix-bbb").unwrap();
+        assert_eq!(
+            vec![
+                b"prefix-aaa".to_vec(),
+                b"prefix-bbb".to_vec(),
+                b"prefix-ccc".to_vec()
+            ],
+            prefixed
+                .iter()
+                .map(|(_, v)| v.into_owned())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn hs_page_scan_filtered() {
+        init();
+        let mut p = Page::new(0);
+        let values = get_random_vec_of_byte_vec(50, 5, 40);
+        for v in &values {
+            p.add_value(v).unwrap();
+        }
+        p.delete_value(4).unwrap();
+        let low_first = |v: &[u8]| v[0] < 128;
+        let expected: Vec<(SlotId, Vec<u8>)> = p
+            .iter()
+            .filter(|(_, v)| low_first(v))
+            .map(|(s, v)| (s, v.into_owned()))
+            .collect();
+        let matched: Vec<(SlotId, Cow<[u8]>)> = p.scan_filtered(low_first).collect();
+        assert!(matched.iter().all(|(_, v)| matches!(v, Cow::Borrowed(_))));
+        assert_eq!(
+            expected,
+            matched
+                .into_iter()
+                .map(|(s, v)| (s, v.into_owned()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(49, p.scan_filtered(|_| true).count());
+        assert_eq!(0, p.scan_filtered(|v| v.len() > 40).count());
+    }
+
+    #[test]
+    fn hs_page_zone_map() {
+        init();
+        let key_of = |v: &[u8]| v[..4].to_vec();
+        let mut p = Page::new(0);
+        assert_eq!(None, p.zone_map());
+        let values = get_random_vec_of_byte_vec(30, 8, 40);
+        for v in &values[..10] {
+            p.add_value(v).unwrap();
+        }
+        let free = p.get_free_space();
+
+        //enabling on a populated page moves the directory and starts stale
+        assert_eq!(Some(()), p.set_zone_map(true));
+        assert_eq!(free - ZONE_MAP_REGION_SIZE, p.get_free_space());
+        assert_eq!(None, p.zone_map());
+        p.rebuild_summaries(key_of);
+        for v in &values[10..] {
+            p.add_value_with_key(v, &key_of(v)).unwrap();
+        }
+        for (i, v) in values.iter().enumerate() {
+            assert_eq!(Some(v.clone()), p.get_value(i as SlotId));
+        }
+        let keys: Vec<Vec<u8>> = values.iter().map(|v| key_of(v)).collect();
+        let zone = p.zone_map().unwrap();
+        assert_eq!(keys.iter().min(), zone.min.as_ref());
+        assert_eq!(keys.iter().max(), zone.max.as_ref());
+        assert!(zone.overlaps(&[0], &[0xff; 8]));
+        assert!(zone.overlaps(zone.max.as_ref().unwrap(), &[0xff; 8]));
+        assert!(!zone.overlaps(&[0xff; 5], &[0xff; 8]));
+        assert!(!zone.overlaps(&[], &[]));
+
+        //deletes keep the bounds valid and a reload keeps the region
+        p.delete_value(3).unwrap();
+        let copy = Page::from_bytes(*p.to_bytes()).unwrap();
+        assert_eq!(Some(zone.clone()), copy.zone_map());
+        assert_eq!(values[4], copy.get_value(4).unwrap());
+
+        //records stored without a key leave the bounds unknown
+        p.update_value(4, &[0; 4]).unwrap();
+        assert_eq!(None, p.zone_map());
+        p.rebuild_summaries(key_of);
+        assert_eq!(Some(vec![0; 4]), p.zone_map().unwrap().min);
+
+        assert_eq!(Some(()), p.set_zone_map(false));
+        assert_eq!(None, p.zone_map());
+        assert_eq!(
+            PAGE_SIZE - p.get_header_size(),
+            p.get_free_space() + p.stats().live_bytes
+        );
+        assert_eq!(values[5], p.get_value(5).unwrap());
+
+        //long keys keep a prefix and a truncated max matches anything starting with it
+        let mut long = Page::new(1);
+        long.set_zone_map(true).unwrap();
+        assert_eq!(Some(ZoneMap::default()), long.zone_map());
+        long.add_value_with_key(b"a", &[b'k'; 40]).unwrap();
+        long.add_value_with_key(b"b", &[b'c'; 20]).unwrap();
+        let zone = long.zone_map().unwrap();
+        assert_eq!(Some(vec![b'c'; ZONE_MAP_KEY_SIZE]), zone.min);
+        assert!(zone.max_truncated);
+        assert!(zone.overlaps(&[b'k'; 30], b"z"));
+        assert!(!zone.overlaps(b"l", b"z"));
+        assert!(!zone.overlaps(b"a", b"b"));
+    }
+
+    #[test]
+    fn hs_page_bloom_filter() {
+        init();
+        let key_of = |v: &[u8]| v[..8].to_vec();
+        let mut p = Page::new(0);
+        assert!(p.may_contain(b"anything"));
+        p.set_zone_map(true).unwrap();
+        p.add_value_with_key(&[9; 8], &[9; 8]).unwrap();
+        let zone = p.zone_map();
+        assert_eq!(Some(()), p.set_bloom_filter(true));
+        assert_eq!(zone, p.zone_map());
+        assert!(p.may_contain(&[1; 8]));
+        p.rebuild_summaries(key_of);
+
+        //keys are fixed so the false positive checks below are deterministic
+        let values: Vec<Vec<u8>> = (0..60u64)
+            .map(|i| [(i * 7919).to_be_bytes().to_vec(), get_random_byte_vec(10)].concat())
+            .collect();
+        for v in &values {
+            p.add_value_with_key(v, &key_of(v)).unwrap();
+        }
+        assert!(values.iter().all(|v| p.may_contain(&key_of(v))));
+        let absent = (0..200u64)
+            .filter(|i| !p.may_contain(&(i + 1_000_000).to_le_bytes()))
+            .count();
+        assert!(absent > 150);
+        assert_eq!(
+            PAGE_SIZE - p.get_header_size() - p.stats().live_bytes,
+            p.get_free_space()
+        );
+        let copy = Page::from_bytes(*p.to_bytes()).unwrap();
+        assert!(values.iter().all(|v| copy.may_contain(&key_of(v))));
+
+        //deleted keys match until the page is vacuumed with its keys
+        p.delete_value(0).unwrap();
+        assert!(p.may_contain(&[9; 8]));
+        p.vacuum_with_keys(key_of);
+        assert!(!p.may_contain(&[9; 8]));
+        assert!(p.zone_map().is_some());
+
+        p.add_value(&[7; 8]).unwrap();
+        assert!(p.may_contain(&[8; 8]));
+        p.rebuild_summaries(key_of);
+        assert!(p.may_contain(&[7; 8]));
+
+        //dropping the zone map keeps the filter which moves to the page end
+        p.set_zone_map(false).unwrap();
+        assert!(values[1..].iter().all(|v| p.may_contain(&key_of(v))));
+        assert_eq!(values[5], p.get_value(6).unwrap());
+        p.set_bloom_filter(false).unwrap();
+        assert_eq!(0, p.tail_size());
+        assert_eq!(values[5], p.get_value(6).unwrap());
+    }
+
+    #[test]
+    fn hs_page_large_slots() {
+        init();
+        const BIG: usize = 1 << 17;
+        assert_eq!(SlotEncoding::Wide, Page::new(0).get_slot_encoding());
+        let mut p = SizedPage::<BIG>::new(3);
+        assert_eq!(SlotEncoding::Large, p.get_slot_encoding());
+        assert_eq!(None, p.set_slot_encoding(SlotEncoding::Wide));
+        assert_eq!(None, Page::new(0).set_slot_encoding(SlotEncoding::Large));
+        assert_eq!(
+            BIG - FIXED_PAGE_META_SIZE - LARGE_FREE_START_SIZE,
+            p.get_free_space()
+        );
+
+        //records past u16::MAX keep their full length and offsets past 64KB resolve
+        let huge = get_random_byte_vec(100_000);
+        let small = get_random_byte_vec(20_000);
+        assert_eq!(Some(0), p.add_value(&huge));
+        assert_eq!(Some(1), p.add_value(&small));
+        assert!(p.get_free_start() > u16::MAX as usize);
+        assert_eq!(huge, p.get_value(0).unwrap());
+        assert_eq!(small, p.get_value(1).unwrap());
+
+        let mut copy = SizedPage::<BIG>::from_bytes(*p.to_bytes()).unwrap();
+        assert_eq!(3, copy.get_page_id());
+        assert_eq!(small, copy.get_value(1).unwrap());
+        assert_eq!(Some(()), copy.delete_value(0));
+        let bigger = get_random_byte_vec(110_000);
+        assert_eq!(Some(0), copy.add_value(&bigger));
+        assert_eq!(bigger, copy.get_value(0).unwrap());
+        assert_eq!(small, copy.get_value(1).unwrap());
+
+        //summaries sit between the slot directory and the trailing free_start
+        assert_eq!(Some(()), copy.set_zone_map(true));
+        assert_eq!(Some(()), copy.set_bloom_filter(true));
+        assert_eq!(bigger, copy.get_value(0).unwrap());
+        assert_eq!(small, copy.get_value(1).unwrap());
+        assert_eq!(Some(()), copy.set_zone_map(false));
+        assert_eq!(small, copy.get_value(1).unwrap());
+    }
 
     #[test]
-    fn hs_page_sizes_header_free_space() {
+    fn hs_page_secure_delete() {
         init();
-        let p = Page::new(0);
-        assert_eq!(0, p.get_page_id());
+        let leaks =
+            |p: &Page, secret: &[u8]| p.to_bytes().windows(secret.len()).any(|w| w == secret);
+        let secret = b"secret-card-4111-1111".to_vec();
 
-        assert_eq!(PAGE_SIZE - p.get_header_size(), p.get_free_space());
+        let mut plain = Page::new(0);
+        let slot = plain.add_value(&secret).unwrap();
+        plain.delete_value(slot).unwrap();
+        assert!(leaks(&plain, &secret));
+        //enabling scrubs what earlier deletes left behind
+        plain.set_secure_delete(true);
+        assert!(!leaks(&plain, &secret));
+
+        let mut p = Page::new(1);
+        p.set_secure_delete(true);
+        assert!(p.is_secure_delete());
+        let keep = get_random_byte_vec(40);
+        p.add_value(&keep).unwrap();
+        let s1 = p.add_value(&secret).unwrap();
+        let other = get_random_byte_vec(40);
+        p.add_value(&other).unwrap();
+        p.delete_value(s1).unwrap();
+        assert!(!leaks(&p, &secret));
+
+        //a shrinking update and a relocating update both leave no stale copy
+        let s3 = p.add_value(&secret).unwrap();
+        p.update_value(s3, b"short").unwrap();
+        assert!(!leaks(&p, &secret));
+        p.update_value(0, &secret).unwrap();
+        assert!(!leaks(&p, &keep));
+        p.update_value(0, &keep).unwrap();
+        assert!(!leaks(&p, &secret));
+        assert_eq!(keep, p.get_value(0).unwrap());
+        assert_eq!(b"short".to_vec(), p.get_value(s3).unwrap());
+
+        //the flag is stored in the page so it survives serialization
+        let mut copy = Page::from_bytes(*p.to_bytes()).unwrap();
+        assert!(copy.is_secure_delete());
+        copy.delete_value(2).unwrap();
+        copy.vacuum();
+        assert!(!leaks(&copy, &other));
+        copy.set_secure_delete(false);
+        assert!(!copy.is_secure_delete());
+        let s = copy.add_value(&secret).unwrap();
+        copy.delete_value(s).unwrap();
+        copy.add_value(b"pin").unwrap();
+        assert!(leaks(&copy, &secret));
+
+        //prefix compressed deletes rewrite the successor after the record is zeroed
+        let mut prefixed = Page::new(2);
+        prefixed.set_secure_delete(true);
+        prefixed.set_prefix_compression(true).unwrap();
+        prefixed.add_value(b"region-west-0001").unwrap();
+        prefixed.add_value(b"region-west-0002-secret").unwrap();
+        prefixed.add_value(b"region-west-0003").unwrap();
+        prefixed.delete_value(1).unwrap();
+        assert!(!leaks(&prefixed, b"secret"));
+        assert_eq!(b"region-west-0003".to_vec(), prefixed.get_value(2).unwrap());
     }
 
     #[test]
-    fn hs_page_debug_insert() {
+    fn hs_page_check_integrity() {
         init();
+        let mut rng = rand::thread_rng();
         let mut p = Page::new(0);
-        let n = 20;
-        let size = 20;
-        let vals = get_ascending_vec_of_byte_vec_02x(n, size, size);
-        for x in &vals {
-            p.add_value(x);
+        assert_eq!(Ok(()), p.check_integrity());
+        for _ in 0..500 {
+            let num_slots = p.get_num_slots() as SlotId;
+            match rng.gen_range(0..4) {
+                0 | 1 => {
+                    p.add_value(&get_random_byte_vec(rng.gen_range(1..200)));
+                }
+                2 if num_slots > 0 => {
+                    p.delete_value(rng.gen_range(0..num_slots));
+                }
+                _ if num_slots > 0 => {
+                    let bytes = get_random_byte_vec(rng.gen_range(1..300));
+                    p.update_value(rng.gen_range(0..num_slots), &bytes);
+                }
+                _ => {}
+            }
+            assert_eq!(Ok(()), p.check_integrity());
         }
+
+        let mut p = Page::new(0);
+        p.add_value(&[1; 100]).unwrap();
+        p.add_value(&[2; 100]).unwrap();
+        let base = FIXED_PAGE_META_SIZE as Offset;
+
+        let mut bad = p.clone();
+        bad.write_slot(1, base + 50, 100, SLOT_IN_USE_VALID);
         assert_eq!(
-            p.get_free_space(),
-            PAGE_SIZE - p.get_header_size() - n * size
+            Err(PageCorruption::SlotsOverlap {
+                first: 0,
+                second: 1
+            }),
+            bad.check_integrity()
         );
-    }
 
-    #[test]
-    fn hs_page_simple_insert() {
-        init();
-        let mut p = Page::new(5);
-        let tuple = int_vec_to_tuple(vec![0, 1, 2]);
-        let tuple_bytes = serde_cbor::to_vec(&tuple).unwrap();
-        let byte_len = tuple_bytes.len();
-        assert_eq!(Some(0), p.add_value(&tuple_bytes));
+        let mut bad = p.clone();
+        bad.write_slot(0, (PAGE_SIZE - 20) as Offset, 100, SLOT_IN_USE_VALID);
+        assert!(matches!(
+            bad.check_integrity(),
+            Err(PageCorruption::SlotOutOfBounds { slot_id: 0, .. })
+        ));
+
+        let mut bad = p.clone();
+        bad.set_free_start(FIXED_PAGE_META_SIZE + 150);
         assert_eq!(
-            PAGE_SIZE - byte_len - p.get_header_size(),
-            p.get_free_space()
+            Err(PageCorruption::RecordPastFreeStart {
+                slot_id: 1,
+                end: FIXED_PAGE_META_SIZE + 200,
+                free_start: FIXED_PAGE_META_SIZE + 150
+            }),
+            bad.check_integrity()
         );
-        let tuple_bytes2 = serde_cbor::to_vec(&tuple).unwrap();
-        assert_eq!(Some(1), p.add_value(&tuple_bytes2));
+        bad.set_free_start(2);
         assert_eq!(
-            PAGE_SIZE - p.get_header_size() - byte_len - byte_len,
-            p.get_free_space()
+            Err(PageCorruption::FreeStartOutOfRange { free_start: 2 }),
+            bad.check_integrity()
         );
+
+        let mut bad = p.clone();
+        bad.write_slot(1, base + 100, 100, 0x80 | SLOT_IN_USE_VALID);
+        assert_eq!(
+            Err(PageCorruption::UnknownSlotState {
+                slot_id: 1,
+                in_use: 0x81
+            }),
+            bad.check_integrity()
+        );
+
+        let mut bad = p.clone();
+        bad.set_num_slots(PAGE_SIZE);
+        assert!(matches!(
+            bad.check_integrity(),
+            Err(PageCorruption::DirectoryOverflow { .. })
+        ));
     }
 
     #[test]
-    fn hs_page_space() {
+    fn hs_page_iter_value_ids() {
         init();
-        let mut p = Page::new(0);
-        let size = 10;
-        let bytes = get_random_byte_vec(size);
-        assert_eq!(10, bytes.len());
-        assert_eq!(Some(0), p.add_value(&bytes));
-        assert_eq!(PAGE_SIZE - p.get_header_size() - size, p.get_free_space());
-        assert_eq!(Some(1), p.add_value(&bytes));
-        assert_eq!(
-            PAGE_SIZE - p.get_header_size() - size * 2,
-            p.get_free_space()
-        );
-        assert_eq!(Some(2), p.add_value(&bytes));
+        let mut p = Page::new(7);
+        let vals = get_random_vec_of_byte_vec(4, 10, 40);
+        for v in &vals {
+            p.add_value(v).unwrap();
+        }
+        p.delete_value(1).unwrap();
+        let ids: Vec<(ValueId, Vec<u8>)> = p
+            .iter_value_ids(3)
+            .map(|(id, value)| (id, value.into_owned()))
+            .collect();
         assert_eq!(
-            PAGE_SIZE - p.get_header_size() - size * 3,
-            p.get_free_space()
+            vec![
+                (ValueId::new_slot(3, 7, 0), vals[0].clone()),
+                (ValueId::new_slot(3, 7, 2), vals[2].clone()),
+                (ValueId::new_slot(3, 7, 3), vals[3].clone()),
+            ],
+            ids
         );
+        assert_eq!(0, Page::new(7).iter_value_ids(3).count());
     }
 
     #[test]
-    fn hs_page_get_value() {
+    fn hs_page_sorted_mode() {
         init();
+        //records are a 4 byte key then a payload
+        fn key(record: &[u8]) -> &[u8] {
+            &record[..4]
+        }
+        let record = |k: u32, tag: u8| {
+            let mut r = k.to_be_bytes().to_vec();
+            r.extend_from_slice(&[tag; 20]);
+            r
+        };
+        let keys_of = |p: &Page| -> Vec<u32> {
+            p.iter()
+                .map(|(_, r)| u32::from_be_bytes(r[..4].try_into().unwrap()))
+                .collect()
+        };
+
+        //enabling orders what is there and drops free entries
         let mut p = Page::new(0);
-        let tuple = int_vec_to_tuple(vec![0, 1, 2]);
-        let tuple_bytes = serde_cbor::to_vec(&tuple).unwrap();
-        assert_eq!(Some(0), p.add_value(&tuple_bytes));
-        let check_bytes = p.get_value(0).unwrap();
-        let check_tuple: Tuple = serde_cbor::from_slice(&check_bytes).unwrap();
-        assert_eq!(tuple_bytes, check_bytes);
-        assert_eq!(tuple, check_tuple);
+        for k in [30, 10, 50, 20] {
+            p.add_value(&record(k, 0)).unwrap();
+        }
+        p.delete_value(2).unwrap();
+        assert_eq!(None, p.add_value_sorted(&record(5, 0), key));
+        assert_eq!(Some(()), p.set_sorted(key));
+        assert!(p.is_sorted());
+        assert_eq!(vec![10, 20, 30], keys_of(&p));
+        assert_eq!(3, p.get_num_slots());
 
-        let tuple2 = int_vec_to_tuple(vec![3, 3, 3]);
-        let tuple_bytes2 = serde_cbor::to_vec(&tuple2).unwrap();
-        assert_eq!(Some(1), p.add_value(&tuple_bytes2));
-        let check_bytes2 = p.get_value(1).unwrap();
-        let check_tuple2: Tuple = serde_cbor::from_slice(&check_bytes2).unwrap();
-        assert_eq!(tuple_bytes2, check_bytes2);
-        assert_eq!(tuple2, check_tuple2);
+        //inserts land in key order after equal keys
+        assert_eq!(Some(0), p.add_value_sorted(&record(5, 0), key));
+        assert_eq!(Some(4), p.add_value_sorted(&record(40, 0), key));
+        assert_eq!(Some(3), p.add_value_sorted(&record(20, 1), key));
+        assert_eq!(vec![5, 10, 20, 20, 30, 40], keys_of(&p));
+        assert_eq!(record(20, 0), p.get_value(2).unwrap());
+        assert_eq!(record(20, 1), p.get_value(3).unwrap());
 
-        //Recheck
-        let check_bytes2 = p.get_value(1).unwrap();
-        let check_tuple2: Tuple = serde_cbor::from_slice(&check_bytes2).unwrap();
-        assert_eq!(tuple_bytes2, check_bytes2);
-        assert_eq!(tuple2, check_tuple2);
-        let check_bytes = p.get_value(0).unwrap();
-        let check_tuple: Tuple = serde_cbor::from_slice(&check_bytes).unwrap();
-        assert_eq!(tuple_bytes, check_bytes);
-        assert_eq!(tuple, check_tuple);
+        //deletes close the gap
+        assert_eq!(Some(()), p.delete_value(1));
+        assert_eq!(vec![5, 20, 20, 30, 40], keys_of(&p));
+        assert_eq!(5, p.get_num_slots());
+        assert_eq!(Ok(()), p.check_integrity());
 
-        //Check that invalid slot gets None
-        assert_eq!(None, p.get_value(2));
+        //unordered inserts and prefix compression are refused
+        assert_eq!(None, p.add_value(&record(1, 0)));
+        assert_eq!(None, p.add_value_with_slot(9, &record(1, 0)));
+        assert_eq!(vec![None], p.add_values(&[&record(1, 0)]));
+        assert_eq!(None, p.set_prefix_compression(true));
+
+        //random inserts and deletes keep the order through a reload
+        let mut rng = rand::thread_rng();
+        let mut p = Page::from_bytes(*p.to_bytes()).unwrap();
+        assert!(p.is_sorted());
+        for i in 0..100 {
+            if rng.gen_bool(0.7) {
+                p.add_value_sorted(&record(rng.gen_range(0..1000), i), key);
+            } else if p.get_num_slots() > 0 {
+                p.delete_value(rng.gen_range(0..p.get_num_slots()) as SlotId);
+            }
+            let keys = keys_of(&p);
+            assert!(keys.windows(2).all(|w| w[0] <= w[1]));
+            assert_eq!(keys.len(), p.get_num_slots());
+        }
+
+        p.clear_sorted();
+        assert!(!p.is_sorted());
+        assert!(p.add_value(&record(0, 0)).is_some());
     }
 
     #[test]
-    fn hs_page_header_size_small() {
+    fn hs_page_find_by_key() {
         init();
-        // Testing that the header is no more than 8 bytes for the header, and 6 bytes per value inserted
+        fn key(record: &[u8]) -> &[u8] {
+            &record[..2]
+        }
         let mut p = Page::new(0);
-        assert!(p.get_header_size() <= FIXED_HEADER_SIZE);
-        let bytes = get_random_byte_vec(10);
-        assert_eq!(Some(0), p.add_value(&bytes));
-        assert!(p.get_header_size() <= FIXED_HEADER_SIZE + HEADER_PER_VAL_SIZE);
-        assert_eq!(Some(1), p.add_value(&bytes));
-        assert_eq!(Some(2), p.add_value(&bytes));
-        assert_eq!(Some(3), p.add_value(&bytes));
-        assert!(p.get_header_size() <= FIXED_HEADER_SIZE + HEADER_PER_VAL_SIZE * 4);
+        p.set_sorted(key).unwrap();
+        for k in (0..400u16).step_by(2).rev() {
+            let mut r = k.to_be_bytes().to_vec();
+            r.push(k as u8);
+            p.add_value_sorted(&r, key).unwrap();
+        }
+        //duplicates resolve to the first of the run
+        p.add_value_sorted(&[0, 10, 1], key).unwrap();
+        p.add_value_sorted(&[0, 10, 2], key).unwrap();
+
+        assert_eq!(Some(0), p.find_by_key(&[0, 0], key));
+        assert_eq!(Some(5), p.find_by_key(&[0, 10], key));
+        assert_eq!(vec![0, 10, 10], p.get_value(5).unwrap());
+        assert_eq!(Some(201), p.find_by_key(&398u16.to_be_bytes(), key));
+        assert_eq!(None, p.find_by_key(&[0, 11], key));
+        assert_eq!(None, p.find_by_key(&[255, 255], key));
+        for k in (0..400u16).step_by(2) {
+            let slot = p.find_by_key(&k.to_be_bytes(), key).unwrap();
+            assert_eq!(k.to_be_bytes()[..], p.get_value(slot).unwrap()[..2]);
+        }
+
+        //a lookup reads a logarithmic number of records
+        let reads = std::cell::Cell::new(0);
+        p.find_by_key(&[1, 0], |record| {
+            reads.set(reads.get() + 1);
+            &record[..2]
+        });
+        assert!(reads.get() <= 10, "{} reads", reads.get());
+
+        assert_eq!(None, Page::new(1).find_by_key(&[0, 0], key));
     }
 
     #[test]
-    fn hs_page_header_size_full() {
+    fn hs_page_slot_flags() {
         init();
-        // Testing that the header is no more than 8 bytes for the header, and 6 bytes per value inserted
         let mut p = Page::new(0);
-        assert!(p.get_header_size() <= FIXED_HEADER_SIZE);
-        let byte_size = 10;
-        let bytes = get_random_byte_vec(byte_size);
-        // how many vals can we hold with 8 bytes
-        let num_vals: usize = (((PAGE_SIZE - FIXED_HEADER_SIZE) as f64
-            / (byte_size + HEADER_PER_VAL_SIZE) as f64)
-            .floor()) as usize;
-        if PAGE_SIZE == 4096 && FIXED_HEADER_SIZE == 8 && HEADER_PER_VAL_SIZE == 6 {
-            assert_eq!(255, num_vals);
+        let vals = get_random_vec_of_byte_vec(3, 50, 100);
+        for v in &vals {
+            p.add_value(v).unwrap();
         }
-        for _ in 0..num_vals {
-            p.add_value(&bytes);
+        assert_eq!(Some(0), p.get_slot_flags(1));
+        let flags = SLOT_FLAG_TOMBSTONE | SLOT_FLAG_LOCKED;
+        assert_eq!(Some(()), p.set_slot_flags(1, flags));
+        assert_eq!(Some(flags), p.get_slot_flags(1));
+        assert_eq!(Some(0), p.get_slot_flags(0));
+        assert_eq!(None, p.set_slot_flags(1, 0x80));
+        assert_eq!(None, p.set_slot_flags(7, SLOT_FLAG_LOCKED));
+        assert_eq!(vals[1], p.get_value(1).unwrap());
+
+        //flags follow the record through relocation compaction and a reload
+        p.delete_value(0).unwrap();
+        let grown = get_random_byte_vec(300);
+        p.update_value(1, &grown).unwrap();
+        p.vacuum();
+        let mut p = Page::from_bytes(*p.to_bytes()).unwrap();
+        assert_eq!(Some(flags), p.get_slot_flags(1));
+        assert_eq!(grown, p.get_value(1).unwrap());
+        assert_eq!(Ok(()), p.check_integrity());
+
+        //the compact encoding has no room for flags
+        assert_eq!(None, p.set_slot_encoding(SlotEncoding::Compact));
+        p.set_slot_flags(1, 0).unwrap();
+        assert_eq!(Some(()), p.set_slot_encoding(SlotEncoding::Compact));
+        assert_eq!(None, p.set_slot_flags(1, SLOT_FLAG_FORWARDED));
+        assert_eq!(Some(()), p.set_slot_flags(1, 0));
+        assert_eq!(Some(()), p.set_slot_encoding(SlotEncoding::Wide));
+
+        //delete clears them so a reused slot starts clean
+        p.set_slot_flags(2, SLOT_FLAG_TOMBSTONE).unwrap();
+        p.delete_value(2).unwrap();
+        assert_eq!(None, p.get_slot_flags(2));
+        p.set_slot_flags(1, SLOT_FLAG_FORWARDED).unwrap();
+        p.delete_value(1).unwrap();
+        assert_eq!(Some(0), p.add_value(&[1, 2, 3]));
+        assert_eq!(Some(1), p.add_value(&[4, 5, 6]));
+        assert_eq!(Some(0), p.get_slot_flags(1));
+
+        //sorted inserts shift entries with their flags
+        fn key(record: &[u8]) -> &[u8] {
+            &record[..1]
         }
-        assert!(p.get_header_size() <= FIXED_HEADER_SIZE + (num_vals * HEADER_PER_VAL_SIZE));
-        assert!(
-            p.get_free_space()
-                >= PAGE_SIZE
-                    - (byte_size * num_vals)
-                    - FIXED_HEADER_SIZE
-                    - (num_vals * HEADER_PER_VAL_SIZE)
+        let mut sorted = Page::new(1);
+        sorted.set_sorted(key).unwrap();
+        sorted.add_value_sorted(&[5], key).unwrap();
+        sorted.set_slot_flags(0, SLOT_FLAG_LOCKED).unwrap();
+        sorted.add_value_sorted(&[1], key).unwrap();
+        assert_eq!(Some(0), sorted.get_slot_flags(0));
+        assert_eq!(Some(SLOT_FLAG_LOCKED), sorted.get_slot_flags(1));
+        sorted.delete_value(0).unwrap();
+        assert_eq!(Some(SLOT_FLAG_LOCKED), sorted.get_slot_flags(0));
+    }
+
+    #[test]
+    fn hs_page_compressed_records() {
+        init();
+        let mut p = Page::new(0);
+        let text = b"the quick brown fox jumps over the lazy dog ".repeat(20);
+        let random = get_random_byte_vec(200);
+        assert_eq!(Some(0), p.add_value_compressed(&text, 64));
+        assert_eq!(Some(1), p.add_value_compressed(&random, 64));
+        //a record that shrinks by less than the threshold stays plain
+        assert_eq!(Some(2), p.add_value_compressed(&text[..120], 1000));
+
+        assert_eq!(Some(SLOT_FLAG_COMPRESSED), p.get_slot_flags(0));
+        assert_eq!(Some(0), p.get_slot_flags(1));
+        assert_eq!(Some(0), p.get_slot_flags(2));
+        assert!(p.live_bytes() < text.len() + random.len());
+        assert_eq!(Some(text.clone()), p.get_value(0));
+        assert_eq!(None, p.get_value_ref(0));
+        assert_eq!(Some(&random[..]), p.get_value_ref(1));
+        assert_eq!(
+            vec![text.clone(), random.clone(), text[..120].to_vec()],
+            p.iter().map(|(_, v)| v.into_owned()).collect::<Vec<_>>()
         );
+
+        //only the page sets the flag and it survives compaction and a reload
+        assert_eq!(None, p.set_slot_flags(0, 0));
+        assert_eq!(None, p.set_slot_flags(1, SLOT_FLAG_COMPRESSED));
+        p.set_slot_flags(0, SLOT_FLAG_COMPRESSED | SLOT_FLAG_LOCKED)
+            .unwrap();
+        p.delete_value(1).unwrap();
+        p.vacuum();
+        let mut p = Page::from_bytes(*p.to_bytes()).unwrap();
+        assert_eq!(Some(text.clone()), p.get_value(0));
+
+        //modes that cannot mark or read compressed records are refused
+        assert_eq!(None, p.set_prefix_compression(true));
+        assert_eq!(None, p.set_sorted(|r: &[u8]| &r[..1]));
+        assert_eq!(None, p.set_slot_encoding(SlotEncoding::Compact));
+
+        //an update stores the new record plain
+        p.update_value(0, &[7; 30]).unwrap();
+        assert_eq!(Some(SLOT_FLAG_LOCKED), p.get_slot_flags(0));
+        assert_eq!(Some(&[7u8; 30][..]), p.get_value_ref(0));
+        assert_eq!(Some(()), p.set_prefix_compression(true));
+        assert_eq!(Some(1), p.add_value_compressed(&text, 64));
+        assert_eq!(Some(0), p.get_slot_flags(1));
+        assert_eq!(Some(text), p.get_value(1));
+
+        //a page holds a record longer than its free space once compressed
+        let mut small = Page::new(1);
+        let long = vec![3u8; PAGE_SIZE - 10];
+        assert_eq!(None, small.add_value(&long));
+        assert_eq!(Some(0), small.add_value_compressed(&long, 0));
+        assert_eq!(Some(long), small.get_value(0));
     }
 
     #[test]
-    fn hs_page_no_space() {
+    fn hs_page_reserve_commit_abort() {
         init();
         let mut p = Page::new(0);
-        let size = PAGE_SIZE / 4;
-        let bytes = get_random_byte_vec(size);
-        assert_eq!(Some(0), p.add_value(&bytes));
-        assert_eq!(PAGE_SIZE - p.get_header_size() - size, p.get_free_space());
-        assert_eq!(Some(1), p.add_value(&bytes));
+        p.add_value(&[1; 10]).unwrap();
+        p.add_value(&[2; 10]).unwrap();
+        p.delete_value(0).unwrap();
+        let before = *p.to_bytes();
+        let free = p.get_free_space();
+
+        //reserving touches no page bytes but holds the space and the SlotId
+        let first = p.reserve(100).unwrap();
+        assert_eq!((0, 100), (first.slot_id(), first.capacity()));
+        let second = p.reserve(50).unwrap();
+        assert_eq!(2, second.slot_id());
+        assert_eq!(before, *p.to_bytes());
+        assert_eq!(free - 150 - BYTES_PER_SLOT_META, p.get_free_space());
+        assert_eq!(Some(3), p.add_value(&[3; 10]));
+        assert_eq!(None, p.add_value_with_slot(0, &[3; 10]));
         assert_eq!(
-            PAGE_SIZE - p.get_header_size() - size * 2,
-            p.get_free_space()
+            vec![None, Some(4)],
+            p.add_values(&[&[0; PAGE_SIZE], &[4; 10]])
         );
-        assert_eq!(Some(2), p.add_value(&bytes));
+
+        //abort leaves nothing behind and frees the SlotId for the next insert
+        p.abort(first);
+        assert_eq!(Some(0), p.add_value(&[5; 10]));
+        assert_eq!(Some(2), p.commit(second, &[6; 40]));
+        assert_eq!(Some(vec![6; 40]), p.get_value(2));
+        assert_eq!(Ok(()), p.check_integrity());
+
+        //a reservation can be committed only once and only with bytes that fit it
+        let third = p.reserve(20).unwrap();
+        let forged = Reservation {
+            slot_id: third.slot_id(),
+            capacity: 20,
+        };
+        assert_eq!(None, p.commit(third, &[7; 21]));
+        assert_eq!(None, p.commit(forged, &[7; 20]));
+        assert_eq!(None, p.get_value(5));
+        let free = p.get_free_space();
+        assert!(p.reserve(free + 1).is_none());
+
+        //where neighbours decide the stored form nothing can be reserved
+        let mut prefixed = Page::new(1);
+        prefixed.set_prefix_compression(true).unwrap();
+        assert!(prefixed.reserve(10).is_none());
+    }
+
+    #[test]
+    fn hs_page_insert_errors() {
+        init();
+        let mut p = Page::new(0);
+        let largest = PAGE_SIZE - FIXED_PAGE_META_SIZE - BYTES_PER_SLOT_META;
+        assert_eq!(largest, p.get_max_record_size());
+        p.set_max_record_size(Some(100));
         assert_eq!(
-            PAGE_SIZE - p.get_header_size() - size * 3,
-            p.get_free_space()
+            Err(PageInsertError::RecordTooLarge { len: 101, max: 100 }),
+            p.try_add_value(&[1; 101])
         );
-        //Should reject here
-        assert_eq!(None, p.add_value(&bytes));
+        assert_eq!(None, p.add_value(&[1; 101]));
+        assert_eq!(Ok(0), p.try_add_value(&[1; 100]));
+        assert_eq!(None, p.update_value(0, &[2; 101]));
+        assert_eq!(vec![None, Some(1)], p.add_values(&[&[3; 101], &[3; 10]]));
+        assert!(p.reserve(101).is_none());
+
+        //records over the cap go to overflow storage even though they would fit inline
+        let mut next_id = 10;
+        let (slot_id, chain) = p
+            .add_large_value(&[4; 500], || {
+                next_id += 1;
+                next_id
+            })
+            .unwrap();
+        assert_eq!((2, 1), (slot_id, chain.len()));
+
+        p.set_max_record_size(None);
+        assert_eq!(largest, p.get_max_record_size());
+        let free = p.get_free_space();
+        let reserved = PAGE_SIZE - PAGE_SIZE * p.get_fill_factor() as usize / 100;
+        let available = free - reserved;
         assert_eq!(
-            PAGE_SIZE - p.get_header_size() - size * 3,
-            p.get_free_space()
+            Err(PageInsertError::PageFull {
+                needed: available + 1 + BYTES_PER_SLOT_META,
+                available,
+            }),
+            p.try_add_value(&vec![5; available + 1])
         );
-        // Take small amount of data
-        let small_bytes = get_random_byte_vec(size / 4);
-        assert_eq!(Some(3), p.add_value(&small_bytes));
+        //the record alone fits but its slot entry does not
         assert_eq!(
-            PAGE_SIZE - p.get_header_size() - size * 3 - small_bytes.len(),
-            p.get_free_space()
+            Err(PageInsertError::SlotDirectoryFull),
+            p.try_add_value(&vec![5; available])
         );
+        p.delete_value(1).unwrap();
+        assert_eq!(Ok(1), p.try_add_value(&vec![5; available]));
+
+        let mut sorted = Page::new(1);
+        sorted.set_sorted(|r| r).unwrap();
+        assert_eq!(Err(PageInsertError::SortedPage), sorted.try_add_value(&[1]));
+        let error: CrustyError = PageInsertError::SlotDirectoryFull.into();
+        assert!(error.to_string().contains("slot entry"));
     }
 
     #[test]
-    fn hs_page_simple_delete() {
+    fn hs_page_free_slot_search() {
         init();
         let mut p = Page::new(0);
-        let tuple = int_vec_to_tuple(vec![0, 1, 2]);
-        let tuple_bytes = serde_cbor::to_vec(&tuple).unwrap();
-        assert_eq!(Some(0), p.add_value(&tuple_bytes));
-        let check_bytes = p.get_value(0).unwrap();
-        let check_tuple: Tuple = serde_cbor::from_slice(&check_bytes).unwrap();
-        assert_eq!(tuple_bytes, check_bytes);
-        assert_eq!(tuple, check_tuple);
+        for i in 0..300u16 {
+            assert_eq!(Some(i), p.add_value(&[1]));
+        }
+        for sid in [299, 250, 130, 64, 63] {
+            p.delete_value(sid).unwrap();
+        }
+        //the freed tail entry is dropped so the rest are found lowest first across words
+        assert_eq!(299, p.slot_count());
+        let mut reloaded = Page::from_bytes(*p.to_bytes()).unwrap();
+        for expected in [63, 64, 130, 250, 299, 300] {
+            assert_eq!(Some(expected), p.add_value(&[2]));
+            assert_eq!(Some(expected), reloaded.add_value(&[2]));
+        }
 
-        let tuple2 = int_vec_to_tuple(vec![3, 3, 3]);
-        let tuple_bytes2 = serde_cbor::to_vec(&tuple2).unwrap();
-        assert_eq!(Some(1), p.add_value(&tuple_bytes2));
-        let check_bytes2 = p.get_value(1).unwrap();
-        let check_tuple2: Tuple = serde_cbor::from_slice(&check_bytes2).unwrap();
-        assert_eq!(tuple_bytes2, check_bytes2);
-        assert_eq!(tuple2, check_tuple2);
+        //a reservation is skipped and its bit survives until commit
+        p.delete_value(5).unwrap();
+        let reservation = p.reserve(1).unwrap();
+        assert_eq!(5, reservation.slot_id());
+        assert_eq!(Some(301), p.add_value(&[3]));
+        p.commit(reservation, &[4]).unwrap();
+        assert_eq!(Some(302), p.add_value(&[3]));
+
+        //bytes replaced wholesale rebuild the bits
+        p.delete_value(1).unwrap();
+        let mut patched = Page::new(0);
+        patched
+            .apply_diff(&p.compare_page(patched.to_bytes().to_vec()))
+            .unwrap();
+        assert_eq!(Some(1), patched.add_value(&[5]));
+    }
+
+    #[test]
+    fn hs_page_lsn() {
+        init();
+        let mut p = Page::new(0);
+        assert_eq!(Lsn::default(), p.get_lsn());
+        let at = |page_id, slot_id| Lsn { page_id, slot_id };
+
+        assert_eq!(Some(0), p.logged(at(1, 4), |p| p.add_value(&[1, 2, 3])));
+        assert_eq!(at(1, 4), p.get_lsn());
+        assert_eq!(Some(()), p.logged(at(2, 0), |p| p.update_value(0, &[9])));
+        assert_eq!(at(2, 0), p.get_lsn());
+
+        //failed mutations and older records leave the lsn where it was
+        assert_eq!(None, p.logged(at(3, 0), |p| p.delete_value(7)));
+        assert_eq!(Some(()), p.logged(at(1, 9), |p| p.delete_value(0)));
+        assert_eq!(at(2, 0), p.get_lsn());
+
+        //the lsn is part of the page image and does not disturb the layout
+        let copy = Page::from_bytes(*p.to_bytes()).unwrap();
+        assert_eq!(at(2, 0), copy.get_lsn());
+        p.set_lsn(at(0, 1));
+        assert_eq!(at(0, 1), p.get_lsn());
+        assert_eq!(p.get_header_size(), copy.get_header_size());
+        assert_eq!(PAGE_SIZE - p.get_header_size(), p.get_free_space());
+    }
+
+    #[test]
+    fn hs_page_get_value_ref() {
+        init();
+        let mut p = Page::new(0);
+        let b0 = get_random_byte_vec(30);
+        let b1 = get_random_byte_vec(0);
+        assert_eq!(Some(0), p.add_value(&b0));
+        assert_eq!(Some(1), p.add_value(&b1));
+        assert_eq!(Some(&b0[..]), p.get_value_ref(0));
+        assert_eq!(Some(&b1[..]), p.get_value_ref(1));
+        assert_eq!(None, p.get_value_ref(2));
+
+        //the slice points into the page itself
+        let offset = p.slot_entries()[0].offset as usize;
+        assert!(std::ptr::eq(
+            &p.to_bytes()[offset],
+            &p.get_value_ref(0).unwrap()[0]
+        ));
 
-        //Delete slot 0
         assert_eq!(Some(()), p.delete_value(0));
+        assert_eq!(None, p.get_value_ref(0));
+    }
 
-        //Recheck slot 1
-        let check_bytes2 = p.get_value(1).unwrap();
-        let check_tuple2: Tuple = serde_cbor::from_slice(&check_bytes2).unwrap();
-        assert_eq!(tuple_bytes2, check_bytes2);
-        assert_eq!(tuple2, check_tuple2);
+    #[test]
+    fn hs_page_update_in_place() {
+        init();
+        let mut p = Page::new(0);
+        let b0 = get_random_byte_vec(30);
+        let b1 = get_random_byte_vec(30);
+        assert_eq!(Some(0), p.add_value(&b0));
+        assert_eq!(Some(1), p.add_value(&b1));
+        let offset = p.slot_entries()[0].offset;
 
-        //Verify slot 0 is gone
-        assert_eq!(None, p.get_value(0));
+        //shrink in place
+        let smaller = get_random_byte_vec(10);
+        assert_eq!(Some(()), p.update_value(0, &smaller));
+        assert_eq!(Some(smaller), p.get_value(0));
+        assert_eq!(offset, p.slot_entries()[0].offset);
+        assert_eq!(Some(b1.clone()), p.get_value(1));
 
-        //Check that invalid slot gets None
-        assert_eq!(None, p.get_value(2));
+        //the last record grows into the free space behind it
+        let bigger = get_random_byte_vec(50);
+        assert_eq!(Some(()), p.update_value(1, &bigger));
+        assert_eq!(Some(bigger), p.get_value(1));
+        assert_eq!(FIXED_PAGE_META_SIZE + 30 + 50, p.free_start());
 
-        //Delete slot 1
+        //deleted and missing slots cannot be updated
+        assert_eq!(Some(()), p.delete_value(0));
+        assert_eq!(None, p.update_value(0, &b0));
+        assert_eq!(None, p.update_value(5, &b0));
+    }
+
+    #[test]
+    fn hs_page_update_relocate() {
+        init();
+        let mut p = Page::new(0);
+        let size = 500;
+        let vals = get_random_vec_of_byte_vec(7, size, size);
+        for (i, v) in vals.iter().enumerate() {
+            assert_eq!(Some(i as SlotId), p.add_value(v));
+        }
+        //free space is fragmented across the deleted records
         assert_eq!(Some(()), p.delete_value(1));
+        assert_eq!(Some(()), p.delete_value(4));
+        let free = p.get_free_space();
 
-        //Verify slot 0 is gone
-        assert_eq!(None, p.get_value(1));
+        //slot 2 grows past the contiguous tail so the page compacts and keeps the SlotId
+        let grown = get_random_byte_vec(size + 600);
+        assert!(PAGE_SIZE - p.free_start() < grown.len());
+        assert_eq!(Some(()), p.update_value(2, &grown));
+        assert_eq!(Some(grown), p.get_value(2));
+        assert_eq!(free - 600, p.get_free_space());
+        for i in [0, 3, 5, 6] {
+            assert_eq!(Some(vals[i].clone()), p.get_value(i as SlotId));
+        }
+
+        //too large for the page leaves the record untouched
+        let huge = get_random_byte_vec(size + p.get_free_space() + 1);
+        assert_eq!(None, p.update_value(3, &huge));
+        assert_eq!(Some(vals[3].clone()), p.get_value(3));
     }
 
     #[test]
@@ -638,6 +3936,59 @@ mod tests {
         assert_eq!(Some(4), p.add_value(&tuple_bytes_small2));
     }
 
+    #[test]
+    fn hs_page_slot_entries() {
+        init();
+        let mut p = Page::new(0);
+        p.add_value(&[1; 10]).unwrap();
+        p.add_value(&[2; 20]).unwrap();
+        p.delete_value(0).unwrap();
+        let entries = p.slot_entries();
+        assert_eq!(2, p.slot_count());
+        assert_eq!(2, entries.len());
+        assert!(!entries[0].in_use);
+        assert!(entries[1].in_use);
+        assert_eq!(20, entries[1].length);
+        let offset = entries[1].offset as usize;
+        assert_eq!(&[2; 20], &p.to_bytes()[offset..offset + 20]);
+        assert_eq!(FIXED_PAGE_META_SIZE + 30, p.free_start());
+        assert_eq!(
+            PAGE_SIZE - 2 * BYTES_PER_SLOT_META,
+            p.slot_directory_offset()
+        );
+    }
+
+    #[test]
+    fn hs_page_new_slots_do_not_move_records() {
+        init();
+        let mut p = Page::new(0);
+        let values: Vec<Vec<u8>> = (0..20).map(|_| get_random_byte_vec(50)).collect();
+        let mut offsets = Vec::new();
+        for v in &values {
+            let slot_id = p.add_value(v).unwrap();
+            offsets.push(p.slot_entries()[slot_id as usize].offset);
+        }
+        //records stay where they were written while the directory grows down from the end
+        let entries = p.slot_entries();
+        for (i, v) in values.iter().enumerate() {
+            assert_eq!(offsets[i], entries[i].offset);
+            assert_eq!(Some(&v[..]), p.get_value_ref(i as SlotId));
+        }
+        assert_eq!(FIXED_PAGE_META_SIZE, offsets[0] as usize);
+        assert_eq!(FIXED_PAGE_META_SIZE + 20 * 50, p.free_start());
+        assert_eq!(
+            PAGE_SIZE - 20 * BYTES_PER_SLOT_META,
+            p.slot_directory_offset()
+        );
+
+        //filling the gap between body and directory exactly
+        let rest = p.slot_directory_offset() - p.free_start() - BYTES_PER_SLOT_META;
+        assert_eq!(Some(20), p.add_value(&get_random_byte_vec(rest)));
+        assert_eq!(0, p.get_free_space());
+        assert_eq!(p.free_start(), p.slot_directory_offset());
+        assert_eq!(values[19], p.get_value(19).unwrap());
+    }
+
     #[test]
     fn hs_page_size() {
         init();
@@ -699,7 +4050,7 @@ mod tests {
         let p0_bytes = p0.to_bytes();
 
         // Reconstruct the page
-        let p1 = Page::from_bytes(*p0_bytes);
+        let p1 = Page::from_bytes(*p0_bytes).unwrap();
         let p1_bytes = p1.to_bytes();
 
         // Enforce that the two pages serialize determinestically
@@ -743,14 +4094,14 @@ mod tests {
         assert_eq!(None, iter.next());
 
         //Check another way
-        let p = Page::from_bytes(page_bytes);
+        let p = Page::from_bytes(page_bytes).unwrap();
         assert_eq!(Some(tuple_bytes.clone()), p.get_value(0));
 
         for (i, x) in p.into_iter().enumerate() {
             assert_eq!(tup_vec[i], x.0);
         }
 
-        let p = Page::from_bytes(page_bytes);
+        let p = Page::from_bytes(page_bytes).unwrap();
         let mut count = 0;
         for _ in p {
             count += 1;
@@ -758,7 +4109,7 @@ mod tests {
         assert_eq!(count, 4);
 
         //Add a value and check
-        let mut p = Page::from_bytes(page_bytes);
+        let mut p = Page::from_bytes(page_bytes).unwrap();
         assert_eq!(Some(4), p.add_value(&tuple_bytes));
         //get the updated bytes
         let page_bytes = *p.to_bytes();
@@ -769,7 +4120,7 @@ mod tests {
         assert_eq!(count, 5);
 
         //Delete
-        let mut p = Page::from_bytes(page_bytes);
+        let mut p = Page::from_bytes(page_bytes).unwrap();
         p.delete_value(2);
         let mut iter = p.into_iter();
         assert_eq!(Some((tuple_bytes.clone(), 0)), iter.next());
@@ -924,11 +4275,19 @@ mod tests {
         let p_clone = p.clone();
         let mut check_vals: Vec<Vec<u8>> = p_clone.into_iter().map(|(a, _)| a).collect();
         assert!(compare_unordered_byte_vecs(&stored_vals, check_vals));
-        trace!("\n==================\n PAGE LOADED - now going to delete to make room as needed \n =======================");
+        tracing::trace!(
+            records = stored_slots.len(),
+            "page loaded, deleting to make room"
+        );
         // Delete and add remaining values until goes through all. Should result in a lot of random deletes and adds.
         while !original_vals.is_empty() {
             let bytes = original_vals.pop_front().unwrap();
-            trace!("Adding new value (left:{}). Need to make space for new record (len:{}).\n - Stored_slots {:?}", original_vals.len(), &bytes.len(), stored_slots);
+            tracing::trace!(
+                left = original_vals.len(),
+                len = bytes.len(),
+                stored_slots = ?stored_slots,
+                "adding value"
+            );
             let mut added = false;
             while !added {
                 let try_slot = p.add_value(&bytes);
@@ -939,21 +4298,21 @@ mod tests {
                         let p_clone = p.clone();
                         check_vals = p_clone.into_iter().map(|(a, _)| a).collect();
                         assert!(compare_unordered_byte_vecs(&stored_vals, check_vals));
-                        trace!("Added new value ({}) {:?}", new_slot, stored_slots);
+                        tracing::trace!(slot_id = new_slot, "added value");
                         added = true;
                     }
                     None => {
                         //Delete a random value and try again
                         let random_idx = rng.gen_range(0..stored_slots.len());
-                        trace!(
-                            "Deleting a random val to see if that makes enough space {}",
-                            stored_slots[random_idx]
+                        tracing::trace!(
+                            slot_id = stored_slots[random_idx],
+                            "deleting a random value to make room"
                         );
                         let value_id_to_del = stored_slots.remove(random_idx);
                         stored_vals.remove(random_idx);
                         p.delete_value(value_id_to_del)
                             .expect("Error deleting slot_id");
-                        trace!("Stored vals left {}", stored_slots.len());
+                        tracing::trace!(records = stored_slots.len(), "deleted value");
                     }
                 }
             }
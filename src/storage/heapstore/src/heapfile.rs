@@ -1,15 +1,67 @@
-use crate::page::Page;
+#[cfg(target_os = "linux")]
+use crate::direct::DirectFile;
+use crate::encryption::{key_for, EncryptionKey, KeyId, PageSealer, NO_KEY_ID};
+use crate::file_header::{HeapFileHeader, NO_SEGMENTS};
+use crate::fsm::{fsm_path, load_fsm, FreeSpaceMap};
+use crate::heap_page::HeapPage;
+use crate::metrics::Metrics;
+#[cfg(feature = "mmap")]
+use crate::mmap::MappedFile;
+use crate::page::{Page, PageReadError};
+use crate::segment::{self, extra_segments, remove_extra_segments, segment_path};
+use crate::wal::Wal;
 use common::prelude::*;
 use common::PAGE_SIZE;
-use std::fs::{File, OpenOptions};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::io::IoSlice;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU16, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 
 //use std::io::BufWriter;
 use std::io::{Seek, SeekFrom};
 
+/// How a heap file moves pages between memory and disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HeapFileIo {
+    /// Positional reads and writes of the file.
+    #[default]
+    Positional,
+    /// Pages copied in and out of a shared memory map of the file, left to the OS page cache
+    /// and synced with msync on flush. Needs the mmap feature.
+    Mmap,
+    /// Data pages read and written with O_DIRECT, bypassing the OS page cache so only the buffer
+    /// pool caches pages. Linux only, and the file system must support direct I/O.
+    Direct,
+}
+
+impl std::str::FromStr for HeapFileIo {
+    type Err = CrustyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pread" => Ok(HeapFileIo::Positional),
+            "mmap" => Ok(HeapFileIo::Mmap),
+            "direct" => Ok(HeapFileIo::Direct),
+            _ => Err(CrustyError::CrustyError(format!(
+                "Unknown heap file I/O {}, expected pread, mmap or direct",
+                s
+            ))),
+        }
+    }
+}
+
+/// Where a heap file's data page reads and writes go.
+enum PageStore {
+    Positional,
+    #[cfg(feature = "mmap")]
+    Mapped(MappedFile),
+    #[cfg(target_os = "linux")]
+    Direct(DirectFile),
+}
+
 /// The struct for a heap file.  
 ///
 /// HINT: You likely will want to design for interior mutability for concurrent accesses.
@@ -28,6 +80,30 @@ pub(crate) struct HeapFile {
     // The following are for profiling/ correctness checks
     pub read_count: AtomicU16,
     pub write_count: AtomicU16,
+    // Held across reading, changing and writing back a page so concurrent writers don't lose updates
+    pub(crate) write_latch: Mutex<()>,
+    // Where the file lives, so the free space map can be saved beside it
+    file_path: PathBuf,
+    // Coarse free space of every page, kept current by every page write
+    fsm: RwLock<FreeSpaceMap>,
+    // Copy of the file's first page, rewritten whenever the page count grows
+    header: RwLock<HeapFileHeader>,
+    // Data pages go through here; the header is always read and written positionally
+    store: PageStore,
+    // Pages that failed their checksum and could not be restored, until written again whole
+    pub(crate) quarantined: Mutex<BTreeSet<PageId>>,
+    // Log a page that fails its checksum is rebuilt from, if the container is logged
+    pub(crate) wal: OnceLock<Arc<Wal>>,
+    // Encrypts and decrypts the data pages, if the header names a key
+    pub(crate) sealer: Option<PageSealer>,
+    // Storage manager counters the page reads and writes are added to
+    pub(crate) metrics: OnceLock<Arc<Metrics>>,
+    // Most bytes the storage manager lets the file grow to, if limited
+    pub(crate) quota: RwLock<Option<u64>>,
+    // Pages of each segment file the heap file is split into, from its header
+    segment_pages: u32,
+    // Segment files after the first, which is file, in order; locked after file
+    segments: RwLock<Vec<File>>,
 }
 
 /// HeapFile required functions
@@ -35,6 +111,41 @@ impl HeapFile {
     /// Create a new heapfile for the given path. Return Result<Self> if able to create.
     /// Errors could arise from permissions, space, etc when trying to create the file used by HeapFile.
     pub(crate) fn new(file_path: PathBuf, container_id: ContainerId) -> Result<Self, CrustyError> {
+        Self::new_with_io(file_path, container_id, HeapFileIo::Positional)
+    }
+
+    /// Open or create a heapfile whose data pages are read and written the way io says.
+    /// Errors if io is not available in this build.
+    pub(crate) fn new_with_io(
+        file_path: PathBuf,
+        container_id: ContainerId,
+        io: HeapFileIo,
+    ) -> Result<Self, CrustyError> {
+        Self::new_with_keys(
+            file_path,
+            container_id,
+            io,
+            &HashMap::new(),
+            None,
+            NO_SEGMENTS,
+        )
+    }
+
+    /// Open or create a heapfile as new_with_io does, encrypting its data pages with the key in
+    /// keys its header names. A new file names new_key, or no key if that is None, and is split
+    /// into segment files of segment_pages pages, or kept whole if that is NO_SEGMENTS.
+    /// Errors if the key named is not in keys.
+    pub(crate) fn new_with_keys(
+        file_path: PathBuf,
+        container_id: ContainerId,
+        io: HeapFileIo,
+        keys: &HashMap<KeyId, EncryptionKey>,
+        new_key: Option<KeyId>,
+        segment_pages: u32,
+    ) -> Result<Self, CrustyError> {
+        if let Some(key_id) = new_key {
+            key_for(keys, key_id, container_id)?;
+        }
         let file = match OpenOptions::new()
             .read(true)
             .write(true)
@@ -50,18 +161,358 @@ impl HeapFile {
                 )))
             }
         };
-        panic!("TODO milestone hs");
+        let new_key = new_key.unwrap_or(NO_KEY_ID);
+        let (header, segments) =
+            Self::open_header(&file, &file_path, container_id, new_key, segment_pages).map_err(
+                |e| {
+                    CrustyError::CrustyError(format!(
+                        "Cannot open heap file {}: {}",
+                        file_path.to_string_lossy(),
+                        e
+                    ))
+                },
+            )?;
+        Self::from_parts(file, file_path, container_id, io, keys, header, segments)
+    }
+
+    /// Open an existing unencrypted heapfile without write access, creating nothing and
+    /// writing no header, for tools that must leave the file as they found it. Data pages are
+    /// read positionally. Writing a page through it fails.
+    /// Errors if the file is missing, empty or not a heap file of container_id.
+    pub(crate) fn open_read_only(
+        file_path: PathBuf,
+        container_id: ContainerId,
+    ) -> Result<Self, CrustyError> {
+        let opened = File::open(&file_path)
+            .map_err(CrustyError::from)
+            .and_then(|file| {
+                if file.metadata()?.len() == 0 {
+                    return Err(CrustyError::CrustyError("File is empty".to_string()));
+                }
+                let (header, segments) =
+                    Self::existing_header(&file, &file_path, container_id, false)?;
+                Ok((file, header, segments))
+            });
+        let (file, header, segments) = opened.map_err(|e| {
+            CrustyError::CrustyError(format!(
+                "Cannot open heap file {} read-only: {}",
+                file_path.to_string_lossy(),
+                e
+            ))
+        })?;
+        Self::from_parts(
+            file,
+            file_path,
+            container_id,
+            HeapFileIo::Positional,
+            &HashMap::new(),
+            header,
+            segments,
+        )
+    }
+
+    /// Heapfile around a file whose header has been read and the segment files after it.
+    fn from_parts(
+        file: File,
+        file_path: PathBuf,
+        container_id: ContainerId,
+        io: HeapFileIo,
+        keys: &HashMap<KeyId, EncryptionKey>,
+        header: HeapFileHeader,
+        segments: Vec<File>,
+    ) -> Result<Self, CrustyError> {
+        let sealer = PageSealer::for_file(&file_path, &header, keys)?;
+        let fsm = load_fsm(&file_path, header.page_count);
+        let rebuild_fsm = fsm.is_none();
+        let store = match io {
+            HeapFileIo::Positional => PageStore::Positional,
+            #[cfg(feature = "mmap")]
+            HeapFileIo::Mmap => PageStore::Mapped(MappedFile::new(&file)?),
+            #[cfg(not(feature = "mmap"))]
+            HeapFileIo::Mmap => {
+                return Err(CrustyError::CrustyError(
+                    "Memory mapped heap files need the mmap feature".to_string(),
+                ))
+            }
+            #[cfg(target_os = "linux")]
+            HeapFileIo::Direct => PageStore::Direct(DirectFile::open(&file_path)?),
+            #[cfg(not(target_os = "linux"))]
+            HeapFileIo::Direct => {
+                return Err(CrustyError::CrustyError(
+                    "Direct I/O heap files are only supported on Linux".to_string(),
+                ))
+            }
+        };
+        let hf = HeapFile {
+            file: Arc::new(RwLock::new(file)),
+            container_id,
+            read_count: AtomicU16::new(0),
+            write_count: AtomicU16::new(0),
+            write_latch: Mutex::new(()),
+            file_path,
+            fsm: RwLock::new(fsm.unwrap_or_else(FreeSpaceMap::new)),
+            header: RwLock::new(header),
+            store,
+            quarantined: Mutex::new(BTreeSet::new()),
+            wal: OnceLock::new(),
+            sealer,
+            metrics: OnceLock::new(),
+            quota: RwLock::new(None),
+            segment_pages: header.segment_pages,
+            segments: RwLock::new(segments),
+        };
+        if rebuild_fsm {
+            hf.refresh_fsm()?;
+        }
+        Ok(hf)
+    }
+
+    /// Header of a just opened file and the segment files after it, writing a fresh header
+    /// naming key_id and segment_pages if the file is empty. Segment files left beside an empty
+    /// file belong to no page and are removed.
+    /// Errors if the file is not a heap file of this container or is shorter than its header says.
+    fn open_header(
+        file: &File,
+        file_path: &Path,
+        container_id: ContainerId,
+        key_id: KeyId,
+        segment_pages: u32,
+    ) -> Result<(HeapFileHeader, Vec<File>), CrustyError> {
+        if file.metadata()?.len() == 0 {
+            remove_extra_segments(file_path)?;
+            let mut header = HeapFileHeader::new(container_id);
+            header.key_id = key_id;
+            header.segment_pages = segment_pages;
+            Self::write_header(file, &header)?;
+            file.sync_data()?;
+            return Ok((header, Vec::new()));
+        }
+        Self::existing_header(file, file_path, container_id, true)
+    }
+
+    /// Header of a file that already has one and the segment files after it, opened for
+    /// writing if writable.
+    /// Errors if the file is not a heap file of this container or is shorter than its header says.
+    fn existing_header(
+        file: &File,
+        file_path: &Path,
+        container_id: ContainerId,
+        writable: bool,
+    ) -> Result<(HeapFileHeader, Vec<File>), CrustyError> {
+        let header = HeapFileHeader::from_bytes(&Self::read_header(file)?)?;
+        if header.container_id != container_id {
+            return Err(CrustyError::CrustyError(format!(
+                "Header belongs to container {} not {}",
+                header.container_id, container_id
+            )));
+        }
+        let mut segments = Vec::new();
+        if header.segment_pages != NO_SEGMENTS {
+            for path in extra_segments(file_path) {
+                segments.push(OpenOptions::new().read(true).write(writable).open(path)?);
+            }
+        }
+        let stored = Self::pages_in(file, &segments);
+        if header.page_count > stored {
+            return Err(CrustyError::CrustyError(format!(
+                "Header counts {} pages but only {} are stored",
+                header.page_count, stored
+            )));
+        }
+        Ok((header, segments))
+    }
+
+    /// Attach the counters page reads and writes are added to. Only the first set is used.
+    pub(crate) fn set_metrics(&self, metrics: Arc<Metrics>) {
+        let _ = self.metrics.set(metrics);
+    }
+
+    /// The file's header as last written.
+    pub(crate) fn header(&self) -> HeapFileHeader {
+        *self.header.read().unwrap()
+    }
+
+    /// Lowest page the free space map says has room for a record of len bytes.
+    /// The map is coarse, so the insert can still fail; report what the page really had with
+    /// note_free_space so it is not offered again.
+    pub(crate) fn find_page_with_space(&self, len: usize) -> Option<PageId> {
+        self.fsm.read().unwrap().find(len)
+    }
+
+    /// Record the free bytes of a page learned without writing it.
+    pub(crate) fn note_free_space(&self, pid: PageId, free: usize) {
+        self.fsm.write().unwrap().update(pid, free);
+    }
+
+    /// Force every page written so far to disk, with msync for a mapped file.
+    pub(crate) fn sync(&self) -> Result<(), CrustyError> {
+        #[cfg(feature = "mmap")]
+        if let PageStore::Mapped(map) = &self.store {
+            map.flush()?;
+        }
+        if let Some(sealer) = &self.sealer {
+            sealer.sync()?;
+        }
+        self.sync_segments(&self.file.read().unwrap())
+    }
+
+    /// Sync the data of the file and every segment after it. The caller holds file.
+    fn sync_segments(&self, file: &File) -> Result<(), CrustyError> {
+        file.sync_data()?;
+        for segment in self.segments.read().unwrap().iter() {
+            segment.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Segment files of the heap file, the first included.
+    pub(crate) fn segment_count(&self) -> usize {
+        self.segments.read().unwrap().len() + 1
+    }
+
+    /// Bytes of the heap file and its segments together.
+    pub(crate) fn file_len(&self) -> Result<u64, CrustyError> {
+        let file = self.file.read().unwrap();
+        let mut len = file.metadata()?.len();
+        for segment in self.segments.read().unwrap().iter() {
+            len += segment.metadata()?.len();
+        }
+        Ok(len)
+    }
+
+    /// Save the free space map beside the heap file so the next open need not scan every page.
+    pub(crate) fn persist_fsm(&self) -> Result<(), CrustyError> {
+        let fsm = self.fsm.read().unwrap();
+        fs::write(fsm_path(&self.file_path), fsm.to_bytes())?;
+        Ok(())
+    }
+
+    /// Build the free space map again by reading every page, for when the saved one may not
+    /// match them. Pages that cannot be read are treated as full.
+    pub(crate) fn refresh_fsm(&self) -> Result<(), CrustyError> {
+        let file = self.file.read().unwrap();
+        let mut fsm = FreeSpaceMap::new();
+        for pid in 0..self.num_pages() {
+            let page = self
+                .read_data_page(&file, pid)
+                .and_then(|page| match &self.sealer {
+                    Some(sealer) => sealer.unseal(pid, &page).map_err(CrustyError::CrustyError),
+                    None => Ok(page),
+                });
+            match page {
+                Ok(page) => fsm.update(pid, page.get_free_space()),
+                Err(e) => {
+                    warn!(
+                        "Treating unreadable page {} of heap file {} as full: {}",
+                        pid, self.container_id, e
+                    );
+                    fsm.update(pid, 0);
+                }
+            }
+        }
+        *self.fsm.write().unwrap() = fsm;
+        Ok(())
     }
 
     /// Return the number of pages for this HeapFile.
     /// Return type is PageId (alias for another type) as we cannot have more
     /// pages than PageId can hold.
+    /// The count is kept in the header page, so pages left past it by a torn extend are not
+    /// counted and get overwritten by the next allocation.
     pub fn num_pages(&self) -> PageId {
-        panic!("TODO milestone hs");
+        self.header.read().unwrap().page_count
+    }
+
+    /// Append an empty page to the file and return its PageId.
+    /// The grown length is synced so the id is not handed out again after a crash.
+    pub(crate) fn allocate_page(&self) -> Result<PageId, CrustyError> {
+        // Held for writing so two allocations cannot pick the same id
+        let file = self.file.write().unwrap();
+        let mut header = self.header.write().unwrap();
+        let pid = header.page_count;
+        if pid == PageId::MAX {
+            return Err(CrustyError::CrustyError(format!(
+                "Heap file {} cannot hold more than {} pages",
+                self.container_id,
+                PageId::MAX
+            )));
+        }
+        #[cfg(feature = "profile")]
+        {
+            self.write_count.fetch_add(1, Ordering::Relaxed);
+        }
+        let page = Page::new(pid);
+        self.write_data_page(&file, &page)?;
+        // The page goes down before the count that covers it
+        header.page_count += 1;
+        Self::write_header(&file, &header)?;
+        self.sync_segments(&file)?;
+        self.note_free_space(pid, page.get_free_space());
+        tracing::trace!(
+            container_id = self.container_id,
+            page_id = pid,
+            "allocated page"
+        );
+        Ok(pid)
+    }
+
+    /// Drop every page from keep on, shortening the file and removing the segment files wholly
+    /// past it. Returns how many segment files were removed. The header is written first so a
+    /// crash part way leaves a file whose header covers no page that is gone.
+    pub(crate) fn truncate_pages(&self, keep: PageId) -> Result<usize, CrustyError> {
+        let file = self.file.write().unwrap();
+        let mut header = self.header.write().unwrap();
+        if keep >= header.page_count {
+            return Ok(0);
+        }
+        header.page_count = keep;
+        Self::write_header(&file, &header)?;
+        let dropped = self.cut_segments(&file, Self::offset_of(keep))?;
+        self.sync_segments(&file)?;
+        if let Some(sealer) = &self.sealer {
+            sealer.truncate(keep)?;
+        }
+        // Mapped bytes past the end of the file cannot be touched, so map what is left
+        #[cfg(feature = "mmap")]
+        if let PageStore::Mapped(map) = &self.store {
+            map.remap(&file)?;
+        }
+        self.fsm.write().unwrap().truncate(keep as usize);
+        Ok(dropped)
+    }
+
+    /// Cut the file and its segments to len bytes, removing the segment files wholly past it,
+    /// and return how many were removed. The caller holds file for writing.
+    fn cut_segments(&self, file: &File, len: u64) -> Result<usize, CrustyError> {
+        let (last, last_len) = segment::end_of(self.segment_pages, len);
+        let mut segments = self.segments.write().unwrap();
+        let mut dropped = 0;
+        while segments.len() > last {
+            segments.pop();
+            let path = segment_path(&self.file_path, segments.len() + 1);
+            fs::remove_file(&path)?;
+            tracing::trace!(
+                container_id = self.container_id,
+                segment = segments.len() + 1,
+                "removed segment"
+            );
+            dropped += 1;
+        }
+        match last {
+            0 => file.set_len(last_len)?,
+            n => {
+                if let Some(segment) = segments.get(n - 1) {
+                    segment.set_len(last_len)?;
+                }
+            }
+        }
+        Ok(dropped)
     }
 
     /// Read the page from the file.
     /// Errors could arise from the filesystem or invalid pageId
+    /// A page whose checksum does not match is rebuilt from the log if it can be and
+    /// quarantined with a CorruptPage error if not.
     /// Note: that std::io::{Seek, SeekFrom} require Write locks on the underlying std::fs::File
     pub(crate) fn read_page_from_file(&self, pid: PageId) -> Result<Page, CrustyError> {
         //If profiling count reads
@@ -69,24 +520,329 @@ impl HeapFile {
         {
             self.read_count.fetch_add(1, Ordering::Relaxed);
         }
-        panic!("TODO milestone hs");
+        let page = {
+            let file = self.file.read().unwrap();
+            let num_pages = self.num_pages();
+            if pid >= num_pages {
+                return Err(CrustyError::CrustyError(format!(
+                    "Page {} is past the end of heap file {} ({} pages)",
+                    pid, self.container_id, num_pages
+                )));
+            }
+            self.read_data_page(&file, pid)?
+        };
+        tracing::trace!(container_id = self.container_id, page_id = pid, "read page");
+        if let Some(metrics) = self.metrics.get() {
+            metrics.page_read();
+        }
+        let page = match self.unsealed(pid, page) {
+            Ok(page) => page,
+            Err(reason) => return self.restore_corrupt_page(pid, reason),
+        };
+        if page.verify_checksum() {
+            return Ok(page);
+        }
+        let reason = PageReadError::ChecksumMismatch {
+            stored: page.stored_checksum(),
+            computed: page.compute_checksum(),
+        };
+        self.restore_corrupt_page(pid, reason.to_string())
     }
 
     /// Take a page and write it to the underlying file.
     /// This could be an existing page or a new page
     pub(crate) fn write_page_to_file(&self, page: &Page) -> Result<(), CrustyError> {
-        trace!(
-            "Writing page {} to file {}",
-            page.get_page_id(),
-            self.container_id
+        tracing::trace!(
+            container_id = self.container_id,
+            page_id = page.get_page_id(),
+            "write page"
         );
         //If profiling count writes
         #[cfg(feature = "profile")]
         {
             self.write_count.fetch_add(1, Ordering::Relaxed);
         }
-        panic!("TODO milestone hs");
+        let page = &stamped(page);
+        // Held for writing so a page appended here cannot race allocate_page for the same id
+        let file = self.file.write().unwrap();
+        let mut header = self.header.write().unwrap();
+        let pid = page.get_page_id();
+        if pid > header.page_count {
+            return Err(CrustyError::CrustyError(format!(
+                "Writing page {} to heap file {} ({} pages) would leave a gap",
+                pid, self.container_id, header.page_count
+            )));
+        }
+        self.write_data_page(&file, page)?;
+        if pid == header.page_count {
+            header.page_count += 1;
+            Self::write_header(&file, &header)?;
+        }
+        self.note_free_space(pid, page.get_free_space());
+        self.quarantined.lock().unwrap().remove(&pid);
+        Ok(())
+    }
+
+    /// Write pages sorted by page id, each run of consecutive pages with a single vectored write.
+    /// Returns the number of writes issued.
+    /// Stores that cannot write a run at once write the pages one at a time.
+    pub(crate) fn write_pages_to_file(&self, pages: &[Page]) -> Result<usize, CrustyError> {
+        #[cfg(feature = "profile")]
+        {
+            self.write_count
+                .fetch_add(pages.len() as u16, Ordering::Relaxed);
+        }
+        let pages: Vec<Page> = pages.iter().map(stamped).collect();
+        let file = self.file.write().unwrap();
+        let mut header = self.header.write().unwrap();
+        let mut writes = 0;
+        // A run is cut where it crosses into another segment file
+        let same_run = |a: &Page, b: &Page| {
+            a.get_page_id() + 1 == b.get_page_id()
+                && self.locate(a.get_page_id()).0 == self.locate(b.get_page_id()).0
+        };
+        for run in pages.chunk_by(same_run) {
+            let first = run[0].get_page_id();
+            if first > header.page_count {
+                return Err(CrustyError::CrustyError(format!(
+                    "Writing page {} to heap file {} ({} pages) would leave a gap",
+                    first, self.container_id, header.page_count
+                )));
+            }
+            tracing::trace!(
+                container_id = self.container_id,
+                page_id = first,
+                pages = run.len(),
+                "write pages"
+            );
+            match &self.store {
+                PageStore::Positional => {
+                    let run: Vec<Page> = run
+                        .iter()
+                        .map(|p| self.sealed(p))
+                        .collect::<Result<_, _>>()?;
+                    let (segment, offset) = self.locate(first);
+                    self.in_segment(&file, segment, true, |f| {
+                        Self::write_run_at(f, offset, &run)
+                    })?;
+                    if let Some(metrics) = self.metrics.get() {
+                        metrics.pages_written(run.len());
+                    }
+                    writes += 1;
+                }
+                #[allow(unreachable_patterns)]
+                _ => {
+                    for page in run {
+                        self.write_data_page(&file, page)?;
+                    }
+                    writes += run.len();
+                }
+            }
+            for page in run {
+                self.note_free_space(page.get_page_id(), page.get_free_space());
+                self.quarantined.lock().unwrap().remove(&page.get_page_id());
+            }
+            let end = first + run.len() as PageId;
+            if end > header.page_count {
+                header.page_count = end;
+                Self::write_header(&file, &header)?;
+            }
+        }
+        Ok(writes)
+    }
+
+    /// Read a data page through the mapping when there is one.
+    /// Only the first segment is mapped or opened for direct I/O; the rest are read positionally.
+    fn read_data_page(&self, file: &File, pid: PageId) -> Result<Page, CrustyError> {
+        let (segment, offset) = self.locate(pid);
+        if segment > 0 {
+            return self.in_segment(file, segment, false, |f| Self::read_at(f, offset));
+        }
+        match &self.store {
+            #[cfg(feature = "mmap")]
+            PageStore::Mapped(map) => match map.read_page(offset) {
+                Some(page) => page,
+                None => Self::read_at(file, offset),
+            },
+            #[cfg(target_os = "linux")]
+            PageStore::Direct(direct) => direct.read_page(offset),
+            PageStore::Positional => Self::read_at(file, offset),
+        }
+    }
+
+    /// Write a data page through the mapping when there is one.
+    /// A page past the end of the mapping extends the file, which is then mapped again.
+    /// An encrypted file gets the page sealed.
+    /// The caller holds the file for writing.
+    fn write_data_page(&self, file: &File, page: &Page) -> Result<(), CrustyError> {
+        let page = &self.sealed(page)?;
+        let (segment, offset) = self.locate(page.get_page_id());
+        if let Some(metrics) = self.metrics.get() {
+            metrics.pages_written(1);
+        }
+        if segment > 0 {
+            return self.in_segment(file, segment, true, |f| Self::write_at(f, page, offset));
+        }
+        match &self.store {
+            #[cfg(feature = "mmap")]
+            PageStore::Mapped(map) => {
+                if !map.write_page(page, offset) {
+                    Self::write_at(file, page, offset)?;
+                    map.remap(file)?;
+                }
+                Ok(())
+            }
+            #[cfg(target_os = "linux")]
+            PageStore::Direct(direct) => direct.write_page(page, offset),
+            PageStore::Positional => Self::write_at(file, page, offset),
+        }
     }
+
+    /// Call f with the segment file segment of the heap file, file itself for segment 0.
+    /// With create, segment files up to it that do not exist yet are created, for which the
+    /// caller holds file for writing; otherwise a missing one is an error.
+    fn in_segment<T>(
+        &self,
+        file: &File,
+        segment: usize,
+        create: bool,
+        f: impl FnOnce(&File) -> Result<T, CrustyError>,
+    ) -> Result<T, CrustyError> {
+        if segment == 0 {
+            return f(file);
+        }
+        if let Some(found) = self.segments.read().unwrap().get(segment - 1) {
+            return f(found);
+        }
+        if !create {
+            return Err(CrustyError::CrustyError(format!(
+                "Heap file {} has no segment {}",
+                self.container_id, segment
+            )));
+        }
+        let mut segments = self.segments.write().unwrap();
+        while segments.len() < segment {
+            let path = segment_path(&self.file_path, segments.len() + 1);
+            segments.push(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&path)?,
+            );
+            tracing::trace!(
+                container_id = self.container_id,
+                segment = segments.len(),
+                "created segment"
+            );
+        }
+        f(&segments[segment - 1])
+    }
+
+    /// Whole data pages stored after the header in the file and the segments after it
+    fn pages_in(file: &File, segments: &[File]) -> PageId {
+        let len: u64 = std::iter::once(file)
+            .chain(segments)
+            .map(|f| f.metadata().map_or(0, |m| m.len()))
+            .sum();
+        (len / PAGE_SIZE as u64)
+            .saturating_sub(1)
+            .min(PageId::MAX as u64) as PageId
+    }
+
+    /// Byte offset of a page in the file, which starts with the header, as if it were whole
+    fn offset_of(pid: PageId) -> u64 {
+        (pid as u64 + 1) * PAGE_SIZE as u64
+    }
+
+    /// Segment file a page is stored in and its offset there
+    fn locate(&self, pid: PageId) -> (usize, u64) {
+        segment::locate(self.segment_pages, Self::offset_of(pid))
+    }
+
+    /// Positional read of the header page
+    #[cfg(unix)]
+    fn read_header(file: &File) -> Result<[u8; PAGE_SIZE], CrustyError> {
+        use std::os::unix::fs::FileExt;
+        let mut data = [0u8; PAGE_SIZE];
+        file.read_exact_at(&mut data, 0)?;
+        Ok(data)
+    }
+
+    /// Positional write of the header page
+    #[cfg(unix)]
+    fn write_header(file: &File, header: &HeapFileHeader) -> Result<(), CrustyError> {
+        use std::os::unix::fs::FileExt;
+        file.write_all_at(&header.to_bytes(), 0)?;
+        Ok(())
+    }
+
+    /// Seek then read the header on platforms without positional I/O
+    #[cfg(not(unix))]
+    fn read_header(mut file: &File) -> Result<[u8; PAGE_SIZE], CrustyError> {
+        let mut data = [0u8; PAGE_SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Seek then write the header on platforms without positional I/O
+    #[cfg(not(unix))]
+    fn write_header(mut file: &File, header: &HeapFileHeader) -> Result<(), CrustyError> {
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header.to_bytes())?;
+        Ok(())
+    }
+
+    /// Positional read so concurrent readers share the file under a read lock
+    #[cfg(unix)]
+    fn read_at(file: &File, offset: u64) -> Result<Page, CrustyError> {
+        Page::read_at(file, offset)
+    }
+
+    /// Positional write leaving the file cursor alone
+    #[cfg(unix)]
+    fn write_at(file: &File, page: &Page, offset: u64) -> Result<(), CrustyError> {
+        page.write_at(file, offset)
+    }
+
+    /// Consecutive pages written with one vectored write from offset, the first page's.
+    /// The caller holds the file for writing, so moving the cursor races no one.
+    fn write_run_at(mut file: &File, offset: u64, run: &[Page]) -> Result<(), CrustyError> {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut bufs: Vec<IoSlice> = run.iter().map(|p| IoSlice::new(p.to_bytes())).collect();
+        let mut bufs = &mut bufs[..];
+        while !bufs.is_empty() {
+            match file.write_vectored(bufs)? {
+                0 => return Err(std::io::Error::from(std::io::ErrorKind::WriteZero).into()),
+                n => IoSlice::advance_slices(&mut bufs, n),
+            }
+        }
+        Ok(())
+    }
+
+    /// Seek then read on platforms without positional I/O
+    #[cfg(not(unix))]
+    fn read_at(mut file: &File, offset: u64) -> Result<Page, CrustyError> {
+        file.seek(SeekFrom::Start(offset))?;
+        Page::read_from(&mut file)
+    }
+
+    /// Seek then write on platforms without positional I/O
+    #[cfg(not(unix))]
+    fn write_at(mut file: &File, page: &Page, offset: u64) -> Result<(), CrustyError> {
+        file.seek(SeekFrom::Start(offset))?;
+        page.write_to(&mut file)
+    }
+}
+
+/// Copy of page with its checksum stamped for writing, so a read can tell it was torn or
+/// changed on disk.
+fn stamped(page: &Page) -> Page {
+    let mut page = page.clone();
+    page.update_checksum();
+    page
 }
 
 #[cfg(test)]
@@ -119,6 +875,8 @@ mod test {
         p0.add_value(&bytes);
         let bytes = get_random_byte_vec(100);
         p0.add_value(&bytes);
+        //pages are written with their checksum stamped
+        p0.update_checksum();
         let p0_bytes = p0.to_bytes();
 
         hf.write_page_to_file(&p0);
@@ -135,6 +893,8 @@ mod test {
         p1.add_value(&bytes);
         let bytes = get_random_byte_vec(100);
         p1.add_value(&bytes);
+        //pages are written with their checksum stamped
+        p1.update_checksum();
         let p1_bytes = p1.to_bytes();
 
         hf.write_page_to_file(&p1);
@@ -154,4 +914,199 @@ mod test {
             assert_eq!(*hf.write_count.get_mut(), 2);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn hs_hf_allocate_and_reopen() {
+        init();
+        let f = gen_random_test_sm_dir();
+        let tdir = TempDir::new(f, true);
+        let mut f = tdir.to_path_buf();
+        f.push(gen_rand_string(4));
+        f.set_extension("hf");
+
+        let hf = HeapFile::new(f.to_path_buf(), 3).unwrap();
+        assert_eq!(0, hf.num_pages());
+        assert!(hf.read_page_from_file(0).is_err());
+        assert_eq!(0, hf.allocate_page().unwrap());
+        assert_eq!(1, hf.allocate_page().unwrap());
+        assert_eq!(0, hf.read_page_from_file(1).unwrap().slot_count());
+
+        let mut p1 = hf.read_page_from_file(1).unwrap();
+        let bytes = get_random_byte_vec(100);
+        p1.add_value(&bytes).unwrap();
+        hf.write_page_to_file(&p1).unwrap();
+        //pages are written where their id says and never past the end of the file
+        assert!(hf.write_page_to_file(&Page::new(3)).is_err());
+        drop(hf);
+
+        let hf = HeapFile::new(f.to_path_buf(), 3).unwrap();
+        assert_eq!(2, hf.num_pages());
+        assert_eq!(Some(bytes), hf.read_page_from_file(1).unwrap().get_value(0));
+        assert_eq!(2, hf.allocate_page().unwrap());
+    }
+
+    #[test]
+    fn hs_hf_open_read_only() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let path = tdir.join("1.hf");
+        let mut p0 = Page::new(0);
+        p0.add_value(&get_random_byte_vec(100));
+        HeapFile::new(path.clone(), 1)
+            .unwrap()
+            .write_page_to_file(&p0)
+            .unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        let hf = HeapFile::open_read_only(path.clone(), 1).unwrap();
+        assert_eq!(1, hf.num_pages());
+        assert_eq!(
+            p0.get_value(0),
+            hf.read_page_from_file(0).unwrap().get_value(0)
+        );
+        assert!(hf.write_page_to_file(&Page::new(1)).is_err());
+        assert!(HeapFile::open_read_only(path.clone(), 2).is_err());
+        assert_eq!(bytes, fs::read(&path).unwrap());
+
+        //a missing file is not created and an empty one gets no header
+        let missing = tdir.join("2.hf");
+        assert!(HeapFile::open_read_only(missing.clone(), 2).is_err());
+        assert!(!missing.exists());
+        fs::write(&missing, []).unwrap();
+        assert!(HeapFile::open_read_only(missing.clone(), 2).is_err());
+        assert_eq!(0, fs::metadata(&missing).unwrap().len());
+    }
+
+    #[test]
+    fn hs_hf_write_runs() {
+        init();
+        let f = gen_random_test_sm_dir();
+        let tdir = TempDir::new(f, true);
+        let mut f = tdir.to_path_buf();
+        f.push(gen_rand_string(4));
+        f.set_extension("hf");
+
+        let hf = HeapFile::new(f.to_path_buf(), 4).unwrap();
+        let mut pages = Vec::new();
+        for pid in [0, 1, 2, 4, 5, 7] {
+            let mut page = Page::new(pid);
+            page.add_value(&get_random_byte_vec(100 + pid as usize))
+                .unwrap();
+            pages.push(page);
+        }
+        //a run may append to the file but not skip past its end
+        assert!(hf.write_pages_to_file(&pages[3..]).is_err());
+        assert_eq!(1, hf.write_pages_to_file(&pages[..3]).unwrap());
+        for pid in 3..7 {
+            hf.write_page_to_file(&Page::new(pid)).unwrap();
+        }
+        assert_eq!(3, hf.write_pages_to_file(&pages).unwrap());
+        assert_eq!(8, hf.num_pages());
+        for page in &pages {
+            let pid = page.get_page_id();
+            assert_eq!(
+                page.get_value(0),
+                hf.read_page_from_file(pid).unwrap().get_value(0)
+            );
+        }
+        drop(hf);
+        let hf = HeapFile::new(f.to_path_buf(), 4).unwrap();
+        assert_eq!(8, hf.num_pages());
+        let last = hf.read_page_from_file(7).unwrap().get_value(0);
+        assert_eq!(Some(107), last.map(|v| v.len()));
+    }
+
+    #[test]
+    fn hs_hf_header() {
+        init();
+        let f = gen_random_test_sm_dir();
+        let tdir = TempDir::new(f, true);
+        let path = tdir.join("7.hf");
+
+        let hf = HeapFile::new(path.clone(), 7).unwrap();
+        hf.allocate_page().unwrap();
+        hf.write_page_to_file(&Page::new(1)).unwrap();
+        let header = hf.header();
+        assert_eq!((7, 2), (header.container_id, header.page_count));
+        drop(hf);
+
+        //the first page of the file says what it is
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(3 * PAGE_SIZE, bytes.len());
+        let stored = HeapFileHeader::from_bytes(bytes[..PAGE_SIZE].try_into().unwrap()).unwrap();
+        assert_eq!(header, stored);
+
+        assert!(HeapFile::new(path.clone(), 8).is_err());
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(2 * PAGE_SIZE as u64).unwrap();
+        assert!(HeapFile::new(path.clone(), 7).is_err());
+        fs::write(&path, Page::new(0).to_bytes()).unwrap();
+        assert!(HeapFile::new(path, 7).is_err());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn hs_hf_mmap() {
+        init();
+        let f = gen_random_test_sm_dir();
+        let tdir = TempDir::new(f, true);
+        let path = tdir.join("4.hf");
+
+        let hf = HeapFile::new_with_io(path.clone(), 4, HeapFileIo::Mmap).unwrap();
+        assert_eq!(0, hf.allocate_page().unwrap());
+        let mut p0 = hf.read_page_from_file(0).unwrap();
+        let bytes = get_random_byte_vec(100);
+        p0.add_value(&bytes).unwrap();
+        hf.write_page_to_file(&p0).unwrap();
+        //appending grows the file and the mapping
+        let mut p1 = Page::new(1);
+        p1.add_value(&bytes).unwrap();
+        hf.write_page_to_file(&p1).unwrap();
+        assert_eq!(2, hf.num_pages());
+        assert_eq!(
+            Some(bytes.clone()),
+            hf.read_page_from_file(1).unwrap().get_value(0)
+        );
+        hf.sync().unwrap();
+        drop(hf);
+
+        //mapped and positional files share one format
+        let hf = HeapFile::new(path, 4).unwrap();
+        assert_eq!(2, hf.num_pages());
+        p0.update_checksum();
+        assert_eq!(p0.to_bytes(), hf.read_page_from_file(0).unwrap().to_bytes());
+        assert_eq!(Some(bytes), hf.read_page_from_file(1).unwrap().get_value(0));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn hs_hf_direct_io() {
+        init();
+        let f = gen_random_test_sm_dir();
+        let tdir = TempDir::new(f, true);
+        let path = tdir.join("5.hf");
+
+        let hf = match HeapFile::new_with_io(path.clone(), 5, HeapFileIo::Direct) {
+            Ok(hf) => hf,
+            Err(e) => {
+                // Some file systems, tmpfs among them, refuse O_DIRECT
+                warn!("Skipping direct I/O test: {}", e);
+                return;
+            }
+        };
+        assert_eq!(0, hf.allocate_page().unwrap());
+        let mut p0 = hf.read_page_from_file(0).unwrap();
+        let bytes = get_random_byte_vec(100);
+        p0.add_value(&bytes).unwrap();
+        hf.write_page_to_file(&p0).unwrap();
+        hf.write_page_to_file(&Page::new(1)).unwrap();
+        assert_eq!(2, hf.num_pages());
+        p0.update_checksum();
+        assert_eq!(p0.to_bytes(), hf.read_page_from_file(0).unwrap().to_bytes());
+        drop(hf);
+
+        let hf = HeapFile::new(path, 5).unwrap();
+        assert_eq!(2, hf.num_pages());
+        assert_eq!(Some(bytes), hf.read_page_from_file(0).unwrap().get_value(0));
+    }
+}
@@ -1,7 +1,13 @@
+use crate::buffer_pool::BufferPool;
+use crate::forward::is_forwarded;
 use crate::heap_page::HeapPage;
 use crate::heap_page::HeapPageIntoIter;
 use crate::heapfile::HeapFile;
+use crate::overflow::{read_overflow_chain, OverflowPointer};
+use crate::page::Page;
+use crate::prefetch::ReadAhead;
 use common::prelude::*;
+use std::iter::Peekable;
 use std::sync::Arc;
 
 #[allow(dead_code)]
@@ -11,20 +17,153 @@ use std::sync::Arc;
 /// This should store the state/metadata required to iterate through the file.
 ///
 /// HINT: This will need an Arc<HeapFile>
+///
+/// Records come out in page order then slot order, so ValueIds only ever increase. Each page is
+/// copied when the scan reaches it and the page count is checked again before every page, so
+/// records inserted on later pages while the scan runs are seen, while ones inserted on the
+/// current or earlier pages are not. No record is returned twice unless it is moved to a later
+/// page during the scan. Records in overflow pages come out in the slot order of their pointers.
 pub struct HeapFileIterator {
-    //TODO milestone hs
+    tid: TransactionId,
+    hf: Arc<HeapFile>,
+    /// Pages are read through the pool when there is one so cached changes are seen
+    bp: Option<Arc<BufferPool>>,
+    /// Page the current page iterator came from
+    page_id: PageId,
+    /// Next page to read once the current one runs out
+    next_page_id: PageId,
+    /// Page the scan stops before, the end of the file if None
+    end_page: Option<PageId>,
+    /// Records on the first page below this slot are skipped by new_from
+    skip_below: SlotId,
+    page_iter: Option<Peekable<HeapPageIntoIter>>,
+    /// Slots of the current page holding forwarding markers, which are not records
+    forwarded: Vec<SlotId>,
+    /// Slots of the current page with overflow pointers still to be returned, in slot order
+    overflow: Vec<(SlotId, OverflowPointer)>,
+    /// Reads the pages after the current one into the pool in the background
+    read_ahead: Option<ReadAhead>,
 }
 
 /// Required HeapFileIterator functions
 impl HeapFileIterator {
     /// Create a new HeapFileIterator that stores the tid, and heapFile pointer.
     /// This should initialize the state required to iterate through the heap file.
-    pub(crate) fn new(tid: TransactionId, hf: Arc<HeapFile>) -> Self {
-        panic!("TODO milestone hs");
+    pub(crate) fn new(
+        tid: TransactionId,
+        hf: Arc<HeapFile>,
+        bp: Option<Arc<BufferPool>>,
+        read_ahead: Option<ReadAhead>,
+    ) -> Self {
+        HeapFileIterator {
+            tid,
+            hf,
+            bp,
+            page_id: 0,
+            next_page_id: 0,
+            end_page: None,
+            skip_below: 0,
+            page_iter: None,
+            forwarded: Vec::new(),
+            overflow: Vec::new(),
+            read_ahead,
+        }
+    }
+
+    /// Iterator starting at value_id itself, or at the start of its page if it has no slot.
+    pub(crate) fn new_from(
+        tid: TransactionId,
+        hf: Arc<HeapFile>,
+        bp: Option<Arc<BufferPool>>,
+        read_ahead: Option<ReadAhead>,
+        value_id: ValueId,
+    ) -> Self {
+        HeapFileIterator {
+            tid,
+            hf,
+            bp,
+            page_id: 0,
+            next_page_id: value_id.page_id.unwrap_or(0),
+            end_page: None,
+            skip_below: value_id.slot_id.unwrap_or(0),
+            page_iter: None,
+            forwarded: Vec::new(),
+            overflow: Vec::new(),
+            read_ahead,
+        }
+    }
+}
+
+/// One of the disjoint page ranges StorageManager::scan_partitions splits a container into.
+/// Iterating it yields the records of its pages as a HeapFileIterator would, and the partitions
+/// of a container can be moved to different threads and iterated at the same time.
+pub struct ScanPartition {
+    tid: TransactionId,
+    hf: Arc<HeapFile>,
+    bp: Option<Arc<BufferPool>>,
+    read_ahead: Option<ReadAhead>,
+    first_page: PageId,
+    end_page: Option<PageId>,
+}
+
+impl ScanPartition {
+    pub(crate) fn new(
+        tid: TransactionId,
+        hf: Arc<HeapFile>,
+        bp: Option<Arc<BufferPool>>,
+        read_ahead: Option<ReadAhead>,
+        first_page: PageId,
+        end_page: Option<PageId>,
+    ) -> Self {
+        ScanPartition {
+            tid,
+            hf,
+            bp,
+            read_ahead,
+            first_page,
+            end_page,
+        }
+    }
+
+    /// First page of the partition.
+    pub fn first_page(&self) -> PageId {
+        self.first_page
     }
 
-    pub(crate) fn new_from(tid: TransactionId, hf: Arc<HeapFile>, value_id: ValueId) -> Self {
-        panic!("TODO milestone hs");
+    /// Page the partition ends before. None for the last partition, which also covers pages
+    /// appended after the split.
+    pub fn end_page(&self) -> Option<PageId> {
+        self.end_page
+    }
+}
+
+impl IntoIterator for ScanPartition {
+    type Item = (Vec<u8>, ValueId);
+    type IntoIter = HeapFileIterator;
+
+    fn into_iter(self) -> Self::IntoIter {
+        HeapFileIterator {
+            tid: self.tid,
+            hf: self.hf,
+            bp: self.bp,
+            page_id: 0,
+            next_page_id: self.first_page,
+            end_page: self.end_page,
+            skip_below: 0,
+            page_iter: None,
+            forwarded: Vec::new(),
+            overflow: Vec::new(),
+            read_ahead: self.read_ahead,
+        }
+    }
+}
+
+impl HeapFileIterator {
+    fn read_page(&self, page_id: PageId) -> Result<Page, CrustyError> {
+        match &self.bp {
+            Some(bp) => bp.get_page(&self.hf, page_id, false),
+            None => self.hf.read_page_from_file(page_id),
+        }
     }
 }
 
@@ -33,6 +172,64 @@ impl HeapFileIterator {
 impl Iterator for HeapFileIterator {
     type Item = (Vec<u8>, ValueId);
     fn next(&mut self) -> Option<Self::Item> {
-        panic!("TODO milestone hs");
+        loop {
+            if let Some(iter) = &mut self.page_iter {
+                let skip_below = self.skip_below;
+                let forwarded = &self.forwarded;
+                while iter
+                    .next_if(|&(_, sid)| sid < skip_below || forwarded.contains(&sid))
+                    .is_some()
+                {}
+                let next_record = iter.peek().map(|&(_, sid)| sid);
+                if let Some(&(slot_id, pointer)) = self.overflow.first() {
+                    if next_record.is_none_or(|record| slot_id < record) {
+                        self.overflow.remove(0);
+                        let id = ValueId::new_slot(self.hf.container_id, self.page_id, slot_id);
+                        match read_overflow_chain(pointer, |page_id| self.read_page(page_id)) {
+                            Ok(value) => return Some((value, id)),
+                            Err(e) => {
+                                error!("Skipping overflow record {:?}: {}", id, e);
+                                continue;
+                            }
+                        }
+                    }
+                }
+                if let Some((value, slot_id)) = iter.next() {
+                    let id = ValueId::new_slot(self.hf.container_id, self.page_id, slot_id);
+                    return Some((value, id));
+                }
+                self.skip_below = 0;
+            }
+            let num_pages = self.hf.num_pages();
+            let end = self.end_page.map_or(num_pages, |end| end.min(num_pages));
+            if self.next_page_id >= end {
+                self.page_iter = None;
+                return None;
+            }
+            if let Some(read_ahead) = &mut self.read_ahead {
+                read_ahead.advance(&self.hf, self.next_page_id, end);
+            }
+            match self.read_page(self.next_page_id) {
+                Ok(page) => {
+                    self.forwarded = (0..page.get_num_slots() as SlotId)
+                        .filter(|sid| is_forwarded(&page, *sid))
+                        .collect();
+                    self.overflow = (self.skip_below..page.get_num_slots() as SlotId)
+                        .filter_map(|sid| Some((sid, page.get_overflow_pointer(sid)?)))
+                        .collect();
+                    self.page_iter = Some(page.into_iter().peekable());
+                }
+                Err(e) => {
+                    error!(
+                        "Stopping scan of container {} at page {}: {}",
+                        self.hf.container_id, self.next_page_id, e
+                    );
+                    self.page_iter = None;
+                    return None;
+                }
+            }
+            self.page_id = self.next_page_id;
+            self.next_page_id += 1;
+        }
     }
 }
@@ -0,0 +1,130 @@
+use crate::storage_manager::StorageManager;
+use common::prelude::*;
+use common::storage_trait::StorageTrait;
+use common::{Field, TableSchema, Tuple};
+use std::io::Read;
+
+/// A row of a CSV import that was not loaded, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvRowError {
+    /// Row of the input, counting from 1.
+    pub row: usize,
+    pub error: CrustyError,
+}
+
+/// What import_csv loaded.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CsvImport {
+    /// Rows read from the input, whether they were loaded or not.
+    pub rows: usize,
+    /// Ids of the tuples loaded, in the order of their rows.
+    pub ids: Vec<ValueId>,
+    /// Rows that could not be converted to a tuple of the schema and were skipped.
+    pub errors: Vec<CsvRowError>,
+}
+
+/// Tuple of schema a CSV row holds, one field per attribute in order.
+fn row_to_tuple(record: &csv::StringRecord, schema: &TableSchema) -> Result<Tuple, CrustyError> {
+    if record.len() != schema.attributes.len() {
+        return Err(CrustyError::ValidationError(format!(
+            "Row has {} fields but the schema has {} attributes",
+            record.len(),
+            schema.attributes.len()
+        )));
+    }
+    let fields = record
+        .iter()
+        .zip(&schema.attributes)
+        .map(|(field, attr)| Field::from_str(field, attr))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Tuple::new(fields))
+}
+
+impl StorageManager {
+    /// Load the rows of the comma separated values in reader, which has no header row, into
+    /// new pages at the end of a container as tuples of schema, in a transaction of its own
+    /// that is committed once the input ends. Rows that do not convert to a tuple of schema
+    /// are skipped and reported with why. Errs if the input cannot be read or a tuple does not
+    /// fit on a page, with the rows loaded before it committed.
+    pub fn import_csv(
+        &self,
+        container_id: ContainerId,
+        reader: impl Read,
+        schema: &TableSchema,
+    ) -> Result<CsvImport, CrustyError> {
+        let tid = TransactionId::new();
+        let mut import = CsvImport::default();
+        let mut failed = None;
+        let mut records = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(reader)
+            .into_records();
+        let tuples = std::iter::from_fn(|| loop {
+            let converted = match records.next()? {
+                Err(e) if e.is_io_error() => {
+                    failed = Some(CrustyError::IOError(e.to_string()));
+                    return None;
+                }
+                Err(e) => Err(CrustyError::ValidationError(e.to_string())),
+                Ok(record) => row_to_tuple(&record, schema),
+            };
+            import.rows += 1;
+            match converted {
+                Ok(tuple) => return Some(tuple.to_bytes()),
+                Err(error) => import.errors.push(CsvRowError {
+                    row: import.rows,
+                    error,
+                }),
+            }
+        });
+        let loaded = self.bulk_insert(container_id, tuples, tid);
+        self.commit_transaction(tid)?;
+        import.ids = loaded?;
+        if let Some(e) = failed {
+            return Err(e);
+        }
+        debug!(
+            "Imported {} of {} rows into container {}",
+            import.ids.len(),
+            import.rows,
+            container_id
+        );
+        Ok(import)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use common::DataType;
+
+    #[test]
+    fn hs_import_csv() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        sm.create_table(1).unwrap();
+        let schema = TableSchema::from_vecs(vec!["a", "b"], vec![DataType::Int, DataType::String]);
+        let input = "1,one\nx,two\n3,three\n4\n5,\"five, quoted\"\n";
+        let import = sm.import_csv(1, input.as_bytes(), &schema).unwrap();
+        assert_eq!(5, import.rows);
+        assert_eq!(
+            vec![2, 4],
+            import.errors.iter().map(|e| e.row).collect::<Vec<_>>()
+        );
+
+        //the loaded rows read back as tuples of the schema
+        let tid = TransactionId::new();
+        let tuples: Vec<Tuple> = import
+            .ids
+            .iter()
+            .map(|id| Tuple::from_bytes(&sm.get_value(*id, tid, Permissions::ReadOnly).unwrap()))
+            .collect();
+        let expected = [(1, "one"), (3, "three"), (5, "five, quoted")]
+            .map(|(a, b)| Tuple::new(vec![Field::Int(a), Field::String(b.to_string())]));
+        assert_eq!(expected.to_vec(), tuples);
+        assert!(sm.import_csv(2, input.as_bytes(), &schema).is_err());
+    }
+}
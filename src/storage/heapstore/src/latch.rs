@@ -0,0 +1,136 @@
+use crate::heap_page::HeapPage;
+use crate::page::Page;
+use common::prelude::*;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError};
+
+///page behind a read write latch so many readers can share it while a writer holds it alone
+///a holder that panics poisons the latch and later acquires panic too since the page may be half written
+pub struct LatchedPage {
+    page: RwLock<Page>,
+}
+
+impl LatchedPage {
+    pub fn new(page: Page) -> Self {
+        LatchedPage {
+            page: RwLock::new(page),
+        }
+    }
+
+    ///shared latch waiting while a writer holds the page
+    pub fn read(&self) -> RwLockReadGuard<'_, Page> {
+        self.page.read().unwrap()
+    }
+
+    ///exclusive latch waiting until every reader and writer has let go
+    pub fn write(&self) -> RwLockWriteGuard<'_, Page> {
+        self.page.write().unwrap()
+    }
+
+    ///shared latch or None if a writer holds the page
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, Page>> {
+        match self.page.try_read() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::WouldBlock) => None,
+            Err(TryLockError::Poisoned(e)) => panic!("{}", e),
+        }
+    }
+
+    ///exclusive latch or None if anyone else holds the page
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, Page>> {
+        match self.page.try_write() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::WouldBlock) => None,
+            Err(TryLockError::Poisoned(e)) => panic!("{}", e),
+        }
+    }
+
+    ///record bytes for slot_id read under a shared latch
+    pub fn get_value(&self, slot_id: SlotId) -> Option<Vec<u8>> {
+        self.read().get_value(slot_id)
+    }
+
+    ///the page without its latch
+    pub fn into_inner(self) -> Page {
+        self.page.into_inner().unwrap()
+    }
+}
+
+impl From<Page> for LatchedPage {
+    fn from(page: Page) -> Self {
+        LatchedPage::new(page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testutil::*;
+    use std::sync::Barrier;
+    use std::thread;
+
+    #[test]
+    fn hs_page_latch_try_variants() {
+        init();
+        let latched = LatchedPage::new(Page::new(0));
+        let first = latched.read();
+        let second = latched.try_read().unwrap();
+        assert!(latched.try_write().is_none());
+        drop(first);
+        drop(second);
+
+        let mut writer = latched.try_write().unwrap();
+        writer.add_value(&[1, 2, 3]).unwrap();
+        assert!(latched.try_read().is_none());
+        assert!(latched.try_write().is_none());
+        drop(writer);
+
+        assert_eq!(Some(vec![1, 2, 3]), latched.get_value(0));
+        assert_eq!(Some(vec![1, 2, 3]), latched.into_inner().get_value(0));
+    }
+
+    #[test]
+    fn hs_page_latch_concurrent_readers() {
+        init();
+        let vals = get_random_vec_of_byte_vec(20, 20, 60);
+        let mut page = Page::new(0);
+        for v in &vals {
+            page.add_value(v).unwrap();
+        }
+        let latched = LatchedPage::from(page);
+        let readers = 4;
+        //every reader holds its latch at the barrier so none can be waiting on another
+        let barrier = Barrier::new(readers);
+        thread::scope(|s| {
+            for _ in 0..readers {
+                s.spawn(|| {
+                    let page = latched.read();
+                    barrier.wait();
+                    for (i, v) in vals.iter().enumerate() {
+                        assert_eq!(Some(v.clone()), page.get_value(i as SlotId));
+                    }
+                });
+            }
+        });
+
+        //writers interleave with readers that always see whole records
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..vals.len() {
+                    latched
+                        .write()
+                        .update_value(i as SlotId, &[i as u8; 40])
+                        .unwrap();
+                }
+            });
+            for _ in 0..readers {
+                s.spawn(|| {
+                    for (i, v) in vals.iter().enumerate() {
+                        let value = latched.get_value(i as SlotId).unwrap();
+                        assert!(value == *v || value == vec![i as u8; 40]);
+                    }
+                });
+            }
+        });
+        assert_eq!(Some(vec![3; 40]), latched.get_value(3));
+    }
+}
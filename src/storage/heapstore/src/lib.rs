@@ -7,9 +7,85 @@ extern crate log;
 #[macro_use]
 extern crate serde;
 
+#[cfg(feature = "async")]
+mod async_io;
+mod backup;
+mod bp_tests;
+mod buffer_pool;
+mod checkpoint;
+mod container;
+#[cfg(target_os = "linux")]
+mod direct;
+mod encryption;
+mod export;
+mod file_header;
+mod fixed_page;
+mod forward;
+pub mod fsck;
+mod fsm;
 mod heap_page;
 mod heapfile;
 mod heapfileiter;
+mod import;
+mod latch;
+mod metrics;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod overflow;
 mod page;
+mod page_html;
+mod page_report;
+mod prefetch;
+mod quarantine;
+mod quota;
+mod recovery;
+mod replication;
+mod segment;
+mod split;
 pub mod storage_manager;
+mod temp;
 pub mod testutil;
+pub mod trace;
+mod vacuum;
+mod wal;
+mod warmup;
+pub mod workload;
+
+/// Write pages to their heap file as soon as the storage manager writes them instead of leaving
+/// them dirty in the buffer pool until evicted or flushed.
+pub const WRITE_THROUGH: bool = false;
+
+pub use buffer_pool::{
+    BufferPoolSize, BufferPoolStats, ClockPolicy, FrameKey, LruPolicy, PageGuard,
+    ReplacementPolicy, ReplacementPolicyKind,
+};
+pub use container::{ContainerInfo, ContainerOptions};
+pub use encryption::{EncryptionKey, KeyId, NO_KEY_ID};
+pub use export::ExportFormat;
+pub use file_header::{HeapFileHeader, HEAP_FILE_FORMAT_VERSION, NO_FSM_ROOT, NO_SEGMENTS};
+pub use fixed_page::{FixedRecordPage, PageLayout};
+pub use heap_page::{
+    CompactionPolicy, HeapPage, HeapPageFilter, HeapPageIter, PageCorruption, PageInsertError,
+    PageStats, Reservation, SlotEncoding, SlotEntry, ZoneMap, BLOOM_FILTER_BITS,
+    DEFAULT_FILL_FACTOR, MAX_COMPACT_SLOT_PAGE_SIZE, MAX_WIDE_SLOT_PAGE_SIZE, PAGE_FORMAT_VERSION,
+    SLOT_FLAGS_MASK, SLOT_FLAG_COMPRESSED, SLOT_FLAG_FORWARDED, SLOT_FLAG_LOCKED,
+    SLOT_FLAG_TOMBSTONE, ZONE_MAP_KEY_SIZE,
+};
+pub use heapfile::HeapFileIo;
+pub use heapfileiter::ScanPartition;
+pub use import::{CsvImport, CsvRowError};
+pub use latch::LatchedPage;
+pub use metrics::{LatencyHistogram, StorageOp, StorageStats, LATENCY_BUCKETS};
+pub use overflow::{
+    read_overflow_chain, OverflowPointer, MAX_INLINE_VALUE_SIZE, NO_NEXT_PAGE, OVERFLOW_CHUNK_SIZE,
+};
+pub use page::{Page, PageHeader, PageReadError, SizedPage};
+pub use page_report::{FreeRegion, FreeRegionKind, PageFormat, PageReport};
+pub use prefetch::DEFAULT_PREFETCH_WINDOW;
+pub use quota::DiskUsage;
+pub use replication::Standby;
+pub use segment::{read_heap_file, DEFAULT_SEGMENT_SIZE};
+pub use split::MergeError;
+pub use temp::TempContainer;
+pub use vacuum::{VacuumReport, VACUUM_SPARSE_PAGE_BYTES};
+pub use wal::{LogEntry, LogRecord, SlotImage, WAL_BUFFER_SIZE};
@@ -0,0 +1,366 @@
+use crate::buffer_pool::BufferPoolStats;
+use crate::storage_manager::StorageManager;
+use common::PAGE_SIZE;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Buckets of a latency histogram. Bucket 0 holds latencies under a microsecond and bucket i
+/// those under 2^i microseconds, with the last taking every latency longer than that.
+pub const LATENCY_BUCKETS: usize = 40;
+
+/// Storage manager operations whose latency is recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub enum StorageOp {
+    Insert,
+    BulkInsert,
+    Get,
+    Update,
+    Delete,
+    Flush,
+    Checkpoint,
+}
+
+impl StorageOp {
+    /// Every operation, in the order the statistics list them.
+    pub const ALL: [StorageOp; 7] = [
+        StorageOp::Insert,
+        StorageOp::BulkInsert,
+        StorageOp::Get,
+        StorageOp::Update,
+        StorageOp::Delete,
+        StorageOp::Flush,
+        StorageOp::Checkpoint,
+    ];
+}
+
+impl fmt::Display for StorageOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            StorageOp::Insert => "insert",
+            StorageOp::BulkInsert => "bulk insert",
+            StorageOp::Get => "get",
+            StorageOp::Update => "update",
+            StorageOp::Delete => "delete",
+            StorageOp::Flush => "flush",
+            StorageOp::Checkpoint => "checkpoint",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Latencies of an operation counted into power of two buckets of microseconds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LatencyHistogram {
+    /// Operations recorded.
+    pub count: u64,
+    /// Time they took altogether.
+    pub total: Duration,
+    /// Longest of them.
+    pub max: Duration,
+    /// Operations per bucket, LATENCY_BUCKETS of them.
+    pub buckets: Vec<u64>,
+}
+
+impl LatencyHistogram {
+    /// Average latency, 0 before the first operation.
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.count as u32
+        }
+    }
+
+    /// Upper bound of the bucket holding the latency at percentile p in 0..=100, capped by
+    /// the longest latency. 0 before the first operation.
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((p / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return bucket_bound(i).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// Longest latency bucket i holds.
+fn bucket_bound(i: usize) -> Duration {
+    if i == 0 {
+        Duration::from_micros(1)
+    } else {
+        Duration::from_micros(1u64 << i.min(63))
+    }
+}
+
+/// Bucket of a latency of micros microseconds.
+fn bucket_of(micros: u64) -> usize {
+    ((u64::BITS - micros.leading_zeros()) as usize).min(LATENCY_BUCKETS - 1)
+}
+
+/// Counters of a storage manager since it was created or its statistics were reset.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StorageStats {
+    /// Data pages read from the heap files.
+    pub page_reads: u64,
+    /// Data pages written to the heap files.
+    pub page_writes: u64,
+    /// Bytes of the data pages written.
+    pub bytes_written: u64,
+    /// Pages whose live records were moved together to close up the gaps deletes left.
+    pub compactions: u64,
+    /// Hits and misses of the buffer pool, None without one.
+    pub buffer_pool: Option<BufferPoolStats>,
+    /// Latencies of every operation.
+    pub latencies: BTreeMap<StorageOp, LatencyHistogram>,
+}
+
+impl fmt::Display for StorageStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "pages: {} reads, {} writes ({} bytes), {} compactions",
+            self.page_reads, self.page_writes, self.bytes_written, self.compactions
+        )?;
+        if let Some(bp) = &self.buffer_pool {
+            writeln!(f, "{}", bp)?;
+        }
+        for (op, latency) in &self.latencies {
+            writeln!(
+                f,
+                "{:<11} {:>8} ops mean {:>8}us p50 {:>8}us p99 {:>8}us max {:>8}us",
+                op.to_string(),
+                latency.count,
+                latency.mean().as_micros(),
+                latency.percentile(50.0).as_micros(),
+                latency.percentile(99.0).as_micros(),
+                latency.max.as_micros()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Latencies of an operation as they are recorded.
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            total_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    fn record(&self, latency: Duration) {
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        self.buckets[bucket_of(micros)].fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> LatencyHistogram {
+        let buckets: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        LatencyHistogram {
+            count: buckets.iter().sum(),
+            total: Duration::from_micros(self.total_micros.load(Ordering::Relaxed)),
+            max: Duration::from_micros(self.max_micros.load(Ordering::Relaxed)),
+            buckets,
+        }
+    }
+
+    fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.total_micros.store(0, Ordering::Relaxed);
+        self.max_micros.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Counters shared by a storage manager and its heap files.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    page_reads: AtomicU64,
+    page_writes: AtomicU64,
+    bytes_written: AtomicU64,
+    compactions: AtomicU64,
+    latencies: [Histogram; StorageOp::ALL.len()],
+}
+
+impl Metrics {
+    /// Count a data page read from a heap file.
+    pub(crate) fn page_read(&self) {
+        self.page_reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count data pages written to a heap file, pages of them.
+    pub(crate) fn pages_written(&self, pages: usize) {
+        self.page_writes.fetch_add(pages as u64, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add((pages * PAGE_SIZE) as u64, Ordering::Relaxed);
+    }
+
+    /// Count compactions of a page an operation changed, compactions of them.
+    pub(crate) fn compacted(&self, compactions: u32) {
+        if compactions > 0 {
+            self.compactions
+                .fetch_add(compactions as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Record the latency of op from now until the timer is dropped.
+    pub(crate) fn time(&self, op: StorageOp) -> OpTimer<'_> {
+        OpTimer {
+            metrics: self,
+            op,
+            start: Instant::now(),
+        }
+    }
+
+    fn histogram(&self, op: StorageOp) -> &Histogram {
+        &self.latencies[op as usize]
+    }
+
+    fn snapshot(&self, buffer_pool: Option<BufferPoolStats>) -> StorageStats {
+        StorageStats {
+            page_reads: self.page_reads.load(Ordering::Relaxed),
+            page_writes: self.page_writes.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            compactions: self.compactions.load(Ordering::Relaxed),
+            buffer_pool,
+            latencies: StorageOp::ALL
+                .iter()
+                .map(|op| (*op, self.histogram(*op).snapshot()))
+                .collect(),
+        }
+    }
+
+    fn reset(&self) {
+        self.page_reads.store(0, Ordering::Relaxed);
+        self.page_writes.store(0, Ordering::Relaxed);
+        self.bytes_written.store(0, Ordering::Relaxed);
+        self.compactions.store(0, Ordering::Relaxed);
+        for histogram in &self.latencies {
+            histogram.reset();
+        }
+    }
+}
+
+/// Records the latency of an operation when dropped, so every return is timed.
+pub(crate) struct OpTimer<'a> {
+    metrics: &'a Metrics,
+    op: StorageOp,
+    start: Instant,
+}
+
+impl Drop for OpTimer<'_> {
+    fn drop(&mut self) {
+        self.metrics.histogram(self.op).record(self.start.elapsed());
+    }
+}
+
+impl StorageManager {
+    /// Page reads and writes, compactions, buffer pool hits and misses and the latency of
+    /// every operation since the storage manager was opened or reset_stats was called.
+    pub fn stats(&self) -> StorageStats {
+        self.metrics.snapshot(self.buffer_pool_stats())
+    }
+
+    /// Start every counter of stats, the buffer pool's among them, again from 0.
+    pub fn reset_stats(&self) {
+        self.metrics.reset();
+        self.reset_buffer_pool_stats();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::prelude::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+
+    #[test]
+    fn hs_metrics_histogram_buckets() {
+        init();
+        let histogram = Histogram::default();
+        for micros in [0, 1, 3, 100, 5000] {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let latency = histogram.snapshot();
+        assert_eq!(5, latency.count);
+        assert_eq!(Duration::from_micros(5000), latency.max);
+        assert_eq!(Duration::from_micros(1), latency.percentile(20.0));
+        assert_eq!(Duration::from_micros(128), latency.percentile(80.0));
+        assert_eq!(Duration::from_micros(5000), latency.percentile(100.0));
+        assert_eq!(Duration::from_nanos(1_020_800), latency.mean());
+    }
+
+    #[test]
+    fn hs_metrics_storage_stats() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        sm.create_table(1).unwrap();
+        let tid = TransactionId::new();
+        let ids: Vec<ValueId> = (0..50)
+            .map(|i| sm.insert_value(1, vec![i as u8; 100], tid))
+            .collect();
+        for id in &ids[..10] {
+            sm.delete_value(*id, tid).unwrap();
+        }
+        sm.update_value(vec![9; 50], ids[20], tid).unwrap();
+        sm.vacuum_container(1).unwrap();
+        sm.flush_all().unwrap();
+
+        let stats = sm.stats();
+        assert_eq!(50, stats.latencies[&StorageOp::Insert].count);
+        assert_eq!(10, stats.latencies[&StorageOp::Delete].count);
+        assert_eq!(1, stats.latencies[&StorageOp::Update].count);
+        assert!(stats.compactions > 0);
+        assert!(stats.page_writes > 0);
+        assert_eq!(stats.page_writes * PAGE_SIZE as u64, stats.bytes_written);
+
+        //reads after the cache is cleared miss the buffer pool and go to the heap file
+        sm.clear_cache();
+        sm.reset_stats();
+        assert_eq!(0, sm.stats().page_writes);
+        assert_eq!(0, sm.stats().latencies[&StorageOp::Insert].count);
+        sm.get_value(ids[20], tid, Permissions::ReadOnly).unwrap();
+        sm.get_value(ids[20], tid, Permissions::ReadOnly).unwrap();
+        let stats = sm.stats();
+        assert_eq!(2, stats.latencies[&StorageOp::Get].count);
+        assert_eq!(1, stats.page_reads);
+        let bp = stats.buffer_pool.unwrap();
+        assert_eq!((1, 1), (bp.hits, bp.misses));
+
+        //compactions count against the storage manager whose page was compacted
+        let other = StorageManager::new_test_sm();
+        other.create_table(1).unwrap();
+        other.insert_value(1, vec![1; 100], tid);
+        let dead = other.insert_value(1, vec![2; 100], tid);
+        other.delete_value(dead, tid).unwrap();
+        other.vacuum_container(1).unwrap();
+        sm.get_value(ids[30], tid, Permissions::ReadOnly).unwrap();
+        assert_eq!(1, other.stats().compactions);
+        assert_eq!(0, sm.stats().compactions);
+    }
+}
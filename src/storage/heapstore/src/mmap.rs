@@ -0,0 +1,64 @@
+use crate::page::Page;
+use common::prelude::*;
+use common::PAGE_SIZE;
+use memmap2::MmapMut;
+use std::fs::File;
+use std::sync::RwLock;
+
+///shared writable mapping of a whole heap file
+///pages are copied straight out of and into the mapping without a system call and the os page
+///cache decides which stay in memory. changes reach the disk when the os writes them back or on
+///flush which calls msync
+pub(crate) struct MappedFile {
+    ///remapped whenever the file grows past it
+    map: RwLock<MmapMut>,
+}
+
+impl MappedFile {
+    ///mapping of all of file which must not be empty
+    pub(crate) fn new(file: &File) -> Result<Self, CrustyError> {
+        Ok(MappedFile {
+            map: RwLock::new(Self::map(file)?),
+        })
+    }
+
+    ///page stored at offset
+    ///None if the mapping ends before it so the caller can fall back to reading the file
+    pub(crate) fn read_page(&self, offset: u64) -> Option<Result<Page, CrustyError>> {
+        let map = self.map.read().unwrap();
+        let mut bytes = map.get(offset as usize..offset as usize + PAGE_SIZE)?;
+        Some(Page::read_from(&mut bytes))
+    }
+
+    ///copy page into the mapping at offset
+    ///false if the mapping ends before it so the caller must extend the file and remap
+    pub(crate) fn write_page(&self, page: &Page, offset: u64) -> bool {
+        let mut map = self.map.write().unwrap();
+        match map.get_mut(offset as usize..offset as usize + PAGE_SIZE) {
+            Some(dest) => {
+                dest.copy_from_slice(page.to_bytes());
+                true
+            }
+            None => false,
+        }
+    }
+
+    ///map file again after it grew
+    pub(crate) fn remap(&self, file: &File) -> Result<(), CrustyError> {
+        *self.map.write().unwrap() = Self::map(file)?;
+        Ok(())
+    }
+
+    ///msync every changed page of the mapping to disk
+    pub(crate) fn flush(&self) -> Result<(), CrustyError> {
+        self.map.read().unwrap().flush()?;
+        Ok(())
+    }
+
+    fn map(file: &File) -> Result<MmapMut, CrustyError> {
+        // Safety: the file is only changed through this heap file, whose writes take the map's
+        // write lock or happen before a remap, so no reader sees the mapping truncated under it
+        unsafe { MmapMut::map_mut(file) }
+            .map_err(|e| CrustyError::CrustyError(format!("Cannot map heap file: {}", e)))
+    }
+}
@@ -0,0 +1,562 @@
+use crate::heap_page::{
+    HeapPage, PageInsertError, BYTES_PER_SLOT_META, FIXED_PAGE_META_SIZE, SLOT_IN_USE_CONTINUATION,
+    SLOT_IN_USE_FREE, SLOT_IN_USE_OVERFLOW,
+};
+use crate::heapfile::HeapFile;
+use crate::page::Page;
+use crate::storage_manager::StorageManager;
+use crate::wal::{LogRecord, SlotImage, SlotKind};
+use common::prelude::*;
+use common::PAGE_SIZE;
+use std::sync::Arc;
+
+///encoded size of an overflow pointer total_len u32 then first_page u16
+const OVERFLOW_POINTER_SIZE: usize = 6;
+///next page id stored before the chunk on a continuation page
+const CONTINUATION_HEADER_SIZE: usize = 2;
+///next page id marking the last page of a chain
+pub const NO_NEXT_PAGE: PageId = PageId::MAX;
+///largest record add_value can ever place inline on an empty page
+pub const MAX_INLINE_VALUE_SIZE: usize = PAGE_SIZE - FIXED_PAGE_META_SIZE - BYTES_PER_SLOT_META;
+///record bytes carried by one continuation page
+pub const OVERFLOW_CHUNK_SIZE: usize = MAX_INLINE_VALUE_SIZE - CONTINUATION_HEADER_SIZE;
+
+///what an overflow slot stores in place of the record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverflowPointer {
+    pub total_len: u32,
+    pub first_page: PageId,
+}
+
+impl OverflowPointer {
+    fn to_bytes(self) -> [u8; OVERFLOW_POINTER_SIZE] {
+        let mut bytes = [0; OVERFLOW_POINTER_SIZE];
+        bytes[0..4].copy_from_slice(&self.total_len.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.first_page.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != OVERFLOW_POINTER_SIZE {
+            return None;
+        }
+        Some(OverflowPointer {
+            total_len: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            first_page: PageId::from_le_bytes(bytes[4..6].try_into().unwrap()),
+        })
+    }
+}
+
+impl Page {
+    ///inserts bytes inline when they fit within the max record size otherwise stores an overflow
+    ///pointer to a chain of continuation pages numbered by next_page_id
+    ///returns the slot and the continuation pages the caller must write or None if this page
+    ///has no room and the record would fit inline on another page or the page is sorted
+    pub fn add_large_value<F>(
+        &mut self,
+        bytes: &[u8],
+        mut next_page_id: F,
+    ) -> Option<(SlotId, Vec<Page>)>
+    where
+        F: FnMut() -> PageId,
+    {
+        let too_large = match self.try_add_value(bytes) {
+            Ok(slot_id) => return Some((slot_id, Vec::new())),
+            Err(e) => matches!(e, PageInsertError::RecordTooLarge { .. }),
+        };
+        if (!too_large && bytes.len() <= MAX_INLINE_VALUE_SIZE)
+            || bytes.len() > u32::MAX as usize
+            || self.is_sorted()
+        {
+            return None;
+        }
+
+        //claim the slot first so no page ids are handed out when the pointer does not fit
+        let slot_id = self.insert_slot(&[0; OVERFLOW_POINTER_SIZE], SLOT_IN_USE_OVERFLOW)?;
+        let page_ids: Vec<PageId> = bytes
+            .chunks(OVERFLOW_CHUNK_SIZE)
+            .map(|_| next_page_id())
+            .collect();
+        let pages = bytes
+            .chunks(OVERFLOW_CHUNK_SIZE)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let next = page_ids.get(i + 1).copied().unwrap_or(NO_NEXT_PAGE);
+                Page::new_continuation(page_ids[i], next, chunk)
+            })
+            .collect();
+
+        let pointer = OverflowPointer {
+            total_len: bytes.len() as u32,
+            first_page: page_ids[0],
+        };
+        self.slot_bytes_mut(slot_id)?
+            .copy_from_slice(&pointer.to_bytes());
+        Some((slot_id, pages))
+    }
+
+    ///overflow pointer held by slot_id or None if the slot is not an overflow slot
+    pub fn get_overflow_pointer(&self, slot_id: SlotId) -> Option<OverflowPointer> {
+        if self.get_slot_in_use(slot_id)? != SLOT_IN_USE_OVERFLOW {
+            return None;
+        }
+        OverflowPointer::from_bytes(self.slot_bytes(slot_id)?)
+    }
+
+    ///record bytes for slot_id reassembling overflow records through fetch_page
+    ///Ok(None) if the slot is invalid or deleted
+    pub fn get_value_chained<F>(
+        &self,
+        slot_id: SlotId,
+        fetch_page: F,
+    ) -> Result<Option<Vec<u8>>, CrustyError>
+    where
+        F: FnMut(PageId) -> Result<Page, CrustyError>,
+    {
+        if let Some(value) = self.get_value(slot_id) {
+            return Ok(Some(value));
+        }
+        match self.get_overflow_pointer(slot_id) {
+            Some(pointer) => read_overflow_chain(pointer, fetch_page).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    ///stores bytes as they are under the free slot_id as an overflow pointer or continuation
+    ///chunk as kind says, which is how a logged change to such a slot is redone or undone
+    pub(crate) fn add_chain_slot(
+        &mut self,
+        slot_id: SlotId,
+        bytes: &[u8],
+        kind: SlotKind,
+    ) -> Option<()> {
+        let in_use = match kind {
+            SlotKind::Overflow => SLOT_IN_USE_OVERFLOW,
+            SlotKind::Continuation => SLOT_IN_USE_CONTINUATION,
+            SlotKind::Record => return None,
+        };
+        if self
+            .get_slot_in_use(slot_id)
+            .is_some_and(|state| state != SLOT_IN_USE_FREE)
+        {
+            return None;
+        }
+        self.insert_slot_at(slot_id, bytes, in_use)
+    }
+
+    ///page holding one chunk of an overflow record followed by next
+    fn new_continuation(page_id: PageId, next: PageId, chunk: &[u8]) -> Self {
+        let mut page = Page::new(page_id);
+        let mut payload = Vec::with_capacity(CONTINUATION_HEADER_SIZE + chunk.len());
+        payload.extend_from_slice(&next.to_le_bytes());
+        payload.extend_from_slice(chunk);
+        page.insert_slot(&payload, SLOT_IN_USE_CONTINUATION)
+            .unwrap();
+        page
+    }
+
+    ///next page id and chunk if this is a continuation page
+    fn continuation_chunk(&self) -> Option<(PageId, &[u8])> {
+        if self.get_slot_in_use(0)? != SLOT_IN_USE_CONTINUATION {
+            return None;
+        }
+        let payload = self.slot_bytes(0)?;
+        if payload.len() < CONTINUATION_HEADER_SIZE {
+            return None;
+        }
+        let next = PageId::from_le_bytes(payload[0..2].try_into().unwrap());
+        Some((next, &payload[CONTINUATION_HEADER_SIZE..]))
+    }
+}
+
+///records too large for a page are stored as an overflow pointer on a page with room for one
+///and a chain of continuation pages added to the end of the heap file for the record itself
+impl StorageManager {
+    ///stores value too large for a record of a page as an overflow record and returns its id
+    ///nothing changes if the pages it needs would take hf past its quota
+    ///the caller must hold hf's write latch
+    pub(crate) fn insert_overflow(
+        &self,
+        hf: &Arc<HeapFile>,
+        value: &[u8],
+        tid: TransactionId,
+    ) -> Result<ValueId, CrustyError> {
+        if value.len() > u32::MAX as usize {
+            return Err(CrustyError::ValidationError(format!(
+                "Cannot insert a value of {} bytes, larger than an overflow chain can hold",
+                value.len()
+            )));
+        }
+        while let Some(page_id) = hf.find_page_with_space(OVERFLOW_POINTER_SIZE) {
+            let mut page = self.fetch_page(hf, page_id, tid, Permissions::ReadWrite)?;
+            let mut next_page_id = hf.num_pages();
+            let added = page.add_large_value(value, || {
+                next_page_id += 1;
+                next_page_id - 1
+            });
+            match added {
+                Some((slot_id, chain)) => {
+                    return self.store_overflow(hf, &mut page, slot_id, &chain, tid)
+                }
+                // The map only rounds free space, and the page is stored with its own again
+                None => hf.note_free_space(page_id, 0),
+            }
+        }
+        // A new page for the pointer with the chain after it
+        let chunks = value.len().div_ceil(OVERFLOW_CHUNK_SIZE) as u64;
+        hf.check_quota(hf.num_pages() as u64 + 1 + chunks)?;
+        let page_id = hf.allocate_page()?;
+        let mut page = Page::new(page_id);
+        page.set_max_record_size(self.get_max_record_size());
+        let allocated = LogRecord::AllocatePage {
+            container_id: hf.container_id,
+            page_id,
+        };
+        self.log_change(&mut page, allocated, tid)?;
+        let mut next_page_id = page_id + 1;
+        let (slot_id, chain) = page
+            .add_large_value(value, || {
+                next_page_id += 1;
+                next_page_id - 1
+            })
+            .ok_or_else(|| {
+                CrustyError::CrustyError(format!(
+                    "Cannot store an overflow pointer on new page {} of container {}",
+                    page_id, hf.container_id
+                ))
+            })?;
+        self.store_overflow(hf, &mut page, slot_id, &chain, tid)
+    }
+
+    ///adds the continuation pages of chain to the end of hf then stores page with the pointer
+    ///in slot_id so the pointer never leads to a page that is not there
+    fn store_overflow(
+        &self,
+        hf: &Arc<HeapFile>,
+        page: &mut Page,
+        slot_id: SlotId,
+        chain: &[Page],
+        tid: TransactionId,
+    ) -> Result<ValueId, CrustyError> {
+        hf.check_quota(hf.num_pages() as u64 + chain.len() as u64)?;
+        for link in chain {
+            let page_id = hf.allocate_page()?;
+            if page_id != link.get_page_id() {
+                return Err(CrustyError::CrustyError(format!(
+                    "Overflow chain of container {} expected page {} but was given {}",
+                    hf.container_id,
+                    link.get_page_id(),
+                    page_id
+                )));
+            }
+            let mut continuation = Page::new(page_id);
+            continuation.set_max_record_size(self.get_max_record_size());
+            let allocated = LogRecord::AllocatePage {
+                container_id: hf.container_id,
+                page_id,
+            };
+            self.log_change(&mut continuation, allocated, tid)?;
+            let chunk = link.slot_bytes(0).unwrap_or_default();
+            continuation
+                .add_chain_slot(0, chunk, SlotKind::Continuation)
+                .ok_or_else(|| {
+                    CrustyError::CrustyError(format!(
+                        "Cannot store an overflow chunk on new page {} of container {}",
+                        page_id, hf.container_id
+                    ))
+                })?;
+            self.log_slot(hf, &mut continuation, 0, None, tid)?;
+            self.store_page(hf, &mut continuation, tid)?;
+        }
+        self.log_slot(hf, page, slot_id, None, tid)?;
+        self.store_page(hf, page, tid)?;
+        Ok(ValueId::new_slot(
+            hf.container_id,
+            page.get_page_id(),
+            slot_id,
+        ))
+    }
+
+    ///frees the continuation pages pointer leads to so they can hold records again
+    ///the pointer itself must already be gone
+    pub(crate) fn free_overflow_chain(
+        &self,
+        hf: &Arc<HeapFile>,
+        pointer: OverflowPointer,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let mut next = pointer.first_page;
+        let chunks = (pointer.total_len as usize).div_ceil(OVERFLOW_CHUNK_SIZE);
+        for _ in 0..chunks {
+            if next == NO_NEXT_PAGE || next >= hf.num_pages() {
+                break;
+            }
+            let mut page = self.fetch_page(hf, next, tid, Permissions::ReadWrite)?;
+            let Some((following, _)) = page.continuation_chunk() else {
+                warn!(
+                    "Page {} of container {} is not an overflow continuation page",
+                    next, hf.container_id
+                );
+                break;
+            };
+            let before = SlotImage::of(&page, 0);
+            page.delete_value(0);
+            self.log_slot(hf, &mut page, 0, before, tid)?;
+            self.store_page(hf, &mut page, tid)?;
+            next = following;
+        }
+        Ok(())
+    }
+}
+
+///follows the chain from pointer.first_page and concatenates the chunks
+pub fn read_overflow_chain<F>(
+    pointer: OverflowPointer,
+    mut fetch_page: F,
+) -> Result<Vec<u8>, CrustyError>
+where
+    F: FnMut(PageId) -> Result<Page, CrustyError>,
+{
+    let total_len = pointer.total_len as usize;
+    let mut value = Vec::with_capacity(total_len);
+    let mut next = pointer.first_page;
+    while value.len() < total_len {
+        if next == NO_NEXT_PAGE {
+            return Err(CrustyError::CrustyError(format!(
+                "Overflow chain ends after {} of {} bytes",
+                value.len(),
+                total_len
+            )));
+        }
+        let page = fetch_page(next)?;
+        let (following, chunk) = match page.continuation_chunk() {
+            Some(c) if !c.1.is_empty() => c,
+            _ => {
+                return Err(CrustyError::CrustyError(format!(
+                    "Page {} is not an overflow continuation page",
+                    next
+                )))
+            }
+        };
+        value.extend_from_slice(chunk);
+        next = following;
+    }
+    if value.len() != total_len {
+        return Err(CrustyError::CrustyError(format!(
+            "Overflow chain holds {} bytes but the pointer expects {}",
+            value.len(),
+            total_len
+        )));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use std::collections::HashMap;
+    use temp_testdir::TempDir;
+
+    ///stores continuation pages and hands out page ids after first_id
+    fn chain_store(first_id: PageId) -> (HashMap<PageId, Page>, impl FnMut() -> PageId) {
+        let mut next_id = first_id;
+        (HashMap::new(), move || {
+            next_id += 1;
+            next_id - 1
+        })
+    }
+
+    #[test]
+    fn hs_page_overflow_roundtrip() {
+        init();
+        let (mut store, mut alloc) = chain_store(1);
+        let mut p = Page::new(0);
+        let small = get_random_byte_vec(100);
+        let large = get_random_byte_vec(3 * PAGE_SIZE + 17);
+
+        let (s0, pages) = p.add_large_value(&small, &mut alloc).unwrap();
+        assert!(pages.is_empty());
+        let (s1, pages) = p.add_large_value(&large, &mut alloc).unwrap();
+        assert_eq!(4, pages.len());
+        assert_eq!(
+            vec![1, 2, 3, 4],
+            pages.iter().map(|pg| pg.get_page_id()).collect::<Vec<_>>()
+        );
+        for page in pages {
+            store.insert(page.get_page_id(), page);
+        }
+
+        let fetch = |pid| {
+            store
+                .get(&pid)
+                .cloned()
+                .ok_or_else(|| CrustyError::CrustyError(format!("no page {}", pid)))
+        };
+        assert_eq!(Some(small.clone()), p.get_value_chained(s0, fetch).unwrap());
+        assert_eq!(Some(large.clone()), p.get_value_chained(s1, fetch).unwrap());
+        assert_eq!(None, p.get_value_chained(9, fetch).unwrap());
+        assert_eq!(
+            Some(OverflowPointer {
+                total_len: large.len() as u32,
+                first_page: 1
+            }),
+            p.get_overflow_pointer(s1)
+        );
+
+        //neither the pointer nor the chunks look like plain records
+        assert_eq!(None, p.get_value(s1));
+        assert_eq!(vec![s0], p.iter().map(|(s, _)| s).collect::<Vec<_>>());
+        assert_eq!(0, store[&2].iter().count());
+
+        assert_eq!(Some(()), p.delete_value(s1));
+        assert_eq!(None, p.get_value_chained(s1, fetch).unwrap());
+    }
+
+    #[test]
+    fn hs_page_overflow_survives_compaction() {
+        init();
+        let (_, mut alloc) = chain_store(1);
+        let mut p = Page::new(0);
+        let first = get_random_byte_vec(1500);
+        p.add_value(&first).unwrap();
+        let (slot, pages) = p
+            .add_large_value(&get_random_byte_vec(PAGE_SIZE), &mut alloc)
+            .unwrap();
+        let pointer = p.get_overflow_pointer(slot).unwrap();
+        p.add_value(&get_random_byte_vec(1500)).unwrap();
+
+        //a full page record forces compaction and a new slot shifts the body
+        p.delete_value(0).unwrap();
+        p.delete_value(2).unwrap();
+        let big = get_random_byte_vec(2800);
+        assert_eq!(Some(0), p.add_value(&big));
+        assert_eq!(Some(2), p.add_value(&[1, 2, 3]));
+        assert_eq!(Some(3), p.add_value(&[4]));
+        assert_eq!(Some(pointer), p.get_overflow_pointer(slot));
+        assert_eq!(big, p.get_value(0).unwrap());
+
+        let fetch = |pid: PageId| Ok(pages[pid as usize - 1].clone());
+        assert_eq!(
+            PAGE_SIZE,
+            p.get_value_chained(slot, fetch).unwrap().unwrap().len()
+        );
+    }
+
+    #[test]
+    fn hs_page_overflow_broken_chain() {
+        init();
+        let (_, mut alloc) = chain_store(5);
+        let mut p = Page::new(0);
+        let (slot, pages) = p
+            .add_large_value(&get_random_byte_vec(2 * PAGE_SIZE), &mut alloc)
+            .unwrap();
+        assert_eq!(3, pages.len());
+
+        //a page that is not part of the chain
+        let fetch = |pid: PageId| {
+            if pid == 6 {
+                Ok(Page::new(6))
+            } else {
+                Ok(pages[pid as usize - 5].clone())
+            }
+        };
+        assert!(p.get_value_chained(slot, fetch).is_err());
+
+        //a chain cut short
+        let pointer = p.get_overflow_pointer(slot).unwrap();
+        let short = OverflowPointer {
+            total_len: pointer.total_len + 1,
+            ..pointer
+        };
+        let fetch = |pid: PageId| Ok(pages[pid as usize - 5].clone());
+        assert!(read_overflow_chain(short, fetch).is_err());
+
+        //no room left for the pointer itself
+        let mut full = Page::new(1);
+        full.add_value(&get_random_byte_vec(MAX_INLINE_VALUE_SIZE))
+            .unwrap();
+        assert!(full
+            .add_large_value(&get_random_byte_vec(2 * PAGE_SIZE), &mut alloc)
+            .is_none());
+        assert!(full.add_large_value(&[0; 10], &mut alloc).is_none());
+    }
+
+    #[test]
+    fn hs_overflow_storage_manager() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        let small = get_random_vec_of_byte_vec(20, 50, 100);
+        let large = get_random_byte_vec(3 * PAGE_SIZE + 17);
+        let (ids, large_id, kept);
+        {
+            let sm = StorageManager::new(&tdir);
+            sm.create_table(1).unwrap();
+            ids = sm.insert_values(1, small.clone(), tid);
+            let hf = sm.get_hf(1).unwrap();
+
+            //the record goes to continuation pages added after its pointer's page
+            let id = sm.insert_value(1, large.clone(), tid);
+            assert_eq!(Some(0), id.page_id);
+            assert_eq!(5, hf.num_pages());
+            assert_eq!(large, sm.get_value(id, tid, Permissions::ReadOnly).unwrap());
+            let scanned: Vec<_> = sm.get_iterator(1, tid, Permissions::ReadOnly).collect();
+            assert_eq!(small.len() + 1, scanned.len());
+            assert!(scanned.contains(&(large.clone(), id)));
+            let slots: Vec<_> = scanned.iter().map(|(_, id)| id.slot_id).collect();
+            assert!(slots.windows(2).all(|pair| pair[0] < pair[1]));
+
+            //growing it moves it to a new chain and frees the old one for records
+            let larger = get_random_byte_vec(4 * PAGE_SIZE);
+            large_id = sm.update_value(larger.clone(), id, tid).unwrap();
+            assert_eq!(10, hf.num_pages());
+            assert_eq!(
+                larger,
+                sm.get_value(large_id, tid, Permissions::ReadOnly).unwrap()
+            );
+            for _ in 0..4 {
+                sm.insert_value(1, get_random_byte_vec(PAGE_SIZE * 3 / 4), tid);
+            }
+            assert_eq!(10, hf.num_pages());
+
+            //a record moves into overflow pages and back
+            let moved = sm
+                .update_value(get_random_byte_vec(2 * PAGE_SIZE), ids[0], tid)
+                .unwrap();
+            let back = sm.update_value(small[0].clone(), moved, tid).unwrap();
+            assert_eq!(
+                small[0],
+                sm.get_value(back, tid, Permissions::ReadOnly).unwrap()
+            );
+            sm.delete_value(large_id, tid).unwrap();
+            assert!(sm.get_value(large_id, tid, Permissions::ReadOnly).is_err());
+            kept = sm.insert_value(1, large.clone(), tid);
+            sm.commit_transaction(tid).unwrap();
+
+            //a record still being inserted at a crash is rolled back
+            let loser = TransactionId::new();
+            sm.insert_value(1, get_random_byte_vec(2 * PAGE_SIZE), loser);
+            //dropped without shutting down
+        }
+
+        let sm = StorageManager::new(&tdir);
+        assert_eq!(
+            large,
+            sm.get_value(kept, tid, Permissions::ReadOnly).unwrap()
+        );
+        for (id, value) in ids.iter().zip(&small).skip(1) {
+            assert_eq!(
+                *value,
+                sm.get_value(*id, tid, Permissions::ReadOnly).unwrap()
+            );
+        }
+        let scanned: Vec<_> = sm
+            .get_iterator(1, tid, Permissions::ReadOnly)
+            .map(|(value, _)| value.len())
+            .collect();
+        assert_eq!(small.len() + 5, scanned.len());
+        assert_eq!(1, scanned.iter().filter(|len| **len > PAGE_SIZE).count());
+    }
+}
@@ -1,33 +1,161 @@
 pub use crate::heap_page::HeapPage;
+use crate::heap_page::{
+    CompactionPolicy, PageCorruption, SlotEncoding, BYTES_PER_SLOT_META, FIXED_PAGE_META_SIZE,
+    MAX_WIDE_SLOT_PAGE_SIZE, PAGE_FLAG_BODY_COMPRESSED, PAGE_FORMAT_VERSION, PAGE_MAGIC,
+    PAGE_META_CHECKSUM_OFFSET, PAGE_META_FLAGS_OFFSET, PAGE_META_MAGIC_OFFSET,
+    PAGE_META_VERSION_OFFSET,
+};
 use common::prelude::*;
 use common::PAGE_SIZE;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::fmt::Write;
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
 
-///page offset u16 fits any offset in a 4096 byte page cast to usize before indexing
-pub type Offset = u16;
+///page offset as slot entries report it stored as u16 unless the page uses large slots
+pub type Offset = u32;
 //for debug formatting
 const BYTES_PER_LINE: usize = 40;
 
 ///initial num_slots for a new page
 const INITIAL_NUM_SLOTS: u16 = 0;
-///initial free_start body begins after the 8 byte page metadata
-const INITIAL_FREE_START: Offset = 8;
+///initial free_start body begins after the fixed page metadata
+const INITIAL_FREE_START: u16 = FIXED_PAGE_META_SIZE as u16;
+///stored checksum of a page that has never been stamped
+const CHECKSUM_UNSET: u32 = 0;
+///u32 length of the lz4 block that follows the header of a compressed on disk image
+const COMPRESSED_LEN_SIZE: usize = 4;
 
-///fixed size page with 8 bytes metadata and 6 bytes per slot
-pub struct Page {
-    ///raw page bytes
-    pub(crate) data: [u8; PAGE_SIZE],
+///fixed size page of N bytes with 20 bytes metadata at the front and 6 bytes per slot at the tail
+pub struct SizedPage<const N: usize> {
+    ///raw page bytes shared between clones until one of them writes
+    pub(crate) data: Arc<[u8; N]>,
+    ///when the body is defragmented in memory only so it resets to the default on reload
+    pub(crate) compaction: CompactionPolicy,
+    ///body bytes held by live slots kept in step by write_slot and rebuilt when bytes are loaded
+    pub(crate) live_byte_count: usize,
+    ///(SlotId, bytes held) for each reservation not yet committed or aborted in memory only
+    pub(crate) reservations: Vec<(SlotId, usize)>,
+    ///largest record the insert paths accept None for as large as fits in memory only
+    pub(crate) max_record_size: Option<usize>,
+    ///one bit per slot entry set while the entry is free kept in step by write_slot and
+    ///set_num_slots and rebuilt when bytes are loaded
+    pub(crate) free_slot_bits: Vec<u64>,
+    ///times the body was compacted since the page was loaded or the count was last taken
+    ///in memory only so the storage manager can charge them to the operation that stored it
+    pub(crate) compactions: u32,
+    ///set on a page fetched for a transaction that may only read it so it is never stored
+    ///in memory only
+    pub(crate) read_only: bool,
 }
 
-impl Page {
+///header fields decoded for people and tools reading a serialized page
+///the raw bytes serialized beside them are what a page is restored from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageHeader {
+    pub page_id: PageId,
+    pub num_slots: usize,
+    pub free_start: usize,
+    pub lsn: Lsn,
+    pub fill_factor: u8,
+    pub slot_encoding: SlotEncoding,
+    pub prefix_compressed: bool,
+    pub secure_delete: bool,
+    pub sorted: bool,
+    pub checksum: u32,
+    pub magic: u16,
+    pub format_version: u8,
+}
+
+///why bytes read back from disk could not be loaded as a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageReadError {
+    ///the bytes do not carry the heap page magic such as a page never written
+    BadMagic(u16),
+    ///the page comes from a layout version this build cannot read or upgrade
+    UnsupportedVersion(u8),
+    ///the header marks a compressed body that does not expand back to a page
+    BadCompressedBody,
+    ///the contents changed after the checksum was stamped such as a torn write
+    ChecksumMismatch { stored: u32, computed: u32 },
+    ///the checksum matches but the layout is broken so the page was written corrupt
+    Corrupt(PageCorruption),
+}
+
+impl fmt::Display for PageReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PageReadError::BadMagic(magic) => {
+                write!(f, "Page magic {:04x} does not mark a heap page", magic)
+            }
+            PageReadError::UnsupportedVersion(version) if *version > PAGE_FORMAT_VERSION => write!(
+                f,
+                "Page layout version {} is newer than the supported version {}",
+                version, PAGE_FORMAT_VERSION
+            ),
+            PageReadError::UnsupportedVersion(version) => {
+                write!(f, "No upgrade path from page layout version {}", version)
+            }
+            PageReadError::BadCompressedBody => {
+                write!(f, "Page body is marked compressed but does not decompress")
+            }
+            PageReadError::ChecksumMismatch { stored, computed } => write!(
+                f,
+                "Page checksum {:08x} does not match its contents {:08x}",
+                stored, computed
+            ),
+            PageReadError::Corrupt(corruption) => write!(f, "Page is corrupt: {}", corruption),
+        }
+    }
+}
+
+impl std::error::Error for PageReadError {}
+
+impl From<PageReadError> for CrustyError {
+    fn from(error: PageReadError) -> Self {
+        CrustyError::CrustyError(error.to_string())
+    }
+}
+
+///page of the configured PAGE_SIZE used by the rest of the storage layer
+pub type Page = SizedPage<PAGE_SIZE>;
+
+impl<const N: usize> SizedPage<N> {
+    ///rejects page sizes whose offsets do not fit an Offset or that cannot hold one slot
+    const VALID_SIZE: () = assert!(
+        N <= Offset::MAX as usize && N > FIXED_PAGE_META_SIZE + BYTES_PER_SLOT_META,
+        "page size must fit a u32 offset and hold at least one slot"
+    );
+
     ///new empty page with the given page_id
     pub fn new(page_id: PageId) -> Self {
-        let mut data = [0u8; PAGE_SIZE];
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_SIZE;
+        let mut data = [0u8; N];
         data[0..2].copy_from_slice(&page_id.to_le_bytes());
         data[2..4].copy_from_slice(&INITIAL_NUM_SLOTS.to_le_bytes());
         data[4..6].copy_from_slice(&INITIAL_FREE_START.to_le_bytes());
-        Page { data }
+        data[PAGE_META_MAGIC_OFFSET..PAGE_META_MAGIC_OFFSET + 2]
+            .copy_from_slice(&PAGE_MAGIC.to_le_bytes());
+        data[PAGE_META_VERSION_OFFSET] = PAGE_FORMAT_VERSION;
+        let mut page = SizedPage {
+            data: Arc::new(data),
+            compaction: CompactionPolicy::default(),
+            live_byte_count: 0,
+            reservations: Vec::new(),
+            max_record_size: None,
+            free_slot_bits: Vec::new(),
+            compactions: 0,
+            read_only: false,
+        };
+        if N > MAX_WIDE_SLOT_PAGE_SIZE {
+            page.use_large_slots();
+        }
+        page
     }
 
     ///page ID
@@ -35,18 +163,249 @@ impl Page {
         PageId::from_le_bytes(self.data[0..2].try_into().unwrap())
     }
 
-    ///page from a raw byte array
-    #[allow(dead_code)]
-    pub fn from_bytes(data: [u8; PAGE_SIZE]) -> Self {
-        Page { data }
+    ///renumbers the page such as a sibling split off under a new id
+    pub fn set_page_id(&mut self, page_id: PageId) {
+        self.data_mut()[0..2].copy_from_slice(&page_id.to_le_bytes());
+    }
+
+    ///page from a raw byte array upgraded to the current layout if it was written by an older one
+    ///Err if the bytes do not carry the heap page magic or come from a newer layout version
+    pub fn from_bytes(data: [u8; N]) -> Result<Self, CrustyError> {
+        Self::checked(Self::from_bytes_unchecked(data))
+    }
+
+    ///page from a raw byte array without checking its magic or version
+    ///for inspection tools that must load damaged or foreign pages to report on them
+    pub fn from_bytes_unchecked(data: [u8; N]) -> Self {
+        Self::from_shared(Arc::new(data))
+    }
+
+    ///reads exactly N bytes from r straight into a new page
+    ///Err if r ends early or the bytes are not a page this version can load
+    pub fn read_from<R: io::Read>(r: &mut R) -> Result<Self, CrustyError> {
+        let mut data = Arc::new([0u8; N]);
+        r.read_exact(&mut Arc::get_mut(&mut data).unwrap()[..])?;
+        Self::checked(Self::from_shared(data))
+    }
+
+    ///writes the page's N bytes to w
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> Result<(), CrustyError> {
+        w.write_all(&self.data[..])?;
+        Ok(())
+    }
+
+    ///reads the page stored at offset in file without moving the file cursor
+    ///so readers sharing a file handle need no lock around a seek
+    #[cfg(unix)]
+    pub fn read_at(file: &File, offset: u64) -> Result<Self, CrustyError> {
+        use std::os::unix::fs::FileExt;
+        let mut data = Arc::new([0u8; N]);
+        file.read_exact_at(&mut Arc::get_mut(&mut data).unwrap()[..], offset)?;
+        Self::checked(Self::from_shared(data))
+    }
+
+    ///writes the page at offset in file without moving the file cursor
+    #[cfg(unix)]
+    pub fn write_at(&self, file: &File, offset: u64) -> Result<(), CrustyError> {
+        use std::os::unix::fs::FileExt;
+        file.write_all_at(&self.data[..], offset)?;
+        Ok(())
+    }
+
+    ///page around bytes already in place
+    fn from_shared(data: Arc<[u8; N]>) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_SIZE;
+        let mut page = SizedPage {
+            data,
+            compaction: CompactionPolicy::default(),
+            live_byte_count: 0,
+            reservations: Vec::new(),
+            max_record_size: None,
+            free_slot_bits: Vec::new(),
+            compactions: 0,
+            read_only: false,
+        };
+        //a body that does not decompress keeps its flag for the checked paths to reject
+        page.decompress_body();
+        page.recount_live_bytes();
+        page.rebuild_free_slots();
+        page
+    }
+
+    ///image with everything after the header lz4 compressed and only as long as that leaves it
+    ///for writers that store pages at their own length such as incremental backups
+    ///heap files keep whole pages since a page is no bigger than a file system block
+    ///the header stays readable and marks the image so from_bytes_compressed expands it back
+    ///the plain bytes if compressing would not leave the image smaller than a page
+    pub fn to_bytes_compressed(&self) -> Vec<u8> {
+        let body_start = FIXED_PAGE_META_SIZE + COMPRESSED_LEN_SIZE;
+        let compressed = lz4_flex::block::compress(&self.data[FIXED_PAGE_META_SIZE..]);
+        if body_start + compressed.len() >= N {
+            return self.data.to_vec();
+        }
+        let mut image = Vec::with_capacity(body_start + compressed.len());
+        image.extend_from_slice(&self.data[..FIXED_PAGE_META_SIZE]);
+        image[PAGE_META_FLAGS_OFFSET] |= PAGE_FLAG_BODY_COMPRESSED;
+        image.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        image.extend_from_slice(&compressed);
+        image
+    }
+
+    ///page from an image written by to_bytes_compressed without checking its magic or version
+    ///as from_bytes_unchecked so pages are carried over whatever state they were copied in
+    ///Err only if the image is longer than a page
+    pub fn from_bytes_compressed(image: &[u8]) -> Result<Self, CrustyError> {
+        let mut data = Arc::new([0u8; N]);
+        Arc::get_mut(&mut data)
+            .unwrap()
+            .get_mut(..image.len())
+            .ok_or_else(|| {
+                CrustyError::CrustyError(format!(
+                    "Compressed page image of {} bytes is longer than a page",
+                    image.len()
+                ))
+            })?
+            .copy_from_slice(image);
+        Ok(Self::from_shared(data))
+    }
+
+    ///true only for an image from to_bytes_compressed whose body could not be expanded
+    pub fn is_body_compressed(&self) -> bool {
+        self.data[PAGE_META_FLAGS_OFFSET] & PAGE_FLAG_BODY_COMPRESSED != 0
+    }
+
+    ///expands a to_bytes_compressed image in place
+    ///None leaving the bytes untouched if the compressed block is cut short or malformed
+    fn decompress_body(&mut self) -> Option<()> {
+        if !self.is_body_compressed() {
+            return Some(());
+        }
+        let body_start = FIXED_PAGE_META_SIZE + COMPRESSED_LEN_SIZE;
+        let len = u32::from_le_bytes(
+            self.data[FIXED_PAGE_META_SIZE..body_start]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let compressed = self.data.get(body_start..body_start.checked_add(len)?)?;
+        let mut body = vec![0u8; N - FIXED_PAGE_META_SIZE];
+        let expanded = lz4_flex::block::decompress_into(compressed, &mut body).ok()?;
+        if expanded != body.len() {
+            return None;
+        }
+        let data = self.data_mut();
+        data[FIXED_PAGE_META_SIZE..].copy_from_slice(&body);
+        data[PAGE_META_FLAGS_OFFSET] &= !PAGE_FLAG_BODY_COMPRESSED;
+        Some(())
+    }
+
+    ///page with its magic checked and its layout upgraded to the current version
+    fn checked(page: Self) -> Result<Self, CrustyError> {
+        Self::checked_layout(page).map_err(CrustyError::from)
+    }
+
+    fn checked_layout(mut page: Self) -> Result<Self, PageReadError> {
+        let magic = page.magic();
+        if magic != PAGE_MAGIC {
+            return Err(PageReadError::BadMagic(magic));
+        }
+        if page.is_body_compressed() {
+            return Err(PageReadError::BadCompressedBody);
+        }
+        let version = page.format_version();
+        if version > PAGE_FORMAT_VERSION {
+            return Err(PageReadError::UnsupportedVersion(version));
+        }
+        page.upgrade_format(version)?;
+        Ok(page)
+    }
+
+    ///copy of the page bytes with the current checksum stamped in for from_bytes_checked
+    ///self is left as it is so a page kept in memory does not need to be mutable to write it
+    pub fn to_bytes_checked(&self) -> [u8; N] {
+        let mut data = *self.data;
+        data[PAGE_META_CHECKSUM_OFFSET..PAGE_META_CHECKSUM_OFFSET + 4]
+            .copy_from_slice(&self.compute_checksum().to_le_bytes());
+        data
+    }
+
+    ///page from bytes written by to_bytes_checked
+    ///unlike from_bytes the checksum must be stamped and match and the layout must be intact
+    ///so a torn or corrupted write is told apart from a page that is merely old
+    pub fn from_bytes_checked(data: [u8; N]) -> Result<Self, PageReadError> {
+        let page = Self::from_bytes_unchecked(data);
+        let magic = page.magic();
+        if magic != PAGE_MAGIC {
+            return Err(PageReadError::BadMagic(magic));
+        }
+        if page.is_body_compressed() {
+            return Err(PageReadError::BadCompressedBody);
+        }
+        let stored = page.stored_checksum();
+        let computed = page.compute_checksum();
+        if stored != computed {
+            return Err(PageReadError::ChecksumMismatch { stored, computed });
+        }
+        let page = Self::checked_layout(page)?;
+        page.check_integrity().map_err(PageReadError::Corrupt)?;
+        Ok(page)
+    }
+
+    ///page bytes for writing copying them first if a clone still shares them
+    pub(crate) fn data_mut(&mut self) -> &mut [u8; N] {
+        Arc::make_mut(&mut self.data)
+    }
+
+    ///magic number stored in the header PAGE_MAGIC for every heap page
+    pub fn magic(&self) -> u16 {
+        u16::from_le_bytes(
+            self.data[PAGE_META_MAGIC_OFFSET..PAGE_META_MAGIC_OFFSET + 2]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    ///layout version the page was written with
+    pub fn format_version(&self) -> u8 {
+        self.data[PAGE_META_VERSION_OFFSET]
+    }
+
+    ///rewrites a page stored in an older layout version into the current layout in place
+    ///each retired layout gets an arm that rewrites it one version forward and recurses
+    ///the checksum is left stale for the caller to restamp when the page is written back
+    fn upgrade_format(&mut self, version: u8) -> Result<(), PageReadError> {
+        match version {
+            PAGE_FORMAT_VERSION => Ok(()),
+            //no layout before version 1 carried a magic number so none has an upgrade
+            _ => Err(PageReadError::UnsupportedVersion(version)),
+        }
+    }
+
+    ///header fields decoded from the page bytes
+    pub fn header(&self) -> PageHeader {
+        PageHeader {
+            page_id: self.get_page_id(),
+            num_slots: self.slot_count(),
+            free_start: self.free_start(),
+            lsn: self.get_lsn(),
+            fill_factor: self.get_fill_factor(),
+            slot_encoding: self.get_slot_encoding(),
+            prefix_compressed: self.is_prefix_compressed(),
+            secure_delete: self.is_secure_delete(),
+            sorted: self.is_sorted(),
+            checksum: self.stored_checksum(),
+            magic: self.magic(),
+            format_version: self.format_version(),
+        }
     }
 
     ///reference to the page's raw bytes
-    pub fn to_bytes(&self) -> &[u8; PAGE_SIZE] {
+    pub fn to_bytes(&self) -> &[u8; N] {
         &self.data
     }
 
     ///list of offsets and differing bytes where this page differs from other_page
+    ///the bytes are this page's so applying the list to other_page reproduces this page
     #[allow(dead_code)]
     pub fn compare_page(&self, other_page: Vec<u8>) -> Vec<(Offset, Vec<u8>)> {
         let mut res = Vec::new();
@@ -69,18 +428,183 @@ impl Page {
                 in_diff = false;
             }
         }
+        if in_diff {
+            res.push((diff_start as Offset, diff_vec));
+        }
         res
     }
+
+    ///patches the page with the (offset, bytes) runs compare_page produced against it
+    ///Err without changing the page if any run reaches past the page end
+    pub fn apply_diff(&mut self, diff: &[(Offset, Vec<u8>)]) -> Result<(), CrustyError> {
+        if let Some((offset, bytes)) = diff
+            .iter()
+            .find(|(offset, bytes)| *offset as usize + bytes.len() > N)
+        {
+            return Err(CrustyError::CrustyError(format!(
+                "Diff of {} bytes at offset {} runs past the {} byte page",
+                bytes.len(),
+                offset,
+                N
+            )));
+        }
+        for (offset, bytes) in diff {
+            let start = *offset as usize;
+            self.data_mut()[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+        self.recount_live_bytes();
+        self.rebuild_free_slots();
+        Ok(())
+    }
+
+    ///crc32 of every page byte except the checksum field itself
+    pub fn compute_checksum(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&self.data[..PAGE_META_CHECKSUM_OFFSET]);
+        hasher.update(&self.data[PAGE_META_CHECKSUM_OFFSET + 4..]);
+        hasher.finalize()
+    }
+
+    ///checksum stored in the header 0 if the page was never stamped
+    pub fn stored_checksum(&self) -> u32 {
+        u32::from_le_bytes(
+            self.data[PAGE_META_CHECKSUM_OFFSET..PAGE_META_CHECKSUM_OFFSET + 4]
+                .try_into()
+                .unwrap(),
+        )
+    }
+
+    ///stamps the current contents checksum into the header
+    ///call after the last mutation and before the page is written out
+    pub fn update_checksum(&mut self) {
+        let checksum = self.compute_checksum();
+        self.data_mut()[PAGE_META_CHECKSUM_OFFSET..PAGE_META_CHECKSUM_OFFSET + 4]
+            .copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    ///false if the page was stamped and its contents changed since
+    ///pages that were never stamped pass so files written before checksums still load
+    pub fn verify_checksum(&self) -> bool {
+        let stored = self.stored_checksum();
+        stored == CHECKSUM_UNSET || stored == self.compute_checksum()
+    }
+
+    ///compactions since the page was loaded or the last call which starts the count again
+    pub(crate) fn take_compactions(&mut self) -> u32 {
+        std::mem::take(&mut self.compactions)
+    }
 }
 
-impl Clone for Page {
+impl<const N: usize> Clone for SizedPage<N> {
     fn clone(&self) -> Self {
-        Page { data: self.data }
+        SizedPage {
+            data: Arc::clone(&self.data),
+            compaction: self.compaction,
+            live_byte_count: self.live_byte_count,
+            reservations: self.reservations.clone(),
+            max_record_size: self.max_record_size,
+            free_slot_bits: self.free_slot_bits.clone(),
+            compactions: self.compactions,
+            read_only: self.read_only,
+        }
+    }
+}
+
+///serialized form of a page the decoded header for readers and the raw bytes to restore from
+#[derive(Serialize)]
+struct PageRepr<'a> {
+    header: PageHeader,
+    #[serde(with = "raw_bytes")]
+    bytes: &'a [u8],
+}
+
+#[derive(Deserialize)]
+struct OwnedPageRepr {
+    header: PageHeader,
+    #[serde(with = "raw_bytes")]
+    bytes: Vec<u8>,
+}
+
+impl<const N: usize> Serialize for SizedPage<N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PageRepr {
+            header: self.header(),
+            bytes: &self.data[..],
+        }
+        .serialize(serializer)
+    }
+}
+
+///rejects byte strings of the wrong size or that do not load as a page
+///and headers that disagree with the bytes since a hand edit to one would be silently lost
+impl<'de, const N: usize> Deserialize<'de> for SizedPage<N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = OwnedPageRepr::deserialize(deserializer)?;
+        let len = repr.bytes.len();
+        let data: [u8; N] = repr
+            .bytes
+            .try_into()
+            .map_err(|_| de::Error::invalid_length(len, &format!("{} page bytes", N).as_str()))?;
+        let page = Self::from_bytes(data).map_err(de::Error::custom)?;
+        if page.header() != repr.header {
+            return Err(de::Error::custom(format!(
+                "serialized header {:?} does not match the page bytes {:?}",
+                repr.header,
+                page.header()
+            )));
+        }
+        Ok(page)
     }
 }
 
-impl fmt::Debug for Page {
+///page bytes as one byte string for formats that have them like CBOR and a list elsewhere
+mod raw_bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("page bytes")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(b) = seq.next_element()? {
+                bytes.push(b);
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+impl<const N: usize> fmt::Debug for SizedPage<N> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.hexdump())
+    }
+}
+
+impl<const N: usize> SizedPage<N> {
+    ///page id then every non empty line of bytes with 0x00 shown as . and 0xff as ##
+    pub fn hexdump(&self) -> String {
         //let bytes: &[u8] = unsafe { any_as_u8_slice(&self) };
         let p = self.to_bytes();
         let mut buffer = String::new();
@@ -147,7 +671,7 @@ impl fmt::Debug for Page {
             write!(&mut buffer, "{} ", empty_lines_count).unwrap();
             buffer += "empty lines were hidden\n";
         }
-        write!(f, "{}", buffer)
+        buffer
     }
 }
 
@@ -175,4 +699,312 @@ mod tests {
         let p = Page::new(1023);
         assert_eq!(1023, p.get_page_id());
     }
+
+    #[test]
+    fn hs_page_checksum() {
+        init();
+        let mut p = Page::new(2);
+        assert_eq!(0, p.stored_checksum());
+        assert!(p.verify_checksum());
+        p.add_value(&get_random_byte_vec(100)).unwrap();
+        p.update_checksum();
+        assert_eq!(p.compute_checksum(), p.stored_checksum());
+        assert!(p.verify_checksum());
+
+        //stamping does not change the checksum it covers
+        let stamped = p.stored_checksum();
+        p.update_checksum();
+        assert_eq!(stamped, p.stored_checksum());
+
+        //flipped body bit survives a round trip through bytes and is caught
+        let mut bytes = *p.to_bytes();
+        bytes[PAGE_SIZE - 1] ^= 1;
+        assert!(!Page::from_bytes(bytes).unwrap().verify_checksum());
+
+        //a mutation after stamping needs a new stamp
+        p.add_value(&[1, 2, 3]).unwrap();
+        assert!(!p.verify_checksum());
+        p.update_checksum();
+        assert!(Page::from_bytes(*p.to_bytes()).unwrap().verify_checksum());
+    }
+
+    #[test]
+    fn hs_page_checked_bytes() {
+        init();
+        let mut p = Page::new(2);
+        p.add_value(&[5; 40]).unwrap();
+        p.add_value(&[6; 40]).unwrap();
+        let bytes = p.to_bytes_checked();
+        assert_eq!(0, p.stored_checksum());
+        let loaded = Page::from_bytes_checked(bytes).unwrap();
+        assert_eq!(Some(vec![6; 40]), loaded.get_value(1));
+        assert!(loaded.verify_checksum());
+
+        //an unstamped page only loads through the trusting path
+        assert!(Page::from_bytes(*p.to_bytes()).is_ok());
+        assert!(matches!(
+            Page::from_bytes_checked(*p.to_bytes()),
+            Err(PageReadError::ChecksumMismatch { stored: 0, .. })
+        ));
+
+        //a torn write where the tail never reached the disk
+        let mut torn = bytes;
+        torn[PAGE_SIZE / 2..].fill(0);
+        assert!(matches!(
+            Page::from_bytes_checked(torn),
+            Err(PageReadError::ChecksumMismatch { .. })
+        ));
+        assert_eq!(
+            Err(PageReadError::BadMagic(0)),
+            Page::from_bytes_checked([0; PAGE_SIZE]).map(|_| ())
+        );
+
+        //stamped over a newer version or a broken layout the checksum alone does not pass it
+        let mut newer = Page::from_bytes_unchecked(bytes);
+        newer.data_mut()[PAGE_META_VERSION_OFFSET] = PAGE_FORMAT_VERSION + 1;
+        assert_eq!(
+            Err(PageReadError::UnsupportedVersion(PAGE_FORMAT_VERSION + 1)),
+            Page::from_bytes_checked(newer.to_bytes_checked()).map(|_| ())
+        );
+        let mut broken = Page::from_bytes_unchecked(bytes);
+        broken.data_mut()[4..6].copy_from_slice(&(PAGE_SIZE as u16 - 1).to_le_bytes());
+        assert!(matches!(
+            Page::from_bytes_checked(broken.to_bytes_checked()),
+            Err(PageReadError::Corrupt(_))
+        ));
+        let err: CrustyError = PageReadError::BadMagic(0).into();
+        assert!(err.to_string().contains("magic"));
+    }
+
+    #[test]
+    fn hs_page_compressed_body() {
+        init();
+        let mut p = Page::new(3);
+        for i in 0..20u8 {
+            p.add_value(&[i; 30]).unwrap();
+        }
+        p.delete_value(4).unwrap();
+        p.update_checksum();
+
+        let image = p.to_bytes_compressed();
+        //the image is cut to the compressed body and the header stays readable
+        assert!(image.len() < PAGE_SIZE / 2);
+        assert_eq!(
+            p.to_bytes()[..PAGE_META_FLAGS_OFFSET],
+            image[..PAGE_META_FLAGS_OFFSET]
+        );
+
+        let expanded = Page::from_bytes_compressed(&image).unwrap();
+        assert_eq!(p.to_bytes(), expanded.to_bytes());
+        assert!(!expanded.is_body_compressed());
+        assert_eq!(p.live_bytes(), expanded.live_bytes());
+        assert!(expanded.verify_checksum());
+        assert!(Page::from_bytes_compressed(&[0u8; PAGE_SIZE + 1]).is_err());
+
+        //a body that does not shrink is written plain
+        let mut full = Page::new(4);
+        full.add_value(&get_random_byte_vec(
+            PAGE_SIZE - FIXED_PAGE_META_SIZE - BYTES_PER_SLOT_META,
+        ))
+        .unwrap();
+        assert_eq!(&full.to_bytes()[..], &full.to_bytes_compressed()[..]);
+        assert_eq!(
+            full.to_bytes(),
+            Page::from_bytes_compressed(&full.to_bytes_compressed())
+                .unwrap()
+                .to_bytes()
+        );
+
+        //a damaged compressed block is refused rather than loaded as a body
+        let mut damaged = [0u8; PAGE_SIZE];
+        damaged[..image.len()].copy_from_slice(&image);
+        damaged[FIXED_PAGE_META_SIZE..FIXED_PAGE_META_SIZE + COMPRESSED_LEN_SIZE]
+            .copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        assert!(Page::from_bytes_compressed(&damaged)
+            .unwrap()
+            .is_body_compressed());
+        assert!(Page::from_bytes(damaged).is_err());
+        assert_eq!(
+            Err(PageReadError::BadCompressedBody),
+            Page::from_bytes_checked(damaged).map(|_| ())
+        );
+        assert!(Page::from_bytes_unchecked(damaged).is_body_compressed());
+    }
+
+    #[test]
+    fn hs_page_stream_io() {
+        init();
+        let mut pages = Vec::new();
+        for pid in 0..3 {
+            let mut p = Page::new(pid);
+            p.add_value(&get_random_byte_vec(50 + pid as usize))
+                .unwrap();
+            pages.push(p);
+        }
+        let mut buf = Vec::new();
+        for p in &pages {
+            p.write_to(&mut buf).unwrap();
+        }
+        assert_eq!(3 * PAGE_SIZE, buf.len());
+
+        let mut reader = &buf[..];
+        for p in &pages {
+            let read = Page::read_from(&mut reader).unwrap();
+            assert_eq!(p.to_bytes(), read.to_bytes());
+            assert_eq!(p.live_bytes(), read.live_bytes());
+        }
+        //a stream that ends mid page or holds no page is an error
+        assert!(Page::read_from(&mut reader).is_err());
+        assert!(Page::read_from(&mut &buf[..PAGE_SIZE - 1]).is_err());
+        assert!(Page::read_from(&mut &[0u8; PAGE_SIZE][..]).is_err());
+    }
+
+    #[test]
+    fn hs_page_positional_io() {
+        init();
+        let dir = temp_testdir::TempDir::new(gen_random_test_sm_dir(), true);
+        let path = dir.join("pages.hf");
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+
+        //pages land at their offsets whatever order they are written in
+        let mut first = Page::new(0);
+        first.add_value(&[1; 10]).unwrap();
+        let mut second = Page::new(1);
+        second.add_value(&[2; 20]).unwrap();
+        second.write_at(&file, PAGE_SIZE as u64).unwrap();
+        first.write_at(&file, 0).unwrap();
+        assert_eq!(2 * PAGE_SIZE as u64, file.metadata().unwrap().len());
+
+        let read = Page::read_at(&file, PAGE_SIZE as u64).unwrap();
+        assert_eq!(Some(vec![2; 20]), read.get_value(0));
+        assert_eq!(
+            first.to_bytes(),
+            Page::read_at(&file, 0).unwrap().to_bytes()
+        );
+        assert!(Page::read_at(&file, 2 * PAGE_SIZE as u64).is_err());
+    }
+
+    #[test]
+    fn hs_page_serde() {
+        init();
+        let mut p = Page::new(4);
+        for v in get_random_vec_of_byte_vec(10, 20, 80) {
+            p.add_value(&v).unwrap();
+        }
+        p.delete_value(3).unwrap();
+        p.set_lsn(Lsn {
+            page_id: 2,
+            slot_id: 9,
+        });
+        p.update_checksum();
+
+        let cbor = serde_cbor::to_vec(&p).unwrap();
+        let from_cbor: Page = serde_cbor::from_slice(&cbor).unwrap();
+        assert_eq!(p.to_bytes(), from_cbor.to_bytes());
+
+        let json = serde_json::to_value(&p).unwrap();
+        assert_eq!(4, json["header"]["page_id"]);
+        assert_eq!(10, json["header"]["num_slots"]);
+        assert_eq!(9, json["header"]["lsn"]["slot_id"]);
+        let from_json: Page = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(p.to_bytes(), from_json.to_bytes());
+        assert_eq!(p.live_bytes(), from_json.live_bytes());
+
+        //an edited header no longer describes the bytes
+        let mut edited = json.clone();
+        edited["header"]["num_slots"] = 11.into();
+        assert!(serde_json::from_value::<Page>(edited).is_err());
+        let mut short = json.clone();
+        short["bytes"].as_array_mut().unwrap().pop();
+        assert!(serde_json::from_value::<Page>(short).is_err());
+        let mut foreign = json;
+        foreign["bytes"][PAGE_META_MAGIC_OFFSET] = 0.into();
+        assert!(serde_json::from_value::<Page>(foreign).is_err());
+    }
+
+    #[test]
+    fn hs_page_clone_copy_on_write() {
+        init();
+        let mut p = Page::new(6);
+        p.add_value(&[1; 64]).unwrap();
+        let snapshot = p.clone();
+        assert!(Arc::ptr_eq(&p.data, &snapshot.data));
+
+        //reads on either side keep the bytes shared
+        assert_eq!(p.get_value(0), snapshot.get_value(0));
+        assert!(snapshot.verify_checksum());
+        assert!(Arc::ptr_eq(&p.data, &snapshot.data));
+
+        //the first write copies and leaves the other clone untouched
+        p.update_value(0, &[2; 64]).unwrap();
+        assert!(!Arc::ptr_eq(&p.data, &snapshot.data));
+        assert_eq!(Some(vec![2; 64]), p.get_value(0));
+        assert_eq!(Some(vec![1; 64]), snapshot.get_value(0));
+
+        //a page with no other clone is written in place
+        let before = Arc::as_ptr(&p.data);
+        p.add_value(&[3; 8]).unwrap();
+        assert_eq!(before, Arc::as_ptr(&p.data));
+    }
+
+    #[test]
+    fn hs_page_apply_diff() {
+        init();
+        let mut base = Page::new(5);
+        base.add_value(&get_random_byte_vec(200)).unwrap();
+        base.add_value(&get_random_byte_vec(50)).unwrap();
+        let mut target = base.clone();
+        target.delete_value(0).unwrap();
+        target.update_value(1, &get_random_byte_vec(80)).unwrap();
+        target.add_value(&get_random_byte_vec(120)).unwrap();
+
+        let diff = target.compare_page(base.to_bytes().to_vec());
+        assert!(!diff.is_empty());
+        let mut patched = base.clone();
+        patched.apply_diff(&diff).unwrap();
+        assert_eq!(target.to_bytes(), patched.to_bytes());
+        assert!(patched.compare_page(target.to_bytes().to_vec()).is_empty());
+
+        //a run touching the last byte is reported and applied
+        let mut last = base.clone();
+        last.data_mut()[PAGE_SIZE - 1] = 9;
+        let diff = last.compare_page(base.to_bytes().to_vec());
+        assert_eq!(vec![((PAGE_SIZE - 1) as Offset, vec![9])], diff);
+
+        //a run past the end is rejected before anything is written
+        let bad = vec![(0, vec![1]), ((PAGE_SIZE - 1) as Offset, vec![1, 2])];
+        assert!(patched.apply_diff(&bad).is_err());
+        assert_eq!(target.to_bytes(), patched.to_bytes());
+    }
+
+    #[test]
+    fn hs_page_magic_version() {
+        init();
+        let mut p = Page::new(4);
+        assert_eq!(PAGE_MAGIC, p.magic());
+        assert_eq!(PAGE_FORMAT_VERSION, p.format_version());
+        p.add_value(&[7; 30]).unwrap();
+        let copy = Page::from_bytes(*p.to_bytes()).unwrap();
+        assert_eq!(Some(vec![7; 30]), copy.get_value(0));
+
+        //zeroed space and foreign bytes are rejected
+        assert!(Page::from_bytes([0; PAGE_SIZE]).is_err());
+        let mut bytes = *p.to_bytes();
+        bytes[PAGE_META_MAGIC_OFFSET] ^= 0xff;
+        assert!(Page::from_bytes(bytes).is_err());
+        assert_eq!(4, Page::from_bytes_unchecked(bytes).get_page_id());
+
+        //a layout from the future or one with no upgrade path is refused
+        let mut bytes = *p.to_bytes();
+        bytes[PAGE_META_VERSION_OFFSET] = PAGE_FORMAT_VERSION + 1;
+        assert!(Page::from_bytes(bytes).is_err());
+        bytes[PAGE_META_VERSION_OFFSET] = 0;
+        assert!(Page::from_bytes(bytes).is_err());
+    }
 }
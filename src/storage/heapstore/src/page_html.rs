@@ -0,0 +1,176 @@
+use crate::heap_page::{HeapPage, BYTES_PER_SLOT_META, FIXED_PAGE_META_SIZE};
+use crate::page::Page;
+use common::prelude::*;
+use common::PAGE_SIZE;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+//bytes per row of the html hex grid
+const HTML_BYTES_PER_LINE: usize = 32;
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; }
+pre { font-size: 13px; line-height: 1.5; }
+.header { background: #f4a261; }
+.slot-0 { background: #e9c46a; }
+.slot-1 { background: #f6dfa0; }
+.record-0 { background: #8ecae6; }
+.record-1 { background: #b5e48c; }
+.summary { background: #e76f51; }
+.dead { background: #d9d9d9; color: #777; }
+.free { color: #bbb; }
+.legend span { padding: 2px 8px; margin-right: 6px; }";
+
+///what a byte of the page holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Region {
+    Header,
+    ///optional summary regions after the slot directory
+    Summary,
+    SlotEntry(SlotId),
+    Record(SlotId),
+    ///body bytes no live slot points at
+    Dead,
+    Free,
+}
+
+impl Region {
+    ///css class alternating so neighbouring entries and records stay distinguishable
+    fn class(self) -> String {
+        match self {
+            Region::Header => "header".to_string(),
+            Region::Summary => "summary".to_string(),
+            Region::SlotEntry(id) => format!("slot-{}", id % 2),
+            Region::Record(id) => format!("record-{}", id % 2),
+            Region::Dead => "dead".to_string(),
+            Region::Free => "free".to_string(),
+        }
+    }
+
+    fn title(self) -> String {
+        match self {
+            Region::Header => "page header".to_string(),
+            Region::Summary => "page summaries".to_string(),
+            Region::SlotEntry(id) => format!("slot {} entry", id),
+            Region::Record(id) => format!("slot {} record", id),
+            Region::Dead => "unreferenced body bytes".to_string(),
+            Region::Free => "free space".to_string(),
+        }
+    }
+}
+
+impl Page {
+    ///region of every byte in the page
+    pub(crate) fn regions(&self) -> Vec<Region> {
+        let directory_start = self.slot_directory_offset().max(FIXED_PAGE_META_SIZE);
+        let free_start = self.free_start().min(directory_start);
+        let mut regions = vec![Region::Dead; PAGE_SIZE];
+        regions[free_start..directory_start].fill(Region::Free);
+        regions[..FIXED_PAGE_META_SIZE].fill(Region::Header);
+        regions[PAGE_SIZE - self.tail_size()..].fill(Region::Summary);
+        for entry in self.slot_entries() {
+            let start = self.slot_meta_offset(entry.slot_id);
+            regions[start..start + self.slot_meta_size()].fill(Region::SlotEntry(entry.slot_id));
+            let end = entry.offset as usize + entry.length as usize;
+            if entry.in_use && end <= PAGE_SIZE {
+                regions[entry.offset as usize..end].fill(Region::Record(entry.slot_id));
+            }
+        }
+        regions
+    }
+
+    ///standalone html page showing the slotted layout with colored header slot entries records and free space
+    pub fn to_html(&self) -> String {
+        let bytes = self.to_bytes();
+        let regions = self.regions();
+        let mut html = String::new();
+        writeln!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">"
+        )
+        .unwrap();
+        writeln!(html, "<title>page {}</title>", self.get_page_id()).unwrap();
+        writeln!(html, "<style>\n{}\n</style>\n</head>\n<body>", HTML_STYLE).unwrap();
+        writeln!(html, "<h1>Page {}</h1>", self.get_page_id()).unwrap();
+        writeln!(
+            html,
+            "<p>{} slots, header {} bytes, free_start {}, {} bytes free</p>",
+            self.slot_count(),
+            self.get_header_size(),
+            self.free_start(),
+            self.get_free_space()
+        )
+        .unwrap();
+        html.push_str("<p class=\"legend\"><span class=\"header\">header</span><span class=\"slot-0\">slot entry</span><span class=\"record-0\">record</span><span class=\"summary\">summary</span><span class=\"dead\">unreferenced</span><span class=\"free\">free</span></p>\n<pre>\n");
+
+        for (line, chunk) in bytes.chunks(HTML_BYTES_PER_LINE).enumerate() {
+            let line_start = line * HTML_BYTES_PER_LINE;
+            write!(html, "[{:4}] ", line_start).unwrap();
+            let mut pos = 0;
+            //one span per run of bytes in the same region
+            while pos < chunk.len() {
+                let region = regions[line_start + pos];
+                let mut end = pos;
+                while end < chunk.len() && regions[line_start + end] == region {
+                    end += 1;
+                }
+                let hex: Vec<String> = chunk[pos..end]
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect();
+                write!(
+                    html,
+                    "<span class=\"{}\" title=\"{}\">{}</span> ",
+                    region.class(),
+                    region.title(),
+                    hex.join(" ")
+                )
+                .unwrap();
+                pos = end;
+            }
+            html.push('\n');
+        }
+        html.push_str("</pre>\n</body>\n</html>\n");
+        html
+    }
+
+    ///writes to_html to path
+    pub fn write_html(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_html())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testutil::init;
+
+    #[test]
+    fn hs_page_html_regions() {
+        init();
+        let mut p = Page::new(3);
+        p.add_value(&[0xaa; 10]).unwrap();
+        p.add_value(&[0xbb; 10]).unwrap();
+        p.delete_value(0).unwrap();
+        let regions = p.regions();
+        assert_eq!(Region::Header, regions[0]);
+        assert_eq!(Region::Dead, regions[FIXED_PAGE_META_SIZE]);
+        assert_eq!(Region::Record(1), regions[FIXED_PAGE_META_SIZE + 10]);
+        assert_eq!(Region::Free, regions[FIXED_PAGE_META_SIZE + 20]);
+        assert_eq!(
+            Region::Free,
+            regions[PAGE_SIZE - 2 * BYTES_PER_SLOT_META - 1]
+        );
+        assert_eq!(
+            Region::SlotEntry(1),
+            regions[PAGE_SIZE - 2 * BYTES_PER_SLOT_META]
+        );
+        assert_eq!(Region::SlotEntry(0), regions[PAGE_SIZE - 1]);
+
+        let html = p.to_html();
+        assert!(html.contains("<h1>Page 3</h1>"));
+        assert!(html.contains("title=\"slot 1 record\">bb bb"));
+        assert!(html.contains("class=\"free\""));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::heap_page::{HeapPage, SlotEntry};
+use crate::page::{Page, PageHeader};
+use crate::page_html::Region;
+use common::prelude::*;
+use std::fmt;
+use std::str::FromStr;
+
+///body bytes no live record uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FreeRegionKind {
+    ///below free_start and left behind by a deleted shrunk or moved record
+    Dead,
+    ///between free_start and the slot directory where new records go
+    Unallocated,
+}
+
+///one run of body bytes no live record uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FreeRegion {
+    pub offset: usize,
+    pub length: usize,
+    pub kind: FreeRegionKind,
+}
+
+///structured view of one page for inspection tools
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PageReport {
+    pub header: PageHeader,
+    pub header_size: usize,
+    pub free_space: usize,
+    pub live_bytes: usize,
+    pub dead_bytes: usize,
+    ///every slot directory entry live or free in SlotId order
+    pub slots: Vec<SlotEntry>,
+    ///free body runs in offset order
+    pub free_regions: Vec<FreeRegion>,
+}
+
+///how Page::render lays out a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFormat {
+    ///one line of counts
+    Summary,
+    ///header fields slot table and free regions
+    Report,
+    ///the Debug hex dump
+    Hexdump,
+    ///the report as JSON
+    Json,
+}
+
+impl FromStr for PageFormat {
+    type Err = CrustyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "summary" => Ok(PageFormat::Summary),
+            "report" => Ok(PageFormat::Report),
+            "hex" => Ok(PageFormat::Hexdump),
+            "json" => Ok(PageFormat::Json),
+            _ => Err(CrustyError::CrustyError(format!(
+                "Unknown page format {}, expected summary, report, hex or json",
+                s
+            ))),
+        }
+    }
+}
+
+impl PageReport {
+    ///page id slot counts and byte counts on one line
+    pub fn summary(&self) -> String {
+        format!(
+            "page {}: {} slots ({} live), {} live bytes, {} dead bytes, {} free bytes, free_start {}",
+            self.header.page_id,
+            self.slots.len(),
+            self.slots.iter().filter(|s| s.in_use).count(),
+            self.live_bytes,
+            self.dead_bytes,
+            self.free_space,
+            self.header.free_start
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+impl fmt::Display for PageReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let h = &self.header;
+        writeln!(f, "{}", self.summary())?;
+        writeln!(
+            f,
+            "header: lsn {}.{}, fill_factor {}%, slot_encoding {:?}, prefix_compressed {}, secure_delete {}, sorted {}, checksum {:08x}, magic {:04x}, version {}, header_size {}",
+            h.lsn.page_id,
+            h.lsn.slot_id,
+            h.fill_factor,
+            h.slot_encoding,
+            h.prefix_compressed,
+            h.secure_delete,
+            h.sorted,
+            h.checksum,
+            h.magic,
+            h.format_version,
+            self.header_size
+        )?;
+        writeln!(f, "{:>6} {:>6} {:>6}  state", "slot", "offset", "length")?;
+        for slot in &self.slots {
+            let state = if slot.in_use { "live" } else { "free" };
+            writeln!(
+                f,
+                "{:>6} {:>6} {:>6}  {}",
+                slot.slot_id, slot.offset, slot.length, state
+            )?;
+        }
+        writeln!(f, "{:>6} {:>6}  kind", "offset", "length")?;
+        for region in &self.free_regions {
+            writeln!(
+                f,
+                "{:>6} {:>6}  {:?}",
+                region.offset, region.length, region.kind
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Page {
+    ///header fields slot entries and free body regions of the page
+    pub fn describe(&self) -> PageReport {
+        let mut free_regions: Vec<FreeRegion> = Vec::new();
+        for (offset, region) in self.regions().into_iter().enumerate() {
+            let kind = match region {
+                Region::Dead => FreeRegionKind::Dead,
+                Region::Free => FreeRegionKind::Unallocated,
+                _ => continue,
+            };
+            match free_regions.last_mut() {
+                Some(last) if last.kind == kind && last.offset + last.length == offset => {
+                    last.length += 1
+                }
+                _ => free_regions.push(FreeRegion {
+                    offset,
+                    length: 1,
+                    kind,
+                }),
+            }
+        }
+        PageReport {
+            header: self.header(),
+            header_size: self.get_header_size(),
+            free_space: self.get_free_space(),
+            live_bytes: self.live_bytes(),
+            dead_bytes: self.dead_bytes(),
+            slots: self.slot_entries(),
+            free_regions,
+        }
+    }
+
+    ///the page laid out in format
+    pub fn render(&self, format: PageFormat) -> String {
+        match format {
+            PageFormat::Summary => self.describe().summary(),
+            PageFormat::Report => self.describe().to_string(),
+            PageFormat::Hexdump => self.hexdump(),
+            PageFormat::Json => self.describe().to_json(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap_page::{BYTES_PER_SLOT_META, FIXED_PAGE_META_SIZE};
+    use common::testutil::init;
+    use common::PAGE_SIZE;
+
+    #[test]
+    fn hs_page_describe() {
+        init();
+        let mut p = Page::new(7);
+        p.add_value(&[0xaa; 10]).unwrap();
+        p.add_value(&[0xbb; 30]).unwrap();
+        p.add_value(&[0xcc; 5]).unwrap();
+        p.delete_value(0).unwrap();
+        p.update_value(1, &[0xdd; 20]).unwrap();
+
+        let report = p.describe();
+        assert_eq!(7, report.header.page_id);
+        assert_eq!((25, 20), (report.live_bytes, report.dead_bytes));
+        assert_eq!(3, report.slots.len());
+        assert!(!report.slots[0].in_use);
+        let body = FIXED_PAGE_META_SIZE;
+        assert_eq!(
+            vec![
+                FreeRegion {
+                    offset: body,
+                    length: 10,
+                    kind: FreeRegionKind::Dead
+                },
+                FreeRegion {
+                    offset: body + 30,
+                    length: 10,
+                    kind: FreeRegionKind::Dead
+                },
+                FreeRegion {
+                    offset: body + 45,
+                    length: PAGE_SIZE - 3 * BYTES_PER_SLOT_META - body - 45,
+                    kind: FreeRegionKind::Unallocated
+                },
+            ],
+            report.free_regions
+        );
+
+        let summary = p.render(PageFormat::Summary);
+        assert!(summary.starts_with("page 7: 3 slots (2 live), 25 live bytes, 20 dead bytes"));
+        assert_eq!(1, summary.lines().count());
+        let table = p.render(PageFormat::Report);
+        assert!(table.contains("     1     30     20  live"));
+        assert!(table.contains("    50     10  Dead"));
+        assert_eq!(format!("{:?}", p), p.render(PageFormat::Hexdump));
+
+        let json: serde_json::Value = serde_json::from_str(&p.render(PageFormat::Json)).unwrap();
+        assert_eq!(3, json["slots"].as_array().unwrap().len());
+        assert_eq!("Unallocated", json["free_regions"][2]["kind"]);
+        assert_eq!(65, json["header"]["free_start"]);
+
+        assert_eq!(PageFormat::Hexdump, "hex".parse().unwrap());
+        assert!("xml".parse::<PageFormat>().is_err());
+    }
+}
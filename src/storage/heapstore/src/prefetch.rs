@@ -0,0 +1,83 @@
+use crate::buffer_pool::BufferPool;
+use crate::heapfile::HeapFile;
+use common::prelude::*;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+
+/// Pages read ahead of a scan by default.
+pub const DEFAULT_PREFETCH_WINDOW: usize = 8;
+
+/// Thread reading pages into a buffer pool ahead of the scans that asked for them, so a scan
+/// finds its next pages cached instead of waiting on each read.
+/// Requests of a scan that has since been dropped are skipped.
+/// The thread stops once the prefetcher and every scan holding it are dropped.
+pub(crate) struct Prefetcher {
+    requests: Mutex<Sender<Request>>,
+}
+
+/// Page to read and the scan that wants it, alive while the scan is.
+type Request = (Arc<HeapFile>, PageId, Weak<()>);
+
+impl Prefetcher {
+    pub(crate) fn start(pool: Arc<BufferPool>) -> Arc<Self> {
+        let (requests, pending) = mpsc::channel::<Request>();
+        thread::Builder::new()
+            .name("heapstore-prefetch".to_string())
+            .spawn(move || {
+                for (hf, page_id, scan) in pending {
+                    if scan.strong_count() == 0 {
+                        continue;
+                    }
+                    if let Err(e) = pool.prefetch(&hf, page_id) {
+                        debug!(
+                            "Skipping read ahead of page {} of container {}: {}",
+                            page_id, hf.container_id, e
+                        );
+                    }
+                }
+            })
+            .expect("error spawning the prefetch thread");
+        Arc::new(Prefetcher {
+            requests: Mutex::new(requests),
+        })
+    }
+
+    /// Ask for a page to be read into the pool. Returns at once.
+    fn request(&self, hf: &Arc<HeapFile>, page_id: PageId, scan: &Arc<()>) {
+        let request = (Arc::clone(hf), page_id, Arc::downgrade(scan));
+        let _ = self.requests.lock().unwrap().send(request);
+    }
+}
+
+/// Read ahead state of one sequential scan.
+pub(crate) struct ReadAhead {
+    prefetcher: Arc<Prefetcher>,
+    /// Pages past the current one to keep requested.
+    window: usize,
+    /// Pages below this have been requested or read.
+    requested_to: PageId,
+    /// Dropped with the scan so its outstanding requests are skipped.
+    alive: Arc<()>,
+}
+
+impl ReadAhead {
+    pub(crate) fn new(prefetcher: Arc<Prefetcher>, window: usize) -> Self {
+        ReadAhead {
+            prefetcher,
+            window,
+            requested_to: 0,
+            alive: Arc::new(()),
+        }
+    }
+
+    /// The scan moved to page_id. Request the pages of the window after it not yet requested,
+    /// stopping before scan_end.
+    pub(crate) fn advance(&mut self, hf: &Arc<HeapFile>, page_id: PageId, scan_end: PageId) {
+        let end = (page_id as usize + 1 + self.window).min(scan_end as usize) as PageId;
+        for pid in self.requested_to.max(page_id + 1)..end {
+            self.prefetcher.request(hf, pid, &self.alive);
+        }
+        self.requested_to = self.requested_to.max(end);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::heap_page::HeapPage;
+use crate::heapfile::HeapFile;
+use crate::page::Page;
+use crate::recovery::page_from_log;
+use crate::storage_manager::StorageManager;
+use crate::wal::Wal;
+use common::prelude::*;
+use std::sync::Arc;
+
+/// What happens to a page read back from its heap file with a checksum that does not match:
+/// it is rebuilt from the images the write-ahead log keeps of recently changed pages and
+/// written back, or, when the log cannot rebuild it, quarantined and refused with a
+/// CorruptPage error. A quarantined page leaves quarantine once it is written again whole.
+impl HeapFile {
+    /// Deal with page page_id, just read from the file and found corrupt for reason, such as a
+    /// checksum that does not match.
+    pub(crate) fn restore_corrupt_page(
+        &self,
+        page_id: PageId,
+        reason: String,
+    ) -> Result<Page, CrustyError> {
+        warn!(
+            "Page {} of heap file {} is corrupt: {}",
+            page_id, self.container_id, reason
+        );
+        if let Some(wal) = self.wal.get() {
+            match page_from_log(wal, self.container_id, page_id) {
+                Ok(Some(restored)) => {
+                    wal.flush(restored.get_lsn())?;
+                    self.write_page_to_file(&restored)?;
+                    info!(
+                        "Restored page {} of heap file {} from the write-ahead log",
+                        page_id, self.container_id
+                    );
+                    return Ok(restored);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Error rebuilding page {} from the log: {}", page_id, e),
+            }
+        }
+        self.quarantined.lock().unwrap().insert(page_id);
+        Err(CrustyError::CorruptPage {
+            container_id: self.container_id,
+            page_id,
+            reason,
+        })
+    }
+
+    /// Attach the log corrupt pages are rebuilt from. Only the first log set is used.
+    pub(crate) fn set_wal(&self, wal: Arc<Wal>) {
+        let _ = self.wal.set(wal);
+    }
+}
+
+impl StorageManager {
+    /// Pages that failed their checksum on a read and could not be rebuilt from the log, in
+    /// container and page order. Reads of them fail until they are written again.
+    pub fn quarantined_pages(&self) -> Vec<(ContainerId, PageId)> {
+        let mut pages: Vec<(ContainerId, PageId)> = self
+            .cid_heapfile_map
+            .read()
+            .unwrap()
+            .values()
+            .flat_map(|hf| {
+                let quarantined = hf.quarantined.lock().unwrap();
+                quarantined
+                    .iter()
+                    .map(|pid| (hf.container_id, *pid))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        pages.sort_unstable();
+        pages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use common::PAGE_SIZE;
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::FileExt;
+    use std::path::Path;
+    use temp_testdir::TempDir;
+
+    /// Flip the last byte of page page_id in the heap file at path.
+    fn corrupt(path: &Path, page_id: PageId) {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        let offset = ((page_id as usize + 2) * PAGE_SIZE - 1) as u64;
+        let mut byte = [0];
+        file.read_exact_at(&mut byte, offset).unwrap();
+        file.write_all_at(&[!byte[0]], offset).unwrap();
+    }
+
+    #[test]
+    fn hs_quarantine_restore_or_refuse() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        let sm = StorageManager::new(&tdir);
+        sm.create_table(1).unwrap();
+        let vals = get_random_vec_of_byte_vec(10, 50, 100);
+        let ids = sm.insert_values(1, vals.clone(), tid);
+        sm.commit_transaction(tid).unwrap();
+        sm.flush_all().unwrap();
+        let path = tdir.join("1.hf");
+
+        //a page changed since the last checkpoint is rebuilt from its image in the log
+        corrupt(&path, 0);
+        sm.clear_cache();
+        for (id, value) in ids.iter().zip(&vals) {
+            assert_eq!(
+                *value,
+                sm.get_value(*id, tid, Permissions::ReadOnly).unwrap()
+            );
+        }
+        assert!(sm.quarantined_pages().is_empty());
+
+        //after a checkpoint the log has nothing to rebuild it from
+        sm.checkpoint().unwrap();
+        corrupt(&path, 0);
+        sm.clear_cache();
+        match sm.get_value(ids[0], tid, Permissions::ReadOnly) {
+            Err(CrustyError::CorruptPage {
+                container_id,
+                page_id,
+                ..
+            }) => assert_eq!((1, 0), (container_id, page_id)),
+            res => panic!("Expected a corrupt page error, got {:?}", res),
+        }
+        assert_eq!(vec![(1, 0)], sm.quarantined_pages());
+
+        //writing the page whole again takes it out of quarantine
+        let hf = sm.get_hf(1).unwrap();
+        hf.write_page_to_file(&Page::new(0)).unwrap();
+        assert!(sm.quarantined_pages().is_empty());
+        assert!(sm.get_value(ids[0], tid, Permissions::ReadOnly).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::encryption::seal_path;
+use crate::fsm::fsm_path;
+use crate::heapfile::HeapFile;
+use crate::storage_manager::StorageManager;
+use common::prelude::*;
+use common::PAGE_SIZE;
+use std::fs;
+use std::path::Path;
+
+/// Space a container takes on disk, as reported by StorageManager::disk_usage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DiskUsage {
+    /// Data pages in the heap file.
+    pub pages: PageId,
+    /// Bytes of the heap file, its header page and every segment file included.
+    pub heap_file: u64,
+    /// Segment files the heap file is split into, 1 if it is kept whole.
+    pub segments: usize,
+    /// Bytes of the free space map saved beside it, 0 if none is saved.
+    pub free_space_map: u64,
+    /// Bytes of the nonces saved beside an encrypted heap file, 0 if it is not encrypted.
+    pub seal: u64,
+    /// Most bytes the heap file may grow to, None if it has no quota.
+    pub quota: Option<u64>,
+}
+
+impl DiskUsage {
+    /// Bytes of every file of the container.
+    pub fn total(&self) -> u64 {
+        self.heap_file + self.free_space_map + self.seal
+    }
+
+    /// Bytes the heap file may still grow by, None if it has no quota.
+    pub fn remaining(&self) -> Option<u64> {
+        self.quota.map(|quota| quota.saturating_sub(self.heap_file))
+    }
+}
+
+/// Bytes of the file at path, 0 if there is none.
+fn file_len(path: &Path) -> Result<u64, CrustyError> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A quota limits the pages of a heap file, counted with its header page, so a file at its
+/// quota can still have its pages changed but not gain one.
+impl HeapFile {
+    /// Most bytes the file may grow to, None if it has no quota.
+    pub(crate) fn quota(&self) -> Option<u64> {
+        *self.quota.read().unwrap()
+    }
+
+    pub(crate) fn set_quota(&self, quota: Option<u64>) {
+        *self.quota.write().unwrap() = quota;
+    }
+
+    /// Err with QuotaExceeded if the file would be past its quota holding pages pages.
+    pub(crate) fn check_quota(&self, pages: u64) -> Result<(), CrustyError> {
+        let size = (pages + 1) * PAGE_SIZE as u64;
+        match self.quota() {
+            Some(quota) if size > quota => Err(CrustyError::QuotaExceeded {
+                container_id: self.container_id,
+                quota,
+                size,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl StorageManager {
+    /// Limit the heap file of a container to quota bytes, its header page included, or lift
+    /// the limit with None. Inserts, updates and bulk loads that need a page past the quota
+    /// fail with QuotaExceeded. A quota below the file's size keeps the pages it has but lets
+    /// it gain none. The quota is saved in the catalog. Errs if the container does not exist.
+    pub fn set_container_quota(
+        &self,
+        container_id: ContainerId,
+        quota: Option<u64>,
+    ) -> Result<(), CrustyError> {
+        self.get_hf(container_id)?.set_quota(quota);
+        self.save_catalog()
+    }
+
+    /// Quota of a container, None if it has none or does not exist.
+    pub fn get_container_quota(&self, container_id: ContainerId) -> Option<u64> {
+        self.get_hf(container_id).ok()?.quota()
+    }
+
+    /// Bytes the files of a container take on disk, and its quota.
+    /// Errs if the container does not exist.
+    pub fn disk_usage(&self, container_id: ContainerId) -> Result<DiskUsage, CrustyError> {
+        let hf = self.get_hf(container_id)?;
+        let path = self
+            .cid_path_map
+            .read()
+            .unwrap()
+            .get(&container_id)
+            .cloned()
+            .ok_or_else(|| {
+                CrustyError::CrustyError(format!("Container {} has no heap file", container_id))
+            })?;
+        Ok(DiskUsage {
+            pages: hf.num_pages(),
+            heap_file: hf.file_len()?,
+            segments: hf.segment_count(),
+            free_space_map: file_len(&fsm_path(&path))?,
+            seal: file_len(&seal_path(&path))?,
+            quota: hf.quota(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_quota_limits_growth() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        let quota = 3 * PAGE_SIZE as u64;
+        {
+            let sm = StorageManager::new(&tdir);
+            sm.create_table(1).unwrap();
+            sm.set_container_quota(1, Some(quota)).unwrap();
+            assert_eq!(Some(quota), sm.get_container_quota(1));
+
+            //two data pages fill the quota with the header, then inserts fail
+            let mut ids = Vec::new();
+            let error = loop {
+                match sm.try_insert_value(1, vec![7; 1000], tid) {
+                    Ok(id) => ids.push(id),
+                    Err(e) => break e,
+                }
+            };
+            assert_eq!(
+                CrustyError::QuotaExceeded {
+                    container_id: 1,
+                    quota,
+                    size: 4 * PAGE_SIZE as u64
+                },
+                error
+            );
+            assert_eq!(2, sm.disk_usage(1).unwrap().pages);
+            let loaded = sm.bulk_insert(1, vec![vec![1; 100]], tid);
+            assert!(matches!(loaded, Err(CrustyError::QuotaExceeded { .. })));
+
+            //space freed on the pages it has can be used again
+            sm.delete_value(ids[0], tid).unwrap();
+            sm.try_insert_value(1, vec![8; 1000], tid).unwrap();
+            sm.commit_transaction(tid).unwrap();
+            sm.shutdown();
+        }
+
+        let sm = StorageManager::new(&tdir);
+        assert_eq!(Some(quota), sm.get_container_quota(1));
+        let usage = sm.disk_usage(1).unwrap();
+        assert_eq!(quota, usage.heap_file);
+        assert_eq!(Some(0), usage.remaining());
+        assert!(usage.free_space_map > 0);
+        assert_eq!(usage.heap_file + usage.free_space_map, usage.total());
+        assert!(sm.disk_usage(2).is_err());
+
+        sm.set_container_quota(1, None).unwrap();
+        sm.try_insert_value(1, vec![9; 1000], tid).unwrap();
+        assert_eq!(3, sm.disk_usage(1).unwrap().pages);
+    }
+}
@@ -0,0 +1,574 @@
+use crate::heap_page::{HeapPage, SLOT_FLAG_FORWARDED};
+use crate::page::Page;
+use crate::storage_manager::StorageManager;
+use crate::wal::{LogEntry, LogRecord, SlotImage, SlotKind, Wal};
+use common::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::path::Path;
+
+/// File in the storage directory written by a shutdown that left every page in its heap file.
+/// A storage manager opened without it recovers from the write-ahead log.
+pub(crate) const CLEAN_SHUTDOWN_FILENAME: &str = "clean_shutdown";
+
+/// What recover did to bring the containers back to the state the log describes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RecoveryReport {
+    /// Records in the log.
+    pub(crate) records: usize,
+    /// Changes applied again to pages that were written without them.
+    pub(crate) redone: usize,
+    /// Transactions that had not committed, whose changes were rolled back.
+    pub(crate) losers: Vec<TransactionId>,
+    /// Changes of the losers rolled back.
+    pub(crate) undone: usize,
+}
+
+/// Record that dir was shut down cleanly.
+pub(crate) fn mark_clean_shutdown(dir: &Path) -> Result<(), CrustyError> {
+    File::create(dir.join(CLEAN_SHUTDOWN_FILENAME))?.sync_all()?;
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Whether dir was shut down cleanly the last time it was used.
+pub(crate) fn was_shut_down_cleanly(dir: &Path) -> bool {
+    dir.join(CLEAN_SHUTDOWN_FILENAME).exists()
+}
+
+/// Clear the clean shutdown mark of dir so a crash from now on is noticed.
+pub(crate) fn clear_clean_shutdown(dir: &Path) -> Result<(), CrustyError> {
+    let marker = dir.join(CLEAN_SHUTDOWN_FILENAME);
+    if marker.exists() {
+        fs::remove_file(marker)?;
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// The slot change a record makes, None for records that change no slot.
+fn slot_change(record: &LogRecord) -> Option<&LogRecord> {
+    match record {
+        LogRecord::Insert { .. } | LogRecord::Delete { .. } | LogRecord::Update { .. } => {
+            Some(record)
+        }
+        LogRecord::Compensate { change, .. } => slot_change(change),
+        _ => None,
+    }
+}
+
+fn changed_id(change: &LogRecord) -> ValueId {
+    match change {
+        LogRecord::Insert { id, .. }
+        | LogRecord::Delete { id, .. }
+        | LogRecord::Update { id, .. } => *id,
+        _ => unreachable!("not a slot change"),
+    }
+}
+
+/// The slot change that rolls back change, None if it cannot be rolled back.
+fn inverse(change: &LogRecord) -> Option<LogRecord> {
+    match change {
+        LogRecord::Insert { id, after } => Some(LogRecord::Delete {
+            id: *id,
+            before: after.clone(),
+        }),
+        LogRecord::Delete { id, before } => Some(LogRecord::Insert {
+            id: *id,
+            after: before.clone(),
+        }),
+        LogRecord::Update { id, before, after } => Some(LogRecord::Update {
+            id: *id,
+            before: after.clone(),
+            after: before.clone(),
+        }),
+        _ => None,
+    }
+}
+
+/// Make slot_id of page hold what change left in it. False if the page cannot.
+fn apply(page: &mut Page, slot_id: SlotId, change: &LogRecord) -> bool {
+    match change {
+        LogRecord::Insert { after, .. } if after.kind != SlotKind::Record => page
+            .add_chain_slot(slot_id, &after.bytes, after.kind)
+            .is_some(),
+        LogRecord::Insert { after, .. } => {
+            page.add_value_with_slot(slot_id, &after.bytes).is_some()
+                && set_forwarded(page, slot_id, after.forwarded)
+        }
+        LogRecord::Delete { .. } => page.delete_value(slot_id).is_some(),
+        LogRecord::Update { after, .. } => {
+            page.update_value(slot_id, &after.bytes).is_some()
+                && set_forwarded(page, slot_id, after.forwarded)
+        }
+        _ => false,
+    }
+}
+
+fn set_forwarded(page: &mut Page, slot_id: SlotId, forwarded: bool) -> bool {
+    let Some(flags) = page.get_slot_flags(slot_id) else {
+        return false;
+    };
+    let wanted = match forwarded {
+        true => flags | SLOT_FLAG_FORWARDED,
+        false => flags & !SLOT_FLAG_FORWARDED,
+    };
+    wanted == flags || page.set_slot_flags(slot_id, wanted).is_some()
+}
+
+/// Contents of page_id of container_id rebuilt from wal: the last image of the page with every
+/// change logged after it applied. None if the log has no image of the page since it was last
+/// truncated away, or the changes do not apply to it.
+pub(crate) fn page_from_log(
+    wal: &Wal,
+    container_id: ContainerId,
+    page_id: PageId,
+) -> Result<Option<Page>, CrustyError> {
+    let entries = wal.entries()?;
+    let last = entries.iter().rposition(|e| match &e.record {
+        LogRecord::PageImage {
+            container_id: cid,
+            page_id: pid,
+            ..
+        } => *cid == container_id && *pid == page_id,
+        LogRecord::Truncate {
+            container_id: cid,
+            pages,
+        } => *cid == container_id && *pages <= page_id,
+        _ => false,
+    });
+    let Some(last) = last else {
+        return Ok(None);
+    };
+    let LogRecord::PageImage { image, .. } = &entries[last].record else {
+        return Ok(None);
+    };
+    let Ok(bytes) = image.as_slice().try_into() else {
+        warn!(
+            "Image of page {} of container {} is cut short",
+            page_id, container_id
+        );
+        return Ok(None);
+    };
+    let mut page = Page::from_bytes(bytes)?;
+    for entry in &entries[last + 1..] {
+        let Some(change) = slot_change(&entry.record) else {
+            continue;
+        };
+        let id = changed_id(change);
+        if id.container_id != container_id || id.page_id != Some(page_id) {
+            continue;
+        }
+        if page.get_lsn() >= entry.lsn {
+            continue;
+        }
+        if !id
+            .slot_id
+            .is_some_and(|slot_id| apply(&mut page, slot_id, change))
+        {
+            warn!("Cannot apply {:?} to the image of page {}", entry, page_id);
+            return Ok(None);
+        }
+        page.set_lsn(entry.lsn);
+    }
+    Ok(Some(page))
+}
+
+/// ARIES style recovery from the write-ahead log, run when a storage manager opens a directory
+/// that was not shut down cleanly. Analysis finds the transactions that never committed, from
+/// the last checkpoint on, redo applies every change logged since the checkpoint that a page
+/// was written without in log order, and undo rolls the losers back newest change first,
+/// logging each rollback so a crash during recovery does not roll anything back twice.
+/// Containers that no longer exist are skipped.
+impl StorageManager {
+    pub(crate) fn recover(&self) -> Result<RecoveryReport, CrustyError> {
+        self.recover_from_log(true)
+    }
+
+    /// Redo the log without rolling back the transactions it leaves running, which stay
+    /// running in the log, as a standby does that may see them commit yet.
+    pub(crate) fn replay(&self) -> Result<RecoveryReport, CrustyError> {
+        self.recover_from_log(false)
+    }
+
+    fn recover_from_log(&self, undo: bool) -> Result<RecoveryReport, CrustyError> {
+        let mut report = RecoveryReport::default();
+        let Some(wal) = &self.wal else {
+            return Ok(report);
+        };
+        let entries = wal.entries()?;
+        report.records = entries.len();
+
+        // Analysis: the last record of every transaction without a commit or abort, starting
+        // from the transactions running at the last checkpoint
+        let checkpoint = entries
+            .iter()
+            .rposition(|e| matches!(e.record, LogRecord::Checkpoint { .. }));
+        let (mut losers, redo_from, analyzed) = match checkpoint.map(|i| (i, &entries[i].record)) {
+            Some((i, LogRecord::Checkpoint { redo_from, active })) => (
+                active.iter().copied().collect(),
+                Some(*redo_from),
+                &entries[i + 1..],
+            ),
+            _ => (HashMap::new(), None, &entries[..]),
+        };
+        for entry in analyzed {
+            match entry.record {
+                LogRecord::Commit | LogRecord::Abort => losers.remove(&entry.tid),
+                _ => losers.insert(entry.tid, entry.lsn),
+            };
+        }
+
+        // Changes before the checkpoint's redo_from were already in the heap files
+        let tid = TransactionId::new();
+        for entry in entries.iter().filter(|e| Some(e.lsn) >= redo_from) {
+            if self.redo(entry, tid)? {
+                report.redone += 1;
+            }
+        }
+
+        let by_lsn: HashMap<Lsn, &LogEntry> = entries.iter().map(|e| (e.lsn, e)).collect();
+        let mut to_undo: BTreeMap<Lsn, TransactionId> = BTreeMap::new();
+        for (&loser, &last) in &losers {
+            wal.resume(loser, last);
+            if undo {
+                to_undo.insert(last, loser);
+            }
+            report.losers.push(loser);
+        }
+        report.losers.sort_by_key(|loser| loser.id());
+        while let Some((lsn, loser)) = to_undo.pop_last() {
+            let Some(entry) = by_lsn.get(&lsn) else {
+                warn!(
+                    "Cannot undo {:?} past {:?}, which is not in the log",
+                    loser, lsn
+                );
+                wal.append(loser, LogRecord::Abort)?;
+                continue;
+            };
+            let next = match &entry.record {
+                LogRecord::Compensate { undo_next, .. } => *undo_next,
+                _ => {
+                    if self.undo(entry)? {
+                        report.undone += 1;
+                    }
+                    entry.prev_lsn
+                }
+            };
+            match next {
+                Some(next) => {
+                    to_undo.insert(next, loser);
+                }
+                None => {
+                    wal.append(loser, LogRecord::Abort)?;
+                }
+            }
+        }
+
+        self.flush_all()?;
+        wal.flush(wal.end_lsn())?;
+        Ok(report)
+    }
+
+    /// Roll back the changes tid logged since it last ended, following its records newest
+    /// first, and log that it aborted. Returns how many changes were rolled back.
+    pub(crate) fn roll_back(&self, tid: TransactionId) -> Result<usize, CrustyError> {
+        let Some(wal) = &self.wal else {
+            return Ok(0);
+        };
+        let Some(mut lsn) = wal.last_lsn(tid) else {
+            return Ok(0);
+        };
+        let entries = wal.entries()?;
+        let by_lsn: HashMap<Lsn, &LogEntry> = entries.iter().map(|e| (e.lsn, e)).collect();
+        let mut undone = 0;
+        loop {
+            let Some(entry) = by_lsn.get(&lsn) else {
+                warn!(
+                    "Cannot undo {:?} past {:?}, which is not in the log",
+                    tid, lsn
+                );
+                break;
+            };
+            let next = match &entry.record {
+                LogRecord::Compensate { undo_next, .. } => *undo_next,
+                _ => {
+                    if self.undo(entry)? {
+                        undone += 1;
+                    }
+                    entry.prev_lsn
+                }
+            };
+            match next {
+                Some(next) => lsn = next,
+                None => break,
+            }
+        }
+        wal.append(tid, LogRecord::Abort)?;
+        Ok(undone)
+    }
+
+    /// Apply entry again if the page it changes was written without it. True if it was.
+    pub(crate) fn redo(&self, entry: &LogEntry, tid: TransactionId) -> Result<bool, CrustyError> {
+        match &entry.record {
+            LogRecord::AllocatePage {
+                container_id,
+                page_id,
+            } => {
+                let Ok(hf) = self.get_hf(*container_id) else {
+                    return Ok(false);
+                };
+                let grows = hf.num_pages() <= *page_id;
+                while hf.num_pages() <= *page_id {
+                    hf.allocate_page()?;
+                }
+                Ok(grows)
+            }
+            LogRecord::Truncate {
+                container_id,
+                pages,
+            } => {
+                let Ok(hf) = self.get_hf(*container_id) else {
+                    return Ok(false);
+                };
+                if hf.num_pages() <= *pages {
+                    return Ok(false);
+                }
+                if let Some(bp) = &self.buffer_pool {
+                    bp.discard_pages(*container_id, *pages);
+                }
+                hf.truncate_pages(*pages)?;
+                Ok(true)
+            }
+            record => {
+                let Some(change) = slot_change(record) else {
+                    return Ok(false);
+                };
+                let id = changed_id(change);
+                let (Some(page_id), Some(slot_id)) = (id.page_id, id.slot_id) else {
+                    return Ok(false);
+                };
+                let Ok(hf) = self.get_hf(id.container_id) else {
+                    return Ok(false);
+                };
+                if page_id >= hf.num_pages() {
+                    warn!("Cannot redo {:?} on a page that is gone", entry);
+                    return Ok(false);
+                }
+                let mut page = self.fetch_page(&hf, page_id, tid, Permissions::ReadWrite)?;
+                if page.get_lsn() >= entry.lsn {
+                    return Ok(false);
+                }
+                if !apply(&mut page, slot_id, change) {
+                    warn!("Cannot redo {:?} on page {:?}", entry, page_id);
+                    return Ok(false);
+                }
+                page.set_lsn(entry.lsn);
+                self.store_page(&hf, &mut page, tid)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Roll back the change of entry and log that it was. Changes whose slot no longer holds
+    /// what they left in it, because the page was truncated and reused since, are left alone.
+    /// True if the change was rolled back.
+    fn undo(&self, entry: &LogEntry) -> Result<bool, CrustyError> {
+        let Some(undo) = inverse(&entry.record) else {
+            return Ok(false);
+        };
+        let id = changed_id(&undo);
+        let (Some(page_id), Some(slot_id)) = (id.page_id, id.slot_id) else {
+            return Ok(false);
+        };
+        let Ok(hf) = self.get_hf(id.container_id) else {
+            return Ok(false);
+        };
+        let _latch = hf.write_latch.lock().unwrap();
+        if page_id >= hf.num_pages() {
+            return Ok(false);
+        }
+        let mut page = self.fetch_page(&hf, page_id, entry.tid, Permissions::ReadWrite)?;
+        let left = match &entry.record {
+            LogRecord::Insert { after, .. } | LogRecord::Update { after, .. } => Some(after),
+            _ => None,
+        };
+        if SlotImage::of(&page, slot_id).as_ref() != left || !apply(&mut page, slot_id, &undo) {
+            warn!("Cannot undo {:?} on page {:?}", entry, page_id);
+            return Ok(false);
+        }
+        let compensation = LogRecord::Compensate {
+            change: Box::new(undo),
+            undo_next: entry.prev_lsn,
+        };
+        self.log_change(&mut page, compensation, entry.tid)?;
+        self.store_page(&hf, &mut page, entry.tid)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_recovery_redo_undo() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let (winner, loser) = (TransactionId::new(), TransactionId::new());
+        let kept = get_random_vec_of_byte_vec(60, 50, 100);
+        let (kept_ids, lost_ids);
+        {
+            let sm = StorageManager::new(&tdir);
+            sm.create_table(1).unwrap();
+            kept_ids = sm.insert_values(1, kept.clone(), winner);
+            sm.commit_transaction(winner).unwrap();
+            //some loser changes reach the heap file before the crash and some do not
+            lost_ids = sm.insert_values(1, get_random_vec_of_byte_vec(40, 50, 100), loser);
+            sm.update_value(get_random_byte_vec(20), kept_ids[3], loser)
+                .unwrap();
+            sm.flush_all().unwrap();
+            sm.delete_value(kept_ids[7], loser).unwrap();
+            //a committed change that only ever reached the log
+            sm.update_value(vec![7; 30], kept_ids[0], winner).unwrap();
+            sm.commit_transaction(winner).unwrap();
+            //dropped without shutting down, so the pool's dirty pages are lost
+        }
+
+        //the committed changes are all there and the loser's are rolled back
+        let sm = StorageManager::new(&tdir);
+        let tid = TransactionId::new();
+        assert_eq!(
+            vec![7; 30],
+            sm.get_value(kept_ids[0], tid, Permissions::ReadOnly)
+                .unwrap()
+        );
+        for (id, value) in kept_ids.iter().zip(&kept).skip(1) {
+            assert_eq!(
+                *value,
+                sm.get_value(*id, tid, Permissions::ReadOnly).unwrap()
+            );
+        }
+        for id in &lost_ids {
+            assert!(sm.get_value(*id, tid, Permissions::ReadOnly).is_err());
+        }
+        assert_eq!(
+            kept.len(),
+            sm.get_iterator(1, tid, Permissions::ReadOnly).count()
+        );
+
+        //recovery emptied the log, leaving nothing to redo or undo
+        let report = sm.recover().unwrap();
+        assert_eq!((0, 0, 0), (report.records, report.redone, report.undone));
+        assert!(report.losers.is_empty());
+    }
+
+    #[test]
+    fn hs_recovery_trait_commit_abort() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        let kept = get_random_vec_of_byte_vec(50, 50, 100);
+        let (kept_ids, lost_ids);
+        {
+            let sm = <StorageManager as StorageTrait>::new(&tdir);
+            sm.create_table(1).unwrap();
+            kept_ids = sm.insert_values(1, kept.clone(), tid);
+            sm.commit_transaction(tid).unwrap();
+
+            //an aborted change is rolled back right away and the transaction goes on
+            let aborted = sm.insert_values(1, get_random_vec_of_byte_vec(5, 50, 100), tid);
+            sm.update_value(vec![1; 40], kept_ids[0], tid).unwrap();
+            sm.delete_value(kept_ids[1], tid).unwrap();
+            sm.abort_transaction(tid).unwrap();
+            assert_eq!(
+                kept[0],
+                sm.get_value(kept_ids[0], tid, Permissions::ReadOnly)
+                    .unwrap()
+            );
+            for id in &aborted {
+                assert!(sm.get_value(*id, tid, Permissions::ReadOnly).is_err());
+            }
+            assert_eq!(
+                kept.len(),
+                sm.get_iterator(1, tid, Permissions::ReadOnly).count()
+            );
+
+            //a committed change only in the log, then changes still running at the crash
+            sm.update_value(vec![7; 30], kept_ids[2], tid).unwrap();
+            sm.commit_transaction(tid).unwrap();
+            lost_ids = sm.insert_values(1, get_random_vec_of_byte_vec(5, 50, 100), tid);
+            sm.delete_value(kept_ids[3], tid).unwrap();
+            sm.clear_cache();
+            //dropped without shutting down
+        }
+
+        let sm = <StorageManager as StorageTrait>::new(&tdir);
+        assert_eq!(
+            vec![7; 30],
+            sm.get_value(kept_ids[2], tid, Permissions::ReadOnly)
+                .unwrap()
+        );
+        for (i, (id, value)) in kept_ids.iter().zip(&kept).enumerate() {
+            if i != 2 {
+                assert_eq!(
+                    *value,
+                    sm.get_value(*id, tid, Permissions::ReadOnly).unwrap()
+                );
+            }
+        }
+        for id in &lost_ids {
+            assert!(sm.get_value(*id, tid, Permissions::ReadOnly).is_err());
+        }
+        assert_eq!(
+            kept.len(),
+            sm.get_iterator(1, tid, Permissions::ReadOnly).count()
+        );
+    }
+
+    #[test]
+    fn hs_recovery_twice_and_truncate() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        {
+            let sm = StorageManager::new(&tdir);
+            sm.create_table(1).unwrap();
+            sm.insert_values(1, get_random_vec_of_byte_vec(100, 50, 100), tid);
+            sm.commit_transaction(tid).unwrap();
+            sm.clear_container(1).unwrap();
+            let loser = TransactionId::new();
+            sm.insert_values(1, get_random_vec_of_byte_vec(10, 50, 100), loser);
+            sm.flush_all().unwrap();
+        }
+
+        //the clear is redone over the records logged before it and the loser rolled back
+        let sm = StorageManager::new(&tdir);
+        assert_eq!(0, sm.get_iterator(1, tid, Permissions::ReadOnly).count());
+
+        //committed inserts that only reached the log are redone onto the cleared container
+        let values = get_random_vec_of_byte_vec(20, 50, 100);
+        sm.insert_values(1, values.clone(), tid);
+        sm.commit_transaction(tid).unwrap();
+        drop(sm);
+        let sm = StorageManager::new(&tdir);
+        let scanned: Vec<Vec<u8>> = sm
+            .get_iterator(1, tid, Permissions::ReadOnly)
+            .map(|(value, _)| value)
+            .collect();
+        assert_eq!(values, scanned);
+
+        //a clean shutdown skips recovery
+        sm.shutdown();
+        drop(sm);
+        assert!(tdir.join(CLEAN_SHUTDOWN_FILENAME).exists());
+        let sm = StorageManager::new(&tdir);
+        assert!(!tdir.join(CLEAN_SHUTDOWN_FILENAME).exists());
+        assert_eq!(20, sm.get_iterator(1, tid, Permissions::ReadOnly).count());
+    }
+}
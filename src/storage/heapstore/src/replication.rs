@@ -0,0 +1,348 @@
+use crate::encryption::{EncryptionKey, KeyId};
+use crate::storage_manager::StorageManager;
+use crate::wal::{lsn_at, Wal};
+use common::prelude::*;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Bytes before the records of a shipment: the log offset of the first record in the
+/// primary's log file, the log offset the records start at and their length.
+const SHIPMENT_HEADER_SIZE: usize = 20;
+
+/// Records of a primary's write-ahead log as they are in its log file, sent to a standby to
+/// append to its own log at the same Lsns.
+#[derive(Debug)]
+pub(crate) struct LogShipment {
+    /// Log offset of the first record in the primary's log file. Records before it were
+    /// truncated from the primary's log, so the standby may drop them as well.
+    pub(crate) start: u64,
+    /// Log offset the records start at.
+    pub(crate) from: u64,
+    /// Whole encoded records. Empty when there was nothing new to ship.
+    pub(crate) records: Vec<u8>,
+}
+
+impl LogShipment {
+    /// Lsn right after the records.
+    fn end(&self) -> Lsn {
+        lsn_at(self.from + self.records.len() as u64)
+    }
+
+    fn write_to(&self, sink: &mut impl Write) -> Result<(), CrustyError> {
+        let mut header = [0u8; SHIPMENT_HEADER_SIZE];
+        header[..8].copy_from_slice(&self.start.to_le_bytes());
+        header[8..16].copy_from_slice(&self.from.to_le_bytes());
+        header[16..].copy_from_slice(&(self.records.len() as u32).to_le_bytes());
+        sink.write_all(&header)?;
+        sink.write_all(&self.records)?;
+        sink.flush()?;
+        Ok(())
+    }
+
+    /// Next shipment in source, None once it ends between shipments.
+    fn read_from(source: &mut impl Read) -> Result<Option<Self>, CrustyError> {
+        let mut header = [0u8; SHIPMENT_HEADER_SIZE];
+        match source.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_le_bytes(header[16..].try_into().unwrap()) as usize;
+        let mut records = vec![0; len];
+        source.read_exact(&mut records)?;
+        Ok(Some(LogShipment {
+            start: u64::from_le_bytes(header[..8].try_into().unwrap()),
+            from: u64::from_le_bytes(header[8..16].try_into().unwrap()),
+            records,
+        }))
+    }
+}
+
+/// Background thread sending the durable records of a write-ahead log to a standby at a fixed
+/// interval, even when there are none so the standby hears from it.
+pub(crate) struct LogShipper {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl LogShipper {
+    fn start(
+        wal: Arc<Wal>,
+        mut sink: Box<dyn Write + Send>,
+        from: Lsn,
+        interval: Duration,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::Builder::new()
+            .name("heapstore-log-shipper".to_string())
+            .spawn(move || {
+                let mut from = from;
+                loop {
+                    // A last shipment on the way out, so the standby has every durable record
+                    let last = !matches!(
+                        stopped.recv_timeout(interval),
+                        Err(RecvTimeoutError::Timeout)
+                    );
+                    let shipped = wal
+                        .shipment(from)
+                        .and_then(|shipment| shipment.write_to(&mut sink).map(|_| shipment.end()));
+                    match shipped {
+                        Ok(end) => from = end,
+                        Err(e) => {
+                            error!("Error shipping the log from {:?}: {}", from, e);
+                            break;
+                        }
+                    }
+                    if last {
+                        break;
+                    }
+                }
+                wal.stop_shipping();
+            })
+            .expect("error spawning the log shipper");
+        LogShipper { stop, handle }
+    }
+
+    /// Stop the thread once it has shipped what the log holds now.
+    pub(crate) fn stop(self) {
+        let _ = self.stop.send(());
+        if self.handle.join().is_err() {
+            error!("Log shipper panicked");
+        }
+    }
+}
+
+impl StorageManager {
+    /// Send the write-ahead log from from on to a standby through sink every interval,
+    /// replacing any shipping already running, until stop_log_shipping or shutdown. from is
+    /// where the standby's log ends, as Standby::end_lsn gives it. Records not yet shipped are
+    /// kept in the log until they are. Errors without a write-ahead log.
+    pub fn start_log_shipping(
+        &self,
+        sink: Box<dyn Write + Send>,
+        from: Lsn,
+        interval: Duration,
+    ) -> Result<(), CrustyError> {
+        let Some(wal) = &self.wal else {
+            return Err(CrustyError::CrustyError(
+                "Storage manager has no write-ahead log to ship".to_string(),
+            ));
+        };
+        self.stop_log_shipping();
+        wal.start_shipping(from)?;
+        *self.shipper.lock().unwrap() =
+            Some(LogShipper::start(Arc::clone(wal), sink, from, interval));
+        Ok(())
+    }
+
+    /// Stop shipping the log, after shipping what it holds now, if it is shipped.
+    pub fn stop_log_shipping(&self) {
+        if let Some(shipper) = self.shipper.lock().unwrap().take() {
+            shipper.stop();
+        }
+    }
+
+    /// Append shipment to the log and redo its records. Once the primary has truncated its
+    /// log, every page is written back so this log can be truncated to match.
+    fn apply_shipment(&self, shipment: &LogShipment) -> Result<(), CrustyError> {
+        let Some(wal) = &self.wal else {
+            return Err(CrustyError::CrustyError(
+                "Storage manager has no write-ahead log to follow".to_string(),
+            ));
+        };
+        let tid = TransactionId::new();
+        for entry in wal.receive(shipment)? {
+            let hf = entry
+                .record
+                .container_id()
+                .and_then(|cid| self.get_hf(cid).ok());
+            let _latch = hf.as_ref().map(|hf| hf.write_latch.lock().unwrap());
+            self.redo(&entry, tid)?;
+        }
+        let start = lsn_at(shipment.start);
+        if start > wal.start_lsn() {
+            self.flush_all()?;
+            wal.truncate(start)?;
+        }
+        Ok(())
+    }
+}
+
+/// Warm standby of a storage manager, kept up to date by redoing the write-ahead log its
+/// primary ships to it, which it can take over from. It starts from a backup of the primary
+/// restored with StorageManager::restore, and follows the log from where the backup's ends.
+/// Containers are not logged, so only the containers in the backup are followed.
+///
+/// The standby serves reads while it follows. They see the changes of the transactions that
+/// are still running on the primary, which promote rolls back.
+pub struct Standby {
+    sm: Arc<StorageManager>,
+    stop: Arc<AtomicBool>,
+    follower: Option<JoinHandle<Result<(), CrustyError>>>,
+}
+
+impl Standby {
+    /// Open storage_dir as a standby, with the keys of its encrypted containers.
+    pub fn open(storage_dir: &Path, keys: HashMap<KeyId, EncryptionKey>) -> Self {
+        Standby {
+            sm: Arc::new(StorageManager::open(storage_dir, keys, true)),
+            stop: Arc::new(AtomicBool::new(false)),
+            follower: None,
+        }
+    }
+
+    /// The standby's storage manager, to read from. It must not be changed while it follows.
+    pub fn storage_manager(&self) -> &StorageManager {
+        &self.sm
+    }
+
+    /// Lsn the standby's log ends at, which the primary ships from.
+    pub fn end_lsn(&self) -> Lsn {
+        self.sm
+            .wal
+            .as_ref()
+            .map(|wal| wal.end_lsn())
+            .unwrap_or_default()
+    }
+
+    /// Redo the shipments of the log in source on a background thread until source ends or
+    /// the standby is promoted. Errors if the standby already follows a primary.
+    pub fn follow(&mut self, mut source: Box<dyn Read + Send>) -> Result<(), CrustyError> {
+        if self.follower.is_some() {
+            return Err(CrustyError::CrustyError(
+                "Standby already follows a primary".to_string(),
+            ));
+        }
+        let sm = Arc::clone(&self.sm);
+        let stop = Arc::clone(&self.stop);
+        let handle = thread::Builder::new()
+            .name("heapstore-standby".to_string())
+            .spawn(move || {
+                while !stop.load(Ordering::Acquire) {
+                    match LogShipment::read_from(&mut source)? {
+                        Some(shipment) => sm.apply_shipment(&shipment)?,
+                        None => break,
+                    }
+                }
+                Ok(())
+            })
+            .expect("error spawning the standby");
+        self.follower = Some(handle);
+        Ok(())
+    }
+
+    /// Stop following and take over as a storage manager of its own, rolling back the
+    /// transactions that had not committed by the last record shipped. Waits for the next
+    /// shipment unless the primary is gone, so the standby is as up to date as it can be.
+    pub fn promote(mut self) -> Result<StorageManager, CrustyError> {
+        self.stop.store(true, Ordering::Release);
+        if let Some(follower) = self.follower.take() {
+            match follower.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Standby stopped following its primary: {}", e),
+                Err(_) => error!("Standby panicked"),
+            }
+        }
+        let sm = Arc::try_unwrap(self.sm)
+            .map_err(|_| CrustyError::CrustyError("Standby is still in use".to_string()))?;
+        let report = sm.recover()?;
+        info!("Promoted standby {:?}: {:?}", sm.storage_dir, report);
+        for hf in sm.cid_heapfile_map.read().unwrap().values() {
+            hf.refresh_fsm()?;
+        }
+        Ok(sm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use std::os::unix::net::UnixStream;
+    use std::time::Instant;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_replication_standby() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let backup_dir = TempDir::new(gen_random_test_sm_dir(), true);
+        let standby_dir = TempDir::new(gen_random_test_sm_dir(), true);
+        let vals = get_random_vec_of_byte_vec(300, 50, 200);
+        let tid = TransactionId::new();
+        let sm = StorageManager::new(&tdir);
+        sm.create_table(1).unwrap();
+        let backed_up = sm.insert_values(1, vals[..100].to_vec(), tid);
+        sm.commit_transaction(tid).unwrap();
+        sm.backup(&backup_dir).unwrap();
+        StorageManager::restore(&backup_dir, &standby_dir).unwrap();
+
+        let mut standby = Standby::open(&standby_dir, HashMap::new());
+        let (sink, source) = UnixStream::pair().unwrap();
+        standby.follow(Box::new(source)).unwrap();
+        assert!(standby.follow(Box::new(std::io::empty())).is_err());
+        let interval = Duration::from_millis(10);
+        sm.start_log_shipping(Box::new(sink), standby.end_lsn(), interval)
+            .unwrap();
+        let shipped = sm.insert_values(1, vals[100..200].to_vec(), tid);
+        sm.commit_transaction(tid).unwrap();
+        let running = TransactionId::new();
+        let rolled_back = sm.insert_values(1, vals[200..].to_vec(), running);
+        sm.delete_value(backed_up[0], running).unwrap();
+        //the checkpoint makes the running transaction durable and truncates both logs
+        sm.checkpoint().unwrap();
+        let wal = sm.wal.as_ref().unwrap();
+        let standby_wal = Arc::clone(standby.sm.wal.as_ref().unwrap());
+        let caught_up =
+            || standby.end_lsn() == wal.end_lsn() && standby_wal.start_lsn() == wal.start_lsn();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !caught_up() && Instant::now() < deadline {
+            thread::sleep(interval);
+        }
+        assert!(caught_up());
+
+        //the standby serves what the primary committed while it follows
+        let reader = standby.storage_manager();
+        for (id, val) in backed_up[1..]
+            .iter()
+            .zip(&vals[1..])
+            .chain(shipped.iter().zip(&vals[100..]))
+        {
+            assert_eq!(
+                *val,
+                reader.get_value(*id, tid, Permissions::ReadOnly).unwrap()
+            );
+        }
+
+        //the primary goes away and the standby takes over without what had not committed
+        drop(standby_wal);
+        drop(sm);
+        let promoted = standby.promote().unwrap();
+        for (id, val) in backed_up
+            .iter()
+            .zip(&vals)
+            .chain(shipped.iter().zip(&vals[100..]))
+        {
+            assert_eq!(
+                *val,
+                promoted.get_value(*id, tid, Permissions::ReadOnly).unwrap()
+            );
+        }
+        for id in &rolled_back {
+            assert!(promoted.get_value(*id, tid, Permissions::ReadOnly).is_err());
+        }
+        let id = promoted.insert_value(1, vals[0].clone(), tid);
+        promoted.commit_transaction(tid).unwrap();
+        assert_eq!(
+            vals[0],
+            promoted.get_value(id, tid, Permissions::ReadOnly).unwrap()
+        );
+    }
+}
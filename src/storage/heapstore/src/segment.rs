@@ -0,0 +1,285 @@
+use crate::file_header::{HeapFileHeader, NO_SEGMENTS};
+use common::prelude::*;
+use common::PAGE_SIZE;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+/// Bytes of each segment file a new container's heap file is split into by default.
+pub const DEFAULT_SEGMENT_SIZE: usize = 256 << 20;
+
+/// File holding segment n of the heap file at heap_file, which is the heap file itself for
+/// segment 0. Segment 0 starts with the header page, and the rest follow on from the page
+/// before them, so the segments laid end to end are the file the heap file would be whole.
+pub(crate) fn segment_path(heap_file: &Path, segment: usize) -> PathBuf {
+    match segment {
+        0 => heap_file.to_path_buf(),
+        n => heap_file.with_extension(format!("seg{}", n)),
+    }
+}
+
+/// Files of the segments after the first that exist beside heap_file, in order.
+pub(crate) fn extra_segments(heap_file: &Path) -> Vec<PathBuf> {
+    (1..)
+        .map(|n| segment_path(heap_file, n))
+        .take_while(|path| path.exists())
+        .collect()
+}
+
+/// Remove the segment files after the first beside heap_file.
+pub(crate) fn remove_extra_segments(heap_file: &Path) -> Result<(), CrustyError> {
+    for path in extra_segments(heap_file) {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Pages of each segment, the header page counted, for segments of segment_size bytes, or
+/// NO_SEGMENTS to keep the heap file whole for None.
+/// Err if a segment would not hold the header and a data page.
+pub(crate) fn segment_pages(segment_size: Option<usize>) -> Result<u32, CrustyError> {
+    let size = match segment_size {
+        Some(size) => size,
+        None => return Ok(NO_SEGMENTS),
+    };
+    match u32::try_from(size / PAGE_SIZE) {
+        Ok(pages) if pages >= 2 => Ok(pages),
+        Ok(_) => Err(CrustyError::CrustyError(format!(
+            "Segments of {} bytes cannot hold two {} byte pages",
+            size, PAGE_SIZE
+        ))),
+        Err(_) => Err(CrustyError::CrustyError(format!(
+            "Segments of {} bytes are too large",
+            size
+        ))),
+    }
+}
+
+/// Bytes of a whole segment of segment_pages pages, None if the file is not split.
+pub(crate) fn segment_len(segment_pages: u32) -> Option<u64> {
+    match segment_pages {
+        NO_SEGMENTS => None,
+        pages => Some(pages as u64 * PAGE_SIZE as u64),
+    }
+}
+
+/// Segment holding the byte at offset of a heap file split into segments of segment_pages
+/// pages, and the offset of that byte in the segment.
+pub(crate) fn locate(segment_pages: u32, offset: u64) -> (usize, u64) {
+    match segment_len(segment_pages) {
+        Some(len) => ((offset / len) as usize, offset % len),
+        None => (0, offset),
+    }
+}
+
+/// Segment the first len bytes of a heap file end in and the bytes they take of it. A length
+/// ending on a segment boundary ends in the whole segment before it, not an empty one.
+pub(crate) fn end_of(segment_pages: u32, len: u64) -> (usize, u64) {
+    match (locate(segment_pages, len), segment_len(segment_pages)) {
+        ((segment, 0), Some(whole)) if segment > 0 => (segment - 1, whole),
+        end => end.0,
+    }
+}
+
+/// Bytes of the heap file at path with its segments after it, as if it were kept whole.
+/// Segments are only looked for beside a file whose header says it is split.
+pub fn read_heap_file(path: &Path) -> Result<Vec<u8>, CrustyError> {
+    let mut bytes = fs::read(path)?;
+    if split(&bytes) {
+        for segment in extra_segments(path) {
+            File::open(segment)?.read_to_end(&mut bytes)?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Whether bytes start with the header of a heap file split into segments.
+fn split(bytes: &[u8]) -> bool {
+    HeapFileHeader::is_header(bytes)
+        && bytes
+            .get(..PAGE_SIZE)
+            .and_then(|page| HeapFileHeader::from_bytes(page.try_into().unwrap()).ok())
+            .is_some_and(|header| header.segment_pages != NO_SEGMENTS)
+}
+
+/// A heap file and its segments, read and written at offsets of the file they make up, for
+/// the tools that work on heap files no storage manager has open.
+pub(crate) struct SegmentedFile {
+    heap_file: PathBuf,
+    segment_pages: u32,
+    // Every segment that exists, the heap file first
+    files: Vec<File>,
+}
+
+impl SegmentedFile {
+    /// Open the heap file at heap_file and its segments, with how it is split read from its
+    /// header. A file without a header is taken to be whole.
+    pub(crate) fn open(heap_file: &Path) -> Result<Self, CrustyError> {
+        let file = Self::open_segment(heap_file, false)?;
+        let mut data = [0u8; PAGE_SIZE];
+        let segment_pages = match file.read_exact_at(&mut data, 0) {
+            Ok(()) if split(&data) => HeapFileHeader::from_bytes(&data)?.segment_pages,
+            _ => NO_SEGMENTS,
+        };
+        Self::with_segments(heap_file, file, segment_pages)
+    }
+
+    /// Open the heap file at heap_file split into segments of segment_pages pages, creating
+    /// it if it does not exist.
+    pub(crate) fn create(heap_file: &Path, segment_pages: u32) -> Result<Self, CrustyError> {
+        let file = Self::open_segment(heap_file, true)?;
+        Self::with_segments(heap_file, file, segment_pages)
+    }
+
+    fn with_segments(
+        heap_file: &Path,
+        file: File,
+        segment_pages: u32,
+    ) -> Result<Self, CrustyError> {
+        let mut files = vec![file];
+        if segment_pages != NO_SEGMENTS {
+            for path in extra_segments(heap_file) {
+                files.push(Self::open_segment(&path, false)?);
+            }
+        }
+        Ok(SegmentedFile {
+            heap_file: heap_file.to_path_buf(),
+            segment_pages,
+            files,
+        })
+    }
+
+    fn open_segment(path: &Path, create: bool) -> Result<File, CrustyError> {
+        Ok(OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(create)
+            .truncate(false)
+            .open(path)?)
+    }
+
+    /// Fill data from offset, which must not cross into another segment.
+    pub(crate) fn read_exact_at(&self, data: &mut [u8], offset: u64) -> Result<(), CrustyError> {
+        let (segment, offset) = locate(self.segment_pages, offset);
+        let file = self
+            .files
+            .get(segment)
+            .ok_or_else(|| std::io::Error::from(ErrorKind::UnexpectedEof))?;
+        file.read_exact_at(data, offset)?;
+        Ok(())
+    }
+
+    /// Write data at offset, which must not cross into another segment, creating the segments
+    /// up to the one it goes in.
+    pub(crate) fn write_all_at(&mut self, data: &[u8], offset: u64) -> Result<(), CrustyError> {
+        let (segment, offset) = locate(self.segment_pages, offset);
+        while self.files.len() <= segment {
+            let path = segment_path(&self.heap_file, self.files.len());
+            self.files.push(Self::open_segment(&path, true)?);
+        }
+        self.files[segment].write_all_at(data, offset)?;
+        Ok(())
+    }
+
+    /// Cut the file to len bytes, removing the segments wholly past it.
+    pub(crate) fn set_len(&mut self, len: u64) -> Result<(), CrustyError> {
+        let (last, last_len) = end_of(self.segment_pages, len);
+        while self.files.len() > last + 1 {
+            self.files.pop();
+            fs::remove_file(segment_path(&self.heap_file, self.files.len()))?;
+        }
+        if let Some(file) = self.files.get(last) {
+            file.set_len(last_len)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn sync_all(&self) -> Result<(), CrustyError> {
+        for file in &self.files {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::ContainerOptions;
+    use crate::fsck;
+    use crate::storage_manager::StorageManager;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_segment_split_heap_file() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let backup_dir = TempDir::new(gen_random_test_sm_dir(), true);
+        let restore_dir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        let vals: Vec<Vec<u8>> = (0..40).map(|i| vec![i as u8; 1000]).collect();
+        let options = ContainerOptions {
+            segment_size: Some(4 * PAGE_SIZE + 100),
+            ..ContainerOptions::default()
+        };
+        let ids = {
+            let sm = StorageManager::new(&tdir);
+            sm.create_container_with(1, options.clone()).unwrap();
+            let ids = sm.bulk_insert(1, vals.clone(), tid).unwrap();
+            sm.commit_transaction(tid).unwrap();
+            sm.flush_all().unwrap();
+
+            //the header and 10 pages take 3 segments of 4 pages
+            let usage = sm.disk_usage(1).unwrap();
+            assert_eq!((10, 3), (usage.pages, usage.segments));
+            assert_eq!(11 * PAGE_SIZE as u64, usage.heap_file);
+            for n in 0..3 {
+                let len = fs::metadata(segment_path(&tdir.join("1.hf"), n))
+                    .unwrap()
+                    .len();
+                assert!(len <= 4 * PAGE_SIZE as u64);
+            }
+            sm.backup(&backup_dir).unwrap();
+            sm.shutdown();
+            ids
+        };
+        let bytes = read_heap_file(&tdir.join("1.hf")).unwrap();
+        assert_eq!(11 * PAGE_SIZE, bytes.len());
+        assert!(fsck::check(&tdir).unwrap().is_empty());
+
+        //pages are found in their segments after a reopen and a restore
+        StorageManager::restore(&backup_dir, &restore_dir).unwrap();
+        assert_eq!(2, extra_segments(&restore_dir.join("1.hf")).len());
+        for dir in [&*tdir, &*restore_dir] {
+            let sm = StorageManager::new(dir);
+            for (id, val) in ids.iter().zip(&vals) {
+                assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+            }
+        }
+
+        //cutting pages off removes the segments past them whole
+        let sm = StorageManager::new(&tdir);
+        for id in ids.iter().filter(|id| id.page_id.unwrap() >= 2) {
+            sm.delete_value(*id, tid).unwrap();
+        }
+        sm.commit_transaction(tid).unwrap();
+        let report = sm.vacuum_container(1).unwrap();
+        assert_eq!((8, 2), (report.pages_truncated, report.segments_removed));
+        assert!(extra_segments(&tdir.join("1.hf")).is_empty());
+        assert_eq!(1, sm.disk_usage(1).unwrap().segments);
+        sm.bulk_insert(1, vals[..8].to_vec(), tid).unwrap();
+        assert_eq!(2, sm.disk_usage(1).unwrap().segments);
+
+        //segments must hold the header and a data page
+        let tiny = ContainerOptions {
+            segment_size: Some(PAGE_SIZE),
+            ..options
+        };
+        assert!(sm.create_container_with(2, tiny).is_err());
+        sm.remove_container(1).unwrap();
+        assert!(extra_segments(&tdir.join("1.hf")).is_empty());
+    }
+}
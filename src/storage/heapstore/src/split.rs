@@ -0,0 +1,316 @@
+use crate::heap_page::{
+    HeapPage, SlotEncoding, PAGE_FLAG_SORTED, PAGE_META_FILL_FACTOR_OFFSET, PAGE_META_FLAGS_OFFSET,
+    SLOT_FLAG_COMPRESSED, SLOT_IN_USE_FREE, SLOT_IN_USE_VALID,
+};
+use crate::page::SizedPage;
+use common::prelude::*;
+use std::fmt;
+
+///why merge_from left both pages as they were
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeError {
+    ///the records do not fit in the free space of the page merged into
+    ///needed counts them as the other page stores them plus any slot entries the directory must grow by
+    NoSpace { needed: usize, available: usize },
+    ///the other page holds slot flags or compressed records the page merged into cannot store
+    UnsupportedRecords,
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeError::NoSpace { needed, available } => write!(
+                f,
+                "Merge needs {} bytes but the page has {} free",
+                needed, available
+            ),
+            MergeError::UnsupportedRecords => {
+                write!(f, "Merged records carry flags the page cannot store")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+impl From<MergeError> for CrustyError {
+    fn from(error: MergeError) -> Self {
+        CrustyError::CrustyError(error.to_string())
+    }
+}
+
+///one live record lifted off a page with what it needs to be stored again elsewhere
+struct MovedRecord {
+    slot_id: SlotId,
+    in_use: u8,
+    flags: u8,
+    ///decoded value for a valid record on a prefix compressed page otherwise the stored bytes
+    bytes: Vec<u8>,
+    ///bytes the record takes in the body as stored
+    stored_len: usize,
+}
+
+impl<const N: usize> SizedPage<N> {
+    ///moves the upper half of the live records by stored byte volume into a new sibling page
+    ///records keep their slot order so the sibling holds the records that followed the ones kept
+    ///a page with fewer than two live records keeps them and returns an empty sibling
+    ///the sibling has this page's id fill factor slot encoding compaction policy
+    ///secure delete prefix compression and sorted mode but neither zone map nor bloom filter
+    ///moved records get new SlotIds on the sibling from 0 in the order they had here
+    ///both pages come back compacted
+    pub fn split(&mut self) -> Self {
+        let records = self.live_records();
+        let split_at = Self::split_point(&records);
+
+        let mut sibling = Self::new(self.get_page_id());
+        sibling.set_compaction_policy(self.get_compaction_policy());
+        if self.get_slot_encoding() == SlotEncoding::Compact {
+            sibling.set_slot_encoding(SlotEncoding::Compact).unwrap();
+        }
+        if self.is_secure_delete() {
+            sibling.set_secure_delete(true);
+        }
+        if self.is_prefix_compressed() {
+            sibling.set_prefix_compression(true).unwrap();
+        }
+        //the records fit on this page past its fill factor so they must fit on the sibling too
+        sibling.set_fill_factor(100).unwrap();
+        for record in &records[split_at..] {
+            sibling
+                .store_record(record)
+                .expect("moved records fit in an empty sibling");
+        }
+        sibling.data_mut()[PAGE_META_FILL_FACTOR_OFFSET] = self.data[PAGE_META_FILL_FACTOR_OFFSET];
+        if self.is_sorted() {
+            sibling.data_mut()[PAGE_META_FLAGS_OFFSET] |= PAGE_FLAG_SORTED;
+        }
+
+        //only a suffix leaves so no kept record was prefix encoded against a moved one
+        //and a sorted page keeps its positions
+        for record in records[split_at..].iter().rev() {
+            self.write_slot_flags(record.slot_id, 0);
+            self.set_slot_in_use(record.slot_id, SLOT_IN_USE_FREE);
+        }
+        self.truncate_free_tail();
+        self.compact();
+        sibling
+    }
+
+    ///pulls every live record of other into this page after its own records
+    ///returns (SlotId on other, SlotId here) for each record in other's SlotId order
+    ///so the caller can fix up references to them
+    ///records fill free slot entries first and this page's fill factor is ignored
+    ///a sorted page stays sorted only if other holds records with the larger keys such as a split sibling
+    ///Err leaves this page unchanged if the records do not fit or carry flags it cannot store
+    pub fn merge_from(&mut self, other: &Self) -> Result<Vec<(SlotId, SlotId)>, MergeError> {
+        let records = other.live_records();
+        let flagged = records.iter().any(|r| r.flags != 0);
+        let compressed = records.iter().any(|r| r.flags & SLOT_FLAG_COMPRESSED != 0);
+        if (flagged && self.get_slot_encoding() == SlotEncoding::Compact)
+            || (compressed && (self.is_prefix_compressed() || self.is_sorted()))
+        {
+            return Err(MergeError::UnsupportedRecords);
+        }
+
+        //merged into a copy so a record that does not fit leaves this page untouched
+        let mut merged = self.clone();
+        merged.set_fill_factor(100).unwrap();
+        let mut remap = Vec::with_capacity(records.len());
+        for record in &records {
+            match merged.store_record(record) {
+                Some(slot_id) => remap.push((record.slot_id, slot_id)),
+                None => {
+                    let free_entries = (0..self.slot_count() as SlotId)
+                        .filter(|&sid| self.get_slot_in_use(sid) == Some(SLOT_IN_USE_FREE))
+                        .count();
+                    let new_entries = records.len().saturating_sub(free_entries);
+                    return Err(MergeError::NoSpace {
+                        needed: other.live_bytes() + new_entries * self.slot_meta_size(),
+                        available: self.get_free_space(),
+                    });
+                }
+            }
+        }
+        merged.data_mut()[PAGE_META_FILL_FACTOR_OFFSET] = self.data[PAGE_META_FILL_FACTOR_OFFSET];
+        *self = merged;
+        Ok(remap)
+    }
+
+    ///stores a record lifted off another page under the lowest free SlotId keeping its flags
+    ///a valid record on a prefix compressed page is encoded against its new neighbours
+    fn store_record(&mut self, record: &MovedRecord) -> Option<SlotId> {
+        let slot_id = if record.in_use == SLOT_IN_USE_VALID && self.is_prefix_compressed() {
+            self.add_value(&record.bytes)?
+        } else {
+            self.insert_slot(&record.bytes, record.in_use)?
+        };
+        self.write_slot_flags(slot_id, record.flags);
+        Some(slot_id)
+    }
+
+    ///every live record in SlotId order
+    fn live_records(&self) -> Vec<MovedRecord> {
+        (0..self.slot_count() as SlotId)
+            .filter_map(|slot_id| {
+                let in_use = self.get_slot_in_use(slot_id)?;
+                if in_use == SLOT_IN_USE_FREE {
+                    return None;
+                }
+                let stored = self.slot_bytes(slot_id)?;
+                let bytes = if in_use == SLOT_IN_USE_VALID && self.is_prefix_compressed() {
+                    self.decode_value(slot_id)?
+                } else {
+                    stored.to_vec()
+                };
+                Some(MovedRecord {
+                    slot_id,
+                    in_use,
+                    flags: self.slot_flags(slot_id),
+                    bytes,
+                    stored_len: stored.len(),
+                })
+            })
+            .collect()
+    }
+
+    ///index of the first record to move
+    ///a record moves when its middle byte lies in the upper half of the volume
+    ///and at least one record stays and one moves when there are two or more
+    fn split_point(records: &[MovedRecord]) -> usize {
+        if records.len() < 2 {
+            return records.len();
+        }
+        let total: usize = records.iter().map(|r| r.stored_len).sum();
+        let mut before = 0;
+        let mut split_at = records.len();
+        for (i, record) in records.iter().enumerate() {
+            if 2 * before + record.stored_len > total {
+                split_at = i;
+                break;
+            }
+            before += record.stored_len;
+        }
+        split_at.clamp(1, records.len() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap_page::SLOT_FLAG_LOCKED;
+    use crate::page::Page;
+    use common::testutil::*;
+
+    #[test]
+    fn hs_page_split() {
+        init();
+        let vals = get_random_vec_of_byte_vec(30, 50, 120);
+        let mut p = Page::new(3);
+        for v in &vals {
+            p.add_value(v).unwrap();
+        }
+        p.delete_value(5).unwrap();
+        p.set_slot_flags(25, SLOT_FLAG_LOCKED).unwrap();
+        let free_before = p.get_free_space();
+
+        let sibling = p.split();
+        assert_eq!(3, sibling.get_page_id());
+        let kept: Vec<Vec<u8>> = p.iter().map(|(_, v)| v.into_owned()).collect();
+        let moved: Vec<Vec<u8>> = sibling.iter().map(|(_, v)| v.into_owned()).collect();
+        //slot order survives across the two pages
+        let mut expected = vals.clone();
+        expected.remove(5);
+        assert_eq!(expected, [kept.clone(), moved.clone()].concat());
+        let kept_bytes: usize = kept.iter().map(Vec::len).sum();
+        let moved_bytes: usize = moved.iter().map(Vec::len).sum();
+        assert!(kept_bytes.abs_diff(moved_bytes) <= 120);
+        assert_eq!((kept_bytes, 0), (p.live_bytes(), p.dead_bytes()));
+        assert_eq!(
+            (moved_bytes, 0),
+            (sibling.live_bytes(), sibling.dead_bytes())
+        );
+        assert!(p.get_free_space() > free_before);
+        //the freed slot 5 stays in the directory below the kept records
+        assert_eq!(kept.len() + 1, p.slot_count());
+        let locked = 25 - p.slot_count() as SlotId;
+        assert_eq!(Some(SLOT_FLAG_LOCKED), sibling.get_slot_flags(locked));
+        assert!(sibling.check_integrity().is_ok() && p.check_integrity().is_ok());
+
+        //a lone record stays put
+        let mut one = Page::new(0);
+        one.add_value(&[1; 10]).unwrap();
+        assert_eq!(0, one.split().slot_count());
+        assert_eq!(Some(vec![1; 10]), one.get_value(0));
+
+        //prefix compressed records are re-encoded for their new neighbours
+        let mut prefixed = Page::new(0);
+        prefixed.set_prefix_compression(true).unwrap();
+        for i in 0..20u8 {
+            prefixed
+                .add_value(&[b"common-prefix-".as_slice(), &[i; 8]].concat())
+                .unwrap();
+        }
+        let mut sibling = prefixed.split();
+        assert!(sibling.is_prefix_compressed());
+        let kept = prefixed.iter().count();
+        assert_eq!(20, kept + sibling.slot_count());
+        //the first record kept its shared prefix so it stores more than the records after it
+        assert_eq!(9, kept);
+        assert_eq!(
+            Some([b"common-prefix-".as_slice(), &[kept as u8; 8]].concat()),
+            sibling.get_value(0)
+        );
+        sibling.set_page_id(9);
+        assert_eq!(9, sibling.get_page_id());
+    }
+
+    #[test]
+    fn hs_page_merge() {
+        init();
+        let vals = get_random_vec_of_byte_vec(20, 40, 100);
+        let mut p = Page::new(0);
+        for v in &vals {
+            p.add_value(v).unwrap();
+        }
+        let mut sibling = p.split();
+        sibling.set_slot_flags(1, SLOT_FLAG_LOCKED).unwrap();
+        p.delete_value(2).unwrap();
+        let kept = p.iter().count();
+
+        let remap = p.merge_from(&sibling).unwrap();
+        assert_eq!(sibling.iter().count(), remap.len());
+        //the freed entry is filled first then the directory grows
+        assert_eq!((0, 2), remap[0]);
+        assert_eq!((1, kept as SlotId + 1), remap[1]);
+        for &(from, to) in &remap {
+            assert_eq!(sibling.get_value(from), p.get_value(to));
+        }
+        assert_eq!(Some(SLOT_FLAG_LOCKED), p.get_slot_flags(remap[1].1));
+        assert_eq!(vals.len() - 1, p.iter().count());
+        assert!(p.check_integrity().is_ok());
+
+        //a merge that does not fit leaves the page as it was
+        let before = *p.to_bytes();
+        let mut full = Page::new(1);
+        while full.add_value(&[7; 100]).is_some() {}
+        match p.merge_from(&full) {
+            Err(MergeError::NoSpace { needed, available }) => assert!(needed > available),
+            other => panic!("expected NoSpace, got {:?}", other),
+        }
+        assert_eq!(before, *p.to_bytes());
+
+        //flags have no room under the compact encoding
+        let mut compact = Page::new(2);
+        compact.set_slot_encoding(SlotEncoding::Compact).unwrap();
+        assert_eq!(
+            Err(MergeError::UnsupportedRecords),
+            compact.merge_from(&sibling)
+        );
+        assert_eq!(0, compact.slot_count());
+        sibling.set_slot_flags(1, 0).unwrap();
+        assert_eq!(
+            sibling.iter().count(),
+            compact.merge_from(&sibling).unwrap().len()
+        );
+    }
+}
@@ -1,15 +1,35 @@
-use crate::heap_page::HeapPage;
-use crate::heapfile::HeapFile;
-use crate::heapfileiter::HeapFileIterator;
+use crate::buffer_pool::{
+    BufferPool, BufferPoolSize, BufferPoolStats, Flusher, PageGuard, PoolSettings,
+    ReplacementPolicy,
+};
+use crate::checkpoint::Checkpointer;
+use crate::container::{CatalogEntry, ContainerCatalog, ContainerInfo, ContainerOptions};
+use crate::encryption::{seal_path, EncryptionKey, KeyId, KeyRing};
+use crate::file_header::NO_SEGMENTS;
+use crate::fixed_page::PageLayout;
+use crate::fsm::fsm_path;
+use crate::heap_page::{HeapPage, PageInsertError};
+use crate::heapfile::{HeapFile, HeapFileIo};
+use crate::heapfileiter::{HeapFileIterator, ScanPartition};
+use crate::metrics::{Metrics, StorageOp};
 use crate::page::Page;
+use crate::prefetch::{Prefetcher, ReadAhead, DEFAULT_PREFETCH_WINDOW};
+use crate::recovery::{clear_clean_shutdown, mark_clean_shutdown, was_shut_down_cleanly};
+use crate::replication::LogShipper;
+use crate::segment::{remove_extra_segments, segment_pages};
+use crate::temp::clear_temp_dir;
+use crate::wal::{LogRecord, SlotImage, Wal};
+use crate::WRITE_THROUGH;
+use common::ids::{StateMeta, StateType};
 use common::prelude::*;
 use common::storage_trait::StorageTrait;
 use common::testutil::gen_random_test_sm_dir;
-use common::PAGE_SIZE;
-use std::collections::HashMap;
+use common::{PAGE_SIZE, PAGE_SLOTS};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::Ordering;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::Duration;
 use std::{fs, num};
 
 pub const STORAGE_DIR: &str = "heapstore";
@@ -17,7 +37,15 @@ pub const STORAGE_DIR: &str = "heapstore";
 // The data types we need for tracking the mapping between containerId and HeapFile/PathBuf
 pub(crate) type ContainerMap = Arc<RwLock<HashMap<ContainerId, Arc<HeapFile>>>>;
 pub(crate) type ContainerPathMap = Arc<RwLock<HashMap<ContainerId, Arc<PathBuf>>>>;
-const PERSIST_CONFIG_FILENAME: &str = "storage_manager";
+pub(crate) type ContainerLayoutMap = Arc<RwLock<HashMap<ContainerId, PageLayout>>>;
+pub(crate) type ContainerMetaMap = Arc<RwLock<HashMap<ContainerId, StateMeta>>>;
+pub(crate) const PERSIST_CONFIG_FILENAME: &str = "storage_manager";
+
+fn default_prefetch_window() -> AtomicUsize {
+    AtomicUsize::new(DEFAULT_PREFETCH_WINDOW)
+}
+/// Pages a bulk load fills in memory before appending them with one write.
+const BULK_LOAD_BATCH_PAGES: usize = 64;
 
 /// The StorageManager struct
 #[derive(Serialize, Deserialize)]
@@ -27,8 +55,59 @@ pub struct StorageManager {
     /// Indicates if this is a temp StorageManager (for testing)
     is_temp: bool,
     pub(crate) cid_path_map: ContainerPathMap,
+    /// Page layout of each container. Containers without an entry use slotted pages.
+    #[serde(default)]
+    pub(crate) cid_layout_map: ContainerLayoutMap,
+    /// Name, type and dependencies of every container
+    #[serde(default)]
+    pub(crate) cid_meta_map: ContainerMetaMap,
+    /// Largest record stored inline on the pages of every container. None keeps the page default.
+    #[serde(default)]
+    pub(crate) max_record_size: RwLock<Option<usize>>,
+    /// How the heap files of containers created or opened from now on read and write pages.
+    #[serde(default)]
+    pub(crate) file_io: RwLock<HeapFileIo>,
     #[serde(skip)]
     pub(crate) cid_heapfile_map: ContainerMap,
+    /// Containers made by create_temp_container, left out of the catalog and the log.
+    #[serde(skip)]
+    pub(crate) temp_containers: RwLock<HashSet<ContainerId>>,
+    /// Cache of pages shared by every container. None reads and writes the heap files directly.
+    #[serde(skip)]
+    pub(crate) buffer_pool: Option<Arc<BufferPool>>,
+    /// Background write back of the buffer pool's dirty pages, if started.
+    #[serde(skip)]
+    flusher: Mutex<Option<Flusher>>,
+    /// Background checkpoints of the write-ahead log, if started.
+    #[serde(skip)]
+    pub(crate) checkpointer: Mutex<Option<Checkpointer>>,
+    /// Background shipping of the write-ahead log to a standby, if started.
+    #[serde(skip)]
+    pub(crate) shipper: Mutex<Option<LogShipper>>,
+    /// Size and replacement policy of the buffer pool as of the last shutdown, restored on open.
+    #[serde(default)]
+    pool_settings: RwLock<Option<PoolSettings>>,
+    /// Pages a container scan reads into the buffer pool ahead of itself. 0 turns read ahead off.
+    #[serde(default = "default_prefetch_window")]
+    prefetch_window: AtomicUsize,
+    /// Whether shutdown saves the pages in the buffer pool for the next open to read back in.
+    #[serde(default)]
+    warm_up: AtomicBool,
+    /// Thread doing the read ahead of every scan, started by the first scan that needs it.
+    #[serde(skip)]
+    prefetcher: OnceLock<Arc<Prefetcher>>,
+    /// Held while saving the catalog so an older list of containers never replaces a newer one.
+    #[serde(skip)]
+    pub(crate) catalog_lock: Mutex<()>,
+    /// Write-ahead log of the changes made to pages. None changes pages without logging.
+    #[serde(skip)]
+    pub(crate) wal: Option<Arc<Wal>>,
+    /// Keys of the encrypted containers. Never saved, so every open supplies them again.
+    #[serde(skip)]
+    pub(crate) encryption_keys: KeyRing,
+    /// Page, compaction and latency counters, shared with every heap file.
+    #[serde(skip)]
+    pub(crate) metrics: Arc<Metrics>,
 }
 
 /// The required functions in HeapStore's StorageManager that are specific for HeapFiles
@@ -40,9 +119,118 @@ impl StorageManager {
         page_id: PageId,
         _tid: TransactionId,
         _perm: Permissions,
-        _pin: bool,
+        pin: bool,
     ) -> Option<Page> {
-        panic!("TODO milestone hs");
+        let hf = self.get_hf(container_id).ok()?;
+        let mut page = match &self.buffer_pool {
+            Some(bp) => bp.get_page(&hf, page_id, pin).ok()?,
+            None => hf.read_page_from_file(page_id).ok()?,
+        };
+        page.set_max_record_size(self.get_max_record_size());
+        Some(page)
+    }
+
+    /// Pin a page in the buffer pool so it is not evicted while the guard is held.
+    /// Writes through the guard mark the page dirty. Err without a buffer pool or if the page
+    /// cannot be read or every frame is pinned.
+    pub fn pin_page(
+        &self,
+        container_id: ContainerId,
+        page_id: PageId,
+    ) -> Result<PageGuard, CrustyError> {
+        let hf = self.get_hf(container_id)?;
+        if page_id >= hf.num_pages() {
+            return Err(CrustyError::CrustyError(format!(
+                "Container {} has no page {}",
+                container_id, page_id
+            )));
+        }
+        match &self.buffer_pool {
+            Some(bp) => bp.pin_page(&hf, page_id),
+            None => Err(CrustyError::CrustyError(
+                "Pages can only be pinned with a buffer pool".to_string(),
+            )),
+        }
+    }
+
+    /// Resize the buffer pool. Shrinking it writes back and evicts the pages that no longer fit.
+    /// Err without a buffer pool or if the size is less than a page.
+    pub fn set_buffer_pool_size(&self, size: BufferPoolSize) -> Result<(), CrustyError> {
+        let frames = size.frames()?;
+        match &self.buffer_pool {
+            Some(bp) => bp.set_capacity(frames),
+            None => Err(CrustyError::CrustyError(
+                "Storage manager has no buffer pool to resize".to_string(),
+            )),
+        }
+    }
+
+    /// Hits, misses, evictions and occupancy of the buffer pool, None without one
+    pub fn buffer_pool_stats(&self) -> Option<BufferPoolStats> {
+        self.buffer_pool.as_ref().map(|bp| bp.stats())
+    }
+
+    /// Start the buffer pool's counters again from 0
+    pub fn reset_buffer_pool_stats(&self) {
+        if let Some(bp) = &self.buffer_pool {
+            bp.reset_stats();
+        }
+    }
+
+    /// Write back up to batch of the pages that have been dirty the longest every interval on a
+    /// background thread, replacing any flusher already running. Does nothing without a buffer
+    /// pool.
+    pub fn start_flusher(&self, interval: Duration, batch: usize) {
+        self.stop_flusher();
+        if let Some(bp) = &self.buffer_pool {
+            let flusher = Flusher::start(Arc::clone(bp), interval, batch);
+            *self.flusher.lock().unwrap() = Some(flusher);
+        }
+    }
+
+    /// Stop the background flusher if one is running.
+    pub fn stop_flusher(&self) {
+        if let Some(flusher) = self.flusher.lock().unwrap().take() {
+            flusher.stop();
+        }
+    }
+
+    /// Write every dirty page and the free space maps to disk and sync the heap files, as a
+    /// checkpoint needs.
+    pub fn flush_all(&self) -> Result<(), CrustyError> {
+        let _timer = self.metrics.time(StorageOp::Flush);
+        let _span = tracing::trace_span!("flush_all").entered();
+        if let Some(bp) = &self.buffer_pool {
+            bp.flush_all()?;
+        }
+        for hf in self.cid_heapfile_map.read().unwrap().values() {
+            hf.sync()?;
+            hf.persist_fsm()?;
+        }
+        Ok(())
+    }
+
+    /// Write back the dirty cached pages among pages as a group, each run of consecutive pages of
+    /// a container with one write, and sync their heap files. Pages not cached are already on
+    /// disk. Returns how many pages were written.
+    pub fn flush_pages(&self, pages: &[(ContainerId, PageId)]) -> Result<usize, CrustyError> {
+        let written = match &self.buffer_pool {
+            Some(bp) => bp.flush_pages(pages)?,
+            None => 0,
+        };
+        let containers: BTreeSet<ContainerId> = pages.iter().map(|(cid, _)| *cid).collect();
+        for cid in containers {
+            self.get_hf(cid)?.sync()?;
+        }
+        Ok(written)
+    }
+
+    /// Release a pin taken by get_page. False if the page was not pinned.
+    pub(crate) fn unpin_page(&self, container_id: ContainerId, page_id: PageId) -> bool {
+        match &self.buffer_pool {
+            Some(bp) => bp.unpin(container_id, page_id),
+            None => false,
+        }
     }
 
     /// Write a page
@@ -50,52 +238,162 @@ impl StorageManager {
         &self,
         container_id: ContainerId,
         page: &Page,
-        _tid: TransactionId,
+        tid: TransactionId,
     ) -> Result<(), CrustyError> {
-        panic!("TODO milestone hs");
+        self.store_page(&self.get_hf(container_id)?, &mut page.clone(), tid)
     }
 
     /// Get the number of pages for a container
     fn get_num_pages(&self, container_id: ContainerId) -> PageId {
-        panic!("TODO milestone hs");
+        self.get_hf(container_id)
+            .map(|hf| hf.num_pages())
+            .unwrap_or(0)
     }
 
     /// Test utility function for counting reads and writes served by the heap file.
     /// Can return 0,0 for invalid container_ids
     #[allow(dead_code)]
     pub(crate) fn get_hf_read_write_count(&self, container_id: ContainerId) -> (u16, u16) {
-        panic!("TODO milestone hs");
+        match self.get_hf(container_id) {
+            Ok(hf) => (
+                hf.read_count.load(Ordering::Relaxed),
+                hf.write_count.load(Ordering::Relaxed),
+            ),
+            Err(_) => (0, 0),
+        }
     }
 
-    /// For testing
-    pub fn get_page_debug(&self, container_id: ContainerId, page_id: PageId) -> String {
-        match self.get_page(
-            container_id,
-            page_id,
-            TransactionId::new(),
-            Permissions::ReadOnly,
-            false,
-        ) {
-            Some(p) => {
-                format!("{:?}", p)
+    /// Heap file backing a container, or an error if the container was never created.
+    pub(crate) fn get_hf(&self, container_id: ContainerId) -> Result<Arc<HeapFile>, CrustyError> {
+        self.cid_heapfile_map
+            .read()
+            .unwrap()
+            .get(&container_id)
+            .cloned()
+            .ok_or_else(|| {
+                CrustyError::CrustyError(format!("Container {} does not exist", container_id))
+            })
+    }
+
+    /// Page of hf read through the buffer pool when there is one, on behalf of tid which
+    /// means to use it as perm says.
+    /// A page fetched with read only permission is refused by store_page.
+    pub(crate) fn fetch_page(
+        &self,
+        hf: &Arc<HeapFile>,
+        page_id: PageId,
+        _tid: TransactionId,
+        perm: Permissions,
+    ) -> Result<Page, CrustyError> {
+        let mut page = match &self.buffer_pool {
+            Some(bp) => bp.get_page(hf, page_id, false)?,
+            None => hf.read_page_from_file(page_id)?,
+        };
+        page.set_max_record_size(self.get_max_record_size());
+        page.read_only = perm == Permissions::ReadOnly;
+        Ok(page)
+    }
+
+    /// Page of hf written through the buffer pool when there is one. Every page change the
+    /// storage manager makes goes through here, attributed to the transaction tid.
+    /// A page that extends the file is written straight away so the file never has a gap.
+    /// The compactions the page counted since it was loaded or last stored are charged here.
+    /// Err if the page was fetched with read only permission.
+    pub(crate) fn store_page(
+        &self,
+        hf: &Arc<HeapFile>,
+        page: &mut Page,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        tracing::trace!(
+            tid = tid.id(),
+            container_id = hf.container_id,
+            page_id = page.get_page_id(),
+            "store page"
+        );
+        if page.read_only {
+            return Err(CrustyError::InvalidMutationError(format!(
+                "Transaction {} fetched page {} of container {} read only and cannot write it",
+                tid.id(),
+                page.get_page_id(),
+                hf.container_id
+            )));
+        }
+        self.metrics.compacted(page.take_compactions());
+        match &self.buffer_pool {
+            Some(bp) if !WRITE_THROUGH && page.get_page_id() < hf.num_pages() => {
+                bp.put_page(hf, page.clone(), true)?;
+                hf.note_free_space(page.get_page_id(), page.get_free_space());
+                Ok(())
+            }
+            Some(bp) => {
+                self.flush_log(std::slice::from_ref(page))?;
+                hf.write_page_to_file(page)?;
+                bp.put_page(hf, page.clone(), false)
+            }
+            None => {
+                self.flush_log(std::slice::from_ref(page))?;
+                hf.write_page_to_file(page)
             }
-            None => String::new(),
         }
     }
-}
 
-/// Implementation of storage trait
-impl StorageTrait for StorageManager {
-    type ValIterator = HeapFileIterator;
+    /// Buffer pool of the default size that writes back no page before wal has its changes.
+    fn logged_buffer_pool(wal: &Arc<Wal>) -> Arc<BufferPool> {
+        let bp = BufferPool::new(PAGE_SLOTS);
+        bp.set_wal(Arc::clone(wal));
+        Arc::new(bp)
+    }
 
-    /// Create a new storage manager that will use storage_dir as the location to persist data
-    /// (if the storage manager persists records on disk)
-    /// For startup/shutdown: check the storage_dir for data persisted in shutdown() that you can
-    /// use to populate this instance of the SM. Otherwise create a new one.
-    fn new(storage_dir: &Path) -> Self {
+    /// Fresh storage manager with no containers, creating storage_dir if needed.
+    fn new_empty(storage_dir: PathBuf, is_temp: bool) -> Self {
+        fs::create_dir_all(&storage_dir).expect("error creating storage directory");
+        let wal = Arc::new(Wal::open(&storage_dir).expect("error opening the write-ahead log"));
+        StorageManager::unopened(storage_dir, is_temp, Some(wal))
+    }
+
+    /// Storage manager with no containers and default settings that touches nothing on disk.
+    /// Without a log there is no buffer pool either, so pages go straight to the heap files.
+    fn unopened(storage_dir: PathBuf, is_temp: bool, wal: Option<Arc<Wal>>) -> Self {
+        StorageManager {
+            storage_dir,
+            is_temp,
+            cid_path_map: Arc::new(RwLock::new(HashMap::new())),
+            cid_layout_map: Arc::new(RwLock::new(HashMap::new())),
+            cid_meta_map: Arc::new(RwLock::new(HashMap::new())),
+            max_record_size: RwLock::new(None),
+            file_io: RwLock::new(HeapFileIo::default()),
+            cid_heapfile_map: Arc::new(RwLock::new(HashMap::new())),
+            temp_containers: RwLock::new(HashSet::new()),
+            buffer_pool: wal.as_ref().map(StorageManager::logged_buffer_pool),
+            flusher: Mutex::new(None),
+            checkpointer: Mutex::new(None),
+            shipper: Mutex::new(None),
+            pool_settings: RwLock::new(None),
+            prefetch_window: default_prefetch_window(),
+            warm_up: AtomicBool::new(false),
+            prefetcher: OnceLock::new(),
+            catalog_lock: Mutex::new(()),
+            wal,
+            encryption_keys: Arc::new(RwLock::new(HashMap::new())),
+            metrics: Arc::new(Metrics::default()),
+        }
+    }
+
+    /// Open storage_dir as StorageTrait::new describes, with keys to its encrypted containers.
+    /// A standby redoes the log without rolling back the transactions it leaves running or
+    /// truncating it, so records shipped from its primary can be appended to it.
+    pub(crate) fn open(
+        storage_dir: &Path,
+        keys: HashMap<KeyId, EncryptionKey>,
+        standby: bool,
+    ) -> Self {
+        clear_temp_dir(storage_dir).expect("error removing leftover temp containers");
+        let catalog =
+            ContainerCatalog::load(storage_dir).expect("error reading the container catalog");
         let sm_file = storage_dir;
         let sm_file = sm_file.join(PERSIST_CONFIG_FILENAME);
-        if sm_file.exists() {
+        let sm = if sm_file.exists() {
             debug!("Loading storage manager from config file {:?}", sm_file);
             let reader = fs::File::open(sm_file).expect("error opening persist config file");
             let sm: StorageManager =
@@ -106,34 +404,634 @@ impl StorageTrait for StorageManager {
 
             let path_map: ContainerPathMap = sm.cid_path_map.clone();
             let old_files = path_map.read().unwrap();
+            let wal = Arc::new(Wal::open(storage_dir).expect("error opening the write-ahead log"));
+            let metrics = Arc::new(Metrics::default());
 
-            for (id, path) in old_files.iter() {
-                let hf = HeapFile::new(path.to_path_buf(), *id)
-                    .expect("Error creating/opening old HF {path}");
+            for (id, path) in old_files.iter().filter(|_| catalog.is_none()) {
+                let hf = HeapFile::new_with_io(path.to_path_buf(), *id, sm.get_file_io())
+                    .unwrap_or_else(|e| {
+                        panic!("Error creating/opening old HF {}: {e}", path.display())
+                    });
+                hf.set_wal(Arc::clone(&wal));
+                hf.set_metrics(Arc::clone(&metrics));
                 hmfiles.insert(*id, Arc::new(path.to_path_buf()));
                 hm.insert(*id, Arc::new(hf));
             }
 
             let cid_heapfile_map = Arc::new(RwLock::new(hm));
             let cid_path_map = Arc::new(RwLock::new(hmfiles));
+            let pool_settings = *sm.pool_settings.read().unwrap();
             StorageManager {
                 storage_dir: storage_dir.to_path_buf(),
                 cid_heapfile_map,
+                temp_containers: RwLock::new(HashSet::new()),
                 cid_path_map,
+                cid_layout_map: sm.cid_layout_map.clone(),
+                cid_meta_map: sm.cid_meta_map.clone(),
+                max_record_size: RwLock::new(sm.get_max_record_size()),
+                file_io: RwLock::new(sm.get_file_io()),
                 is_temp: false,
+                buffer_pool: Some(StorageManager::logged_buffer_pool(&wal)),
+                flusher: Mutex::new(None),
+                checkpointer: Mutex::new(None),
+                shipper: Mutex::new(None),
+                pool_settings: RwLock::new(pool_settings),
+                prefetch_window: AtomicUsize::new(sm.get_prefetch_window()),
+                warm_up: AtomicBool::new(sm.get_warm_up()),
+                prefetcher: OnceLock::new(),
+                catalog_lock: Mutex::new(()),
+                wal: Some(wal),
+                encryption_keys: Arc::new(RwLock::new(HashMap::new())),
+                metrics,
             }
         } else {
             debug!("Making new storage_manager in directory {:?}", storage_dir);
-            panic!("TODO milestone hs");
+            StorageManager::new_empty(storage_dir.to_path_buf(), false)
+        };
+        // Before the catalog is opened, so its encrypted containers can be
+        *sm.encryption_keys.write().unwrap() = keys;
+        match catalog {
+            Some(catalog) => sm
+                .open_catalog(catalog, false)
+                .expect("error opening the containers in the catalog"),
+            None => sm
+                .save_catalog()
+                .expect("error saving the container catalog"),
+        }
+        if let (Some(bp), Some(settings)) = (&sm.buffer_pool, *sm.pool_settings.read().unwrap()) {
+            bp.apply_settings(settings)
+                .expect("error restoring the buffer pool settings");
+        }
+        if standby {
+            let report = sm.replay().expect("error replaying the write-ahead log");
+            debug!("Replayed {:?} as a standby: {:?}", storage_dir, report);
+            clear_clean_shutdown(storage_dir).expect("error clearing the clean shutdown mark");
+            return sm;
+        }
+        if !was_shut_down_cleanly(storage_dir) {
+            let report = sm
+                .recover()
+                .expect("error recovering from the write-ahead log");
+            if report.records > 0 {
+                info!(
+                    "Recovered {:?} from the write-ahead log: {:?}",
+                    storage_dir, report
+                );
+            }
+            // The saved free space maps are only known to match the pages after a clean shutdown
+            for hf in sm.cid_heapfile_map.read().unwrap().values() {
+                hf.refresh_fsm().expect("error rebuilding a free space map");
+            }
+        }
+        // Every logged change is in the heap files now, so the log starts over before the
+        // shutdown mark goes and a crash from here on recovers from this point
+        if let Some(wal) = &sm.wal {
+            wal.truncate(wal.end_lsn())
+                .expect("error truncating the write-ahead log");
+        }
+        clear_clean_shutdown(storage_dir).expect("error clearing the clean shutdown mark");
+        // The cache only starts cold if the pages cannot be read back
+        if let Err(e) = sm.warm_up_buffer_pool() {
+            warn!("Error warming up the buffer pool: {}", e);
+        }
+        sm
+    }
+
+    /// Open storage_dir to read the containers in its catalog without changing anything in it,
+    /// so a tool can look at a directory another storage manager may have open. Temp containers
+    /// and the clean shutdown mark are left alone, the log is not opened, recovered from or
+    /// truncated and the buffer pool is not warmed up, so changes only in the log are not seen.
+    /// The heap files are opened without write access, so nothing can be written through it.
+    /// Errors if storage_dir has no catalog, a container in it is encrypted or its heap file is
+    /// missing, empty or cannot be read.
+    pub fn open_read_only(storage_dir: &Path) -> Result<Self, CrustyError> {
+        let catalog = ContainerCatalog::load(storage_dir)?.ok_or_else(|| {
+            CrustyError::CrustyError(format!("No container catalog in {:?}", storage_dir))
+        })?;
+        let sm_file = storage_dir.join(PERSIST_CONFIG_FILENAME);
+        let mut sm = if sm_file.exists() {
+            // Deserialized without a log or a buffer pool, as the skipped fields default to None
+            let reader = fs::File::open(sm_file)?;
+            serde_json::from_reader(reader).map_err(|e| {
+                CrustyError::CrustyError(format!("Error reading the persist config: {}", e))
+            })?
+        } else {
+            StorageManager::unopened(storage_dir.to_path_buf(), false, None)
+        };
+        sm.storage_dir = storage_dir.to_path_buf();
+        sm.is_temp = false;
+        sm.open_catalog(catalog, true)?;
+        Ok(sm)
+    }
+
+    /// Insert value into a container as insert_value does, returning an error where it panics:
+    /// if the container does not exist or the pages it needs would take the container past
+    /// its quota. A value larger than the max record size is stored in overflow pages.
+    pub fn try_insert_value(
+        &self,
+        container_id: ContainerId,
+        value: Vec<u8>,
+        tid: TransactionId,
+    ) -> Result<ValueId, CrustyError> {
+        let _timer = self.metrics.time(StorageOp::Insert);
+        let span = tracing::trace_span!(
+            "insert_value",
+            tid = tid.id(),
+            container_id,
+            page_id = tracing::field::Empty,
+            slot_id = tracing::field::Empty,
+            len = value.len()
+        );
+        let _entered = span.enter();
+        let hf = self.get_hf(container_id)?;
+        let _latch = hf.write_latch.lock().unwrap();
+        let id = self.insert_into(&hf, &value, tid)?;
+        span.record("page_id", id.page_id);
+        span.record("slot_id", id.slot_id);
+        Ok(id)
+    }
+
+    /// Store value on a page the free space map says has room for it, adding a page if none has.
+    /// A value larger than the max record size goes to overflow pages instead.
+    /// The caller must hold hf's write latch.
+    pub(crate) fn insert_into(
+        &self,
+        hf: &Arc<HeapFile>,
+        value: &[u8],
+        tid: TransactionId,
+    ) -> Result<ValueId, CrustyError> {
+        if !self.fits_inline(value.len()) {
+            return self.insert_overflow(hf, value, tid);
+        }
+        while let Some(page_id) = hf.find_page_with_space(value.len()) {
+            let mut page = self.fetch_page(hf, page_id, tid, Permissions::ReadWrite)?;
+            match page.try_add_value(value) {
+                Ok(slot_id) => {
+                    self.log_slot(hf, &mut page, slot_id, None, tid)?;
+                    self.store_page(hf, &mut page, tid)?;
+                    return Ok(ValueId::new_slot(hf.container_id, page_id, slot_id));
+                }
+                // The page was given a smaller max record size than the one set now
+                Err(PageInsertError::RecordTooLarge { .. }) => {
+                    return self.insert_overflow(hf, value, tid)
+                }
+                // The map only rounds free space, so correct it and look again
+                Err(PageInsertError::PageFull { available, .. }) => {
+                    hf.note_free_space(page_id, available)
+                }
+                Err(_) => hf.note_free_space(page_id, 0),
+            }
+        }
+        // A new page is known to be empty so it is not read back
+        hf.check_quota(hf.num_pages() as u64 + 1)?;
+        let page_id = hf.allocate_page()?;
+        let mut page = Page::new(page_id);
+        page.set_max_record_size(self.get_max_record_size());
+        let allocated = LogRecord::AllocatePage {
+            container_id: hf.container_id,
+            page_id,
+        };
+        self.log_change(&mut page, allocated, tid)?;
+        let slot_id = page.try_add_value(value)?;
+        self.log_slot(hf, &mut page, slot_id, None, tid)?;
+        self.store_page(hf, &mut page, tid)?;
+        Ok(ValueId::new_slot(hf.container_id, page_id, slot_id))
+    }
+
+    /// Whether a record of len bytes fits on an empty page of this storage manager, rather
+    /// than going to overflow pages.
+    pub(crate) fn fits_inline(&self, len: usize) -> bool {
+        let mut page = Page::new(0);
+        page.set_max_record_size(self.get_max_record_size());
+        len <= page.get_max_record_size()
+    }
+
+    /// Choose how the pages of a container lay out their records.
+    /// Must be set before the container holds any pages since existing pages are not rewritten.
+    pub fn set_container_layout(&self, container_id: ContainerId, layout: PageLayout) {
+        self.cid_layout_map
+            .write()
+            .unwrap()
+            .insert(container_id, layout);
+        if let Err(e) = self.save_catalog() {
+            error!("Error saving the container catalog: {}", e);
+        }
+    }
+
+    /// Page layout of a container, slotted unless another was chosen
+    pub fn get_container_layout(&self, container_id: ContainerId) -> PageLayout {
+        self.cid_layout_map
+            .read()
+            .unwrap()
+            .get(&container_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Create a container with its own heap file. Does nothing if the container exists.
+    /// Err if another container already has the name.
+    pub fn create_container_with(
+        &self,
+        container_id: ContainerId,
+        options: ContainerOptions,
+    ) -> Result<(), CrustyError> {
+        {
+            let mut heapfiles = self.cid_heapfile_map.write().unwrap();
+            if heapfiles.contains_key(&container_id) {
+                debug!("Container {} already exists", container_id);
+                return Ok(());
+            }
+            let mut metas = self.cid_meta_map.write().unwrap();
+            if let Some(name) = &options.name {
+                if let Some(other) = metas.values().find(|m| m.name.as_ref() == Some(name)) {
+                    return Err(CrustyError::CrustyError(format!(
+                        "Container {} is already named {}",
+                        other.id, name
+                    )));
+                }
+            }
+            let path = self.storage_dir.join(format!("{}.hf", container_id));
+            let hf = self.open_heap_file(
+                path.clone(),
+                container_id,
+                options.encryption_key,
+                segment_pages(options.segment_size)?,
+            )?;
+            hf.set_quota(options.quota);
+            heapfiles.insert(container_id, Arc::new(hf));
+            self.cid_path_map
+                .write()
+                .unwrap()
+                .insert(container_id, Arc::new(path));
+            self.cid_layout_map
+                .write()
+                .unwrap()
+                .insert(container_id, options.layout);
+            metas.insert(container_id, options.to_meta(container_id));
+        }
+        self.save_catalog()
+    }
+
+    /// Open or create the heap file of a logged container, rebuilding pages that fail their
+    /// checksum from the log. A new file is encrypted with new_key if that is not None and
+    /// split into segment files of segment_pages pages.
+    fn open_heap_file(
+        &self,
+        path: PathBuf,
+        container_id: ContainerId,
+        new_key: Option<KeyId>,
+        segment_pages: u32,
+    ) -> Result<HeapFile, CrustyError> {
+        let keys = self.encryption_keys.read().unwrap();
+        let hf = HeapFile::new_with_keys(
+            path,
+            container_id,
+            self.get_file_io(),
+            &keys,
+            new_key,
+            segment_pages,
+        )?;
+        if let Some(wal) = &self.wal {
+            hf.set_wal(Arc::clone(wal));
+        }
+        hf.set_metrics(Arc::clone(&self.metrics));
+        Ok(hf)
+    }
+
+    /// Write the containers and their options to the catalog, replacing the saved one whole.
+    pub(crate) fn save_catalog(&self) -> Result<(), CrustyError> {
+        let _saving = self.catalog_lock.lock().unwrap();
+        let containers = {
+            let heapfiles = self.cid_heapfile_map.read().unwrap();
+            let paths = self.cid_path_map.read().unwrap();
+            let layouts = self.cid_layout_map.read().unwrap();
+            let metas = self.cid_meta_map.read().unwrap();
+            let temps = self.temp_containers.read().unwrap();
+            paths
+                .iter()
+                .filter(|(id, _)| !temps.contains(id))
+                .map(|(&id, path)| CatalogEntry {
+                    meta: metas
+                        .get(&id)
+                        .cloned()
+                        .unwrap_or_else(|| ContainerOptions::default().to_meta(id)),
+                    path: path.to_path_buf(),
+                    layout: layouts.get(&id).copied().unwrap_or_default(),
+                    quota: heapfiles.get(&id).and_then(|hf| hf.quota()),
+                })
+                .collect()
+        };
+        ContainerCatalog::new(containers).save(&self.storage_dir)
+    }
+
+    /// Open the containers a catalog lists in place of any already open, without write access
+    /// to their heap files if read_only.
+    fn open_catalog(&self, catalog: ContainerCatalog, read_only: bool) -> Result<(), CrustyError> {
+        let mut heapfiles = self.cid_heapfile_map.write().unwrap();
+        let mut paths = self.cid_path_map.write().unwrap();
+        let mut layouts = self.cid_layout_map.write().unwrap();
+        let mut metas = self.cid_meta_map.write().unwrap();
+        heapfiles.clear();
+        paths.clear();
+        layouts.clear();
+        metas.clear();
+        for entry in catalog.containers {
+            let id = entry.meta.id;
+            let hf = if read_only {
+                let hf = HeapFile::open_read_only(entry.path.clone(), id)?;
+                hf.set_metrics(Arc::clone(&self.metrics));
+                hf
+            } else {
+                self.open_heap_file(entry.path.clone(), id, None, NO_SEGMENTS)?
+            };
+            hf.set_quota(entry.quota);
+            heapfiles.insert(id, Arc::new(hf));
+            paths.insert(id, Arc::new(entry.path));
+            layouts.insert(id, entry.layout);
+            metas.insert(id, entry.meta);
+        }
+        Ok(())
+    }
+
+    /// Load values into new pages appended after a container's last page, filling each page in
+    /// memory and writing them a batch at a time without searching for free space.
+    /// Existing pages are left alone. Returns the ids of the values in order.
+    /// Err if a value does not fit on an empty page. Values loaded before it stay.
+    pub fn bulk_insert(
+        &self,
+        container_id: ContainerId,
+        values: impl IntoIterator<Item = Vec<u8>>,
+        tid: TransactionId,
+    ) -> Result<Vec<ValueId>, CrustyError> {
+        let _timer = self.metrics.time(StorageOp::BulkInsert);
+        let hf = self.get_hf(container_id)?;
+        let _latch = hf.write_latch.lock().unwrap();
+        let new_page = |page_id: PageId| {
+            let mut page = Page::new(page_id);
+            page.set_max_record_size(self.get_max_record_size());
+            page
+        };
+        let mut ids = Vec::new();
+        let mut filled: Vec<Page> = Vec::new();
+        let mut page = new_page(hf.num_pages());
+        for value in values {
+            let slot_id = match page.try_add_value(&value) {
+                Ok(slot_id) => slot_id,
+                Err(PageInsertError::PageFull { .. } | PageInsertError::SlotDirectoryFull)
+                    if page.slot_count() > 0 =>
+                {
+                    let next_id = page.get_page_id().checked_add(1).ok_or_else(|| {
+                        CrustyError::CrustyError(format!(
+                            "Container {} has no page ids left to load into",
+                            container_id
+                        ))
+                    })?;
+                    filled.push(std::mem::replace(&mut page, new_page(next_id)));
+                    if filled.len() == BULK_LOAD_BATCH_PAGES {
+                        self.flush_log(&filled)?;
+                        hf.write_pages_to_file(&filled)?;
+                        filled.clear();
+                    }
+                    page.try_add_value(&value)?
+                }
+                Err(e) => {
+                    self.flush_log(&filled)?;
+                    hf.write_pages_to_file(&filled)?;
+                    return Err(e.into());
+                }
+            };
+            if page.slot_count() == 1 {
+                // Checked before the page is logged, so nothing past the quota is ever redone
+                if let Err(e) = hf.check_quota(page.get_page_id() as u64 + 1) {
+                    self.flush_log(&filled)?;
+                    hf.write_pages_to_file(&filled)?;
+                    return Err(e);
+                }
+                let allocated = LogRecord::AllocatePage {
+                    container_id,
+                    page_id: page.get_page_id(),
+                };
+                self.log_change(&mut page, allocated, tid)?;
+            }
+            self.log_slot(&hf, &mut page, slot_id, None, tid)?;
+            ids.push(ValueId::new_slot(container_id, page.get_page_id(), slot_id));
+        }
+        if page.slot_count() > 0 {
+            filled.push(page);
+        }
+        self.flush_log(&filled)?;
+        hf.write_pages_to_file(&filled)?;
+        debug!(
+            "Transaction {} loaded {} values into container {}",
+            tid.id(),
+            ids.len(),
+            container_id
+        );
+        Ok(ids)
+    }
+
+    /// Id of the container with a name
+    pub fn find_container(&self, name: &str) -> Option<ContainerId> {
+        self.cid_meta_map
+            .read()
+            .unwrap()
+            .values()
+            .find(|m| m.name.as_deref() == Some(name))
+            .map(|m| m.id)
+    }
+
+    /// Every container in id order
+    pub fn list_containers(&self) -> Vec<ContainerInfo> {
+        let heapfiles = self.cid_heapfile_map.read().unwrap();
+        let paths = self.cid_path_map.read().unwrap();
+        let metas = self.cid_meta_map.read().unwrap();
+        let mut containers: Vec<ContainerInfo> = heapfiles
+            .iter()
+            .map(|(&id, hf)| {
+                let meta = metas.get(&id);
+                ContainerInfo {
+                    id,
+                    name: meta.and_then(|m| m.name.clone()),
+                    state_type: meta.map_or(StateType::BaseTable, |m| m.state_type.clone()),
+                    dependencies: meta.and_then(|m| m.dependencies.clone()),
+                    layout: self.get_container_layout(id),
+                    pages: hf.num_pages(),
+                    path: paths.get(&id).map(|p| p.to_path_buf()).unwrap_or_default(),
+                    encryption_key: hf.sealer.is_some().then(|| hf.header().key_id),
+                    quota: hf.quota(),
+                }
+            })
+            .collect();
+        containers.sort_by_key(|c| c.id);
+        containers
+    }
+
+    /// Drop every record of a container, leaving it empty with its name and options.
+    /// Same as clear_container.
+    pub fn truncate_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
+        self.clear_container(container_id)
+    }
+
+    /// Empty a container in time independent of how many records it holds. Its cached pages
+    /// are discarded unwritten and its heap file cut back to the header in place, header
+    /// first, so a crash part way leaves either the old records or none. Iterators already open
+    /// on the container stop after the page they are reading.
+    pub fn clear_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
+        let hf = self.get_hf(container_id)?;
+        // Let a write in progress finish before its pages are discarded
+        let _latch = hf.write_latch.lock().unwrap();
+        self.log_truncate(&hf, 0)?;
+        if let Some(bp) = &self.buffer_pool {
+            bp.discard_container(container_id);
+        }
+        hf.truncate_pages(0)?;
+        hf.persist_fsm()
+    }
+
+    /// Cap the records stored inline on the pages this storage manager creates or reads.
+    /// Larger ones are inserted into overflow pages, as records larger than a page always are,
+    /// and updating a record past the cap moves it there. None keeps the largest record a page
+    /// can hold.
+    pub fn set_max_record_size(&self, max: Option<usize>) {
+        *self.max_record_size.write().unwrap() = max;
+    }
+
+    /// Largest record stored inline if one was set
+    pub fn get_max_record_size(&self) -> Option<usize> {
+        *self.max_record_size.read().unwrap()
+    }
+
+    /// Choose how the buffer pool picks pages to evict. Pages already cached stay cached.
+    pub fn set_replacement_policy(&self, policy: Box<dyn ReplacementPolicy>) {
+        if let Some(bp) = &self.buffer_pool {
+            bp.set_policy(policy);
+        }
+    }
+
+    /// Name of the buffer pool's replacement policy, None without a buffer pool
+    pub fn get_replacement_policy(&self) -> Option<&'static str> {
+        self.buffer_pool.as_ref().map(|bp| bp.policy_name())
+    }
+
+    /// Choose how the heap files of containers created from now on read and write pages.
+    /// The choice is saved on shutdown and applies to every container when the storage manager
+    /// is opened again.
+    pub fn set_file_io(&self, io: HeapFileIo) {
+        *self.file_io.write().unwrap() = io;
+    }
+
+    /// How new heap files read and write pages
+    pub fn get_file_io(&self) -> HeapFileIo {
+        *self.file_io.read().unwrap()
+    }
+
+    /// Set how many pages ahead of itself a container scan reads into the buffer pool.
+    /// Applies to scans started from now on. 0 turns read ahead off.
+    pub fn set_prefetch_window(&self, pages: usize) {
+        self.prefetch_window.store(pages, Ordering::Relaxed);
+    }
+
+    /// Pages a container scan reads ahead of itself
+    pub fn get_prefetch_window(&self) -> usize {
+        self.prefetch_window.load(Ordering::Relaxed)
+    }
+
+    /// Set whether shutdown saves the pages in the buffer pool so the next open reads them
+    /// back in and starts with a warm cache. Off by default, and saved with the settings.
+    pub fn set_warm_up(&self, on: bool) {
+        self.warm_up.store(on, Ordering::Relaxed);
+    }
+
+    /// Whether the buffer pool is warmed up from the pages it held at the last shutdown
+    pub fn get_warm_up(&self) -> bool {
+        self.warm_up.load(Ordering::Relaxed)
+    }
+
+    /// Split a container's pages into n disjoint partitions of about the same number of pages
+    /// that together cover the container, in page order. The last partition has no end so
+    /// records on pages appended after the split are scanned too. Errors if n is 0 or the
+    /// container does not exist.
+    pub fn scan_partitions(
+        &self,
+        container_id: ContainerId,
+        n: usize,
+        tid: TransactionId,
+    ) -> Result<Vec<ScanPartition>, CrustyError> {
+        if n == 0 {
+            return Err(CrustyError::CrustyError(
+                "A scan needs at least one partition".to_string(),
+            ));
+        }
+        let hf = self.get_hf(container_id)?;
+        let pages = hf.num_pages() as usize;
+        let bound = |i: usize| (pages * i / n) as PageId;
+        Ok((0..n)
+            .map(|i| {
+                let end = if i + 1 == n { None } else { Some(bound(i + 1)) };
+                ScanPartition::new(
+                    tid,
+                    Arc::clone(&hf),
+                    self.buffer_pool.clone(),
+                    self.read_ahead(),
+                    bound(i),
+                    end,
+                )
+            })
+            .collect())
+    }
+
+    /// Read ahead for a new scan, None without a buffer pool or with read ahead off.
+    fn read_ahead(&self) -> Option<ReadAhead> {
+        let bp = self.buffer_pool.as_ref()?;
+        let window = self.get_prefetch_window();
+        if window == 0 {
+            return None;
+        }
+        let prefetcher = self
+            .prefetcher
+            .get_or_init(|| Prefetcher::start(Arc::clone(bp)));
+        Some(ReadAhead::new(Arc::clone(prefetcher), window))
+    }
+
+    /// For testing
+    pub fn get_page_debug(&self, container_id: ContainerId, page_id: PageId) -> String {
+        match self.get_page(
+            container_id,
+            page_id,
+            TransactionId::new(),
+            Permissions::ReadOnly,
+            false,
+        ) {
+            Some(p) => {
+                format!("{:?}", p)
+            }
+            None => String::new(),
         }
     }
+}
+
+/// Implementation of storage trait
+impl StorageTrait for StorageManager {
+    type ValIterator = HeapFileIterator;
+
+    /// Create a new storage manager that will use storage_dir as the location to persist data
+    /// (if the storage manager persists records on disk)
+    /// For startup/shutdown: check the storage_dir for data persisted in shutdown() that you can
+    /// use to populate this instance of the SM. Otherwise create a new one.
+    /// The containers come from the catalog when there is one, since it is saved on every
+    /// container change and so is current even if the last run did not shut down.
+    /// The buffer pool size and policy, read ahead and other settings saved by the last shutdown
+    /// are restored. Temp containers a crash left behind are deleted.
+    fn new(storage_dir: &Path) -> Self {
+        StorageManager::open(storage_dir, HashMap::new(), false)
+    }
 
     /// Create a new storage manager for testing. There is no startup/shutdown logic here: it
     /// should simply create a fresh SM and set is_temp to true
     fn new_test_sm() -> Self {
         let storage_dir = gen_random_test_sm_dir();
         debug!("Making new temp storage_manager {:?}", storage_dir);
-        panic!("TODO milestone hs");
+        StorageManager::new_empty(storage_dir, true)
     }
 
     /// Insert some bytes into a container for a particular value (e.g. record).
@@ -141,16 +1039,15 @@ impl StorageTrait for StorageManager {
     /// Returns the value id associated with the stored value.
     /// Function will need to find the first page that can hold the value.
     /// A new page may need to be created if no space on existing pages can be found.
+    /// Panics where try_insert_value returns an error.
     fn insert_value(
         &self,
         container_id: ContainerId,
         value: Vec<u8>,
         tid: TransactionId,
     ) -> ValueId {
-        if value.len() > PAGE_SIZE {
-            panic!("Cannot handle inserting a value larger than the page size");
-        }
-        panic!("TODO milestone hs");
+        self.try_insert_value(container_id, value, tid)
+            .unwrap_or_else(|e| panic!("Cannot insert value: {}", e))
     }
 
     /// Insert some bytes into a container for vector of values (e.g. record).
@@ -171,19 +1068,50 @@ impl StorageTrait for StorageManager {
 
     /// Delete the data for a value. If the valueID is not found it returns Ok() still.
     fn delete_value(&self, id: ValueId, tid: TransactionId) -> Result<(), CrustyError> {
-        panic!("TODO milestone hs");
+        let _timer = self.metrics.time(StorageOp::Delete);
+        let _span = tracing::trace_span!(
+            "delete_value",
+            tid = tid.id(),
+            container_id = id.container_id,
+            page_id = id.page_id,
+            slot_id = id.slot_id
+        )
+        .entered();
+        let hf = self.get_hf(id.container_id)?;
+        let _latch = hf.write_latch.lock().unwrap();
+        self.delete_forwarded(&hf, id, tid)
     }
 
     /// Updates a value. Returns valueID on update (which may have changed). Error on failure
     /// Any process that needs to determine if a value changed will need to compare the return valueId against
     /// the sent value.
+    /// A moved value leaves no forwarding marker; see update_value_with for one that does.
     fn update_value(
         &self,
         value: Vec<u8>,
         id: ValueId,
-        _tid: TransactionId,
+        tid: TransactionId,
     ) -> Result<ValueId, CrustyError> {
-        panic!("TODO milestone hs");
+        self.update_value_with(value, id, tid, false)
+    }
+
+    /// Commit tid, returning once the log holds its changes durably. The changes of a
+    /// transaction that has not committed are rolled back by recovery after a crash.
+    /// A transaction that changed nothing since it last committed logs nothing.
+    fn commit_transaction(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        if let Some(wal) = &self.wal {
+            if wal.last_lsn(tid).is_some() {
+                wal.commit(tid)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Roll back the changes of tid since it last committed, newest first, and log that it
+    /// aborted.
+    fn abort_transaction(&self, tid: TransactionId) -> Result<(), CrustyError> {
+        self.roll_back(tid)?;
+        Ok(())
     }
 
     /// Create a new container (i.e., a HeapFile) to be stored.
@@ -199,11 +1127,18 @@ impl StorageTrait for StorageManager {
     fn create_container(
         &self,
         container_id: ContainerId,
-        _name: Option<String>,
-        _container_type: common::ids::StateType,
-        _dependencies: Option<Vec<ContainerId>>,
+        name: Option<String>,
+        container_type: common::ids::StateType,
+        dependencies: Option<Vec<ContainerId>>,
     ) -> Result<(), CrustyError> {
-        panic!("TODO milestone hs");
+        let options = ContainerOptions {
+            name,
+            state_type: container_type,
+            dependencies,
+            layout: self.get_container_layout(container_id),
+            ..Default::default()
+        };
+        self.create_container_with(container_id, options)
     }
 
     /// A wrapper function to call create container
@@ -214,17 +1149,51 @@ impl StorageTrait for StorageManager {
     /// Remove the container and all stored values in the container.
     /// If the container is persisted, remove the underlying files
     fn remove_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
-        panic!("TODO milestone hs");
+        // A container created again with this id starts empty when the log is replayed
+        let hf = self.get_hf(container_id).ok();
+        let _latch = hf.as_ref().map(|hf| hf.write_latch.lock().unwrap());
+        if let Some(hf) = &hf {
+            self.log_truncate(hf, 0)?;
+        }
+        if let Some(bp) = &self.buffer_pool {
+            bp.discard_container(container_id);
+        }
+        self.cid_heapfile_map.write().unwrap().remove(&container_id);
+        self.cid_layout_map.write().unwrap().remove(&container_id);
+        self.cid_meta_map.write().unwrap().remove(&container_id);
+        self.temp_containers.write().unwrap().remove(&container_id);
+        let path = match self.cid_path_map.write().unwrap().remove(&container_id) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        // Forget the container before its files go so a crash leaves no entry without a file
+        self.save_catalog()?;
+        if fsm_path(&path).exists() {
+            fs::remove_file(fsm_path(&path))?;
+        }
+        if seal_path(&path).exists() {
+            fs::remove_file(seal_path(&path))?;
+        }
+        remove_extra_segments(&path)?;
+        if path.exists() {
+            fs::remove_file(path.as_ref())?;
+        }
+        Ok(())
     }
 
     /// Get an iterator that returns all valid records
+    /// Pages are read through the buffer pool in order; see HeapFileIterator for what a scan
+    /// sees of inserts made while it runs.
     fn get_iterator(
         &self,
         container_id: ContainerId,
         tid: TransactionId,
         _perm: Permissions,
     ) -> Self::ValIterator {
-        panic!("TODO milestone hs");
+        let hf = self
+            .get_hf(container_id)
+            .unwrap_or_else(|e| panic!("Cannot scan container: {}", e));
+        HeapFileIterator::new(tid, hf, self.buffer_pool.clone(), self.read_ahead())
     }
 
     fn get_iterator_from(
@@ -234,7 +1203,10 @@ impl StorageTrait for StorageManager {
         _perm: Permissions,
         start: ValueId,
     ) -> Self::ValIterator {
-        panic!("TODO milestone hs");
+        let hf = self
+            .get_hf(container_id)
+            .unwrap_or_else(|e| panic!("Cannot scan container: {}", e));
+        HeapFileIterator::new_from(tid, hf, self.buffer_pool.clone(), self.read_ahead(), start)
     }
 
     /// Get the data for a particular ValueId. Error if does not exists
@@ -244,7 +1216,18 @@ impl StorageTrait for StorageManager {
         tid: TransactionId,
         perm: Permissions,
     ) -> Result<Vec<u8>, CrustyError> {
-        panic!("TODO milestone hs");
+        let _timer = self.metrics.time(StorageOp::Get);
+        let _span = tracing::trace_span!(
+            "get_value",
+            tid = tid.id(),
+            container_id = id.container_id,
+            page_id = id.page_id,
+            slot_id = id.slot_id
+        )
+        .entered();
+        let hf = self.get_hf(id.container_id)?;
+        self.read_forwarded(&hf, id, tid, perm)?
+            .ok_or_else(|| CrustyError::CrustyError(format!("No value stored at {:?}", id)))
     }
 
     fn get_storage_path(&self) -> &Path {
@@ -257,14 +1240,30 @@ impl StorageTrait for StorageManager {
     ///
     /// Clear any data structures in the SM you add
     fn reset(&self) -> Result<(), CrustyError> {
+        if let Some(bp) = &self.buffer_pool {
+            bp.discard_all();
+        }
         fs::remove_dir_all(self.storage_dir.clone())?;
         fs::create_dir_all(self.storage_dir.clone()).unwrap();
-        panic!("TODO milestone hs");
+        if let Some(wal) = &self.wal {
+            wal.reset()?;
+        }
+        self.cid_heapfile_map.write().unwrap().clear();
+        self.cid_path_map.write().unwrap().clear();
+        self.cid_layout_map.write().unwrap().clear();
+        self.cid_meta_map.write().unwrap().clear();
+        Ok(())
     }
 
     /// If there is a buffer pool or cache it should be cleared/reset.
     /// Otherwise do nothing.
-    fn clear_cache(&self) {}
+    fn clear_cache(&self) {
+        if let Some(bp) = &self.buffer_pool {
+            if let Err(e) = bp.clear() {
+                error!("Error writing back the buffer pool: {}", e);
+            }
+        }
+    }
 
     /// Shutdown the storage manager. Should be safe to call multiple times. You can assume this
     /// function will never be called on a temp SM.
@@ -273,8 +1272,38 @@ impl StorageTrait for StorageManager {
     /// HINT: Heapfile won't be serializable/deserializable. You'll want to serialize information
     /// that can be used to create a HeapFile object pointing to the same data. You don't need to
     /// worry about recreating read_count or write_count.
+    /// Pages and free space maps go to disk through a last checkpoint, then the catalog and
+    /// the settings are saved and the directory is marked shut down cleanly, so the next open
+    /// skips recovery and trusts the saved free space maps. Nothing is marked if a step fails.
     fn shutdown(&self) {
         debug!("serializing storage manager");
+        fs::create_dir_all(&self.storage_dir).expect("error creating storage directory");
+        self.stop_checkpointer();
+        self.stop_flusher();
+        if let Err(e) = self.remove_temp_containers() {
+            error!("Error removing the temp containers: {}", e);
+            return;
+        }
+        // A last checkpoint puts every page and free space map on disk and empties the log
+        let flushed = match &self.wal {
+            Some(_) => self.checkpoint().map(|_| ()),
+            None => self.flush_all(),
+        };
+        if let Err(e) = flushed {
+            error!("Error writing back the buffer pool: {}", e);
+            return;
+        }
+        // After the checkpoint, so the standby gets every record up to it
+        self.stop_log_shipping();
+        if let Err(e) = self.save_catalog() {
+            error!("Error saving the container catalog: {}", e);
+            return;
+        }
+        // Only a hint for the next open, so a list that cannot be saved is left out
+        if let Err(e) = self.save_hot_pages() {
+            warn!("Error saving the pages in the buffer pool: {}", e);
+        }
+        *self.pool_settings.write().unwrap() = self.buffer_pool.as_ref().map(|bp| bp.settings());
         let mut filename = self.storage_dir.clone();
         filename.push(PERSIST_CONFIG_FILENAME);
         serde_json::to_writer(
@@ -282,6 +1311,7 @@ impl StorageTrait for StorageManager {
             &self,
         )
         .expect("error serializing storage manager");
+        mark_clean_shutdown(&self.storage_dir).expect("error marking a clean shutdown");
     }
 }
 
@@ -289,6 +1319,9 @@ impl StorageTrait for StorageManager {
 impl Drop for StorageManager {
     // if temp SM this clears the storage path entirely when it leaves scope; used for testing
     fn drop(&mut self) {
+        self.stop_checkpointer();
+        self.stop_log_shipping();
+        self.stop_flusher();
         if self.is_temp {
             debug!("Removing storage path on drop {:?}", self.storage_dir);
             let remove_all = fs::remove_dir_all(self.storage_dir.clone());
@@ -304,9 +1337,12 @@ impl Drop for StorageManager {
 #[allow(unused_must_use)]
 mod test {
     use super::*;
+    use crate::buffer_pool::ReplacementPolicyKind;
     use crate::storage_manager::StorageManager;
     use common::storage_trait::StorageTrait;
     use common::testutil::*;
+    use std::thread;
+    use temp_testdir::TempDir;
 
     #[test]
     fn hs_sm_a_insert() {
@@ -396,6 +1432,571 @@ mod test {
         }
     }
 
+    #[test]
+    fn hs_sm_b_iter_concurrent_insert() {
+        init();
+        let sm = Arc::new(StorageManager::new_test_sm());
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+        let before = sm.insert_values(cid, get_random_vec_of_byte_vec(300, 40, 400), tid);
+
+        let writer = Arc::clone(&sm);
+        let handle = thread::spawn(move || {
+            writer.insert_values(cid, get_random_vec_of_byte_vec(2000, 40, 400), tid)
+        });
+        let scanned: Vec<(Vec<u8>, ValueId)> =
+            sm.get_iterator(cid, tid, Permissions::ReadOnly).collect();
+        let during = handle.join().unwrap();
+
+        //ids only increase, so nothing is returned twice
+        let key = |id: &ValueId| (id.page_id, id.slot_id);
+        assert!(scanned.windows(2).all(|w| key(&w[0].1) < key(&w[1].1)));
+        let seen: BTreeSet<_> = scanned.iter().map(|(_, id)| key(id)).collect();
+        assert!(before.iter().all(|id| seen.contains(&key(id))));
+        assert!(scanned.len() <= before.len() + during.len());
+        for (val, id) in &scanned {
+            assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+        let count = sm.get_iterator(cid, tid, Permissions::ReadOnly).count();
+        assert_eq!(before.len() + during.len(), count);
+    }
+
+    #[test]
+    fn hs_sm_b_scan_partitions() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+        sm.insert_values(cid, get_random_vec_of_byte_vec(1000, 40, 400), tid);
+        let pages = sm.get_hf(cid).unwrap().num_pages();
+
+        let partitions = sm.scan_partitions(cid, 4, tid).unwrap();
+        assert_eq!(4, partitions.len());
+        assert_eq!(0, partitions[0].first_page());
+        for pair in partitions.windows(2) {
+            assert_eq!(pair[0].end_page(), Some(pair[1].first_page()));
+        }
+        assert_eq!(None, partitions[3].end_page());
+        let handles: Vec<_> = partitions
+            .into_iter()
+            .map(|p| thread::spawn(move || p.into_iter().collect::<Vec<_>>()))
+            .collect();
+        let scanned: Vec<(Vec<u8>, ValueId)> = handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect();
+        let all: Vec<(Vec<u8>, ValueId)> =
+            sm.get_iterator(cid, tid, Permissions::ReadOnly).collect();
+        assert_eq!(all, scanned);
+
+        //more partitions than pages leaves some empty
+        let partitions = sm.scan_partitions(cid, pages as usize + 3, tid).unwrap();
+        assert!(partitions
+            .iter()
+            .any(|p| p.end_page() == Some(p.first_page())));
+        let scanned: usize = partitions.into_iter().map(|p| p.into_iter().count()).sum();
+        assert_eq!(all.len(), scanned);
+
+        //the last partition also scans pages appended after the split
+        let last = sm.scan_partitions(cid, 2, tid).unwrap().pop().unwrap();
+        let first = Some(last.first_page());
+        let before = all.iter().filter(|(_, id)| id.page_id >= first).count();
+        sm.insert_values(cid, vec![vec![1; 3000]; 4], tid);
+        assert!(sm.get_hf(cid).unwrap().num_pages() > pages);
+        assert_eq!(before + 4, last.into_iter().count());
+        assert!(sm.scan_partitions(cid, 0, tid).is_err());
+        assert!(sm.scan_partitions(7, 2, tid).is_err());
+    }
+
+    #[test]
+    fn hs_sm_c_update_delete() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+
+        let ids = sm.insert_values(cid, get_random_vec_of_byte_vec(10, 300, 300), tid);
+        assert_eq!(1, sm.get_num_pages(cid));
+
+        // Fits in place so the id is kept
+        let small = get_random_byte_vec(100);
+        assert_eq!(ids[0], sm.update_value(small.clone(), ids[0], tid).unwrap());
+        assert_eq!(
+            small,
+            sm.get_value(ids[0], tid, Permissions::ReadOnly).unwrap()
+        );
+
+        // Too large for the full first page so it moves to a new one
+        let large = get_random_byte_vec(2000);
+        let moved = sm.update_value(large.clone(), ids[1], tid).unwrap();
+        assert_eq!(Some(1), moved.page_id);
+        assert_eq!(
+            large,
+            sm.get_value(moved, tid, Permissions::ReadOnly).unwrap()
+        );
+        assert!(sm.get_value(ids[1], tid, Permissions::ReadOnly).is_err());
+
+        sm.delete_value(ids[2], tid).unwrap();
+        sm.delete_value(ids[2], tid).unwrap();
+        assert!(sm.get_value(ids[2], tid, Permissions::ReadOnly).is_err());
+        assert!(sm.update_value(small, ids[2], tid).is_err());
+        assert_eq!(9, sm.get_iterator(cid, tid, Permissions::ReadOnly).count());
+
+        let from: Vec<ValueId> = sm
+            .get_iterator_from(cid, tid, Permissions::ReadOnly, ids[5])
+            .map(|(_, id)| id)
+            .collect();
+        assert_eq!(ids[5..].to_vec(), from[..5]);
+        assert_eq!(moved, from[5]);
+
+        sm.remove_container(cid).unwrap();
+        assert_eq!(0, sm.get_num_pages(cid));
+        assert!(sm.delete_value(ids[0], tid).is_err());
+        assert!(sm.get_value(ids[0], tid, Permissions::ReadOnly).is_err());
+        sm.remove_container(cid).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_c_read_only_page_not_stored() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let cid = 1;
+        sm.create_table(cid).unwrap();
+        let tid = TransactionId::new();
+        let id = sm.insert_value(cid, get_random_byte_vec(100), tid);
+        let hf = sm.get_hf(cid).unwrap();
+
+        // A page fetched to be read is refused when the change made to it is stored
+        let mut page = sm.fetch_page(&hf, 0, tid, Permissions::ReadOnly).unwrap();
+        page.delete_value(id.slot_id.unwrap()).unwrap();
+        assert!(matches!(
+            sm.store_page(&hf, &mut page, tid),
+            Err(CrustyError::InvalidMutationError(_))
+        ));
+        assert!(sm.get_value(id, tid, Permissions::ReadOnly).is_ok());
+
+        // The same change to the page fetched to be written is stored
+        let mut page = sm.fetch_page(&hf, 0, tid, Permissions::ReadWrite).unwrap();
+        page.delete_value(id.slot_id.unwrap()).unwrap();
+        sm.store_page(&hf, &mut page, tid).unwrap();
+        assert!(sm.get_value(id, tid, Permissions::ReadOnly).is_err());
+    }
+
+    #[test]
+    fn hs_sm_d_shutdown_reload() {
+        init();
+        let dir = gen_random_test_sm_dir();
+        let tid = TransactionId::new();
+        let vals = get_random_vec_of_byte_vec(50, 40, 400);
+        let ids = {
+            let sm = StorageManager::new(&dir);
+            sm.create_table(3).unwrap();
+            let ids = sm.insert_values(3, vals.clone(), tid);
+            sm.shutdown();
+            ids
+        };
+        let sm = StorageManager::new(&dir);
+        for (id, val) in ids.iter().zip(&vals) {
+            assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+        assert_eq!(50, sm.get_iterator(3, tid, Permissions::ReadOnly).count());
+        sm.reset().unwrap();
+        assert_eq!(0, sm.get_num_pages(3));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_d_restart_restores_state() {
+        init();
+        //removed when the test ends, whether it passes or not
+        let dir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        {
+            let sm = StorageManager::new(&dir);
+            sm.create_table(1).unwrap();
+            sm.create_table(2).unwrap();
+            sm.set_buffer_pool_size(BufferPoolSize::Pages(32)).unwrap();
+            sm.set_replacement_policy(ReplacementPolicyKind::Clock.build());
+            sm.set_prefetch_window(3);
+            sm.set_max_record_size(Some(300));
+            sm.shutdown();
+        }
+        let mut stored: Vec<(ValueId, Vec<u8>)> = Vec::new();
+        for round in 0..4 {
+            let sm = StorageManager::new(&dir);
+            assert_eq!(32, sm.buffer_pool_stats().unwrap().capacity);
+            assert_eq!(Some("clock"), sm.get_replacement_policy());
+            assert_eq!(3, sm.get_prefetch_window());
+            assert_eq!(Some(300), sm.get_max_record_size());
+            for (id, val) in &stored {
+                assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+            }
+            let count = |cid| sm.get_iterator(cid, tid, Permissions::ReadOnly).count();
+            assert_eq!(stored.len(), count(1) + count(2));
+
+            // The free space maps saved by the last shutdown match the pages
+            for cid in [1, 2] {
+                let fsm_file = dir.join(format!("{}.fsm", cid));
+                let saved = fs::read(&fsm_file).unwrap();
+                let hf = sm.get_hf(cid).unwrap();
+                hf.refresh_fsm().unwrap();
+                hf.persist_fsm().unwrap();
+                assert_eq!(saved, fs::read(&fsm_file).unwrap());
+            }
+
+            if let Some((id, _)) = stored.pop() {
+                sm.delete_value(id, tid).unwrap();
+            }
+            let mut vals = get_random_vec_of_byte_vec(40, 50, 250);
+            // Over the max record size, so it goes to overflow pages
+            vals.push(get_random_byte_vec(400));
+            let ids = sm.insert_values(1 + round % 2, vals.clone(), tid);
+            stored.extend(ids.into_iter().zip(vals));
+            sm.commit_transaction(tid).unwrap();
+            sm.shutdown();
+            // The last checkpoint left nothing to recover
+            assert_eq!(1, sm.wal.as_ref().unwrap().entries().unwrap().len());
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn hs_sm_d_mmap_reload() {
+        init();
+        let dir = gen_random_test_sm_dir();
+        let tid = TransactionId::new();
+        let vals = get_random_vec_of_byte_vec(500, 40, 400);
+        let ids = {
+            let sm = StorageManager::new(&dir);
+            sm.set_file_io(HeapFileIo::Mmap);
+            sm.create_table(3).unwrap();
+            let ids = sm.insert_values(3, vals.clone(), tid);
+            sm.flush_all().unwrap();
+            sm.shutdown();
+            ids
+        };
+        let sm = StorageManager::new(&dir);
+        assert_eq!(HeapFileIo::Mmap, sm.get_file_io());
+        for (id, val) in ids.iter().zip(&vals) {
+            assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+        drop(sm);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_d_container_lifecycle() {
+        init();
+        let dir = gen_random_test_sm_dir();
+        let tid = TransactionId::new();
+        {
+            let sm = StorageManager::new(&dir);
+            let named = |name: &str| ContainerOptions {
+                name: Some(name.to_string()),
+                ..ContainerOptions::default()
+            };
+            sm.create_container_with(1, named("users")).unwrap();
+            sm.create_container_with(2, named("orders")).unwrap();
+            assert!(sm.create_container_with(3, named("users")).is_err());
+            sm.create_table(4).unwrap();
+            sm.insert_values(1, get_random_vec_of_byte_vec(300, 40, 400), tid);
+            sm.insert_values(2, get_random_vec_of_byte_vec(300, 40, 400), tid);
+
+            sm.truncate_container(2).unwrap();
+            assert_eq!(0, sm.get_iterator(2, tid, Permissions::ReadOnly).count());
+            let id = sm.insert_value(2, vec![7; 50], tid);
+            let value = sm.get_value(id, tid, Permissions::ReadOnly).unwrap();
+            assert_eq!(vec![7; 50], value);
+
+            sm.remove_container(4).unwrap();
+            assert!(!dir.join("4.hf").exists());
+            assert!(sm.truncate_container(4).is_err());
+            sm.shutdown();
+        }
+        let sm = StorageManager::new(&dir);
+        let containers = sm.list_containers();
+        let ids: Vec<ContainerId> = containers.iter().map(|c| c.id).collect();
+        assert_eq!(vec![1, 2], ids);
+        assert_eq!(Some("orders"), containers[1].name.as_deref());
+        assert!(containers[0].pages > 1);
+        assert_eq!(1, containers[1].pages);
+        assert_eq!(Some(2), sm.find_container("orders"));
+        assert_eq!(300, sm.get_iterator(1, tid, Permissions::ReadOnly).count());
+        assert_eq!(1, sm.get_iterator(2, tid, Permissions::ReadOnly).count());
+        drop(sm);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_d_catalog_recovery() {
+        init();
+        let dir = gen_random_test_sm_dir();
+        let tid = TransactionId::new();
+        let vals = get_random_vec_of_byte_vec(100, 40, 400);
+        let ids = {
+            let sm = StorageManager::new(&dir);
+            sm.create_table(1).unwrap();
+            sm.shutdown();
+            //changes after the last shutdown are only in the catalog
+            let options = ContainerOptions {
+                name: Some("fixed".to_string()),
+                layout: PageLayout::FixedRecord { record_width: 16 },
+                ..ContainerOptions::default()
+            };
+            sm.create_container_with(2, options).unwrap();
+            sm.create_table(3).unwrap();
+            let ids = sm.insert_values(3, vals.clone(), tid);
+            sm.remove_container(1).unwrap();
+            sm.flush_all().unwrap();
+            ids
+        };
+        let sm = StorageManager::new(&dir);
+        let ids_listed: Vec<ContainerId> = sm.list_containers().iter().map(|c| c.id).collect();
+        assert_eq!(vec![2, 3], ids_listed);
+        assert_eq!(Some(2), sm.find_container("fixed"));
+        assert_eq!(
+            PageLayout::FixedRecord { record_width: 16 },
+            sm.get_container_layout(2)
+        );
+        for (id, val) in ids.iter().zip(&vals) {
+            assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+        drop(sm);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hs_sm_d_clear_container() {
+        init();
+        let dir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        {
+            let sm = StorageManager::new(&dir);
+            sm.create_table(1).unwrap();
+            sm.create_table(2).unwrap();
+            let ids = sm.insert_values(1, get_random_vec_of_byte_vec(500, 40, 400), tid);
+            sm.insert_values(2, get_random_vec_of_byte_vec(100, 40, 400), tid);
+            let file_len = || fs::metadata(dir.join("1.hf")).unwrap().len();
+            let mut open = sm.get_iterator(1, tid, Permissions::ReadOnly);
+            assert!(open.next().is_some());
+
+            //dirty cached pages are dropped rather than written back over the empty file
+            sm.clear_container(1).unwrap();
+            assert_eq!(0, sm.get_hf(1).unwrap().num_pages());
+            assert_eq!(PAGE_SIZE as u64, file_len());
+            assert!(open.all(|(_, id)| id.page_id == Some(0)));
+            assert!(sm.get_value(ids[0], tid, Permissions::ReadOnly).is_err());
+            assert_eq!(0, sm.get_iterator(1, tid, Permissions::ReadOnly).count());
+            assert_eq!(100, sm.get_iterator(2, tid, Permissions::ReadOnly).count());
+            sm.flush_all().unwrap();
+            assert_eq!(PAGE_SIZE as u64, file_len());
+
+            let id = sm.insert_value(1, vec![3; 60], tid);
+            assert_eq!(Some(0), id.page_id);
+            assert!(sm.clear_container(3).is_err());
+            sm.shutdown();
+        }
+        let sm = StorageManager::new(&dir);
+        let values: Vec<Vec<u8>> = sm
+            .get_iterator(1, tid, Permissions::ReadOnly)
+            .map(|(val, _)| val)
+            .collect();
+        assert_eq!(vec![vec![3; 60]], values);
+        assert_eq!(100, sm.get_iterator(2, tid, Permissions::ReadOnly).count());
+    }
+
+    #[test]
+    fn hs_sm_g_bulk_insert() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        sm.create_table(1).unwrap();
+        let before = sm.insert_values(1, get_random_vec_of_byte_vec(20, 40, 400), tid);
+        let loaded_from = sm.get_hf(1).unwrap().num_pages();
+
+        let vals = get_random_vec_of_byte_vec(2000, 40, 400);
+        let ids = sm.bulk_insert(1, vals.clone(), tid).unwrap();
+        assert_eq!(vals.len(), ids.len());
+        assert_eq!(Some(loaded_from), ids[0].page_id);
+        assert!(ids.iter().all(|id| id.page_id >= Some(loaded_from)));
+        for (id, val) in ids.iter().zip(&vals) {
+            assert_eq!(*val, sm.get_value(*id, tid, Permissions::ReadOnly).unwrap());
+        }
+        let count = sm.get_iterator(1, tid, Permissions::ReadOnly).count();
+        assert_eq!(before.len() + vals.len(), count);
+
+        //pages are only appended for values that were loaded
+        let pages = sm.get_hf(1).unwrap().num_pages();
+        assert!(sm.bulk_insert(1, Vec::new(), tid).unwrap().is_empty());
+        assert!(sm.bulk_insert(1, vec![vec![0; PAGE_SIZE]], tid).is_err());
+        assert_eq!(pages, sm.get_hf(1).unwrap().num_pages());
+    }
+
+    #[test]
+    fn hs_sm_e_value_ids() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        sm.create_table(4).unwrap();
+        let ids = sm.insert_values(4, get_random_vec_of_byte_vec(100, 40, 400), tid);
+
+        // Ids handed out by a scan are packed, unpacked and used to fetch and delete
+        let scanned: Vec<(Vec<u8>, u64)> = sm
+            .get_iterator(4, tid, Permissions::ReadOnly)
+            .map(|(val, id)| (val, id.to_u64()))
+            .collect();
+        let mut unpacked: Vec<ValueId> = scanned.iter().map(|(_, p)| ValueId::from(*p)).collect();
+        unpacked.sort_by_key(|id| id.to_u64());
+        let mut inserted = ids.clone();
+        inserted.sort_by_key(|id| id.to_u64());
+        assert_eq!(inserted, unpacked);
+        for (val, p) in scanned.iter().step_by(2) {
+            let id = ValueId::from_u64(*p);
+            assert_eq!(*val, sm.get_value(id, tid, Permissions::ReadOnly).unwrap());
+            sm.delete_value(id, tid).unwrap();
+        }
+        assert_eq!(50, sm.get_iterator(4, tid, Permissions::ReadOnly).count());
+    }
+
+    #[test]
+    fn hs_sm_f_free_space_map() {
+        init();
+        let dir = gen_random_test_sm_dir();
+        let tid = TransactionId::new();
+        let sm = StorageManager::new(&dir);
+        sm.create_table(5).unwrap();
+        let ids = sm.insert_values(5, get_random_vec_of_byte_vec(30, 400, 400), tid);
+        let pages = sm.get_num_pages(5);
+        assert!(pages > 2);
+
+        // Room freed on the first page is found again instead of growing the file
+        sm.delete_value(ids[0], tid).unwrap();
+        sm.delete_value(ids[1], tid).unwrap();
+        let id = sm.insert_value(5, get_random_byte_vec(700), tid);
+        assert_eq!(Some(0), id.page_id);
+        assert_eq!(pages, sm.get_num_pages(5));
+
+        // The map saved on shutdown is reloaded, and rebuilt from the pages when it is missing
+        sm.shutdown();
+        let saved = fs::read(dir.join("5.fsm")).unwrap();
+        assert_eq!(pages as usize, saved.len());
+        drop(sm);
+        fs::remove_file(dir.join("5.fsm")).unwrap();
+        let sm = StorageManager::new(&dir);
+        sm.shutdown();
+        assert_eq!(saved, fs::read(dir.join("5.fsm")).unwrap());
+        let id = sm.insert_value(5, get_random_byte_vec(80), tid);
+        assert_eq!(Some(0), id.page_id);
+
+        sm.remove_container(5).unwrap();
+        assert!(!dir.join("5.fsm").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    type Recorded = Vec<(String, HashMap<String, String>)>;
+
+    //names of the spans and events made while it is the default, with their fields
+    #[derive(Clone, Default)]
+    struct Collector(Arc<Mutex<Recorded>>);
+
+    struct Fields<'a>(&'a mut HashMap<String, String>);
+
+    impl tracing::field::Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl Collector {
+        fn named(&self, name: &str) -> Vec<HashMap<String, String>> {
+            let all = self.0.lock().unwrap();
+            all.iter()
+                .filter(|(n, _)| n == name)
+                .map(|(_, f)| f.clone())
+                .collect()
+        }
+    }
+
+    impl tracing::Subscriber for Collector {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut fields = HashMap::new();
+            span.record(&mut Fields(&mut fields));
+            let mut all = self.0.lock().unwrap();
+            all.push((span.metadata().name().to_string(), fields));
+            tracing::span::Id::from_u64(all.len() as u64)
+        }
+
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            let mut all = self.0.lock().unwrap();
+            values.record(&mut Fields(&mut all[span.into_u64() as usize - 1].1));
+        }
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        //events are named by their message
+        fn event(&self, event: &tracing::Event<'_>) {
+            let mut fields = HashMap::new();
+            event.record(&mut Fields(&mut fields));
+            let name = fields.remove("message").unwrap_or_default();
+            self.0.lock().unwrap().push((name, fields));
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[test]
+    fn hs_sm_h_tracing_spans() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        sm.create_table(1).unwrap();
+        let tid = TransactionId::new();
+        let collector = Collector::default();
+        tracing::subscriber::with_default(collector.clone(), || {
+            let ids: Vec<ValueId> = (0..20)
+                .map(|_| sm.insert_value(1, get_random_byte_vec(100), tid))
+                .collect();
+            sm.delete_value(ids[3], tid).unwrap();
+            sm.vacuum_container(1).unwrap();
+            sm.flush_all().unwrap();
+            sm.clear_cache();
+            sm.get_value(ids[4], tid, Permissions::ReadOnly).unwrap();
+        });
+
+        //the span of an insert gets the id it was stored at
+        let inserts = collector.named("insert_value");
+        assert_eq!(20, inserts.len());
+        assert_eq!("1", inserts[0]["container_id"]);
+        assert_eq!("0", inserts[0]["page_id"]);
+        assert_eq!("0", inserts[0]["slot_id"]);
+        assert_eq!("100", inserts[0]["len"]);
+        assert_eq!("3", collector.named("delete_value")[0]["slot_id"]);
+        assert!(!collector.named("store page").is_empty());
+        assert!(!collector.named("compacted page").is_empty());
+
+        let flushes = collector.named("flush");
+        assert!(flushes
+            .iter()
+            .any(|f| f.get("written").is_some_and(|w| w != "0")));
+        let written = collector.named("wrote back dirty pages");
+        assert_eq!("1", written[0]["container_id"]);
+
+        let reads = collector.named("read page");
+        assert_eq!(1, reads.len());
+        assert_eq!("1", reads[0]["container_id"]);
+        assert_eq!("0", reads[0]["page_id"]);
+        assert_eq!("1", collector.named("get_value")[0]["container_id"]);
+    }
+
     #[test]
     #[ignore]
     fn hs_sm_b_iter_large() {
@@ -415,5 +2016,3 @@ mod test {
         assert_eq!(1000, count);
     }
 }
-
-
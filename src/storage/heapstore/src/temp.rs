@@ -0,0 +1,164 @@
+use crate::heapfile::HeapFile;
+use crate::storage_manager::StorageManager;
+use common::prelude::*;
+use common::storage_trait::StorageTrait;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Directory in the storage directory holding the heap files of temp containers.
+pub(crate) const TEMP_DIR_NAME: &str = "temp";
+
+/// Delete the temp containers a run of the storage manager in dir left behind, which only
+/// happens if it crashed.
+pub(crate) fn clear_temp_dir(dir: &Path) -> Result<(), CrustyError> {
+    let temp_dir = dir.join(TEMP_DIR_NAME);
+    if temp_dir.exists() {
+        debug!("Removing leftover temp containers in {:?}", temp_dir);
+        fs::remove_dir_all(temp_dir)?;
+    }
+    Ok(())
+}
+
+/// Scratch container for a query operator that spills, such as a sort or a hash join.
+/// It is not in the catalog, its changes are not logged and it is removed with its files when
+/// dropped or when the storage manager shuts down, whichever comes first.
+pub struct TempContainer<'a> {
+    sm: &'a StorageManager,
+    container_id: ContainerId,
+}
+
+impl TempContainer<'_> {
+    /// Id to read and write the container's values with.
+    pub fn id(&self) -> ContainerId {
+        self.container_id
+    }
+}
+
+impl Drop for TempContainer<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.sm.remove_container(self.container_id) {
+            error!("Error removing temp container {}: {}", self.container_id, e);
+        }
+    }
+}
+
+impl StorageManager {
+    /// Create an empty temp container under the storage directory's temp directory. Temp
+    /// containers take the highest free ids, counting down from ContainerId::MAX, so they stay
+    /// clear of the ids callers pick. Errors if every id is taken.
+    pub fn create_temp_container(&self) -> Result<TempContainer<'_>, CrustyError> {
+        let temp_dir = self.storage_dir.join(TEMP_DIR_NAME);
+        fs::create_dir_all(&temp_dir)?;
+        let mut heapfiles = self.cid_heapfile_map.write().unwrap();
+        let container_id = (0..=ContainerId::MAX)
+            .rev()
+            .find(|id| !heapfiles.contains_key(id))
+            .ok_or_else(|| CrustyError::CrustyError("No container id is free".to_string()))?;
+        let path = temp_dir.join(format!("{}.hf", container_id));
+        let hf = HeapFile::new_with_io(path.clone(), container_id, self.get_file_io())?;
+        hf.set_metrics(Arc::clone(&self.metrics));
+        heapfiles.insert(container_id, Arc::new(hf));
+        self.temp_containers.write().unwrap().insert(container_id);
+        self.cid_path_map
+            .write()
+            .unwrap()
+            .insert(container_id, Arc::new(path));
+        Ok(TempContainer {
+            sm: self,
+            container_id,
+        })
+    }
+
+    /// Whether container_id is a temp container.
+    pub(crate) fn is_temp_container(&self, container_id: ContainerId) -> bool {
+        self.temp_containers.read().unwrap().contains(&container_id)
+    }
+
+    /// Remove every temp container still open, for a shutdown.
+    pub(crate) fn remove_temp_containers(&self) -> Result<(), CrustyError> {
+        let ids: Vec<ContainerId> = self
+            .temp_containers
+            .read()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect();
+        for container_id in ids {
+            self.remove_container(container_id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::ContainerCatalog;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_temp_container() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        let sm = StorageManager::new(&tdir);
+        sm.create_table(1).unwrap();
+        let path = {
+            let temp = sm.create_temp_container().unwrap();
+            let other = sm.create_temp_container().unwrap();
+            assert_eq!(ContainerId::MAX, temp.id());
+            assert_eq!(ContainerId::MAX - 1, other.id());
+            let mut vals = get_random_vec_of_byte_vec(100, 50, 200);
+            sm.insert_values(temp.id(), vals.clone(), tid);
+            let mut scanned: Vec<Vec<u8>> = sm
+                .get_iterator(temp.id(), tid, Permissions::ReadOnly)
+                .map(|(value, _)| value)
+                .collect();
+            vals.sort();
+            scanned.sort();
+            assert_eq!(vals, scanned);
+
+            //kept out of the catalog and the log
+            let catalog = ContainerCatalog::load(&tdir).unwrap().unwrap();
+            assert_eq!(1, catalog.containers.len());
+            let wal = sm.wal.as_ref().unwrap();
+            assert!(wal.entries().unwrap().is_empty());
+
+            let path = tdir.join(TEMP_DIR_NAME).join(format!("{}.hf", temp.id()));
+            assert!(path.exists());
+            path
+        };
+        //dropping the handles removed the containers and their files
+        assert!(!path.exists());
+        assert!(sm.get_hf(ContainerId::MAX).is_err());
+        assert_eq!(
+            vec![1],
+            sm.list_containers()
+                .iter()
+                .map(|c| c.id)
+                .collect::<Vec<_>>()
+        );
+
+        //a shutdown removes the temp containers still open
+        let temp = sm.create_temp_container().unwrap();
+        sm.insert_values(temp.id(), get_random_vec_of_byte_vec(10, 50, 200), tid);
+        sm.shutdown();
+        assert!(sm.get_hf(temp.id()).is_err());
+        drop(temp);
+        drop(sm);
+
+        //and so does opening the directory after a crash
+        let sm = StorageManager::new(&tdir);
+        let temp = sm.create_temp_container().unwrap();
+        sm.insert_values(temp.id(), get_random_vec_of_byte_vec(10, 50, 200), tid);
+        sm.flush_all().unwrap();
+        std::mem::forget(temp);
+        drop(sm);
+        assert!(tdir.join(TEMP_DIR_NAME).exists());
+        let sm = StorageManager::new(&tdir);
+        assert!(!tdir.join(TEMP_DIR_NAME).exists());
+        assert_eq!(1, sm.list_containers().len());
+    }
+}
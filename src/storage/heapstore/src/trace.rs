@@ -0,0 +1,535 @@
+use common::prelude::*;
+use common::storage_trait::StorageTrait;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+///trace file written next to the data by Traced::new
+pub const TRACE_FILENAME: &str = "storage.trace";
+
+///length and fnv-1a hash standing in for the value bytes in a trace
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueDigest {
+    pub len: u32,
+    pub hash: u64,
+}
+
+impl ValueDigest {
+    pub fn of(bytes: &[u8]) -> Self {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for b in bytes {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        ValueDigest {
+            len: bytes.len() as u32,
+            hash,
+        }
+    }
+
+    ///deterministic stand in value of the recorded length seeded by the hash
+    pub fn synthesize(&self) -> Vec<u8> {
+        let mut state = self.hash | 1;
+        (0..self.len)
+            .map(|_| {
+                //xorshift64
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+}
+
+///one storage manager call
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TraceOp {
+    CreateContainer {
+        container_id: ContainerId,
+        name: Option<String>,
+    },
+    RemoveContainer {
+        container_id: ContainerId,
+    },
+    Insert {
+        container_id: ContainerId,
+        value: ValueDigest,
+        ///id the traced storage manager returned so replays can map later calls
+        result: ValueId,
+    },
+    Delete {
+        id: ValueId,
+    },
+    Update {
+        id: ValueId,
+        value: ValueDigest,
+        result: Option<ValueId>,
+    },
+    Get {
+        id: ValueId,
+    },
+    Scan {
+        container_id: ContainerId,
+        start: Option<ValueId>,
+    },
+    Reset,
+    ClearCache,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    ///microseconds since the trace started
+    pub at_micros: u64,
+    ///transaction id of the call or 0 for calls without one
+    pub tid: u64,
+    pub op: TraceOp,
+}
+
+///appends cbor encoded TraceRecords to a writer
+pub struct TraceWriter {
+    out: Mutex<BufWriter<Box<dyn Write + Send>>>,
+    start: Instant,
+}
+
+impl TraceWriter {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        TraceWriter {
+            out: Mutex::new(BufWriter::new(out)),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn create(path: &Path) -> Result<Self, CrustyError> {
+        Ok(TraceWriter::new(Box::new(File::create(path)?)))
+    }
+
+    ///tracing never fails the traced call so write errors are logged and dropped
+    pub fn record(&self, tid: u64, op: TraceOp) {
+        let record = TraceRecord {
+            at_micros: self.start.elapsed().as_micros() as u64,
+            tid,
+            op,
+        };
+        let mut out = self.out.lock().unwrap();
+        if let Err(e) = serde_cbor::to_writer(&mut *out, &record) {
+            error!("dropping trace record: {}", e);
+        }
+    }
+
+    pub fn flush(&self) -> Result<(), CrustyError> {
+        self.out.lock().unwrap().flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for TraceWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("flushing trace: {:?}", e);
+        }
+    }
+}
+
+///every record of a trace
+pub fn read_trace(reader: impl Read) -> Result<Vec<TraceRecord>, CrustyError> {
+    serde_cbor::Deserializer::from_reader(BufReader::new(reader))
+        .into_iter::<TraceRecord>()
+        .collect::<Result<_, _>>()
+        .map_err(|e| CrustyError::SerializationError(e.to_string()))
+}
+
+///storage manager wrapper that records every call before returning its result
+pub struct Traced<S: StorageTrait> {
+    inner: S,
+    trace: TraceWriter,
+}
+
+impl<S: StorageTrait> Traced<S> {
+    pub fn new(inner: S, trace: TraceWriter) -> Self {
+        Traced { inner, trace }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn trace(&self) -> &TraceWriter {
+        &self.trace
+    }
+
+    fn trace_path(storage_dir: &Path) -> PathBuf {
+        storage_dir.join(TRACE_FILENAME)
+    }
+}
+
+impl<S: StorageTrait> StorageTrait for Traced<S> {
+    type ValIterator = S::ValIterator;
+
+    fn new(storage_dir: &Path) -> Self {
+        let inner = S::new(storage_dir);
+        std::fs::create_dir_all(storage_dir).expect("error creating storage dir for trace");
+        let trace =
+            TraceWriter::create(&Self::trace_path(storage_dir)).expect("error creating trace file");
+        Traced::new(inner, trace)
+    }
+
+    fn new_test_sm() -> Self {
+        let inner = S::new_test_sm();
+        std::fs::create_dir_all(inner.get_storage_path())
+            .expect("error creating storage dir for trace");
+        let trace = TraceWriter::create(&Self::trace_path(inner.get_storage_path()))
+            .expect("error creating trace file");
+        Traced::new(inner, trace)
+    }
+
+    fn insert_value(
+        &self,
+        container_id: ContainerId,
+        value: Vec<u8>,
+        tid: TransactionId,
+    ) -> ValueId {
+        let digest = ValueDigest::of(&value);
+        let result = self.inner.insert_value(container_id, value, tid);
+        self.trace.record(
+            tid.id(),
+            TraceOp::Insert {
+                container_id,
+                value: digest,
+                result,
+            },
+        );
+        result
+    }
+
+    fn insert_values(
+        &self,
+        container_id: ContainerId,
+        values: Vec<Vec<u8>>,
+        tid: TransactionId,
+    ) -> Vec<ValueId> {
+        let digests: Vec<ValueDigest> = values.iter().map(|v| ValueDigest::of(v)).collect();
+        let results = self.inner.insert_values(container_id, values, tid);
+        for (value, result) in digests.into_iter().zip(&results) {
+            self.trace.record(
+                tid.id(),
+                TraceOp::Insert {
+                    container_id,
+                    value,
+                    result: *result,
+                },
+            );
+        }
+        results
+    }
+
+    fn delete_value(&self, id: ValueId, tid: TransactionId) -> Result<(), CrustyError> {
+        let result = self.inner.delete_value(id, tid);
+        self.trace.record(tid.id(), TraceOp::Delete { id });
+        result
+    }
+
+    fn update_value(
+        &self,
+        value: Vec<u8>,
+        id: ValueId,
+        tid: TransactionId,
+    ) -> Result<ValueId, CrustyError> {
+        let digest = ValueDigest::of(&value);
+        let result = self.inner.update_value(value, id, tid);
+        self.trace.record(
+            tid.id(),
+            TraceOp::Update {
+                id,
+                value: digest,
+                result: result.as_ref().ok().copied(),
+            },
+        );
+        result
+    }
+
+    fn create_container(
+        &self,
+        container_id: ContainerId,
+        name: Option<String>,
+        container_type: StateType,
+        dependencies: Option<Vec<ContainerId>>,
+    ) -> Result<(), CrustyError> {
+        self.trace.record(
+            0,
+            TraceOp::CreateContainer {
+                container_id,
+                name: name.clone(),
+            },
+        );
+        self.inner
+            .create_container(container_id, name, container_type, dependencies)
+    }
+
+    fn create_table(&self, container_id: ContainerId) -> Result<(), CrustyError> {
+        self.trace.record(
+            0,
+            TraceOp::CreateContainer {
+                container_id,
+                name: None,
+            },
+        );
+        self.inner.create_table(container_id)
+    }
+
+    fn remove_container(&self, container_id: ContainerId) -> Result<(), CrustyError> {
+        self.trace
+            .record(0, TraceOp::RemoveContainer { container_id });
+        self.inner.remove_container(container_id)
+    }
+
+    fn get_iterator(
+        &self,
+        container_id: ContainerId,
+        tid: TransactionId,
+        perm: Permissions,
+    ) -> Self::ValIterator {
+        self.trace.record(
+            tid.id(),
+            TraceOp::Scan {
+                container_id,
+                start: None,
+            },
+        );
+        self.inner.get_iterator(container_id, tid, perm)
+    }
+
+    fn get_iterator_from(
+        &self,
+        container_id: ContainerId,
+        tid: TransactionId,
+        perm: Permissions,
+        start: ValueId,
+    ) -> Self::ValIterator {
+        self.trace.record(
+            tid.id(),
+            TraceOp::Scan {
+                container_id,
+                start: Some(start),
+            },
+        );
+        self.inner.get_iterator_from(container_id, tid, perm, start)
+    }
+
+    fn get_value(
+        &self,
+        id: ValueId,
+        tid: TransactionId,
+        perm: Permissions,
+    ) -> Result<Vec<u8>, CrustyError> {
+        self.trace.record(tid.id(), TraceOp::Get { id });
+        self.inner.get_value(id, tid, perm)
+    }
+
+    fn get_storage_path(&self) -> &Path {
+        self.inner.get_storage_path()
+    }
+
+    fn reset(&self) -> Result<(), CrustyError> {
+        self.trace.record(0, TraceOp::Reset);
+        self.inner.reset()
+    }
+
+    fn clear_cache(&self) {
+        self.trace.record(0, TraceOp::ClearCache);
+        self.inner.clear_cache()
+    }
+
+    fn shutdown(&self) {
+        if let Err(e) = self.trace.flush() {
+            error!("flushing trace: {:?}", e);
+        }
+        self.inner.shutdown()
+    }
+}
+
+///outcome of a replay
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub operations: usize,
+    ///calls that failed during replay
+    pub errors: usize,
+    ///ids the replay assigned differently from the trace
+    pub remapped_ids: usize,
+    pub elapsed: Duration,
+}
+
+///re-executes records against sm
+///speed None runs as fast as possible and Some(x) keeps the recorded pacing x times faster
+///values are synthesized from their digests and ids are mapped from the trace to what sm returns
+pub fn replay<S: StorageTrait>(
+    sm: &S,
+    records: &[TraceRecord],
+    speed: Option<f64>,
+) -> Result<ReplayReport, CrustyError> {
+    let mut ids: HashMap<ValueId, ValueId> = HashMap::new();
+    let mut tids: HashMap<u64, TransactionId> = HashMap::new();
+    let mut report = ReplayReport::default();
+    let start = Instant::now();
+
+    for record in records {
+        if let Some(speed) = speed.filter(|s| *s > 0.0) {
+            let due = Duration::from_secs_f64(record.at_micros as f64 / 1_000_000.0 / speed);
+            if let Some(wait) = due.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+        let tid = *tids.entry(record.tid).or_default();
+        let map = |id: &ValueId| *ids.get(id).unwrap_or(id);
+        let failed = match &record.op {
+            TraceOp::CreateContainer { container_id, name } => sm
+                .create_container(*container_id, name.clone(), StateType::BaseTable, None)
+                .is_err(),
+            TraceOp::RemoveContainer { container_id } => {
+                sm.remove_container(*container_id).is_err()
+            }
+            TraceOp::Insert {
+                container_id,
+                value,
+                result,
+            } => {
+                let new_id = sm.insert_value(*container_id, value.synthesize(), tid);
+                if new_id != *result {
+                    report.remapped_ids += 1;
+                    ids.insert(*result, new_id);
+                }
+                false
+            }
+            TraceOp::Delete { id } => sm.delete_value(map(id), tid).is_err(),
+            TraceOp::Update { id, value, result } => {
+                match sm.update_value(value.synthesize(), map(id), tid) {
+                    Ok(new_id) => {
+                        if let Some(result) = result {
+                            if new_id != *result {
+                                report.remapped_ids += 1;
+                                ids.insert(*result, new_id);
+                            }
+                        }
+                        false
+                    }
+                    Err(_) => true,
+                }
+            }
+            TraceOp::Get { id } => sm.get_value(map(id), tid, Permissions::ReadOnly).is_err(),
+            TraceOp::Scan {
+                container_id,
+                start,
+            } => {
+                match start {
+                    Some(start) => sm
+                        .get_iterator_from(*container_id, tid, Permissions::ReadOnly, map(start))
+                        .count(),
+                    None => sm
+                        .get_iterator(*container_id, tid, Permissions::ReadOnly)
+                        .count(),
+                };
+                false
+            }
+            TraceOp::Reset => sm.reset().is_err(),
+            TraceOp::ClearCache => {
+                sm.clear_cache();
+                false
+            }
+        };
+        report.operations += 1;
+        if failed {
+            report.errors += 1;
+        }
+    }
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testutil::*;
+    use memstore::storage_manager::StorageManager as MemStore;
+    use std::sync::{Arc, Mutex as StdMutex};
+
+    ///in memory sink shared with the test
+    #[derive(Clone, Default)]
+    struct Sink(Arc<StdMutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn hs_trace_digest_synthesize() {
+        let bytes = get_random_byte_vec(64);
+        let digest = ValueDigest::of(&bytes);
+        assert_eq!(64, digest.len);
+        assert_eq!(digest, ValueDigest::of(&bytes));
+        assert_ne!(digest, ValueDigest::of(&bytes[1..]));
+        let synthesized = digest.synthesize();
+        assert_eq!(64, synthesized.len());
+        assert_eq!(synthesized, digest.synthesize());
+    }
+
+    #[test]
+    fn hs_trace_record_and_replay() {
+        init();
+        let sink = Sink::default();
+        let traced = Traced::new(
+            MemStore::new_test_sm(),
+            TraceWriter::new(Box::new(sink.clone())),
+        );
+        let tid = TransactionId::new();
+        traced.create_table(1).unwrap();
+        let ids = traced.insert_values(1, get_random_vec_of_byte_vec(10, 20, 40), tid);
+        let updated = traced.update_value(vec![1, 2, 3], ids[3], tid).unwrap();
+        traced.delete_value(ids[0], tid).unwrap();
+        assert_eq!(
+            vec![1, 2, 3],
+            traced
+                .get_value(updated, tid, Permissions::ReadOnly)
+                .unwrap()
+        );
+        assert_eq!(
+            9,
+            traced.get_iterator(1, tid, Permissions::ReadOnly).count()
+        );
+        traced.trace().flush().unwrap();
+
+        let records = read_trace(&sink.0.lock().unwrap()[..]).unwrap();
+        assert_eq!(15, records.len());
+        assert!(records.windows(2).all(|w| w[0].at_micros <= w[1].at_micros));
+        assert_eq!(
+            TraceOp::Update {
+                id: ids[3],
+                value: ValueDigest::of(&[1, 2, 3]),
+                result: Some(updated),
+            },
+            records[11].op
+        );
+
+        let fresh = MemStore::new_test_sm();
+        let report = replay(&fresh, &records, None).unwrap();
+        assert_eq!(15, report.operations);
+        assert_eq!(0, report.errors);
+        assert_eq!(9, fresh.get_iterator(1, tid, Permissions::ReadOnly).count());
+        assert_eq!(
+            3,
+            fresh
+                .get_value(updated, tid, Permissions::ReadOnly)
+                .unwrap()
+                .len()
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::heap_page::{HeapPage, PageInsertError};
+use crate::heapfile::HeapFile;
+use crate::page::Page;
+use crate::storage_manager::StorageManager;
+use crate::wal::SlotImage;
+use common::prelude::*;
+use common::storage_trait::StorageTrait;
+use common::PAGE_SIZE;
+use std::sync::Arc;
+
+/// Pages with fewer live bytes than this have their records moved to earlier pages.
+pub const VACUUM_SPARSE_PAGE_BYTES: usize = PAGE_SIZE / 4;
+
+/// What vacuum_container did to a container.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VacuumReport {
+    /// Pages whose dead space was compacted away.
+    pub pages_compacted: usize,
+    /// Old and new id of every record moved off a sparse page. Old ids no longer resolve,
+    /// but forwarding markers that led to them now lead to the new ones.
+    pub moved: Vec<(ValueId, ValueId)>,
+    /// Empty pages cut off the end of the heap file.
+    pub pages_truncated: PageId,
+    /// Segment files of the heap file removed whole with the pages cut off.
+    pub segments_removed: usize,
+}
+
+impl StorageManager {
+    /// Reclaim the space deletes left in a container. Every page with dead space is compacted,
+    /// the records of sparse pages are moved to the earliest pages with room for them, and
+    /// the empty pages this leaves at the end of the heap file are cut off.
+    /// Moved records get new ids, listed in the report for callers that keep ids elsewhere.
+    /// Records with slot flags stay where they are.
+    /// The vacuum runs as a transaction of its own.
+    pub fn vacuum_container(&self, container_id: ContainerId) -> Result<VacuumReport, CrustyError> {
+        let hf = self.get_hf(container_id)?;
+        let tid = TransactionId::new();
+        let _latch = hf.write_latch.lock().unwrap();
+        let mut report = VacuumReport::default();
+        for page_id in 0..hf.num_pages() {
+            let mut page = self.fetch_page(&hf, page_id, tid, Permissions::ReadWrite)?;
+            if page.dead_bytes() > 0 {
+                page.vacuum();
+                self.store_page(&hf, &mut page, tid)?;
+                report.pages_compacted += 1;
+            }
+        }
+        for page_id in (1..hf.num_pages()).rev() {
+            let page = self.fetch_page(&hf, page_id, tid, Permissions::ReadWrite)?;
+            if page.live_bytes() < VACUUM_SPARSE_PAGE_BYTES {
+                self.move_records_before(&hf, page, &mut report.moved, tid)?;
+            }
+        }
+        if !report.moved.is_empty() {
+            self.repoint_forwards(&hf, &report.moved, tid)?;
+        }
+        self.commit_transaction(tid)?;
+
+        let mut keep = hf.num_pages();
+        while keep > 0
+            && self
+                .fetch_page(&hf, keep - 1, tid, Permissions::ReadOnly)?
+                .iter()
+                .next()
+                .is_none()
+        {
+            keep -= 1;
+        }
+        if keep < hf.num_pages() {
+            report.pages_truncated = hf.num_pages() - keep;
+            self.log_truncate(&hf, keep)?;
+            if let Some(bp) = &self.buffer_pool {
+                bp.discard_pages(container_id, keep);
+            }
+            report.segments_removed = hf.truncate_pages(keep)?;
+        }
+        debug!(
+            "Vacuumed container {}: {} pages compacted, {} records moved, {} pages truncated",
+            container_id,
+            report.pages_compacted,
+            report.moved.len(),
+            report.pages_truncated
+        );
+        Ok(report)
+    }
+
+    /// Move the records of page to the earliest pages before it with room, stopping at the
+    /// first record no earlier page can take. The caller holds hf's write latch.
+    fn move_records_before(
+        &self,
+        hf: &Arc<HeapFile>,
+        mut page: Page,
+        moved: &mut Vec<(ValueId, ValueId)>,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let page_id = page.get_page_id();
+        let records: Vec<(SlotId, Vec<u8>)> = page
+            .iter()
+            .filter(|(slot_id, _)| page.get_slot_flags(*slot_id) == Some(0))
+            .map(|(slot_id, value)| (slot_id, value.into_owned()))
+            .collect();
+        'records: for (slot_id, value) in records {
+            while let Some(target_id) = hf.find_page_with_space(value.len()) {
+                if target_id >= page_id {
+                    break 'records;
+                }
+                let mut target = self.fetch_page(hf, target_id, tid, Permissions::ReadWrite)?;
+                match target.try_add_value(&value) {
+                    Ok(new_slot) => {
+                        self.log_slot(hf, &mut target, new_slot, None, tid)?;
+                        self.store_page(hf, &mut target, tid)?;
+                        let before = SlotImage::of(&page, slot_id);
+                        page.delete_value(slot_id);
+                        self.log_slot(hf, &mut page, slot_id, before, tid)?;
+                        moved.push((
+                            ValueId::new_slot(hf.container_id, page_id, slot_id),
+                            ValueId::new_slot(hf.container_id, target_id, new_slot),
+                        ));
+                        continue 'records;
+                    }
+                    // The map only rounds free space, so correct it and look again
+                    Err(PageInsertError::PageFull { available, .. }) => {
+                        hf.note_free_space(target_id, available)
+                    }
+                    Err(_) => hf.note_free_space(target_id, 0),
+                }
+            }
+            break;
+        }
+        page.vacuum();
+        self.store_page(hf, &mut page, tid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+
+    #[test]
+    fn hs_vacuum_container() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let tid = TransactionId::new();
+        sm.create_table(1).unwrap();
+        let vals = get_random_vec_of_byte_vec(400, 100, 200);
+        let ids = sm.insert_values(1, vals.clone(), tid);
+        let pages = sm.get_hf(1).unwrap().num_pages();
+        assert!(pages > 10);
+
+        //keep every eighth record so every page is sparse
+        let mut kept = Vec::new();
+        for (i, (id, val)) in ids.iter().zip(&vals).enumerate() {
+            if i % 8 == 0 {
+                kept.push((*id, val.clone()));
+            } else {
+                sm.delete_value(*id, tid).unwrap();
+            }
+        }
+        let report = sm.vacuum_container(1).unwrap();
+        assert_eq!(pages as usize, report.pages_compacted);
+        assert!(report.pages_truncated >= pages / 2);
+        let remaining = sm.get_hf(1).unwrap().num_pages();
+        assert_eq!(pages - report.pages_truncated, remaining);
+
+        for (id, val) in &kept {
+            let id = report
+                .moved
+                .iter()
+                .find(|(old, _)| old == id)
+                .map_or(*id, |(_, new)| *new);
+            assert!(id.page_id < Some(remaining));
+            assert_eq!(*val, sm.get_value(id, tid, Permissions::ReadOnly).unwrap());
+        }
+        let count = sm.get_iterator(1, tid, Permissions::ReadOnly).count();
+        assert_eq!(kept.len(), count);
+
+        //a second pass finds nothing left to move or cut
+        let again = sm.vacuum_container(1).unwrap();
+        assert!(again.moved.is_empty());
+        assert_eq!(0, again.pages_truncated);
+    }
+}
@@ -0,0 +1,921 @@
+use crate::forward::is_forwarded;
+use crate::heap_page::{HeapPage, SLOT_IN_USE_CONTINUATION, SLOT_IN_USE_OVERFLOW};
+use crate::heapfile::HeapFile;
+use crate::page::Page;
+use crate::replication::LogShipment;
+use crate::storage_manager::StorageManager;
+use common::prelude::*;
+use common::PAGE_SIZE;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name of the write-ahead log in the storage directory.
+pub(crate) const WAL_FILENAME: &str = "wal";
+/// First bytes of every log file.
+const WAL_MAGIC: &[u8; 8] = b"CRUSTWAL";
+/// Current layout of the log file.
+const WAL_VERSION: u32 = 1;
+/// Bytes before the first record: the magic, the version and the log offset of the file start.
+const WAL_HEADER_SIZE: u64 = 20;
+/// Bytes before each record: the length of the encoded record then its crc32.
+const RECORD_HEADER_SIZE: usize = 8;
+/// Encoded records held in memory before they are written to the log file without a commit.
+pub const WAL_BUFFER_SIZE: usize = 64 * 1024;
+/// Offset past the last record an Lsn can name, which is as long as the log can grow.
+const MAX_LOG_OFFSET: u64 = (PageId::MAX as u64 + 1) * PAGE_SIZE as u64;
+
+/// Lsn of the record at offset in the log: the PAGE_SIZE block of the log it starts in and
+/// where in the block it starts, so Lsns order like offsets.
+pub(crate) fn lsn_at(offset: u64) -> Lsn {
+    Lsn {
+        page_id: (offset / PAGE_SIZE as u64) as PageId,
+        slot_id: (offset % PAGE_SIZE as u64) as SlotId,
+    }
+}
+
+/// Offset in the log of the record at lsn.
+pub(crate) fn offset_of(lsn: Lsn) -> u64 {
+    lsn.page_id as u64 * PAGE_SIZE as u64 + lsn.slot_id as u64
+}
+
+/// What a slot holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SlotKind {
+    #[default]
+    Record,
+    /// An overflow pointer to the continuation pages holding a record too large for a page.
+    Overflow,
+    /// One chunk of an overflow record on a continuation page.
+    Continuation,
+}
+
+/// A slot's contents on one side of a logged change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotImage {
+    /// The record, or the slot's bytes as they are if it holds anything else.
+    pub bytes: Vec<u8>,
+    /// Whether the slot holds a forwarding marker rather than a record.
+    pub forwarded: bool,
+    #[serde(default)]
+    pub kind: SlotKind,
+}
+
+impl SlotImage {
+    /// Contents of slot_id of page, None if the slot is free.
+    pub(crate) fn of(page: &Page, slot_id: SlotId) -> Option<Self> {
+        let kind = match page.get_slot_in_use(slot_id)? {
+            SLOT_IN_USE_OVERFLOW => SlotKind::Overflow,
+            SLOT_IN_USE_CONTINUATION => SlotKind::Continuation,
+            _ => SlotKind::Record,
+        };
+        let bytes = match kind {
+            SlotKind::Record => page.get_value(slot_id)?,
+            _ => page.slot_bytes(slot_id)?.to_vec(),
+        };
+        Some(SlotImage {
+            bytes,
+            forwarded: is_forwarded(page, slot_id),
+            kind,
+        })
+    }
+}
+
+/// A change a transaction made, with what it replaced so it can be undone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogRecord {
+    /// The free slot id was filled.
+    Insert { id: ValueId, after: SlotImage },
+    /// The slot id was freed.
+    Delete { id: ValueId, before: SlotImage },
+    /// The contents of slot id were replaced.
+    Update {
+        id: ValueId,
+        before: SlotImage,
+        after: SlotImage,
+    },
+    /// Page page_id was added to the end of the container's heap file.
+    AllocatePage {
+        container_id: ContainerId,
+        page_id: PageId,
+    },
+    /// Pages of the container's heap file from pages on were dropped. Never undone.
+    Truncate {
+        container_id: ContainerId,
+        pages: PageId,
+    },
+    /// change was made to roll back a change of the transaction. It is redone but never
+    /// undone, and rolling back carries on from undo_next.
+    Compensate {
+        change: Box<LogRecord>,
+        undo_next: Option<Lsn>,
+    },
+    /// Whole contents of the page after the transaction's record before this one, logged with
+    /// the first change to the page after a checkpoint. Only read to rebuild a page that fails
+    /// its checksum, never redone or undone.
+    PageImage {
+        container_id: ContainerId,
+        page_id: PageId,
+        image: Vec<u8>,
+    },
+    /// Every change before redo_from was in the heap files, and the transactions in active
+    /// were running with the Lsn of their last record.
+    Checkpoint {
+        redo_from: Lsn,
+        active: Vec<(TransactionId, Lsn)>,
+    },
+    /// The transaction committed. Its changes are durable once this record is.
+    Commit,
+    /// The transaction ended with every change it made rolled back.
+    Abort,
+}
+
+/// A record as it sits in the log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub lsn: Lsn,
+    /// The transaction's record before this one, None for its first.
+    pub prev_lsn: Option<Lsn>,
+    pub tid: TransactionId,
+    pub record: LogRecord,
+}
+
+/// Append-only log of every change the storage manager makes to a page, written ahead of the
+/// page. Appends are buffered and reach the file when the buffer fills or the log is flushed,
+/// and a commit flushes and syncs the log so the committed changes survive a crash.
+/// The log can name 256 MiB of records with the 4 byte page Lsn, after which appends fail.
+pub(crate) struct Wal {
+    path: PathBuf,
+    state: Mutex<WalState>,
+}
+
+struct WalState {
+    file: File,
+    /// Log offset of the start of the file, so the file can drop old records without the
+    /// Lsns of the rest changing.
+    base: u64,
+    /// Encoded records not yet written to the file.
+    buffer: Vec<u8>,
+    /// Log offset where the buffered records start, which is where the file ends.
+    written: u64,
+    /// Log offset up to which the file is synced.
+    synced: u64,
+    /// Lsn of the first change recovery would roll back, None while there is none, and of
+    /// the last record of every transaction that has not ended.
+    active: HashMap<TransactionId, (Option<Lsn>, Lsn)>,
+    /// Pages with an image in the log since the last checkpoint.
+    imaged: HashSet<(ContainerId, PageId)>,
+    /// Backups in progress, which need every record in the log kept until they copy it.
+    holds: usize,
+    /// Log offset of the first record not yet shipped to a standby, if the log is shipped.
+    ship_from: Option<u64>,
+}
+
+impl WalState {
+    fn end(&self) -> u64 {
+        self.written + self.buffer.len() as u64
+    }
+
+    /// Buffer record of tid at the end of the log, returning its Lsn.
+    fn append(&mut self, tid: TransactionId, record: LogRecord) -> Result<Lsn, CrustyError> {
+        let lsn = lsn_at(self.end());
+        let entry = LogEntry {
+            lsn,
+            prev_lsn: self.active.get(&tid).map(|(_, last)| *last),
+            tid,
+            record,
+        };
+        let bytes = encode(&entry)?;
+        if self.end() + bytes.len() as u64 > MAX_LOG_OFFSET {
+            return Err(CrustyError::CrustyError(
+                "Write-ahead log is full".to_string(),
+            ));
+        }
+        self.buffer.extend_from_slice(&bytes);
+        match entry.record {
+            LogRecord::Commit | LogRecord::Abort | LogRecord::Checkpoint { .. } => {
+                self.active.remove(&tid);
+            }
+            LogRecord::Insert { .. } | LogRecord::Delete { .. } | LogRecord::Update { .. } => {
+                let (first, last) = self.active.entry(tid).or_insert((None, lsn));
+                first.get_or_insert(lsn);
+                *last = lsn;
+            }
+            _ => self.active.entry(tid).or_insert((None, lsn)).1 = lsn,
+        }
+        if self.buffer.len() >= WAL_BUFFER_SIZE {
+            self.write_out()?;
+        }
+        Ok(lsn)
+    }
+
+    /// Log offset of from, if the durable records from there on are all in the file.
+    fn shippable(&self, from: Lsn) -> Result<u64, CrustyError> {
+        let from = offset_of(from);
+        if from < self.base + WAL_HEADER_SIZE || from > self.synced {
+            return Err(CrustyError::CrustyError(format!(
+                "Log holds no records from {:?}",
+                lsn_at(from)
+            )));
+        }
+        Ok(from)
+    }
+
+    /// Move the buffered records to the file.
+    fn write_out(&mut self) -> Result<(), CrustyError> {
+        if !self.buffer.is_empty() {
+            self.file.write_all(&self.buffer)?;
+            self.written += self.buffer.len() as u64;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Wal {
+    /// Log in dir, created if there is none. Anything after the last whole record is where a
+    /// crash cut off an append and is cut off the file.
+    pub(crate) fn open(dir: &Path) -> Result<Self, CrustyError> {
+        let path = dir.join(WAL_FILENAME);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let (base, end) = if file.metadata()?.len() == 0 {
+            write_header(&mut file, 0)?;
+            (0, WAL_HEADER_SIZE)
+        } else {
+            let (base, entries, end) = read_log(&mut file)?;
+            if end < file.metadata()?.len() {
+                warn!(
+                    "Cutting {} bytes of partly written records off the end of log {:?}",
+                    file.metadata()?.len() - end,
+                    path
+                );
+                file.set_len(end)?;
+                file.sync_all()?;
+            }
+            debug!("Opened log {:?} holding {} records", path, entries.len());
+            (base, base + end)
+        };
+        file.seek(SeekFrom::Start(end - base))?;
+        Ok(Wal {
+            path,
+            state: Mutex::new(WalState {
+                file,
+                base,
+                buffer: Vec::new(),
+                written: end,
+                synced: end,
+                active: HashMap::new(),
+                imaged: HashSet::new(),
+                holds: 0,
+                ship_from: None,
+            }),
+        })
+    }
+
+    /// Append record to the log on behalf of tid and return its Lsn. The record is only
+    /// buffered, so it may be lost in a crash until the log is flushed past it.
+    pub(crate) fn append(&self, tid: TransactionId, record: LogRecord) -> Result<Lsn, CrustyError> {
+        self.state.lock().unwrap().append(tid, record)
+    }
+
+    /// Make the log durable up to and including the record at lsn.
+    pub(crate) fn flush(&self, lsn: Lsn) -> Result<(), CrustyError> {
+        let mut state = self.state.lock().unwrap();
+        if offset_of(lsn) < state.synced {
+            return Ok(());
+        }
+        state.write_out()?;
+        state.file.sync_data()?;
+        state.synced = state.written;
+        Ok(())
+    }
+
+    /// Log that tid committed and make the log durable up to the commit.
+    pub(crate) fn commit(&self, tid: TransactionId) -> Result<Lsn, CrustyError> {
+        let lsn = self.append(tid, LogRecord::Commit)?;
+        self.flush(lsn)?;
+        Ok(lsn)
+    }
+
+    /// Lsn of the last record of tid, None if it has logged nothing since it last ended.
+    pub(crate) fn last_lsn(&self, tid: TransactionId) -> Option<Lsn> {
+        let state = self.state.lock().unwrap();
+        state.active.get(&tid).map(|(_, last)| *last)
+    }
+
+    /// Chain the next record of tid to last, for a transaction left unfinished in the log
+    /// when it was opened.
+    pub(crate) fn resume(&self, tid: TransactionId, last: Lsn) {
+        let mut state = self.state.lock().unwrap();
+        state.active.entry(tid).or_insert((Some(last), last)).1 = last;
+    }
+
+    /// Log a checkpoint with the transactions running now and make it durable. Changes before
+    /// redo_from must be in the heap files already. Returns the checkpoint's Lsn and the Lsn
+    /// of the oldest record recovery could still need, the first change of the running
+    /// transactions or redo_from if that is older. Transactions with no change to roll back
+    /// are forgotten, so they keep no record and their next one starts a new chain.
+    pub(crate) fn checkpoint(&self, redo_from: Lsn) -> Result<(Lsn, Lsn), CrustyError> {
+        let (lsn, needed) = {
+            let mut state = self.state.lock().unwrap();
+            state.active.retain(|_, (first, _)| first.is_some());
+            let mut active: Vec<(TransactionId, Lsn)> = state
+                .active
+                .iter()
+                .map(|(tid, (_, last))| (*tid, *last))
+                .collect();
+            active.sort_by_key(|(tid, _)| tid.id());
+            let oldest = state.active.values().filter_map(|(first, _)| *first).min();
+            // Pages changed from here on are imaged again, since the images before may be
+            // truncated away
+            state.imaged.clear();
+            let record = LogRecord::Checkpoint { redo_from, active };
+            let lsn = state.append(TransactionId::new(), record)?;
+            (
+                lsn,
+                oldest.map_or(redo_from, |oldest| oldest.min(redo_from)),
+            )
+        };
+        self.flush(lsn)?;
+        Ok((lsn, needed))
+    }
+
+    /// Drop the records before keep_from, which must be the Lsn of a record or the end of the
+    /// log, from the log file. The records kept keep their Lsns. The shorter file is written
+    /// and synced beside the log then renamed over it, so a crash leaves one or the other.
+    pub(crate) fn truncate(&self, keep_from: Lsn) -> Result<(), CrustyError> {
+        let mut state = self.state.lock().unwrap();
+        state.write_out()?;
+        // Records not yet shipped are kept for the standby
+        let from = offset_of(keep_from)
+            .min(state.written)
+            .min(state.ship_from.unwrap_or(u64::MAX));
+        if from <= state.base + WAL_HEADER_SIZE || state.holds > 0 {
+            return Ok(());
+        }
+        let mut kept = Vec::new();
+        let start = from - state.base;
+        state.file.seek(SeekFrom::Start(start))?;
+        state.file.read_to_end(&mut kept)?;
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        let base = from - WAL_HEADER_SIZE;
+        write_header(&mut file, base)?;
+        file.write_all(&kept)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        #[cfg(unix)]
+        File::open(self.path.parent().unwrap())?.sync_all()?;
+        let mut file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        file.seek(SeekFrom::End(0))?;
+        debug!(
+            "Truncated log {:?} to {} bytes of records",
+            self.path,
+            kept.len()
+        );
+        state.file = file;
+        state.base = base;
+        state.synced = state.written;
+        state.imaged.clear();
+        Ok(())
+    }
+
+    /// True the first time it is asked about a page since the last checkpoint, when the change
+    /// just logged to the page should be followed by an image of it.
+    pub(crate) fn needs_image(&self, container_id: ContainerId, page_id: PageId) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.imaged.insert((container_id, page_id))
+    }
+
+    /// Forget the images of the pages of container_id from pages on, which are being dropped,
+    /// so a page added again in their place is imaged afresh.
+    pub(crate) fn forget_images(&self, container_id: ContainerId, pages: PageId) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .imaged
+            .retain(|(cid, pid)| *cid != container_id || *pid < pages);
+    }
+
+    /// Lsn the next record will get.
+    pub(crate) fn end_lsn(&self) -> Lsn {
+        lsn_at(self.state.lock().unwrap().end())
+    }
+
+    /// Lsn of the first record that could be lost in a crash.
+    pub(crate) fn durable_lsn(&self) -> Lsn {
+        lsn_at(self.state.lock().unwrap().synced)
+    }
+
+    /// Every whole record in the log, including the buffered ones, in Lsn order.
+    pub(crate) fn entries(&self) -> Result<Vec<LogEntry>, CrustyError> {
+        let mut state = self.state.lock().unwrap();
+        state.write_out()?;
+        let (_, entries, _) = read_log(&mut File::open(&self.path)?)?;
+        Ok(entries)
+    }
+
+    /// Keep truncate from dropping any record until release is called as often as hold was.
+    pub(crate) fn hold(&self) {
+        self.state.lock().unwrap().holds += 1;
+    }
+
+    /// Undo a call to hold.
+    pub(crate) fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.holds = state.holds.saturating_sub(1);
+    }
+
+    /// Write every record in the log, including the buffered ones, to a new log at path.
+    /// They are made durable here first, so the copy holds none this log could lose.
+    pub(crate) fn copy_to(&self, path: &Path) -> Result<(), CrustyError> {
+        let mut state = self.state.lock().unwrap();
+        state.write_out()?;
+        state.file.sync_data()?;
+        state.synced = state.written;
+        let mut file = File::create(path)?;
+        io::copy(&mut File::open(&self.path)?, &mut file)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Lsn of the first record in the log file.
+    pub(crate) fn start_lsn(&self) -> Lsn {
+        lsn_at(self.state.lock().unwrap().base + WAL_HEADER_SIZE)
+    }
+
+    /// Keep the records from from on for a standby whose log ends at from, until they are
+    /// shipped or stop_shipping is called. Errors if the log no longer holds them.
+    pub(crate) fn start_shipping(&self, from: Lsn) -> Result<(), CrustyError> {
+        let mut state = self.state.lock().unwrap();
+        let from = state.shippable(from)?;
+        state.ship_from = Some(from);
+        Ok(())
+    }
+
+    /// The durable records from from on, for a standby whose log ends at from, which are
+    /// kept from then on until the next shipment or stop_shipping. Errors if the log no
+    /// longer holds them.
+    pub(crate) fn shipment(&self, from: Lsn) -> Result<LogShipment, CrustyError> {
+        let mut state = self.state.lock().unwrap();
+        let from = state.shippable(from)?;
+        let mut records = vec![0; (state.synced - from) as usize];
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(from - state.base))?;
+        file.read_exact(&mut records)?;
+        state.ship_from = Some(from + records.len() as u64);
+        Ok(LogShipment {
+            start: state.base + WAL_HEADER_SIZE,
+            from,
+            records,
+        })
+    }
+
+    /// Let truncate drop records that were not shipped.
+    pub(crate) fn stop_shipping(&self) {
+        self.state.lock().unwrap().ship_from = None;
+    }
+
+    /// Append the records of shipment, which must start where the log ends, and make them
+    /// durable. Returns them decoded, to be redone.
+    pub(crate) fn receive(&self, shipment: &LogShipment) -> Result<Vec<LogEntry>, CrustyError> {
+        let mut state = self.state.lock().unwrap();
+        if shipment.from != state.end() {
+            return Err(CrustyError::CrustyError(format!(
+                "Shipment of records from {:?} does not follow the end of the log at {:?}",
+                lsn_at(shipment.from),
+                lsn_at(state.end())
+            )));
+        }
+        let entries = decode(&shipment.records, shipment.from)?;
+        state.buffer.extend_from_slice(&shipment.records);
+        state.write_out()?;
+        state.file.sync_data()?;
+        state.synced = state.written;
+        Ok(entries)
+    }
+
+    /// Start the log over empty, as if it had just been created.
+    pub(crate) fn reset(&self) -> Result<(), CrustyError> {
+        let mut state = self.state.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.path)?;
+        write_header(&mut file, 0)?;
+        *state = WalState {
+            file,
+            base: 0,
+            buffer: Vec::new(),
+            written: WAL_HEADER_SIZE,
+            synced: WAL_HEADER_SIZE,
+            active: HashMap::new(),
+            imaged: HashSet::new(),
+            holds: state.holds,
+            ship_from: None,
+        };
+        Ok(())
+    }
+}
+
+fn write_header(file: &mut File, base: u64) -> Result<(), CrustyError> {
+    let mut header = Vec::with_capacity(WAL_HEADER_SIZE as usize);
+    header.extend_from_slice(WAL_MAGIC);
+    header.extend_from_slice(&WAL_VERSION.to_le_bytes());
+    header.extend_from_slice(&base.to_le_bytes());
+    file.write_all(&header)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn encode(entry: &LogEntry) -> Result<Vec<u8>, CrustyError> {
+    let payload = serde_cbor::to_vec(entry)
+        .map_err(|e| CrustyError::CrustyError(format!("Error encoding log record: {}", e)))?;
+    let mut bytes = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+/// Records encoded in bytes, which start at log offset from. Errors unless they are all whole.
+fn decode(bytes: &[u8], from: u64) -> Result<Vec<LogEntry>, CrustyError> {
+    let mut entries = Vec::new();
+    let mut at = 0;
+    while at < bytes.len() {
+        let corrupt = || {
+            CrustyError::CrustyError(format!(
+                "Corrupt log record at {:?}",
+                lsn_at(from + at as u64)
+            ))
+        };
+        let header = bytes.get(at..at + RECORD_HEADER_SIZE).ok_or_else(corrupt)?;
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+        let start = at + RECORD_HEADER_SIZE;
+        let payload = bytes.get(start..start + len).ok_or_else(corrupt)?;
+        if crc32fast::hash(payload) != crc {
+            return Err(corrupt());
+        }
+        match serde_cbor::from_slice::<LogEntry>(payload) {
+            Ok(entry) if entry.lsn == lsn_at(from + at as u64) => entries.push(entry),
+            _ => return Err(corrupt()),
+        }
+        at = start + len;
+    }
+    Ok(entries)
+}
+
+/// Log offset of the start of file, its whole records and the file position after the last
+/// of them. Reading stops at the first record that is cut short, fails its checksum or is not
+/// where its Lsn says, since a crash in the middle of an append leaves such a tail.
+fn read_log(file: &mut File) -> Result<(u64, Vec<LogEntry>, u64), CrustyError> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(file);
+    let mut header = [0; WAL_HEADER_SIZE as usize];
+    reader.read_exact(&mut header)?;
+    if &header[..8] != WAL_MAGIC {
+        return Err(CrustyError::CrustyError(
+            "Not a write-ahead log file".to_string(),
+        ));
+    }
+    let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    if version > WAL_VERSION {
+        return Err(CrustyError::CrustyError(format!(
+            "Log version {} is newer than the supported version {}",
+            version, WAL_VERSION
+        )));
+    }
+    let base = u64::from_le_bytes(header[12..20].try_into().unwrap());
+    let mut entries = Vec::new();
+    let mut end = WAL_HEADER_SIZE;
+    loop {
+        let mut record_header = [0; RECORD_HEADER_SIZE];
+        let mut payload = Vec::new();
+        let read = reader.read_exact(&mut record_header).and_then(|_| {
+            let len = u32::from_le_bytes(record_header[..4].try_into().unwrap()) as u64;
+            (&mut reader).take(len).read_to_end(&mut payload)?;
+            if payload.len() as u64 == len {
+                Ok(())
+            } else {
+                Err(ErrorKind::UnexpectedEof.into())
+            }
+        });
+        match read {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let crc = u32::from_le_bytes(record_header[4..].try_into().unwrap());
+        if crc32fast::hash(&payload) != crc {
+            break;
+        }
+        match serde_cbor::from_slice::<LogEntry>(&payload) {
+            Ok(entry) if entry.lsn == lsn_at(base + end) => entries.push(entry),
+            _ => break,
+        }
+        end += (RECORD_HEADER_SIZE + payload.len()) as u64;
+    }
+    Ok((base, entries, end))
+}
+
+/// Write-ahead logging of the changes the storage manager makes to pages. Every change is
+/// logged before the page is stored and the page stamped with the record's Lsn, so the page
+/// cannot reach its heap file before the log has the change. Pages written whole through
+/// write_page or a page guard are not logged.
+impl LogRecord {
+    /// Container the record changes, None for records that change none.
+    pub(crate) fn container_id(&self) -> Option<ContainerId> {
+        match self {
+            LogRecord::Insert { id, .. }
+            | LogRecord::Delete { id, .. }
+            | LogRecord::Update { id, .. } => Some(id.container_id),
+            LogRecord::AllocatePage { container_id, .. }
+            | LogRecord::Truncate { container_id, .. }
+            | LogRecord::PageImage { container_id, .. } => Some(*container_id),
+            LogRecord::Compensate { change, .. } => change.container_id(),
+            LogRecord::Checkpoint { .. } | LogRecord::Commit | LogRecord::Abort => None,
+        }
+    }
+}
+
+impl StorageManager {
+    /// Append record to the log on behalf of tid and stamp page with its Lsn, followed by an
+    /// image of the page if this is its first change since the last checkpoint.
+    /// Does nothing without a log or for a change to a temp container, which is deleted
+    /// rather than recovered after a crash.
+    pub(crate) fn log_change(
+        &self,
+        page: &mut Page,
+        record: LogRecord,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let container_id = record.container_id();
+        if container_id.is_some_and(|cid| self.is_temp_container(cid)) {
+            return Ok(());
+        }
+        if let Some(wal) = &self.wal {
+            page.set_lsn(wal.append(tid, record)?);
+            let page_id = page.get_page_id();
+            if let Some(container_id) = container_id {
+                if wal.needs_image(container_id, page_id) {
+                    let image = LogRecord::PageImage {
+                        container_id,
+                        page_id,
+                        image: page.to_bytes().to_vec(),
+                    };
+                    wal.append(tid, image)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Log the change to slot_id of page from before, what the slot held before it changed.
+    /// A slot that is still free logs nothing.
+    pub(crate) fn log_slot(
+        &self,
+        hf: &HeapFile,
+        page: &mut Page,
+        slot_id: SlotId,
+        before: Option<SlotImage>,
+        tid: TransactionId,
+    ) -> Result<(), CrustyError> {
+        let id = ValueId::new_slot(hf.container_id, page.get_page_id(), slot_id);
+        let record = match (before, SlotImage::of(page, slot_id)) {
+            (None, Some(after)) => LogRecord::Insert { id, after },
+            (Some(before), None) => LogRecord::Delete { id, before },
+            (Some(before), Some(after)) => LogRecord::Update { id, before, after },
+            (None, None) => return Ok(()),
+        };
+        self.log_change(page, record, tid)
+    }
+
+    /// Log that the pages of hf from pages on are about to be dropped. The record is made
+    /// durable straight away since the heap file shrinks without going through the pool.
+    pub(crate) fn log_truncate(&self, hf: &HeapFile, pages: PageId) -> Result<(), CrustyError> {
+        if self.is_temp_container(hf.container_id) {
+            return Ok(());
+        }
+        if let Some(wal) = &self.wal {
+            let tid = TransactionId::new();
+            let record = LogRecord::Truncate {
+                container_id: hf.container_id,
+                pages,
+            };
+            wal.append(tid, record)?;
+            wal.commit(tid)?;
+            wal.forget_images(hf.container_id, pages);
+        }
+        Ok(())
+    }
+
+    /// Make the log durable up to the last change of pages before they are written.
+    pub(crate) fn flush_log(&self, pages: &[Page]) -> Result<(), CrustyError> {
+        match (&self.wal, pages.iter().map(|page| page.get_lsn()).max()) {
+            (Some(wal), Some(lsn)) => wal.flush(lsn),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_wal_append_commit() {
+        init();
+        let dir = TempDir::new(gen_random_test_sm_dir(), true);
+        let wal = Wal::open(&dir).unwrap();
+        let (t1, t2) = (TransactionId::new(), TransactionId::new());
+        let id = ValueId::new_slot(1, 0, 0);
+        let image = |bytes: &[u8]| SlotImage {
+            bytes: bytes.to_vec(),
+            forwarded: false,
+            kind: SlotKind::Record,
+        };
+
+        //records get increasing lsns chained per transaction
+        let alloc = LogRecord::AllocatePage {
+            container_id: 1,
+            page_id: 0,
+        };
+        let insert = LogRecord::Insert {
+            id,
+            after: image(&[1, 2]),
+        };
+        let update = LogRecord::Update {
+            id,
+            before: image(&[1, 2]),
+            after: image(&[3]),
+        };
+        let a = wal.append(t1, alloc.clone()).unwrap();
+        let b = wal.append(t2, insert.clone()).unwrap();
+        let c = wal.append(t1, update.clone()).unwrap();
+        assert!(Lsn::default() < a && a < b && b < c);
+        assert_eq!(a, wal.durable_lsn());
+
+        //a commit makes everything before it durable and ends the chain
+        let commit = wal.commit(t1).unwrap();
+        assert_eq!(wal.end_lsn(), wal.durable_lsn());
+        let d = wal.append(t1, insert.clone()).unwrap();
+        let expected = vec![
+            (a, None, t1, alloc),
+            (b, None, t2, insert.clone()),
+            (c, Some(a), t1, update),
+            (commit, Some(c), t1, LogRecord::Commit),
+            (d, None, t1, insert),
+        ];
+        let entries: Vec<_> = wal
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.lsn, e.prev_lsn, e.tid, e.record))
+            .collect();
+        assert_eq!(expected, entries);
+
+        //reopening finds the same records and appends after them
+        drop(wal);
+        let wal = Wal::open(&dir).unwrap();
+        assert_eq!(5, wal.entries().unwrap().len());
+        assert!(d < wal.append(t2, LogRecord::Commit).unwrap());
+    }
+
+    #[test]
+    fn hs_wal_torn_tail() {
+        init();
+        let dir = TempDir::new(gen_random_test_sm_dir(), true);
+        let wal = Wal::open(&dir).unwrap();
+        let tid = TransactionId::new();
+        let record = LogRecord::Delete {
+            id: ValueId::new_slot(1, 2, 3),
+            before: SlotImage {
+                bytes: get_random_byte_vec(100),
+                forwarded: true,
+                kind: SlotKind::Record,
+            },
+        };
+        wal.append(tid, record.clone()).unwrap();
+        let last = wal.commit(tid).unwrap();
+        drop(wal);
+
+        //a record cut short by a crash is cut off when the log is opened
+        let path = dir.join(WAL_FILENAME);
+        let whole = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(
+            &encode(&LogEntry {
+                lsn: lsn_at(whole),
+                prev_lsn: None,
+                tid,
+                record,
+            })
+            .unwrap()[..50],
+        )
+        .unwrap();
+        drop(file);
+        let wal = Wal::open(&dir).unwrap();
+        assert_eq!(whole, fs::metadata(&path).unwrap().len());
+        assert_eq!(last, wal.entries().unwrap().last().unwrap().lsn);
+        assert_eq!(lsn_at(whole), wal.append(tid, LogRecord::Commit).unwrap());
+        assert_eq!(3, wal.entries().unwrap().len());
+
+        //so is a record that fails its checksum
+        drop(wal);
+        let mut bytes = fs::read(&path).unwrap();
+        let len = bytes.len();
+        bytes[len - 1] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(2, Wal::open(&dir).unwrap().entries().unwrap().len());
+    }
+
+    #[test]
+    fn hs_wal_storage_manager() {
+        init();
+        let sm = StorageManager::new_test_sm();
+        let wal = sm.wal.as_ref().unwrap();
+        let tid = TransactionId::new();
+        sm.create_table(1).unwrap();
+        let first = get_random_byte_vec(50);
+        let id = sm.insert_value(1, first.clone(), tid);
+        let second = get_random_byte_vec(60);
+        sm.update_value(second.clone(), id, tid).unwrap();
+        sm.delete_value(id, tid).unwrap();
+
+        //every change is logged with what it replaced
+        let image = |bytes: &Vec<u8>| SlotImage {
+            bytes: bytes.clone(),
+            forwarded: false,
+            kind: SlotKind::Record,
+        };
+        let records: Vec<_> = wal
+            .entries()
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.tid == tid)
+            .map(|e| e.record)
+            .collect();
+        //the first change to the page since the last checkpoint is followed by its image
+        assert!(matches!(
+            records[1],
+            LogRecord::PageImage {
+                container_id: 1,
+                page_id: 0,
+                ..
+            }
+        ));
+        let records: Vec<_> = records
+            .into_iter()
+            .filter(|r| !matches!(r, LogRecord::PageImage { .. }))
+            .collect();
+        let expected = vec![
+            LogRecord::AllocatePage {
+                container_id: 1,
+                page_id: 0,
+            },
+            LogRecord::Insert {
+                id,
+                after: image(&first),
+            },
+            LogRecord::Update {
+                id,
+                before: image(&first),
+                after: image(&second),
+            },
+            LogRecord::Delete {
+                id,
+                before: image(&second),
+            },
+        ];
+        assert_eq!(expected, records);
+
+        //the page carries the lsn of its last change and cannot be written before it is durable
+        let page = sm
+            .get_page(1, 0, tid, Permissions::ReadOnly, false)
+            .unwrap();
+        let last = wal.entries().unwrap().last().unwrap().lsn;
+        assert_eq!(last, page.get_lsn());
+        assert!(wal.durable_lsn() <= last);
+        sm.flush_all().unwrap();
+        assert!(wal.durable_lsn() > last);
+
+        //committing makes the commit durable too
+        sm.insert_value(1, first, tid);
+        sm.commit_transaction(tid).unwrap();
+        assert_eq!(wal.end_lsn(), wal.durable_lsn());
+        let entries = wal.entries().unwrap();
+        assert_eq!(LogRecord::Commit, entries.last().unwrap().record);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::buffer_pool::FrameKey;
+use crate::storage_manager::StorageManager;
+use common::prelude::*;
+use std::fs;
+use std::io::ErrorKind;
+
+/// File in the storage directory listing the pages the buffer pool held at the last shutdown.
+pub(crate) const HOT_PAGES_FILENAME: &str = "hot_pages";
+
+impl StorageManager {
+    /// Save the pages in the buffer pool to the hot page list when warm up is on, and remove
+    /// any list an earlier shutdown saved when it is off.
+    pub(crate) fn save_hot_pages(&self) -> Result<(), CrustyError> {
+        let path = self.storage_dir.join(HOT_PAGES_FILENAME);
+        let bp = match &self.buffer_pool {
+            Some(bp) if self.get_warm_up() => bp,
+            _ => {
+                return match fs::remove_file(&path) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+                    _ => Ok(()),
+                }
+            }
+        };
+        let pages = bp.resident_pages();
+        let bytes = serde_json::to_vec(&pages)
+            .map_err(|e| CrustyError::CrustyError(format!("Error encoding hot pages: {}", e)))?;
+        fs::write(&path, bytes)?;
+        debug!("Saved {} hot pages to {:?}", pages.len(), path);
+        Ok(())
+    }
+
+    /// Read the pages of the hot page list saved by the last shutdown into the buffer pool, in
+    /// container and page order, while it has free frames. Pages of containers that are gone
+    /// or past the end of their heap file are skipped. Returns how many pages were read, 0 if
+    /// there is no list.
+    pub fn warm_up_buffer_pool(&self) -> Result<usize, CrustyError> {
+        let path = self.storage_dir.join(HOT_PAGES_FILENAME);
+        let bp = match &self.buffer_pool {
+            Some(bp) if path.exists() => bp,
+            _ => return Ok(0),
+        };
+        let pages: Vec<FrameKey> = serde_json::from_slice(&fs::read(&path)?).map_err(|e| {
+            CrustyError::CrustyError(format!("Error reading hot pages {:?}: {}", path, e))
+        })?;
+        let mut read = 0;
+        for (container_id, page_id) in pages {
+            if bp.len() >= bp.capacity() {
+                break;
+            }
+            let hf = match self.get_hf(container_id) {
+                Ok(hf) if page_id < hf.num_pages() => hf,
+                _ => continue,
+            };
+            if bp.prefetch(&hf, page_id)? {
+                read += 1;
+            }
+        }
+        info!("Warmed up the buffer pool with {} pages", read);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::storage_trait::StorageTrait;
+    use common::testutil::*;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn hs_warmup_hot_pages() {
+        init();
+        let tdir = TempDir::new(gen_random_test_sm_dir(), true);
+        let tid = TransactionId::new();
+        let ids = {
+            let sm = StorageManager::new(&tdir);
+            sm.set_warm_up(true);
+            sm.create_table(1).unwrap();
+            let ids = sm.insert_values(1, vec![vec![3; 1000]; 20], tid);
+            sm.commit_transaction(tid).unwrap();
+            sm.shutdown();
+            ids
+        };
+        assert!(tdir.join(HOT_PAGES_FILENAME).exists());
+
+        //the pages cached at shutdown are cached again before the first read
+        {
+            let sm = StorageManager::new(&tdir);
+            assert!(sm.get_warm_up());
+            let bp = sm.stats().buffer_pool.unwrap();
+            assert_eq!((5, 5), (bp.occupancy, bp.prefetches));
+            sm.reset_stats();
+            for id in &ids {
+                sm.get_value(*id, tid, Permissions::ReadOnly).unwrap();
+            }
+            let stats = sm.stats();
+            assert_eq!(0, stats.page_reads);
+            assert_eq!(0, stats.buffer_pool.unwrap().misses);
+
+            //with warm up off no list is left for the next open
+            sm.set_warm_up(false);
+            sm.shutdown();
+        }
+        assert!(!tdir.join(HOT_PAGES_FILENAME).exists());
+        let sm = StorageManager::new(&tdir);
+        assert_eq!(0, sm.stats().buffer_pool.unwrap().occupancy);
+        assert_eq!(0, sm.warm_up_buffer_pool().unwrap());
+    }
+}
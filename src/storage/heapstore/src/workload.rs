@@ -0,0 +1,524 @@
+use common::prelude::*;
+use common::storage_trait::StorageTrait;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+///skew of the zipfian key distribution used by the ycsb core workloads
+const ZIPFIAN_THETA: f64 = 0.99;
+
+///storage operations a workload issues
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Op {
+    Insert,
+    Read,
+    Update,
+    Delete,
+    Scan,
+}
+
+impl FromStr for Op {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "insert" => Ok(Op::Insert),
+            "read" => Ok(Op::Read),
+            "update" => Ok(Op::Update),
+            "delete" => Ok(Op::Delete),
+            "scan" => Ok(Op::Scan),
+            _ => Err(format!("unknown operation {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Op::Insert => "insert",
+            Op::Read => "read",
+            Op::Update => "update",
+            Op::Delete => "delete",
+            Op::Scan => "scan",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+///relative weights of the operations in a workload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpMix {
+    weights: Vec<(Op, u32)>,
+    total: u32,
+}
+
+impl OpMix {
+    pub fn new(weights: Vec<(Op, u32)>) -> Result<Self, String> {
+        let total = weights.iter().map(|(_, w)| w).sum();
+        if total == 0 {
+            return Err("operation mix has no weight".to_string());
+        }
+        Ok(OpMix { weights, total })
+    }
+
+    ///the ycsb core workloads a to f with read-modify-write in f run as a read plus an update
+    pub fn preset(name: &str) -> Option<Self> {
+        let weights = match name.to_ascii_lowercase().as_str() {
+            "a" => vec![(Op::Read, 50), (Op::Update, 50)],
+            "b" => vec![(Op::Read, 95), (Op::Update, 5)],
+            "c" => vec![(Op::Read, 100)],
+            "d" => vec![(Op::Read, 95), (Op::Insert, 5)],
+            "e" => vec![(Op::Scan, 95), (Op::Insert, 5)],
+            "f" => vec![(Op::Read, 50), (Op::Update, 50)],
+            _ => return None,
+        };
+        OpMix::new(weights).ok()
+    }
+
+    ///picks an operation with probability proportional to its weight
+    fn choose(&self, rng: &mut impl Rng) -> Op {
+        let mut pick = rng.gen_range(0..self.total);
+        for (op, weight) in &self.weights {
+            if pick < *weight {
+                return *op;
+            }
+            pick -= weight;
+        }
+        unreachable!("pick is below the total weight")
+    }
+}
+
+///parses a preset name or a list like read=50,update=45,scan=5
+impl FromStr for OpMix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(mix) = OpMix::preset(s) {
+            return Ok(mix);
+        }
+        let weights = s
+            .split(',')
+            .map(|part| {
+                let (op, weight) = part
+                    .split_once('=')
+                    .ok_or_else(|| format!("expected op=weight, got {}", part))?;
+                let weight = weight
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("bad weight in {}", part))?;
+                Ok((op.trim().parse()?, weight))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        OpMix::new(weights)
+    }
+}
+
+///how keys are picked for reads updates deletes and scans
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyDistribution {
+    Uniform,
+    ///few hot keys spread over the key space
+    Zipfian,
+    ///skewed towards the most recently inserted keys
+    Latest,
+}
+
+impl FromStr for KeyDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(KeyDistribution::Uniform),
+            "zipfian" => Ok(KeyDistribution::Zipfian),
+            "latest" => Ok(KeyDistribution::Latest),
+            _ => Err(format!("unknown key distribution {}", s)),
+        }
+    }
+}
+
+///zipfian generator over 0..n from Gray et al. Quickly Generating Billion-Record Synthetic Databases
+#[derive(Debug, Clone)]
+pub struct Zipfian {
+    n: usize,
+    theta: f64,
+    alpha: f64,
+    zeta_n: f64,
+    eta: f64,
+}
+
+impl Zipfian {
+    pub fn new(n: usize, theta: f64) -> Self {
+        let n = n.max(1);
+        let zeta = |n: usize| (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum::<f64>();
+        let zeta_n = zeta(n);
+        let zeta_2 = zeta(2.min(n));
+        let eta = (1.0 - (2.0 / n as f64).powf(1.0 - theta)) / (1.0 - zeta_2 / zeta_n);
+        Zipfian {
+            n,
+            theta,
+            alpha: 1.0 / (1.0 - theta),
+            zeta_n,
+            eta,
+        }
+    }
+
+    ///rank in 0..n where 0 is the most popular
+    pub fn sample(&self, rng: &mut impl Rng) -> usize {
+        let u: f64 = rng.gen();
+        let uz = u * self.zeta_n;
+        if uz < 1.0 {
+            return 0;
+        }
+        if uz < 1.0 + 0.5f64.powf(self.theta) {
+            return 1.min(self.n - 1);
+        }
+        let rank = (self.n as f64 * (self.eta * u - self.eta + 1.0).powf(self.alpha)) as usize;
+        rank.min(self.n - 1)
+    }
+}
+
+///workload parameters
+#[derive(Debug, Clone)]
+pub struct WorkloadConfig {
+    pub container_id: ContainerId,
+    ///records inserted before the measured run
+    pub records: usize,
+    ///stop after this many operations
+    pub operations: Option<usize>,
+    ///stop after this long
+    pub duration: Option<Duration>,
+    pub threads: usize,
+    pub mix: OpMix,
+    pub distribution: KeyDistribution,
+    ///inclusive range of value sizes in bytes
+    pub value_size: (usize, usize),
+    ///records read by one scan
+    pub scan_length: usize,
+    pub seed: u64,
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> Self {
+        WorkloadConfig {
+            container_id: 1,
+            records: 1000,
+            operations: Some(10_000),
+            duration: None,
+            threads: 1,
+            mix: OpMix::preset("a").unwrap(),
+            distribution: KeyDistribution::Zipfian,
+            value_size: (100, 100),
+            scan_length: 10,
+            seed: 0,
+        }
+    }
+}
+
+///latencies of one operation kind
+#[derive(Debug, Clone, Default)]
+pub struct OpStats {
+    latencies: Vec<Duration>,
+    ///operations the storage manager returned an error for
+    pub errors: usize,
+}
+
+impl OpStats {
+    pub fn count(&self) -> usize {
+        self.latencies.len()
+    }
+
+    ///latency at percentile p in 0..=100 over sorted latencies
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = ((p / 100.0) * (self.latencies.len() - 1) as f64).round() as usize;
+        self.latencies[rank.min(self.latencies.len() - 1)]
+    }
+
+    fn merge(&mut self, other: OpStats) {
+        self.latencies.extend(other.latencies);
+        self.errors += other.errors;
+    }
+}
+
+///results of a workload run
+#[derive(Debug, Clone)]
+pub struct WorkloadReport {
+    pub load_time: Duration,
+    pub elapsed: Duration,
+    pub ops: BTreeMap<Op, OpStats>,
+}
+
+impl WorkloadReport {
+    pub fn total_ops(&self) -> usize {
+        self.ops.values().map(OpStats::count).sum()
+    }
+
+    ///operations per second over the measured run
+    pub fn throughput(&self) -> f64 {
+        self.total_ops() as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for WorkloadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "load: {:.3}s", self.load_time.as_secs_f64())?;
+        writeln!(
+            f,
+            "run: {} ops in {:.3}s, {:.0} ops/s",
+            self.total_ops(),
+            self.elapsed.as_secs_f64(),
+            self.throughput()
+        )?;
+        writeln!(
+            f,
+            "{:>8} {:>10} {:>8} {:>10} {:>10} {:>10} {:>10}",
+            "op", "count", "errors", "p50(us)", "p95(us)", "p99(us)", "max(us)"
+        )?;
+        for (op, stats) in &self.ops {
+            writeln!(
+                f,
+                "{:>8} {:>10} {:>8} {:>10} {:>10} {:>10} {:>10}",
+                op.to_string(),
+                stats.count(),
+                stats.errors,
+                stats.percentile(50.0).as_micros(),
+                stats.percentile(95.0).as_micros(),
+                stats.percentile(99.0).as_micros(),
+                stats.percentile(100.0).as_micros()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+///ids of the records a workload inserted with None for deleted keys
+struct KeyTable {
+    ids: RwLock<Vec<Option<ValueId>>>,
+    zipfian: Zipfian,
+}
+
+impl KeyTable {
+    fn pick(&self, distribution: KeyDistribution, rng: &mut impl Rng) -> Option<usize> {
+        let n = self.ids.read().unwrap().len();
+        if n == 0 {
+            return None;
+        }
+        let key = match distribution {
+            KeyDistribution::Uniform => rng.gen_range(0..n),
+            //scatter the hot ranks over the key space like ycsb's scrambled zipfian
+            KeyDistribution::Zipfian => {
+                (self.zipfian.sample(rng) as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) as usize % n
+            }
+            KeyDistribution::Latest => n - 1 - self.zipfian.sample(rng) % n,
+        };
+        Some(key)
+    }
+
+    fn get(&self, key: usize) -> Option<ValueId> {
+        self.ids.read().unwrap()[key]
+    }
+
+    fn set(&self, key: usize, id: Option<ValueId>) {
+        self.ids.write().unwrap()[key] = id;
+    }
+
+    fn push(&self, id: ValueId) {
+        self.ids.write().unwrap().push(id.into());
+    }
+}
+
+fn random_value(config: &WorkloadConfig, rng: &mut impl Rng) -> Vec<u8> {
+    let (min, max) = config.value_size;
+    let len = rng.gen_range(min..=max.max(min));
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+///loads config.records values into a fresh container then runs the operation mix on config.threads threads
+pub fn run_workload<S: StorageTrait + Sync>(
+    sm: &S,
+    config: &WorkloadConfig,
+) -> Result<WorkloadReport, CrustyError> {
+    let cid = config.container_id;
+    sm.create_table(cid)?;
+    let tid = TransactionId::new();
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    let load_start = Instant::now();
+    let values: Vec<Vec<u8>> = (0..config.records)
+        .map(|_| random_value(config, &mut rng))
+        .collect();
+    let ids = sm.insert_values(cid, values, tid);
+    let load_time = load_start.elapsed();
+
+    let keys = KeyTable {
+        zipfian: Zipfian::new(config.records, ZIPFIAN_THETA),
+        ids: RwLock::new(ids.into_iter().map(Some).collect()),
+    };
+    let budget = AtomicUsize::new(0);
+    let start = Instant::now();
+    let per_thread: Vec<BTreeMap<Op, OpStats>> = thread::scope(|s| {
+        let workers: Vec<_> = (0..config.threads.max(1))
+            .map(|t| {
+                let (keys, budget) = (&keys, &budget);
+                s.spawn(move || {
+                    let mut rng = StdRng::seed_from_u64(config.seed.wrapping_add(t as u64 + 1));
+                    let mut stats: BTreeMap<Op, OpStats> = BTreeMap::new();
+                    loop {
+                        if let Some(max) = config.operations {
+                            if budget.fetch_add(1, Ordering::Relaxed) >= max {
+                                break;
+                            }
+                        }
+                        if config.duration.is_some_and(|d| start.elapsed() >= d) {
+                            break;
+                        }
+                        let op = config.mix.choose(&mut rng);
+                        let op_start = Instant::now();
+                        let ok = run_op(sm, config, keys, op, &mut rng);
+                        let entry = stats.entry(op).or_default();
+                        entry.latencies.push(op_start.elapsed());
+                        if !ok {
+                            entry.errors += 1;
+                        }
+                    }
+                    stats
+                })
+            })
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap()).collect()
+    });
+    let elapsed = start.elapsed();
+
+    let mut ops: BTreeMap<Op, OpStats> = BTreeMap::new();
+    for stats in per_thread {
+        for (op, s) in stats {
+            ops.entry(op).or_default().merge(s);
+        }
+    }
+    for stats in ops.values_mut() {
+        stats.latencies.sort();
+    }
+    Ok(WorkloadReport {
+        load_time,
+        elapsed,
+        ops,
+    })
+}
+
+///executes one operation returning false if the storage manager reported an error
+fn run_op<S: StorageTrait>(
+    sm: &S,
+    config: &WorkloadConfig,
+    keys: &KeyTable,
+    op: Op,
+    rng: &mut impl Rng,
+) -> bool {
+    let tid = TransactionId::new();
+    if op == Op::Insert {
+        keys.push(sm.insert_value(config.container_id, random_value(config, rng), tid));
+        return true;
+    }
+    let key = match keys.pick(config.distribution, rng) {
+        Some(key) => key,
+        None => return false,
+    };
+    //deleted keys count as misses
+    let id = match keys.get(key) {
+        Some(id) => id,
+        None => return false,
+    };
+    match op {
+        Op::Read => sm.get_value(id, tid, Permissions::ReadOnly).is_ok(),
+        Op::Update => match sm.update_value(random_value(config, rng), id, tid) {
+            Ok(new_id) => {
+                keys.set(key, Some(new_id));
+                true
+            }
+            Err(_) => false,
+        },
+        Op::Delete => {
+            keys.set(key, None);
+            sm.delete_value(id, tid).is_ok()
+        }
+        Op::Scan => {
+            sm.get_iterator_from(config.container_id, tid, Permissions::ReadOnly, id)
+                .take(config.scan_length)
+                .count();
+            true
+        }
+        Op::Insert => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::testutil::init;
+
+    #[test]
+    fn hs_workload_mix_parse() {
+        let mix: OpMix = "read=90, update=10".parse().unwrap();
+        assert_eq!(
+            OpMix::new(vec![(Op::Read, 90), (Op::Update, 10)]).unwrap(),
+            mix
+        );
+        assert_eq!(OpMix::preset("c"), "C".parse().ok());
+        assert!("read=0".parse::<OpMix>().is_err());
+        assert!("read".parse::<OpMix>().is_err());
+        assert!("fly=3".parse::<OpMix>().is_err());
+
+        let mut rng = StdRng::seed_from_u64(7);
+        let only_reads = OpMix::preset("c").unwrap();
+        assert!((0..100).all(|_| only_reads.choose(&mut rng) == Op::Read));
+    }
+
+    #[test]
+    fn hs_workload_zipfian_skew() {
+        let zipf = Zipfian::new(1000, ZIPFIAN_THETA);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut counts = vec![0usize; 1000];
+        for _ in 0..20_000 {
+            counts[zipf.sample(&mut rng)] += 1;
+        }
+        //rank 0 is the single most popular key and the top 10 take a large share
+        assert_eq!(0, (0..1000).max_by_key(|&i| counts[i]).unwrap());
+        assert!(counts[..10].iter().sum::<usize>() > 20_000 / 4);
+    }
+
+    #[test]
+    fn hs_workload_percentiles() {
+        let stats = OpStats {
+            latencies: (1..=100).map(Duration::from_micros).collect(),
+            errors: 0,
+        };
+        assert_eq!(Duration::from_micros(1), stats.percentile(0.0));
+        assert_eq!(Duration::from_micros(51), stats.percentile(50.0));
+        assert_eq!(Duration::from_micros(100), stats.percentile(100.0));
+        assert_eq!(Duration::ZERO, OpStats::default().percentile(99.0));
+    }
+
+    #[test]
+    fn hs_workload_runs_against_storage_trait() {
+        init();
+        let sm = memstore::storage_manager::StorageManager::new_test_sm();
+        let config = WorkloadConfig {
+            records: 200,
+            operations: Some(1000),
+            threads: 4,
+            mix: "read=60,update=20,insert=10,delete=10".parse().unwrap(),
+            ..WorkloadConfig::default()
+        };
+        let report = run_workload(&sm, &config).unwrap();
+        assert_eq!(1000, report.total_ops());
+        assert_eq!(0, report.ops[&Op::Insert].errors);
+        assert!(report.to_string().contains("update"));
+    }
+}